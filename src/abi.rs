@@ -238,6 +238,26 @@ impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8);
+impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8, 9 => P9);
+impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8, 9 => P9, 10 => P10);
+
+/// Reserve stack space for a return value that is returned via memory, and
+/// pass a pointer to it as the implicit first argument. The space is zeroed,
+/// so that the result is predictable if the callee doesn't write to it, e.g.
+/// when a message is sent to `nil`. The caller must free the space after the
+/// call by adding `size` to the stack pointer.
+fn reserve_retval_space(
+    size: GuestUSize,
+    regs: &mut [u32],
+    mem: &mut Mem,
+    reg_offset: &mut usize,
+) -> ConstVoidPtr {
+    regs[Cpu::SP] -= size;
+    let ptr: MutVoidPtr = Ptr::from_bits(regs[Cpu::SP]);
+    mem.bytes_at_mut(ptr.cast(), size).fill(0);
+    write_next_arg(reg_offset, regs, mem, ptr.cast_const());
+    ptr.cast_const()
+}
 
 /// This trait represents a guest or host function that can be called from host
 /// code, but using the guest ABI. See [CallFromGuest], which this is the
@@ -260,10 +280,7 @@ macro_rules! impl_CallFromHost {
                 let mut reg_offset = 0;
                 let regs = env.cpu.regs_mut();
                 let retval_ptr = R::SIZE_IN_MEM.map(|size| {
-                    regs[Cpu::SP] -= size;
-                    let ptr: ConstVoidPtr = Ptr::from_bits(regs[Cpu::SP]);
-                    write_next_arg(&mut reg_offset, regs, &mut env.mem, ptr);
-                    ptr
+                    reserve_retval_space(size, regs, &mut env.mem, &mut reg_offset)
                 });
                 let old_sp = extend_stack_for_args(
                     0 $(+ <$P as GuestArg>::REG_COUNT)*,
@@ -291,17 +308,25 @@ macro_rules! impl_CallFromHost {
                 env: &mut Environment,
                 args: ($($P,)*),
             ) -> R {
-                assert!(R::SIZE_IN_MEM.is_none()); // pointer return TODO
+                let mut reg_offset = 0;
                 let regs = env.cpu.regs_mut();
+                let retval_ptr = R::SIZE_IN_MEM.map(|size| {
+                    reserve_retval_space(size, regs, &mut env.mem, &mut reg_offset)
+                });
                 let old_sp = extend_stack_for_args(
                     0 $(+ <$P as GuestArg>::REG_COUNT)*,
                     regs,
                 );
-                let mut reg_offset = 0;
                 $(write_next_arg::<$P>(&mut reg_offset, regs, &mut env.mem, args.$p);)*
                 self.call(env);
-                env.cpu.regs_mut()[Cpu::SP] = old_sp;
-                <R as GuestRet>::from_regs(env.cpu.regs())
+                let regs = env.cpu.regs_mut(); // reborrow
+                regs[Cpu::SP] = old_sp;
+                if let Some(retval_ptr) = retval_ptr {
+                    regs[Cpu::SP] += R::SIZE_IN_MEM.unwrap();
+                    <R as GuestRet>::from_mem(retval_ptr, &env.mem)
+                } else {
+                    <R as GuestRet>::from_regs(regs)
+                }
             }
         }
 
@@ -675,6 +700,15 @@ impl<T, const MUT: bool> GuestRet for Ptr<T, MUT> {
     }
 }
 
+impl GuestRet for GuestFunction {
+    fn from_regs(regs: &[u32]) -> Self {
+        GuestFunction(<ConstVoidPtr as GuestRet>::from_regs(regs))
+    }
+    fn to_regs(self, regs: &mut [u32]) {
+        <ConstVoidPtr as GuestRet>::to_regs(self.0, regs)
+    }
+}
+
 // GuestRet implementations for u64-like types
 
 impl GuestRet for u64 {
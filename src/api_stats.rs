@@ -0,0 +1,147 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! API usage statistics (`--api-stats=`).
+//!
+//! This counts how often the app calls each host function and each host
+//! Objective-C method, and which missing functions, classes and methods it
+//! tried to use. The report written at exit is meant to help decide what's
+//! most worth implementing next.
+
+use crate::objc::{Class, SEL};
+use crate::Environment;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(Default)]
+pub struct ApiStats {
+    host_functions: HashMap<&'static str, u64>,
+    /// Keyed by the class that has the method and the selector. Names are
+    /// only looked up when writing the report, to keep counting cheap.
+    host_methods: HashMap<(Class, SEL), u64>,
+    /// Description of each missing API the app tried to use.
+    missing: HashMap<String, u64>,
+    /// Set when the guest calls `objc_msgSend()` or a variant, so that only
+    /// messages sent by the app are counted, not ones sent by host code.
+    guest_message_pending: bool,
+    report_written: bool,
+}
+
+/// Count a call to a host function by the app.
+pub fn count_host_function(env: &mut Environment, symbol: &'static str) {
+    let Some(stats) = &mut env.api_stats else {
+        return;
+    };
+    *stats.host_functions.entry(symbol).or_default() += 1;
+    if symbol.starts_with("objc_msgSend") {
+        stats.guest_message_pending = true;
+    }
+}
+
+/// Check whether the message being sent was sent by the app. This must be
+/// called exactly once for each message send.
+pub fn take_guest_message_pending(env: &mut Environment) -> bool {
+    env.api_stats
+        .as_mut()
+        .is_some_and(|stats| std::mem::take(&mut stats.guest_message_pending))
+}
+
+/// Count a message sent by the app that was handled by a host method.
+pub fn count_host_method(env: &mut Environment, class: Class, selector: SEL) {
+    if let Some(stats) = &mut env.api_stats {
+        *stats.host_methods.entry((class, selector)).or_default() += 1;
+    }
+}
+
+/// Count an attempt to use an API touchHLE doesn't have.
+pub fn count_missing(env: &mut Environment, description: String) {
+    if let Some(stats) = &mut env.api_stats {
+        *stats.missing.entry(description).or_default() += 1;
+    }
+}
+
+fn write_section<W: Write>(out: &mut W, title: &str, counts: Vec<(String, u64)>) {
+    let mut counts = counts;
+    // Most-used first, then alphabetical so the output is deterministic.
+    counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+    let total: u64 = counts.iter().map(|&(_, count)| count).sum();
+    let _ = writeln!(
+        out,
+        "== {} ({} distinct, {} calls) ==",
+        title,
+        counts.len(),
+        total
+    );
+    for (name, count) in counts {
+        let _ = writeln!(out, "{:>12} {}", count, name);
+    }
+    let _ = writeln!(out);
+}
+
+/// Write the report, if statistics are enabled. This is called when the app
+/// exits, or when touchHLE crashes.
+pub fn write_report(env: &mut Environment) {
+    let Some(path) = env.options.api_stats_path.clone() else {
+        return;
+    };
+    let Some(stats) = &mut env.api_stats else {
+        return;
+    };
+    if std::mem::replace(&mut stats.report_written, true) {
+        return;
+    }
+
+    let host_functions = stats
+        .host_functions
+        .iter()
+        .map(|(&symbol, &count)| (symbol.to_string(), count))
+        .collect();
+    let missing = stats
+        .missing
+        .iter()
+        .map(|(name, &count)| (name.clone(), count))
+        .collect();
+    let host_methods = stats
+        .host_methods
+        .iter()
+        .map(|(&(class, selector), &count)| {
+            let prefix = if env.objc.class_is_metaclass(class) {
+                '+'
+            } else {
+                '-'
+            };
+            let name = format!(
+                "{}[{} {}]",
+                prefix,
+                env.objc.get_class_name(class),
+                selector.as_str(&env.mem)
+            );
+            (name, count)
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    let _ = writeln!(
+        out,
+        "API usage statistics for {:?} ({})",
+        env.bundle.display_name(),
+        env.bundle.bundle_identifier()
+    );
+    let _ = writeln!(out);
+    write_section(&mut out, "Missing APIs", missing);
+    write_section(&mut out, "Host functions", host_functions);
+    write_section(&mut out, "Host methods", host_methods);
+
+    match std::fs::write(&path, out) {
+        Ok(()) => echo!("Wrote API usage statistics to {:?}.", path),
+        Err(e) => echo!(
+            "Warning: couldn't write API usage statistics to {:?}: {}",
+            path,
+            e
+        ),
+    }
+}
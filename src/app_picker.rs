@@ -433,9 +433,6 @@ fn make_icon_grid(
         };
         let icon_button: id = msg_class![env; UIButton buttonWithType:UIButtonTypeCustom];
         () = msg![env; icon_button setFrame:icon_frame];
-        let image_view: id = msg![env; icon_button imageView];
-        let bounds: CGRect = msg![env; icon_button bounds];
-        () = msg![env; image_view setFrame:bounds];
         () = msg![env; icon_button addTarget:delegate
                                       action:icon_tapped_sel
                             forControlEvents:UIControlEventTouchUpInside];
@@ -652,8 +649,6 @@ fn make_button_row(
         let text = ns_string::get_static_str(env, title_text);
         () = msg![env; button setTitle:text forState:UIControlStateNormal];
         () = msg![env; button setFrame:button_frame];
-        // FIXME: manually calling layoutSubviews shouldn't be needed?
-        () = msg![env; button layoutSubviews];
 
         if let Some(font_size) = font_size {
             let label: id = msg![env; button titleLabel];
@@ -3,7 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! Audio file decoding and OpenAL bindings.
+//! Audio file decoding, OpenAL bindings, and text-to-speech.
 //!
 //! The audio file decoding support is an abstraction over various libraries
 //! (currently [caf], [hound], and dr_mp3), usage of which should be confined to
@@ -13,9 +13,18 @@
 //! - [Apple Core Audio Format Specification 1.0](https://developer.apple.com/library/archive/documentation/MusicAudio/Reference/CAFSpec/CAF_intro/CAF_intro.html)
 
 mod aac;
+mod capture;
+mod device;
 mod ima4;
+mod speech;
 
+pub use capture::{encode_caf, encode_wav, AudioCapture};
+pub use device::{
+    open_output_device, output_context_attributes, reconnect_output_device_if_needed,
+    set_output_refresh_rate,
+};
 pub use ima4::decode_ima4;
+pub use speech::Speech;
 use touchHLE_dr_mp3_wrapper as dr_mp3;
 pub use touchHLE_openal_soft_wrapper as openal;
 
@@ -0,0 +1,175 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Recording from the host's microphone, and encoding recorded audio.
+//!
+//! Recorded audio is always 16-bit signed integer linear PCM, interleaved if
+//! there are multiple channels.
+
+use super::openal as al;
+use super::openal::alc_types::*;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+/// How many seconds of audio OpenAL Soft should buffer between calls to
+/// [AudioCapture::read_samples].
+const BUFFER_SECONDS: u32 = 2;
+
+/// A source of recorded audio. If the host has no microphone (or it can't be
+/// opened), this produces silence instead, so apps that record still work.
+pub struct AudioCapture {
+    /// Null if there is no microphone.
+    device: *mut ALCdevice,
+    sample_rate: u32,
+    channels: u16,
+    /// `Some` while capturing. Used to generate silence without a microphone.
+    last_read: Option<Instant>,
+}
+
+impl AudioCapture {
+    pub fn open(sample_rate: u32, channels: u16) -> AudioCapture {
+        assert!(channels == 1 || channels == 2);
+        let format = if channels == 1 {
+            al::AL_FORMAT_MONO16
+        } else {
+            al::AL_FORMAT_STEREO16
+        };
+        let device = unsafe {
+            al::alcCaptureOpenDevice(
+                std::ptr::null(),
+                sample_rate,
+                format,
+                (sample_rate * BUFFER_SECONDS).try_into().unwrap(),
+            )
+        };
+        if device.is_null() {
+            log!("Warning: Could not open a microphone, recording silence instead.");
+        }
+        AudioCapture {
+            device,
+            sample_rate,
+            channels,
+            last_read: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        if self.last_read.is_some() {
+            return;
+        }
+        if !self.device.is_null() {
+            unsafe { al::alcCaptureStart(self.device) };
+        }
+        self.last_read = Some(Instant::now());
+    }
+
+    /// Stop capturing. Call [Self::read_samples] first to avoid losing the
+    /// last samples.
+    pub fn stop(&mut self) {
+        if self.last_read.take().is_none() {
+            return;
+        }
+        if !self.device.is_null() {
+            unsafe { al::alcCaptureStop(self.device) };
+        }
+    }
+
+    /// Append the samples captured since the last call to `samples`. This
+    /// must be called regularly while capturing.
+    pub fn read_samples(&mut self, samples: &mut Vec<i16>) {
+        let Some(last_read) = self.last_read else {
+            return;
+        };
+
+        let frames: usize = if self.device.is_null() {
+            let elapsed = last_read.elapsed().as_secs_f64();
+            let frames = (elapsed * f64::from(self.sample_rate)) as usize;
+            // Only advance by whole frames, so no time is lost to rounding.
+            let frames_duration = frames as f64 / f64::from(self.sample_rate);
+            self.last_read = Some(last_read + Duration::from_secs_f64(frames_duration));
+            frames
+        } else {
+            let mut frames: ALCint = 0;
+            unsafe { al::alcGetIntegerv(self.device, al::ALC_CAPTURE_SAMPLES, 1, &mut frames) };
+            frames.try_into().unwrap()
+        };
+        if frames == 0 {
+            return;
+        }
+
+        let start = samples.len();
+        samples.resize(start + frames * usize::from(self.channels), 0);
+        if !self.device.is_null() {
+            unsafe {
+                al::alcCaptureSamples(
+                    self.device,
+                    samples[start..].as_mut_ptr().cast(),
+                    frames.try_into().unwrap(),
+                )
+            };
+        }
+    }
+}
+
+impl Drop for AudioCapture {
+    fn drop(&mut self) {
+        self.stop();
+        if !self.device.is_null() {
+            unsafe { al::alcCaptureCloseDevice(self.device) };
+        }
+    }
+}
+
+/// Encode recorded audio as a WAVE file.
+pub fn encode_wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+    for &sample in samples {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+    bytes.into_inner()
+}
+
+/// Encode recorded audio as a Core Audio Format file.
+pub fn encode_caf(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    // kCAFLinearPCMFormatFlagIsLittleEndian
+    const FLAG_IS_LITTLE_ENDIAN: u32 = 1 << 1;
+
+    let data_size = samples.len() * 2;
+    let mut bytes = Vec::with_capacity(68 + data_size);
+
+    // File header
+    bytes.extend_from_slice(b"caff");
+    bytes.extend_from_slice(&1u16.to_be_bytes()); // version
+    bytes.extend_from_slice(&0u16.to_be_bytes()); // flags
+
+    // Audio Description chunk
+    bytes.extend_from_slice(b"desc");
+    bytes.extend_from_slice(&32i64.to_be_bytes());
+    bytes.extend_from_slice(&f64::from(sample_rate).to_be_bytes());
+    bytes.extend_from_slice(b"lpcm");
+    bytes.extend_from_slice(&FLAG_IS_LITTLE_ENDIAN.to_be_bytes());
+    bytes.extend_from_slice(&(u32::from(channels) * 2).to_be_bytes()); // bytes per packet
+    bytes.extend_from_slice(&1u32.to_be_bytes()); // frames per packet
+    bytes.extend_from_slice(&u32::from(channels).to_be_bytes());
+    bytes.extend_from_slice(&16u32.to_be_bytes()); // bits per channel
+
+    // Audio Data chunk
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(4 + data_size as i64).to_be_bytes());
+    bytes.extend_from_slice(&0u32.to_be_bytes()); // edit count
+    for &sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    bytes
+}
@@ -0,0 +1,112 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Selection of the OpenAL output device, and recovery when it disconnects.
+//!
+//! All of touchHLE's OpenAL devices should be opened with
+//! [open_output_device], so that the `--audio-device=` option is respected.
+
+use super::openal as al;
+use super::openal::alc_types::*;
+use std::ffi::{CStr, CString};
+
+/// Get the names of the available output devices.
+fn output_device_names() -> Vec<String> {
+    let list = unsafe { al::alcGetString(std::ptr::null_mut(), al::ALC_ALL_DEVICES_SPECIFIER) };
+    let mut names = Vec::new();
+    if list.is_null() {
+        return names;
+    }
+    // The list is a series of NUL-terminated strings, ending with an empty
+    // string.
+    let mut ptr = list;
+    loop {
+        let name = unsafe { CStr::from_ptr(ptr) }.to_bytes();
+        if name.is_empty() {
+            break;
+        }
+        names.push(String::from_utf8_lossy(name).into_owned());
+        ptr = unsafe { ptr.add(name.len() + 1) };
+    }
+    names
+}
+
+/// Open an output device. The preferred device (`--audio-device=`) is used if
+/// possible, otherwise the default one. Returns null on failure, like
+/// `alcOpenDevice()`.
+pub fn open_output_device(preferred: Option<&str>) -> *mut ALCdevice {
+    if let Some(name) = preferred {
+        let c_name = CString::new(name).unwrap();
+        let device = unsafe { al::alcOpenDevice(c_name.as_ptr()) };
+        if !device.is_null() {
+            return device;
+        }
+        log!(
+            "Warning: Could not open audio device {:?}, using the default device instead. Available devices: {:?}",
+            name,
+            output_device_names()
+        );
+    }
+    unsafe { al::alcOpenDevice(std::ptr::null()) }
+}
+
+/// Check whether an output device has been disconnected (e.g. headphones were
+/// unplugged), and if so, move it to the preferred device or the default one.
+/// The device's contexts, sources and buffers survive this, so playback
+/// continues.
+pub fn reconnect_output_device_if_needed(device: *mut ALCdevice, preferred: Option<&str>) {
+    let mut connected: ALCint = 1;
+    unsafe { al::alcGetIntegerv(device, al::ALC_CONNECTED, 1, &mut connected) };
+    if connected != 0 {
+        return;
+    }
+
+    let preferred = preferred.map(|name| CString::new(name).unwrap());
+    for name in [preferred.as_deref(), None] {
+        let name_ptr = name.map_or(std::ptr::null(), CStr::as_ptr);
+        let res = unsafe { al::alcReopenDeviceSOFT(device, name_ptr, std::ptr::null()) };
+        if res != al::ALC_FALSE {
+            log!(
+                "Audio device {:?} was disconnected, switched to {}.",
+                device,
+                name.map_or("the default device".to_string(), |name| format!(
+                    "{:?}",
+                    name
+                ))
+            );
+            return;
+        }
+    }
+    // This will be retried the next time, so don't spam the log.
+    log_dbg!(
+        "Audio device {:?} was disconnected, and no other device is available.",
+        device
+    );
+}
+
+/// Get the zero-terminated attribute list to pass to `alcCreateContext()` for
+/// an output device, requesting a specific `ALC_REFRESH` rate if there is one
+/// (see [crate::Environment::audio_refresh_rate]).
+pub fn output_context_attributes(refresh_rate: Option<ALCint>) -> Vec<ALCint> {
+    match refresh_rate {
+        Some(refresh_rate) => vec![al::ALC_REFRESH, refresh_rate, 0],
+        None => vec![0],
+    }
+}
+
+/// Change the `ALC_REFRESH` rate of an output device that is already in use.
+/// [None] restores the default. The device's contexts, sources and buffers
+/// survive this.
+pub fn set_output_refresh_rate(device: *mut ALCdevice, refresh_rate: Option<ALCint>) {
+    let attributes = output_context_attributes(refresh_rate);
+    let res = unsafe { al::alcResetDeviceSOFT(device, attributes.as_ptr()) };
+    if res == al::ALC_FALSE {
+        log!(
+            "Warning: Could not change the refresh rate of audio device {:?} to {:?}.",
+            device,
+            refresh_rate
+        );
+    }
+}
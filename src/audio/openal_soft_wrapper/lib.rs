@@ -49,6 +49,12 @@ pub const ALC_FALSE: ALCboolean = 0;
 pub const ALC_TRUE: ALCboolean = 1;
 
 pub const ALC_DEVICE_SPECIFIER: ALCenum = 0x1005;
+pub const ALC_REFRESH: ALCenum = 0x1008;
+/// From `ALC_ENUMERATE_ALL_EXT`.
+pub const ALC_ALL_DEVICES_SPECIFIER: ALCenum = 0x1013;
+/// From `ALC_EXT_disconnect`.
+pub const ALC_CONNECTED: ALCenum = 0x313;
+pub const ALC_CAPTURE_SAMPLES: ALCenum = 0x312;
 
 extern "C" {
     pub fn alcOpenDevice(devicename: *const ALCchar) -> *mut ALCdevice;
@@ -67,6 +73,33 @@ extern "C" {
     pub fn alcGetError(device: *mut ALCdevice) -> ALCenum;
 
     pub fn alcGetString(device: *mut ALCdevice, param: ALCenum) -> *const ALCchar;
+    pub fn alcGetIntegerv(
+        device: *mut ALCdevice,
+        param: ALCenum,
+        size: ALCsizei,
+        values: *mut ALCint,
+    );
+
+    pub fn alcCaptureOpenDevice(
+        devicename: *const ALCchar,
+        frequency: ALCuint,
+        format: ALCenum,
+        buffersize: ALCsizei,
+    ) -> *mut ALCdevice;
+    pub fn alcCaptureCloseDevice(device: *mut ALCdevice) -> ALCboolean;
+    pub fn alcCaptureStart(device: *mut ALCdevice);
+    pub fn alcCaptureStop(device: *mut ALCdevice);
+    pub fn alcCaptureSamples(device: *mut ALCdevice, buffer: *mut ALCvoid, samples: ALCsizei);
+
+    // From alext.h (ALC_SOFT_reopen_device)
+    pub fn alcReopenDeviceSOFT(
+        device: *mut ALCdevice,
+        deviceName: *const ALCchar,
+        attribs: *const ALCint,
+    ) -> ALCboolean;
+
+    // From alext.h (ALC_SOFT_HRTF)
+    pub fn alcResetDeviceSOFT(device: *mut ALCdevice, attribs: *const ALCint) -> ALCboolean;
 }
 
 // === al.h ===
@@ -93,6 +126,7 @@ use al_types::*;
 
 pub const AL_NO_ERROR: ALenum = 0;
 
+pub const AL_GAIN: ALenum = 0x100A;
 pub const AL_MAX_GAIN: ALenum = 0x100E;
 
 pub const AL_SOURCE_STATE: ALenum = 0x1010;
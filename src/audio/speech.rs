@@ -0,0 +1,108 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Text-to-speech using the host's speech synthesizer.
+//!
+//! There's no portable speech synthesis library, so this runs the host's
+//! command-line speech tool, if it has one. This is only done if the user
+//! opted in with `--text-to-speech`, otherwise speech is silent and the text is
+//! just logged.
+
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+/// Commands that read text from stdin and speak it, in order of preference.
+#[cfg(target_os = "macos")]
+const COMMANDS: &[&[&str]] = &[&["say"]];
+#[cfg(target_os = "windows")]
+const COMMANDS: &[&[&str]] = &[&[
+    "powershell",
+    "-NoProfile",
+    "-Command",
+    "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak([Console]::In.ReadToEnd())",
+]];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const COMMANDS: &[&[&str]] = &[
+    &["espeak-ng", "--stdin"],
+    &["espeak", "--stdin"],
+    &["spd-say", "--wait", "--pipe-mode"],
+];
+
+/// A voice that can speak one utterance at a time.
+pub struct Speech {
+    enabled: bool,
+    child: Option<Child>,
+}
+
+impl Speech {
+    pub fn new(enabled: bool) -> Speech {
+        Speech {
+            enabled,
+            child: None,
+        }
+    }
+
+    /// Start speaking some text, interrupting anything that was already being
+    /// spoken.
+    pub fn speak(&mut self, text: &str) {
+        self.stop();
+        if !self.enabled {
+            log!("Speech (silent, see --text-to-speech): {:?}", text);
+            return;
+        }
+        log_dbg!("Speech: {:?}", text);
+
+        for command in COMMANDS {
+            let Ok(mut child) = Command::new(command[0])
+                .args(&command[1..])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+            else {
+                continue;
+            };
+            // Dropping stdin closes it, which tells the tool the text is done.
+            let mut stdin = child.stdin.take().unwrap();
+            let _ = stdin.write_all(text.as_bytes());
+            self.child = Some(child);
+            return;
+        }
+        log!(
+            "Warning: No host speech synthesizer found (tried {:?}), speech will be silent.",
+            COMMANDS
+                .iter()
+                .map(|command| command[0])
+                .collect::<Vec<_>>()
+        );
+        self.enabled = false;
+    }
+
+    /// Whether an utterance is still being spoken.
+    pub fn is_speaking(&mut self) -> bool {
+        let Some(child) = self.child.as_mut() else {
+            return false;
+        };
+        if matches!(child.try_wait(), Ok(None)) {
+            return true;
+        }
+        self.child = None;
+        false
+    }
+
+    /// Stop speaking immediately.
+    pub fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Speech {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
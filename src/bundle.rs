@@ -105,6 +105,22 @@ impl Bundle {
             .map(|v| v.as_string().unwrap())
     }
 
+    /// Device capabilities the app requires (`UIRequiredDeviceCapabilities`),
+    /// e.g. `"armv7"` or `"opengles-2"`. This may be either an array of
+    /// capabilities, or a dictionary where a capability is required if its
+    /// value is true. Capabilities that must be absent are not included.
+    pub fn required_device_capabilities(&self) -> Vec<&str> {
+        match self.plist.get("UIRequiredDeviceCapabilities") {
+            Some(Value::Array(array)) => array.iter().filter_map(|v| v.as_string()).collect(),
+            Some(Value::Dictionary(dict)) => dict
+                .iter()
+                .filter(|(_, required)| required.as_boolean() == Some(true))
+                .map(|(capability, _)| capability.as_str())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn executable_path(&self) -> GuestPathBuf {
         // FIXME: Is this key optional? All iPhone apps seem to have it.
         self.path
@@ -154,6 +170,14 @@ impl Bundle {
         Ok(image)
     }
 
+    /// Name of the `UIApplication` subclass to use, if Info.plist specifies
+    /// one.
+    pub fn principal_class(&self) -> Option<&str> {
+        self.plist
+            .get("NSPrincipalClass")
+            .map(|name| name.as_string().unwrap())
+    }
+
     pub fn main_nib_file_path(&self) -> Option<GuestPathBuf> {
         self.plist.get("NSMainNibFile").map(|filename| {
             let filename = filename.as_string().unwrap();
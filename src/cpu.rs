@@ -8,10 +8,13 @@
 //! Implemented using the C++ library dynarmic, which is a dynamic recompiler.
 //!
 //! iPhone OS apps used either ARMv6 or ARMv7-A, which are both 32-bit ISAs.
-//! For the moment, only ARMv6 has been tested.
+//! Most testing has been done with ARMv6 apps. There are some conformance tests
+//! for tricky instructions (including Thumb-2 ones) at the end of this file.
 
 use crate::abi::GuestFunction;
-use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead, SafeWrite};
+use crate::mem::{
+    guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead, SafeWrite, UnalignedAccess,
+};
 
 // Import functions from C++
 use touchHLE_dynarmic_wrapper::*;
@@ -38,6 +41,8 @@ fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
     // the emulator will crash anyway, maybe this is okay.
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
         let mem = unsafe { &mut *mem.cast::<Mem>() };
+        mem.check_cpu_access_stack_guard(addr, guest_size_of::<T>());
+        mem.check_cpu_access_alignment(addr, guest_size_of::<T>(), /* write: */ false);
         let ptr: ConstPtr<T> = Ptr::from_bits(addr);
         mem.read(ptr)
     }));
@@ -51,6 +56,8 @@ fn touchHLE_cpu_write_impl<T: SafeWrite>(mem: *mut touchHLE_Mem, addr: VAddr, va
     // See comments above about catch_unwind
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
         let mem = unsafe { &mut *mem.cast::<Mem>() };
+        mem.check_cpu_access_stack_guard(addr, guest_size_of::<T>());
+        mem.check_cpu_access_alignment(addr, guest_size_of::<T>(), /* write: */ true);
         let ptr: MutPtr<T> = Ptr::from_bits(addr);
         mem.write(ptr, value)
     }));
@@ -136,7 +143,8 @@ pub enum CpuState {
 /// A reason that can cause CPU execution to be interrupted.
 #[derive(Debug)]
 pub enum CpuError {
-    /// Memory error during execution (probably a null page access).
+    /// Memory error during execution (probably a null page access, or a stack
+    /// overflow, see [Mem::take_stack_overflow]).
     MemoryError,
     /// Undefined instruction (perhaps from a GDB software breakpoint).
     UndefinedInstruction,
@@ -159,10 +167,18 @@ impl Cpu {
     /// When this bit is set in CPSR, the CPU is in user mode.
     pub const CPSR_USER_MODE: u32 = 0x00000010;
 
+    /// The bits of CPSR that hold the Thumb-2 IT block state (ITSTATE), which
+    /// is split across two fields.
+    pub const CPSR_IT_STATE: u32 = 0x0600FC00;
+
     /// Construct a new CPU instance. If a mutable reference to a [Mem] instance
     /// is provided, direct memory access is enabled, and the CPU instance
     /// becomes bound to that [Mem] instance (subsequent calls must use the same
     /// one).
+    ///
+    /// If direct memory access is enabled, the [Mem]'s [UnalignedAccess]
+    /// setting must be final, because unaligned accesses can't be checked
+    /// without making them use the slow path.
     pub fn new(direct_memory_access: Option<&mut Mem>) -> Cpu {
         // Null page count is in pages rather than bytes. Mem ensures it is
         // page aligned.
@@ -171,6 +187,9 @@ impl Cpu {
             .map_or(0, |mem| mem.null_segment_size() / 0x1000)
             .try_into()
             .unwrap();
+        let detect_misaligned_access = direct_memory_access
+            .as_ref()
+            .is_some_and(|mem| mem.unaligned_access() != UnalignedAccess::Allow);
         // Safety: the direct memory access pointer will be retained directly by
         // the dynarmic wrapper and indirectly by cached JIT code, so we must
         // ensure we only execute the CPU while holding a &mut on the Mem object
@@ -179,8 +198,13 @@ impl Cpu {
             .map_or(std::ptr::null_mut(), |mem| unsafe {
                 mem.direct_memory_access_ptr()
             });
-        let dynarmic_wrapper =
-            unsafe { touchHLE_DynarmicWrapper_new(direct_memory_access_ptr, null_page_count) };
+        let dynarmic_wrapper = unsafe {
+            touchHLE_DynarmicWrapper_new(
+                direct_memory_access_ptr,
+                null_page_count,
+                detect_misaligned_access,
+            )
+        };
         Cpu {
             dynarmic_wrapper,
             direct_memory_access_ptr,
@@ -243,10 +267,14 @@ impl Cpu {
 
     /// Set PC and the Thumb flag for executing a guest function. Note that this
     /// does not touch LR.
+    ///
+    /// Any IT block state is cleared, like for a real branch instruction.
+    /// Otherwise, if the CPU had stopped inside an IT block, the first few
+    /// instructions at the new PC would wrongly be conditional.
     pub fn branch(&mut self, new_pc: GuestFunction) {
         self.regs_mut()[Self::PC] = new_pc.addr_without_thumb_bit();
-        let cpsr_without_thumb = self.cpsr() & (!Self::CPSR_THUMB);
-        self.set_cpsr(cpsr_without_thumb | ((new_pc.is_thumb() as u32) * Self::CPSR_THUMB))
+        let cpsr = self.cpsr() & !(Self::CPSR_THUMB | Self::CPSR_IT_STATE);
+        self.set_cpsr(cpsr | ((new_pc.is_thumb() as u32) * Self::CPSR_THUMB))
     }
 
     /// Set the PC and Thumb flag (like [Self::branch]), but also set the LR,
@@ -272,6 +300,16 @@ impl Cpu {
         }
     }
 
+    /// Make accesses to a page-aligned range of addresses always use the slow
+    /// path (memory callbacks), so that they can be checked by [Mem], or undo
+    /// this. This is used for stack guard regions.
+    pub fn set_pages_trapped(&mut self, base: VAddr, size: GuestUSize, trapped: bool) {
+        assert!(base & 0xfff == 0 && size & 0xfff == 0);
+        unsafe {
+            touchHLE_DynarmicWrapper_set_pages_trapped(self.dynarmic_wrapper, base, size, trapped)
+        }
+    }
+
     /// Start CPU execution.
     ///
     /// If `ticks` is [Some], it is used as an abstract time limit. The value
@@ -306,3 +344,235 @@ impl Cpu {
         }
     }
 }
+
+/// CPU conformance tests. Each test runs a short sequence of hand-assembled
+/// instructions that ends in an `svc #0`, and compares the resulting register
+/// and flag values with the ones an ARMv7-A CPU produces.
+///
+/// Since these are in guest memory, Thumb-2 32-bit instructions are written as
+/// two halfwords, with the first halfword at the lower address.
+#[cfg(test)]
+mod conformance_tests {
+    use super::{Cpu, CpuError, CpuState};
+    use crate::abi::GuestFunction;
+    use crate::mem::{GuestUSize, Mem, MutPtr, UnalignedAccess};
+
+    const CPSR_N: u32 = 1 << 31;
+    const CPSR_Z: u32 = 1 << 30;
+    const CPSR_C: u32 = 1 << 29;
+    const CPSR_V: u32 = 1 << 28;
+    const CPSR_NZCV: u32 = CPSR_N | CPSR_Z | CPSR_C | CPSR_V;
+
+    const THUMB_SVC_0: u16 = 0xDF00;
+    const ARM_SVC_0: u32 = 0xEF000000;
+
+    struct Harness {
+        mem: Mem,
+        cpu: Cpu,
+        code: GuestFunction,
+    }
+    impl Harness {
+        fn new(code: &[u8], thumb: bool, unaligned_access: UnalignedAccess) -> Harness {
+            let mut mem = Mem::new();
+            // Like a real app, so the code isn't at address 0.
+            mem.set_null_segment_size(0x1000);
+            mem.set_unaligned_access(unaligned_access);
+            let addr = mem.alloc(code.len() as GuestUSize);
+            mem.bytes_at_mut(addr.cast(), code.len() as GuestUSize)
+                .copy_from_slice(code);
+            let code = GuestFunction::from_addr_and_thumb_flag(addr.to_bits(), thumb);
+            let cpu = Cpu::new(Some(&mut mem));
+            Harness { mem, cpu, code }
+        }
+        fn new_thumb(code: &[u16]) -> Harness {
+            Self::new_thumb_with_unaligned_access(code, UnalignedAccess::Allow)
+        }
+        fn new_thumb_with_unaligned_access(
+            code: &[u16],
+            unaligned_access: UnalignedAccess,
+        ) -> Harness {
+            let code: Vec<u8> = code.iter().flat_map(|hw| hw.to_le_bytes()).collect();
+            Self::new(&code, true, unaligned_access)
+        }
+        fn new_arm(code: &[u32]) -> Harness {
+            let code: Vec<u8> = code.iter().flat_map(|w| w.to_le_bytes()).collect();
+            Self::new(&code, false, UnalignedAccess::Allow)
+        }
+
+        /// Set up the registers and flags, then run or step to the `svc`.
+        fn run(&mut self, regs: &[(usize, u32)], flags: u32, step: bool) -> ([u32; 16], u32) {
+            *self.cpu.regs_mut() = [0; 16];
+            for &(reg, value) in regs {
+                self.cpu.regs_mut()[reg] = value;
+            }
+            self.cpu.set_cpsr(Cpu::CPSR_USER_MODE | flags);
+            self.cpu.branch(self.code);
+            loop {
+                let mut ticks = 100;
+                let ticks = (!step).then_some(&mut ticks);
+                match self.cpu.run_or_step(&mut self.mem, ticks) {
+                    CpuState::Normal => (),
+                    CpuState::Svc(0) => break,
+                    other => panic!("Unexpected CPU state: {:?}", other),
+                }
+            }
+            (*self.cpu.regs(), self.cpu.cpsr() & CPSR_NZCV)
+        }
+    }
+
+    /// Run both normally and by single-stepping, and check the results match.
+    fn run_both(harness: &mut Harness, regs: &[(usize, u32)], flags: u32) -> ([u32; 16], u32) {
+        let run_result = harness.run(regs, flags, false);
+        let step_result = harness.run(regs, flags, true);
+        assert_eq!(run_result, step_result);
+        run_result
+    }
+
+    #[test]
+    fn thumb_flags_add() {
+        let mut h = Harness::new_thumb(&[
+            0x1842, // adds r2, r0, r1
+            THUMB_SVC_0,
+        ]);
+        let (regs, flags) = run_both(&mut h, &[(0, 0xFFFFFFFF), (1, 1)], 0);
+        assert_eq!((regs[2], flags), (0, CPSR_Z | CPSR_C));
+        let (regs, flags) = run_both(&mut h, &[(0, 0x7FFFFFFF), (1, 1)], 0);
+        assert_eq!((regs[2], flags), (0x80000000, CPSR_N | CPSR_V));
+    }
+
+    #[test]
+    fn thumb_it_then_else() {
+        let mut h = Harness::new_thumb(&[
+            0x2801, // cmp r0, #1
+            0xBF0C, // ite eq
+            0x210A, // moveq r1, #10
+            0x2114, // movne r1, #20
+            THUMB_SVC_0,
+        ]);
+        let (regs, flags) = run_both(&mut h, &[(0, 1)], 0);
+        // A 16-bit mov inside an IT block doesn't set the flags.
+        assert_eq!((regs[1], flags), (10, CPSR_Z | CPSR_C));
+        let (regs, _) = run_both(&mut h, &[(0, 2)], 0);
+        assert_eq!(regs[1], 20);
+    }
+
+    #[test]
+    fn thumb_it_flags_set_inside_block() {
+        let mut h = Harness::new_thumb(&[
+            0x2800, // cmp r0, #0
+            0xBF04, // itt eq
+            0x2900, // cmpeq r1, #0
+            0x2201, // moveq r2, #1
+            THUMB_SVC_0,
+        ]);
+        // The second instruction's condition uses the flags set by the first.
+        let (regs, _) = run_both(&mut h, &[(0, 0), (1, 5)], 0);
+        assert_eq!(regs[2], 0);
+        let (regs, _) = run_both(&mut h, &[(0, 0), (1, 0)], 0);
+        assert_eq!(regs[2], 1);
+        let (regs, _) = run_both(&mut h, &[(0, 1), (1, 0)], 0);
+        assert_eq!(regs[2], 0);
+    }
+
+    #[test]
+    fn thumb2_bitfield_and_clz() {
+        let mut h = Harness::new_thumb(&[
+            0xF3C0,
+            0x1107, // ubfx r1, r0, #4, #8
+            0xFAB0,
+            0xF280, // clz r2, r0
+            THUMB_SVC_0,
+        ]);
+        let (regs, _) = run_both(&mut h, &[(0, 0x12345678)], 0);
+        assert_eq!((regs[1], regs[2]), (0x67, 3));
+        let (regs, _) = run_both(&mut h, &[(0, 0)], 0);
+        assert_eq!((regs[1], regs[2]), (0, 32));
+    }
+
+    #[test]
+    fn branch_clears_it_state() {
+        let mut h = Harness::new_thumb(&[
+            0x210A, // movs r1, #10
+            THUMB_SVC_0,
+        ]);
+        // Pretend the CPU was stopped inside an "it ne" block with Z set. If
+        // this state survived the branch, the mov would be skipped.
+        let it_ne = 0x1800;
+        assert_eq!(it_ne & !Cpu::CPSR_IT_STATE, 0);
+        let (regs, _) = h.run(&[], CPSR_Z | Cpu::CPSR_THUMB | it_ne, false);
+        assert_eq!(regs[1], 10);
+    }
+
+    #[test]
+    fn arm_conditional_execution() {
+        let mut h = Harness::new_arm(&[
+            0xE3500001, // cmp r0, #1
+            0x03A0100A, // moveq r1, #10
+            0x13A01014, // movne r1, #20
+            0xE0912000, // adds r2, r1, r0
+            ARM_SVC_0,
+        ]);
+        let (regs, flags) = run_both(&mut h, &[(0, 1)], 0);
+        assert_eq!((regs[1], regs[2], flags), (10, 11, 0));
+        let (regs, _) = run_both(&mut h, &[(0, 0xFFFFFFEC)], 0);
+        assert_eq!((regs[1], regs[2]), (20, 0));
+    }
+
+    /// Allocate a word-aligned buffer holding 0x11223344, 0x55667788, and
+    /// return a pointer to its second byte.
+    fn unaligned_data(h: &mut Harness) -> u32 {
+        let data: MutPtr<u32> = h.mem.alloc(8).cast();
+        h.mem.write(data, 0x11223344);
+        h.mem.write(data + 1, 0x55667788);
+        let unaligned: MutPtr<u8> = data.cast();
+        (unaligned + 1).to_bits()
+    }
+
+    const UNALIGNED_LOAD: &[u16] = &[
+        0x6801, // ldr r1, [r0]
+        THUMB_SVC_0,
+    ];
+
+    #[test]
+    fn unaligned_load_allowed() {
+        for unaligned_access in [UnalignedAccess::Allow, UnalignedAccess::Strict] {
+            let mut h = Harness::new_thumb_with_unaligned_access(UNALIGNED_LOAD, unaligned_access);
+            let addr = unaligned_data(&mut h);
+            let (regs, _) = run_both(&mut h, &[(0, addr)], 0);
+            assert_eq!(regs[1], 0x88112233);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "MemoryError")]
+    fn unaligned_load_fault() {
+        let mut h =
+            Harness::new_thumb_with_unaligned_access(UNALIGNED_LOAD, UnalignedAccess::Fault);
+        let addr = unaligned_data(&mut h);
+        assert_ne!(addr & 3, 0);
+        h.run(&[(0, addr)], 0, false);
+    }
+
+    #[test]
+    fn stack_guard_overflow() {
+        let mut h = Harness::new_thumb(&[
+            0xB401, // push {r0}
+            THUMB_SVC_0,
+        ]);
+        let stack_low_end = h.mem.alloc_thread_stack(0x1000);
+        h.cpu.set_pages_trapped(
+            stack_low_end - Mem::STACK_GUARD_SIZE,
+            Mem::STACK_GUARD_SIZE,
+            true,
+        );
+        // Pushing at the bottom of the stack is fine.
+        h.run(&[(Cpu::SP, stack_low_end + 4)], 0, false);
+        assert_eq!(h.mem.take_stack_overflow(), None);
+        // Pushing past it is a stack overflow.
+        h.cpu.regs_mut()[Cpu::SP] = stack_low_end;
+        h.cpu.branch(h.code);
+        let state = h.cpu.run_or_step(&mut h.mem, Some(&mut 100));
+        assert!(matches!(state, CpuState::Error(CpuError::MemoryError)));
+        assert_eq!(h.mem.take_stack_overflow(), Some(stack_low_end - 4));
+    }
+}
@@ -139,14 +139,17 @@ class DynarmicWrapper {
   std::unique_ptr<Dynarmic::A32::Jit> cpu;
   std::array<std::uint8_t *, Dynarmic::A32::UserConfig::NUM_PAGE_TABLE_ENTRIES>
       page_table;
+  std::uint8_t *direct_memory_access_ptr = nullptr;
 
 public:
-  DynarmicWrapper(void *direct_memory_access_ptr, size_t null_page_count) {
+  DynarmicWrapper(void *direct_memory_access_ptr, size_t null_page_count,
+                  bool detect_misaligned_access) {
     Dynarmic::A32::UserConfig user_config;
     user_config.callbacks = &env;
     // TODO: only do this in debug builds? it's probably expensive
     user_config.check_halt_on_memory_access = true;
     if (direct_memory_access_ptr) {
+      this->direct_memory_access_ptr = (std::uint8_t *)direct_memory_access_ptr;
       // Allow fast accesses to all pages other than the null page, which will
       // fall back to a memory callback, which will then abort execution.
       // TODO: Eventually we should use dynarmic's true fastmem mode, but that
@@ -166,6 +169,14 @@ public:
       }
       user_config.page_table = &page_table;
       user_config.absolute_offset_page_table = true;
+
+      if (detect_misaligned_access) {
+        // Make all unaligned accesses fall back to the memory callbacks, so
+        // they can be checked (see UnalignedAccess in src/mem.rs).
+        user_config.detect_misaligned_access_via_page_table = 8 | 16 | 32 | 64;
+        user_config.only_detect_misalignment_via_page_table_on_page_boundary =
+            false;
+      }
     }
     cpu = std::make_unique<Dynarmic::A32::Jit>(user_config);
     env.cpu = cpu.get();
@@ -181,6 +192,22 @@ public:
     cpu->InvalidateCacheRange(start, size);
   }
 
+  void set_pages_trapped(VAddr start, std::uint32_t size, bool trapped) {
+    // Without direct memory access, every access uses the memory callbacks
+    // anyway.
+    if (!direct_memory_access_ptr) {
+      return;
+    }
+    // The JIT code reads the page table on every access, so this takes
+    // effect immediately.
+    for (std::uint64_t page = start >> Dynarmic::A32::UserConfig::PAGE_BITS;
+         page < ((std::uint64_t)start + size) >>
+                    Dynarmic::A32::UserConfig::PAGE_BITS;
+         page++) {
+      page_table[page] = trapped ? nullptr : direct_memory_access_ptr;
+    }
+  }
+
   void swap_context(void *context) {
     Dynarmic::A32::Context tmp = cpu->SaveContext();
     cpu->LoadContext(*(Dynarmic::A32::Context *)context);
@@ -222,8 +249,10 @@ public:
 extern "C" {
 
 DynarmicWrapper *touchHLE_DynarmicWrapper_new(void *direct_memory_access_ptr,
-                                              size_t null_page_count) {
-  return new DynarmicWrapper(direct_memory_access_ptr, null_page_count);
+                                              size_t null_page_count,
+                                              bool detect_misaligned_access) {
+  return new DynarmicWrapper(direct_memory_access_ptr, null_page_count,
+                             detect_misaligned_access);
 }
 void touchHLE_DynarmicWrapper_delete(DynarmicWrapper *cpu) { delete cpu; }
 
@@ -254,6 +283,12 @@ void touchHLE_DynarmicWrapper_invalidate_cache_range(DynarmicWrapper *cpu,
   cpu->invalidate_cache_range(start, size);
 }
 
+void touchHLE_DynarmicWrapper_set_pages_trapped(DynarmicWrapper *cpu,
+                                                VAddr start, std::uint32_t size,
+                                                bool trapped) {
+  cpu->set_pages_trapped(start, size, trapped);
+}
+
 std::int32_t touchHLE_DynarmicWrapper_run_or_step(DynarmicWrapper *cpu,
                                                   touchHLE_Mem *mem,
                                                   std::uint64_t *ticks) {
@@ -0,0 +1,53 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Crash reports for when the app gives up on itself: failed assertions,
+//! `abort()` and uncaught exceptions.
+//!
+//! These are bugs in the app (or touchHLE not behaving like iPhone OS), so the
+//! report tries to say what went wrong rather than just stopping. The log
+//! output leading up to the crash is included because apps often log the
+//! details of a problem just before giving up. With `--crash-report-window`,
+//! the report is also shown to the user, who might not be looking at the log.
+
+use crate::log;
+use crate::Environment;
+
+/// Exit code used after a crash report, matching a process killed by
+/// `SIGABRT` on Unix-like systems.
+const CRASH_EXIT_CODE: i32 = 128 + crate::libc::signal::SIGABRT;
+
+/// Print a crash report for the current thread and terminate the app.
+/// `reason` should explain why, e.g. the assertion that failed.
+pub fn report_crash(env: &mut Environment, reason: &str) -> ! {
+    let recent_lines = log::recent_lines(0);
+
+    echo!();
+    echo!("===== Crash report =====");
+    echo!("{}", reason);
+    echo!();
+    let backtrace_start = log::line_count();
+    env.stack_trace();
+    let backtrace = log::recent_lines(backtrace_start);
+    echo!();
+    echo!("Most recent log output:");
+    for line in &recent_lines {
+        echo!("  {}", line);
+    }
+    echo!("========================");
+
+    if env.options.crash_report_window {
+        if let Some(window) = &env.window {
+            let message = format!(
+                "The app has crashed.\n\n{}\n\n{}\n\nSee the log for more details.",
+                reason,
+                backtrace.join("\n")
+            );
+            window.show_error_message("touchHLE crash report", &message);
+        }
+    }
+
+    env.exit(CRASH_EXIT_CODE)
+}
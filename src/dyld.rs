@@ -96,6 +96,8 @@ pub enum HostConstant {
     NSString(&'static str),
     NullPtr,
     Custom(fn(&mut Mem) -> ConstVoidPtr),
+    /// Like [HostConstant::Custom], for constants that depend on other state.
+    CustomWithEnv(fn(&mut Environment) -> ConstVoidPtr),
 }
 
 /// Type for lists of constants exported by host implementations of frameworks.
@@ -163,6 +165,9 @@ pub struct Dyld {
     thread_exit_routine: Option<GuestFunction>,
     constants_to_link_later: Vec<(MutPtr<ConstVoidPtr>, &'static HostConstant)>,
     non_lazy_host_functions: HashMap<&'static str, GuestFunction>,
+    /// Symbols that non-lazy linking couldn't resolve. See
+    /// [Self::unresolved_symbols].
+    unhandled_symbols: Vec<String>,
 }
 
 impl Dyld {
@@ -186,6 +191,7 @@ impl Dyld {
             thread_exit_routine: None,
             constants_to_link_later: Vec::new(),
             non_lazy_host_functions: HashMap::new(),
+            unhandled_symbols: Vec::new(),
         }
     }
 
@@ -218,12 +224,31 @@ impl Dyld {
             self.do_non_lazy_linking(bin, bins, mem, objc);
         }
 
+        objc.register_bin_protocols(&bins[0], mem);
         objc.register_bin_classes(&bins[0], mem);
         objc.register_bin_categories(&bins[0], mem);
 
         ns_string::register_constant_strings(&bins[0], mem, objc);
     }
 
+    /// Do linking-related tasks for a binary loaded at runtime with
+    /// `dlopen()`, which must be the last one in `bins`.
+    /// [Self::do_late_linking] must be called afterwards.
+    pub fn do_runtime_linking(&mut self, bins: &[MachO], mem: &mut Mem, objc: &mut ObjC) {
+        let bin = bins.last().unwrap();
+
+        objc.register_bin_selectors(bin, mem);
+
+        self.setup_lazy_linking(bin, mem);
+        self.do_non_lazy_linking(bin, bins, mem, objc);
+
+        objc.register_bin_protocols(bin, mem);
+        objc.register_bin_classes(bin, mem);
+        objc.register_bin_categories(bin, mem);
+
+        ns_string::register_constant_strings(bin, mem, objc);
+    }
+
     /// [Self::do_initial_linking] but for when this is the app picker's special
     /// environment with no binary (see [crate::Environment::new_without_app]).
     pub fn do_initial_linking_with_no_bins(&mut self, mem: &mut Mem, objc: &mut ObjC) {
@@ -312,6 +337,12 @@ impl Dyld {
                 objc.link_class(name, /* is_metaclass: */ true, mem)
                     .cast()
                     .cast_const()
+            } else if let Some(class) = objc.link_block_class(name, mem) {
+                // Used as the isa of global blocks
+                class.cast().cast_const()
+            } else if let Some(type_info) = objc.link_ehtype_symbol(name, mem) {
+                // Used by @catch blocks
+                type_info
             } else if name == "___CFConstantStringClassReference" {
                 // See ns_string::register_constant_strings
                 nil.cast().cast_const()
@@ -342,6 +373,7 @@ impl Dyld {
         // Collecting unhandled relocations for the same symbol onto one line
         // makes the log output much less spammy.
         for (name, addrs) in unhandled_relocations {
+            self.unhandled_symbols.push(name.to_string());
             log!(
                 "Warning: unhandled external relocation {:?} in {:?} at {}",
                 name,
@@ -377,6 +409,18 @@ impl Dyld {
                 }
             }
 
+            if let Some(class) = objc.link_block_class(symbol, mem) {
+                // Used as the isa of stack blocks
+                mem.write(ptr_ptr, class.cast().cast_const());
+                continue;
+            }
+
+            if let Some(type_info) = objc.link_ehtype_symbol(symbol, mem) {
+                // Used by @catch blocks
+                mem.write(ptr_ptr, type_info);
+                continue;
+            }
+
             if let Some((symbol, _)) = search_lists(function_lists::FUNCTION_LISTS, symbol) {
                 // We want the same symbol name to always point to the same
                 // function. It could point to a specific stub entry, but it's
@@ -402,6 +446,7 @@ impl Dyld {
                 continue;
             }
 
+            self.unhandled_symbols.push(symbol.to_string());
             log!(
                 "Warning: unhandled non-lazy symbol {:?} at {:?} in \"{}\"",
                 symbol,
@@ -413,6 +458,33 @@ impl Dyld {
         // FIXME: check for internal relocations?
     }
 
+    /// Get the symbols imported by the binaries that can't be resolved, sorted
+    /// by name. Non-lazy symbols are resolved when the binaries are linked,
+    /// but for lazy symbols (functions), this predicts what will happen if the
+    /// app calls them. This is used by `--probe`.
+    pub fn unresolved_symbols(&self, bins: &[MachO]) -> Vec<String> {
+        let mut symbols: Vec<String> = self.unhandled_symbols.clone();
+        for bin in bins {
+            let Some(stubs) = bin.get_section(SectionType::SymbolStubs) else {
+                continue;
+            };
+            let info = stubs.dyld_indirect_symbol_info.as_ref().unwrap();
+            for symbol in info.indirect_undef_symbols.iter().flatten() {
+                let resolved = self.non_lazy_host_functions.contains_key(symbol.as_str())
+                    || search_lists(function_lists::FUNCTION_LISTS, symbol).is_some()
+                    || bins
+                        .iter()
+                        .any(|bin| bin.exported_symbols.contains_key(symbol));
+                if !resolved {
+                    symbols.push(symbol.clone());
+                }
+            }
+        }
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
     /// Do linking that can only be done once there is a full [Environment].
     /// Not to be confused with lazy linking.
     pub fn do_late_linking(env: &mut Environment) {
@@ -432,14 +504,17 @@ impl Dyld {
                     null_ptr_ptr.cast().cast_const()
                 }
                 HostConstant::Custom(f) => f(&mut env.mem),
+                HostConstant::CustomWithEnv(f) => f(env),
             };
             env.mem.write(symbol_ptr_ptr, symbol_ptr.cast());
         }
     }
 
-    /// Return a host function that can be called to handle an SVC instruction
-    /// encountered during CPU emulation. If `None` is returned, the execution
-    /// needs to resume at `svc_pc`.
+    /// Return a host function (and its symbol) that can be called to handle an
+    /// SVC instruction encountered during CPU emulation. If `None` is
+    /// returned, the execution needs to resume at `svc_pc`. If the app is
+    /// calling a function that isn't implemented, its name is returned as the
+    /// error.
     pub fn get_svc_handler(
         &mut self,
         bins: &[MachO],
@@ -447,7 +522,7 @@ impl Dyld {
         cpu: &mut Cpu,
         svc_pc: u32,
         svc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Result<Option<(&'static str, HostFunction)>, String> {
         match svc {
             Self::SVC_LAZY_LINK => self.do_lazy_link(bins, mem, cpu, svc_pc),
             Self::SVC_THREAD_EXIT | Self::SVC_RETURN_TO_HOST => unreachable!(), // don't handle here
@@ -459,7 +534,7 @@ impl Dyld {
                     panic!("Unexpected SVC #{} at {:#x}", svc, svc_pc);
                 };
                 log_dbg!("Call to host function, already linked: {}", symbol);
-                Some(f)
+                Ok(Some((symbol, f)))
             }
         }
     }
@@ -470,7 +545,7 @@ impl Dyld {
         mem: &mut Mem,
         cpu: &mut Cpu,
         svc_pc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Result<Option<(&'static str, HostFunction)>, String> {
         // Links by restoring the original stub function, then updating
         // __la_symbol_ptr to the appropriate function.
         fn link_by_restoring_stub(
@@ -543,7 +618,7 @@ impl Dyld {
             );
             // The stub jumps to the non-lazy function, which calls the
             // host function.
-            return None;
+            return Ok(None);
         }
 
         if let Some(&(symbol, f)) = search_lists(function_lists::FUNCTION_LISTS, symbol) {
@@ -567,7 +642,7 @@ impl Dyld {
 
             // Return the host function so that we can call it now that we're
             // done.
-            return Some(f);
+            return Ok(Some((symbol, f)));
         }
 
         for dylib in bins.iter() {
@@ -583,11 +658,11 @@ impl Dyld {
                     dylib.name
                 );
                 // Tell the caller it needs to restart execution at svc_pc.
-                return None;
+                return Ok(None);
             }
         }
 
-        panic!("Call to unimplemented function {}", symbol);
+        Err(symbol.to_string())
     }
 
     /// Creates a guest function that will call a host function with the name
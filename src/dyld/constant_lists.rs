@@ -7,15 +7,24 @@
 //! very long and frequently-updated list.
 
 use crate::frameworks::{
-    core_foundation, core_graphics, foundation, media_player, opengles, uikit,
+    av_audio, core_animation, core_foundation, core_graphics, foundation, libxml2, media_player,
+    opengles, security, uikit,
 };
 use crate::libc;
 
 /// All the lists of constants that the linker should search through.
 pub const CONSTANT_LISTS: &[super::ConstantExports] = &[
     libc::ctype::CONSTANTS,
+    libc::dispatch::CONSTANTS,
+    libc::mach_task::CONSTANTS,
     libc::stdio::CONSTANTS,
+    libc::stdlib::CONSTANTS,
+    libc::time::CONSTANTS,
+    av_audio::av_audio_recorder::CONSTANTS,
+    core_animation::ca_layer::CONSTANTS,
     core_foundation::cf_allocator::CONSTANTS,
+    core_foundation::cf_locale::CONSTANTS,
+    core_foundation::cf_number::CONSTANTS,
     core_foundation::cf_run_loop::CONSTANTS,
     core_graphics::cg_affine_transform::CONSTANTS,
     core_graphics::cg_color_space::CONSTANTS,
@@ -25,7 +34,12 @@ pub const CONSTANT_LISTS: &[super::ConstantExports] = &[
     foundation::ns_keyed_unarchiver::CONSTANTS,
     foundation::ns_locale::CONSTANTS,
     foundation::ns_run_loop::CONSTANTS,
+    libxml2::CONSTANTS,
     media_player::movie_player::CONSTANTS,
     opengles::eagl::CONSTANTS,
+    security::sec_item::CONSTANTS,
+    security::sec_random::CONSTANTS,
+    uikit::ui_accessibility::CONSTANTS,
+    uikit::ui_application::CONSTANTS,
     uikit::ui_device::CONSTANTS,
 ];
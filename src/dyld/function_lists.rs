@@ -7,30 +7,41 @@
 //! very long and frequently-updated list.
 
 use crate::frameworks::{
-    audio_toolbox, core_foundation, core_graphics, dnssd, foundation, openal, opengles, uikit,
+    audio_toolbox, common_crypto, core_foundation, core_graphics, dnssd, foundation, libxml2,
+    openal, opengles, security, sqlite3, uikit, zlib,
 };
 use crate::libc;
 
 /// All the lists of functions that the linker should search through.
 pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
+    libc::assert::FUNCTIONS,
     libc::clocale::FUNCTIONS,
     libc::ctype::FUNCTIONS,
     libc::cxxabi::FUNCTIONS,
     libc::dirent::FUNCTIONS,
+    libc::dispatch::FUNCTIONS,
     libc::dlfcn::FUNCTIONS,
+    libc::err::FUNCTIONS,
     libc::errno::FUNCTIONS,
     libc::ifaddrs::FUNCTIONS,
     libc::keymgr::FUNCTIONS,
+    libc::mach_semaphore::FUNCTIONS,
+    libc::mach_task::FUNCTIONS,
     libc::mach_thread_info::FUNCTIONS,
     libc::mach_time::FUNCTIONS,
     libc::math::FUNCTIONS,
     libc::mmap::FUNCTIONS,
     libc::net::if_::FUNCTIONS,
+    libc::netdb::FUNCTIONS,
+    libc::notify::FUNCTIONS,
     libc::posix_io::FUNCTIONS,
+    libc::posix_io::poll::FUNCTIONS,
     libc::posix_io::stat::FUNCTIONS,
+    libc::pthread::cond::FUNCTIONS,
     libc::pthread::key::FUNCTIONS,
     libc::pthread::mutex::FUNCTIONS,
     libc::pthread::once::FUNCTIONS,
+    libc::pthread::rwlock::FUNCTIONS,
     libc::pthread::thread::FUNCTIONS,
     libc::semaphore::FUNCTIONS,
     libc::setjmp::FUNCTIONS,
@@ -42,22 +53,31 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     libc::string::FUNCTIONS,
     libc::sys::utsname::FUNCTIONS,
     libc::sysctl::FUNCTIONS,
+    libc::syslog::FUNCTIONS,
     libc::time::FUNCTIONS,
     libc::unistd::FUNCTIONS,
+    libc::unwind::FUNCTIONS,
     libc::wchar::FUNCTIONS,
     crate::objc::FUNCTIONS,
     audio_toolbox::audio_file::FUNCTIONS,
     audio_toolbox::audio_queue::FUNCTIONS,
     audio_toolbox::audio_services::FUNCTIONS,
     audio_toolbox::audio_session::FUNCTIONS,
+    common_crypto::common_cryptor::FUNCTIONS,
+    common_crypto::common_digest::FUNCTIONS,
+    common_crypto::common_hmac::FUNCTIONS,
     core_foundation::cf_array::FUNCTIONS,
     core_foundation::cf_bundle::FUNCTIONS,
     core_foundation::cf_data::FUNCTIONS,
+    core_foundation::cf_locale::FUNCTIONS,
+    core_foundation::cf_notification_center::FUNCTIONS,
+    core_foundation::cf_number::FUNCTIONS,
     core_foundation::cf_run_loop::FUNCTIONS,
     core_foundation::cf_run_loop_timer::FUNCTIONS,
     core_foundation::cf_string::FUNCTIONS,
     core_foundation::cf_type::FUNCTIONS,
     core_foundation::cf_url::FUNCTIONS,
+    core_foundation::cf_uuid::FUNCTIONS,
     core_foundation::time::FUNCTIONS,
     core_graphics::cg_affine_transform::FUNCTIONS,
     core_graphics::cg_bitmap_context::FUNCTIONS,
@@ -67,12 +87,21 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     core_graphics::cg_geometry::FUNCTIONS,
     core_graphics::cg_image::FUNCTIONS,
     dnssd::FUNCTIONS,
+    foundation::ns_enumerator::FUNCTIONS,
+    foundation::ns_exception::FUNCTIONS,
     foundation::ns_file_manager::FUNCTIONS,
     foundation::ns_log::FUNCTIONS,
     foundation::ns_objc_runtime::FUNCTIONS,
+    foundation::ns_range::FUNCTIONS,
+    libxml2::FUNCTIONS,
     openal::FUNCTIONS,
     opengles::FUNCTIONS,
+    security::sec_item::FUNCTIONS,
+    security::sec_random::FUNCTIONS,
+    sqlite3::FUNCTIONS,
+    uikit::ui_accessibility::FUNCTIONS,
     uikit::ui_application::FUNCTIONS,
     uikit::ui_geometry::FUNCTIONS,
     uikit::ui_graphics::FUNCTIONS,
+    zlib::FUNCTIONS,
 ];
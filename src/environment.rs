@@ -8,19 +8,25 @@
 //! Unlike its siblings, this module should be considered private and only used
 //! via the re-exports one level up.
 
+mod cpu_speed_limit;
 mod mutex;
+mod power_governor;
 
 use crate::abi::GuestRet;
+use crate::libc::errno::ETIMEDOUT;
+use crate::libc::pthread::cond::CondId;
+use crate::libc::pthread::rwlock::RwLockId;
 use crate::libc::semaphore::sem_t;
 use crate::mem::{MutPtr, MutVoidPtr};
 use crate::{
-    abi, bundle, cpu, dyld, frameworks, fs, gdb, image, libc, mach_o, mem, objc, options, stack,
-    window,
+    abi, api_stats, bundle, cpu, dyld, frameworks, fs, gdb, image, libc, mach_o, mem, objc,
+    options, stack, window,
 };
 use std::net::TcpListener;
 use std::time::{Duration, Instant};
 
 pub use mutex::{MutexId, MutexType, PTHREAD_MUTEX_DEFAULT};
+pub use power_governor::PowerSaving;
 
 /// Index into the [Vec] of threads. Thread 0 is always the main thread.
 pub type ThreadId = usize;
@@ -54,6 +60,9 @@ pub struct Thread {
     /// frame" of the thread is a host function, not whether there are any host
     /// functions at all.
     in_host_function: bool,
+    /// Name of the host function the thread is currently in, if any. Like
+    /// [Self::in_host_function], this only reflects the top-most "stack frame".
+    host_function: Option<&'static str>,
     /// Context object containing the CPU state for this thread.
     ///
     /// There should always be `(threads.len() - 1)` contexts in existence.
@@ -64,6 +73,57 @@ pub struct Thread {
     /// Address range of this thread's stack, used to check if addresses are in
     /// range while producing a stack trace.
     stack: Option<std::ops::RangeInclusive<u32>>,
+    /// The guest stack pointer at the start of each host-to-guest call that is
+    /// in progress on this thread, outermost first. Guest stack frames below
+    /// one of these were created by that call. See [Environment::long_jump].
+    host_call_stack_pointers: Vec<u32>,
+    /// Value to put in r0 when this thread next resumes, replacing the return
+    /// value of the host function that blocked it. This is how a timed-out
+    /// condition variable wait reports `ETIMEDOUT`.
+    resume_return_value: Option<u32>,
+}
+
+/// How a dynamic library that an app depends on is provided by touchHLE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DylibKind {
+    /// There is a host implementation of the library, e.g. `libSystem`.
+    HostImplementation,
+    /// Some Free Software libraries are bundled with touchHLE and exposed via
+    /// the guest file system (see [fs::Fs::new]).
+    Bundled,
+    /// System frameworks will have host implementations.
+    /// TODO: warn about unimplemented frameworks?
+    SystemFramework,
+    Unexpected,
+}
+
+pub fn classify_dylib(dylib: &str, fs: &fs::Fs) -> DylibKind {
+    if dylib == "/usr/lib/libSystem.B.dylib"
+        || dylib == "/usr/lib/libobjc.A.dylib"
+        || dylib.starts_with("/usr/lib/libsqlite3.")
+        || dylib.starts_with("/usr/lib/libxml2.")
+        || dylib.starts_with("/usr/lib/libz.")
+    {
+        DylibKind::HostImplementation
+    } else if fs.is_file(fs::GuestPath::new(dylib)) {
+        DylibKind::Bundled
+    } else if dylib.starts_with("/System/Library/Frameworks/")
+        || dylib.starts_with("/System/Library/PrivateFrameworks/")
+    {
+        DylibKind::SystemFramework
+    } else {
+        DylibKind::Unexpected
+    }
+}
+
+/// Panic payload used by [Environment::long_jump].
+struct LongJump {
+    thread: ThreadId,
+    /// Number of host-to-guest calls on the thread that are kept.
+    depth: usize,
+    sp: u32,
+    fp: u32,
+    pc: abi::GuestFunction,
 }
 
 impl Thread {
@@ -94,6 +154,12 @@ pub struct Environment {
     pub framework_state: frameworks::State,
     pub mutex_state: mutex::MutexState,
     pub options: options::Options,
+    /// Only present when `--api-stats=` is used.
+    pub api_stats: Option<api_stats::ApiStats>,
+    /// Only present when `--limit-cpu-speed` is used.
+    cpu_speed_limit: Option<cpu_speed_limit::CpuSpeedLimit>,
+    /// Only present when `--power-saving=` is used.
+    power_governor: Option<power_governor::PowerGovernor>,
     gdb_server: Option<gdb::GdbServer>,
 }
 
@@ -122,6 +188,11 @@ enum ThreadBlock {
     Semaphore(MutPtr<sem_t>),
     // Thread is waiting for another thread to finish (joining).
     Joining(ThreadId, MutPtr<MutVoidPtr>),
+    // Thread is waiting on a condition variable (until Instant, if given).
+    // The mutex is relocked once it wakes up.
+    Condition(CondId, MutexId, Option<Instant>),
+    // Thread is waiting to lock a read-write lock (for writing if true).
+    RwLock(RwLockId, bool),
     // Deferred guest-to-host return
     DeferredReturn,
 }
@@ -137,12 +208,21 @@ impl Environment {
     /// when allocating a second [mem::Mem] instance.
     pub fn new(
         bundle: bundle::Bundle,
-        fs: fs::Fs,
-        options: options::Options,
+        mut fs: fs::Fs,
+        mut options: options::Options,
         env_for_salvage: Option<Environment>,
     ) -> Result<Environment, String> {
         let startup_time = Instant::now();
 
+        let power_governor = options.power_saving.map(power_governor::PowerGovernor::new);
+        if let Some(ref power_governor) = power_governor {
+            power_governor.apply_to_launch_options(&mut options);
+        }
+
+        if options.jailbroken {
+            fs.add_jailbreak_files();
+        }
+
         // Extract things to salvage from the old environment, and then drop it.
         // This needs to be done before creating a new window, because SDL2 only
         // allows one window at once.
@@ -192,34 +272,37 @@ impl Environment {
         } else {
             mem::Mem::new()
         };
+        mem.set_heap_limit(options.heap_limit_mib.map(|mib| mib.get() * 1024 * 1024));
+        mem.set_unaligned_access(options.unaligned_access);
+        let main_thread_stack_low_end = mem.set_up_main_thread_stack(
+            mem::Mem::MAIN_THREAD_STACK_SIZE * options.stack_size_multiplier.get(),
+        );
 
-        let executable = mach_o::MachO::load_from_file(bundle.executable_path(), &fs, &mut mem)
-            .map_err(|e| format!("Could not load executable: {}", e))?;
+        let executable =
+            mach_o::MachO::load_from_file(bundle.executable_path(), &fs, &mut mem, false)
+                .map_err(|e| format!("Could not load executable: {}", e))?;
 
         let mut dylibs = Vec::new();
         for dylib in &executable.dynamic_libraries {
-            if dylib == "/usr/lib/libSystem.B.dylib" || dylib == "/usr/lib/libobjc.A.dylib" {
-                // We have host implementations of these
-                continue;
-            }
-
-            // There are some Free Software libraries bundled with touchHLE and
-            // exposed via the guest file system (see Fs::new()).
-            if fs.is_file(fs::GuestPath::new(dylib)) {
-                let dylib = mach_o::MachO::load_from_file(fs::GuestPath::new(dylib), &fs, &mut mem)
+            match classify_dylib(dylib, &fs) {
+                DylibKind::HostImplementation | DylibKind::SystemFramework => (),
+                DylibKind::Bundled => {
+                    let dylib = mach_o::MachO::load_from_file(
+                        fs::GuestPath::new(dylib),
+                        &fs,
+                        &mut mem,
+                        false,
+                    )
                     .map_err(|e| format!("Could not load bundled dylib: {}", e))?;
-                dylibs.push(dylib);
-            } else {
-                // System frameworks will have host implementations.
-                // TODO: warn about unimplemented frameworks?
-                if !dylib.starts_with("/System/Library/Frameworks/") {
+                    dylibs.push(dylib);
+                }
+                DylibKind::Unexpected => {
                     log!(
                         "Warning: app binary depends on unexpected dylib \"{}\"",
                         dylib
                     );
                 }
-                continue;
-            };
+            }
         }
 
         let entry_point_addr = executable.entry_point_pc.ok_or_else(|| {
@@ -238,10 +321,15 @@ impl Environment {
         let mut dyld = dyld::Dyld::new();
         dyld.do_initial_linking(&bins, &mut mem, &mut objc);
 
-        let cpu = cpu::Cpu::new(match options.direct_memory_access {
+        let mut cpu = cpu::Cpu::new(match options.direct_memory_access {
             true => Some(&mut mem),
             false => None,
         });
+        cpu.set_pages_trapped(
+            main_thread_stack_low_end - mem::Mem::STACK_GUARD_SIZE,
+            mem::Mem::STACK_GUARD_SIZE,
+            true,
+        );
 
         let main_thread = Thread {
             active: true,
@@ -249,8 +337,11 @@ impl Environment {
             return_value: None,
             in_start_routine: false, // main thread never terminates
             in_host_function: false,
+            host_function: None,
             context: None,
-            stack: Some(mem::Mem::MAIN_THREAD_STACK_LOW_END..=0u32.wrapping_sub(1)),
+            stack: Some(main_thread_stack_low_end..=0u32.wrapping_sub(1)),
+            host_call_stack_pointers: Vec::new(),
+            resume_return_value: None,
         };
 
         let mut env = Environment {
@@ -268,6 +359,11 @@ impl Environment {
             libc_state: Default::default(),
             mutex_state: Default::default(),
             framework_state: Default::default(),
+            api_stats: options.api_stats_path.is_some().then(Default::default),
+            cpu_speed_limit: options
+                .limit_cpu_speed
+                .then(|| cpu_speed_limit::CpuSpeedLimit::new(options.device_model.cpu_mhz)),
+            power_governor,
             options,
             gdb_server: None,
         };
@@ -309,6 +405,9 @@ impl Environment {
 
         echo!("CPU emulation begins now.");
 
+        // As on a real device, +load methods run before static initializers.
+        objc::call_load_methods(&mut env);
+
         // Static initializers for libraries must be run before the initializer
         // in the app binary.
         // TODO: once we support more libraries, replace this hard-coded order
@@ -363,6 +462,9 @@ impl Environment {
         ));
 
         let mut mem = mem::Mem::new();
+        let main_thread_stack_low_end = mem.set_up_main_thread_stack(
+            mem::Mem::MAIN_THREAD_STACK_SIZE * options.stack_size_multiplier.get(),
+        );
 
         let bins = Vec::new();
 
@@ -371,10 +473,15 @@ impl Environment {
         let mut dyld = dyld::Dyld::new();
         dyld.do_initial_linking_with_no_bins(&mut mem, &mut objc);
 
-        let cpu = cpu::Cpu::new(match options.direct_memory_access {
+        let mut cpu = cpu::Cpu::new(match options.direct_memory_access {
             true => Some(&mut mem),
             false => None,
         });
+        cpu.set_pages_trapped(
+            main_thread_stack_low_end - mem::Mem::STACK_GUARD_SIZE,
+            mem::Mem::STACK_GUARD_SIZE,
+            true,
+        );
 
         let main_thread = Thread {
             active: true,
@@ -382,8 +489,11 @@ impl Environment {
             return_value: None,
             in_start_routine: false, // main thread never terminates
             in_host_function: false,
+            host_function: None,
             context: None,
-            stack: Some(mem::Mem::MAIN_THREAD_STACK_LOW_END..=0u32.wrapping_sub(1)),
+            stack: Some(main_thread_stack_low_end..=0u32.wrapping_sub(1)),
+            host_call_stack_pointers: Vec::new(),
+            resume_return_value: None,
         };
 
         let mut env = Environment {
@@ -402,6 +512,9 @@ impl Environment {
             mutex_state: Default::default(),
             framework_state: Default::default(),
             options,
+            api_stats: None,
+            cpu_speed_limit: None,
+            power_governor: None,
             gdb_server: None,
         };
 
@@ -441,7 +554,45 @@ impl Environment {
         )
     }
 
-    fn stack_trace(&self) {
+    /// Get the symbol name (e.g. `_glEnable`) of the host function that the
+    /// current thread is executing, if any.
+    pub fn current_host_function(&self) -> Option<&'static str> {
+        self.threads[self.current_thread].host_function
+    }
+
+    /// Describe an address in guest code for a stack trace, including the
+    /// nearest preceding exported symbol of the binary it's in, if any. Most
+    /// functions aren't exported, so the symbol is only a hint.
+    pub fn describe_code_addr(&self, addr: u32) -> String {
+        let addr_without_thumb_bit = addr & !1;
+        let Some(bin) = self.bins.iter().find(|bin| {
+            bin.sections.iter().any(|section| {
+                (section.addr..section.addr + section.size).contains(&addr_without_thumb_bit)
+            })
+        }) else {
+            return format!("{:#x}", addr);
+        };
+        let symbol = bin
+            .exported_symbols
+            .iter()
+            .map(|(name, &symbol_addr)| (name, symbol_addr & !1))
+            .filter(|&(_, symbol_addr)| symbol_addr <= addr_without_thumb_bit)
+            .max_by_key(|&(_, symbol_addr)| symbol_addr);
+        if let Some((name, symbol_addr)) = symbol {
+            format!(
+                "{:#x} ({}+{:#x} in {})",
+                addr,
+                name,
+                addr_without_thumb_bit - symbol_addr,
+                bin.name
+            )
+        } else {
+            format!("{:#x} (in {})", addr, bin.name)
+        }
+    }
+
+    /// Print a stack trace for the current thread's guest code.
+    pub fn stack_trace(&self) {
         if self.current_thread == 0 {
             echo!("Attempting to produce stack trace for main thread:");
         } else {
@@ -452,8 +603,8 @@ impl Environment {
         }
         let stack_range = self.threads[self.current_thread].stack.clone().unwrap();
         echo!(
-            " 0. {:#x} (PC)",
-            self.cpu.pc_with_thumb_bit().addr_with_thumb_bit()
+            " 0. {} (PC)",
+            self.describe_code_addr(self.cpu.pc_with_thumb_bit().addr_with_thumb_bit())
         );
         let regs = self.cpu.regs();
         let mut lr = regs[cpu::Cpu::LR];
@@ -465,7 +616,7 @@ impl Environment {
             echo!(" 1. [thread exit] (LR)");
             return;
         } else {
-            echo!(" 1. {:#x} (LR)", lr);
+            echo!(" 1. {} (LR)", self.describe_code_addr(lr));
         }
         let mut i = 2;
         let mut fp: mem::ConstPtr<u8> = mem::Ptr::from_bits(regs[abi::FRAME_POINTER]);
@@ -482,23 +633,76 @@ impl Environment {
                 echo!("{:2}. [thread exit]", i);
                 return;
             } else {
-                echo!("{:2}. {:#x}", i, lr);
+                echo!("{:2}. {}", i, self.describe_code_addr(lr));
             }
             i += 1;
         }
     }
 
+    /// Print information about a stack overflow, see
+    /// [mem::Mem::take_stack_overflow].
+    fn report_stack_overflow(&self, addr: u32) {
+        let overflowing_thread = self.threads.iter().position(|thread| {
+            thread.stack.as_ref().is_some_and(|stack| {
+                (stack.start() - mem::Mem::STACK_GUARD_SIZE..*stack.start()).contains(&addr)
+            })
+        });
+        if let Some(thread_id) = overflowing_thread {
+            echo!(
+                "Thread {} overflowed its stack! The access at {:#x} is below the end of the stack ({:#x}). SP is {:#x}.",
+                thread_id,
+                addr,
+                self.threads[thread_id].stack.as_ref().unwrap().start(),
+                self.cpu.regs()[cpu::Cpu::SP]
+            );
+        } else {
+            echo!(
+                "Stack overflow! The access at {:#x} is in a stack guard region.",
+                addr
+            );
+        }
+        // Stack memory starts out zeroed, so the lowest non-zero byte gives
+        // the peak usage (or an underestimate of it).
+        echo!("Peak stack usage of each thread:");
+        for (thread_id, thread) in self.threads.iter().enumerate() {
+            let Some(stack) = thread.stack.as_ref() else {
+                continue;
+            };
+            let size = stack.end() - stack.start() + 1;
+            let stack_low_end: mem::ConstPtr<u8> = mem::Ptr::from_bits(*stack.start());
+            let bytes = self.mem.bytes_at(stack_low_end, size);
+            let unused = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+            let used = size - unused as u32;
+            echo!(
+                " Thread {}: {} KiB of {} KiB ({}%)",
+                thread_id,
+                used / 1024,
+                size / 1024,
+                (used as u64 * 100) / size as u64
+            );
+        }
+        echo!("If the app needs bigger stacks, try the --stack-size-multiplier= option.");
+    }
+
     /// Create a new thread and return its ID. The `start_routine` and
     /// `user_data` arguments have the same meaning as the last two arguments to
-    /// `pthread_create`.
+    /// `pthread_create`. The default stack size is
+    /// [mem::Mem::SECONDARY_THREAD_STACK_SIZE]. The stack size is multiplied
+    /// by the `--stack-size-multiplier=` option.
     pub fn new_thread(
         &mut self,
         start_routine: abi::GuestFunction,
         user_data: mem::MutVoidPtr,
+        stack_size: mem::GuestUSize,
     ) -> ThreadId {
-        let stack_size = mem::Mem::SECONDARY_THREAD_STACK_SIZE;
-        let stack_alloc = self.mem.alloc(stack_size);
-        let stack_high_addr = stack_alloc.to_bits() + stack_size;
+        let stack_size = stack_size * self.options.stack_size_multiplier.get();
+        let stack_low_end = self.mem.alloc_thread_stack(stack_size);
+        self.cpu.set_pages_trapped(
+            stack_low_end - mem::Mem::STACK_GUARD_SIZE,
+            mem::Mem::STACK_GUARD_SIZE,
+            true,
+        );
+        let stack_high_addr = stack_low_end + stack_size;
         assert!(stack_high_addr % 4 == 0);
 
         self.threads.push(Thread {
@@ -507,12 +711,15 @@ impl Environment {
             return_value: None,
             in_start_routine: true,
             in_host_function: false,
+            host_function: None,
             context: Some(cpu::CpuContext::new()),
-            stack: Some(stack_alloc.to_bits()..=(stack_high_addr - 1)),
+            stack: Some(stack_low_end..=(stack_high_addr - 1)),
+            host_call_stack_pointers: Vec::new(),
+            resume_return_value: None,
         });
         let new_thread_id = self.threads.len() - 1;
 
-        log_dbg!("Created new thread {} with stack {:#x}–{:#x}, will execute function {:?} with data {:?}", new_thread_id, stack_alloc.to_bits(), (stack_high_addr - 1), start_routine, user_data);
+        log_dbg!("Created new thread {} with stack {:#x}–{:#x}, will execute function {:?} with data {:?}", new_thread_id, stack_low_end, (stack_high_addr - 1), start_routine, user_data);
 
         let old_thread = self.current_thread;
 
@@ -671,6 +878,90 @@ impl Environment {
         self.threads[self.current_thread].blocked_by = ThreadBlock::Joining(joinee_thread, ptr);
     }
 
+    /// Blocks the current thread until the condition variable is signalled or
+    /// `until` is reached, then relocks the mutex. The caller is responsible
+    /// for unlocking the mutex beforehand.
+    ///
+    /// Also note that like [Self::sleep], this only takes effect after the host
+    /// function returns to the main run loop ([Environment::run]).
+    pub fn wait_on_condition(
+        &mut self,
+        cond_id: CondId,
+        mutex_id: MutexId,
+        until: Option<Instant>,
+    ) {
+        assert!(matches!(
+            self.threads[self.current_thread].blocked_by,
+            ThreadBlock::NotBlocked
+        ));
+        log_dbg!(
+            "Thread {} waiting on condition variable #{} with mutex #{}.",
+            self.current_thread,
+            cond_id,
+            mutex_id
+        );
+        self.threads[self.current_thread].blocked_by =
+            ThreadBlock::Condition(cond_id, mutex_id, until);
+    }
+
+    /// Wakes one thread waiting on the condition variable, or all of them if
+    /// `broadcast` is set. Returns whether any thread was waiting.
+    pub fn signal_condition(&mut self, cond_id: CondId, broadcast: bool) -> bool {
+        let mut woke_any = false;
+        for i in 0..self.threads.len() {
+            let ThreadBlock::Condition(waiting_on, mutex_id, _) = self.threads[i].blocked_by else {
+                continue;
+            };
+            if waiting_on != cond_id {
+                continue;
+            }
+            log_dbg!(
+                "Thread {} woken by condition variable #{}, waiting on mutex #{}.",
+                i,
+                cond_id,
+                mutex_id
+            );
+            self.requeue_on_mutex(i, mutex_id);
+            woke_any = true;
+            if !broadcast {
+                break;
+            }
+        }
+        woke_any
+    }
+
+    /// Returns whether any thread is waiting on the condition variable.
+    pub fn threads_waiting_on_condition(&self, cond_id: CondId) -> bool {
+        self.threads.iter().any(|thread| {
+            matches!(thread.blocked_by, ThreadBlock::Condition(waiting_on, _, _) if waiting_on == cond_id)
+        })
+    }
+
+    /// Block the current thread until it can lock the read-write lock.
+    ///
+    /// Also note that like [Self::sleep], this only takes effect after the host
+    /// function returns to the main run loop ([Environment::run]).
+    pub fn block_on_rwlock(&mut self, rwlock_id: RwLockId, write: bool) {
+        assert!(matches!(
+            self.threads[self.current_thread].blocked_by,
+            ThreadBlock::NotBlocked
+        ));
+        log_dbg!(
+            "Thread {} blocking on read-write lock #{} (write: {}).",
+            self.current_thread,
+            rwlock_id,
+            write
+        );
+        self.threads[self.current_thread].blocked_by = ThreadBlock::RwLock(rwlock_id, write);
+    }
+
+    /// Exit touchHLE because the app has exited (or been terminated), after
+    /// writing out anything that's wanted at exit.
+    pub fn exit(&mut self, exit_code: i32) -> ! {
+        api_stats::write_report(self);
+        std::process::exit(exit_code);
+    }
+
     /// Run the emulator. This is the main loop and won't return until app exit.
     /// Only `main.rs` should call this.
     pub fn run(&mut self) {
@@ -681,6 +972,7 @@ impl Environment {
             echo!("Register state immediately after panic:");
             self.cpu.dump_regs();
             self.stack_trace();
+            api_stats::write_report(self);
             std::panic::resume_unwind(e);
         }
     }
@@ -694,11 +986,53 @@ impl Environment {
         let was_in_host_function = self.threads[self.current_thread].in_host_function;
         let old_thread = self.current_thread;
         self.threads[self.current_thread].in_host_function = false;
-        self.run_inner(false);
+        let sp = self.cpu.regs()[cpu::Cpu::SP];
+        self.threads[old_thread].host_call_stack_pointers.push(sp);
+        // The call might be abandoned by Self::long_jump().
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_inner(false)));
+        self.threads[old_thread].host_call_stack_pointers.pop();
+        if let Err(payload) = res {
+            std::panic::resume_unwind(payload);
+        }
         assert!(self.current_thread == old_thread);
         self.threads[self.current_thread].in_host_function = was_in_host_function;
     }
 
+    /// Make the current thread continue execution at `pc` with the stack
+    /// pointer `sp` and frame pointer `fp`, abandoning everything that was
+    /// called since that stack frame, like `longjmp()` does. This is needed for
+    /// exception unwinding (see [crate::libc::unwind]).
+    ///
+    /// The frame might belong to guest code that called a host function that
+    /// called the current guest code, so host stack frames may need to be
+    /// abandoned too. This is done by panicking with a special payload that is
+    /// caught by the host function call that came from the frame's guest code.
+    /// Host code that gets unwound this way has no chance to clean up, but
+    /// that's also true of the system frameworks on a real device.
+    pub fn long_jump(&mut self, sp: u32, fp: u32, pc: abi::GuestFunction) -> ! {
+        // Host-to-guest calls that started above the frame were made by it or
+        // by one of its callers, so their host code has to be kept.
+        let depth = self.threads[self.current_thread]
+            .host_call_stack_pointers
+            .iter()
+            .filter(|&&call_sp| sp < call_sp)
+            .count();
+        log_dbg!(
+            "Long jump to {:?} (sp {:#x}, fp {:#x}) at host call depth {}",
+            pc,
+            sp,
+            fp,
+            depth
+        );
+        std::panic::resume_unwind(Box::new(LongJump {
+            thread: self.current_thread,
+            depth,
+            sp,
+            fp,
+            pc,
+        }))
+    }
+
     fn switch_thread(&mut self, new_thread: ThreadId) {
         assert!(new_thread != self.current_thread);
 
@@ -720,6 +1054,16 @@ impl Environment {
     /// connected. Returns [true] if the CPU should step and then resume
     /// debugging, or [false] if it should resume normal execution.
     fn debug_cpu_error(&mut self, error: cpu::CpuError) -> bool {
+        let mut stack_overflow = false;
+        let mut alignment_fault = false;
+        if matches!(error, cpu::CpuError::MemoryError) {
+            if let Some(addr) = self.mem.take_stack_overflow() {
+                self.report_stack_overflow(addr);
+                stack_overflow = true;
+            }
+            alignment_fault = self.mem.take_alignment_fault();
+        }
+
         if matches!(error, cpu::CpuError::UndefinedInstruction)
             || matches!(error, cpu::CpuError::Breakpoint)
         {
@@ -736,6 +1080,18 @@ impl Environment {
         }
 
         if self.gdb_server.is_none() {
+            // Give the app's crash handler a chance to run. There's no
+            // sigaltstack(), so this isn't possible for stack overflows.
+            let signal = match error {
+                // A real device's kernel reports alignment faults as SIGBUS.
+                cpu::CpuError::MemoryError if alignment_fault => Some(libc::signal::SIGBUS),
+                cpu::CpuError::MemoryError if !stack_overflow => Some(libc::signal::SIGSEGV),
+                cpu::CpuError::UndefinedInstruction => Some(libc::signal::SIGILL),
+                _ => None,
+            };
+            if let Some(signal) = signal {
+                libc::signal::deliver_fault_signal(self, signal);
+            }
             panic!("Error during CPU execution: {:?}", error);
         }
 
@@ -792,13 +1148,27 @@ impl Environment {
                                 self.current_thread,
                                 initial_thread
                             );
+                            let return_value = GuestRet::from_regs(self.cpu.regs());
+                            // This calls back into guest code, so it must
+                            // happen before the thread becomes inactive.
+                            libc::pthread::key::run_destructors(self);
+                            frameworks::foundation::ns_autorelease_pool::thread_exited(self);
+                            libc::errno::thread_exited(self, self.current_thread);
                             let curr_thread = &mut self.threads[self.current_thread];
-                            curr_thread.return_value = Some(GuestRet::from_regs(self.cpu.regs()));
+                            curr_thread.return_value = Some(return_value);
                             curr_thread.active = false;
-                            let stack = curr_thread.stack.take().unwrap();
-                            let stack: mem::MutVoidPtr = mem::Ptr::from_bits(*stack.start());
-                            log_dbg!("Freeing thread {} stack {:?}", self.current_thread, stack);
-                            self.mem.free(stack);
+                            let stack_low_end = *curr_thread.stack.take().unwrap().start();
+                            log_dbg!(
+                                "Freeing thread {} stack {:#x}",
+                                self.current_thread,
+                                stack_low_end
+                            );
+                            self.cpu.set_pages_trapped(
+                                stack_low_end - mem::Mem::STACK_GUARD_SIZE,
+                                mem::Mem::STACK_GUARD_SIZE,
+                                false,
+                            );
+                            self.mem.free_thread_stack(stack_low_end);
                             ThreadNextAction::Yield
                         }
                     }
@@ -832,31 +1202,10 @@ impl Environment {
                         }
                     }
                     dyld::Dyld::SVC_LAZY_LINK | dyld::Dyld::SVC_LINKED_FUNCTIONS_BASE.. => {
-                        if let Some(f) = self.dyld.get_svc_handler(
-                            &self.bins,
-                            &mut self.mem,
-                            &mut self.cpu,
-                            svc_pc,
-                            svc,
-                        ) {
-                            let was_in_host_function =
-                                self.threads[self.current_thread].in_host_function;
-                            self.threads[self.current_thread].in_host_function = true;
-                            f.call_from_guest(self);
-                            self.threads[self.current_thread].in_host_function =
-                                was_in_host_function;
-                            // Host function might have put the thread to sleep.
-                            if let ThreadBlock::NotBlocked =
-                                self.threads[self.current_thread].blocked_by
-                            {
-                                ThreadNextAction::Continue
-                            } else {
-                                log_dbg!("Yielding: thread {} is blocked.", self.current_thread);
-                                ThreadNextAction::Yield
-                            }
+                        if self.options.keep_going {
+                            self.call_host_function_contained(svc_pc, svc)
                         } else {
-                            self.cpu.regs_mut()[cpu::Cpu::PC] = svc_pc;
-                            ThreadNextAction::Continue
+                            self.call_host_function(svc_pc, svc, &mut None)
                         }
                     }
                 }
@@ -865,6 +1214,160 @@ impl Environment {
         }
     }
 
+    /// Find and call the host function for an SVC instruction. `symbol` is
+    /// set to the function's name once it's known.
+    fn call_host_function(
+        &mut self,
+        svc_pc: u32,
+        svc: u32,
+        symbol: &mut Option<&'static str>,
+    ) -> ThreadNextAction {
+        let handler =
+            self.dyld
+                .get_svc_handler(&self.bins, &mut self.mem, &mut self.cpu, svc_pc, svc);
+        let (f_symbol, f) = match handler {
+            Ok(Some(handler)) => handler,
+            Ok(None) => {
+                self.cpu.regs_mut()[cpu::Cpu::PC] = svc_pc;
+                return ThreadNextAction::Continue;
+            }
+            Err(missing_symbol) => {
+                let description = format!("{} (unimplemented function)", missing_symbol);
+                api_stats::count_missing(self, description);
+                panic!("Call to unimplemented function {}", missing_symbol);
+            }
+        };
+        *symbol = Some(f_symbol);
+        api_stats::count_host_function(self, f_symbol);
+        let was_in_host_function = self.threads[self.current_thread].in_host_function;
+        let previous_host_function = self.threads[self.current_thread].host_function;
+        self.threads[self.current_thread].in_host_function = true;
+        self.threads[self.current_thread].host_function = Some(f_symbol);
+        let res =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f.call_from_guest(self)));
+        self.threads[self.current_thread].in_host_function = was_in_host_function;
+        self.threads[self.current_thread].host_function = previous_host_function;
+        if let Err(payload) = res {
+            // See Self::long_jump().
+            let thread = &self.threads[self.current_thread];
+            match payload.downcast::<LongJump>() {
+                Ok(jump)
+                    if jump.thread == self.current_thread
+                        && jump.depth == thread.host_call_stack_pointers.len() =>
+                {
+                    let regs = self.cpu.regs_mut();
+                    regs[cpu::Cpu::SP] = jump.sp;
+                    regs[abi::FRAME_POINTER] = jump.fp;
+                    self.cpu.branch(jump.pc);
+                }
+                Ok(jump) => std::panic::resume_unwind(jump),
+                Err(payload) => std::panic::resume_unwind(payload),
+            }
+        }
+        // Host function might have put the thread to sleep.
+        if let ThreadBlock::NotBlocked = self.threads[self.current_thread].blocked_by {
+            ThreadNextAction::Continue
+        } else {
+            log_dbg!("Yielding: thread {} is blocked.", self.current_thread);
+            ThreadNextAction::Yield
+        }
+    }
+
+    /// [Self::call_host_function] for `--keep-going` mode: if the host function
+    /// panics, log some context and return zero/nil to the app, rather than
+    /// letting the panic take down the emulator.
+    ///
+    /// This is only attempted when the panic didn't leave the current thread
+    /// in a state we can't get back from (e.g. a different thread is running).
+    /// The guest registers are restored to what they were at the time of the
+    /// call, which also discards any guest stack frames from host-to-guest
+    /// calls that were in progress.
+    fn call_host_function_contained(&mut self, svc_pc: u32, svc: u32) -> ThreadNextAction {
+        let thread = self.current_thread;
+        let regs = *self.cpu.regs();
+        let cpsr = self.cpu.cpsr();
+        let was_in_host_function = self.threads[thread].in_host_function;
+        let previous_host_function = self.threads[thread].host_function;
+        let mut symbol = None;
+
+        // This isn't really unwind-safe, see Self::run(). The app might well
+        // crash later, but that's what --keep-going is for.
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.call_host_function(svc_pc, svc, &mut symbol)
+        }));
+        let payload = match res {
+            Ok(action) => return action,
+            Err(payload) => payload,
+        };
+        if payload.is::<LongJump>() {
+            std::panic::resume_unwind(payload);
+        }
+
+        if self.current_thread != thread || self.threads[thread].context.is_some() {
+            echo!("Can't recover from panic in host function, giving up.");
+            std::panic::resume_unwind(payload);
+        }
+
+        *self.cpu.regs_mut() = regs;
+        self.cpu.set_cpsr(cpsr);
+        self.threads[thread].in_host_function = was_in_host_function;
+        self.threads[thread].host_function = previous_host_function;
+        self.threads[thread].blocked_by = ThreadBlock::NotBlocked;
+
+        echo!(
+            "Host function {} panicked, continuing because of --keep-going.",
+            symbol.unwrap_or("(not yet linked)")
+        );
+        echo!(
+            "Arguments: r0 = {:#x}, r1 = {:#x}, r2 = {:#x}, r3 = {:#x}, sp = {:#x}",
+            regs[0],
+            regs[1],
+            regs[2],
+            regs[3],
+            regs[cpu::Cpu::SP]
+        );
+        self.stack_trace();
+        echo!("Returning zero/nil to the app.");
+
+        // Covers integer, pointer and (soft-float) floating-point returns of
+        // up to 64 bits. The return instruction follows the SVC.
+        self.cpu.regs_mut()[0] = 0;
+        self.cpu.regs_mut()[1] = 0;
+        ThreadNextAction::Continue
+    }
+
+    /// The framerate limit to apply, taking the power-saving governor into
+    /// account. See [power_governor].
+    pub fn fps_limit(&self) -> Option<f64> {
+        match self.power_governor {
+            Some(ref power_governor) => power_governor.fps_limit(self.options.fps_limit),
+            None => self.options.fps_limit,
+        }
+    }
+
+    /// The OpenAL `ALC_REFRESH` value to request for output devices, if the
+    /// power-saving governor wants one. See [power_governor].
+    pub fn audio_refresh_rate(&self) -> Option<i32> {
+        self.power_governor
+            .as_ref()
+            .and_then(|power_governor| power_governor.audio_refresh_rate())
+    }
+
+    /// Re-check whether the power-saving governor should be engaged. Returns
+    /// [true] if subsystems need to apply a change. See [power_governor].
+    pub fn poll_power_governor(&mut self) -> bool {
+        self.power_governor
+            .as_mut()
+            .is_some_and(|power_governor| power_governor.poll())
+    }
+
+    /// See [cpu_speed_limit].
+    fn limit_cpu_speed(&mut self, ticks: u64) {
+        if let Some(ref mut cpu_speed_limit) = self.cpu_speed_limit {
+            cpu_speed_limit.throttle(ticks);
+        }
+    }
+
     fn run_inner(&mut self, root: bool) {
         let initial_thread = self.current_thread;
         assert!(self.threads[initial_thread].active);
@@ -875,7 +1378,7 @@ impl Environment {
             // large so we aren't jumping in and out of dynarmic or trying to
             // poll for events too often. At the same time, very large values
             // are bad for responsiveness.
-            let mut ticks = if self.threads[self.current_thread].is_blocked() {
+            let initial_ticks = if self.threads[self.current_thread].is_blocked() {
                 // The current thread might be asleep, in which case we want to
                 // immediately switch to another thread. This only happens when
                 // called from Self::sleep().
@@ -883,6 +1386,7 @@ impl Environment {
             } else {
                 100_000
             };
+            let mut ticks = initial_ticks;
             let mut step_and_debug = false;
             while ticks > 0 {
                 let state = self.cpu.run_or_step(
@@ -904,13 +1408,18 @@ impl Environment {
                         }
                     }
                     ThreadNextAction::Yield => break,
-                    ThreadNextAction::ReturnToHost => return,
+                    ThreadNextAction::ReturnToHost => {
+                        self.limit_cpu_speed(initial_ticks - ticks);
+                        return;
+                    }
                     ThreadNextAction::DebugCpuError(e) => {
                         step_and_debug = self.debug_cpu_error(e);
                     }
                 }
             }
 
+            self.limit_cpu_speed(initial_ticks - ticks);
+
             // To maintain responsiveness when moving the window and so on, we
             // need to poll for events occasionally, even if the app isn't
             // actively processing them.
@@ -998,6 +1507,42 @@ impl Environment {
                                 break;
                             }
                         }
+                        ThreadBlock::Condition(_, mutex_id, Some(until)) => {
+                            if until <= Instant::now() {
+                                // Timed out. The thread has to relock the mutex
+                                // before it can run again.
+                                log_dbg!("Thread {} timed out waiting on condition variable.", i);
+                                self.threads[i].resume_return_value = Some(ETIMEDOUT as u32);
+                                self.requeue_on_mutex(i, mutex_id);
+                                if !self.mutex_state.mutex_is_locked(mutex_id) {
+                                    self.threads[i].blocked_by = ThreadBlock::NotBlocked;
+                                    suitable_thread = Some(i);
+                                    mutex_to_relock = Some(mutex_id);
+                                    break;
+                                }
+                            } else {
+                                next_awakening = match next_awakening {
+                                    None => Some(until),
+                                    Some(other) => Some(other.min(until)),
+                                };
+                            }
+                        }
+                        ThreadBlock::Condition(_, _, None) => {}
+                        ThreadBlock::RwLock(rwlock_id, write) => {
+                            let rwlock = self
+                                .libc_state
+                                .pthread
+                                .rwlock
+                                .rwlocks
+                                .get_mut(&rwlock_id)
+                                .unwrap();
+                            if rwlock.try_lock(i, write) {
+                                log_dbg!("Thread {} locked read-write lock #{}.", i, rwlock_id);
+                                self.threads[i].blocked_by = ThreadBlock::NotBlocked;
+                                suitable_thread = Some(i);
+                                break;
+                            }
+                        }
                         ThreadBlock::DeferredReturn => {
                             if i == initial_thread {
                                 log_dbg!("Thread {} is now able to return, returning", i);
@@ -1023,6 +1568,9 @@ impl Environment {
                     if let Some(mutex_id) = mutex_to_relock {
                         self.relock_unblocked_mutex(mutex_id);
                     }
+                    if let Some(value) = self.threads[suitable_thread].resume_return_value.take() {
+                        self.cpu.regs_mut()[0] = value;
+                    }
                     break;
                 // All suitable threads are blocked and at least one is asleep.
                 // Sleep until one of them wakes up.
@@ -0,0 +1,52 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Limiting guest code to the simulated device's CPU speed
+//! (`--limit-cpu-speed`).
+//!
+//! This counts CPU ticks, which are roughly instructions, and treats each one
+//! as one clock cycle. Real ARM11 and Cortex-A8 CPUs often take more than one
+//! cycle per instruction, and touchHLE does some work in host code that would
+//! have been guest code on a real device, so this is only an approximation.
+//! Still, it's enough to make apps that measure their own performance (e.g. to
+//! pick a detail level) see something closer to the real device.
+
+use std::time::{Duration, Instant};
+
+/// How long a measurement window lasts. Time spent idle only counts within a
+/// window, so that a thread that has been waiting for a long time can't then
+/// run at full speed for a long time.
+const WINDOW: Duration = Duration::from_millis(100);
+
+pub struct CpuSpeedLimit {
+    ticks_per_second: f64,
+    window_start: Instant,
+    window_ticks: u64,
+}
+
+impl CpuSpeedLimit {
+    pub fn new(cpu_mhz: u32) -> CpuSpeedLimit {
+        CpuSpeedLimit {
+            ticks_per_second: f64::from(cpu_mhz) * 1_000_000.0,
+            window_start: Instant::now(),
+            window_ticks: 0,
+        }
+    }
+
+    /// Account for `ticks` having been executed, sleeping if they were
+    /// executed faster than the simulated CPU could.
+    pub fn throttle(&mut self, ticks: u64) {
+        self.window_ticks += ticks;
+        let expected = Duration::from_secs_f64(self.window_ticks as f64 / self.ticks_per_second);
+        let elapsed = self.window_start.elapsed();
+        if expected > elapsed {
+            std::thread::sleep(expected - elapsed);
+        }
+        if expected.max(elapsed) >= WINDOW {
+            self.window_start = Instant::now();
+            self.window_ticks = 0;
+        }
+    }
+}
@@ -8,7 +8,7 @@
 use std::collections::HashMap;
 use std::num::NonZeroU32;
 
-use super::{Environment, ThreadId};
+use super::{Environment, ThreadBlock, ThreadId};
 use crate::libc::errno::{EBUSY, EDEADLK, EPERM};
 
 /// Stores and manages mutexes. Note that all the methods for locking and
@@ -116,6 +116,19 @@ impl Environment {
             .waiting_count -= 1;
     }
 
+    /// Make a thread that was waiting on a condition variable wait on the
+    /// associated mutex instead. It will be relocked by the thread scheduler
+    /// once it's available.
+    pub fn requeue_on_mutex(&mut self, thread: ThreadId, mutex_id: MutexId) {
+        // This is subtracted in relock_unblocked_mutex.
+        self.mutex_state
+            .mutexes
+            .get_mut(&mutex_id)
+            .unwrap()
+            .waiting_count += 1;
+        self.threads[thread].blocked_by = ThreadBlock::Mutex(mutex_id);
+    }
+
     /// Locks a mutex and returns the lock count or an error (as errno). Similar
     /// to `pthread_mutex_lock`, but for host code.
     /// NOTE: This only takes effect _after_ the calling function returns to the
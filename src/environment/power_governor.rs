@@ -0,0 +1,152 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Power-saving performance governor (`--power-saving=`).
+//!
+//! While the governor is engaged, touchHLE trades quality for lower energy
+//! use on handheld hosts: the framerate limit is capped, the internal
+//! resolution (`--scale-hack=`) is reduced, and audio is mixed in larger
+//! chunks. In automatic mode, it is engaged while the host runs on battery or
+//! reports thermal pressure, which is re-checked every few seconds.
+//!
+//! The internal resolution can only be chosen before the app starts, so it
+//! only depends on whether the governor was engaged at launch.
+
+use crate::options::Options;
+use std::time::{Duration, Instant};
+
+/// How often the host's power state is re-checked in automatic mode.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Framerate limit while engaged.
+const FPS_LIMIT: f64 = 30.0;
+
+/// OpenAL mixing updates per second while engaged (`ALC_REFRESH`). OpenAL
+/// Soft's default is roughly 50.
+const AUDIO_REFRESH_RATE: i32 = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerSaving {
+    /// Engage while the host runs on battery or reports thermal pressure.
+    Auto,
+    /// Always engaged.
+    Always,
+}
+
+pub struct PowerGovernor {
+    mode: PowerSaving,
+    engaged: bool,
+    /// Whether [PowerGovernor::poll] has yet to report a change.
+    changed: bool,
+    last_check: Instant,
+}
+
+impl PowerGovernor {
+    pub fn new(mode: PowerSaving) -> PowerGovernor {
+        let engaged = match mode {
+            PowerSaving::Auto => host_wants_power_saving(),
+            PowerSaving::Always => true,
+        };
+        log!(
+            "Power-saving governor is {}.",
+            if engaged { "engaged" } else { "not engaged" }
+        );
+        PowerGovernor {
+            mode,
+            engaged,
+            changed: engaged,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Reduce the options that can't be changed once the app is running. This
+    /// only cancels `--scale-hack=`: rendering below the device's native
+    /// resolution isn't supported.
+    pub fn apply_to_launch_options(&self, options: &mut Options) {
+        if self.engaged && options.scale_hack != 1.0 {
+            log!("Power-saving governor: ignoring --scale-hack= option.");
+            options.scale_hack = 1.0;
+        }
+    }
+
+    /// Re-check the host's power state if it's time to. Returns [true] if the
+    /// governor was engaged or disengaged since the last call, which includes
+    /// being engaged from the start.
+    pub fn poll(&mut self) -> bool {
+        if self.mode == PowerSaving::Auto && self.last_check.elapsed() >= CHECK_INTERVAL {
+            self.last_check = Instant::now();
+            let engaged = host_wants_power_saving();
+            if engaged != self.engaged {
+                log!(
+                    "Power-saving governor is now {}.",
+                    if engaged { "engaged" } else { "disengaged" }
+                );
+                self.engaged = engaged;
+                self.changed = true;
+            }
+        }
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn fps_limit(&self, fps_limit: Option<f64>) -> Option<f64> {
+        if !self.engaged {
+            return fps_limit;
+        }
+        Some(fps_limit.map_or(FPS_LIMIT, |limit| limit.min(FPS_LIMIT)))
+    }
+
+    pub fn audio_refresh_rate(&self) -> Option<i32> {
+        self.engaged.then_some(AUDIO_REFRESH_RATE)
+    }
+}
+
+fn host_wants_power_saving() -> bool {
+    host_on_battery() || host_under_thermal_pressure()
+}
+
+fn host_on_battery() -> bool {
+    let state = unsafe { sdl2_sys::SDL_GetPowerInfo(std::ptr::null_mut(), std::ptr::null_mut()) };
+    state == sdl2_sys::SDL_PowerState::SDL_POWERSTATE_ON_BATTERY
+}
+
+/// SDL has no API for this, so this is platform-specific. On Linux, a thermal
+/// zone whose temperature has reached a passive trip point is one where the
+/// kernel has started throttling.
+/// TODO: Android (`PowerManager.getCurrentThermalStatus()`).
+#[cfg(target_os = "linux")]
+fn host_under_thermal_pressure() -> bool {
+    let read_number = |path: std::path::PathBuf| -> Option<i64> {
+        std::fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+    let Ok(zones) = std::fs::read_dir("/sys/class/thermal") else {
+        return false;
+    };
+    for zone in zones.flatten() {
+        let zone = zone.path();
+        let Some(temp) = read_number(zone.join("temp")) else {
+            continue;
+        };
+        for i in 0.. {
+            let Ok(trip_type) =
+                std::fs::read_to_string(zone.join(format!("trip_point_{}_type", i)))
+            else {
+                break;
+            };
+            if trip_type.trim() != "passive" {
+                continue;
+            }
+            if read_number(zone.join(format!("trip_point_{}_temp", i)))
+                .is_some_and(|trip_temp| trip_temp > 0 && temp >= trip_temp)
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+#[cfg(not(target_os = "linux"))]
+fn host_under_thermal_pressure() -> bool {
+    false
+}
@@ -12,13 +12,104 @@
 //! code has its own, not particularly good implementation. We might want to
 //! switch to something like cosmic-text in future, but that has a _lot_ more
 //! dependencies.
+//!
+//! Rasterized glyphs are cached (see [GlyphCache]), because apps often draw
+//! the same text over and over.
 
 use crate::paths;
 use rusttype::{Point, Scale};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::Read;
 
 pub struct Font {
     font: rusttype::Font<'static>,
+    glyph_cache: RefCell<GlyphCache>,
+}
+
+/// Glyph positions are rounded to this fraction of a pixel horizontally, so
+/// that rasterized glyphs can be reused without visibly affecting spacing.
+const SUBPIXEL_STEPS: u8 = 4;
+
+/// Limit on the size of [GlyphCache::atlas], in coverage values. When this is
+/// reached, the cache is emptied. This is 16MiB.
+const GLYPH_CACHE_LIMIT: usize = 4 * 1024 * 1024;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct GlyphCacheKey {
+    /// Font size as `f32` bits.
+    font_size: u32,
+    glyph_id: u16,
+    /// Horizontal offset within the pixel, in units of 1/[SUBPIXEL_STEPS].
+    subpixel_x: u8,
+}
+
+struct CachedGlyph {
+    /// Pixel bounding box's minimum x and maximum y, relative to the glyph's
+    /// position rounded down to a whole pixel. y points down.
+    min_x: i32,
+    max_y: i32,
+    dimensions: (usize, usize),
+    /// Where the glyph's coverage values start in [GlyphCache::atlas].
+    atlas_offset: usize,
+}
+
+/// Cache of rasterized glyphs for one font, keyed by size, glyph and subpixel
+/// position.
+#[derive(Default)]
+struct GlyphCache {
+    /// [None] means the glyph has nothing to draw, e.g. it's a space.
+    glyphs: HashMap<GlyphCacheKey, Option<CachedGlyph>>,
+    /// Coverage values for all cached glyphs, one after another.
+    atlas: Vec<f32>,
+}
+impl GlyphCache {
+    fn clear(&mut self) {
+        self.glyphs.clear();
+        self.atlas = Vec::new();
+    }
+
+    /// Get a glyph from the cache, rasterizing it first if necessary.
+    fn get_or_rasterize(
+        &mut self,
+        font_size: f32,
+        glyph: &rusttype::ScaledGlyph<'static>,
+        subpixel_x: u8,
+    ) -> Option<&CachedGlyph> {
+        let key = GlyphCacheKey {
+            font_size: font_size.to_bits(),
+            glyph_id: glyph.id().0,
+            subpixel_x,
+        };
+        if !self.glyphs.contains_key(&key) {
+            let positioned = glyph.clone().positioned(Point {
+                x: f32::from(subpixel_x) / f32::from(SUBPIXEL_STEPS),
+                y: 0.0,
+            });
+            let cached = positioned.pixel_bounding_box().map(|bounds| {
+                let dimensions = (bounds.width() as usize, bounds.height() as usize);
+                if self.atlas.len() + dimensions.0 * dimensions.1 > GLYPH_CACHE_LIMIT {
+                    log_dbg!("Glyph cache is full, emptying it");
+                    self.clear();
+                }
+                let atlas_offset = self.atlas.len();
+                self.atlas
+                    .resize(atlas_offset + dimensions.0 * dimensions.1, 0.0);
+                let pixels = &mut self.atlas[atlas_offset..];
+                positioned.draw(|x, y, coverage| {
+                    pixels[y as usize * dimensions.0 + x as usize] = coverage;
+                });
+                CachedGlyph {
+                    min_x: bounds.min.x,
+                    max_y: bounds.max.y,
+                    dimensions,
+                    atlas_offset,
+                }
+            });
+            self.glyphs.insert(key, cached);
+        }
+        self.glyphs[&key].as_ref()
+    }
 }
 
 pub enum TextAlignment {
@@ -83,7 +174,15 @@ impl Font {
             panic!("Couldn't parse bundled font file {:?}. This probably means the file is corrupt. Try re-downloading it.", path);
         };
 
-        Font { font }
+        Font {
+            font,
+            glyph_cache: Default::default(),
+        }
+    }
+
+    /// Empty the cache of rasterized glyphs, e.g. to free memory.
+    pub fn clear_glyph_cache(&self) {
+        self.glyph_cache.borrow_mut().clear();
     }
 
     pub fn sans_regular() -> Font {
@@ -299,13 +398,13 @@ impl Font {
         // each pixel in the glyph's bounding box, in left-to-right
         // top-to-bottom order. This is unfortunately incompatible with
         // touchHLE's code which needs to be able to sample the pixels in any
-        // order in order to support rotation. This is worked around by creating
-        // a temporary bitmap for the glyph, and then the caller of this
-        // function can provide a "draw glyph" callback that can do whatever it
-        // wants with this bitmap.
+        // order in order to support rotation. This is worked around by
+        // rasterizing the glyph into a bitmap (which is cached, see
+        // [GlyphCache]), and then the caller of this function can provide a
+        // "draw glyph" callback that can do whatever it wants with this bitmap.
         // TODO: Do we need to increase the font size when scale transforms are
         //       used, to avoid blurry text?
-        let mut glyph_bitmap: Vec<f32> = Vec::new();
+        let mut glyph_cache = self.glyph_cache.borrow_mut();
 
         for (line_width, line_text) in lines {
             let line_x_offset = match alignment {
@@ -321,19 +420,37 @@ impl Font {
                     y: 0.0,
                 },
             ) {
-                let Some(glyph_bounds) = glyph.pixel_bounding_box() else {
+                // Split the position into whole pixels and a rounded
+                // fraction, so the rasterized glyph can be cached.
+                let position_x = glyph.position().x;
+                let mut pixel_x = position_x.floor();
+                let mut subpixel_x =
+                    ((position_x - pixel_x) * f32::from(SUBPIXEL_STEPS)).round() as u8;
+                if subpixel_x == SUBPIXEL_STEPS {
+                    pixel_x += 1.0;
+                    subpixel_x = 0;
+                }
+                let pixel_x = pixel_x as i32;
+
+                let Some(&CachedGlyph {
+                    min_x,
+                    max_y,
+                    dimensions,
+                    atlas_offset,
+                }) = glyph_cache.get_or_rasterize(font_size, glyph.unpositioned(), subpixel_x)
+                else {
                     continue;
                 };
                 // y needs to be flipped to point up
-                let glyph_height = glyph_bounds.height();
-                let x_offset = glyph_bounds.min.x;
-                let y_offset = ((origin.1 + line_y).round() as i32) + glyph_bounds.max.y;
+                let glyph_height = dimensions.1;
+                let x_offset = pixel_x + min_x;
+                let y_offset = ((origin.1 + line_y).round() as i32) + max_y;
 
                 // TODO: Refactor this method to support y clipping too.
                 // It's not mandatory since the caller can do it, but it would
                 // be more efficient.
                 if let Some((wrap_width, _)) = wrap {
-                    if glyph_bounds.min.x as f32 > origin.0 + wrap_width {
+                    if x_offset as f32 > origin.0 + wrap_width {
                         // Avoid wasting effort on glyphs that are entirely
                         // clipped. Partial clipping is the responsibility of
                         // the draw_glyph implementation.
@@ -341,21 +458,11 @@ impl Font {
                     }
                 }
 
-                let glyph_bitmap_bounds = (
-                    glyph_bounds.width() as usize,
-                    glyph_bounds.height() as usize,
-                );
-                glyph_bitmap.clear();
-                glyph_bitmap.resize(glyph_bitmap_bounds.0 * glyph_bitmap_bounds.1, 0.0);
-
-                glyph.draw(|x, y, coverage| {
-                    glyph_bitmap[y as usize * glyph_bitmap_bounds.0 + x as usize] = coverage;
-                });
-
+                let pixels = &glyph_cache.atlas[atlas_offset..][..dimensions.0 * dimensions.1];
                 let raster_glyph = RasterGlyph {
                     origin: (x_offset as f32, y_offset as f32 - glyph_height as f32),
-                    dimensions: (glyph_bitmap_bounds.0 as _, glyph_bitmap_bounds.1 as _),
-                    pixels: &glyph_bitmap,
+                    dimensions: (dimensions.0 as _, dimensions.1 as _),
+                    pixels,
                 };
 
                 draw_glyph(raster_glyph);
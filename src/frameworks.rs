@@ -23,26 +23,39 @@
 pub mod audio_toolbox;
 pub mod av_audio;
 pub mod carbon_core;
+pub mod common_crypto;
 pub mod core_animation;
 pub mod core_audio_types;
 pub mod core_foundation;
 pub mod core_graphics;
 pub mod dnssd;
 pub mod foundation;
+pub mod libxml2;
 pub mod media_player;
 pub mod openal;
 pub mod opengles;
+pub mod security;
+pub mod sqlite3;
 pub mod store_kit;
 pub mod uikit;
+pub mod voice_services;
+pub mod zlib;
 
 /// Container for state of various child modules
 #[derive(Default)]
 pub struct State {
     audio_toolbox: audio_toolbox::State,
+    av_audio: av_audio::State,
+    common_crypto: common_crypto::State,
     core_animation: core_animation::State,
+    core_foundation: core_foundation::State,
     foundation: foundation::State,
+    libxml2: libxml2::State,
     media_player: media_player::State,
     openal: openal::State,
     opengles: opengles::State,
+    security: security::State,
+    sqlite3: sqlite3::State,
     uikit: uikit::State,
+    zlib: zlib::State,
 }
@@ -9,10 +9,10 @@
 //! Apple's implementation probably uses Core Audio instead.
 
 use crate::abi::{CallFromHost, GuestFunction};
-use crate::audio::decode_ima4;
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
+use crate::audio::{self, decode_ima4};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::carbon_core::OSStatus;
 use crate::frameworks::core_audio_types::{
@@ -29,6 +29,7 @@ use crate::mem::{
     guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead,
 };
 use crate::objc::msg;
+use crate::options::Options;
 use crate::Environment;
 use std::collections::{HashMap, VecDeque};
 
@@ -36,16 +37,19 @@ use std::collections::{HashMap, VecDeque};
 pub struct State {
     audio_queues: HashMap<AudioQueueRef, AudioQueueHostObject>,
     al_device_and_context: Option<(*mut ALCdevice, *mut ALCcontext)>,
+    /// See [handle_power_saving_change].
+    al_refresh_rate: Option<ALCint>,
 }
 impl State {
     fn get(framework_state: &mut crate::frameworks::State) -> &mut Self {
         &mut framework_state.audio_toolbox.audio_queue
     }
-    fn make_al_context_current(&mut self) -> ContextManager {
+    fn make_al_context_current(&mut self, options: &Options) -> ContextManager {
         if self.al_device_and_context.is_none() {
-            let device = unsafe { al::alcOpenDevice(std::ptr::null()) };
+            let device = audio::open_output_device(options.audio_device.as_deref());
             assert!(!device.is_null());
-            let context = unsafe { al::alcCreateContext(device, std::ptr::null()) };
+            let attributes = audio::output_context_attributes(self.al_refresh_rate);
+            let context = unsafe { al::alcCreateContext(device, attributes.as_ptr()) };
             assert!(!context.is_null());
             log_dbg!(
                 "New internal OpenAL device ({:?}) and context ({:?})",
@@ -56,10 +60,14 @@ impl State {
         }
         let (device, context) = self.al_device_and_context.unwrap();
         assert!(!device.is_null() && !context.is_null());
+        audio::reconnect_output_device_if_needed(device, options.audio_device.as_deref());
 
         // This object will make sure the existing context, which will belong
         // to the guest app, is restored once we're done.
-        ContextManager::make_active(context)
+        let context_manager = ContextManager::make_active(context);
+        // The volume can change at any time, see [super::audio_session].
+        unsafe { al::alListenerf(al::AL_GAIN, options.volume) };
+        context_manager
     }
 }
 
@@ -249,7 +257,7 @@ pub fn AudioQueueSetParameter(
 
     host_object.volume = in_value;
     if let Some(al_source) = host_object.al_source {
-        let _context_manager = state.make_al_context_current();
+        let _context_manager = state.make_al_context_current(&env.options);
         unsafe {
             al::alSourcef(al_source, al::AL_MAX_GAIN, in_value);
             assert!(al::alGetError() == 0);
@@ -532,7 +540,8 @@ fn prime_audio_queue(
 ) -> ContextManager {
     let state = State::get(&mut env.framework_state);
 
-    let context_manager = context_manager.unwrap_or_else(|| state.make_al_context_current());
+    let context_manager =
+        context_manager.unwrap_or_else(|| state.make_al_context_current(&env.options));
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
 
     if !is_supported_audio_format(&host_object.format) {
@@ -632,6 +641,17 @@ fn unqueue_buffers<F: FnMut(ALuint)>(al_source: ALuint, mut callback: F) {
     }
 }
 
+/// For use by [crate::frameworks::foundation::ns_run_loop]: apply the
+/// power-saving governor's new audio settings to the internal device.
+pub fn handle_power_saving_change(env: &mut Environment) {
+    let refresh_rate = env.audio_refresh_rate();
+    let state = State::get(&mut env.framework_state);
+    state.al_refresh_rate = refresh_rate;
+    if let Some((device, _context)) = state.al_device_and_context {
+        audio::set_output_refresh_rate(device, refresh_rate);
+    }
+}
+
 /// For use by `NSRunLoop`: check the status of an audio queue, recycle buffers,
 /// call callbacks, push new buffers etc.
 pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
@@ -640,7 +660,7 @@ pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
 
     let state = State::get(&mut env.framework_state);
 
-    let context_manager = state.make_al_context_current();
+    let context_manager = state.make_al_context_current(&env.options);
 
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
     let Some(al_source) = host_object.al_source else {
@@ -786,7 +806,7 @@ pub fn AudioQueuePause(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus
 
     let state = State::get(&mut env.framework_state);
 
-    let _context_manager = state.make_al_context_current();
+    let _context_manager = state.make_al_context_current(&env.options);
 
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
     // FIXME: is this correct? is it notifiable?
@@ -820,7 +840,7 @@ pub fn AudioQueueStop(env: &mut Environment, in_aq: AudioQueueRef, in_immediate:
     if in_immediate {
         log_dbg!("Performing immediate AudioQueueStop for {:?}.", in_aq);
 
-        let _context_manager = state.make_al_context_current();
+        let _context_manager = state.make_al_context_current(&env.options);
 
         let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
         if let Some(al_source) = host_object.al_source {
@@ -852,7 +872,7 @@ fn AudioQueueReset(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
 
     log_dbg!("Resetting queue {:?}.", in_aq);
 
-    let _context_manager = state.make_al_context_current();
+    let _context_manager = state.make_al_context_current(&env.options);
 
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
 
@@ -933,7 +953,7 @@ pub fn AudioQueueDispose(
     }
 
     if let Some(al_source) = host_object.al_source {
-        let _context_manager = state.make_al_context_current();
+        let _context_manager = state.make_al_context_current(&env.options);
 
         unsafe {
             al::alSourceStop(al_source);
@@ -4,8 +4,12 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `AudioSession.h` (Audio Session) // TODO: is this the real name?
+//!
+//! This is also where the simulated volume buttons are handled. The output
+//! volume is stored in [crate::options::Options::volume] and applies to all of
+//! the app's audio (see [crate::frameworks::openal] and [super::audio_queue]).
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::carbon_core::OSStatus;
 use crate::frameworks::core_audio_types::{debug_fourcc, fourcc};
@@ -13,6 +17,23 @@ use crate::frameworks::core_foundation::cf_run_loop::{CFRunLoopMode, CFRunLoopRe
 use crate::mem::{guest_size_of, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr};
 use crate::Environment;
 
+#[derive(Default)]
+pub struct State {
+    property_listeners: Vec<(
+        AudioSessionPropertyID,
+        AudioSessionPropertyListener,
+        MutVoidPtr,
+    )>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.audio_toolbox.audio_session
+    }
+}
+
+/// iPhone OS's volume buttons move the volume in 16 steps.
+const VOLUME_STEP: f32 = 1.0 / 16.0;
+
 type AudioSessionInterruptionListener = GuestFunction;
 type AudioSessionPropertyListener = GuestFunction;
 
@@ -22,6 +43,7 @@ const kAudioSessionBadPropertySizeError: OSStatus = fourcc(b"!siz") as _;
 type AudioSessionPropertyID = u32;
 const kAudioSessionProperty_OtherAudioIsPlaying: AudioSessionPropertyID = fourcc(b"othr");
 const kAudioSessionProperty_AudioCategory: AudioSessionPropertyID = fourcc(b"acat");
+const kAudioSessionProperty_CurrentHardwareOutputVolume: AudioSessionPropertyID = fourcc(b"chov");
 
 const kAudioSessionCategory_SoloAmbientSound: u32 = fourcc(b"solo");
 
@@ -45,6 +67,7 @@ fn AudioSessionGetProperty(
     let required_size: GuestUSize = match in_ID {
         kAudioSessionProperty_OtherAudioIsPlaying => guest_size_of::<u32>(),
         kAudioSessionProperty_AudioCategory => guest_size_of::<u32>(),
+        kAudioSessionProperty_CurrentHardwareOutputVolume => guest_size_of::<f32>(),
         _ => unimplemented!("Unimplemented property ID: {}", debug_fourcc(in_ID)),
     };
     if env.mem.read(io_data_size) != required_size {
@@ -62,6 +85,10 @@ fn AudioSessionGetProperty(
             let value: u32 = kAudioSessionCategory_SoloAmbientSound;
             env.mem.write(out_data.cast(), value);
         }
+        kAudioSessionProperty_CurrentHardwareOutputVolume => {
+            let value: f32 = env.options.volume;
+            env.mem.write(out_data.cast(), value);
+        }
         _ => unreachable!(),
     }
 
@@ -93,20 +120,88 @@ fn AudioSessionSetActive(_env: &mut Environment, _active: bool) -> OSStatus {
 }
 
 fn AudioSessionAddPropertyListener(
-    _env: &mut Environment,
+    env: &mut Environment,
     inID: AudioSessionPropertyID,
     inProc: AudioSessionPropertyListener,
     inClientData: MutVoidPtr,
 ) -> OSStatus {
-    let result = 0; // success
-    log!(
-        "TODO: AudioSessionAddPropertyListener({:?}, {:?}, {:?}) -> {}",
-        inID,
+    log_dbg!(
+        "AudioSessionAddPropertyListener({}, {:?}, {:?})",
+        debug_fourcc(inID),
         inProc,
         inClientData,
-        result
     );
-    result
+    if inID != kAudioSessionProperty_CurrentHardwareOutputVolume {
+        log!(
+            "TODO: AudioSessionAddPropertyListener() for property {} will never be called",
+            debug_fourcc(inID)
+        );
+    }
+    State::get(env)
+        .property_listeners
+        .push((inID, inProc, inClientData));
+    0 // success
+}
+
+fn AudioSessionRemovePropertyListenerWithUserData(
+    env: &mut Environment,
+    inID: AudioSessionPropertyID,
+    inProc: AudioSessionPropertyListener,
+    inClientData: MutVoidPtr,
+) -> OSStatus {
+    State::get(env)
+        .property_listeners
+        .retain(|&(id, proc_, client_data)| {
+            id != inID
+                || proc_.addr_with_thumb_bit() != inProc.addr_with_thumb_bit()
+                || client_data != inClientData
+        });
+    0 // success
+}
+
+/// Simulate pressing one of the volume buttons on the side of the device:
+/// change the output volume by one step and notify the app.
+pub fn press_volume_button(env: &mut Environment, up: bool) {
+    let old_volume = env.options.volume;
+    let step = if up { VOLUME_STEP } else { -VOLUME_STEP };
+    let volume = (old_volume + step).clamp(0.0, 1.0);
+    // There's no volume HUD to draw this on, so print it instead.
+    echo!("Volume: {}%", (volume * 100.0).round());
+    if volume == old_volume {
+        return;
+    }
+    env.options.volume = volume;
+
+    crate::frameworks::openal::handle_volume_change(env);
+    // Audio queues pick up the new volume the next time they're updated.
+
+    let listeners: Vec<_> = State::get(env)
+        .property_listeners
+        .iter()
+        .filter(|&&(id, _, _)| id == kAudioSessionProperty_CurrentHardwareOutputVolume)
+        .map(|&(_, proc_, client_data)| (proc_, client_data))
+        .collect();
+    if listeners.is_empty() {
+        return;
+    }
+    let data: ConstVoidPtr = env.mem.alloc_and_write(volume).cast_const().cast();
+    for (proc_, client_data) in listeners {
+        log_dbg!(
+            "Calling property listener {:?} for {}",
+            proc_,
+            debug_fourcc(kAudioSessionProperty_CurrentHardwareOutputVolume)
+        );
+        () = proc_.call_from_host(
+            env,
+            (
+                client_data,
+                kAudioSessionProperty_CurrentHardwareOutputVolume,
+                guest_size_of::<f32>(),
+                data,
+            ),
+        );
+    }
+    env.mem.free(data.cast_mut());
 }
 
 pub const FUNCTIONS: FunctionExports = &[
@@ -115,4 +210,5 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(AudioSessionSetProperty(_, _, _)),
     export_c_func!(AudioSessionSetActive(_)),
     export_c_func!(AudioSessionAddPropertyListener(_, _, _)),
+    export_c_func!(AudioSessionRemovePropertyListenerWithUserData(_, _, _)),
 ];
@@ -3,5 +3,17 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! The AV Foundation framework's audio classes.
 
 pub mod av_audio_player;
+pub mod av_audio_recorder;
+
+#[derive(Default)]
+pub struct State {
+    av_audio_recorder: av_audio_recorder::State,
+}
+
+/// For use by `NSRunLoop`: collect audio captured by recorders.
+pub fn handle_recorders(env: &mut crate::Environment) {
+    av_audio_recorder::handle_recorders(env);
+}
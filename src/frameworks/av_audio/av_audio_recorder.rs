@@ -0,0 +1,357 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! AVAudioRecorder
+//!
+//! Audio is captured from the host's microphone (see [crate::audio]) and
+//! always recorded as 16-bit linear PCM. It's written to a WAVE file if the
+//! URL has a `.wav` extension, and a Core Audio Format file otherwise.
+
+use crate::audio::{encode_caf, encode_wav, AudioCapture};
+use crate::dyld::{ConstantExports, HostConstant};
+use crate::frameworks::core_audio_types::{debug_fourcc, kAudioFormatLinearPCM};
+use crate::frameworks::foundation::{ns_string, ns_url, NSUInteger};
+use crate::mem::MutPtr;
+use crate::objc::{
+    id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+};
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    /// Recorders that are currently recording, which need to be polled.
+    recording: Vec<id>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.av_audio.av_audio_recorder
+    }
+}
+
+pub const AVFormatIDKey: &str = "AVFormatIDKey";
+pub const AVSampleRateKey: &str = "AVSampleRateKey";
+pub const AVNumberOfChannelsKey: &str = "AVNumberOfChannelsKey";
+pub const AVLinearPCMBitDepthKey: &str = "AVLinearPCMBitDepthKey";
+pub const AVEncoderAudioQualityKey: &str = "AVEncoderAudioQualityKey";
+
+/// Settings dictionary keys.
+pub const CONSTANTS: ConstantExports = &[
+    ("_AVFormatIDKey", HostConstant::NSString(AVFormatIDKey)),
+    ("_AVSampleRateKey", HostConstant::NSString(AVSampleRateKey)),
+    (
+        "_AVNumberOfChannelsKey",
+        HostConstant::NSString(AVNumberOfChannelsKey),
+    ),
+    (
+        "_AVLinearPCMBitDepthKey",
+        HostConstant::NSString(AVLinearPCMBitDepthKey),
+    ),
+    (
+        "_AVEncoderAudioQualityKey",
+        HostConstant::NSString(AVEncoderAudioQualityKey),
+    ),
+];
+
+/// The power level reported for silence, in decibels.
+const MIN_POWER: f32 = -160.0;
+
+struct AVAudioRecorderHostObject {
+    url: id,
+    /// Weak reference
+    delegate: id,
+    sample_rate: u32,
+    channels: u16,
+    /// `Some` once prepared.
+    capture: Option<AudioCapture>,
+    samples: Vec<i16>,
+    is_recording: bool,
+    metering_enabled: bool,
+    /// How many samples had been recorded when the meters were last updated.
+    metered_until: usize,
+    /// Average and peak power for each channel, in decibels.
+    meters: Vec<(f32, f32)>,
+}
+impl HostObject for AVAudioRecorderHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation AVAudioRecorder: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(AVAudioRecorderHostObject {
+        url: nil,
+        delegate: nil,
+        sample_rate: 44100,
+        channels: 1,
+        capture: None,
+        samples: Vec::new(),
+        is_recording: false,
+        metering_enabled: false,
+        metered_until: 0,
+        meters: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (id)initWithURL:(id)url // NSURL*
+         settings:(id)settings // NSDictionary*
+            error:(MutPtr<id>)error { // NSError**
+    if !error.is_null() {
+        env.mem.write(error, nil);
+    }
+
+    let get_setting = |env: &mut Environment, key: &'static str| -> id {
+        if settings == nil {
+            return nil;
+        }
+        let key = ns_string::get_static_str(env, key);
+        msg![env; settings objectForKey:key]
+    };
+
+    let format_id = get_setting(env, AVFormatIDKey);
+    if format_id != nil {
+        let format_id: u32 = msg![env; format_id unsignedIntValue];
+        if format_id != kAudioFormatLinearPCM {
+            log!(
+                "TODO: AVAudioRecorder format {}, recording linear PCM instead",
+                debug_fourcc(format_id)
+            );
+        }
+    }
+    let sample_rate = get_setting(env, AVSampleRateKey);
+    if sample_rate != nil {
+        let sample_rate: f64 = msg![env; sample_rate doubleValue];
+        env.objc.borrow_mut::<AVAudioRecorderHostObject>(this).sample_rate = sample_rate as u32;
+    }
+    let channels = get_setting(env, AVNumberOfChannelsKey);
+    if channels != nil {
+        let channels: i64 = msg![env; channels longLongValue];
+        // AudioCapture only supports mono and stereo.
+        let channels = channels.clamp(1, 2) as u16;
+        env.objc.borrow_mut::<AVAudioRecorderHostObject>(this).channels = channels;
+    }
+
+    retain(env, url);
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    host_object.url = url;
+    log_dbg!(
+        "[(AVAudioRecorder*){:?} initWithURL:{:?} settings:{:?}] => {} Hz, {} channels",
+        this,
+        url,
+        settings,
+        host_object.sample_rate,
+        host_object.channels,
+    );
+    this
+}
+
+- (())dealloc {
+    let &AVAudioRecorderHostObject { url, is_recording, .. } = env.objc.borrow(this);
+    if is_recording {
+        () = msg![env; this stop];
+    }
+    release(env, url);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)url {
+    env.objc.borrow::<AVAudioRecorderHostObject>(this).url
+}
+
+- (id)delegate {
+    env.objc.borrow::<AVAudioRecorderHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate {
+    env.objc.borrow_mut::<AVAudioRecorderHostObject>(this).delegate = delegate;
+}
+
+- (bool)prepareToRecord {
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    if host_object.capture.is_some() {
+        return true;
+    }
+    host_object.capture = Some(AudioCapture::open(host_object.sample_rate, host_object.channels));
+    host_object.samples.clear();
+    host_object.metered_until = 0;
+    host_object.meters.clear();
+    // The file is created when preparing, like on a real device.
+    write_file(env, this)
+}
+
+- (bool)record {
+    let prepared: bool = msg![env; this prepareToRecord];
+    if !prepared {
+        return false;
+    }
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    if host_object.is_recording {
+        return true;
+    }
+    host_object.capture.as_mut().unwrap().start();
+    host_object.is_recording = true;
+    State::get(env).recording.push(this);
+    true
+}
+
+- (bool)isRecording {
+    env.objc.borrow::<AVAudioRecorderHostObject>(this).is_recording
+}
+
+- (())pause {
+    stop_capture(env, this);
+}
+
+- (())stop {
+    let host_object = env.objc.borrow::<AVAudioRecorderHostObject>(this);
+    if host_object.capture.is_none() {
+        return;
+    }
+    stop_capture(env, this);
+    let success = write_file(env, this);
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    host_object.capture = None;
+    let delegate = host_object.delegate;
+
+    if delegate != nil
+        && env.objc.object_has_method_named(
+            &env.mem,
+            delegate,
+            "audioRecorderDidFinishRecording:successfully:",
+        )
+    {
+        () = msg![env; delegate audioRecorderDidFinishRecording:this successfully:success];
+    }
+}
+
+- (bool)deleteRecording {
+    let &AVAudioRecorderHostObject { url, is_recording, .. } = env.objc.borrow(this);
+    if is_recording {
+        return false;
+    }
+    let path = ns_url::to_rust_path(env, url);
+    env.fs.remove(&path).is_ok()
+}
+
+- (f64)currentTime {
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    if !host_object.is_recording {
+        return 0.0;
+    }
+    host_object.capture.as_mut().unwrap().read_samples(&mut host_object.samples);
+    let frames = host_object.samples.len() / usize::from(host_object.channels);
+    frames as f64 / f64::from(host_object.sample_rate)
+}
+
+- (bool)isMeteringEnabled {
+    env.objc.borrow::<AVAudioRecorderHostObject>(this).metering_enabled
+}
+- (())setMeteringEnabled:(bool)enabled {
+    env.objc.borrow_mut::<AVAudioRecorderHostObject>(this).metering_enabled = enabled;
+}
+
+- (())updateMeters {
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    if !host_object.metering_enabled {
+        return;
+    }
+    if let Some(capture) = host_object.capture.as_mut() {
+        capture.read_samples(&mut host_object.samples);
+    }
+    let channels = usize::from(host_object.channels);
+    let new_samples = &host_object.samples[host_object.metered_until..];
+    host_object.meters = (0..channels)
+        .map(|channel| {
+            let mut sum_of_squares = 0.0;
+            let mut peak = 0.0f32;
+            let mut count = 0;
+            for &sample in new_samples.iter().skip(channel).step_by(channels) {
+                let sample = f32::from(sample) / 32768.0;
+                sum_of_squares += sample * sample;
+                peak = peak.max(sample.abs());
+                count += 1;
+            }
+            if count == 0 {
+                return (MIN_POWER, MIN_POWER);
+            }
+            let average = (sum_of_squares / count as f32).sqrt();
+            (to_decibels(average), to_decibels(peak))
+        })
+        .collect();
+    host_object.metered_until = host_object.samples.len();
+}
+
+- (f32)averagePowerForChannel:(NSUInteger)channel {
+    let host_object = env.objc.borrow::<AVAudioRecorderHostObject>(this);
+    host_object.meters.get(channel as usize).map_or(MIN_POWER, |&(average, _)| average)
+}
+- (f32)peakPowerForChannel:(NSUInteger)channel {
+    let host_object = env.objc.borrow::<AVAudioRecorderHostObject>(this);
+    host_object.meters.get(channel as usize).map_or(MIN_POWER, |&(_, peak)| peak)
+}
+
+@end
+
+};
+
+fn to_decibels(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        MIN_POWER
+    } else {
+        (20.0 * amplitude.log10()).max(MIN_POWER)
+    }
+}
+
+/// Stop capturing without discarding anything.
+fn stop_capture(env: &mut Environment, this: id) {
+    let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(this);
+    if !host_object.is_recording {
+        return;
+    }
+    let capture = host_object.capture.as_mut().unwrap();
+    capture.read_samples(&mut host_object.samples);
+    capture.stop();
+    host_object.is_recording = false;
+    State::get(env)
+        .recording
+        .retain(|&recorder| recorder != this);
+}
+
+/// Write everything recorded so far to the recorder's file. Returns `false` on
+/// failure.
+fn write_file(env: &mut Environment, this: id) -> bool {
+    let url = env.objc.borrow::<AVAudioRecorderHostObject>(this).url;
+    let path = ns_url::to_rust_path(env, url);
+    let host_object = env.objc.borrow::<AVAudioRecorderHostObject>(this);
+    let encode = if path.as_str().to_ascii_lowercase().ends_with(".wav") {
+        encode_wav
+    } else {
+        encode_caf
+    };
+    let bytes = encode(
+        &host_object.samples,
+        host_object.sample_rate,
+        host_object.channels,
+    );
+    let res = env.fs.write(&path, &bytes).is_ok();
+    if !res {
+        log!("Warning: AVAudioRecorder couldn't write to {:?}", path);
+    }
+    res
+}
+
+/// For use by `NSRunLoop` via [super::handle_recorders]: collect captured
+/// audio, so the host's buffer doesn't overflow.
+pub(super) fn handle_recorders(env: &mut Environment) {
+    for recorder in State::get(env).recording.clone() {
+        let host_object = env.objc.borrow_mut::<AVAudioRecorderHostObject>(recorder);
+        host_object
+            .capture
+            .as_mut()
+            .unwrap()
+            .read_samples(&mut host_object.samples);
+    }
+}
@@ -0,0 +1,25 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! CommonCrypto.
+//!
+//! This isn't a framework, it's part of libSystem, but it's organised like one
+//! because it has several headers. The algorithms are implemented directly
+//! here, since the few that apps use (MD5, SHA-1, SHA-256, AES) are small and
+//! well-specified.
+//!
+//! Resources:
+//! - Apple's [CommonCrypto man pages](https://opensource.apple.com/source/CommonCrypto/CommonCrypto-36064/doc/)
+
+pub mod common_cryptor;
+pub mod common_digest;
+pub mod common_hmac;
+
+#[derive(Default)]
+pub struct State {
+    common_cryptor: common_cryptor::State,
+    common_digest: common_digest::State,
+    common_hmac: common_hmac::State,
+}
@@ -0,0 +1,503 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CommonCryptor.h`
+//!
+//! Only AES is supported, in CBC and ECB modes.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr};
+use crate::Environment;
+use std::collections::HashMap;
+
+type CCCryptorStatus = i32;
+const kCCSuccess: CCCryptorStatus = 0;
+const kCCParamError: CCCryptorStatus = -4300;
+const kCCBufferTooSmall: CCCryptorStatus = -4301;
+const kCCAlignmentError: CCCryptorStatus = -4303;
+const kCCDecodeError: CCCryptorStatus = -4304;
+const kCCUnimplemented: CCCryptorStatus = -4305;
+
+type CCOperation = u32;
+const kCCEncrypt: CCOperation = 0;
+const kCCDecrypt: CCOperation = 1;
+
+type CCAlgorithm = u32;
+const kCCAlgorithmAES128: CCAlgorithm = 0;
+
+type CCOptions = u32;
+const kCCOptionPKCS7Padding: CCOptions = 1;
+const kCCOptionECBMode: CCOptions = 2;
+
+/// Opaque guest type for `CCCryptorRef`.
+pub struct CCCryptor {}
+
+#[derive(Default)]
+pub struct State {
+    cryptors: HashMap<MutPtr<CCCryptor>, Cryptor>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.common_crypto.common_cryptor
+    }
+}
+
+/// AES block size in bytes.
+const AES_BLOCK_SIZE: usize = 16;
+
+const fn make_sbox() -> [u8; 256] {
+    // Walks through the multiplicative group using 3 as a generator, so that
+    // q is always the inverse of p, then applies the affine transformation.
+    let mut sbox = [0u8; 256];
+    let mut p: u8 = 1;
+    let mut q: u8 = 1;
+    loop {
+        p = p ^ (p << 1) ^ (if p & 0x80 != 0 { 0x1b } else { 0 });
+        q ^= q << 1;
+        q ^= q << 2;
+        q ^= q << 4;
+        if q & 0x80 != 0 {
+            q ^= 0x09;
+        }
+        let x = q ^ q.rotate_left(1) ^ q.rotate_left(2) ^ q.rotate_left(3) ^ q.rotate_left(4);
+        sbox[p as usize] = x ^ 0x63;
+        if p == 1 {
+            break;
+        }
+    }
+    sbox[0] = 0x63;
+    sbox
+}
+
+const fn invert_sbox(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut inv_sbox = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv_sbox[sbox[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv_sbox
+}
+
+const SBOX: [u8; 256] = make_sbox();
+const INV_SBOX: [u8; 256] = invert_sbox(&SBOX);
+
+/// Multiplication in GF(2^8).
+fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        a = (a << 1) ^ (if a & 0x80 != 0 { 0x1b } else { 0 });
+        b >>= 1;
+    }
+    result
+}
+
+/// AES-128, AES-192 or AES-256 block cipher with an expanded key.
+struct Aes {
+    round_keys: Vec<[u8; AES_BLOCK_SIZE]>,
+}
+impl Aes {
+    /// Returns [None] if the key size is invalid.
+    fn new(key: &[u8]) -> Option<Self> {
+        let nk = match key.len() {
+            16 | 24 | 32 => key.len() / 4,
+            _ => return None,
+        };
+        let rounds = nk + 6;
+        let mut words: Vec<[u8; 4]> = key.chunks(4).map(|c| c.try_into().unwrap()).collect();
+        let mut rcon = 1u8;
+        for i in nk..(rounds + 1) * 4 {
+            let mut temp = words[i - 1];
+            if i % nk == 0 {
+                temp = [
+                    SBOX[temp[1] as usize] ^ rcon,
+                    SBOX[temp[2] as usize],
+                    SBOX[temp[3] as usize],
+                    SBOX[temp[0] as usize],
+                ];
+                rcon = gmul(rcon, 2);
+            } else if nk > 6 && i % nk == 4 {
+                temp = temp.map(|b| SBOX[b as usize]);
+            }
+            let prev = words[i - nk];
+            words.push(std::array::from_fn(|j| prev[j] ^ temp[j]));
+        }
+        let round_keys = words
+            .chunks(4)
+            .map(|w| std::array::from_fn(|i| w[i / 4][i % 4]))
+            .collect();
+        Some(Aes { round_keys })
+    }
+
+    fn add_round_key(&self, state: &mut [u8; AES_BLOCK_SIZE], round: usize) {
+        for (byte, key_byte) in state.iter_mut().zip(self.round_keys[round]) {
+            *byte ^= key_byte;
+        }
+    }
+
+    fn encrypt_block(&self, state: &mut [u8; AES_BLOCK_SIZE]) {
+        let rounds = self.round_keys.len() - 1;
+        self.add_round_key(state, 0);
+        for round in 1..=rounds {
+            // SubBytes and ShiftRows. The state is in column-major order.
+            let old = *state;
+            for c in 0..4 {
+                for r in 0..4 {
+                    state[r + 4 * c] = SBOX[old[r + 4 * ((c + r) % 4)] as usize];
+                }
+            }
+            if round != rounds {
+                // MixColumns
+                for column in state.chunks_mut(4) {
+                    let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+                    column[0] = gmul(a0, 2) ^ gmul(a1, 3) ^ a2 ^ a3;
+                    column[1] = a0 ^ gmul(a1, 2) ^ gmul(a2, 3) ^ a3;
+                    column[2] = a0 ^ a1 ^ gmul(a2, 2) ^ gmul(a3, 3);
+                    column[3] = gmul(a0, 3) ^ a1 ^ a2 ^ gmul(a3, 2);
+                }
+            }
+            self.add_round_key(state, round);
+        }
+    }
+
+    fn decrypt_block(&self, state: &mut [u8; AES_BLOCK_SIZE]) {
+        let rounds = self.round_keys.len() - 1;
+        for round in (1..=rounds).rev() {
+            self.add_round_key(state, round);
+            if round != rounds {
+                // InvMixColumns
+                for column in state.chunks_mut(4) {
+                    let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+                    column[0] = gmul(a0, 14) ^ gmul(a1, 11) ^ gmul(a2, 13) ^ gmul(a3, 9);
+                    column[1] = gmul(a0, 9) ^ gmul(a1, 14) ^ gmul(a2, 11) ^ gmul(a3, 13);
+                    column[2] = gmul(a0, 13) ^ gmul(a1, 9) ^ gmul(a2, 14) ^ gmul(a3, 11);
+                    column[3] = gmul(a0, 11) ^ gmul(a1, 13) ^ gmul(a2, 9) ^ gmul(a3, 14);
+                }
+            }
+            // InvShiftRows and InvSubBytes
+            let old = *state;
+            for c in 0..4 {
+                for r in 0..4 {
+                    state[r + 4 * ((c + r) % 4)] = INV_SBOX[old[r + 4 * c] as usize];
+                }
+            }
+        }
+        self.add_round_key(state, 0);
+    }
+}
+
+/// Host implementation of a `CCCryptorRef`.
+struct Cryptor {
+    decrypt: bool,
+    aes: Aes,
+    ecb: bool,
+    padding: bool,
+    /// The IV, or the previous ciphertext block when in CBC mode.
+    chain: [u8; AES_BLOCK_SIZE],
+    /// Input that doesn't make a full block yet, or that might be the final
+    /// block when decrypting with padding.
+    buffered: Vec<u8>,
+}
+impl Cryptor {
+    fn new(
+        op: CCOperation,
+        alg: CCAlgorithm,
+        options: CCOptions,
+        key: &[u8],
+        iv: Option<&[u8]>,
+    ) -> Result<Self, CCCryptorStatus> {
+        let decrypt = match op {
+            kCCEncrypt => false,
+            kCCDecrypt => true,
+            _ => return Err(kCCParamError),
+        };
+        if alg != kCCAlgorithmAES128 {
+            log!("TODO: CommonCrypto algorithm {}", alg);
+            return Err(kCCUnimplemented);
+        }
+        let aes = Aes::new(key).ok_or(kCCParamError)?;
+        let chain = iv.map_or([0; AES_BLOCK_SIZE], |iv| iv.try_into().unwrap());
+        Ok(Cryptor {
+            decrypt,
+            aes,
+            ecb: options & kCCOptionECBMode != 0,
+            padding: options & kCCOptionPKCS7Padding != 0,
+            chain,
+            buffered: Vec::new(),
+        })
+    }
+
+    /// How many bytes will be output by [Self::update] with this much input.
+    fn update_output_length(&self, input_length: usize) -> usize {
+        let total = self.buffered.len() + input_length;
+        let blocks = total / AES_BLOCK_SIZE;
+        // When decrypting with padding, the last block has to be kept until
+        // the end.
+        if self.decrypt && self.padding && blocks > 0 && blocks * AES_BLOCK_SIZE == total {
+            (blocks - 1) * AES_BLOCK_SIZE
+        } else {
+            blocks * AES_BLOCK_SIZE
+        }
+    }
+
+    /// Upper bound on how many bytes will be output by [Self::update] and
+    /// [Self::finish] with this much input.
+    fn final_output_length(&self, input_length: usize) -> usize {
+        let total = self.buffered.len() + input_length;
+        if !self.decrypt && self.padding {
+            (total / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE
+        } else {
+            total
+        }
+    }
+
+    fn process_block(&mut self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        if self.ecb {
+            if self.decrypt {
+                self.aes.decrypt_block(block);
+            } else {
+                self.aes.encrypt_block(block);
+            }
+        } else if self.decrypt {
+            let ciphertext = *block;
+            self.aes.decrypt_block(block);
+            for (byte, chain_byte) in block.iter_mut().zip(self.chain) {
+                *byte ^= chain_byte;
+            }
+            self.chain = ciphertext;
+        } else {
+            for (byte, chain_byte) in block.iter_mut().zip(self.chain) {
+                *byte ^= chain_byte;
+            }
+            self.aes.encrypt_block(block);
+            self.chain = *block;
+        }
+    }
+
+    fn process_blocks(&mut self, length: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = self.buffered.drain(..length).collect();
+        for block in data.chunks_mut(AES_BLOCK_SIZE) {
+            let block: &mut [u8; AES_BLOCK_SIZE] = block.try_into().unwrap();
+            self.process_block(block);
+        }
+        data
+    }
+
+    fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        let length = self.update_output_length(input.len());
+        self.buffered.extend_from_slice(input);
+        self.process_blocks(length)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, CCCryptorStatus> {
+        if !self.decrypt && self.padding {
+            let pad = AES_BLOCK_SIZE - self.buffered.len() % AES_BLOCK_SIZE;
+            self.buffered.resize(self.buffered.len() + pad, pad as u8);
+        }
+        let length = self.buffered.len();
+        if length / AES_BLOCK_SIZE * AES_BLOCK_SIZE != length {
+            return Err(kCCAlignmentError);
+        }
+        let mut output = self.process_blocks(length);
+        if self.decrypt && self.padding {
+            let &pad = output.last().ok_or(kCCDecodeError)?;
+            let pad = pad as usize;
+            if pad == 0
+                || pad > AES_BLOCK_SIZE
+                || !output[output.len() - pad..]
+                    .iter()
+                    .all(|&b| b as usize == pad)
+            {
+                return Err(kCCDecodeError);
+            }
+            output.truncate(output.len() - pad);
+        }
+        Ok(output)
+    }
+}
+
+fn read_iv(env: &Environment, iv: ConstVoidPtr) -> Option<&[u8]> {
+    (!iv.is_null()).then(|| env.mem.bytes_at(iv.cast(), AES_BLOCK_SIZE as GuestUSize))
+}
+
+/// Write output to the app's buffer, or fail if it's too small.
+fn write_output(
+    env: &mut Environment,
+    output: &[u8],
+    data_out: MutVoidPtr,
+    data_out_available: GuestUSize,
+    data_out_moved: MutPtr<GuestUSize>,
+) -> CCCryptorStatus {
+    let length: GuestUSize = output.len().try_into().unwrap();
+    if !data_out_moved.is_null() {
+        env.mem.write(data_out_moved, length);
+    }
+    if length > data_out_available {
+        return kCCBufferTooSmall;
+    }
+    env.mem
+        .bytes_at_mut(data_out.cast(), length)
+        .copy_from_slice(output);
+    kCCSuccess
+}
+
+fn CCCryptorCreate(
+    env: &mut Environment,
+    op: CCOperation,
+    alg: CCAlgorithm,
+    options: CCOptions,
+    key: ConstVoidPtr,
+    key_length: GuestUSize,
+    iv: ConstVoidPtr,
+    cryptor_ref: MutPtr<MutPtr<CCCryptor>>,
+) -> CCCryptorStatus {
+    let key = env.mem.bytes_at(key.cast(), key_length);
+    let cryptor = match Cryptor::new(op, alg, options, key, read_iv(env, iv)) {
+        Ok(cryptor) => cryptor,
+        Err(status) => return status,
+    };
+    let handle = env.mem.alloc(4).cast();
+    State::get(env).cryptors.insert(handle, cryptor);
+    env.mem.write(cryptor_ref, handle);
+    kCCSuccess
+}
+
+fn CCCryptorRelease(env: &mut Environment, cryptor: MutPtr<CCCryptor>) -> CCCryptorStatus {
+    if State::get(env).cryptors.remove(&cryptor).is_none() {
+        return kCCParamError;
+    }
+    env.mem.free(cryptor.cast());
+    kCCSuccess
+}
+
+fn CCCryptorGetOutputLength(
+    env: &mut Environment,
+    cryptor: MutPtr<CCCryptor>,
+    input_length: GuestUSize,
+    final_: bool,
+) -> GuestUSize {
+    let Some(cryptor) = State::get(env).cryptors.get(&cryptor) else {
+        log!(
+            "Warning: CCCryptorGetOutputLength() for invalid cryptor {:?}",
+            cryptor
+        );
+        return 0;
+    };
+    let length = if final_ {
+        cryptor.final_output_length(input_length as usize)
+    } else {
+        cryptor.update_output_length(input_length as usize)
+    };
+    length.try_into().unwrap()
+}
+
+fn CCCryptorUpdate(
+    env: &mut Environment,
+    cryptor: MutPtr<CCCryptor>,
+    data_in: ConstVoidPtr,
+    data_in_length: GuestUSize,
+    data_out: MutVoidPtr,
+    data_out_available: GuestUSize,
+    data_out_moved: MutPtr<GuestUSize>,
+) -> CCCryptorStatus {
+    let input = env.mem.bytes_at(data_in.cast(), data_in_length).to_vec();
+    let Some(cryptor) = State::get(env).cryptors.get_mut(&cryptor) else {
+        return kCCParamError;
+    };
+    let needed = cryptor.update_output_length(input.len());
+    if needed > data_out_available as usize {
+        if !data_out_moved.is_null() {
+            env.mem.write(data_out_moved, needed as GuestUSize);
+        }
+        return kCCBufferTooSmall;
+    }
+    let output = cryptor.update(&input);
+    write_output(env, &output, data_out, data_out_available, data_out_moved)
+}
+
+fn CCCryptorFinal(
+    env: &mut Environment,
+    cryptor: MutPtr<CCCryptor>,
+    data_out: MutVoidPtr,
+    data_out_available: GuestUSize,
+    data_out_moved: MutPtr<GuestUSize>,
+) -> CCCryptorStatus {
+    let Some(cryptor) = State::get(env).cryptors.get_mut(&cryptor) else {
+        return kCCParamError;
+    };
+    let needed = cryptor.final_output_length(0);
+    if needed > data_out_available as usize {
+        if !data_out_moved.is_null() {
+            env.mem.write(data_out_moved, needed as GuestUSize);
+        }
+        return kCCBufferTooSmall;
+    }
+    match cryptor.finish() {
+        Ok(output) => write_output(env, &output, data_out, data_out_available, data_out_moved),
+        Err(status) => status,
+    }
+}
+
+fn CCCryptorReset(
+    env: &mut Environment,
+    cryptor: MutPtr<CCCryptor>,
+    iv: ConstVoidPtr,
+) -> CCCryptorStatus {
+    let iv = read_iv(env, iv).map_or([0; AES_BLOCK_SIZE], |iv| iv.try_into().unwrap());
+    let Some(cryptor) = State::get(env).cryptors.get_mut(&cryptor) else {
+        return kCCParamError;
+    };
+    cryptor.chain = iv;
+    cryptor.buffered.clear();
+    kCCSuccess
+}
+
+#[allow(clippy::too_many_arguments)]
+fn CCCrypt(
+    env: &mut Environment,
+    op: CCOperation,
+    alg: CCAlgorithm,
+    options: CCOptions,
+    key: ConstVoidPtr,
+    key_length: GuestUSize,
+    iv: ConstVoidPtr,
+    data_in: ConstVoidPtr,
+    data_in_length: GuestUSize,
+    data_out: MutVoidPtr,
+    data_out_available: GuestUSize,
+    data_out_moved: MutPtr<GuestUSize>,
+) -> CCCryptorStatus {
+    let key = env.mem.bytes_at(key.cast(), key_length);
+    let mut cryptor = match Cryptor::new(op, alg, options, key, read_iv(env, iv)) {
+        Ok(cryptor) => cryptor,
+        Err(status) => return status,
+    };
+    let needed = cryptor.final_output_length(data_in_length as usize);
+    if needed > data_out_available as usize {
+        if !data_out_moved.is_null() {
+            env.mem.write(data_out_moved, needed as GuestUSize);
+        }
+        return kCCBufferTooSmall;
+    }
+    let mut output = cryptor.update(env.mem.bytes_at(data_in.cast(), data_in_length));
+    match cryptor.finish() {
+        Ok(final_output) => output.extend_from_slice(&final_output),
+        Err(status) => return status,
+    }
+    write_output(env, &output, data_out, data_out_available, data_out_moved)
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CCCryptorCreate(_, _, _, _, _, _, _)),
+    export_c_func!(CCCryptorRelease(_)),
+    export_c_func!(CCCryptorGetOutputLength(_, _, _)),
+    export_c_func!(CCCryptorUpdate(_, _, _, _, _, _)),
+    export_c_func!(CCCryptorFinal(_, _, _, _)),
+    export_c_func!(CCCryptorReset(_, _)),
+    export_c_func!(CCCrypt(_, _, _, _, _, _, _, _, _, _, _)),
+];
@@ -0,0 +1,575 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CommonDigest.h` (MD5, SHA-1 and SHA-256)
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr};
+use crate::Environment;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Digests in progress, keyed by the address of the app's context struct
+    /// (`CC_MD5_CTX` etc), which is otherwise unused.
+    contexts: HashMap<MutVoidPtr, Digest>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.common_crypto.common_digest
+    }
+}
+
+/// The digest algorithms supported. MD5, SHA-1, SHA-224 and SHA-256 use
+/// 64-byte blocks and 32-bit words, SHA-384 and SHA-512 use 128-byte blocks and
+/// 64-bit words, but they all use the same kind of padding, so they share most
+/// of their implementation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum DigestAlgorithm {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+impl DigestAlgorithm {
+    pub(super) fn digest_length(self) -> usize {
+        match self {
+            DigestAlgorithm::Md5 => 16,
+            DigestAlgorithm::Sha1 => 20,
+            DigestAlgorithm::Sha224 => 28,
+            DigestAlgorithm::Sha256 => 32,
+            DigestAlgorithm::Sha384 => 48,
+            DigestAlgorithm::Sha512 => 64,
+        }
+    }
+    pub(super) fn block_size(self) -> usize {
+        if self.has_64_bit_words() {
+            128
+        } else {
+            64
+        }
+    }
+    fn has_64_bit_words(self) -> bool {
+        matches!(self, DigestAlgorithm::Sha384 | DigestAlgorithm::Sha512)
+    }
+}
+
+/// Host implementation of the digest algorithms.
+#[derive(Clone)]
+pub(super) struct Digest {
+    algorithm: DigestAlgorithm,
+    /// Algorithms with 32-bit words only use the low half of each word.
+    state: [u64; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+impl Digest {
+    pub(super) fn new(algorithm: DigestAlgorithm) -> Self {
+        let state = match algorithm {
+            DigestAlgorithm::Sha384 => SHA384_INITIAL_STATE,
+            DigestAlgorithm::Sha512 => SHA512_INITIAL_STATE,
+            _ => {
+                let state: [u32; 8] = match algorithm {
+                    DigestAlgorithm::Md5 => {
+                        [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0, 0, 0, 0]
+                    }
+                    DigestAlgorithm::Sha1 => [
+                        0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0, 0, 0, 0,
+                    ],
+                    DigestAlgorithm::Sha224 => [
+                        0xc1059ed8, 0x367cd507, 0x3070dd17, 0xf70e5939, 0xffc00b31, 0x68581511,
+                        0x64f98fa7, 0xbefa4fa4,
+                    ],
+                    DigestAlgorithm::Sha256 => [
+                        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+                        0x1f83d9ab, 0x5be0cd19,
+                    ],
+                    DigestAlgorithm::Sha384 | DigestAlgorithm::Sha512 => unreachable!(),
+                };
+                state.map(u64::from)
+            }
+        };
+        Digest {
+            algorithm,
+            state,
+            buffer: Vec::with_capacity(algorithm.block_size()),
+            length: 0,
+        }
+    }
+
+    pub(super) fn update(&mut self, mut data: &[u8]) {
+        let block_size = self.algorithm.block_size();
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let take = (block_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == block_size {
+                let block = std::mem::replace(&mut self.buffer, Vec::with_capacity(block_size));
+                self.compress(&block);
+            }
+        }
+    }
+
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        let block_size = self.algorithm.block_size();
+        // The message length in bits is stored in 64 bits for the algorithms
+        // with 64-byte blocks, and in 128 bits for the others.
+        let length_size = block_size / 8;
+        let bit_length = u128::from(self.length) * 8;
+        let mut padding = vec![0x80];
+        padding.resize(
+            1 + (block_size * 2 - 1 - length_size - self.buffer.len()) % block_size,
+            0,
+        );
+        if self.algorithm == DigestAlgorithm::Md5 {
+            padding.extend_from_slice(&bit_length.to_le_bytes()[..length_size]);
+        } else {
+            padding.extend_from_slice(&bit_length.to_be_bytes()[16 - length_size..]);
+        }
+        self.update(&padding);
+        assert!(self.buffer.is_empty());
+
+        let mut bytes: Vec<u8> = if self.algorithm == DigestAlgorithm::Md5 {
+            self.state
+                .iter()
+                .flat_map(|&word| (word as u32).to_le_bytes())
+                .collect()
+        } else if self.algorithm.has_64_bit_words() {
+            self.state
+                .iter()
+                .flat_map(|&word| word.to_be_bytes())
+                .collect()
+        } else {
+            self.state
+                .iter()
+                .flat_map(|&word| (word as u32).to_be_bytes())
+                .collect()
+        };
+        bytes.truncate(self.algorithm.digest_length());
+        bytes
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        if self.algorithm.has_64_bit_words() {
+            sha512_compress(&mut self.state, block.try_into().unwrap());
+            return;
+        }
+        let block = block.try_into().unwrap();
+        let mut state = self.state.map(|word| word as u32);
+        match self.algorithm {
+            DigestAlgorithm::Md5 => md5_compress(&mut state, block),
+            DigestAlgorithm::Sha1 => sha1_compress(&mut state, block),
+            DigestAlgorithm::Sha224 | DigestAlgorithm::Sha256 => sha256_compress(&mut state, block),
+            DigestAlgorithm::Sha384 | DigestAlgorithm::Sha512 => unreachable!(),
+        }
+        self.state = state.map(u64::from);
+    }
+}
+
+/// One-shot digest.
+pub(super) fn digest(algorithm: DigestAlgorithm, data: &[u8]) -> Vec<u8> {
+    let mut digest = Digest::new(algorithm);
+    digest.update(data);
+    digest.finish()
+}
+
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+const MD5_SHIFTS: [[u32; 4]; 4] = [
+    [7, 12, 17, 22],
+    [5, 9, 14, 20],
+    [4, 11, 16, 23],
+    [6, 10, 15, 21],
+];
+
+fn md5_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let m: [u32; 16] =
+        std::array::from_fn(|i| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap()));
+    let [mut a, mut b, mut c, mut d] = [state[0], state[1], state[2], state[3]];
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16][i % 4]));
+    }
+    for (word, new) in state.iter_mut().zip([a, b, c, d]) {
+        *word = word.wrapping_add(new);
+    }
+}
+
+fn sha1_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = [state[0], state[1], state[2], state[3], state[4]];
+    for (i, &w) in w.iter().enumerate() {
+        let (f, k) = match i / 20 {
+            0 => ((b & c) | (!b & d), 0x5a827999),
+            1 => (b ^ c ^ d, 0x6ed9eba1),
+            2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+            _ => (b ^ c ^ d, 0xca62c1d6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (word, new) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(new);
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 64];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let mut v = *state;
+    for i in 0..64 {
+        let [a, b, c, d, e, f, g, h] = v;
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA256_K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        v = [
+            temp1.wrapping_add(temp2),
+            a,
+            b,
+            c,
+            d.wrapping_add(temp1),
+            e,
+            f,
+            g,
+        ];
+    }
+    for (word, new) in state.iter_mut().zip(v) {
+        *word = word.wrapping_add(new);
+    }
+}
+
+const SHA384_INITIAL_STATE: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+const SHA512_INITIAL_STATE: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0xfc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x6ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x6f067aa72176fba,
+    0xa637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+fn sha512_compress(state: &mut [u64; 8], block: &[u8; 128]) {
+    let mut w = [0u64; 80];
+    for i in 0..16 {
+        w[i] = u64::from_be_bytes(block[i * 8..i * 8 + 8].try_into().unwrap());
+    }
+    for i in 16..80 {
+        let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+        let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let mut v = *state;
+    for i in 0..80 {
+        let [a, b, c, d, e, f, g, h] = v;
+        let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA512_K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        v = [
+            temp1.wrapping_add(temp2),
+            a,
+            b,
+            c,
+            d.wrapping_add(temp1),
+            e,
+            f,
+            g,
+        ];
+    }
+    for (word, new) in state.iter_mut().zip(v) {
+        *word = word.wrapping_add(new);
+    }
+}
+
+fn one_shot(
+    env: &mut Environment,
+    algorithm: DigestAlgorithm,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+    md: MutPtr<u8>,
+) -> MutPtr<u8> {
+    let result = digest(algorithm, env.mem.bytes_at(data.cast(), len));
+    env.mem
+        .bytes_at_mut(md, result.len() as GuestUSize)
+        .copy_from_slice(&result);
+    md
+}
+
+fn init(env: &mut Environment, algorithm: DigestAlgorithm, c: MutVoidPtr) -> i32 {
+    State::get(env).contexts.insert(c, Digest::new(algorithm));
+    1
+}
+
+fn update(env: &mut Environment, c: MutVoidPtr, data: ConstVoidPtr, len: GuestUSize) -> i32 {
+    let data = env.mem.bytes_at(data.cast(), len);
+    let Some(digest) = env
+        .framework_state
+        .common_crypto
+        .common_digest
+        .contexts
+        .get_mut(&c)
+    else {
+        log!("Warning: digest update for uninitialized context {:?}", c);
+        return 0;
+    };
+    digest.update(data);
+    1
+}
+
+fn finish(env: &mut Environment, md: MutPtr<u8>, c: MutVoidPtr) -> i32 {
+    let Some(digest) = State::get(env).contexts.remove(&c) else {
+        log!("Warning: digest final for uninitialized context {:?}", c);
+        return 0;
+    };
+    let result = digest.finish();
+    env.mem
+        .bytes_at_mut(md, result.len() as GuestUSize)
+        .copy_from_slice(&result);
+    1
+}
+
+fn CC_MD5(
+    env: &mut Environment,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+    md: MutPtr<u8>,
+) -> MutPtr<u8> {
+    one_shot(env, DigestAlgorithm::Md5, data, len, md)
+}
+fn CC_MD5_Init(env: &mut Environment, c: MutVoidPtr) -> i32 {
+    init(env, DigestAlgorithm::Md5, c)
+}
+fn CC_MD5_Update(env: &mut Environment, c: MutVoidPtr, data: ConstVoidPtr, len: GuestUSize) -> i32 {
+    update(env, c, data, len)
+}
+fn CC_MD5_Final(env: &mut Environment, md: MutPtr<u8>, c: MutVoidPtr) -> i32 {
+    finish(env, md, c)
+}
+
+fn CC_SHA1(
+    env: &mut Environment,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+    md: MutPtr<u8>,
+) -> MutPtr<u8> {
+    one_shot(env, DigestAlgorithm::Sha1, data, len, md)
+}
+fn CC_SHA1_Init(env: &mut Environment, c: MutVoidPtr) -> i32 {
+    init(env, DigestAlgorithm::Sha1, c)
+}
+fn CC_SHA1_Update(
+    env: &mut Environment,
+    c: MutVoidPtr,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+) -> i32 {
+    update(env, c, data, len)
+}
+fn CC_SHA1_Final(env: &mut Environment, md: MutPtr<u8>, c: MutVoidPtr) -> i32 {
+    finish(env, md, c)
+}
+
+fn CC_SHA256(
+    env: &mut Environment,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+    md: MutPtr<u8>,
+) -> MutPtr<u8> {
+    one_shot(env, DigestAlgorithm::Sha256, data, len, md)
+}
+fn CC_SHA256_Init(env: &mut Environment, c: MutVoidPtr) -> i32 {
+    init(env, DigestAlgorithm::Sha256, c)
+}
+fn CC_SHA256_Update(
+    env: &mut Environment,
+    c: MutVoidPtr,
+    data: ConstVoidPtr,
+    len: GuestUSize,
+) -> i32 {
+    update(env, c, data, len)
+}
+fn CC_SHA256_Final(env: &mut Environment, md: MutPtr<u8>, c: MutVoidPtr) -> i32 {
+    finish(env, md, c)
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CC_MD5(_, _, _)),
+    export_c_func!(CC_MD5_Init(_)),
+    export_c_func!(CC_MD5_Update(_, _, _)),
+    export_c_func!(CC_MD5_Final(_, _)),
+    export_c_func!(CC_SHA1(_, _, _)),
+    export_c_func!(CC_SHA1_Init(_)),
+    export_c_func!(CC_SHA1_Update(_, _, _)),
+    export_c_func!(CC_SHA1_Final(_, _)),
+    export_c_func!(CC_SHA256(_, _, _)),
+    export_c_func!(CC_SHA256_Init(_)),
+    export_c_func!(CC_SHA256_Update(_, _, _)),
+    export_c_func!(CC_SHA256_Final(_, _)),
+];
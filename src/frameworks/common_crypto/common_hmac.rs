@@ -0,0 +1,157 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CommonHMAC.h`
+
+use super::common_digest::{digest, Digest, DigestAlgorithm};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{ConstVoidPtr, GuestUSize, MutVoidPtr};
+use crate::Environment;
+use std::collections::HashMap;
+
+/// `CCHmacAlgorithm`
+type CCHmacAlgorithm = u32;
+const kCCHmacAlgSHA1: CCHmacAlgorithm = 0;
+const kCCHmacAlgMD5: CCHmacAlgorithm = 1;
+const kCCHmacAlgSHA256: CCHmacAlgorithm = 2;
+const kCCHmacAlgSHA384: CCHmacAlgorithm = 3;
+const kCCHmacAlgSHA512: CCHmacAlgorithm = 4;
+const kCCHmacAlgSHA224: CCHmacAlgorithm = 5;
+
+#[derive(Default)]
+pub struct State {
+    /// HMACs in progress, keyed by the address of the app's `CCHmacContext`,
+    /// which is otherwise unused.
+    contexts: HashMap<MutVoidPtr, Hmac>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.common_crypto.common_hmac
+    }
+}
+
+/// HMAC (RFC 2104) using one of the digest algorithms.
+#[derive(Clone)]
+struct Hmac {
+    inner: Digest,
+    outer: Digest,
+}
+impl Hmac {
+    fn new(algorithm: DigestAlgorithm, key: &[u8]) -> Self {
+        let block_size = algorithm.block_size();
+        let mut key = if key.len() > block_size {
+            digest(algorithm, key)
+        } else {
+            key.to_vec()
+        };
+        key.resize(block_size, 0);
+        let mut inner = Digest::new(algorithm);
+        inner.update(&key.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+        let mut outer = Digest::new(algorithm);
+        outer.update(&key.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+        Hmac { inner, outer }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let Hmac { inner, mut outer } = self;
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+}
+
+/// Returns [None] for an invalid algorithm, which is logged.
+fn digest_algorithm(algorithm: CCHmacAlgorithm) -> Option<DigestAlgorithm> {
+    match algorithm {
+        kCCHmacAlgSHA1 => Some(DigestAlgorithm::Sha1),
+        kCCHmacAlgMD5 => Some(DigestAlgorithm::Md5),
+        kCCHmacAlgSHA256 => Some(DigestAlgorithm::Sha256),
+        kCCHmacAlgSHA384 => Some(DigestAlgorithm::Sha384),
+        kCCHmacAlgSHA512 => Some(DigestAlgorithm::Sha512),
+        kCCHmacAlgSHA224 => Some(DigestAlgorithm::Sha224),
+        _ => {
+            log!("Warning: invalid CCHmacAlgorithm {}", algorithm);
+            None
+        }
+    }
+}
+
+fn write_mac(env: &mut Environment, mac_out: MutVoidPtr, mac: &[u8]) {
+    env.mem
+        .bytes_at_mut(mac_out.cast(), mac.len() as GuestUSize)
+        .copy_from_slice(mac);
+}
+
+fn CCHmac(
+    env: &mut Environment,
+    algorithm: CCHmacAlgorithm,
+    key: ConstVoidPtr,
+    key_length: GuestUSize,
+    data: ConstVoidPtr,
+    data_length: GuestUSize,
+    mac_out: MutVoidPtr,
+) {
+    let Some(algorithm) = digest_algorithm(algorithm) else {
+        return;
+    };
+    let mut hmac = Hmac::new(algorithm, env.mem.bytes_at(key.cast(), key_length));
+    hmac.update(env.mem.bytes_at(data.cast(), data_length));
+    write_mac(env, mac_out, &hmac.finish());
+}
+
+fn CCHmacInit(
+    env: &mut Environment,
+    ctx: MutVoidPtr,
+    algorithm: CCHmacAlgorithm,
+    key: ConstVoidPtr,
+    key_length: GuestUSize,
+) {
+    let Some(algorithm) = digest_algorithm(algorithm) else {
+        return;
+    };
+    let hmac = Hmac::new(algorithm, env.mem.bytes_at(key.cast(), key_length));
+    State::get(env).contexts.insert(ctx, hmac);
+}
+
+fn CCHmacUpdate(
+    env: &mut Environment,
+    ctx: MutVoidPtr,
+    data: ConstVoidPtr,
+    data_length: GuestUSize,
+) {
+    let data = env.mem.bytes_at(data.cast(), data_length);
+    let Some(hmac) = env
+        .framework_state
+        .common_crypto
+        .common_hmac
+        .contexts
+        .get_mut(&ctx)
+    else {
+        log!(
+            "Warning: CCHmacUpdate() for uninitialized context {:?}",
+            ctx
+        );
+        return;
+    };
+    hmac.update(data);
+}
+
+fn CCHmacFinal(env: &mut Environment, ctx: MutVoidPtr, mac_out: MutVoidPtr) {
+    let Some(hmac) = State::get(env).contexts.remove(&ctx) else {
+        log!("Warning: CCHmacFinal() for uninitialized context {:?}", ctx);
+        return;
+    };
+    write_mac(env, mac_out, &hmac.finish());
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CCHmac(_, _, _, _, _, _)),
+    export_c_func!(CCHmacInit(_, _, _, _)),
+    export_c_func!(CCHmacUpdate(_, _, _)),
+    export_c_func!(CCHmacFinal(_, _)),
+];
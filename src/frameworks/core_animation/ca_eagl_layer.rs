@@ -16,6 +16,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation CAEAGLLayer: CALayer
 
+// The content of this layer comes from OpenGL ES, so there's nothing to draw,
+// even if the delegate (usually the app's EAGLView) has a drawRect: method.
+- (())display {
+}
+
 // EAGLDrawable implementation (the only one)
 
 - (id)drawableProperties {
@@ -110,6 +115,36 @@ pub fn find_fullscreen_eagl_layer(env: &mut Environment) -> id {
     layer
 }
 
+/// For use by `EAGLContext` when allocating storage for a `CAEAGLLayer`: get
+/// the size in pixels that the drawable should have. This is the layer's
+/// bounds multiplied by its `contentsScale`, so the app will get a drawable of
+/// the new size if it reallocates the storage after the layer is resized (e.g.
+/// on rotation). The scale hack is not applied.
+pub fn drawable_size(env: &mut Environment, layer: id) -> (u32, u32) {
+    let &CALayerHostObject {
+        bounds,
+        contents_scale,
+        ..
+    } = env.objc.borrow(layer);
+    let width = (bounds.size.width * contents_scale).round();
+    let height = (bounds.size.height * contents_scale).round();
+    if width < 1.0 || height < 1.0 {
+        // Empty storage can't be rendered to, so this is probably a layer that
+        // hasn't been given a size yet. Using the screen size seems to be the
+        // best guess.
+        let (width, height) = env.window().size_unrotated_unscaled();
+        log!(
+            "Warning: CAEAGLLayer {:?} has empty bounds {:?}, using screen size {}x{} for drawable",
+            layer,
+            bounds,
+            width,
+            height
+        );
+        return (width, height);
+    }
+    (width as u32, height as u32)
+}
+
 /// For use by `EAGLContext` when presenting to a `CAEAGLLayer`:
 /// [std::mem::take]s the buffer used to hold the pixels. It should be passed
 /// back to [present_pixels] once it has been filled.
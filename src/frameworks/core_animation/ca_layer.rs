@@ -5,6 +5,8 @@
  */
 //! `CALayer`.
 
+use super::composition::damage_removed_layer;
+use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::core_foundation::{CFRelease, CFRetain};
 use crate::frameworks::core_graphics::cg_bitmap_context::{
     CGBitmapContextCreate, CGBitmapContextGetHeight, CGBitmapContextGetWidth,
@@ -16,11 +18,182 @@ use crate::frameworks::core_graphics::cg_context::{
 use crate::frameworks::core_graphics::cg_image::{
     kCGImageAlphaPremultipliedLast, kCGImageByteOrder32Big,
 };
-use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::ns_string::{get_static_str, to_rust_string};
 use crate::mem::{GuestUSize, Ptr};
-use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, ObjC};
+use crate::objc::{
+    id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr, ObjC,
+};
 use std::collections::HashMap;
 
+pub const kCAGravityCenter: &str = "center";
+pub const kCAGravityTop: &str = "top";
+pub const kCAGravityBottom: &str = "bottom";
+pub const kCAGravityLeft: &str = "left";
+pub const kCAGravityRight: &str = "right";
+pub const kCAGravityTopLeft: &str = "topLeft";
+pub const kCAGravityTopRight: &str = "topRight";
+pub const kCAGravityBottomLeft: &str = "bottomLeft";
+pub const kCAGravityBottomRight: &str = "bottomRight";
+pub const kCAGravityResize: &str = "resize";
+pub const kCAGravityResizeAspect: &str = "resizeAspect";
+pub const kCAGravityResizeAspectFill: &str = "resizeAspectFill";
+
+/// All the `contentsGravity` values, in the order of [ContentsGravity]'s
+/// variants.
+const GRAVITY_NAMES: [&str; 12] = [
+    kCAGravityCenter,
+    kCAGravityTop,
+    kCAGravityBottom,
+    kCAGravityLeft,
+    kCAGravityRight,
+    kCAGravityTopLeft,
+    kCAGravityTopRight,
+    kCAGravityBottomLeft,
+    kCAGravityBottomRight,
+    kCAGravityResize,
+    kCAGravityResizeAspect,
+    kCAGravityResizeAspectFill,
+];
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_kCAGravityCenter",
+        HostConstant::NSString(kCAGravityCenter),
+    ),
+    ("_kCAGravityTop", HostConstant::NSString(kCAGravityTop)),
+    (
+        "_kCAGravityBottom",
+        HostConstant::NSString(kCAGravityBottom),
+    ),
+    ("_kCAGravityLeft", HostConstant::NSString(kCAGravityLeft)),
+    ("_kCAGravityRight", HostConstant::NSString(kCAGravityRight)),
+    (
+        "_kCAGravityTopLeft",
+        HostConstant::NSString(kCAGravityTopLeft),
+    ),
+    (
+        "_kCAGravityTopRight",
+        HostConstant::NSString(kCAGravityTopRight),
+    ),
+    (
+        "_kCAGravityBottomLeft",
+        HostConstant::NSString(kCAGravityBottomLeft),
+    ),
+    (
+        "_kCAGravityBottomRight",
+        HostConstant::NSString(kCAGravityBottomRight),
+    ),
+    (
+        "_kCAGravityResize",
+        HostConstant::NSString(kCAGravityResize),
+    ),
+    (
+        "_kCAGravityResizeAspect",
+        HostConstant::NSString(kCAGravityResizeAspect),
+    ),
+    (
+        "_kCAGravityResizeAspectFill",
+        HostConstant::NSString(kCAGravityResizeAspectFill),
+    ),
+];
+
+/// How a layer's `contents` image is positioned and scaled within its bounds.
+///
+/// Note that like the real Core Animation, "top" and "bottom" are in terms of
+/// the macOS coordinate system, where y points upwards. On iPhone OS, this
+/// means they are the wrong way round!
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContentsGravity {
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Resize,
+    ResizeAspect,
+    ResizeAspectFill,
+}
+impl ContentsGravity {
+    const ALL: [ContentsGravity; 12] = [
+        ContentsGravity::Center,
+        ContentsGravity::Top,
+        ContentsGravity::Bottom,
+        ContentsGravity::Left,
+        ContentsGravity::Right,
+        ContentsGravity::TopLeft,
+        ContentsGravity::TopRight,
+        ContentsGravity::BottomLeft,
+        ContentsGravity::BottomRight,
+        ContentsGravity::Resize,
+        ContentsGravity::ResizeAspect,
+        ContentsGravity::ResizeAspectFill,
+    ];
+
+    pub fn name(self) -> &'static str {
+        GRAVITY_NAMES[self as usize]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let idx = GRAVITY_NAMES.iter().position(|&n| n == name)?;
+        Some(Self::ALL[idx])
+    }
+
+    /// Work out where to draw contents of a particular size, given the layer's
+    /// bounds (in the coordinate space the result should be in).
+    pub fn contents_rect(self, bounds: CGRect, contents_size: CGSize) -> CGRect {
+        let CGSize { width, height } = contents_size;
+        let size = match self {
+            ContentsGravity::Resize => return bounds,
+            ContentsGravity::ResizeAspect | ContentsGravity::ResizeAspectFill => {
+                if width <= 0.0 || height <= 0.0 {
+                    return bounds;
+                }
+                let x_scale = bounds.size.width / width;
+                let y_scale = bounds.size.height / height;
+                let scale = if self == ContentsGravity::ResizeAspect {
+                    x_scale.min(y_scale)
+                } else {
+                    x_scale.max(y_scale)
+                };
+                CGSize {
+                    width: width * scale,
+                    height: height * scale,
+                }
+            }
+            _ => contents_size,
+        };
+
+        // Fractions of the free space that go on the left and the top.
+        // Remember that y points down here, but not in the gravity names.
+        let x_fraction = match self {
+            ContentsGravity::Left | ContentsGravity::TopLeft | ContentsGravity::BottomLeft => 0.0,
+            ContentsGravity::Right | ContentsGravity::TopRight | ContentsGravity::BottomRight => {
+                1.0
+            }
+            _ => 0.5,
+        };
+        let y_fraction = match self {
+            ContentsGravity::Bottom
+            | ContentsGravity::BottomLeft
+            | ContentsGravity::BottomRight => 0.0,
+            ContentsGravity::Top | ContentsGravity::TopLeft | ContentsGravity::TopRight => 1.0,
+            _ => 0.5,
+        };
+        CGRect {
+            origin: CGPoint {
+                x: bounds.origin.x + (bounds.size.width - size.width) * x_fraction,
+                y: bounds.origin.y + (bounds.size.height - size.height) * y_fraction,
+            },
+            size,
+        }
+    }
+}
+
 pub(super) struct CALayerHostObject {
     /// Possibly nil, usually a UIView. This is a weak reference.
     delegate: id,
@@ -38,6 +211,10 @@ pub(super) struct CALayerHostObject {
     pub(super) needs_display: bool,
     /// `CGImageRef*`
     pub(super) contents: id,
+    pub(super) contents_gravity: ContentsGravity,
+    /// Ratio of pixels to points. This was added in iPhone OS 4, where it
+    /// determines the size of a `CAEAGLLayer`'s drawable.
+    pub(super) contents_scale: CGFloat,
     /// For CAEAGLLayer only
     pub(super) drawable_properties: id,
     /// For CAEAGLLayer only (internal state for compositor)
@@ -48,6 +225,13 @@ pub(super) struct CALayerHostObject {
     pub(super) gles_texture: Option<crate::gles::gles11_raw::types::GLuint>,
     /// Internal state for compositor
     pub(super) gles_texture_is_up_to_date: bool,
+    /// Internal state for compositor: a property affecting how this layer or
+    /// its sublayers are drawn has changed since the last frame.
+    pub(super) needs_composite: bool,
+    /// Internal state for compositor: the area of the screen this layer was
+    /// drawn to in the last frame, if it was drawn. See
+    /// [super::composition::damage_removed_layer].
+    pub(super) last_composited_rect: Option<CGRect>,
 }
 impl HostObject for CALayerHostObject {}
 
@@ -57,7 +241,9 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation CALayer: NSObject
 
-+ (id)alloc {
+// Overriding allocWithZone: rather than alloc means this also works for
+// subclasses defined by the app, whichever they use.
++ (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = Box::new(CALayerHostObject {
         delegate: nil,
         sublayers: Vec::new(),
@@ -74,11 +260,15 @@ pub const CLASSES: ClassExports = objc_classes! {
         background_color: nil, // transparency
         needs_display: true,
         contents: nil,
+        contents_gravity: ContentsGravity::Resize,
+        contents_scale: 1.0,
         drawable_properties: nil,
         presented_pixels: None,
         cg_context: None,
         gles_texture: None,
         gles_texture_is_up_to_date: false,
+        needs_composite: true,
+        last_composited_rect: None,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -143,7 +333,9 @@ pub const CLASSES: ClassExports = objc_classes! {
     } else {
         retain(env, layer);
         () = msg![env; layer removeFromSuperlayer];
-        env.objc.borrow_mut::<CALayerHostObject>(layer).superlayer = this;
+        let host_obj = env.objc.borrow_mut::<CALayerHostObject>(layer);
+        host_obj.superlayer = this;
+        host_obj.needs_composite = true;
         env.objc.borrow_mut::<CALayerHostObject>(this).sublayers.push(layer);
     }
 }
@@ -155,6 +347,8 @@ pub const CLASSES: ClassExports = objc_classes! {
         return;
     }
 
+    damage_removed_layer(env, this);
+
     let CALayerHostObject { ref mut sublayers, .. } = env.objc.borrow_mut(superlayer);
     let idx = sublayers.iter().position(|&sublayer| sublayer == this).unwrap();
     let sublayer = sublayers.remove(idx);
@@ -166,19 +360,25 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<CALayerHostObject>(this).bounds
 }
 - (())setBounds:(CGRect)bounds {
-    env.objc.borrow_mut::<CALayerHostObject>(this).bounds = bounds;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.bounds = bounds;
+    host_obj.needs_composite = true;
 }
 - (CGPoint)position {
     env.objc.borrow::<CALayerHostObject>(this).position
 }
 - (())setPosition:(CGPoint)position {
-    env.objc.borrow_mut::<CALayerHostObject>(this).position = position;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.position = position;
+    host_obj.needs_composite = true;
 }
 - (CGPoint)anchorPoint {
     env.objc.borrow::<CALayerHostObject>(this).anchor_point
 }
 - (())setAnchorPoint:(CGPoint)anchor_point {
-    env.objc.borrow_mut::<CALayerHostObject>(this).anchor_point = anchor_point;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.anchor_point = anchor_point;
+    host_obj.needs_composite = true;
 }
 
 - (CGRect)frame {
@@ -201,8 +401,10 @@ pub const CLASSES: ClassExports = objc_classes! {
         bounds,
         position,
         anchor_point,
+        needs_composite,
         ..
     } = env.objc.borrow_mut(this);
+    *needs_composite = true;
     *position = CGPoint {
         x: frame.origin.x + frame.size.width * anchor_point.x,
         y: frame.origin.y + frame.size.height * anchor_point.y,
@@ -217,21 +419,27 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<CALayerHostObject>(this).hidden
 }
 - (())setHidden:(bool)hidden {
-    env.objc.borrow_mut::<CALayerHostObject>(this).hidden = hidden;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.hidden = hidden;
+    host_obj.needs_composite = true;
 }
 
 - (bool)isOpaque {
     env.objc.borrow::<CALayerHostObject>(this).opaque
 }
 - (())setOpaque:(bool)opaque {
-    env.objc.borrow_mut::<CALayerHostObject>(this).opaque = opaque;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.opaque = opaque;
+    host_obj.needs_composite = true;
 }
 
 - (f32)opacity {
     env.objc.borrow::<CALayerHostObject>(this).opacity
 }
 - (())setOpacity:(f32)opacity {
-    env.objc.borrow_mut::<CALayerHostObject>(this).opacity = opacity;
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.opacity = opacity;
+    host_obj.needs_composite = true;
 }
 
 // See remarks in ui_view.rs about the type of this property
@@ -240,6 +448,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 - (())setBackgroundColor:(id)new_color {
     let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.needs_composite = true;
     let old_color = std::mem::replace(&mut host_obj.background_color, new_color);
     if new_color != nil {
         CFRetain(env, new_color); // CFRetain doesn't like nil
@@ -257,28 +466,41 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 // TODO: support setNeedsDisplayInRect:
 - (())displayIfNeeded {
-    let &mut CALayerHostObject {
-        ref mut needs_display,
-        delegate,
-        ..
-    } = env.objc.borrow_mut(this);
+    let needs_display = &mut env.objc.borrow_mut::<CALayerHostObject>(this).needs_display;
     if !std::mem::take(needs_display) {
         return;
     }
+    // The app may have overridden this.
+    () = msg![env; this display];
+}
+
+- (())display {
+    let layer_class = ObjC::read_isa(this, &env.mem);
+    let ca_layer_class = env.objc.get_known_class("CALayer", &mut env.mem);
+    let draw_in_context_sel = env.objc.lookup_selector("drawInContext:").unwrap();
+    // A subclass of CALayer (probably defined by the app) can draw itself
+    // rather than relying on its delegate.
+    let layer_draws_itself = env.objc.class_overrides_method_of_superclass(
+        layer_class,
+        draw_in_context_sel,
+        ca_layer_class,
+    );
 
-    if delegate == nil {
+    let delegate = env.objc.borrow::<CALayerHostObject>(this).delegate;
+    if delegate == nil && !layer_draws_itself {
         return;
     }
 
-    let delegate_class = ObjC::read_isa(delegate, &env.mem);
-
     // According to the Core Animation Programming Guide, a layer delegate must
     // provide either displayLayer: or drawLayer:inContext:, and the former is
     // called if both are defined.
 
-    if env.objc.class_has_method_named(delegate_class, "displayLayer:") {
-        () = msg![env; delegate displayLayer:this];
-        return;
+    if delegate != nil && !layer_draws_itself {
+        let delegate_class = ObjC::read_isa(delegate, &env.mem);
+        if env.objc.class_has_method_named(delegate_class, "displayLayer:") {
+            () = msg![env; delegate displayLayer:this];
+            return;
+        }
     }
 
     // UIView has a method called drawRect: that subclasses override if they
@@ -292,7 +514,12 @@ pub const CLASSES: ClassExports = objc_classes! {
     // (TODO: somehow do this optimization in UIView rather than CALayer.
     // Apparently Apple do it that way: https://stackoverflow.com/q/4979192)
     let ui_view_class = env.objc.get_known_class("UIView", &mut env.mem);
-    if env.objc.class_is_subclass_of(delegate_class, ui_view_class) {
+    let delegate_class = if delegate != nil {
+        ObjC::read_isa(delegate, &env.mem)
+    } else {
+        nil
+    };
+    if !layer_draws_itself && env.objc.class_is_subclass_of(delegate_class, ui_view_class) {
         let draw_rect_sel = env.objc.lookup_selector("drawRect:").unwrap();
         let draw_layer_sel = env.objc.lookup_selector("drawLayer:inContext:").unwrap();
         if !env.objc.class_overrides_method_of_superclass(
@@ -357,10 +584,18 @@ pub const CLASSES: ClassExports = objc_classes! {
     CGContextTranslateCTM(env, cg_context, -origin.x, -origin.y);
     // TODO: move clearing to UIKit (clearsContextBeforeDrawing)?
     CGContextClearRect(env, cg_context, CGRect { origin, size });
-    () = msg![env; delegate drawLayer:this inContext:cg_context];
+    () = msg![env; this drawInContext:cg_context];
     CGContextTranslateCTM(env, cg_context, origin.x, origin.y);
 }
 
+- (())drawInContext:(CGContextRef)context {
+    // The default implementation defers to the delegate.
+    let delegate = env.objc.borrow::<CALayerHostObject>(this).delegate;
+    if delegate != nil {
+        () = msg![env; delegate drawLayer:this inContext:context];
+    }
+}
+
 // CGImageRef*
 - (id)contents {
     env.objc.borrow::<CALayerHostObject>(this).contents
@@ -368,11 +603,34 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (())setContents:(id)new_contents {
     let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
     host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_composite = true;
     let old_contents = std::mem::replace(&mut host_obj.contents, new_contents);
     retain(env, new_contents);
     release(env, old_contents);
 }
 
+- (id)contentsGravity {
+    let gravity = env.objc.borrow::<CALayerHostObject>(this).contents_gravity;
+    get_static_str(env, gravity.name())
+}
+- (())setContentsGravity:(id)gravity { // NSString*
+    let name = to_rust_string(env, gravity);
+    let Some(gravity) = ContentsGravity::from_name(&name) else {
+        log!("Warning: ignoring unknown contentsGravity {:?}", name);
+        return;
+    };
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.contents_gravity = gravity;
+    host_obj.needs_composite = true;
+}
+
+- (CGFloat)contentsScale {
+    env.objc.borrow::<CALayerHostObject>(this).contents_scale
+}
+- (())setContentsScale:(CGFloat)scale {
+    env.objc.borrow_mut::<CALayerHostObject>(this).contents_scale = scale;
+}
+
 - (bool)containsPoint:(CGPoint)point {
     let bounds: CGRect = msg![env; this bounds];
     let x_range = bounds.origin.x..(bounds.origin.x + bounds.size.width);
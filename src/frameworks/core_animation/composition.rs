@@ -8,6 +8,14 @@
 //! This is completely original; I don't think Apple document how this works and
 //! I haven't attempted to reverse-engineer the details. As such, it probably
 //! diverges wildly from what the real iPhone OS does.
+//!
+//! The composited frame is kept in a texture between frames, so only the parts
+//! of the screen affected by layers that changed need to be redrawn. Layers
+//! mark themselves with `needs_composite` when their properties change, and
+//! remember where they were last drawn, so moving or hiding a layer redraws
+//! both where it was and where it is now. If nothing changed at all, the
+//! previous frame is left on the screen. The `--full-recomposite` option turns
+//! this off.
 
 use super::ca_eagl_layer::find_fullscreen_eagl_layer;
 use super::ca_layer::CALayerHostObject;
@@ -19,21 +27,40 @@ use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::gles11_raw::types::*;
 use crate::gles::present::{present_frame, FpsCounter};
 use crate::gles::GLES;
+use crate::matrix::Matrix;
 use crate::mem::Mem;
 use crate::objc::{id, msg, msg_class, nil, ObjC};
 use crate::Environment;
 use std::time::{Duration, Instant};
 
+/// Arguments for [present_frame]: viewport, rotation matrix and virtual cursor.
+type PresentFrameArgs = ((u32, u32, u32, u32), Matrix<2>, Option<(f32, f32, bool)>);
+
 #[derive(Default)]
 pub(super) struct State {
     texture_framebuffer: Option<(GLuint, GLuint)>,
     recomposite_next: Option<Instant>,
     fps_counter: Option<FpsCounter>,
+    /// Parts of the screen where layers that were removed from the layer tree
+    /// since the last frame were drawn. See [damage_removed_layer].
+    removed_layer_damage: Option<CGRect>,
+    /// Root layer of the last composited frame. If this is [None], or isn't
+    /// the current root layer, the texture's contents are stale and the next
+    /// frame must be redrawn in full.
+    last_root_layer: Option<id>,
+    /// Arguments used when last presenting the composited frame, so that
+    /// presenting can be skipped if nothing has changed.
+    last_present_args: Option<PresentFrameArgs>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut State {
+        &mut env.framework_state.core_animation.composition
+    }
 }
 
-/// For use by `NSRunLoop`: call this 60 times per second. Composites the app's
-/// visible layers (i.e. UI) and presents it to the screen. Does nothing if
-/// composition isn't in use or it's too soon.
+/// For use by `NSRunLoop`: call this 60 times per second (or at the framerate
+/// limit, if lower). Composites the app's visible layers (i.e. UI) and presents
+/// it to the screen. Does nothing if composition isn't in use or it's too soon.
 ///
 /// Returns the time a recomposite is due, if any.
 pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
@@ -50,26 +77,21 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         .last()
     else {
         log_dbg!("No visible window, skipping composition");
+        forget_last_frame(env);
         return None;
     };
 
     if find_fullscreen_eagl_layer(env) != nil {
         // No composition done, EAGLContext will present directly.
         log_dbg!("Using CAEAGLLayer fast path, skipping composition");
+        forget_last_frame(env);
         return None;
     }
 
-    if env.options.print_fps {
-        env.framework_state
-            .core_animation
-            .composition
-            .fps_counter
-            .get_or_insert_with(FpsCounter::start)
-            .count_frame(format_args!("Core Animation compositor"));
-    }
-
     let now = Instant::now();
-    let interval = 1.0 / 60.0; // 60Hz
+    // 60Hz, unless the framerate limit (which the power-saving governor may
+    // lower) is below that.
+    let interval = 1.0 / env.fps_limit().map_or(60.0, |limit| limit.min(60.0));
     let new_recomposite_next = if let Some(recomposite_next) = env
         .framework_state
         .core_animation
@@ -112,10 +134,10 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         let screen: id = msg_class![env; UIScreen mainScreen];
         msg![env; screen bounds]
     };
-    let scale_hack: u32 = env.options.scale_hack.get();
-    let fb_width = screen_bounds.size.width as u32 * scale_hack;
-    let fb_height = screen_bounds.size.height as u32 * scale_hack;
-    let present_frame_args = (
+    let scale_hack: f32 = env.options.scale_hack;
+    let fb_width = (screen_bounds.size.width * scale_hack).round() as u32;
+    let fb_height = (screen_bounds.size.height * scale_hack).round() as u32;
+    let present_frame_args: PresentFrameArgs = (
         env.window().viewport(),
         env.window().rotation_matrix(),
         env.window().virtual_cursor_visible_at(),
@@ -125,12 +147,50 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
 
     // Initial state for layer tree traversal (see composite_layer_recursive)
     let origin = CGPoint { x: 0.0, y: 0.0 };
-    let clip_to = CGRect {
+    let screen_rect = CGRect {
         origin,
         size: screen_bounds.size,
     };
     let opacity = 1.0;
 
+    // Work out what needs to be redrawn. The damage always has to be
+    // collected, even for a full redraw, so that layers remember where they
+    // were drawn.
+    let mut damage = State::get(env).removed_layer_damage.take();
+    collect_damage(
+        &mut env.objc,
+        root_layer,
+        origin,
+        /* visible: */ true,
+        /* ancestor_changed: */ false,
+        &mut damage,
+    );
+    let full_recomposite = env.options.full_recomposite;
+    let state = State::get(env);
+    let full_redraw = full_recomposite
+        || state.texture_framebuffer.is_none()
+        || state.last_root_layer != Some(root_layer);
+    let clip_to = if full_redraw {
+        Some(screen_rect)
+    } else {
+        damage
+            .map(|damage| clip_rects(screen_rect, round_out_rect(damage)))
+            .filter(|damage| damage.size.width > 0.0 && damage.size.height > 0.0)
+    };
+    if clip_to.is_none() && state.last_present_args == Some(present_frame_args) {
+        log_dbg!("Nothing changed, skipping composition");
+        return new_recomposite_next;
+    }
+    state.last_root_layer = Some(root_layer);
+    state.last_present_args = Some(present_frame_args);
+
+    if env.options.print_fps {
+        State::get(env)
+            .fps_counter
+            .get_or_insert_with(FpsCounter::start)
+            .count_frame(format_args!("Core Animation compositor"));
+    }
+
     let window = env.window.as_mut().unwrap();
     window.make_internal_gl_ctx_current();
     let gles = window.get_internal_gl_ctx();
@@ -198,29 +258,37 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         texture
     };
 
-    // Clear the framebuffer and set up state to prepare for rendering
-    unsafe {
-        gles.Viewport(0, 0, fb_width as _, fb_height as _);
-        gles.ClearColor(0.0, 0.0, 0.0, 1.0);
-        gles.Clear(gles11::COLOR_BUFFER_BIT);
-        gles.Enable(gles11::SCISSOR_TEST);
-        gles.Scissor(0, 0, fb_width as _, fb_height as _);
-        gles.Color4f(1.0, 1.0, 1.0, 1.0);
-    }
+    if let Some(clip_to) = clip_to {
+        log_dbg!("Recompositing {:?}", clip_to);
 
-    // Here's where the actual drawing happens
-    unsafe {
-        composite_layer_recursive(
-            gles,
-            &mut env.objc,
-            &env.mem,
-            root_layer,
-            origin,
-            clip_to,
-            opacity,
-            scale_hack,
-            fb_height,
-        );
+        // Clear the area to be redrawn and set up state to prepare for
+        // rendering
+        unsafe {
+            gles.Viewport(0, 0, fb_width as _, fb_height as _);
+            gles.Enable(gles11::SCISSOR_TEST);
+            let (x, y, w, h) = gl_rect_from_cg_rect(clip_to, scale_hack, fb_height);
+            gles.Scissor(x, y, w, h);
+            gles.ClearColor(0.0, 0.0, 0.0, 1.0);
+            gles.Clear(gles11::COLOR_BUFFER_BIT);
+            gles.Color4f(1.0, 1.0, 1.0, 1.0);
+        }
+
+        // Here's where the actual drawing happens
+        unsafe {
+            composite_layer_recursive(
+                gles,
+                &mut env.objc,
+                &env.mem,
+                root_layer,
+                origin,
+                clip_to,
+                opacity,
+                scale_hack,
+                fb_height,
+            );
+        }
+    } else {
+        log_dbg!("Nothing changed, presenting previous frame again");
     }
 
     // Clean up some GL state
@@ -249,6 +317,123 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
     new_recomposite_next
 }
 
+/// Make sure the next frame is redrawn in full, e.g. because something else
+/// has been presented in the meantime.
+fn forget_last_frame(env: &mut Environment) {
+    let state = State::get(env);
+    state.last_root_layer = None;
+    state.last_present_args = None;
+}
+
+/// For use by `CALayer` when a layer is removed from its superlayer: the parts
+/// of the screen where it and its sublayers were drawn need to be redrawn.
+pub(super) fn damage_removed_layer(env: &mut Environment, layer: id) {
+    fn traverse(objc: &mut ObjC, layer: id, damage: &mut Option<CGRect>) {
+        let host_obj = objc.borrow_mut::<CALayerHostObject>(layer);
+        if let Some(rect) = host_obj.last_composited_rect.take() {
+            add_damage(damage, rect);
+        }
+        for sublayer in host_obj.sublayers.clone() {
+            traverse(objc, sublayer, damage);
+        }
+    }
+
+    let mut damage = State::get(env).removed_layer_damage;
+    traverse(&mut env.objc, layer, &mut damage);
+    State::get(env).removed_layer_damage = damage;
+}
+
+/// Traverses the layer tree to find the parts of the screen that need to be
+/// redrawn because layers were changed, moved, shown or hidden since the last
+/// frame. Each layer's record of where it was drawn is updated.
+///
+/// This must compute positions the same way as [composite_layer_recursive].
+fn collect_damage(
+    objc: &mut ObjC,
+    layer: id,
+    origin: CGPoint,
+    visible: bool,
+    ancestor_changed: bool,
+    damage: &mut Option<CGRect>,
+) {
+    let host_obj = objc.borrow::<CALayerHostObject>(layer);
+
+    let visible = visible && !host_obj.hidden;
+    let bounds = host_obj.bounds;
+    let absolute_frame = {
+        let position = host_obj.position;
+        let anchor_point = host_obj.anchor_point;
+        CGRect {
+            origin: CGPoint {
+                x: origin.x + position.x - bounds.size.width * anchor_point.x,
+                y: origin.y + position.y - bounds.size.height * anchor_point.y,
+            },
+            size: bounds.size,
+        }
+    };
+    let drawn_rect = if !visible {
+        None
+    } else if host_obj.contents != nil {
+        // Images set as the layer's contents can extend outside of it.
+        let (width, height) = cg_image::borrow_image(objc, host_obj.contents).dimensions();
+        let contents_size = CGSize {
+            width: width as CGFloat,
+            height: height as CGFloat,
+        };
+        let contents_frame = host_obj
+            .contents_gravity
+            .contents_rect(absolute_frame, contents_size);
+        Some(union_rects(absolute_frame, contents_frame))
+    } else {
+        Some(absolute_frame)
+    };
+
+    let need_texture = host_obj.presented_pixels.is_some()
+        || host_obj.contents != nil
+        || host_obj.cg_context.is_some();
+    // A change to a layer (e.g. its position or opacity) can affect how its
+    // sublayers are drawn, so they are considered changed too.
+    let changed = ancestor_changed
+        || host_obj.needs_composite
+        || (need_texture && !host_obj.gles_texture_is_up_to_date);
+
+    let host_obj = objc.borrow_mut::<CALayerHostObject>(layer);
+    if changed {
+        if let Some(rect) = host_obj.last_composited_rect {
+            add_damage(damage, rect);
+        }
+        if let Some(rect) = drawn_rect {
+            add_damage(damage, rect);
+        }
+    }
+    host_obj.needs_composite = false;
+    host_obj.last_composited_rect = drawn_rect;
+
+    // Hidden sublayers don't need to be visited unless they were drawn in the
+    // last frame.
+    if !visible && !changed {
+        return;
+    }
+
+    let sublayer_origin = CGPoint {
+        x: absolute_frame.origin.x - bounds.origin.x,
+        y: absolute_frame.origin.y - bounds.origin.y,
+    };
+    for sublayer in host_obj.sublayers.clone() {
+        collect_damage(objc, sublayer, sublayer_origin, visible, changed, damage);
+    }
+}
+
+fn add_damage(damage: &mut Option<CGRect>, rect: CGRect) {
+    if rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+        return;
+    }
+    *damage = Some(match *damage {
+        Some(existing) => union_rects(existing, rect),
+        None => rect,
+    });
+}
+
 /// Call `displayIfNeeded` on all relevant layers in the tree, so their bitmaps
 /// are up to date before compositing.
 fn display_layers(env: &mut Environment, root_layer: id) {
@@ -284,7 +469,7 @@ unsafe fn composite_layer_recursive(
     origin: CGPoint,
     clip_to: CGRect,
     opacity: CGFloat,
-    scale_hack: u32,
+    scale_hack: f32,
     fb_height: u32,
 ) {
     // TODO: this can't handle zPosition, non-AABB layer transforms, rounded
@@ -370,6 +555,21 @@ unsafe fn composite_layer_recursive(
     // re-borrow immutably
     let host_obj = objc.borrow::<CALayerHostObject>(layer);
 
+    // Images set as the layer's contents can be scaled and positioned within
+    // the layer, or even extend outside of it.
+    let contents_frame = if need_texture && host_obj.contents != nil {
+        let (width, height) = cg_image::borrow_image(objc, host_obj.contents).dimensions();
+        let contents_size = CGSize {
+            width: width as CGFloat,
+            height: height as CGFloat,
+        };
+        host_obj
+            .contents_gravity
+            .contents_rect(absolute_frame, contents_size)
+    } else {
+        absolute_frame
+    };
+
     // Update texture with CGImageRef or CGContextRef pixels, if any
     if need_update {
         if host_obj.contents != nil {
@@ -405,8 +605,12 @@ unsafe fn composite_layer_recursive(
             gles.BlendFunc(gles11::ONE, gles11::ONE_MINUS_SRC_ALPHA);
         }
 
-        let (x, y, w, h) = gl_rect_from_cg_rect(absolute_frame_clipped, scale_hack, fb_height);
+        // TODO: clip contents to bounds when masksToBounds is implemented
+        let contents_frame_clipped = clip_rects(clip_to, contents_frame);
+
+        let (x, y, w, h) = gl_rect_from_cg_rect(contents_frame_clipped, scale_hack, fb_height);
         gles.Scissor(x, y, w, h);
+        let (x, y, w, h) = gl_rect_from_cg_rect(contents_frame, scale_hack, fb_height);
         gles.Viewport(x, y, w, h);
 
         gles.BindBuffer(gles11::ARRAY_BUFFER, 0);
@@ -500,15 +704,45 @@ fn clip_rects(a_clip: CGRect, b_clip: CGRect) -> CGRect {
     }
 }
 
+fn union_rects(a: CGRect, b: CGRect) -> CGRect {
+    let x1 = a.origin.x.min(b.origin.x);
+    let y1 = a.origin.y.min(b.origin.y);
+    let x2 = (a.origin.x + a.size.width).max(b.origin.x + b.size.width);
+    let y2 = (a.origin.y + a.size.height).max(b.origin.y + b.size.height);
+    CGRect {
+        origin: CGPoint { x: x1, y: y1 },
+        size: CGSize {
+            width: x2 - x1,
+            height: y2 - y1,
+        },
+    }
+}
+
+/// Expand a rect to whole points, so that redrawing it also covers any pixels
+/// partially covered by layers at fractional positions.
+fn round_out_rect(rect: CGRect) -> CGRect {
+    let x1 = rect.origin.x.floor();
+    let y1 = rect.origin.y.floor();
+    let x2 = (rect.origin.x + rect.size.width).ceil();
+    let y2 = (rect.origin.y + rect.size.height).ceil();
+    CGRect {
+        origin: CGPoint { x: x1, y: y1 },
+        size: CGSize {
+            width: x2 - x1,
+            height: y2 - y1,
+        },
+    }
+}
+
 fn gl_rect_from_cg_rect(
     rect: CGRect,
-    scale_hack: u32,
+    scale_hack: f32,
     fb_height: u32,
 ) -> (GLint, GLint, GLint, GLint) {
-    let x = (rect.origin.x * scale_hack as f32).round() as GLint;
-    let y = (rect.origin.y * scale_hack as f32).round() as GLint;
-    let w = (rect.size.width * scale_hack as f32).round() as GLint;
-    let h = (rect.size.height * scale_hack as f32).round() as GLint;
+    let x = (rect.origin.x * scale_hack).round() as GLint;
+    let y = (rect.origin.y * scale_hack).round() as GLint;
+    let w = (rect.size.width * scale_hack).round() as GLint;
+    let h = (rect.size.height * scale_hack).round() as GLint;
     // y points up in OpenGL ES, but down in UIKit and Core Animation
     (x, fb_height as GLint - h - y, w, h)
 }
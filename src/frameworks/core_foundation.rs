@@ -21,11 +21,15 @@ pub mod cf_array;
 pub mod cf_bundle;
 pub mod cf_data;
 pub mod cf_dictionary;
+pub mod cf_locale;
+pub mod cf_notification_center;
+pub mod cf_number;
 pub mod cf_run_loop;
 pub mod cf_run_loop_timer;
 pub mod cf_string;
 pub mod cf_type;
 pub mod cf_url;
+pub mod cf_uuid;
 pub mod time;
 
 pub use cf_type::{CFRelease, CFRetain, CFTypeRef};
@@ -33,6 +37,12 @@ pub use cf_type::{CFRelease, CFRetain, CFTypeRef};
 pub type CFIndex = i32;
 pub type CFOptionFlags = u32;
 
+#[derive(Default)]
+pub struct State {
+    cf_notification_center: cf_notification_center::State,
+    cf_uuid: cf_uuid::State,
+}
+
 use crate::abi::GuestArg;
 use crate::impl_GuestRet_for_large_struct;
 use crate::mem::SafeRead;
@@ -12,7 +12,7 @@ use super::cf_array::CFArrayRef;
 use super::cf_string::CFStringRef;
 use super::cf_url::CFURLRef;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::foundation::ns_bundle::NSBundleHostObject;
+use crate::frameworks::foundation::ns_bundle::{NSBundleHostObject, LANG_ID_TO_LANG_PROJ};
 use crate::frameworks::foundation::{ns_array, ns_string, NSUInteger};
 use crate::objc::{id, msg, msg_class, retain};
 use crate::Environment;
@@ -102,27 +102,35 @@ pub fn CFBundleCopyPreferredLocalizationsFromArray(
 
     let preferred_languages: id = msg_class![env; NSLocale preferredLanguages];
 
-    // Check if the user's preferred languages are in loc_array
+    // Find the most preferred language that is in loc_array. Localizations
+    // may use either language codes ("fr") or the legacy names of .lproj
+    // directories ("French"), so both are accepted.
     let loc_count: NSUInteger = msg![env; loc_array count];
     let pref_loc_count: NSUInteger = msg![env; preferred_languages count];
-    for loc_index in 0..loc_count {
-        let loc: id = msg![env; loc_array objectAtIndex:loc_index];
-        for pref_loc_index in 0..pref_loc_count {
-            let pref_loc: id = msg![env; preferred_languages objectAtIndex:pref_loc_index];
-            let equal: bool = msg![env; loc isEqualToString:pref_loc];
-            if equal {
-                // If one of them is, add it to the array
+    'outer: for pref_loc_index in 0..pref_loc_count {
+        let pref_loc: id = msg![env; preferred_languages objectAtIndex:pref_loc_index];
+        let pref_loc = ns_string::to_rust_string(env, pref_loc);
+        let legacy_name = LANG_ID_TO_LANG_PROJ
+            .iter()
+            .find(|&&(code, _)| code == pref_loc)
+            .map(|&(_, lproj)| lproj.strip_suffix(".lproj").unwrap());
+        for loc_index in 0..loc_count {
+            let loc: id = msg![env; loc_array objectAtIndex:loc_index];
+            let loc_str = ns_string::to_rust_string(env, loc);
+            if loc_str == pref_loc || Some(&*loc_str) == legacy_name {
                 result.push(loc);
                 retain(env, loc);
-                break;
+                break 'outer;
             }
         }
     }
 
-    // Add the first element as fallback
-    let first_loc: id = msg![env; loc_array objectAtIndex: (0 as NSUInteger)];
-    result.push(first_loc);
-    retain(env, first_loc);
+    // Use the first element as a fallback
+    if result.is_empty() && loc_count > 0 {
+        let first_loc: id = msg![env; loc_array objectAtIndex: (0 as NSUInteger)];
+        result.push(first_loc);
+        retain(env, first_loc);
+    }
 
     let result = ns_array::from_vec(env, result);
     log_dbg!(
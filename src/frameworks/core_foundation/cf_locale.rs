@@ -0,0 +1,63 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFLocale`.
+//!
+//! This is toll-free bridged to `NSLocale` in Apple's implementation. Here it
+//! is the same type.
+
+use super::cf_array::CFArrayRef;
+use super::cf_string::CFStringRef;
+use super::CFTypeRef;
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::objc::{msg, msg_class, retain};
+use crate::Environment;
+
+pub type CFLocaleRef = super::CFTypeRef;
+pub type CFLocaleKey = CFStringRef;
+
+pub const kCFLocaleIdentifier: &str = "kCFLocaleIdentifierKey";
+pub const kCFLocaleLanguageCode: &str = "kCFLocaleLanguageCodeKey";
+pub const kCFLocaleCountryCode: &str = "kCFLocaleCountryCodeKey";
+
+fn CFLocaleCopyCurrent(env: &mut Environment) -> CFLocaleRef {
+    let locale: CFLocaleRef = msg_class![env; NSLocale currentLocale];
+    retain(env, locale)
+}
+
+fn CFLocaleCopyPreferredLanguages(env: &mut Environment) -> CFArrayRef {
+    let languages: CFArrayRef = msg_class![env; NSLocale preferredLanguages];
+    retain(env, languages)
+}
+
+fn CFLocaleGetIdentifier(env: &mut Environment, locale: CFLocaleRef) -> CFStringRef {
+    msg![env; locale localeIdentifier]
+}
+
+fn CFLocaleGetValue(env: &mut Environment, locale: CFLocaleRef, key: CFLocaleKey) -> CFTypeRef {
+    msg![env; locale objectForKey:key]
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_kCFLocaleIdentifier",
+        HostConstant::NSString(kCFLocaleIdentifier),
+    ),
+    (
+        "_kCFLocaleLanguageCode",
+        HostConstant::NSString(kCFLocaleLanguageCode),
+    ),
+    (
+        "_kCFLocaleCountryCode",
+        HostConstant::NSString(kCFLocaleCountryCode),
+    ),
+];
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CFLocaleCopyCurrent()),
+    export_c_func!(CFLocaleCopyPreferredLanguages()),
+    export_c_func!(CFLocaleGetIdentifier(_)),
+    export_c_func!(CFLocaleGetValue(_, _)),
+];
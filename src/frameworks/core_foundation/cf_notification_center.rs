@@ -0,0 +1,187 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFNotificationCenter`.
+//!
+//! Only the Darwin notification center is currently supported. It's backed by
+//! the `notify.h` implementation, see [crate::libc::notify].
+
+use super::cf_string::CFStringRef;
+use super::{CFIndex, CFOptionFlags, CFTypeRef};
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::foundation::ns_string;
+use crate::libc::notify;
+use crate::mem::{ConstVoidPtr, Ptr};
+use crate::objc::{id, msg_class, nil, release};
+use crate::Environment;
+
+pub type CFNotificationCenterRef = CFTypeRef;
+pub type CFNotificationName = CFStringRef;
+type CFDictionaryRef = CFTypeRef;
+
+#[derive(Default)]
+pub struct State {
+    darwin_notify_center: Option<CFNotificationCenterRef>,
+    darwin_observers: Vec<Observer>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.core_foundation.cf_notification_center
+    }
+}
+
+#[derive(Clone)]
+struct Observer {
+    observer: ConstVoidPtr,
+    /// void (*)(CFNotificationCenterRef center, void *observer,
+    ///          CFNotificationName name, const void *object,
+    ///          CFDictionaryRef userInfo)
+    callback: GuestFunction,
+    /// [None] means all notifications.
+    name: Option<String>,
+}
+
+fn CFNotificationCenterGetDarwinNotifyCenter(env: &mut Environment) -> CFNotificationCenterRef {
+    if let Some(center) = State::get_mut(env).darwin_notify_center {
+        return center;
+    }
+    // There's nothing interesting about this object, it's just something
+    // unique to hand out.
+    let center: id = msg_class![env; NSObject new];
+    State::get_mut(env).darwin_notify_center = Some(center);
+    center
+}
+
+fn assert_is_darwin_notify_center(env: &mut Environment, center: CFNotificationCenterRef) {
+    // TODO: local center (bridge to NSNotificationCenter?)
+    assert_eq!(Some(center), State::get_mut(env).darwin_notify_center);
+}
+
+fn CFNotificationCenterAddObserver(
+    env: &mut Environment,
+    center: CFNotificationCenterRef,
+    observer: ConstVoidPtr,
+    callback: GuestFunction,
+    name: CFNotificationName,
+    _object: ConstVoidPtr,         // ignored by the Darwin center
+    _suspension_behavior: CFIndex, // ignored by the Darwin center
+) {
+    assert_is_darwin_notify_center(env, center);
+    let name = (name != nil).then(|| ns_string::to_rust_string(env, name).into_owned());
+    log_dbg!(
+        "CFNotificationCenterAddObserver({:?}, {:?}, {:?}, {:?})",
+        center,
+        observer,
+        callback,
+        name
+    );
+    State::get_mut(env).darwin_observers.push(Observer {
+        observer,
+        callback,
+        name,
+    });
+}
+
+fn CFNotificationCenterRemoveObserver(
+    env: &mut Environment,
+    center: CFNotificationCenterRef,
+    observer: ConstVoidPtr,
+    name: CFNotificationName,
+    _object: ConstVoidPtr,
+) {
+    assert_is_darwin_notify_center(env, center);
+    let name = (name != nil).then(|| ns_string::to_rust_string(env, name));
+    State::get_mut(env).darwin_observers.retain(|o| {
+        o.observer != observer
+            || name
+                .as_deref()
+                .is_some_and(|n| o.name.as_deref() != Some(n))
+    });
+}
+
+fn CFNotificationCenterRemoveEveryObserver(
+    env: &mut Environment,
+    center: CFNotificationCenterRef,
+    observer: ConstVoidPtr,
+) {
+    assert_is_darwin_notify_center(env, center);
+    State::get_mut(env)
+        .darwin_observers
+        .retain(|o| o.observer != observer);
+}
+
+fn CFNotificationCenterPostNotification(
+    env: &mut Environment,
+    center: CFNotificationCenterRef,
+    name: CFNotificationName,
+    object: ConstVoidPtr,
+    user_info: CFDictionaryRef,
+    _deliver_immediately: bool,
+) {
+    CFNotificationCenterPostNotificationWithOptions(env, center, name, object, user_info, 0)
+}
+
+fn CFNotificationCenterPostNotificationWithOptions(
+    env: &mut Environment,
+    center: CFNotificationCenterRef,
+    name: CFNotificationName,
+    _object: ConstVoidPtr,       // ignored by the Darwin center
+    _user_info: CFDictionaryRef, // ignored by the Darwin center
+    _options: CFOptionFlags,
+) {
+    assert_is_darwin_notify_center(env, center);
+    let name = ns_string::to_rust_string(env, name);
+    log_dbg!("CFNotificationCenterPostNotification({:?})", name);
+    notify::post(env, &name);
+}
+
+/// Called by [notify::post] for each Darwin notification posted.
+pub fn deliver_darwin_notification(env: &mut Environment, name: &str) {
+    let Some(center) = State::get_mut(env).darwin_notify_center else {
+        return;
+    };
+    // Copy so that the callbacks can add or remove observers.
+    let observers: Vec<Observer> = State::get_mut(env)
+        .darwin_observers
+        .iter()
+        .filter(|o| o.name.is_none() || o.name.as_deref() == Some(name))
+        .cloned()
+        .collect();
+    if observers.is_empty() {
+        return;
+    }
+    let name_string = ns_string::from_rust_string(env, name.to_string());
+    for Observer {
+        observer, callback, ..
+    } in observers
+    {
+        log_dbg!(
+            "Delivering Darwin notification {:?} to {:?} via {:?}",
+            name,
+            observer,
+            callback
+        );
+        let object: ConstVoidPtr = Ptr::null();
+        let user_info: CFDictionaryRef = nil;
+        () = callback.call_from_host(env, (center, observer, name_string, object, user_info));
+    }
+    release(env, name_string);
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CFNotificationCenterGetDarwinNotifyCenter()),
+    export_c_func!(CFNotificationCenterAddObserver(_, _, _, _, _, _)),
+    export_c_func!(CFNotificationCenterRemoveObserver(_, _, _, _)),
+    export_c_func!(CFNotificationCenterRemoveEveryObserver(_, _)),
+    export_c_func!(CFNotificationCenterPostNotification(_, _, _, _, _)),
+    export_c_func!(CFNotificationCenterPostNotificationWithOptions(
+        _,
+        _,
+        _,
+        _,
+        _
+    )),
+];
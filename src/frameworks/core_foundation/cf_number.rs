@@ -0,0 +1,40 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFNumber` and `CFBoolean`.
+//!
+//! These are toll-free bridged to `NSNumber` in Apple's implementation. Here
+//! they are the same type. Only `CFBoolean` is implemented so far.
+
+use crate::dyld::{ConstantExports, FunctionExports, HostConstant};
+use crate::export_c_func;
+use crate::mem::ConstVoidPtr;
+use crate::objc::{id, msg, msg_class};
+use crate::Environment;
+
+pub type CFBooleanRef = super::CFTypeRef;
+
+fn new_boolean_constant(env: &mut Environment, value: bool) -> ConstVoidPtr {
+    let boolean: id = msg_class![env; NSNumber alloc];
+    let boolean: id = msg![env; boolean initWithBool:value];
+    env.mem.alloc_and_write(boolean).cast().cast_const()
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_kCFBooleanTrue",
+        HostConstant::CustomWithEnv(|env| new_boolean_constant(env, true)),
+    ),
+    (
+        "_kCFBooleanFalse",
+        HostConstant::CustomWithEnv(|env| new_boolean_constant(env, false)),
+    ),
+];
+
+fn CFBooleanGetValue(env: &mut Environment, boolean: CFBooleanRef) -> bool {
+    msg![env; boolean boolValue]
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(CFBooleanGetValue(_))];
@@ -0,0 +1,154 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFUUID`.
+//!
+//! This is not toll-free bridged to `NSUUID` in Apple's implementation, but
+//! here they are the same type.
+
+use super::cf_allocator::{kCFAllocatorDefault, CFAllocatorRef};
+use super::cf_string::CFStringRef;
+use crate::abi::{DotDotDot, GuestArg};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::foundation::ns_string::{from_rust_string, to_rust_string};
+use crate::frameworks::foundation::ns_uuid::{
+    format_uuid, parse_uuid, random_uuid, uuid_bytes, uuid_with_bytes, UUIDBytes,
+};
+use crate::impl_GuestRet_for_large_struct;
+use crate::mem::SafeRead;
+use crate::objc::nil;
+use crate::Environment;
+use std::collections::HashMap;
+
+pub type CFUUIDRef = super::CFTypeRef;
+
+#[derive(Default)]
+pub struct State {
+    /// UUIDs returned by `CFUUIDGetConstantUUIDWithBytes`, which are never
+    /// freed.
+    constant_uuids: HashMap<UUIDBytes, CFUUIDRef>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.core_foundation.cf_uuid
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct CFUUIDBytes {
+    pub bytes: UUIDBytes,
+}
+unsafe impl SafeRead for CFUUIDBytes {}
+impl_GuestRet_for_large_struct!(CFUUIDBytes);
+impl GuestArg for CFUUIDBytes {
+    const REG_COUNT: usize = 4;
+
+    fn from_regs(regs: &[u32]) -> Self {
+        let mut bytes = [0u8; 16];
+        for (chunk, &reg) in bytes.chunks_mut(4).zip(regs) {
+            chunk.copy_from_slice(&reg.to_le_bytes());
+        }
+        CFUUIDBytes { bytes }
+    }
+    fn to_regs(self, regs: &mut [u32]) {
+        let bytes = self.bytes;
+        for (reg, chunk) in regs.iter_mut().zip(bytes.chunks(4)) {
+            *reg = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+    }
+}
+
+fn CFUUIDCreate(env: &mut Environment, allocator: CFAllocatorRef) -> CFUUIDRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    let bytes = random_uuid();
+    uuid_with_bytes(env, bytes)
+}
+
+/// The 16 bytes of a UUID, passed as separate arguments (after the allocator)
+/// to `CFUUIDCreateWithBytes` and `CFUUIDGetConstantUUIDWithBytes`. These have
+/// too many arguments for the usual argument handling, but the bytes are
+/// passed like variadic arguments would be.
+fn read_byte_args(env: &mut Environment, bytes: DotDotDot) -> UUIDBytes {
+    let mut args = bytes.start();
+    std::array::from_fn(|_| args.next::<u8>(env))
+}
+
+fn CFUUIDCreateWithBytes(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    bytes: DotDotDot,
+) -> CFUUIDRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    let bytes = read_byte_args(env, bytes);
+    uuid_with_bytes(env, bytes)
+}
+
+fn CFUUIDGetConstantUUIDWithBytes(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    bytes: DotDotDot,
+) -> CFUUIDRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    let bytes = read_byte_args(env, bytes);
+    if let Some(&uuid) = State::get_mut(env).constant_uuids.get(&bytes) {
+        return uuid;
+    }
+    let uuid = uuid_with_bytes(env, bytes);
+    State::get_mut(env).constant_uuids.insert(bytes, uuid);
+    uuid
+}
+
+fn CFUUIDCreateFromUUIDBytes(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    bytes: CFUUIDBytes,
+) -> CFUUIDRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    uuid_with_bytes(env, bytes.bytes)
+}
+
+fn CFUUIDCreateFromString(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    string: CFStringRef,
+) -> CFUUIDRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    let string = to_rust_string(env, string);
+    let Some(bytes) = parse_uuid(&string) else {
+        log!(
+            "Warning: CFUUIDCreateFromString() got invalid UUID {:?}",
+            string
+        );
+        return nil;
+    };
+    uuid_with_bytes(env, bytes)
+}
+
+fn CFUUIDCreateString(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    uuid: CFUUIDRef,
+) -> CFStringRef {
+    assert!(allocator == kCFAllocatorDefault); // unimplemented
+    let string = format_uuid(&uuid_bytes(env, uuid));
+    from_rust_string(env, string)
+}
+
+fn CFUUIDGetUUIDBytes(env: &mut Environment, uuid: CFUUIDRef) -> CFUUIDBytes {
+    CFUUIDBytes {
+        bytes: uuid_bytes(env, uuid),
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CFUUIDCreate(_)),
+    export_c_func!(CFUUIDCreateWithBytes(_, _)),
+    export_c_func!(CFUUIDGetConstantUUIDWithBytes(_, _)),
+    export_c_func!(CFUUIDCreateFromUUIDBytes(_, _)),
+    export_c_func!(CFUUIDCreateFromString(_, _)),
+    export_c_func!(CFUUIDCreateString(_, _)),
+    export_c_func!(CFUUIDGetUUIDBytes(_)),
+];
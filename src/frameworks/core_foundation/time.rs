@@ -6,11 +6,13 @@
 //! Time things including `CFAbsoluteTime`.
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::cf_string::CFStringRef;
 use crate::frameworks::core_foundation::CFTypeRef;
+use crate::frameworks::foundation::ns_time_zone::time_zone_for;
 use crate::frameworks::foundation::NSTimeInterval;
 use crate::libc::time::{time_t, timestamp_to_calendar_date};
 use crate::mem::SafeRead;
-use crate::objc::nil;
+use crate::objc::{msg, msg_class, retain};
 use crate::{impl_GuestRet_for_large_struct, Environment};
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
@@ -45,25 +47,71 @@ fn CFAbsoluteTimeGetCurrent(_env: &mut Environment) -> CFAbsoluteTime {
         .as_secs_f64()
 }
 
+/// Toll-free bridged to `NSTimeZone`.
 type CFTimeZoneRef = CFTypeRef;
 
-fn CFTimeZoneCopySystem(_env: &mut Environment) -> CFTimeZoneRef {
-    // TODO: implement (nil seems to correspond to GMT)
-    nil
+fn CFTimeZoneCopySystem(env: &mut Environment) -> CFTimeZoneRef {
+    let time_zone = msg_class![env; NSTimeZone systemTimeZone];
+    retain(env, time_zone)
+}
+
+fn CFTimeZoneCopyDefault(env: &mut Environment) -> CFTimeZoneRef {
+    let time_zone = msg_class![env; NSTimeZone defaultTimeZone];
+    retain(env, time_zone)
+}
+
+fn CFTimeZoneGetName(env: &mut Environment, tz: CFTimeZoneRef) -> CFStringRef {
+    msg![env; tz name]
+}
+
+fn CFTimeZoneGetSecondsFromGMT(
+    env: &mut Environment,
+    tz: CFTimeZoneRef,
+    at: CFAbsoluteTime,
+) -> CFTimeInterval {
+    let time = (at + unix_epoch_offset()).floor() as i64;
+    time_zone_for(env, tz)
+        .local_time_type(time)
+        .utc_offset
+        .into()
+}
+
+fn CFTimeZoneIsDaylightSavingTime(
+    env: &mut Environment,
+    tz: CFTimeZoneRef,
+    at: CFAbsoluteTime,
+) -> bool {
+    let time = (at + unix_epoch_offset()).floor() as i64;
+    time_zone_for(env, tz).local_time_type(time).is_dst
+}
+
+/// Seconds between the UNIX epoch and the absolute reference date.
+fn unix_epoch_offset() -> f64 {
+    apple_epoch()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
 }
 
 pub fn CFAbsoluteTimeGetGregorianDate(
-    _env: &mut Environment,
+    env: &mut Environment,
     at: CFAbsoluteTime,
     tz: CFTimeZoneRef,
 ) -> CFGregorianDate {
-    assert!(tz.is_null());
     let time64 = apple_epoch()
         .add(Duration::from_secs_f64(at))
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs();
-    let time = time64 as time_t;
+    // A NULL time zone means GMT.
+    let utc_offset = if tz.is_null() {
+        0
+    } else {
+        time_zone_for(env, tz)
+            .local_time_type(time64 as i64)
+            .utc_offset
+    };
+    let time = (time64 as time_t).wrapping_add(utc_offset);
     let tm = timestamp_to_calendar_date(time);
     CFGregorianDate {
         year: 1900 + tm.tm_year,
@@ -78,5 +126,9 @@ pub fn CFAbsoluteTimeGetGregorianDate(
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(CFAbsoluteTimeGetCurrent()),
     export_c_func!(CFTimeZoneCopySystem()),
+    export_c_func!(CFTimeZoneCopyDefault()),
+    export_c_func!(CFTimeZoneGetName(_)),
+    export_c_func!(CFTimeZoneGetSecondsFromGMT(_, _)),
+    export_c_func!(CFTimeZoneIsDaylightSavingTime(_, _)),
     export_c_func!(CFAbsoluteTimeGetGregorianDate(_, _)),
 ];
@@ -48,6 +48,28 @@ impl GuestArg for CGAffineTransform {
     }
 }
 impl_GuestRet_for_large_struct!(CGAffineTransform);
+impl std::str::FromStr for CGAffineTransform {
+    type Err = ();
+    fn from_str(s: &str) -> Result<CGAffineTransform, ()> {
+        let s = s.strip_prefix('[').ok_or(())?.strip_suffix(']').ok_or(())?;
+        let mut values = [0.0; 6];
+        let mut parts = s.split(',');
+        for value in values.iter_mut() {
+            *value = parts.next().ok_or(())?.trim().parse().map_err(|_| ())?;
+        }
+        if parts.next().is_some() {
+            return Err(());
+        }
+        let [a, b, c, d, tx, ty] = values;
+        Ok(CGAffineTransform { a, b, c, d, tx, ty })
+    }
+}
+impl std::fmt::Display for CGAffineTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let &CGAffineTransform { a, b, c, d, tx, ty } = self;
+        write!(f, "[{}, {}, {}, {}, {}, {}]", a, b, c, d, tx, ty)
+    }
+}
 
 // These conversions allow sharing code with the touchHLE Matrix type.
 impl TryFrom<Matrix<3>> for CGAffineTransform {
@@ -10,12 +10,15 @@
 use super::CGFloat;
 use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
-use crate::mem::SafeRead;
+use crate::mem::{MutPtr, SafeRead};
 use crate::Environment;
 
 fn parse_tuple(s: &str) -> Result<(f32, f32), ()> {
-    let (a, b) = s.split_once(", ").ok_or(())?;
-    Ok((a.parse().map_err(|_| ())?, b.parse().map_err(|_| ())?))
+    let (a, b) = s.split_once(',').ok_or(())?;
+    Ok((
+        a.trim().parse().map_err(|_| ())?,
+        b.trim().parse().map_err(|_| ())?,
+    ))
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -135,12 +138,15 @@ impl GuestArg for CGRect {
 impl std::str::FromStr for CGRect {
     type Err = ();
     fn from_str(s: &str) -> Result<CGRect, ()> {
-        let s = s
-            .strip_prefix("{{")
+        let s = s.strip_prefix('{').ok_or(())?.strip_suffix('}').ok_or(())?;
+        let (a, b) = s.split_once("},").ok_or(())?;
+        let a = a.trim().strip_prefix('{').ok_or(())?;
+        let b = b
+            .trim()
+            .strip_prefix('{')
             .ok_or(())?
-            .strip_suffix("}}")
+            .strip_suffix('}')
             .ok_or(())?;
-        let (a, b) = s.split_once("}, {").ok_or(())?;
         let (x, y) = parse_tuple(a)?;
         let (width, height) = parse_tuple(b)?;
         Ok(CGRect {
@@ -165,18 +171,350 @@ pub const CGRectZero: CGRect = CGRect {
     size: CGSizeZero,
 };
 
+/// Special value used to represent the absence of a rectangle, e.g. the result
+/// of intersecting two rectangles that don't intersect.
+pub const CGRectNull: CGRect = CGRect {
+    origin: CGPoint {
+        x: CGFloat::INFINITY,
+        y: CGFloat::INFINITY,
+    },
+    size: CGSizeZero,
+};
+
+/// Special value used to represent a rectangle with no bounds.
+pub const CGRectInfinite: CGRect = CGRect {
+    origin: CGPoint {
+        x: CGFloat::MIN / 2.0,
+        y: CGFloat::MIN / 2.0,
+    },
+    size: CGSize {
+        width: CGFloat::MAX,
+        height: CGFloat::MAX,
+    },
+};
+
+type CGRectEdge = u32;
+const CGRectMinXEdge: CGRectEdge = 0;
+const CGRectMinYEdge: CGRectEdge = 1;
+const CGRectMaxXEdge: CGRectEdge = 2;
+const CGRectMaxYEdge: CGRectEdge = 3;
+
+// Like with CGAffineTransform, the CGRect* functions are wrappers around these
+// methods so host code can use them conveniently.
+impl CGRect {
+    pub fn is_null(self) -> bool {
+        self.origin.x == CGFloat::INFINITY || self.origin.y == CGFloat::INFINITY
+    }
+    pub fn is_infinite(self) -> bool {
+        self == CGRectInfinite
+    }
+    pub fn is_empty(self) -> bool {
+        self.is_null() || self.size.width == 0.0 || self.size.height == 0.0
+    }
+    /// Returns an equivalent rectangle with a non-negative width and height.
+    pub fn standardize(self) -> CGRect {
+        if self.is_null() {
+            return CGRectNull;
+        }
+        let CGRect {
+            origin: CGPoint { mut x, mut y },
+            size: CGSize {
+                mut width,
+                mut height,
+            },
+        } = self;
+        if width < 0.0 {
+            x += width;
+            width = -width;
+        }
+        if height < 0.0 {
+            y += height;
+            height = -height;
+        }
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+    // These work on the standardized rectangle, as in Apple's implementation.
+    pub fn min_x(self) -> CGFloat {
+        self.standardize().origin.x
+    }
+    pub fn min_y(self) -> CGFloat {
+        self.standardize().origin.y
+    }
+    pub fn max_x(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.x + rect.size.width
+    }
+    pub fn max_y(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.y + rect.size.height
+    }
+    pub fn mid_x(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.x + rect.size.width / 2.0
+    }
+    pub fn mid_y(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.y + rect.size.height / 2.0
+    }
+    pub fn width(self) -> CGFloat {
+        self.size.width.abs()
+    }
+    pub fn height(self) -> CGFloat {
+        self.size.height.abs()
+    }
+    fn from_min_max(min_x: CGFloat, min_y: CGFloat, max_x: CGFloat, max_y: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x: min_x, y: min_y },
+            size: CGSize {
+                width: max_x - min_x,
+                height: max_y - min_y,
+            },
+        }
+    }
+
+    pub fn contains_point(self, point: CGPoint) -> bool {
+        // The maximum edges are excluded.
+        !self.is_null()
+            && self.min_x() <= point.x
+            && point.x < self.max_x()
+            && self.min_y() <= point.y
+            && point.y < self.max_y()
+    }
+    pub fn contains_rect(self, other: CGRect) -> bool {
+        !self.is_null()
+            && !other.is_null()
+            && self.min_x() <= other.min_x()
+            && other.max_x() <= self.max_x()
+            && self.min_y() <= other.min_y()
+            && other.max_y() <= self.max_y()
+    }
+    /// Returns [CGRectNull] if the rectangles don't intersect. Rectangles that
+    /// only share an edge have an intersection with zero width or height.
+    pub fn intersection(self, other: CGRect) -> CGRect {
+        if self.is_null() || other.is_null() {
+            return CGRectNull;
+        }
+        let min_x = self.min_x().max(other.min_x());
+        let min_y = self.min_y().max(other.min_y());
+        let max_x = self.max_x().min(other.max_x());
+        let max_y = self.max_y().min(other.max_y());
+        if max_x < min_x || max_y < min_y {
+            return CGRectNull;
+        }
+        CGRect::from_min_max(min_x, min_y, max_x, max_y)
+    }
+    /// Unlike [CGRect::intersection], this is [false] for rectangles that only
+    /// share an edge.
+    pub fn intersects_rect(self, other: CGRect) -> bool {
+        !self.intersection(other).is_empty()
+    }
+    pub fn union(self, other: CGRect) -> CGRect {
+        if self.is_null() {
+            return other.standardize();
+        }
+        if other.is_null() {
+            return self.standardize();
+        }
+        CGRect::from_min_max(
+            self.min_x().min(other.min_x()),
+            self.min_y().min(other.min_y()),
+            self.max_x().max(other.max_x()),
+            self.max_y().max(other.max_y()),
+        )
+    }
+    pub fn inset(self, dx: CGFloat, dy: CGFloat) -> CGRect {
+        if self.is_null() {
+            return CGRectNull;
+        }
+        let rect = self.standardize();
+        let width = rect.size.width - dx * 2.0;
+        let height = rect.size.height - dy * 2.0;
+        if width < 0.0 || height < 0.0 {
+            return CGRectNull;
+        }
+        CGRect {
+            origin: CGPoint {
+                x: rect.origin.x + dx,
+                y: rect.origin.y + dy,
+            },
+            size: CGSize { width, height },
+        }
+    }
+    pub fn offset(self, dx: CGFloat, dy: CGFloat) -> CGRect {
+        if self.is_null() {
+            return CGRectNull;
+        }
+        let rect = self.standardize();
+        CGRect {
+            origin: CGPoint {
+                x: rect.origin.x + dx,
+                y: rect.origin.y + dy,
+            },
+            size: rect.size,
+        }
+    }
+    /// Smallest rectangle with integral co-ordinates containing this one.
+    pub fn integral(self) -> CGRect {
+        if self.is_null() {
+            return CGRectNull;
+        }
+        CGRect::from_min_max(
+            self.min_x().floor(),
+            self.min_y().floor(),
+            self.max_x().ceil(),
+            self.max_y().ceil(),
+        )
+    }
+    /// Splits the rectangle in two at `amount` units from `edge`, returning
+    /// `(slice, remainder)`.
+    fn divide(self, amount: CGFloat, edge: CGRectEdge) -> (CGRect, CGRect) {
+        if self.is_null() {
+            return (CGRectNull, CGRectNull);
+        }
+        let rect = self.standardize();
+        let (min_x, min_y, max_x, max_y) = (rect.min_x(), rect.min_y(), rect.max_x(), rect.max_y());
+        let amount = amount.max(0.0);
+        match edge {
+            CGRectMinXEdge => {
+                let split = (min_x + amount).min(max_x);
+                (
+                    CGRect::from_min_max(min_x, min_y, split, max_y),
+                    CGRect::from_min_max(split, min_y, max_x, max_y),
+                )
+            }
+            CGRectMinYEdge => {
+                let split = (min_y + amount).min(max_y);
+                (
+                    CGRect::from_min_max(min_x, min_y, max_x, split),
+                    CGRect::from_min_max(min_x, split, max_x, max_y),
+                )
+            }
+            CGRectMaxXEdge => {
+                let split = (max_x - amount).max(min_x);
+                (
+                    CGRect::from_min_max(split, min_y, max_x, max_y),
+                    CGRect::from_min_max(min_x, min_y, split, max_y),
+                )
+            }
+            CGRectMaxYEdge => {
+                let split = (max_y - amount).max(min_y);
+                (
+                    CGRect::from_min_max(min_x, split, max_x, max_y),
+                    CGRect::from_min_max(min_x, min_y, max_x, split),
+                )
+            }
+            _ => panic!("Invalid CGRectEdge: {}", edge),
+        }
+    }
+}
+
+fn CGRectIsNull(_env: &mut Environment, rect: CGRect) -> bool {
+    rect.is_null()
+}
+fn CGRectIsInfinite(_env: &mut Environment, rect: CGRect) -> bool {
+    rect.is_infinite()
+}
+fn CGRectIsEmpty(_env: &mut Environment, rect: CGRect) -> bool {
+    rect.is_empty()
+}
+fn CGRectStandardize(_env: &mut Environment, rect: CGRect) -> CGRect {
+    rect.standardize()
+}
+
+fn CGRectGetMinX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.min_x()
+}
+fn CGRectGetMinY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.min_y()
+}
+fn CGRectGetMidX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.mid_x()
+}
+fn CGRectGetMidY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.mid_y()
+}
+fn CGRectGetMaxX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.max_x()
+}
+fn CGRectGetMaxY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.max_y()
+}
+fn CGRectGetWidth(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.width()
+}
+fn CGRectGetHeight(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.height()
+}
+
 fn CGRectContainsPoint(_env: &mut Environment, rect: CGRect, point: CGPoint) -> bool {
-    rect.origin.x <= point.x
-        && rect.origin.x + rect.size.width > point.x
-        && rect.origin.y <= point.y
-        && rect.origin.y + rect.size.height > point.y
+    rect.contains_point(point)
+}
+fn CGRectContainsRect(_env: &mut Environment, rect1: CGRect, rect2: CGRect) -> bool {
+    rect1.contains_rect(rect2)
+}
+fn CGRectIntersectsRect(_env: &mut Environment, rect1: CGRect, rect2: CGRect) -> bool {
+    rect1.intersects_rect(rect2)
+}
+fn CGRectIntersection(_env: &mut Environment, rect1: CGRect, rect2: CGRect) -> CGRect {
+    rect1.intersection(rect2)
+}
+fn CGRectUnion(_env: &mut Environment, rect1: CGRect, rect2: CGRect) -> CGRect {
+    rect1.union(rect2)
+}
+fn CGRectInset(_env: &mut Environment, rect: CGRect, dx: CGFloat, dy: CGFloat) -> CGRect {
+    rect.inset(dx, dy)
+}
+fn CGRectOffset(_env: &mut Environment, rect: CGRect, dx: CGFloat, dy: CGFloat) -> CGRect {
+    rect.offset(dx, dy)
+}
+fn CGRectIntegral(_env: &mut Environment, rect: CGRect) -> CGRect {
+    rect.integral()
+}
+fn CGRectDivide(
+    env: &mut Environment,
+    rect: CGRect,
+    slice: MutPtr<CGRect>,
+    remainder: MutPtr<CGRect>,
+    amount: CGFloat,
+    edge: CGRectEdge,
+) {
+    let (slice_rect, remainder_rect) = rect.divide(amount, edge);
+    if !slice.is_null() {
+        env.mem.write(slice, slice_rect);
+    }
+    if !remainder.is_null() {
+        env.mem.write(remainder, remainder_rect);
+    }
 }
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(CGPointEqualToPoint(_, _)),
     export_c_func!(CGSizeEqualToSize(_, _)),
     export_c_func!(CGRectEqualToRect(_, _)),
+    export_c_func!(CGRectIsNull(_)),
+    export_c_func!(CGRectIsInfinite(_)),
+    export_c_func!(CGRectIsEmpty(_)),
+    export_c_func!(CGRectStandardize(_)),
+    export_c_func!(CGRectGetMinX(_)),
+    export_c_func!(CGRectGetMinY(_)),
+    export_c_func!(CGRectGetMidX(_)),
+    export_c_func!(CGRectGetMidY(_)),
+    export_c_func!(CGRectGetMaxX(_)),
+    export_c_func!(CGRectGetMaxY(_)),
+    export_c_func!(CGRectGetWidth(_)),
+    export_c_func!(CGRectGetHeight(_)),
     export_c_func!(CGRectContainsPoint(_, _)),
+    export_c_func!(CGRectContainsRect(_, _)),
+    export_c_func!(CGRectIntersectsRect(_, _)),
+    export_c_func!(CGRectIntersection(_, _)),
+    export_c_func!(CGRectUnion(_, _)),
+    export_c_func!(CGRectInset(_, _, _)),
+    export_c_func!(CGRectOffset(_, _, _)),
+    export_c_func!(CGRectIntegral(_)),
+    export_c_func!(CGRectDivide(_, _, _, _, _)),
 ];
 
 pub const CONSTANTS: ConstantExports = &[
@@ -192,4 +530,12 @@ pub const CONSTANTS: ConstantExports = &[
         "_CGRectZero",
         HostConstant::Custom(|mem| mem.alloc_and_write(CGRectZero).cast().cast_const()),
     ),
+    (
+        "_CGRectNull",
+        HostConstant::Custom(|mem| mem.alloc_and_write(CGRectNull).cast().cast_const()),
+    ),
+    (
+        "_CGRectInfinite",
+        HostConstant::Custom(|mem| mem.alloc_and_write(CGRectInfinite).cast().cast_const()),
+    ),
 ];
@@ -12,8 +12,10 @@
 //! `NSString` easier to understand.
 
 pub mod ns_array;
+pub mod ns_assertion_handler;
 pub mod ns_autorelease_pool;
 pub mod ns_bundle;
+pub mod ns_cache;
 pub mod ns_character_set;
 pub mod ns_coder;
 pub mod ns_data;
@@ -24,36 +26,52 @@ pub mod ns_enumerator;
 pub mod ns_error;
 pub mod ns_exception;
 pub mod ns_file_manager;
+pub mod ns_hash_table;
+pub mod ns_index_set;
+pub mod ns_invocation;
 pub mod ns_keyed_unarchiver;
 pub mod ns_locale;
 pub mod ns_lock;
 pub mod ns_log;
+pub mod ns_map_table;
+pub mod ns_method_signature;
 pub mod ns_notification;
 pub mod ns_notification_center;
 pub mod ns_null;
 pub mod ns_objc_runtime;
 pub mod ns_object;
+pub mod ns_pointer_array;
+pub mod ns_pointer_functions;
 pub mod ns_process_info;
 pub mod ns_property_list_serialization;
+pub mod ns_range;
 pub mod ns_run_loop;
 pub mod ns_set;
 pub mod ns_string;
 pub mod ns_thread;
+pub mod ns_time_zone;
 pub mod ns_timer;
 pub mod ns_url;
 pub mod ns_user_defaults;
+pub mod ns_uuid;
 pub mod ns_value;
 
 #[derive(Default)]
 pub struct State {
+    ns_assertion_handler: ns_assertion_handler::State,
     ns_autorelease_pool: ns_autorelease_pool::State,
     ns_bundle: ns_bundle::State,
+    ns_cache: ns_cache::State,
+    ns_enumerator: ns_enumerator::State,
+    ns_exception: ns_exception::State,
     ns_file_manager: ns_file_manager::State,
     ns_locale: ns_locale::State,
     ns_notification_center: ns_notification_center::State,
     ns_null: ns_null::State,
+    ns_process_info: ns_process_info::State,
     ns_run_loop: ns_run_loop::State,
     ns_string: ns_string::State,
+    ns_time_zone: ns_time_zone::State,
     ns_user_defaults: ns_user_defaults::State,
 }
 
@@ -63,7 +81,7 @@ pub type NSUInteger = u32;
 // this should be equal to NSIntegerMax
 pub const NSNotFound: i32 = 0x7fffffff;
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 #[repr(C, packed)]
 pub struct NSRange {
     pub location: NSUInteger,
@@ -5,14 +5,21 @@
  */
 //! The `NSArray` class cluster, including `NSMutableArray`.
 
-use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
+use super::ns_enumerator::{
+    fast_enumeration_helper, forget_collection, mutations_ptr, note_mutation,
+    NSFastEnumerationState,
+};
 use super::ns_property_list_serialization::deserialize_plist_from_file;
-use super::{ns_keyed_unarchiver, ns_string, ns_url, NSUInteger};
+use super::{
+    ns_exception, ns_index_set, ns_keyed_unarchiver, ns_string, ns_url, NSComparisonResult,
+    NSNotFound, NSOrderedDescending, NSRange, NSUInteger,
+};
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::fs::GuestPath;
-use crate::mem::MutPtr;
+use crate::mem::{MutPtr, MutVoidPtr};
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, msg_send, msg_send_discarding_return, nil, objc_classes,
+    release, retain, ClassExports, HostObject, NSZonePtr, SEL,
 };
 use crate::Environment;
 
@@ -36,7 +43,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 // - (id)objectAtIndex:(NSUInteger)index;
 // We can pick whichever subclass we want for the various alloc methods.
 // For the time being, that will always be _touchHLE_NSArray.
-@implementation NSArray: NSObject
+@implementation NSArray: NSObject <NSCopying, NSMutableCopying, NSCoding, NSFastEnumeration>
 
 + (id)allocWithZone:(NSZonePtr)zone {
     // NSArray might be subclassed by something which needs allocWithZone:
@@ -106,6 +113,131 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg![env; this objectAtIndex: (size - 1)]
 }
 
+- (bool)containsObject:(id)object {
+    let index: NSUInteger = msg![env; this indexOfObject:object];
+    index != NSNotFound as NSUInteger
+}
+
+- (NSUInteger)indexOfObject:(id)object {
+    let objects = to_vec(env, this);
+    objects
+        .into_iter()
+        .position(|candidate| candidate == object || msg![env; candidate isEqualTo:object])
+        .map_or(NSNotFound as NSUInteger, |index| index.try_into().unwrap())
+}
+
+- (NSUInteger)indexOfObjectIdenticalTo:(id)object {
+    let objects = to_vec(env, this);
+    objects
+        .into_iter()
+        .position(|candidate| candidate == object)
+        .map_or(NSNotFound as NSUInteger, |index| index.try_into().unwrap())
+}
+
+- (id)subarrayWithRange:(NSRange)range {
+    let count: NSUInteger = msg![env; this count];
+    let NSRange { location, length } = range;
+    if !matches!(location.checked_add(length), Some(end) if end <= count) {
+        let reason = format!("Range {{{}, {}}} out of bounds; count {}", location, length, count);
+        ns_exception::raise(env, "NSRangeException", reason);
+        return nil;
+    }
+    let objects: Vec<id> = (location..location + length)
+        .map(|index| {
+            let object: id = msg![env; this objectAtIndex:index];
+            retain(env, object)
+        })
+        .collect();
+    let array = from_vec(env, objects);
+    autorelease(env, array)
+}
+
+- (id)objectsAtIndexes:(id)indexes { // NSIndexSet*
+    let count: NSUInteger = msg![env; this count];
+    let indexes = ns_index_set::to_vec(env, indexes);
+    if let Some(&last) = indexes.last().filter(|&&last| last >= count) {
+        let reason = format!("Index {} out of bounds; count {}", last, count);
+        ns_exception::raise(env, "NSRangeException", reason);
+        return nil;
+    }
+    let objects: Vec<id> = indexes
+        .into_iter()
+        .map(|index| {
+            let object: id = msg![env; this objectAtIndex:index];
+            retain(env, object)
+        })
+        .collect();
+    let array = from_vec(env, objects);
+    autorelease(env, array)
+}
+
+- (id)componentsJoinedByString:(id)separator { // NSString*
+    let separator = ns_string::to_rust_string(env, separator);
+    let mut joined = String::new();
+    for (i, object) in to_vec(env, this).into_iter().enumerate() {
+        if i > 0 {
+            joined.push_str(&separator);
+        }
+        let description: id = msg![env; object description];
+        joined.push_str(&ns_string::to_rust_string(env, description));
+    }
+    let joined = ns_string::from_rust_string(env, joined);
+    autorelease(env, joined)
+}
+
+- (id)objectEnumerator { // NSEnumerator*
+    let objects = to_vec(env, this);
+    new_object_enumerator(env, objects)
+}
+
+- (id)reverseObjectEnumerator { // NSEnumerator*
+    let mut objects = to_vec(env, this);
+    objects.reverse();
+    new_object_enumerator(env, objects)
+}
+
+- (id)sortedArrayUsingSelector:(SEL)comparator {
+    let mut objects = to_vec(env, this);
+    sort_objects(env, &mut objects, |env, a, b| msg_send(env, (a, comparator, b)));
+    for &object in &objects {
+        retain(env, object);
+    }
+    let array = from_vec(env, objects);
+    autorelease(env, array)
+}
+
+- (id)sortedArrayUsingFunction:(GuestFunction)comparator // NSInteger (*)(id, id, void*)
+                       context:(MutVoidPtr)context {
+    let mut objects = to_vec(env, this);
+    sort_objects(env, &mut objects, |env, a, b| comparator.call_from_host(env, (a, b, context)));
+    for &object in &objects {
+        retain(env, object);
+    }
+    let array = from_vec(env, objects);
+    autorelease(env, array)
+}
+
+- (())makeObjectsPerformSelector:(SEL)selector {
+    for object in to_vec(env, this) {
+        msg_send_discarding_return(env, (object, selector));
+    }
+}
+
+- (())makeObjectsPerformSelector:(SEL)selector
+                      withObject:(id)argument {
+    for object in to_vec(env, this) {
+        msg_send_discarding_return(env, (object, selector, argument));
+    }
+}
+
+// NSFastEnumeration implementation, for subclasses that don't provide their own
+- (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
+                                  objects:(MutPtr<id>)stackbuf
+                                    count:(NSUInteger)len {
+    let mut iterator = to_vec(env, this).into_iter();
+    fast_enumeration_helper(&mut env.mem, this.cast(), &mut iterator, state, stackbuf, len)
+}
+
 @end
 
 // NSMutableArray is an abstract class. A subclass must provide everything
@@ -131,6 +263,124 @@ pub const CLASSES: ClassExports = objc_classes! {
     todo!(); // TODO: this should produce an immutable copy
 }
 
+// The following are implemented in terms of the primitive methods, so they
+// work for any subclass.
+
+- (())addObjectsFromArray:(id)other { // NSArray*
+    for object in to_vec(env, other) {
+        () = msg![env; this addObject:object];
+    }
+}
+
+- (())removeObject:(id)object {
+    // Iterate backwards so that indices stay valid after removal.
+    let count: NSUInteger = msg![env; this count];
+    for index in (0..count).rev() {
+        let candidate: id = msg![env; this objectAtIndex:index];
+        if candidate == object || msg![env; candidate isEqualTo:object] {
+            () = msg![env; this removeObjectAtIndex:index];
+        }
+    }
+}
+
+- (())removeObjectIdenticalTo:(id)object {
+    let count: NSUInteger = msg![env; this count];
+    for index in (0..count).rev() {
+        let candidate: id = msg![env; this objectAtIndex:index];
+        if candidate == object {
+            () = msg![env; this removeObjectAtIndex:index];
+        }
+    }
+}
+
+- (())insertObjects:(id)objects // NSArray*
+           atIndexes:(id)indexes { // NSIndexSet*
+    let objects = to_vec(env, objects);
+    let indexes = ns_index_set::to_vec(env, indexes);
+    if objects.len() != indexes.len() {
+        let reason = format!(
+            "Count of objects ({}) differs from count of indexes ({})",
+            objects.len(),
+            indexes.len(),
+        );
+        ns_exception::raise(env, "NSInvalidArgumentException", reason);
+        return;
+    }
+    // Ascending order, so that each index refers to the array as it will be
+    // once all the objects are inserted.
+    for (object, index) in objects.into_iter().zip(indexes) {
+        () = msg![env; this insertObject:object atIndex:index];
+    }
+}
+
+- (())removeObjectsAtIndexes:(id)indexes { // NSIndexSet*
+    let count: NSUInteger = msg![env; this count];
+    let indexes = ns_index_set::to_vec(env, indexes);
+    if let Some(&last) = indexes.last().filter(|&&last| last >= count) {
+        let reason = format!("Index {} out of bounds; count {}", last, count);
+        ns_exception::raise(env, "NSRangeException", reason);
+        return;
+    }
+    // Iterate backwards so that indices stay valid after removal.
+    for index in indexes.into_iter().rev() {
+        () = msg![env; this removeObjectAtIndex:index];
+    }
+}
+
+- (())replaceObjectsAtIndexes:(id)indexes // NSIndexSet*
+                  withObjects:(id)objects { // NSArray*
+    let objects = to_vec(env, objects);
+    let indexes = ns_index_set::to_vec(env, indexes);
+    if objects.len() != indexes.len() {
+        let reason = format!(
+            "Count of objects ({}) differs from count of indexes ({})",
+            objects.len(),
+            indexes.len(),
+        );
+        ns_exception::raise(env, "NSInvalidArgumentException", reason);
+        return;
+    }
+    for (index, object) in indexes.into_iter().zip(objects) {
+        () = msg![env; this replaceObjectAtIndex:index withObject:object];
+    }
+}
+
+- (())removeAllObjects {
+    loop {
+        let count: NSUInteger = msg![env; this count];
+        if count == 0 {
+            break;
+        }
+        () = msg![env; this removeLastObject];
+    }
+}
+
+- (())exchangeObjectAtIndex:(NSUInteger)index1
+          withObjectAtIndex:(NSUInteger)index2 {
+    let object1: id = msg![env; this objectAtIndex:index1];
+    let object2: id = msg![env; this objectAtIndex:index2];
+    // Keep the objects alive while they are only in the array once.
+    retain(env, object1);
+    retain(env, object2);
+    () = msg![env; this replaceObjectAtIndex:index1 withObject:object2];
+    () = msg![env; this replaceObjectAtIndex:index2 withObject:object1];
+    release(env, object1);
+    release(env, object2);
+}
+
+- (())sortUsingSelector:(SEL)comparator {
+    let mut objects = to_vec(env, this);
+    sort_objects(env, &mut objects, |env, a, b| msg_send(env, (a, comparator, b)));
+    replace_all_objects(env, this, objects);
+}
+
+- (())sortUsingFunction:(GuestFunction)comparator // NSInteger (*)(id, id, void*)
+                context:(MutVoidPtr)context {
+    let mut objects = to_vec(env, this);
+    sort_objects(env, &mut objects, |env, a, b| comparator.call_from_host(env, (a, b, context)));
+    replace_all_objects(env, this, objects);
+}
+
 @end
 
 // Our private subclass that is the single implementation of NSArray for the
@@ -177,23 +427,12 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
-- (id)objectEnumerator { // NSEnumerator*
-    let array_host_object: &mut ArrayHostObject = env.objc.borrow_mut(this);
-    let vec = array_host_object.array.to_vec();
-    let host_object = Box::new(ObjectEnumeratorHostObject {
-        iterator: vec.into_iter(),
-    });
-    let class = env.objc.get_known_class("_touchHLE_NSArray_ObjectEnumerator", &mut env.mem);
-    let enumerator = env.objc.alloc_object(class, host_object, &mut env.mem);
-    autorelease(env, enumerator)
-}
-
 // NSFastEnumeration implementation
 - (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
                                   objects:(MutPtr<id>)stackbuf
                                     count:(NSUInteger)len {
-    let mut iterator = env.objc.borrow_mut::<ArrayHostObject>(this).array.iter().copied();
-    fast_enumeration_helper(&mut env.mem, this, &mut iterator, state, stackbuf, len)
+    let mut iterator = env.objc.borrow::<ArrayHostObject>(this).array.iter().copied();
+    fast_enumeration_helper(&mut env.mem, this.cast(), &mut iterator, state, stackbuf, len)
 }
 
 // TODO: more init methods, etc
@@ -245,6 +484,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         release(env, object);
     }
 
+    forget_collection(env, this);
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
@@ -264,23 +504,72 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<ArrayHostObject>(this).array[index as usize]
 }
 
-// TODO: more mutation methods
+// NSFastEnumeration implementation
+- (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
+                                  objects:(MutPtr<id>)stackbuf
+                                    count:(NSUInteger)len {
+    let mutations_ptr = mutations_ptr(env, this);
+    let mut iterator = env.objc.borrow::<ArrayHostObject>(this).array.iter().copied();
+    fast_enumeration_helper(&mut env.mem, mutations_ptr, &mut iterator, state, stackbuf, len)
+}
 
 - (())addObject:(id)object {
     retain(env, object);
     env.objc.borrow_mut::<ArrayHostObject>(this).array.push(object);
+    note_mutation(env, this);
+}
+
+- (())insertObject:(id)object
+           atIndex:(NSUInteger)index {
+    let count = env.objc.borrow::<ArrayHostObject>(this).array.len();
+    if !check_index(env, index, count, /* allow_end: */ true) {
+        return;
+    }
+    retain(env, object);
+    env.objc.borrow_mut::<ArrayHostObject>(this).array.insert(index as usize, object);
+    note_mutation(env, this);
+}
+
+- (())replaceObjectAtIndex:(NSUInteger)index
+                withObject:(id)object {
+    let count = env.objc.borrow::<ArrayHostObject>(this).array.len();
+    if !check_index(env, index, count, /* allow_end: */ false) {
+        return;
+    }
+    retain(env, object);
+    let array = &mut env.objc.borrow_mut::<ArrayHostObject>(this).array;
+    let old = std::mem::replace(&mut array[index as usize], object);
+    note_mutation(env, this);
+    release(env, old);
 }
 
 - (())removeObjectAtIndex:(NSUInteger)index {
+    let count = env.objc.borrow::<ArrayHostObject>(this).array.len();
+    if !check_index(env, index, count, /* allow_end: */ false) {
+        return;
+    }
     let object = env.objc.borrow_mut::<ArrayHostObject>(this).array.remove(index as usize);
+    note_mutation(env, this);
     release(env, object)
 }
 
 - (())removeLastObject {
-    let object = env.objc.borrow_mut::<ArrayHostObject>(this).array.pop().unwrap();
+    let Some(object) = env.objc.borrow_mut::<ArrayHostObject>(this).array.pop() else {
+        ns_exception::raise(env, "NSRangeException", "Array is empty".to_string());
+        return;
+    };
+    note_mutation(env, this);
     release(env, object)
 }
 
+- (())removeAllObjects {
+    let array = std::mem::take(&mut env.objc.borrow_mut::<ArrayHostObject>(this).array);
+    note_mutation(env, this);
+    for object in array {
+        release(env, object);
+    }
+}
+
 @end
 
 // Special variant for use by CFArray with NULL callbacks: objects aren't
@@ -288,21 +577,141 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation _touchHLE_NSMutableArray_non_retaining: _touchHLE_NSMutableArray
 
 - (())dealloc {
+    forget_collection(env, this);
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
 - (())addObject:(id)object {
     env.objc.borrow_mut::<ArrayHostObject>(this).array.push(object);
+    note_mutation(env, this);
+}
+
+- (())insertObject:(id)object
+           atIndex:(NSUInteger)index {
+    env.objc.borrow_mut::<ArrayHostObject>(this).array.insert(index as usize, object);
+    note_mutation(env, this);
+}
+
+- (())replaceObjectAtIndex:(NSUInteger)index
+                withObject:(id)object {
+    env.objc.borrow_mut::<ArrayHostObject>(this).array[index as usize] = object;
+    note_mutation(env, this);
 }
 
 - (())removeObjectAtIndex:(NSUInteger)index {
     env.objc.borrow_mut::<ArrayHostObject>(this).array.remove(index as usize);
+    note_mutation(env, this);
+}
+
+- (())removeLastObject {
+    if env.objc.borrow_mut::<ArrayHostObject>(this).array.pop().is_none() {
+        ns_exception::raise(env, "NSRangeException", "Array is empty".to_string());
+        return;
+    }
+    note_mutation(env, this);
+}
+
+- (())removeAllObjects {
+    env.objc.borrow_mut::<ArrayHostObject>(this).array.clear();
+    note_mutation(env, this);
 }
 
 @end
 
 };
 
+/// Get the objects in any `NSArray`, including guest subclasses. They are not
+/// retained.
+pub(super) fn to_vec(env: &mut Environment, array: id) -> Vec<id> {
+    let count: NSUInteger = msg![env; array count];
+    (0..count)
+        .map(|index| msg![env; array objectAtIndex:index])
+        .collect()
+}
+
+/// Create an (autoreleased) enumerator over a list of objects.
+pub(super) fn new_object_enumerator(env: &mut Environment, objects: Vec<id>) -> id {
+    let host_object = Box::new(ObjectEnumeratorHostObject {
+        iterator: objects.into_iter(),
+    });
+    let class = env
+        .objc
+        .get_known_class("_touchHLE_NSArray_ObjectEnumerator", &mut env.mem);
+    let enumerator = env.objc.alloc_object(class, host_object, &mut env.mem);
+    autorelease(env, enumerator)
+}
+
+/// Replace the contents of a mutable array with a permutation of them, e.g.
+/// after sorting.
+fn replace_all_objects(env: &mut Environment, array: id, objects: Vec<id>) {
+    // Keep the objects alive while they are only in the array once.
+    for &object in &objects {
+        retain(env, object);
+    }
+    for (index, &object) in objects.iter().enumerate() {
+        let index: NSUInteger = index.try_into().unwrap();
+        () = msg![env; array replaceObjectAtIndex:index withObject:object];
+    }
+    for object in objects {
+        release(env, object);
+    }
+}
+
+/// Check an index is in bounds of an array-like collection with `count`
+/// elements, raising `NSRangeException` if not. `allow_end` permits the index
+/// one past the end, for insertion.
+pub(super) fn check_index(
+    env: &mut Environment,
+    index: NSUInteger,
+    count: usize,
+    allow_end: bool,
+) -> bool {
+    let index = index as usize;
+    if index < count || (allow_end && index == count) {
+        return true;
+    }
+    let reason = format!("Index {} out of bounds; count {}", index, count);
+    ns_exception::raise(env, "NSRangeException", reason);
+    false
+}
+
+/// Stable merge sort using a comparator that needs the [Environment], for use
+/// by the sorting methods of `NSArray` and `NSMutableArray`.
+///
+/// [slice::sort_by] isn't used because it can panic if the guest comparator is
+/// inconsistent, and because it can't take a `&mut Environment` separately.
+pub(super) fn sort_objects<F>(env: &mut Environment, objects: &mut [id], mut compare: F)
+where
+    F: FnMut(&mut Environment, id, id) -> NSComparisonResult,
+{
+    fn merge_sort<F>(env: &mut Environment, objects: &mut [id], compare: &mut F)
+    where
+        F: FnMut(&mut Environment, id, id) -> NSComparisonResult,
+    {
+        if objects.len() <= 1 {
+            return;
+        }
+        let mid = objects.len() / 2;
+        merge_sort(env, &mut objects[..mid], compare);
+        merge_sort(env, &mut objects[mid..], compare);
+        let left = objects[..mid].to_vec();
+        let right = objects[mid..].to_vec();
+        let (mut l, mut r) = (0, 0);
+        for slot in objects.iter_mut() {
+            let take_left = r == right.len()
+                || (l < left.len() && compare(env, left[l], right[r]) != NSOrderedDescending);
+            if take_left {
+                *slot = left[l];
+                l += 1;
+            } else {
+                *slot = right[r];
+                r += 1;
+            }
+        }
+    }
+    merge_sort(env, objects, &mut compare)
+}
+
 /// Shortcut for host code, roughly equivalent to
 /// `[[NSArray alloc] initWithObjects:count]` but without copying.
 /// The elements should already be "retained by" the `Vec`.
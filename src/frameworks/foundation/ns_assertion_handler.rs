@@ -0,0 +1,119 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSAssertionHandler`, used by the `NSAssert()` family of macros.
+//!
+//! A failed assertion logs where it happened and raises an
+//! `NSInternalInconsistencyException`. Apps can catch this, but usually don't,
+//! in which case the app terminates with a crash report (see
+//! [crate::crash_report]).
+
+use super::ns_string::{from_rust_string, get_static_str, to_rust_string, with_format};
+use super::NSInteger;
+use crate::abi::DotDotDot;
+use crate::libc::syslog;
+use crate::objc::{autorelease, id, msg, msg_class, nil, objc_classes, ClassExports, ObjC, SEL};
+use crate::{Environment, ThreadId};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Each thread has its own handler, created on first use.
+    current_handlers: HashMap<ThreadId, id>,
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSAssertionHandler: NSObject
+
++ (id)currentHandler {
+    let thread = env.current_thread;
+    if let Some(&handler) = env
+        .framework_state
+        .foundation
+        .ns_assertion_handler
+        .current_handlers
+        .get(&thread)
+    {
+        return handler;
+    }
+    let handler: id = msg![env; this new];
+    env.framework_state
+        .foundation
+        .ns_assertion_handler
+        .current_handlers
+        .insert(thread, handler);
+    handler
+}
+
+- (())handleFailureInMethod:(SEL)selector
+                     object:(id)object
+                       file:(id)file_name // NSString*
+                 lineNumber:(NSInteger)line
+                description:(id)format, // NSString*
+                ...args {
+    let method = if object == nil {
+        // The receiver's class can't be known, so it's printed like a nil
+        // object in a format string.
+        format!("-[(null) {}]", selector.as_str(&env.mem))
+    } else {
+        let class = ObjC::read_isa(object, &env.mem);
+        let is_class = env.objc.class_is_metaclass(class);
+        format!(
+            "{}[{} {}]",
+            if is_class { '+' } else { '-' },
+            env.objc.get_class_name(class),
+            selector.as_str(&env.mem)
+        )
+    };
+    handle_failure(env, &method, file_name, line, format, args);
+}
+
+- (())handleFailureInFunction:(id)function_name // NSString*
+                         file:(id)file_name // NSString*
+                   lineNumber:(NSInteger)line
+                  description:(id)format, // NSString*
+                  ...args {
+    let function = to_rust_string(env, function_name).into_owned();
+    handle_failure(env, &function, file_name, line, format, args);
+}
+
+@end
+
+};
+
+fn handle_failure(
+    env: &mut Environment,
+    location: &str,
+    file_name: id,
+    line: NSInteger,
+    format: id,
+    args: DotDotDot,
+) {
+    let file_name = to_rust_string(env, file_name);
+    // Apple's implementation logs this with NSLog().
+    let message = format!(
+        "*** Assertion failure in {}, {}:{}",
+        location, file_name, line
+    );
+    let process_name = syslog::process_name(env);
+    syslog::log_app_message(env, &process_name, None, message.as_bytes());
+
+    let reason = if format == nil {
+        String::new()
+    } else {
+        with_format(env, format, args.start())
+    };
+    let reason = from_rust_string(env, reason);
+    let reason = autorelease(env, reason);
+    let name = get_static_str(env, "NSInternalInconsistencyException");
+    let exception: id = msg_class![env; NSException exceptionWithName:name
+                                                               reason:reason
+                                                             userInfo:nil];
+    // This normally doesn't return.
+    () = msg![env; exception raise];
+}
@@ -4,8 +4,12 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `NSAutoreleasePool`.
+//!
+//! Each thread has its own stack of pools, and objects are autoreleased into
+//! the innermost pool of the current thread. Pools that are still in place
+//! when a thread exits are drained.
 
-use crate::objc::{id, msg, objc_classes, release, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{id, msg, objc_classes, release, ClassExports, HostObject, NSZonePtr, ObjC};
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -49,9 +53,12 @@ pub const CLASSES: ClassExports = objc_classes! {
     {
         msg![env; current_pool addObject:obj]
     } else {
-        log_dbg!(
-            "Warning: no active NSAutoreleasePool, leaking {:?}, current thread {}",
+        // Apple's implementation prints a similar message.
+        let class = ObjC::read_isa(obj, &env.mem);
+        log!(
+            "Warning: object {:?} of class {:?} autoreleased with no pool in place on thread {} - just leaking",
             obj,
+            env.objc.get_class_name(class),
             current_thread
         );
     }
@@ -87,25 +94,65 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (())dealloc {
     let current_thread = env.current_thread;
     log_dbg!("Draining pool: {:?}, current thread {}", this, current_thread);
-    let host_obj: &mut NSAutoreleasePoolHostObject = env.objc.borrow_mut(this);
+    let host_obj: &NSAutoreleasePoolHostObject = env.objc.borrow(this);
     // It's unclear what should happen when draining a pool on the wrong thread,
     // but we prefer to be conservative here
     assert_eq!(host_obj.original_thread, current_thread);
-    let pool_stack = &mut env
-        .framework_state
-        .foundation
-        .ns_autorelease_pool
+    // Draining a pool also drains the pools nested inside it.
+    loop {
+        let top = State::get(env)
+            .pool_stacks
+            .get(&current_thread)
+            .and_then(|pool_stack| pool_stack.last().copied());
+        match top {
+            Some(top) if top == this => break,
+            Some(inner) => {
+                log_dbg!("Draining nested pool {:?} first", inner);
+                pop_and_drain(env, inner);
+            }
+            None => panic!("Pool {:?} is not in the current thread's stack!", this),
+        }
+    }
+    pop_and_drain(env, this);
+}
+
+@end
+
+};
+
+/// Remove the innermost pool of the current thread, which must be `pool`, free
+/// it and release its objects. This doesn't rely on releasing the pool, which
+/// would do nothing if something has an extra reference to it.
+fn pop_and_drain(env: &mut Environment, pool: id) {
+    let current_thread = env.current_thread;
+    let popped = State::get(env)
         .pool_stacks
-        .get_mut(&current_thread).unwrap();
-    let pop_res = pool_stack.pop();
-    assert!(pop_res == Some(this));
+        .get_mut(&current_thread)
+        .and_then(|pool_stack| pool_stack.pop());
+    assert_eq!(popped, Some(pool));
+    let host_obj: &mut NSAutoreleasePoolHostObject = env.objc.borrow_mut(pool);
     let objects = std::mem::take(&mut host_obj.objects);
-    env.objc.dealloc_object(this, &mut env.mem);
+    env.objc.dealloc_object(pool, &mut env.mem);
     for object in objects {
         release(env, object);
     }
 }
 
-@end
-
-};
+/// For use when a thread exits: drain the pools that it left in place,
+/// innermost first.
+pub fn thread_exited(env: &mut Environment) {
+    let current_thread = env.current_thread;
+    while let Some(pool) = State::get(env)
+        .pool_stacks
+        .get(&current_thread)
+        .and_then(|pool_stack| pool_stack.last().copied())
+    {
+        log_dbg!(
+            "Thread {} exited with pool {:?} in place, draining it",
+            current_thread,
+            pool
+        );
+        pop_and_drain(env, pool);
+    }
+    State::get(env).pool_stacks.remove(&current_thread);
+}
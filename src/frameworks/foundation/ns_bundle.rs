@@ -22,7 +22,7 @@ use std::collections::HashSet;
 
 // Should be ISO 639-1 (or ISO 639-2) compliant
 // TODO: complete this list or use some crate for mapping
-const LANG_ID_TO_LANG_PROJ: &[(&str, &str)] = &[
+pub const LANG_ID_TO_LANG_PROJ: &[(&str, &str)] = &[
     ("da", "Danish.lproj"),
     ("nl", "Dutch.lproj"),
     ("en", "English.lproj"),
@@ -158,13 +158,21 @@ pub const CLASSES: ClassExports = objc_classes! {
         return path
     }
 
-    // Try preferred languages in order of preference
+    // Try preferred languages in order of preference. Apps may use either the
+    // language code (e.g. "fr.lproj") or the legacy name (e.g.
+    // "French.lproj"), so try both.
     let langs: id = msg_class![env; NSLocale preferredLanguages];
     let lang_count: NSUInteger = msg![env; langs count];
     let mut unknown_codes = HashSet::new();
     for i in 0..lang_count {
         let lang_code: id = msg![env; langs objectAtIndex:i];
         let lang_code = ns_string::to_rust_string(env, lang_code); // TODO: avoid copy
+        let lproj = ns_string::from_rust_string(env, format!("{}.lproj", lang_code));
+        let localized_path = path_for_resource_helper(env, this, name, lproj, directory, extension);
+        release(env, lproj);
+        if localized_path != nil {
+            return localized_path;
+        }
         if let Some(&(_, lproj)) = LANG_ID_TO_LANG_PROJ.iter().find(|&&(code, _)| code == lang_code) {
             let lproj: id = ns_string::get_static_str(env, lproj);
             let localized_path = path_for_resource_helper(env, this, name, lproj, directory, extension);
@@ -180,10 +188,16 @@ pub const CLASSES: ClassExports = objc_classes! {
     // TODO: fallback to a development language (CFBundleDevelopmentRegion from
     // Info.plist)
     if !unknown_codes.is_empty() {
-        log!("TODO: language codes {:?} aren't mapped to a language name, falling back to English", unknown_codes);
+        log_dbg!("Language codes {:?} aren't mapped to a legacy language name", unknown_codes);
+    }
+    for lproj in ["en.lproj", "English.lproj"] {
+        let lproj: id = ns_string::get_static_str(env, lproj);
+        let localized_path = path_for_resource_helper(env, this, name, lproj, directory, extension);
+        if localized_path != nil {
+            return localized_path;
+        }
     }
-    let lproj: id = ns_string::get_static_str(env, "English.lproj");
-    path_for_resource_helper(env, this, name, lproj, directory, extension)
+    nil
 }
 - (id)pathForResource:(id)name // NSString*
                ofType:(id)extension { // NSString*
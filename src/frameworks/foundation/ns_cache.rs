@@ -0,0 +1,265 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSCache`.
+//!
+//! Apple don't document which objects get evicted first, so this uses a simple
+//! least-recently-used policy. Caches are also emptied when the app gets a
+//! memory warning (see [handle_memory_warning]), since that is when a real
+//! device would be evicting things.
+
+use super::NSUInteger;
+use crate::objc::{
+    id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+};
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    /// List of caches, so they can be emptied on a memory warning.
+    /// Non-retaining!
+    caches: Vec<id>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.foundation.ns_cache
+    }
+}
+
+struct CacheEntry {
+    /// Strong reference. Unlike `NSDictionary`, keys are not copied.
+    key: id,
+    key_hash: NSUInteger,
+    /// Strong reference.
+    object: id,
+    cost: NSUInteger,
+}
+
+#[derive(Default)]
+struct NSCacheHostObject {
+    /// Least recently used entries first.
+    entries: Vec<CacheEntry>,
+    total_cost: NSUInteger,
+    count_limit: NSUInteger,
+    total_cost_limit: NSUInteger,
+    /// `NSString*`
+    name: id,
+    /// Weak reference.
+    delegate: id,
+    evicts_objects_with_discarded_content: bool,
+}
+impl HostObject for NSCacheHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSCache: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSCacheHostObject {
+        evicts_objects_with_discarded_content: true,
+        ..Default::default()
+    });
+    let new = env.objc.alloc_object(this, host_object, &mut env.mem);
+    State::get(env).caches.push(new);
+    new
+}
+
+- (())dealloc {
+    let host_object = std::mem::take(env.objc.borrow_mut::<NSCacheHostObject>(this));
+    for entry in host_object.entries {
+        release(env, entry.key);
+        release(env, entry.object);
+    }
+    release(env, host_object.name);
+
+    let caches = &mut State::get(env).caches;
+    caches.swap_remove(caches.iter().position(|&c| c == this).unwrap());
+
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)name {
+    env.objc.borrow::<NSCacheHostObject>(this).name
+}
+- (())setName:(id)name { // NSString*
+    let name: id = msg![env; name copy];
+    let host_object = env.objc.borrow_mut::<NSCacheHostObject>(this);
+    let old = std::mem::replace(&mut host_object.name, name);
+    release(env, old);
+}
+
+- (id)delegate {
+    env.objc.borrow::<NSCacheHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate { // something implementing NSCacheDelegate
+    env.objc.borrow_mut::<NSCacheHostObject>(this).delegate = delegate;
+}
+
+- (NSUInteger)countLimit {
+    env.objc.borrow::<NSCacheHostObject>(this).count_limit
+}
+- (())setCountLimit:(NSUInteger)limit {
+    env.objc.borrow_mut::<NSCacheHostObject>(this).count_limit = limit;
+    evict_over_limits(env, this);
+}
+
+- (NSUInteger)totalCostLimit {
+    env.objc.borrow::<NSCacheHostObject>(this).total_cost_limit
+}
+- (())setTotalCostLimit:(NSUInteger)limit {
+    env.objc.borrow_mut::<NSCacheHostObject>(this).total_cost_limit = limit;
+    evict_over_limits(env, this);
+}
+
+- (bool)evictsObjectsWithDiscardedContent {
+    env.objc.borrow::<NSCacheHostObject>(this).evicts_objects_with_discarded_content
+}
+- (())setEvictsObjectsWithDiscardedContent:(bool)evicts {
+    // NSDiscardableContent isn't implemented, so this has no effect.
+    env.objc.borrow_mut::<NSCacheHostObject>(this).evicts_objects_with_discarded_content = evicts;
+}
+
+- (id)objectForKey:(id)key {
+    let Some(idx) = find_entry(env, this, key) else {
+        return nil;
+    };
+    // Move the entry to the back, since it's now the most recently used.
+    let entries = &mut env.objc.borrow_mut::<NSCacheHostObject>(this).entries;
+    let entry = entries.remove(idx);
+    let object = entry.object;
+    entries.push(entry);
+    object
+}
+
+- (())setObject:(id)object
+         forKey:(id)key {
+    msg![env; this setObject:object forKey:key cost:0u32]
+}
+- (())setObject:(id)object
+         forKey:(id)key
+           cost:(NSUInteger)cost {
+    if object == nil {
+        log!("Warning: [(NSCache*){:?} setObject:nil forKey:{:?}] ignored", this, key);
+        return;
+    }
+
+    retain(env, object);
+    if let Some(idx) = find_entry(env, this, key) {
+        let host_object = env.objc.borrow_mut::<NSCacheHostObject>(this);
+        let mut entry = host_object.entries.remove(idx);
+        host_object.total_cost = host_object.total_cost - entry.cost + cost;
+        let old_object = std::mem::replace(&mut entry.object, object);
+        entry.cost = cost;
+        host_object.entries.push(entry);
+        release(env, old_object);
+    } else {
+        retain(env, key);
+        let key_hash: NSUInteger = msg![env; key hash];
+        let host_object = env.objc.borrow_mut::<NSCacheHostObject>(this);
+        host_object.total_cost += cost;
+        host_object.entries.push(CacheEntry {
+            key,
+            key_hash,
+            object,
+            cost,
+        });
+    }
+
+    evict_over_limits(env, this);
+}
+
+- (())removeObjectForKey:(id)key {
+    if let Some(idx) = find_entry(env, this, key) {
+        remove_entry(env, this, idx);
+    }
+}
+
+- (())removeAllObjects {
+    while !env.objc.borrow::<NSCacheHostObject>(this).entries.is_empty() {
+        remove_entry(env, this, 0);
+    }
+}
+
+@end
+
+};
+
+fn find_entry(env: &mut Environment, cache: id, key: id) -> Option<usize> {
+    let key_hash: NSUInteger = msg![env; key hash];
+    // TODO: avoid copy?
+    let candidates: Vec<(usize, id)> = env
+        .objc
+        .borrow::<NSCacheHostObject>(cache)
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.key_hash == key_hash)
+        .map(|(idx, entry)| (idx, entry.key))
+        .collect();
+    candidates
+        .into_iter()
+        .find(|&(_, candidate_key)| candidate_key == key || msg![env; candidate_key isEqualTo:key])
+        .map(|(idx, _)| idx)
+}
+
+/// Remove an entry from the cache, telling the delegate about it first.
+fn remove_entry(env: &mut Environment, cache: id, idx: usize) {
+    let host_object = env.objc.borrow_mut::<NSCacheHostObject>(cache);
+    let entry = host_object.entries.remove(idx);
+    host_object.total_cost -= entry.cost;
+    let delegate = host_object.delegate;
+
+    if delegate != nil
+        && env
+            .objc
+            .object_has_method_named(&env.mem, delegate, "cache:willEvictObject:")
+    {
+        () = msg![env; delegate cache:cache willEvictObject:(entry.object)];
+    }
+
+    release(env, entry.key);
+    release(env, entry.object);
+}
+
+/// Evict the least recently used entries until the cache is within its limits.
+/// A limit of zero means no limit.
+fn evict_over_limits(env: &mut Environment, cache: id) {
+    loop {
+        let &NSCacheHostObject {
+            ref entries,
+            total_cost,
+            count_limit,
+            total_cost_limit,
+            ..
+        } = env.objc.borrow(cache);
+        let count: NSUInteger = entries.len().try_into().unwrap();
+        let over_count_limit = count_limit != 0 && count > count_limit;
+        let over_cost_limit = total_cost_limit != 0 && total_cost > total_cost_limit;
+        if !over_count_limit && !over_cost_limit {
+            break;
+        }
+        remove_entry(env, cache, 0);
+    }
+}
+
+/// For use by `UIApplication`'s memory warning handling: evict everything from
+/// all caches.
+pub fn handle_memory_warning(env: &mut Environment) {
+    let caches = State::get(env).caches.clone();
+    for cache in caches {
+        // A delegate could have released another cache.
+        if !State::get(env).caches.contains(&cache) {
+            continue;
+        }
+        let count = env.objc.borrow::<NSCacheHostObject>(cache).entries.len();
+        if count == 0 {
+            continue;
+        }
+        log_dbg!("Evicting {} objects from cache {:?}", count, cache);
+        () = msg![env; cache removeAllObjects];
+    }
+}
@@ -5,18 +5,35 @@
  */
 //! `NSData` and `NSMutableData`.
 
-use super::ns_string::to_rust_string;
-use super::{NSRange, NSUInteger};
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::{hash_helper, ns_exception, NSNotFound, NSRange, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::{ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, HostObject,
+    NSZonePtr,
 };
 use crate::{msg_class, Environment};
 
+type NSDataBase64EncodingOptions = NSUInteger;
+const NSDataBase64Encoding64CharacterLineLength: NSDataBase64EncodingOptions = 1 << 0;
+const NSDataBase64Encoding76CharacterLineLength: NSDataBase64EncodingOptions = 1 << 1;
+const NSDataBase64EncodingEndLineWithCarriageReturn: NSDataBase64EncodingOptions = 1 << 4;
+const NSDataBase64EncodingEndLineWithLineFeed: NSDataBase64EncodingOptions = 1 << 5;
+
+type NSDataBase64DecodingOptions = NSUInteger;
+const NSDataBase64DecodingIgnoreUnknownCharacters: NSDataBase64DecodingOptions = 1 << 0;
+
+type NSDataSearchOptions = NSUInteger;
+const NSDataSearchBackwards: NSDataSearchOptions = 1 << 0;
+const NSDataSearchAnchored: NSDataSearchOptions = 1 << 1;
+
 struct NSDataHostObject {
     bytes: MutVoidPtr,
     length: NSUInteger,
+    /// Size of the allocation `bytes` points to. This is only larger than
+    /// `length` for `NSMutableData`, see [set_length].
+    capacity: NSUInteger,
 }
 impl HostObject for NSDataHostObject {}
 
@@ -25,12 +42,13 @@ pub const CLASSES: ClassExports = objc_classes! {
 (env, this, _cmd);
 
 // NSData doesn't seem to be an abstract class?
-@implementation NSData: NSObject
+@implementation NSData: NSObject <NSCopying, NSMutableCopying, NSCoding>
 
 + (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = Box::new(NSDataHostObject {
         bytes: Ptr::null(),
         length: 0,
+        capacity: 0,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -55,6 +73,18 @@ pub const CLASSES: ClassExports = objc_classes! {
     autorelease(env, new)
 }
 
++ (id)dataWithContentsOfURL:(id)url { // NSURL*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithContentsOfURL:url];
+    autorelease(env, new)
+}
+
++ (id)dataWithData:(id)data { // NSData*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithData:data];
+    autorelease(env, new)
+}
+
 // Calling the standard `init` is also allowed, in which case we just get data
 // of size 0.
 
@@ -64,6 +94,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     assert!(host_object.bytes.is_null() && host_object.length == 0);
     host_object.bytes = bytes;
     host_object.length = length;
+    host_object.capacity = length;
     this
 }
 
@@ -75,10 +106,39 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.mem.memmove(alloc, bytes.cast_const(), length);
     host_object.bytes = alloc;
     host_object.length = length;
+    host_object.capacity = length;
     this
 }
 
+- (id)initWithData:(id)data { // NSData*
+    let bytes: ConstVoidPtr = msg![env; data bytes];
+    let length: NSUInteger = msg![env; data length];
+    msg![env; this initWithBytes:bytes length:length]
+}
+
+- (id)initWithBase64EncodedString:(id)string // NSString*
+                          options:(NSDataBase64DecodingOptions)options {
+    let ignore_unknown = options & NSDataBase64DecodingIgnoreUnknownCharacters != 0;
+    let encoded = to_rust_string(env, string);
+    init_with_base64(env, this, encoded.as_bytes(), ignore_unknown)
+}
+- (id)initWithBase64EncodedData:(id)data // NSData*
+                        options:(NSDataBase64DecodingOptions)options {
+    let ignore_unknown = options & NSDataBase64DecodingIgnoreUnknownCharacters != 0;
+    let encoded = as_slice(env, data).to_vec();
+    init_with_base64(env, this, &encoded, ignore_unknown)
+}
+// Deprecated predecessor of the above, which ignores unknown characters.
+- (id)initWithBase64Encoding:(id)string { // NSString*
+    msg![env; this initWithBase64EncodedString:string
+                                       options:NSDataBase64DecodingIgnoreUnknownCharacters]
+}
+
 - (id)initWithContentsOfURL:(id)url { // NSURL *
+    if msg![env; url isFileURL] {
+        let path: id = msg![env; url path];
+        return msg![env; this initWithContentsOfFile:path];
+    }
     let path: id = msg![env; url absoluteString];
     let path = to_rust_string(env, path);
     // TODO: file URL case
@@ -103,6 +163,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     let host_object = env.objc.borrow_mut::<NSDataHostObject>(this);
     host_object.bytes = alloc;
     host_object.length = size;
+    host_object.capacity = size;
     this
 }
 
@@ -122,6 +183,16 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.fs.write(GuestPath::new(&file), slice).is_ok()
 }
 
+- (bool)writeToURL:(id)url // NSURL*
+        atomically:(bool)use_aux_file {
+    if !msg![env; url isFileURL] {
+        log!("TODO: [(NSData*){:?} writeToURL:{:?} atomically:_] (not a file URL)", this, url);
+        return false;
+    }
+    let path: id = msg![env; url path];
+    msg![env; this writeToFile:path atomically:use_aux_file]
+}
+
 - (())dealloc {
     let &NSDataHostObject { bytes, .. } = env.objc.borrow(this);
     if !bytes.is_null() {
@@ -150,12 +221,10 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (())getBytes:(MutPtr<u8>)buffer range:(NSRange)range {
-    if range.length == 0 {
+    if !check_range(env, this, range) || range.length == 0 {
         return;
     }
-    let &NSDataHostObject { bytes, length, .. } = env.objc.borrow(this);
-    // TODO: throw NSRangeException if out-of-range instead of panic?
-    assert!(range.location < length && range.location + range.length <= length);
+    let &NSDataHostObject { bytes, .. } = env.objc.borrow(this);
     env.mem.memmove(
         buffer.cast(),
         bytes.cast_const() + range.location,
@@ -171,11 +240,131 @@ pub const CLASSES: ClassExports = objc_classes! {
         length,
     );
 }
+- (())getBytes:(MutPtr<u8>)buffer length:(NSUInteger)max_length {
+    let &NSDataHostObject { bytes, length, .. } = env.objc.borrow(this);
+    env.mem.memmove(
+        buffer.cast(),
+        bytes.cast_const(),
+        length.min(max_length),
+    );
+}
+
+- (id)subdataWithRange:(NSRange)range {
+    if !check_range(env, this, range) {
+        return nil;
+    }
+    let NSRange { location, length } = range;
+    let bytes = as_slice(env, this)[location as usize..][..length as usize].to_vec();
+    let new = from_rust_slice(env, &bytes);
+    autorelease(env, new)
+}
+
+- (NSRange)rangeOfData:(id)data // NSData*
+               options:(NSDataSearchOptions)options
+                 range:(NSRange)range {
+    let not_found = NSRange { location: NSNotFound as NSUInteger, length: 0 };
+    if data == nil {
+        ns_exception::raise(env, "NSInvalidArgumentException", "Data to find is nil".to_string());
+        return not_found;
+    }
+    if !check_range(env, this, range) {
+        return not_found;
+    }
+    let needle = as_slice(env, data).to_vec();
+    let haystack = &as_slice(env, this)[range.location as usize..][..range.length as usize];
+    // Apple's implementation never finds empty data.
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return not_found;
+    }
+    let last = haystack.len() - needle.len();
+    let matches_at = |i: &usize| haystack[*i..].starts_with(&needle);
+    let backwards = options & NSDataSearchBackwards != 0;
+    let found = match (options & NSDataSearchAnchored != 0, backwards) {
+        (true, false) => Some(0).filter(matches_at),
+        (true, true) => Some(last).filter(matches_at),
+        (false, false) => (0..=last).find(matches_at),
+        (false, true) => (0..=last).rev().find(matches_at),
+    };
+    match found {
+        Some(i) => NSRange {
+            location: range.location + NSUInteger::try_from(i).unwrap(),
+            length: needle.len().try_into().unwrap(),
+        },
+        None => not_found,
+    }
+}
+
+- (id)base64EncodedStringWithOptions:(NSDataBase64EncodingOptions)options { // NSString*
+    let encoded = base64_encode(as_slice(env, this), options);
+    let string = from_rust_string(env, encoded);
+    autorelease(env, string)
+}
+- (id)base64EncodedDataWithOptions:(NSDataBase64EncodingOptions)options { // NSData*
+    let encoded = base64_encode(as_slice(env, this), options);
+    let data = from_rust_slice(env, encoded.as_bytes());
+    autorelease(env, data)
+}
+// Deprecated predecessor of the above.
+- (id)base64Encoding { // NSString*
+    msg![env; this base64EncodedStringWithOptions:0u32]
+}
+
+- (NSUInteger)hash {
+    hash_helper(&as_slice(env, this))
+}
+- (bool)isEqualTo:(id)other {
+    if this == other {
+        return true;
+    }
+    let class: Class = msg_class![env; NSData class];
+    if !msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    msg![env; this isEqualToData:other]
+}
+- (bool)isEqualToData:(id)other { // NSData*
+    if this == other {
+        return true;
+    }
+    if other == nil {
+        return false;
+    }
+    // TODO: avoid copying
+    let bytes = as_slice(env, this).to_vec();
+    bytes == as_slice(env, other)
+}
+
+- (id)description {
+    // Some apps parse this, e.g. to get a hex string from a push notification
+    // device token, so the format must match Apple's: lowercase hexadecimal,
+    // with a space after every four bytes.
+    let mut description = String::from("<");
+    for (i, byte) in as_slice(env, this).iter().enumerate() {
+        if i > 0 && i % 4 == 0 {
+            description.push(' ');
+        }
+        description.push_str(&format!("{:02x}", byte));
+    }
+    description.push('>');
+    let description = from_rust_string(env, description);
+    autorelease(env, description)
+}
 
 @end
 
 @implementation NSMutableData: NSData
 
++ (id)dataWithCapacity:(NSUInteger)capacity {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCapacity:capacity];
+    autorelease(env, new)
+}
++ (id)dataWithLength:(NSUInteger)length {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithLength:length];
+    autorelease(env, new)
+}
+
 - (id)copyWithZone:(NSZonePtr)_zone {
     let bytes: ConstVoidPtr = msg![env; this bytes];
     let length: NSUInteger = msg![env; this length];
@@ -183,19 +372,279 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg![env; new initWithBytes:bytes length:length]
 }
 
-- (())increaseLengthBy:(NSUInteger)add_len {
-    let &NSDataHostObject { bytes, length, .. } = env.objc.borrow(this);
-    let new_len = length + add_len;
-    let new_bytes = env.mem.realloc(bytes, new_len);
-    let host = env.objc.borrow_mut::<NSDataHostObject>(this);
-    host.length = new_len;
-    host.bytes = new_bytes;
+- (id)initWithCapacity:(NSUInteger)capacity {
+    let host_object = env.objc.borrow_mut::<NSDataHostObject>(this);
+    assert!(host_object.bytes.is_null() && host_object.length == 0);
+    if capacity > 0 {
+        let alloc = env.mem.alloc(capacity);
+        let host_object = env.objc.borrow_mut::<NSDataHostObject>(this);
+        host_object.bytes = alloc;
+        host_object.capacity = capacity;
+    }
+    this
+}
+- (id)initWithLength:(NSUInteger)length {
+    set_length(env, this, length);
+    this
+}
+
+- (MutVoidPtr)mutableBytes {
+    env.objc.borrow::<NSDataHostObject>(this).bytes
+}
+
+- (())setLength:(NSUInteger)length {
+    set_length(env, this, length);
+}
+- (())increaseLengthBy:(NSUInteger)extra_length {
+    let length = env.objc.borrow::<NSDataHostObject>(this).length;
+    let Some(new_length) = length.checked_add(extra_length) else {
+        let reason = format!("Can't increase length {} by {}", length, extra_length);
+        ns_exception::raise(env, "NSMallocException", reason);
+        return;
+    };
+    set_length(env, this, new_length);
+}
+
+- (())appendBytes:(ConstVoidPtr)bytes
+           length:(NSUInteger)length {
+    let old_length = env.objc.borrow::<NSDataHostObject>(this).length;
+    let range = NSRange { location: old_length, length: 0 };
+    let bytes = read_replacement_bytes(env, bytes, length);
+    replace_bytes(env, this, range, &bytes);
+}
+- (())appendData:(id)other { // NSData*
+    let old_length = env.objc.borrow::<NSDataHostObject>(this).length;
+    let range = NSRange { location: old_length, length: 0 };
+    let bytes = as_slice(env, other).to_vec();
+    replace_bytes(env, this, range, &bytes);
+}
+
+- (())replaceBytesInRange:(NSRange)range
+                withBytes:(ConstVoidPtr)bytes {
+    let bytes = read_replacement_bytes(env, bytes, range.length);
+    replace_bytes(env, this, range, &bytes);
+}
+- (())replaceBytesInRange:(NSRange)range
+                withBytes:(ConstVoidPtr)bytes
+                   length:(NSUInteger)length {
+    let bytes = read_replacement_bytes(env, bytes, length);
+    replace_bytes(env, this, range, &bytes);
+}
+- (())resetBytesInRange:(NSRange)range {
+    let zeroes = vec![0; range.length as usize];
+    replace_bytes(env, this, range, &zeroes);
+}
+- (())setData:(id)other { // NSData*
+    let length = env.objc.borrow::<NSDataHostObject>(this).length;
+    let range = NSRange { location: 0, length };
+    let bytes = as_slice(env, other).to_vec();
+    replace_bytes(env, this, range, &bytes);
 }
 
 @end
 
 };
 
+/// Create a new `NSData` with a copy of some bytes. The result is not
+/// autoreleased.
+pub fn from_rust_slice(env: &mut Environment, bytes: &[u8]) -> id {
+    let length: NSUInteger = bytes.len().try_into().unwrap();
+    let alloc = env.mem.alloc(length);
+    env.mem
+        .bytes_at_mut(alloc.cast(), length)
+        .copy_from_slice(bytes);
+    let data: id = msg_class![env; NSData alloc];
+    msg![env; data initWithBytesNoCopy:alloc length:length]
+}
+
+/// Like [to_rust_slice], but allows the data to be empty.
+fn as_slice(env: &mut Environment, data: id) -> &[u8] {
+    let &NSDataHostObject { bytes, length, .. } = env.objc.borrow(data);
+    // Mem::bytes_at() panics when the pointer is NULL, but NSData's pointer can
+    // be NULL if the length is 0.
+    if length == 0 {
+        &[]
+    } else {
+        env.mem.bytes_at(bytes.cast(), length)
+    }
+}
+
+/// Check a range is within the bounds of some data. If it isn't, an exception
+/// is raised and [false] is returned.
+fn check_range(env: &mut Environment, data: id, range: NSRange) -> bool {
+    let length = env.objc.borrow::<NSDataHostObject>(data).length;
+    if matches!(range.location.checked_add(range.length), Some(end) if end <= length) {
+        return true;
+    }
+    let reason = format!("Range {} exceeds data length {}", range, length);
+    ns_exception::raise(env, "NSRangeException", reason);
+    false
+}
+
+/// Change the length of an `NSMutableData`, zero-filling any new bytes. The
+/// allocation grows geometrically and never shrinks, so the pointer returned
+/// by `mutableBytes` stays the same across most appends, which some apps rely
+/// on even though Apple doesn't promise it.
+fn set_length(env: &mut Environment, data: id, new_length: NSUInteger) {
+    let &NSDataHostObject {
+        bytes,
+        length,
+        capacity,
+    } = env.objc.borrow(data);
+    let bytes = if new_length > capacity {
+        let new_capacity = new_length.max(capacity.saturating_mul(2));
+        let new_bytes = env.mem.alloc(new_capacity);
+        if !bytes.is_null() {
+            env.mem.memmove(new_bytes, bytes.cast_const(), length);
+            env.mem.free(bytes);
+        }
+        let host_object = env.objc.borrow_mut::<NSDataHostObject>(data);
+        host_object.bytes = new_bytes;
+        host_object.capacity = new_capacity;
+        new_bytes
+    } else {
+        bytes
+    };
+    if new_length > length {
+        env.mem
+            .bytes_at_mut(bytes.cast::<u8>() + length, new_length - length)
+            .fill(0);
+    }
+    env.objc.borrow_mut::<NSDataHostObject>(data).length = new_length;
+}
+
+/// Copy bytes passed by the app, which might be part of the data they will be
+/// written to. A null pointer is treated as zeroes.
+fn read_replacement_bytes(
+    env: &mut Environment,
+    bytes: ConstVoidPtr,
+    length: NSUInteger,
+) -> Vec<u8> {
+    if bytes.is_null() || length == 0 {
+        vec![0; length as usize]
+    } else {
+        env.mem.bytes_at(bytes.cast(), length).to_vec()
+    }
+}
+
+/// Replace a range of an `NSMutableData`'s bytes with a different number of
+/// bytes. Like in Apple's implementation, the range may extend past the end.
+fn replace_bytes(env: &mut Environment, data: id, range: NSRange, replacement: &[u8]) {
+    let length = env.objc.borrow::<NSDataHostObject>(data).length;
+    if range.location > length {
+        let reason = format!("Range {} exceeds data length {}", range, length);
+        ns_exception::raise(env, "NSRangeException", reason);
+        return;
+    }
+    let start = range.location as usize;
+    let end = range.location.saturating_add(range.length).min(length) as usize;
+    let tail = as_slice(env, data)[end..].to_vec();
+    let new_length = start + replacement.len() + tail.len();
+    set_length(env, data, new_length.try_into().unwrap());
+    let bytes = env.objc.borrow::<NSDataHostObject>(data).bytes;
+    if new_length > start {
+        let out = env.mem.bytes_at_mut(
+            bytes.cast::<u8>() + range.location,
+            (new_length - start).try_into().unwrap(),
+        );
+        out[..replacement.len()].copy_from_slice(replacement);
+        out[replacement.len()..].copy_from_slice(&tail);
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8], options: NSDataBase64EncodingOptions) -> String {
+    let line_length = if options & NSDataBase64Encoding64CharacterLineLength != 0 {
+        Some(64)
+    } else if options & NSDataBase64Encoding76CharacterLineLength != 0 {
+        Some(76)
+    } else {
+        None
+    };
+    let line_ending = match (
+        options & NSDataBase64EncodingEndLineWithCarriageReturn != 0,
+        options & NSDataBase64EncodingEndLineWithLineFeed != 0,
+    ) {
+        (true, false) => "\r",
+        (false, true) => "\n",
+        _ => "\r\n",
+    };
+
+    let mut encoded = String::new();
+    let mut line_used = 0;
+    for chunk in bytes.chunks(3) {
+        if line_length == Some(line_used) {
+            encoded.push_str(line_ending);
+            line_used = 0;
+        }
+        let group = (u32::from(chunk[0]) << 16)
+            | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - i * 6)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+        line_used += 4;
+    }
+    encoded
+}
+
+/// Decode Base64 data. Returns [None] if it is invalid.
+fn base64_decode(encoded: &[u8], ignore_unknown: bool) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut group: u32 = 0;
+    let mut group_len = 0;
+    let mut padding = 0;
+    for &c in encoded {
+        let value = if c == b'=' {
+            padding += 1;
+            0
+        } else if let Some(value) = BASE64_ALPHABET.iter().position(|&a| a == c) {
+            // Padding is only allowed at the end.
+            if padding > 0 {
+                return None;
+            }
+            value as u32
+        } else if ignore_unknown {
+            continue;
+        } else {
+            return None;
+        };
+        group = (group << 6) | value;
+        group_len += 1;
+        if group_len == 4 {
+            let bytes = group.to_be_bytes();
+            decoded.extend_from_slice(&bytes[1..]);
+            group = 0;
+            group_len = 0;
+        }
+    }
+    if group_len != 0 || padding > 2 {
+        return None;
+    }
+    decoded.truncate(decoded.len() - padding);
+    Some(decoded)
+}
+
+fn init_with_base64(env: &mut Environment, this: id, encoded: &[u8], ignore_unknown: bool) -> id {
+    let Some(decoded) = base64_decode(encoded, ignore_unknown) else {
+        release(env, this);
+        return nil;
+    };
+    let length: NSUInteger = decoded.len().try_into().unwrap();
+    let alloc = env.mem.alloc(length);
+    env.mem
+        .bytes_at_mut(alloc.cast(), length)
+        .copy_from_slice(&decoded);
+    msg![env; this initWithBytesNoCopy:alloc length:length]
+}
+
 pub fn to_rust_slice(env: &mut Environment, data: id) -> &[u8] {
     let borrowed_data = env.objc.borrow::<NSDataHostObject>(data);
     assert!(!borrowed_data.bytes.is_null() && borrowed_data.length != 0);
@@ -8,6 +8,7 @@
 use super::NSTimeInterval;
 use crate::frameworks::core_foundation::time::apple_epoch;
 use crate::objc::{autorelease, id, objc_classes, ClassExports, HostObject};
+use crate::Environment;
 
 use std::time::SystemTime;
 
@@ -16,11 +17,19 @@ struct NSDateHostObject {
 }
 impl HostObject for NSDateHostObject {}
 
+/// Create a new `NSDate` (not autoreleased) for a time interval relative to
+/// the reference date.
+pub fn from_time_interval(env: &mut Environment, time_interval: NSTimeInterval) -> id {
+    let class = env.objc.get_known_class("NSDate", &mut env.mem);
+    let host_object = Box::new(NSDateHostObject { time_interval });
+    env.objc.alloc_object(class, host_object, &mut env.mem)
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
-@implementation NSDate: NSObject
+@implementation NSDate: NSObject <NSCopying, NSCoding>
 
 + (id)date {
     // "Date objects are immutable, representing an invariant time interval
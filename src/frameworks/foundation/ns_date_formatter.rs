@@ -11,10 +11,14 @@
 
 use crate::frameworks::core_foundation::time::CFAbsoluteTimeGetGregorianDate;
 use crate::frameworks::foundation::{ns_string, NSTimeInterval};
-use crate::objc::{id, msg, nil, objc_classes, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{
+    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+};
 
 struct NSDateFormatterHostObject {
     date_format: Option<id>,
+    /// `NSTimeZone*`, [None] means the default time zone.
+    time_zone: Option<id>,
 }
 impl HostObject for NSDateFormatterHostObject {}
 
@@ -27,6 +31,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = Box::new(NSDateFormatterHostObject {
         date_format: None,
+        time_zone: None,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -36,15 +41,49 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow_mut::<NSDateFormatterHostObject>(this).date_format = Some(date_format);
 }
 
+- (id)timeZone {
+    if let Some(time_zone) = env.objc.borrow::<NSDateFormatterHostObject>(this).time_zone {
+        time_zone
+    } else {
+        msg_class![env; NSTimeZone defaultTimeZone]
+    }
+}
+- (())setTimeZone:(id)time_zone { // NSTimeZone*
+    let time_zone = (time_zone != nil).then(|| retain(env, time_zone));
+    let old = std::mem::replace(
+        &mut env.objc.borrow_mut::<NSDateFormatterHostObject>(this).time_zone,
+        time_zone,
+    );
+    if let Some(old) = old {
+        release(env, old);
+    }
+}
+
+- (())dealloc {
+    let &NSDateFormatterHostObject {
+        date_format,
+        time_zone,
+    } = env.objc.borrow(this);
+    if let Some(date_format) = date_format {
+        release(env, date_format);
+    }
+    if let Some(time_zone) = time_zone {
+        release(env, time_zone);
+    }
+    env.objc.dealloc_object(this, &mut env.mem);
+}
+
 - (id)stringFromDate:(id)date {
     let &NSDateFormatterHostObject {
-        date_format
+        date_format,
+        ..
     } = env.objc.borrow(this);
     let mut format = ns_string::to_rust_string(env, date_format.unwrap()).to_string().clone();
     log_dbg!("date_format before: {:?}", format);
 
     let ti: NSTimeInterval = msg![env; date timeIntervalSinceReferenceDate];
-    let greg_date = CFAbsoluteTimeGetGregorianDate(env, ti, nil);
+    let time_zone: id = msg![env; this timeZone];
+    let greg_date = CFAbsoluteTimeGetGregorianDate(env, ti, time_zone);
     let year = greg_date.year;
     let month = greg_date.month;
     let day = greg_date.day;
@@ -5,10 +5,12 @@
  */
 //! The `NSDictionary` class cluster, including `NSMutableDictionary`.
 
-use super::ns_property_list_serialization::deserialize_plist_from_file;
-use super::{ns_string, ns_url, NSUInteger};
+use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
+use super::ns_property_list_serialization::{deserialize_plist_from_file, serialize_plist_to_file};
+use super::{ns_array, ns_exception, ns_string, ns_url, NSUInteger};
 use crate::abi::VaList;
 use crate::fs::GuestPath;
+use crate::mem::MutPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
     NSZonePtr,
@@ -33,17 +35,24 @@ pub(super) struct DictionaryHostObject {
 }
 impl HostObject for DictionaryHostObject {}
 impl DictionaryHostObject {
-    pub(super) fn lookup(&self, env: &mut Environment, key: id) -> id {
+    /// Find the key-value pair for a key, returning the stored key rather
+    /// than the one passed in.
+    fn find(&self, env: &mut Environment, key: id) -> Option<(id, id)> {
         let hash: Hash = msg![env; key hash];
-        let Some(collisions) = self.map.get(&hash) else {
-            return nil;
-        };
+        let collisions = self.map.get(&hash)?;
         for &(candidate_key, value) in collisions {
             if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
-                return value;
+                return Some((candidate_key, value));
             }
         }
-        nil
+        None
+    }
+    pub(super) fn lookup(&self, env: &mut Environment, key: id) -> id {
+        self.find(env, key).map_or(nil, |(_key, value)| value)
+    }
+    /// Like [Self::lookup], but returns the stored key that is equal to `key`.
+    pub(super) fn lookup_key(&self, env: &mut Environment, key: id) -> id {
+        self.find(env, key).map_or(nil, |(key, _value)| key)
     }
     pub(super) fn insert(&mut self, env: &mut Environment, key: id, value: id, copy_key: bool) {
         let key: id = if copy_key {
@@ -62,6 +71,8 @@ impl DictionaryHostObject {
         };
         for &mut (candidate_key, ref mut existing_value) in collisions.iter_mut() {
             if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
+                // The existing key is kept.
+                release(env, key);
                 release(env, *existing_value);
                 *existing_value = value;
                 return;
@@ -70,6 +81,32 @@ impl DictionaryHostObject {
         collisions.push((key, value));
         self.count += 1;
     }
+    /// Remove a key-value pair, releasing both. Returns [false] if the key
+    /// wasn't present.
+    pub(super) fn remove(&mut self, env: &mut Environment, key: id) -> bool {
+        let hash: Hash = msg![env; key hash];
+        let Some(collisions) = self.map.get_mut(&hash) else {
+            return false;
+        };
+        let mut found = None;
+        for (index, &(candidate_key, _)) in collisions.iter().enumerate() {
+            if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
+                found = Some(index);
+                break;
+            }
+        }
+        let Some(index) = found else {
+            return false;
+        };
+        let (key, value) = collisions.remove(index);
+        if collisions.is_empty() {
+            self.map.remove(&hash);
+        }
+        self.count -= 1;
+        release(env, key);
+        release(env, value);
+        true
+    }
     pub(super) fn release(&mut self, env: &mut Environment) {
         for collisions in self.map.values() {
             for &(key, value) in collisions {
@@ -91,20 +128,23 @@ pub fn init_with_objects_and_keys(
     first_object: id,
     mut va_args: VaList,
 ) -> id {
-    let first_key: id = va_args.next(env);
-    assert!(first_key != nil); // TODO: raise proper exception
-
     let mut host_object = <DictionaryHostObject as Default>::default();
-    host_object.insert(env, first_key, first_object, /* copy_key: */ true);
 
-    loop {
-        let object: id = va_args.next(env);
-        if object == nil {
-            break;
-        }
+    // The list is terminated by a nil object, which may be the first one.
+    let mut object = first_object;
+    while object != nil {
         let key: id = va_args.next(env);
-        assert!(key != nil); // TODO: raise proper exception
+        if key == nil {
+            host_object.release(env);
+            ns_exception::raise(
+                env,
+                "NSInvalidArgumentException",
+                "Second object of each pair must be non-nil".to_string(),
+            );
+            return this;
+        }
         host_object.insert(env, key, object, /* copy_key: */ true);
+        object = va_args.next(env);
     }
 
     *env.objc.borrow_mut(this) = host_object;
@@ -123,7 +163,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 // - (NSEnumerator*)keyEnumerator
 // We can pick whichever subclass we want for the various alloc methods.
 // For the time being, that will always be _touchHLE_NSDictionary.
-@implementation NSDictionary: NSObject
+@implementation NSDictionary: NSObject <NSCopying, NSMutableCopying, NSCoding, NSFastEnumeration>
 
 + (id)allocWithZone:(NSZonePtr)zone {
     // NSDictionary might be subclassed by something which needs allocWithZone:
@@ -186,7 +226,52 @@ pub const CLASSES: ClassExports = objc_classes! {
     retain(env, this)
 }
 
-// TODO
+- (id)allKeys { // NSArray*
+    let keys = all_keys(env, this);
+    for &key in &keys {
+        retain(env, key);
+    }
+    let array = ns_array::from_vec(env, keys);
+    autorelease(env, array)
+}
+- (id)allValues { // NSArray*
+    let keys = all_keys(env, this);
+    let values: Vec<id> = keys
+        .into_iter()
+        .map(|key| {
+            let value: id = msg![env; this objectForKey:key];
+            retain(env, value)
+        })
+        .collect();
+    let array = ns_array::from_vec(env, values);
+    autorelease(env, array)
+}
+
+- (id)objectsForKeys:(id)keys // NSArray*
+      notFoundMarker:(id)marker {
+    let keys = ns_array::to_vec(env, keys);
+    let objects: Vec<id> = keys
+        .into_iter()
+        .map(|key| {
+            let object: id = msg![env; this objectForKey:key];
+            let object = if object == nil { marker } else { object };
+            retain(env, object)
+        })
+        .collect();
+    let array = ns_array::from_vec(env, objects);
+    autorelease(env, array)
+}
+
+// These probably come from some category related to plists.
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)use_aux_file {
+    serialize_plist_to_file(env, this, path, use_aux_file)
+}
+- (bool)writeToURL:(id)url // NSURL*
+        atomically:(bool)use_aux_file {
+    let path: id = msg![env; url path];
+    serialize_plist_to_file(env, this, path, use_aux_file)
+}
 
 @end
 
@@ -214,11 +299,23 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
-// TODO: enumeration, more init methods, etc
+// TODO: more init methods, etc
+
+// NSFastEnumeration implementation
+- (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
+                                  objects:(MutPtr<id>)stackbuf
+                                    count:(NSUInteger)len {
+    let mut iterator = env.objc.borrow::<DictionaryHostObject>(this).iter_keys();
+    fast_enumeration_helper(&mut env.mem, this.cast(), &mut iterator, state, stackbuf, len)
+}
 
 - (NSUInteger)count {
     env.objc.borrow::<DictionaryHostObject>(this).count
 }
+- (id)keyEnumerator { // NSEnumerator*
+    let keys: Vec<id> = env.objc.borrow::<DictionaryHostObject>(this).iter_keys().collect();
+    ns_array::new_object_enumerator(env, keys)
+}
 - (id)objectForKey:(id)key {
     let host_obj: DictionaryHostObject = std::mem::take(env.objc.borrow_mut(this));
     let res = host_obj.lookup(env, key);
@@ -230,6 +327,21 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Get the keys of any dictionary, using `keyEnumerator`. The keys are not
+/// retained.
+fn all_keys(env: &mut Environment, dict: id) -> Vec<id> {
+    let enumerator: id = msg![env; dict keyEnumerator];
+    let mut keys = Vec::new();
+    loop {
+        let key: id = msg![env; enumerator nextObject];
+        if key == nil {
+            break;
+        }
+        keys.push(key);
+    }
+    keys
+}
+
 /// Direct constructor for use by host code, similar to
 /// `[[NSDictionary alloc] initWithObjectsAndKeys:]` but without variadics and
 /// with a more intuitive argument order. Unlike [super::ns_array::from_vec],
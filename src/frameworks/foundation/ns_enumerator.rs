@@ -12,13 +12,33 @@
 //!                                     count:(NSUInteger)len;
 //! ```
 //!
+//! The compiled `for (x in collection)` loop checks the value pointed to by
+//! `mutationsPtr` on every iteration and calls `objc_enumerationMutation()` if
+//! it changed. For mutable collections, that points to a counter in guest
+//! memory that is incremented by [note_mutation]. Immutable collections can
+//! use any pointer that stays valid.
+//!
 //! Resources:
 //! - The GCC documentation's [Fast Enumeration Protocol section](https://gcc.gnu.org/onlinedocs/gcc/Fast-enumeration-protocol.html)
 
+use super::{ns_exception, NSUInteger};
+use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{Mem, MutPtr, MutVoidPtr, SafeRead};
-use crate::objc::{id, objc_classes, ClassExports};
+use crate::objc::{id, msg, objc_classes, ClassExports};
+use crate::Environment;
+use std::collections::HashMap;
 
-use super::NSUInteger;
+#[derive(Default)]
+pub struct State {
+    /// Mutation counters in guest memory for mutable collections that have
+    /// been fast-enumerated, see [mutations_ptr].
+    mutation_counters: HashMap<id, MutPtr<u32>>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut State {
+        &mut env.framework_state.foundation.ns_enumerator
+    }
+}
 
 #[repr(C, packed)]
 pub struct NSFastEnumerationState {
@@ -41,9 +61,41 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Get the `mutationsPtr` for a mutable collection, allocating its counter if
+/// necessary. The collection must call [forget_collection] when deallocated.
+pub fn mutations_ptr(env: &mut Environment, collection: id) -> MutVoidPtr {
+    if let Some(&counter) = State::get(env).mutation_counters.get(&collection) {
+        return counter.cast();
+    }
+    let counter = env.mem.alloc_and_write(0u32);
+    State::get(env)
+        .mutation_counters
+        .insert(collection, counter);
+    counter.cast()
+}
+
+/// For use by mutable collections whenever they are mutated, so that fast
+/// enumeration can detect this.
+pub fn note_mutation(env: &mut Environment, collection: id) {
+    if let Some(&counter) = State::get(env).mutation_counters.get(&collection) {
+        let count = env.mem.read(counter);
+        env.mem.write(counter, count.wrapping_add(1));
+    }
+}
+
+/// For use by mutable collections when they are deallocated.
+pub fn forget_collection(env: &mut Environment, collection: id) {
+    if let Some(counter) = State::get(env).mutation_counters.remove(&collection) {
+        env.mem.free(counter.cast());
+    }
+}
+
+/// Shared implementation of `countByEnumeratingWithState:objects:count:`.
+/// `mutations_ptr` is `this` for immutable collections, or the result of
+/// [mutations_ptr] for mutable ones.
 pub fn fast_enumeration_helper(
     mem: &mut Mem,
-    this: id,
+    mutations_ptr: MutVoidPtr,
     iterator: &mut impl Iterator<Item = id>,
     state: MutPtr<NSFastEnumerationState>,
     stackbuf: MutPtr<id>,
@@ -73,12 +125,28 @@ pub fn fast_enumeration_helper(
             state: start_index + batch_count,
             items_ptr: stackbuf,
             // can be anything as long as it's dereferenceable and the same
-            // each iteration
-            // Note: stackbuf can be different each time, it's better to return
-            // self pointer
-            mutations_ptr: this.cast(),
+            // each iteration, unless the collection is mutated
+            // Note: stackbuf can be different each time, so it can't be used
+            mutations_ptr,
             extra: Default::default(),
         },
     );
     batch_count
 }
+
+/// Called by compiled fast enumeration loops when the collection was mutated.
+/// This is part of the Objective-C runtime, but the behavior is Foundation's.
+fn objc_enumerationMutation(env: &mut Environment, collection: id) {
+    let class: id = msg![env; collection class];
+    let class_name = env.objc.get_class_name(class).to_string();
+    ns_exception::raise(
+        env,
+        "NSGenericException",
+        format!(
+            "*** Collection <{}: {:?}> was mutated while being enumerated.",
+            class_name, collection
+        ),
+    );
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(objc_enumerationMutation(_))];
@@ -3,8 +3,177 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! `NSException` and the uncaught exception handler.
+//!
+//! Throwing and catching exceptions is handled by the Objective-C runtime, see
+//! [crate::objc::exceptions].
 
-use crate::dyld::{ConstantExports, HostConstant};
+use super::ns_string::{from_rust_string, get_static_str, to_rust_string, with_format};
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::libc::stdlib::abort_with_reason;
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
+    HostObject, NSZonePtr,
+};
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    uncaught_exception_handler: Option<GuestFunction>,
+}
+
+struct NSExceptionHostObject {
+    /// `NSString*`
+    name: id,
+    /// `NSString*`
+    reason: id,
+    /// `NSDictionary*`
+    user_info: id,
+}
+impl HostObject for NSExceptionHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSException: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSExceptionHostObject {
+        name: nil,
+        reason: nil,
+        user_info: nil,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)exceptionWithName:(id)name // NSString*
+                 reason:(id)reason // NSString*
+               userInfo:(id)user_info { // NSDictionary*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithName:name reason:reason userInfo:user_info];
+    autorelease(env, new)
+}
+
++ (())raise:(id)name // NSString*
+     format:(id)format, // NSString*
+     ...args {
+    let reason = with_format(env, format, args.start());
+    let reason = from_rust_string(env, reason);
+    let reason = autorelease(env, reason);
+    let exception: id = msg![env; this exceptionWithName:name reason:reason userInfo:nil];
+    () = msg![env; exception raise];
+}
+
+- (id)initWithName:(id)name // NSString*
+            reason:(id)reason // NSString*
+          userInfo:(id)user_info { // NSDictionary*
+    let name: id = msg![env; name copy];
+    let reason: id = msg![env; reason copy];
+    retain(env, user_info);
+    let host_object = env.objc.borrow_mut::<NSExceptionHostObject>(this);
+    host_object.name = name;
+    host_object.reason = reason;
+    host_object.user_info = user_info;
+    this
+}
+
+- (())dealloc {
+    let &NSExceptionHostObject { name, reason, user_info } = env.objc.borrow(this);
+    release(env, name);
+    release(env, reason);
+    release(env, user_info);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)name {
+    env.objc.borrow::<NSExceptionHostObject>(this).name
+}
+- (id)reason {
+    env.objc.borrow::<NSExceptionHostObject>(this).reason
+}
+- (id)userInfo {
+    env.objc.borrow::<NSExceptionHostObject>(this).user_info
+}
+
+- (id)description {
+    env.objc.borrow::<NSExceptionHostObject>(this).reason
+}
+
+- (())raise {
+    crate::objc::objc_exception_throw(env, this);
+}
+
+@end
+
+};
+
+type NSUncaughtExceptionHandler = GuestFunction;
+
+fn NSSetUncaughtExceptionHandler(env: &mut Environment, handler: NSUncaughtExceptionHandler) {
+    env.framework_state
+        .foundation
+        .ns_exception
+        .uncaught_exception_handler = (!handler.to_ptr().is_null()).then_some(handler);
+}
+
+fn NSGetUncaughtExceptionHandler(env: &mut Environment) -> NSUncaughtExceptionHandler {
+    env.framework_state
+        .foundation
+        .ns_exception
+        .uncaught_exception_handler
+        .unwrap_or(GuestFunction::from_addr_with_thumb_bit(0))
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(NSSetUncaughtExceptionHandler(_)),
+    export_c_func!(NSGetUncaughtExceptionHandler()),
+];
+
+/// Shortcut for host code: raise an `NSException` with one of the standard
+/// names, e.g. `"NSInvalidArgumentException"`. This doesn't return: control
+/// goes to a `@catch` block, or the app is terminated.
+pub fn raise(env: &mut Environment, name: &'static str, reason: String) {
+    let name = get_static_str(env, name);
+    let reason = from_rust_string(env, reason);
+    let reason = autorelease(env, reason);
+    let exception: id = msg_class![env; NSException exceptionWithName:name
+                                                               reason:reason
+                                                             userInfo:nil];
+    () = msg![env; exception raise];
+}
+
+/// For use by the Objective-C runtime: call the uncaught exception handler,
+/// if any, then terminate the app, like Apple's implementation does.
+pub fn handle_uncaught_exception(env: &mut Environment, exception: id) -> ! {
+    if let Some(handler) = env
+        .framework_state
+        .foundation
+        .ns_exception
+        .uncaught_exception_handler
+    {
+        () = handler.call_from_host(env, (exception,));
+    }
+
+    let ns_exception_class: Class = msg_class![env; NSException class];
+    let reason = if exception != nil && msg![env; exception isKindOfClass:ns_exception_class] {
+        let name: id = msg![env; exception name];
+        let reason: id = msg![env; exception reason];
+        format!(
+            "Terminating app due to uncaught exception '{}', reason: '{}'",
+            to_rust_string(env, name),
+            to_rust_string(env, reason)
+        )
+    } else {
+        let description: id = msg![env; exception description];
+        format!(
+            "Terminating app due to uncaught exception: {}",
+            to_rust_string(env, description)
+        )
+    };
+    abort_with_reason(env, &reason)
+}
 
 // All constants are NSExceptionName
 pub const CONSTANTS: ConstantExports = &[
@@ -0,0 +1,162 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSHashTable`.
+//!
+//! Weak members are unsafe-unretained, see [super::ns_pointer_functions].
+
+use super::ns_pointer_functions::{
+    NSPointerFunctionsOptions, NSPointerFunctionsStrongMemory, NSPointerFunctionsWeakMemory,
+    PointerFunctions,
+};
+use super::NSUInteger;
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, ClassExports, HostObject, NSZonePtr,
+};
+use crate::Environment;
+
+pub type NSHashTableOptions = NSUInteger;
+pub const NSHashTableStrongMemory: NSHashTableOptions = NSPointerFunctionsStrongMemory;
+pub const NSHashTableWeakMemory: NSHashTableOptions = NSPointerFunctionsWeakMemory;
+
+struct NSHashTableHostObject {
+    functions: PointerFunctions,
+    /// Member and its hash. Since hashing and equality need a
+    /// `&mut Environment`, a `HashSet` can't be used directly.
+    members: Vec<(id, NSUInteger)>,
+}
+impl HostObject for NSHashTableHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSHashTable: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSHashTableHostObject {
+        functions: PointerFunctions::from_options(NSHashTableStrongMemory),
+        members: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)hashTableWithOptions:(NSPointerFunctionsOptions)options {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithOptions:options capacity:0u32];
+    autorelease(env, new)
+}
++ (id)weakObjectsHashTable {
+    msg![env; this hashTableWithOptions:NSHashTableWeakMemory]
+}
+// Deprecated name for the above.
++ (id)hashTableWithWeakObjects {
+    msg![env; this weakObjectsHashTable]
+}
+
+- (id)init {
+    msg![env; this initWithOptions:NSHashTableStrongMemory capacity:0u32]
+}
+- (id)initWithOptions:(NSPointerFunctionsOptions)options
+             capacity:(NSUInteger)_capacity {
+    env.objc.borrow_mut::<NSHashTableHostObject>(this).functions =
+        PointerFunctions::from_options(options);
+    this
+}
+
+- (())dealloc {
+    () = msg![env; this removeAllObjects];
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSUInteger)count {
+    env.objc.borrow::<NSHashTableHostObject>(this).members.len().try_into().unwrap()
+}
+
+- (id)member:(id)object {
+    match find_member(env, this, object) {
+        Some(idx) => env.objc.borrow::<NSHashTableHostObject>(this).members[idx].0,
+        None => nil,
+    }
+}
+- (bool)containsObject:(id)object {
+    find_member(env, this, object).is_some()
+}
+- (id)anyObject {
+    let members = &env.objc.borrow::<NSHashTableHostObject>(this).members;
+    members.first().map_or(nil, |&(member, _)| member)
+}
+
+- (())addObject:(id)object {
+    if object == nil || find_member(env, this, object).is_some() {
+        return;
+    }
+    let functions = env.objc.borrow::<NSHashTableHostObject>(this).functions;
+    let hash = functions.hash(env, object);
+    let object = functions.acquire(env, object);
+    env.objc.borrow_mut::<NSHashTableHostObject>(this).members.push((object, hash));
+}
+
+- (())removeObject:(id)object {
+    let Some(idx) = find_member(env, this, object) else {
+        return;
+    };
+    let host_object = env.objc.borrow_mut::<NSHashTableHostObject>(this);
+    let functions = host_object.functions;
+    let (member, _) = host_object.members.remove(idx);
+    functions.relinquish(env, member);
+}
+
+- (())removeAllObjects {
+    let host_object = env.objc.borrow_mut::<NSHashTableHostObject>(this);
+    let functions = host_object.functions;
+    for (member, _) in std::mem::take(&mut host_object.members) {
+        functions.relinquish(env, member);
+    }
+}
+
+- (id)allObjects {
+    let host_object = env.objc.borrow::<NSHashTableHostObject>(this);
+    let functions = host_object.functions;
+    let members: Vec<id> = host_object.members.iter().map(|&(member, _)| member).collect();
+    functions.to_array(env, members.into_iter())
+}
+- (id)objectEnumerator {
+    let array: id = msg![env; this allObjects];
+    msg![env; array objectEnumerator]
+}
+
+- (id)setRepresentation {
+    let host_object = env.objc.borrow::<NSHashTableHostObject>(this);
+    let members: Vec<id> = host_object.members.iter().map(|&(member, _)| member).collect();
+    let set: id = msg_class![env; NSMutableSet new];
+    for member in members {
+        () = msg![env; set addObject:member];
+    }
+    autorelease(env, set)
+}
+
+@end
+
+};
+
+fn find_member(env: &mut Environment, table: id, object: id) -> Option<usize> {
+    let functions = env.objc.borrow::<NSHashTableHostObject>(table).functions;
+    let hash = functions.hash(env, object);
+    // TODO: avoid copy?
+    let candidates: Vec<(usize, id)> = env
+        .objc
+        .borrow::<NSHashTableHostObject>(table)
+        .members
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(_, candidate_hash))| candidate_hash == hash)
+        .map(|(idx, &(candidate, _))| (idx, candidate))
+        .collect();
+    candidates
+        .into_iter()
+        .find(|&(_, candidate)| functions.is_equal(env, candidate, object))
+        .map(|(idx, _)| idx)
+}
@@ -0,0 +1,412 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSIndexSet` and `NSMutableIndexSet`.
+//!
+//! Unlike most Foundation collections, these aren't class clusters, so there
+//! is just one host object shared by both classes.
+
+use super::{hash_helper, ns_exception, NSInteger, NSNotFound, NSRange, NSUInteger};
+use crate::abi::{CallFromHost, GuestArg};
+use crate::mem::MutPtr;
+use crate::objc::{
+    autorelease, block_invoke_function, id, msg, msg_class, nil, objc_classes, release, retain,
+    Class, ClassExports, HostObject, NSZonePtr,
+};
+use crate::Environment;
+use std::ops::Range;
+
+/// Indexes must be less than this, as in Apple's implementation.
+const INDEX_LIMIT: NSUInteger = NSNotFound as NSUInteger;
+
+#[derive(Clone, Default)]
+struct IndexSetHostObject {
+    /// Sorted, non-overlapping and non-adjacent ranges of indexes.
+    ranges: Vec<Range<NSUInteger>>,
+}
+impl HostObject for IndexSetHostObject {}
+impl IndexSetHostObject {
+    fn count(&self) -> NSUInteger {
+        self.ranges
+            .iter()
+            .map(|range| range.len() as NSUInteger)
+            .sum()
+    }
+    fn count_in_range(&self, within: Range<NSUInteger>) -> NSUInteger {
+        self.ranges
+            .iter()
+            .map(|range| {
+                let start = range.start.max(within.start);
+                let end = range.end.min(within.end);
+                end.saturating_sub(start)
+            })
+            .sum()
+    }
+    fn contains(&self, index: NSUInteger) -> bool {
+        let i = self.ranges.partition_point(|range| range.end <= index);
+        self.ranges.get(i).is_some_and(|range| range.start <= index)
+    }
+    fn contains_range(&self, within: Range<NSUInteger>) -> bool {
+        if within.is_empty() {
+            return false;
+        }
+        let i = self
+            .ranges
+            .partition_point(|range| range.end <= within.start);
+        self.ranges
+            .get(i)
+            .is_some_and(|range| range.start <= within.start && within.end <= range.end)
+    }
+    /// Lowest index that is at least `index`.
+    fn index_at_or_after(&self, index: NSUInteger) -> Option<NSUInteger> {
+        let i = self.ranges.partition_point(|range| range.end <= index);
+        self.ranges.get(i).map(|range| range.start.max(index))
+    }
+    /// Highest index that is at most `index`.
+    fn index_at_or_before(&self, index: NSUInteger) -> Option<NSUInteger> {
+        let i = self.ranges.partition_point(|range| range.start <= index);
+        let range = self.ranges.get(i.checked_sub(1)?)?;
+        Some((range.end - 1).min(index))
+    }
+    fn indexes(&self) -> impl Iterator<Item = NSUInteger> + '_ {
+        self.ranges.iter().flat_map(|range| range.clone())
+    }
+
+    fn add(&mut self, new: Range<NSUInteger>) {
+        if new.is_empty() {
+            return;
+        }
+        // Ranges that overlap or touch the new one are merged with it.
+        let first = self.ranges.partition_point(|range| range.end < new.start);
+        let last = self.ranges.partition_point(|range| range.start <= new.end);
+        let mut merged = new;
+        if first < last {
+            merged.start = merged.start.min(self.ranges[first].start);
+            merged.end = merged.end.max(self.ranges[last - 1].end);
+        }
+        self.ranges.splice(first..last, [merged]);
+    }
+    fn remove(&mut self, old: Range<NSUInteger>) {
+        if old.is_empty() {
+            return;
+        }
+        let first = self.ranges.partition_point(|range| range.end <= old.start);
+        let last = self.ranges.partition_point(|range| range.start < old.end);
+        let mut kept = Vec::new();
+        if first < last {
+            let (start, end) = (self.ranges[first].start, self.ranges[last - 1].end);
+            if start < old.start {
+                kept.push(start..old.start);
+            }
+            if old.end < end {
+                kept.push(old.end..end);
+            }
+        }
+        self.ranges.splice(first..last, kept);
+    }
+    fn shift(&mut self, start: NSUInteger, delta: NSInteger) {
+        let mut moved = self.clone();
+        moved.remove(0..start);
+        self.remove(start..INDEX_LIMIT);
+        // Indexes that get shifted onto are lost.
+        if delta < 0 {
+            self.remove(start.saturating_sub(delta.unsigned_abs())..start);
+        }
+        let shift = |index: NSUInteger| {
+            (i64::from(index) + i64::from(delta)).clamp(0, i64::from(INDEX_LIMIT)) as NSUInteger
+        };
+        for range in moved.ranges {
+            self.add(shift(range.start)..shift(range.end));
+        }
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSIndexSet: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::<IndexSetHostObject>::default();
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)indexSet {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+    autorelease(env, new)
+}
++ (id)indexSetWithIndex:(NSUInteger)index {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithIndex:index];
+    autorelease(env, new)
+}
++ (id)indexSetWithIndexesInRange:(NSRange)range {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithIndexesInRange:range];
+    autorelease(env, new)
+}
++ (id)indexSetWithIndexSet:(id)other { // NSIndexSet*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithIndexSet:other];
+    autorelease(env, new)
+}
+
+- (id)initWithIndex:(NSUInteger)index {
+    msg![env; this initWithIndexesInRange:(NSRange { location: index, length: 1 })]
+}
+- (id)initWithIndexesInRange:(NSRange)range {
+    let Some(range) = check_range(env, range) else {
+        release(env, this);
+        return nil;
+    };
+    env.objc.borrow_mut::<IndexSetHostObject>(this).add(range);
+    this
+}
+- (id)initWithIndexSet:(id)other { // NSIndexSet*
+    if other != nil {
+        let ranges = env.objc.borrow::<IndexSetHostObject>(other).ranges.clone();
+        env.objc.borrow_mut::<IndexSetHostObject>(this).ranges = ranges;
+    }
+    this
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(NSZonePtr)_zone {
+    retain(env, this)
+}
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(NSZonePtr)_zone {
+    let new: id = msg_class![env; NSMutableIndexSet alloc];
+    msg![env; new initWithIndexSet:this]
+}
+
+- (NSUInteger)hash {
+    hash_helper(&env.objc.borrow::<IndexSetHostObject>(this).ranges)
+}
+- (bool)isEqualTo:(id)other {
+    if this == other {
+        return true;
+    }
+    let class: Class = msg_class![env; NSIndexSet class];
+    if !msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    msg![env; this isEqualToIndexSet:other]
+}
+- (bool)isEqualToIndexSet:(id)other { // NSIndexSet*
+    if other == nil {
+        return false;
+    }
+    let a = &env.objc.borrow::<IndexSetHostObject>(this).ranges;
+    let b = &env.objc.borrow::<IndexSetHostObject>(other).ranges;
+    a == b
+}
+
+- (NSUInteger)count {
+    env.objc.borrow::<IndexSetHostObject>(this).count()
+}
+- (NSUInteger)countOfIndexesInRange:(NSRange)range {
+    let Some(range) = check_range(env, range) else {
+        return 0;
+    };
+    env.objc.borrow::<IndexSetHostObject>(this).count_in_range(range)
+}
+
+- (NSUInteger)firstIndex {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    host_object.index_at_or_after(0).unwrap_or(NSNotFound as NSUInteger)
+}
+- (NSUInteger)lastIndex {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    host_object.index_at_or_before(INDEX_LIMIT).unwrap_or(NSNotFound as NSUInteger)
+}
+- (NSUInteger)indexGreaterThanIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    index
+        .checked_add(1)
+        .and_then(|index| host_object.index_at_or_after(index))
+        .unwrap_or(NSNotFound as NSUInteger)
+}
+- (NSUInteger)indexGreaterThanOrEqualToIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    host_object.index_at_or_after(index).unwrap_or(NSNotFound as NSUInteger)
+}
+- (NSUInteger)indexLessThanIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    index
+        .checked_sub(1)
+        .and_then(|index| host_object.index_at_or_before(index))
+        .unwrap_or(NSNotFound as NSUInteger)
+}
+- (NSUInteger)indexLessThanOrEqualToIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    host_object.index_at_or_before(index).unwrap_or(NSNotFound as NSUInteger)
+}
+
+- (NSUInteger)getIndexes:(MutPtr<NSUInteger>)buffer
+                maxCount:(NSUInteger)max_count
+            inIndexRange:(MutPtr<NSRange>)range_ptr {
+    let range = if range_ptr.is_null() {
+        0..INDEX_LIMIT
+    } else {
+        let range = env.mem.read(range_ptr);
+        let Some(range) = check_range(env, range) else {
+            return 0;
+        };
+        range
+    };
+    let indexes: Vec<NSUInteger> = env
+        .objc
+        .borrow::<IndexSetHostObject>(this)
+        .indexes()
+        .skip_while(|&index| index < range.start)
+        .take_while(|&index| index < range.end)
+        .take(max_count as usize)
+        .collect();
+    for (i, &index) in indexes.iter().enumerate() {
+        env.mem.write(buffer + i.try_into().unwrap(), index);
+    }
+    if !range_ptr.is_null() {
+        // The range is updated to cover only the indexes not yet examined.
+        let start = match indexes.last() {
+            Some(&last) if indexes.len() == max_count as usize => last + 1,
+            _ if max_count == 0 => range.start,
+            _ => range.end,
+        };
+        env.mem.write(range_ptr, NSRange { location: start, length: range.end - start });
+    }
+    indexes.len().try_into().unwrap()
+}
+
+- (bool)containsIndex:(NSUInteger)index {
+    env.objc.borrow::<IndexSetHostObject>(this).contains(index)
+}
+- (bool)containsIndexesInRange:(NSRange)range {
+    let Some(range) = check_range(env, range) else {
+        return false;
+    };
+    env.objc.borrow::<IndexSetHostObject>(this).contains_range(range)
+}
+- (bool)containsIndexes:(id)other { // NSIndexSet*
+    let other = env.objc.borrow::<IndexSetHostObject>(other).ranges.clone();
+    let host_object = env.objc.borrow::<IndexSetHostObject>(this);
+    other.into_iter().all(|range| host_object.contains_range(range))
+}
+- (bool)intersectsIndexesInRange:(NSRange)range {
+    let Some(range) = check_range(env, range) else {
+        return false;
+    };
+    env.objc.borrow::<IndexSetHostObject>(this).count_in_range(range) > 0
+}
+
+- (())enumerateIndexesUsingBlock:(id)block { // void (^)(NSUInteger, BOOL*)
+    let indexes: Vec<_> = env.objc.borrow::<IndexSetHostObject>(this).indexes().collect();
+    enumerate_using_block(env, block, indexes);
+}
+- (())enumerateRangesUsingBlock:(id)block { // void (^)(NSRange, BOOL*)
+    let ranges: Vec<_> = env
+        .objc
+        .borrow::<IndexSetHostObject>(this)
+        .ranges
+        .iter()
+        .map(|range| NSRange { location: range.start, length: range.end - range.start })
+        .collect();
+    enumerate_using_block(env, block, ranges);
+}
+
+@end
+
+@implementation NSMutableIndexSet: NSIndexSet
+
+// NSCopying implementation
+- (id)copyWithZone:(NSZonePtr)_zone {
+    let new: id = msg_class![env; NSIndexSet alloc];
+    msg![env; new initWithIndexSet:this]
+}
+
+- (())addIndex:(NSUInteger)index {
+    () = msg![env; this addIndexesInRange:(NSRange { location: index, length: 1 })];
+}
+- (())addIndexesInRange:(NSRange)range {
+    if let Some(range) = check_range(env, range) {
+        env.objc.borrow_mut::<IndexSetHostObject>(this).add(range);
+    }
+}
+- (())addIndexes:(id)other { // NSIndexSet*
+    let other = env.objc.borrow::<IndexSetHostObject>(other).ranges.clone();
+    let host_object = env.objc.borrow_mut::<IndexSetHostObject>(this);
+    for range in other {
+        host_object.add(range);
+    }
+}
+
+- (())removeIndex:(NSUInteger)index {
+    () = msg![env; this removeIndexesInRange:(NSRange { location: index, length: 1 })];
+}
+- (())removeIndexesInRange:(NSRange)range {
+    if let Some(range) = check_range(env, range) {
+        env.objc.borrow_mut::<IndexSetHostObject>(this).remove(range);
+    }
+}
+- (())removeIndexes:(id)other { // NSIndexSet*
+    let other = env.objc.borrow::<IndexSetHostObject>(other).ranges.clone();
+    let host_object = env.objc.borrow_mut::<IndexSetHostObject>(this);
+    for range in other {
+        host_object.remove(range);
+    }
+}
+- (())removeAllIndexes {
+    env.objc.borrow_mut::<IndexSetHostObject>(this).ranges.clear();
+}
+
+- (())shiftIndexesStartingAtIndex:(NSUInteger)index
+                               by:(NSInteger)delta {
+    env.objc.borrow_mut::<IndexSetHostObject>(this).shift(index, delta);
+}
+
+@end
+
+};
+
+/// Check that a range only contains valid indexes. If it doesn't, an exception
+/// is raised and [None] is returned.
+fn check_range(env: &mut Environment, range: NSRange) -> Option<Range<NSUInteger>> {
+    let NSRange { location, length } = range;
+    match location.checked_add(length) {
+        Some(end) if end <= INDEX_LIMIT => Some(location..end),
+        _ => {
+            let reason = format!(
+                "Range {} exceeds maximum index value of NSNotFound - 1",
+                range
+            );
+            ns_exception::raise(env, "NSRangeException", reason);
+            None
+        }
+    }
+}
+
+/// Call a block taking an item and a `BOOL *stop` for each item, until the
+/// block sets `*stop`.
+fn enumerate_using_block<T: GuestArg>(env: &mut Environment, block: id, items: Vec<T>) {
+    let invoke = block_invoke_function(env, block);
+    let stop: MutPtr<bool> = env.mem.alloc_and_write(false);
+    for item in items {
+        () = invoke.call_from_host(env, (block, item, stop));
+        if env.mem.read(stop) {
+            break;
+        }
+    }
+    env.mem.free(stop.cast());
+}
+
+/// Get the indexes of an `NSIndexSet` in ascending order.
+pub fn to_vec(env: &mut Environment, index_set: id) -> Vec<NSUInteger> {
+    env.objc
+        .borrow::<IndexSetHostObject>(index_set)
+        .indexes()
+        .collect()
+}
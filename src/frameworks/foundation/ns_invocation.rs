@@ -0,0 +1,354 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSInvocation` and message forwarding.
+//!
+//! Resources:
+//! - Apple's [Message Forwarding](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtForwarding.html)
+//!   documentation.
+
+use super::ns_method_signature::{get_types, ObjCType};
+use super::NSInteger;
+use crate::abi::{extend_stack_for_args, write_next_arg, GuestArg};
+use crate::cpu::Cpu;
+use crate::mem::{ConstPtr, ConstVoidPtr, MutVoidPtr, Ptr};
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send_prepared, nil, objc_classes, release, retain,
+    ClassExports, HostObject, NSZonePtr, SEL,
+};
+use crate::Environment;
+
+struct NSInvocationHostObject {
+    signature: id,
+    /// The arguments, as they are passed to a method. The first two are the
+    /// target and the selector. Each one is padded to a multiple of 4 bytes.
+    arguments: Vec<Vec<u8>>,
+    return_value: Vec<u8>,
+    arguments_retained: bool,
+}
+impl HostObject for NSInvocationHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSInvocation: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSInvocationHostObject {
+        signature: nil,
+        arguments: Vec::new(),
+        return_value: Vec::new(),
+        arguments_retained: false,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)invocationWithMethodSignature:(id)signature { // NSMethodSignature*
+    let (return_type, argument_types) = get_types(env, signature);
+    let new: id = msg![env; this alloc];
+    retain(env, signature);
+    let host_object = env.objc.borrow_mut::<NSInvocationHostObject>(new);
+    host_object.signature = signature;
+    host_object.arguments = argument_types
+        .iter()
+        .map(|type_| vec![0; (type_.size_in_words() * 4) as usize])
+        .collect();
+    host_object.return_value = vec![0; return_type.size as usize];
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let &NSInvocationHostObject {
+        signature,
+        arguments_retained,
+        ..
+    } = env.objc.borrow(this);
+    if arguments_retained {
+        for object in retained_objects(env, this) {
+            release(env, object);
+        }
+    }
+    release(env, signature);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)methodSignature {
+    env.objc.borrow::<NSInvocationHostObject>(this).signature
+}
+
+- (id)target {
+    object_from_bytes(&env.objc.borrow::<NSInvocationHostObject>(this).arguments[0])
+}
+- (())setTarget:(id)target {
+    set_argument(env, this, 0, &target.to_bits().to_le_bytes());
+}
+
+- (SEL)selector {
+    let bytes = &env.objc.borrow::<NSInvocationHostObject>(this).arguments[1];
+    <SEL as GuestArg>::from_regs(&[u32::from_le_bytes(bytes[..4].try_into().unwrap())])
+}
+- (())setSelector:(SEL)selector {
+    let bytes = &mut env.objc.borrow_mut::<NSInvocationHostObject>(this).arguments[1];
+    bytes.copy_from_slice(&selector.as_ptr().to_bits().to_le_bytes());
+}
+
+- (())getArgument:(MutVoidPtr)buffer
+          atIndex:(NSInteger)index {
+    let (_, argument_types) = get_types(env, env.objc.borrow::<NSInvocationHostObject>(this).signature);
+    let size = argument_types[index as usize].size;
+    let bytes = &env.objc.borrow::<NSInvocationHostObject>(this).arguments[index as usize];
+    env.mem
+        .bytes_at_mut(buffer.cast(), size)
+        .copy_from_slice(&bytes[..size as usize]);
+}
+- (())setArgument:(ConstVoidPtr)buffer
+          atIndex:(NSInteger)index {
+    let signature = env.objc.borrow::<NSInvocationHostObject>(this).signature;
+    let (_, argument_types) = get_types(env, signature);
+    let size = argument_types[index as usize].size;
+    let new_bytes = env.mem.bytes_at(buffer.cast(), size).to_vec();
+    set_argument(env, this, index as usize, &new_bytes);
+}
+
+- (())getReturnValue:(MutVoidPtr)buffer {
+    let return_value = &env.objc.borrow::<NSInvocationHostObject>(this).return_value;
+    let size = return_value.len().try_into().unwrap();
+    env.mem
+        .bytes_at_mut(buffer.cast(), size)
+        .copy_from_slice(return_value);
+}
+- (())setReturnValue:(ConstVoidPtr)buffer {
+    let size = env.objc.borrow::<NSInvocationHostObject>(this).return_value.len();
+    let new_bytes = env.mem.bytes_at(buffer.cast(), size.try_into().unwrap()).to_vec();
+    env.objc.borrow_mut::<NSInvocationHostObject>(this).return_value = new_bytes;
+}
+
+// TODO: Apple's implementation also copies C string arguments.
+- (())retainArguments {
+    if env.objc.borrow::<NSInvocationHostObject>(this).arguments_retained {
+        return;
+    }
+    env.objc.borrow_mut::<NSInvocationHostObject>(this).arguments_retained = true;
+    for object in retained_objects(env, this) {
+        retain(env, object);
+    }
+}
+- (bool)argumentsRetained {
+    env.objc.borrow::<NSInvocationHostObject>(this).arguments_retained
+}
+
+- (())invoke {
+    invoke(env, this);
+}
+- (())invokeWithTarget:(id)target {
+    () = msg![env; this setTarget:target];
+    invoke(env, this);
+}
+
+@end
+
+};
+
+fn object_from_bytes(bytes: &[u8]) -> id {
+    Ptr::from_bits(u32::from_le_bytes(bytes[..4].try_into().unwrap()))
+}
+
+fn set_argument(env: &mut Environment, invocation: id, index: usize, new_bytes: &[u8]) {
+    let &NSInvocationHostObject {
+        signature,
+        arguments_retained,
+        ..
+    } = env.objc.borrow(invocation);
+    let (_, argument_types) = get_types(env, signature);
+
+    let bytes = &mut env
+        .objc
+        .borrow_mut::<NSInvocationHostObject>(invocation)
+        .arguments[index];
+    let old_bytes = bytes.clone();
+    bytes[..new_bytes.len()].copy_from_slice(new_bytes);
+
+    if arguments_retained && argument_types[index].is_object() {
+        retain(env, object_from_bytes(new_bytes));
+        release(env, object_from_bytes(&old_bytes));
+    }
+}
+
+/// Get the arguments of an invocation which are objects, and are therefore
+/// retained by `retainArguments`.
+fn retained_objects(env: &mut Environment, invocation: id) -> Vec<id> {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(invocation);
+    let (_, argument_types) = get_types(env, host_object.signature);
+    argument_types
+        .iter()
+        .zip(host_object.arguments.iter())
+        .filter(|(type_, _)| type_.is_object())
+        .map(|(_, bytes)| object_from_bytes(bytes))
+        .filter(|&object| object != nil)
+        .collect()
+}
+
+fn invoke(env: &mut Environment, invocation: id) {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(invocation);
+    let (return_type, _) = get_types(env, host_object.signature);
+    let mut words: Vec<u32> = host_object
+        .arguments
+        .iter()
+        .flat_map(|bytes| bytes.chunks(4))
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    let stret = return_type.is_returned_in_memory();
+    let return_ptr: MutVoidPtr = if stret {
+        env.mem.alloc(return_type.size)
+    } else {
+        Ptr::null()
+    };
+    if stret {
+        words.insert(0, return_ptr.to_bits());
+    }
+
+    let old_sp = extend_stack_for_args(words.len(), env.cpu.regs_mut());
+    let mut reg_offset = 0;
+    for word in words {
+        write_next_arg(&mut reg_offset, env.cpu.regs_mut(), &mut env.mem, word);
+    }
+    msg_send_prepared(env, stret);
+    env.cpu.regs_mut()[Cpu::SP] = old_sp;
+
+    let return_value = if stret {
+        let bytes = env
+            .mem
+            .bytes_at(return_ptr.cast(), return_type.size)
+            .to_vec();
+        env.mem.free(return_ptr);
+        bytes
+    } else {
+        let regs = env.cpu.regs();
+        let mut bytes = [regs[0].to_le_bytes(), regs[1].to_le_bytes()].concat();
+        bytes.truncate(return_type.size as usize);
+        bytes
+    };
+    env.objc
+        .borrow_mut::<NSInvocationHostObject>(invocation)
+        .return_value = return_value;
+}
+
+/// Shortcut for host code: send a message whose types are only known at
+/// runtime. `arguments` are the bytes of the arguments after the receiver and
+/// selector, and the bytes of the return value are returned.
+pub(super) fn invoke_with_signature(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    signature: id, // NSMethodSignature*
+    arguments: &[&[u8]],
+) -> Vec<u8> {
+    let invocation: id = msg_class![env; NSInvocation invocationWithMethodSignature:signature];
+    () = msg![env; invocation setTarget:receiver];
+    () = msg![env; invocation setSelector:selector];
+    for (i, &bytes) in arguments.iter().enumerate() {
+        set_argument(env, invocation, i + 2, bytes);
+    }
+    invoke(env, invocation);
+    env.objc
+        .borrow::<NSInvocationHostObject>(invocation)
+        .return_value
+        .clone()
+}
+
+/// For use by the Objective-C runtime: forward a message that the receiver
+/// has no method for, using `methodSignatureForSelector:` and
+/// `forwardInvocation:`. Returns `false` if the receiver has no signature for
+/// the selector, i.e. the message can't be forwarded.
+///
+/// `arg_regs` are the values that r0-r3 had when the message was sent, and the
+/// stack pointer must still point to the message's stack arguments.
+pub fn forward_invocation(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    stret: bool,
+    arg_regs: [u32; 4],
+) -> bool {
+    let stack_args: ConstPtr<u32> = Ptr::from_bits(env.cpu.regs()[Cpu::SP]);
+
+    let mem = &env.mem;
+    if !env
+        .objc
+        .object_has_method_named(mem, receiver, "methodSignatureForSelector:")
+        || !env
+            .objc
+            .object_has_method_named(mem, receiver, "forwardInvocation:")
+    {
+        return false;
+    }
+    let signature: id = msg![env; receiver methodSignatureForSelector:selector];
+    if signature == nil {
+        return false;
+    }
+
+    let (return_type, argument_types) = get_types(env, signature);
+    if return_type.is_returned_in_memory() != stret {
+        panic!(
+            "Return type {:?} of forwarded message {:?} doesn't match how it was sent",
+            return_type.encoding,
+            selector.as_str(&env.mem),
+        );
+    }
+
+    // Collect the arguments from the registers and the stack.
+    let mut word_index = usize::from(stret);
+    let mut read_argument = |type_: &ObjCType| -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for _ in 0..type_.size_in_words() {
+            let word = if word_index < 4 {
+                arg_regs[word_index]
+            } else {
+                env.mem
+                    .read(stack_args + u32::try_from(word_index - 4).unwrap())
+            };
+            bytes.extend_from_slice(&word.to_le_bytes());
+            word_index += 1;
+        }
+        bytes
+    };
+    let arguments: Vec<Vec<u8>> = argument_types.iter().map(&mut read_argument).collect();
+
+    let invocation: id = msg_class![env; NSInvocation invocationWithMethodSignature:signature];
+    env.objc
+        .borrow_mut::<NSInvocationHostObject>(invocation)
+        .arguments = arguments;
+
+    log_dbg!(
+        "Forwarding {:?} to [{:?} forwardInvocation:{:?}]",
+        selector.as_str(&env.mem),
+        receiver,
+        invocation
+    );
+    () = msg![env; receiver forwardInvocation:invocation];
+
+    // Return whatever the invocation returned, as if it was returned by the
+    // original message.
+    let return_value = env
+        .objc
+        .borrow::<NSInvocationHostObject>(invocation)
+        .return_value
+        .clone();
+    if stret {
+        let return_ptr: ConstPtr<u8> = Ptr::from_bits(arg_regs[0]);
+        env.mem
+            .bytes_at_mut(return_ptr.cast_mut(), return_type.size)
+            .copy_from_slice(&return_value);
+    } else {
+        let mut words = [0u8; 8];
+        words[..return_value.len()].copy_from_slice(&return_value);
+        let regs = env.cpu.regs_mut();
+        regs[0] = u32::from_le_bytes(words[..4].try_into().unwrap());
+        regs[1] = u32::from_le_bytes(words[4..].try_into().unwrap());
+    }
+    true
+}
@@ -4,20 +4,38 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `NSLocale`.
+//!
+//! The preferred languages and the current locale's region can be overridden
+//! with the `--preferred-languages=` and `--region=` options.
 
-use super::{ns_array, ns_string};
+use super::{ns_array, ns_string, NSUInteger};
 use crate::dyld::{ConstantExports, HostConstant};
-use crate::objc::{id, objc_classes, ClassExports, HostObject};
+use crate::frameworks::core_foundation::cf_locale::{
+    kCFLocaleCountryCode, kCFLocaleIdentifier, kCFLocaleLanguageCode,
+};
+use crate::objc::{id, msg, nil, objc_classes, ClassExports, HostObject};
 use crate::options::Options;
 use crate::Environment;
 use std::ffi::CStr;
 
-const NSLocaleCountryCode: &str = "NSLocaleCountryCode";
-
-pub const CONSTANTS: ConstantExports = &[(
-    "_NSLocaleCountryCode",
-    HostConstant::NSString(NSLocaleCountryCode),
-)];
+const NSLocaleIdentifier: &str = kCFLocaleIdentifier;
+const NSLocaleLanguageCode: &str = kCFLocaleLanguageCode;
+const NSLocaleCountryCode: &str = kCFLocaleCountryCode;
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_NSLocaleIdentifier",
+        HostConstant::NSString(NSLocaleIdentifier),
+    ),
+    (
+        "_NSLocaleLanguageCode",
+        HostConstant::NSString(NSLocaleLanguageCode),
+    ),
+    (
+        "_NSLocaleCountryCode",
+        HostConstant::NSString(NSLocaleCountryCode),
+    ),
+];
 
 #[derive(Default)]
 pub struct State {
@@ -74,7 +92,12 @@ fn get_preferred_languages(options: &Options) -> Vec<String> {
     }
 }
 
-fn get_preferred_countries() -> Vec<String> {
+fn get_preferred_countries(options: &Options) -> Vec<String> {
+    if let Some(ref region) = options.region {
+        log!("The app requested your current locale. {:?} will be reported based on your --region= option.", region);
+        return vec![region.clone()];
+    }
+
     // Unfortunately Rust-SDL2 doesn't provide a wrapper for this yet.
     let countries = unsafe {
         let mut countries = Vec::new();
@@ -108,6 +131,11 @@ fn get_preferred_countries() -> Vec<String> {
 }
 
 struct NSLocaleHostObject {
+    /// `NSString*`
+    locale_identifier: id,
+    /// `NSString*`
+    language_code: id,
+    /// `NSString*`
     country_code: id,
 }
 impl HostObject for NSLocaleHostObject {}
@@ -138,10 +166,23 @@ pub const CLASSES: ClassExports = objc_classes! {
     if let Some(locale) = State::get(env).current_locale {
         locale
     } else {
-        let countries = get_preferred_countries();
-        let country_code = ns_string::from_rust_string(env, countries[0].clone());
+        // The language part of the locale follows the most preferred language,
+        // so that it's consistent with what `preferredLanguages` reports.
+        let langs: id = msg![env; this preferredLanguages];
+        let lang: id = msg![env; langs objectAtIndex:(0 as NSUInteger)];
+        let lang = ns_string::to_rust_string(env, lang);
+        // Strip any region or script suffix, e.g. "en-GB" => "en".
+        let lang = lang.split(['-', '_']).next().unwrap().to_string();
+        let countries = get_preferred_countries(&env.options);
+        let country = countries[0].clone();
+
+        let locale_identifier = ns_string::from_rust_string(env, format!("{}_{}", lang, country));
+        let language_code = ns_string::from_rust_string(env, lang);
+        let country_code = ns_string::from_rust_string(env, country);
         let host_object = NSLocaleHostObject {
-            country_code
+            locale_identifier,
+            language_code,
+            country_code,
         };
         let new_locale = env.objc.alloc_object(
             this,
@@ -153,16 +194,28 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
 }
 
++ (id)autoupdatingCurrentLocale {
+    // The locale can't change while the app is running.
+    msg![env; this currentLocale]
+}
+
 // TODO: constructors, more accessors
 
+- (id)localeIdentifier {
+    env.objc.borrow::<NSLocaleHostObject>(this).locale_identifier
+}
+
 - (id)objectForKey:(id)key {
     let key_str: &str = &ns_string::to_rust_string(env, key);
+    let host_object = env.objc.borrow::<NSLocaleHostObject>(this);
     match key_str {
-        NSLocaleCountryCode => {
-            let &NSLocaleHostObject { country_code } = env.objc.borrow(this);
-            country_code
-        },
-        _ => unimplemented!()
+        NSLocaleIdentifier => host_object.locale_identifier,
+        NSLocaleLanguageCode => host_object.language_code,
+        NSLocaleCountryCode => host_object.country_code,
+        _ => {
+            log!("TODO: [(NSLocale*){:?} objectForKey:{:?}], returning nil", this, key_str);
+            nil
+        }
     }
 }
 
@@ -4,6 +4,7 @@ use super::ns_string;
 use crate::abi::DotDotDot;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::stdio::printf::printf_inner;
+use crate::libc::syslog;
 use crate::objc::id;
 use crate::Environment;
 
@@ -28,13 +29,9 @@ fn NSLog(
         },
         args.start(),
     );
-    // TODO: Should we include a timestamp, like the real NSLog?
-    echo!(
-        "{}[{}] {}",
-        env.bundle.executable_path().file_name().unwrap(),
-        env.current_thread,
-        String::from_utf8_lossy(&res)
-    );
+    // NSLog() writes to the system log on a real device.
+    let process_name = syslog::process_name(env);
+    syslog::log_app_message(env, &process_name, None, &res);
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(NSLog(_, _))];
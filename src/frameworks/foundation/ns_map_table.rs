@@ -0,0 +1,207 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSMapTable`.
+//!
+//! Weak keys and values are unsafe-unretained, see
+//! [super::ns_pointer_functions].
+
+use super::ns_dictionary::dict_from_keys_and_objects;
+use super::ns_pointer_functions::{
+    NSPointerFunctionsCopyIn, NSPointerFunctionsOptions, NSPointerFunctionsStrongMemory,
+    NSPointerFunctionsWeakMemory, PointerFunctions,
+};
+use super::NSUInteger;
+use crate::objc::{autorelease, id, msg, nil, objc_classes, ClassExports, HostObject, NSZonePtr};
+use crate::Environment;
+
+pub type NSMapTableOptions = NSUInteger;
+pub const NSMapTableStrongMemory: NSMapTableOptions = NSPointerFunctionsStrongMemory;
+pub const NSMapTableCopyIn: NSMapTableOptions = NSPointerFunctionsCopyIn;
+pub const NSMapTableWeakMemory: NSMapTableOptions = NSPointerFunctionsWeakMemory;
+
+struct NSMapTableHostObject {
+    key_functions: PointerFunctions,
+    value_functions: PointerFunctions,
+    /// Key, hash of key, value. Since hashing and equality need a
+    /// `&mut Environment`, a `HashMap` can't be used directly.
+    entries: Vec<(id, NSUInteger, id)>,
+}
+impl HostObject for NSMapTableHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSMapTable: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSMapTableHostObject {
+        key_functions: PointerFunctions::from_options(NSMapTableStrongMemory),
+        value_functions: PointerFunctions::from_options(NSMapTableStrongMemory),
+        entries: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)mapTableWithKeyOptions:(NSPointerFunctionsOptions)key_options
+                valueOptions:(NSPointerFunctionsOptions)value_options {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithKeyOptions:key_options
+                                      valueOptions:value_options
+                                          capacity:0u32];
+    autorelease(env, new)
+}
++ (id)strongToStrongObjectsMapTable {
+    msg![env; this mapTableWithKeyOptions:NSMapTableStrongMemory
+                             valueOptions:NSMapTableStrongMemory]
+}
++ (id)weakToStrongObjectsMapTable {
+    msg![env; this mapTableWithKeyOptions:NSMapTableWeakMemory
+                             valueOptions:NSMapTableStrongMemory]
+}
++ (id)strongToWeakObjectsMapTable {
+    msg![env; this mapTableWithKeyOptions:NSMapTableStrongMemory
+                             valueOptions:NSMapTableWeakMemory]
+}
++ (id)weakToWeakObjectsMapTable {
+    msg![env; this mapTableWithKeyOptions:NSMapTableWeakMemory
+                             valueOptions:NSMapTableWeakMemory]
+}
+// Deprecated names for the above. The "strong" ones copy their keys.
++ (id)mapTableWithStrongToStrongObjects {
+    msg![env; this mapTableWithKeyOptions:NSMapTableCopyIn
+                             valueOptions:NSMapTableStrongMemory]
+}
++ (id)mapTableWithWeakToStrongObjects {
+    msg![env; this weakToStrongObjectsMapTable]
+}
++ (id)mapTableWithStrongToWeakObjects {
+    msg![env; this mapTableWithKeyOptions:NSMapTableCopyIn
+                             valueOptions:NSMapTableWeakMemory]
+}
++ (id)mapTableWithWeakToWeakObjects {
+    msg![env; this weakToWeakObjectsMapTable]
+}
+
+- (id)init {
+    msg![env; this initWithKeyOptions:NSMapTableStrongMemory
+                         valueOptions:NSMapTableStrongMemory
+                             capacity:0u32]
+}
+- (id)initWithKeyOptions:(NSPointerFunctionsOptions)key_options
+            valueOptions:(NSPointerFunctionsOptions)value_options
+                capacity:(NSUInteger)_capacity {
+    let host_object = env.objc.borrow_mut::<NSMapTableHostObject>(this);
+    host_object.key_functions = PointerFunctions::from_options(key_options);
+    host_object.value_functions = PointerFunctions::from_options(value_options);
+    this
+}
+
+- (())dealloc {
+    () = msg![env; this removeAllObjects];
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSUInteger)count {
+    env.objc.borrow::<NSMapTableHostObject>(this).entries.len().try_into().unwrap()
+}
+
+- (id)objectForKey:(id)key {
+    match find_entry(env, this, key) {
+        Some(idx) => env.objc.borrow::<NSMapTableHostObject>(this).entries[idx].2,
+        None => nil,
+    }
+}
+
+- (())setObject:(id)object
+         forKey:(id)key {
+    if object == nil {
+        // Apple's implementation seems to treat this as a removal.
+        return msg![env; this removeObjectForKey:key];
+    }
+    let &NSMapTableHostObject { key_functions, value_functions, .. } = env.objc.borrow(this);
+    let object = value_functions.acquire(env, object);
+    if let Some(idx) = find_entry(env, this, key) {
+        let entries = &mut env.objc.borrow_mut::<NSMapTableHostObject>(this).entries;
+        let old = std::mem::replace(&mut entries[idx].2, object);
+        value_functions.relinquish(env, old);
+    } else {
+        let hash = key_functions.hash(env, key);
+        let key = key_functions.acquire(env, key);
+        let entries = &mut env.objc.borrow_mut::<NSMapTableHostObject>(this).entries;
+        entries.push((key, hash, object));
+    }
+}
+
+- (())removeObjectForKey:(id)key {
+    let Some(idx) = find_entry(env, this, key) else {
+        return;
+    };
+    let host_object = env.objc.borrow_mut::<NSMapTableHostObject>(this);
+    let &mut NSMapTableHostObject { key_functions, value_functions, .. } = host_object;
+    let (key, _, value) = host_object.entries.remove(idx);
+    key_functions.relinquish(env, key);
+    value_functions.relinquish(env, value);
+}
+
+- (())removeAllObjects {
+    let host_object = env.objc.borrow_mut::<NSMapTableHostObject>(this);
+    let &mut NSMapTableHostObject { key_functions, value_functions, .. } = host_object;
+    for (key, _, value) in std::mem::take(&mut host_object.entries) {
+        key_functions.relinquish(env, key);
+        value_functions.relinquish(env, value);
+    }
+}
+
+- (id)keyEnumerator {
+    let host_object = env.objc.borrow::<NSMapTableHostObject>(this);
+    let key_functions = host_object.key_functions;
+    let keys: Vec<id> = host_object.entries.iter().map(|&(key, _, _)| key).collect();
+    let array = key_functions.to_array(env, keys.into_iter());
+    msg![env; array objectEnumerator]
+}
+- (id)objectEnumerator {
+    let host_object = env.objc.borrow::<NSMapTableHostObject>(this);
+    let value_functions = host_object.value_functions;
+    let values: Vec<id> = host_object.entries.iter().map(|&(_, _, value)| value).collect();
+    let array = value_functions.to_array(env, values.into_iter());
+    msg![env; array objectEnumerator]
+}
+
+- (id)dictionaryRepresentation {
+    let keys_and_objects: Vec<(id, id)> = env
+        .objc
+        .borrow::<NSMapTableHostObject>(this)
+        .entries
+        .iter()
+        .map(|&(key, _, value)| (key, value))
+        .collect();
+    let dict = dict_from_keys_and_objects(env, &keys_and_objects);
+    autorelease(env, dict)
+}
+
+@end
+
+};
+
+fn find_entry(env: &mut Environment, table: id, key: id) -> Option<usize> {
+    let key_functions = env.objc.borrow::<NSMapTableHostObject>(table).key_functions;
+    let hash = key_functions.hash(env, key);
+    // TODO: avoid copy?
+    let candidates: Vec<(usize, id)> = env
+        .objc
+        .borrow::<NSMapTableHostObject>(table)
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(_, candidate_hash, _))| candidate_hash == hash)
+        .map(|(idx, &(candidate_key, _, _))| (idx, candidate_key))
+        .collect();
+    candidates
+        .into_iter()
+        .find(|&(_, candidate_key)| key_functions.is_equal(env, candidate_key, key))
+        .map(|(idx, _)| idx)
+}
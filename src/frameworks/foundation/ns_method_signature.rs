@@ -0,0 +1,380 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSMethodSignature`.
+//!
+//! Resources:
+//! - Apple's [Type Encodings](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtTypeEncodings.html)
+//!   documentation.
+//! - Apple's [iOS ABI Function Call Guide](https://developer.apple.com/library/archive/documentation/Xcode/Conceptual/iPhoneOSABIReference/Articles/ARMv6FunctionCallingConventions.html),
+//!   which explains the type sizes and alignments. Notably, 64-bit types are
+//!   only 4-byte aligned.
+
+use super::{NSRange, NSUInteger};
+use crate::frameworks::core_graphics::cg_affine_transform::CGAffineTransform;
+use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::mem::{ConstPtr, GuestUSize};
+use crate::objc::{autorelease, id, nil, objc_classes, Class, ClassExports, HostObject, SEL};
+use crate::Environment;
+use std::any::TypeId;
+
+/// The type of a method's return value or one of its arguments.
+#[derive(Clone)]
+pub(super) struct ObjCType {
+    /// The type encoding, including any qualifiers like `r` (const).
+    pub(super) encoding: String,
+    pub(super) size: GuestUSize,
+}
+impl ObjCType {
+    /// The type encoding without qualifiers.
+    pub(super) fn unqualified(&self) -> &str {
+        self.encoding.trim_start_matches(QUALIFIERS)
+    }
+
+    /// Whether this is an Objective-C object (including a class or block).
+    pub(super) fn is_object(&self) -> bool {
+        matches!(self.unqualified().as_bytes().first(), Some(b'@' | b'#'))
+    }
+
+    /// Whether a return value of this type is returned via a pointer passed
+    /// as an implicit first argument, rather than in registers. See
+    /// [crate::abi::GuestRet::SIZE_IN_MEM].
+    pub(super) fn is_returned_in_memory(&self) -> bool {
+        matches!(
+            self.unqualified().as_bytes().first(),
+            Some(b'{' | b'(' | b'[')
+        ) && self.size > 4
+    }
+
+    /// The size of this type when passed as an argument, which is always a
+    /// multiple of 4 bytes.
+    pub(super) fn size_in_words(&self) -> GuestUSize {
+        self.size.div_ceil(4)
+    }
+}
+
+const QUALIFIERS: &[char] = &['r', 'n', 'N', 'o', 'O', 'R', 'V'];
+
+struct TypeParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl TypeParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+    fn skip_while(&mut self, f: impl Fn(u8) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+    }
+    fn number(&mut self) -> Option<GuestUSize> {
+        let start = self.pos;
+        self.skip_while(|c| c.is_ascii_digit());
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// Parse a single type, without qualifiers, and return its size and
+    /// alignment.
+    fn parse_type(&mut self) -> Option<(GuestUSize, GuestUSize)> {
+        Some(match self.next()? {
+            b'c' | b'C' | b'B' => (1, 1),
+            b's' | b'S' => (2, 2),
+            b'i' | b'I' | b'l' | b'L' | b'f' | b'*' | b'#' | b':' | b'?' => (4, 4),
+            b'q' | b'Q' | b'd' => (8, 4),
+            b'v' => (0, 1),
+            b'@' => {
+                match self.peek() {
+                    // Block
+                    Some(b'?') => self.pos += 1,
+                    // Class name
+                    Some(b'"') => {
+                        self.pos += 1;
+                        self.skip_while(|c| c != b'"');
+                        self.next()?;
+                    }
+                    _ => (),
+                }
+                (4, 4)
+            }
+            b'^' => {
+                self.skip_while(|c| QUALIFIERS.contains(&char::from(c)));
+                self.parse_type()?;
+                (4, 4)
+            }
+            b'[' => {
+                let count = self.number()?;
+                let (size, align) = self.parse_type()?;
+                if self.next()? != b']' {
+                    return None;
+                }
+                (count * size, align)
+            }
+            open @ (b'{' | b'(') => {
+                let close = if open == b'{' { b'}' } else { b')' };
+                self.skip_while(|c| c != b'=' && c != close);
+                let mut size: GuestUSize = 0;
+                let mut align = 1;
+                if self.next()? == b'=' {
+                    while self.peek()? != close {
+                        // Field name
+                        if self.peek() == Some(b'"') {
+                            self.pos += 1;
+                            self.skip_while(|c| c != b'"');
+                            self.next()?;
+                        }
+                        let (field_size, field_align) = self.parse_type()?;
+                        align = align.max(field_align);
+                        size = if open == b'{' {
+                            size.next_multiple_of(field_align) + field_size
+                        } else {
+                            size.max(field_size)
+                        };
+                    }
+                    self.next()?;
+                }
+                (size.next_multiple_of(align), align)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Parse a type from a method type string, including its qualifiers and
+    /// the offset that follows it.
+    fn parse_method_type(&mut self) -> Option<ObjCType> {
+        let start = self.pos;
+        self.skip_while(|c| QUALIFIERS.contains(&char::from(c)));
+        let (size, _align) = self.parse_type()?;
+        let encoding = std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()?
+            .to_string();
+        // Offset (sometimes negative, or prefixed with + in old binaries)
+        if matches!(self.peek(), Some(b'-' | b'+')) {
+            self.pos += 1;
+        }
+        self.skip_while(|c| c.is_ascii_digit());
+        Some(ObjCType { encoding, size })
+    }
+}
+
+/// Get the size of the single type described by an encoding, which may have
+/// qualifiers. Returns [None] if the encoding is malformed.
+pub(super) fn type_size(encoding: &str) -> Option<GuestUSize> {
+    let mut parser = TypeParser {
+        bytes: encoding.as_bytes(),
+        pos: 0,
+    };
+    parser.skip_while(|c| QUALIFIERS.contains(&char::from(c)));
+    let (size, _align) = parser.parse_type()?;
+    parser.peek().is_none().then_some(size)
+}
+
+/// Parse a method type string into the return type and argument types.
+fn parse_method_types(types: &[u8]) -> Option<(ObjCType, Vec<ObjCType>)> {
+    let mut parser = TypeParser {
+        bytes: types,
+        pos: 0,
+    };
+    let return_type = parser.parse_method_type()?;
+    let mut argument_types = Vec::new();
+    while parser.peek().is_some() {
+        argument_types.push(parser.parse_method_type()?);
+    }
+    // There must be at least a receiver and a selector.
+    if argument_types.len() < 2 {
+        return None;
+    }
+    Some((return_type, argument_types))
+}
+
+struct NSMethodSignatureHostObject {
+    return_type: ObjCType,
+    argument_types: Vec<ObjCType>,
+    /// C strings for `methodReturnType` and `getArgumentTypeAtIndex:`, created
+    /// on demand.
+    cstrs: Vec<Option<ConstPtr<u8>>>,
+}
+impl HostObject for NSMethodSignatureHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSMethodSignature: NSObject
+
++ (id)signatureWithObjCTypes:(ConstPtr<u8>)types {
+    let types = env.mem.cstr_at(types).to_vec();
+    let new = new_signature(env, this, &types);
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let host_object = env.objc.borrow_mut::<NSMethodSignatureHostObject>(this);
+    for cstr in std::mem::take(&mut host_object.cstrs).into_iter().flatten() {
+        env.mem.free(cstr.cast_void().cast_mut());
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSUInteger)numberOfArguments {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    host_object.argument_types.len().try_into().unwrap()
+}
+
+- (ConstPtr<u8>)getArgumentTypeAtIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    assert!((index as usize) < host_object.argument_types.len());
+    get_type_cstr(env, this, 1 + index as usize)
+}
+
+- (ConstPtr<u8>)methodReturnType {
+    get_type_cstr(env, this, 0)
+}
+
+- (NSUInteger)methodReturnLength {
+    env.objc.borrow::<NSMethodSignatureHostObject>(this).return_type.size
+}
+
+- (NSUInteger)frameLength {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    host_object
+        .argument_types
+        .iter()
+        .map(|type_| type_.size_in_words() * 4)
+        .sum()
+}
+
+- (bool)isOneway {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    let return_type = &host_object.return_type;
+    let qualifiers_len = return_type.encoding.len() - return_type.unqualified().len();
+    return_type.encoding[..qualifiers_len].contains('V')
+}
+
+@end
+
+};
+
+fn new_signature(env: &mut Environment, class: Class, types: &[u8]) -> id {
+    let Some((return_type, argument_types)) = parse_method_types(types) else {
+        panic!(
+            "Unsupported method type string {:?}",
+            String::from_utf8_lossy(types)
+        );
+    };
+    let host_object = Box::new(NSMethodSignatureHostObject {
+        cstrs: vec![None; 1 + argument_types.len()],
+        return_type,
+        argument_types,
+    });
+    env.objc.alloc_object(class, host_object, &mut env.mem)
+}
+
+/// Get a type's encoding as a C string. Index 0 is the return type, and the
+/// argument types follow it.
+fn get_type_cstr(env: &mut Environment, signature: id, index: usize) -> ConstPtr<u8> {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(signature);
+    if let Some(cstr) = host_object.cstrs[index] {
+        return cstr;
+    }
+    let type_ = if index == 0 {
+        &host_object.return_type
+    } else {
+        &host_object.argument_types[index - 1]
+    };
+    let cstr = env
+        .mem
+        .alloc_and_write_cstr(type_.encoding.as_bytes())
+        .cast_const();
+    env.objc
+        .borrow_mut::<NSMethodSignatureHostObject>(signature)
+        .cstrs[index] = Some(cstr);
+    cstr
+}
+
+/// Get the return type and argument types of a signature.
+pub(super) fn get_types(env: &Environment, signature: id) -> (ObjCType, Vec<ObjCType>) {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(signature);
+    (
+        host_object.return_type.clone(),
+        host_object.argument_types.clone(),
+    )
+}
+
+/// Get the type string for a host method that is a getter or setter of a
+/// common type, given the [TypeId] of its Rust signature (see
+/// [crate::objc::HostIMP::type_info]). This is what key-value coding needs.
+fn host_accessor_types(type_id: TypeId) -> Option<String> {
+    macro_rules! accessor_types {
+        ($($type:ty => $encoding:literal),* $(,)?) => {
+            $(
+                if type_id == TypeId::of::<($type, (id, SEL))>() {
+                    return Some(format!("{}@:", $encoding));
+                }
+                if type_id == TypeId::of::<((), (id, SEL, $type))>() {
+                    return Some(format!("v@:{}", $encoding));
+                }
+            )*
+        };
+    }
+    accessor_types!(
+        id => "@",
+        bool => "c", // BOOL is a signed char
+        i32 => "i",
+        u32 => "I",
+        i64 => "q",
+        u64 => "Q",
+        f32 => "f",
+        f64 => "d",
+        CGPoint => "{CGPoint=ff}",
+        CGSize => "{CGSize=ff}",
+        CGRect => "{CGRect={CGPoint=ff}{CGSize=ff}}",
+        CGAffineTransform => "{CGAffineTransform=ffffff}",
+        NSRange => "{_NSRange=II}",
+    );
+    None
+}
+
+/// Get the signature of the method that instances of a class have for a
+/// selector, or [nil] if there is no such method. This is the implementation
+/// of `methodSignatureForSelector:` and friends.
+pub(super) fn signature_for_method(env: &mut Environment, class: Class, sel: SEL) -> id {
+    if sel.is_null() || !env.objc.class_has_method(class, sel) {
+        return nil;
+    }
+    let types = match env.objc.class_method_types(class, sel) {
+        Some(types) => env.mem.cstr_at(types).to_vec(),
+        None => {
+            // Host methods don't have type strings, but accessors can be
+            // recognized from their Rust types. Otherwise, most methods that
+            // are forwarded take and return objects, so that's the best guess.
+            let types = env
+                .objc
+                .class_host_method_type_id(class, sel)
+                .and_then(host_accessor_types)
+                .unwrap_or_else(|| {
+                    let arg_count = sel.as_str(&env.mem).matches(':').count();
+                    format!("@@:{}", "@".repeat(arg_count))
+                });
+            log_dbg!(
+                "Guessing type string {:?} for host method {:?}",
+                types,
+                sel.as_str(&env.mem)
+            );
+            types.into_bytes()
+        }
+    };
+    let signature_class = env.objc.get_known_class("NSMethodSignature", &mut env.mem);
+    let new = new_signature(env, signature_class, &types);
+    autorelease(env, new)
+}
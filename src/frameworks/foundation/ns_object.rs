@@ -10,23 +10,33 @@
 //!   explains how reference counting works. Note that we are interested in what
 //!   it calls "manual retain-release", not ARC.
 //! - Apple's [Key-Value Coding Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/KeyValueCoding/SearchImplementation.html)
-//!   explains the algorithm `setValue:forKey:` should follow.
+//!   explains the algorithms `valueForKey:` and `setValue:forKey:` should
+//!   follow. When there's no accessor method, ivars are accessed directly, and
+//!   scalar values are boxed in `NSNumber` or `NSValue`.
 //!
 //! See also: [crate::objc], especially the `objects` module.
 
-use super::ns_string::to_rust_string;
-use super::NSUInteger;
-use crate::mem::MutVoidPtr;
+use super::ns_exception;
+use super::ns_invocation::invoke_with_signature;
+use super::ns_method_signature::{get_types, signature_for_method, ObjCType};
+use super::ns_run_loop;
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::ns_value::{box_value, unbox_value};
+use super::{NSTimeInterval, NSUInteger};
+use crate::mem::{MutVoidPtr, Ptr};
 use crate::objc::{
-    id, msg, msg_class, msg_send, objc_classes, Class, ClassExports, NSZonePtr, ObjC,
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release,
+    release_associated_objects_of_deallocated, retain, Class, ClassExports, NSZonePtr, ObjC,
     TrivialHostObject, SEL,
 };
+use crate::Environment;
+use std::time::Duration;
 
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
-@implementation NSObject
+@implementation NSObject <NSObject>
 
 + (id)alloc {
     msg![env; this allocWithZone:(MutVoidPtr::null())]
@@ -56,10 +66,53 @@ pub const CLASSES: ClassExports = objc_classes! {
     // classes are not refcounted
 }
 
++ (bool)conformsToProtocol:(id)protocol {
+    env.objc.class_conforms_to_protocol(this, protocol)
+}
+
++ (bool)accessInstanceVariablesDirectly {
+    true
+}
+
 + (bool)instancesRespondToSelector:(SEL)selector {
     env.objc.class_has_method(this, selector)
 }
 
++ (id)instanceMethodSignatureForSelector:(SEL)selector {
+    signature_for_method(env, this, selector)
+}
+
+// Message forwarding (see ns_invocation.rs)
++ (id)methodSignatureForSelector:(SEL)selector {
+    let metaclass = ObjC::read_isa(this, &env.mem);
+    signature_for_method(env, metaclass, selector)
+}
++ (id)forwardingTargetForSelector:(SEL)_selector {
+    nil
+}
++ (())forwardInvocation:(id)invocation { // NSInvocation*
+    let selector: SEL = msg![env; invocation selector];
+    msg![env; this doesNotRecognizeSelector:selector]
+}
++ (())doesNotRecognizeSelector:(SEL)selector {
+    panic!(
+        "Class {:?} does not recognize selector \"{}\"!",
+        this,
+        selector.as_str(&env.mem)
+    );
+}
+
++ (())cancelPreviousPerformRequestsWithTarget:(id)target {
+    let run_loop: id = msg_class![env; NSRunLoop currentRunLoop];
+    ns_run_loop::cancel_delayed_performs(env, run_loop, target, None);
+}
++ (())cancelPreviousPerformRequestsWithTarget:(id)target
+                                     selector:(SEL)selector
+                                       object:(id)object {
+    let run_loop: id = msg_class![env; NSRunLoop currentRunLoop];
+    ns_run_loop::cancel_delayed_performs(env, run_loop, target, Some((selector, object)));
+}
+
 - (id)init {
     this
 }
@@ -74,6 +127,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     log_dbg!("[{:?} release]", this);
     if env.objc.decrement_refcount(this) {
         () = msg![env; this dealloc];
+        release_associated_objects_of_deallocated(env);
     }
 }
 - (id)autorelease {
@@ -104,6 +158,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (bool)isEqual:(id)other {
     this == other
 }
+// From NSComparisonMethods. Our NSString and NSNumber override this rather
+// than isEqual:, so collections use this one.
+- (bool)isEqualTo:(id)other {
+    msg![env; this isEqual:other]
+}
 
 // TODO: description and debugDescription (both the instance and class method).
 // This is not hard to add, but before adding a fallback implementation of it,
@@ -122,34 +181,55 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 // NSKeyValueCoding
+- (id)valueForKey:(id)key { // NSString*
+    let key_string = to_rust_string(env, key); // TODO: avoid copy?
+    if let Some(value) = value_for_key(env, this, &key_string) {
+        value
+    } else {
+        msg![env; this valueForUndefinedKey:key]
+    }
+}
 - (())setValue:(id)value
        forKey:(id)key { // NSString*
-    let key = to_rust_string(env, key); // TODO: avoid copy?
-    assert!(key.is_ascii()); // TODO: do we have to handle non-ASCII keys?
-
-    let class = msg![env; this class];
-
-    if let Some(sel) = env.objc.lookup_selector(&format!(
-        "set{}{}:",
-        key.as_bytes()[0].to_ascii_uppercase() as char,
-        &key[1..],
-    )) {
-        if env.objc.class_has_method(class, sel) {
-            return msg_send(env, (this, sel, value));
-        }
+    let key_string = to_rust_string(env, key); // TODO: avoid copy?
+    if !set_value_for_key(env, this, value, &key_string) {
+        () = msg![env; this setValue:value forUndefinedKey:key];
     }
+}
 
-    if let Some(sel) = env.objc.lookup_selector(&format!(
-        "_set{}{}:",
-        key.as_bytes()[0].to_ascii_uppercase() as char,
-        &key[1..],
-    )) {
-        if env.objc.class_has_method(class, sel) {
-            return msg_send(env, (this, sel, value));
-        }
-    }
+- (id)valueForUndefinedKey:(id)key { // NSString*
+    let reason = format!(
+        "[<{} {:?}> valueForUndefinedKey:]: this class is not key value coding-compliant for the key {}.",
+        class_name(env, this),
+        this,
+        to_rust_string(env, key),
+    );
+    ns_exception::raise(env, "NSUndefinedKeyException", reason);
+    nil
+}
+- (())setValue:(id)_value
+forUndefinedKey:(id)key { // NSString*
+    let reason = format!(
+        "[<{} {:?}> setValue:forUndefinedKey:]: this class is not key value coding-compliant for the key {}.",
+        class_name(env, this),
+        this,
+        to_rust_string(env, key),
+    );
+    ns_exception::raise(env, "NSUndefinedKeyException", reason);
+}
+- (())setNilValueForKey:(id)key { // NSString*
+    let reason = format!(
+        "[<{} {:?}> setNilValueForKey]: could not set nil as the value for the key {}.",
+        class_name(env, this),
+        this,
+        to_rust_string(env, key),
+    );
+    ns_exception::raise(env, "NSInvalidArgumentException", reason);
+}
 
-    unimplemented!("TODO: object {:?} does not have simple setter method for {}, use fallback", this, key);
+- (bool)conformsToProtocol:(id)protocol {
+    let class = msg![env; this class];
+    env.objc.class_conforms_to_protocol(class, protocol)
 }
 
 - (bool)respondsToSelector:(SEL)selector {
@@ -157,6 +237,26 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.class_has_method(class, selector)
 }
 
+// Message forwarding (see ns_invocation.rs)
+- (id)methodSignatureForSelector:(SEL)selector {
+    let class = msg![env; this class];
+    signature_for_method(env, class, selector)
+}
+- (id)forwardingTargetForSelector:(SEL)_selector {
+    nil
+}
+- (())forwardInvocation:(id)invocation { // NSInvocation*
+    let selector: SEL = msg![env; invocation selector];
+    msg![env; this doesNotRecognizeSelector:selector]
+}
+- (())doesNotRecognizeSelector:(SEL)selector {
+    panic!(
+        "Object {:?} does not recognize selector \"{}\"!",
+        this,
+        selector.as_str(&env.mem)
+    );
+}
+
 - (id)performSelector:(SEL)sel {
     assert!(!sel.is_null());
     msg_send(env, (this, sel))
@@ -175,6 +275,149 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg_send(env, (this, sel, o1, o2))
 }
 
+- (())performSelector:(SEL)sel
+           withObject:(id)object
+           afterDelay:(NSTimeInterval)delay {
+    assert!(!sel.is_null());
+    let run_loop: id = msg_class![env; NSRunLoop currentRunLoop];
+    // Even a zero delay means the message is sent on a later run loop
+    // iteration, not immediately.
+    let delay = Duration::from_secs_f64(delay.max(0.0));
+    ns_run_loop::schedule_delayed_perform(env, run_loop, this, sel, object, delay);
+}
+
+- (())performSelector:(SEL)sel
+           withObject:(id)object
+           afterDelay:(NSTimeInterval)delay
+              inModes:(id)_modes { // NSArray* of NSString*
+    // TODO: handle run loop modes
+    msg![env; this performSelector:sel withObject:object afterDelay:delay]
+}
+
 @end
 
 };
+
+fn class_name(env: &Environment, object: id) -> String {
+    let class = ObjC::read_isa(object, &env.mem);
+    env.objc.get_class_name(class).to_string()
+}
+
+/// Capitalize the first letter of a key, as used in accessor names.
+fn capitalize(key: &str) -> String {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// Find the first of several accessor methods that a class has.
+fn find_accessor(env: &Environment, class: Class, names: &[String]) -> Option<SEL> {
+    names.iter().find_map(|name| {
+        let sel = env.objc.lookup_selector(name)?;
+        env.objc.class_has_method(class, sel).then_some(sel)
+    })
+}
+
+/// Find the ivar that backs a key, if the class allows direct access to ivars.
+/// The result is the ivar's offset and its type.
+fn find_ivar_for_key(env: &mut Environment, class: Class, key: &str) -> Option<(u32, ObjCType)> {
+    if !msg![env; class accessInstanceVariablesDirectly] {
+        return None;
+    }
+    let capitalized = capitalize(key);
+    let names = [
+        format!("_{}", key),
+        format!("_is{}", capitalized),
+        key.to_string(),
+        format!("is{}", capitalized),
+    ];
+    names.iter().find_map(|name| {
+        let (offset, size, encoding) = env.objc.find_ivar(class, name, &env.mem)?;
+        Some((offset, ObjCType { encoding, size }))
+    })
+}
+
+/// The search pattern `valueForKey:` uses, minus the collection accessor
+/// patterns. Returns [None] if the key is undefined.
+fn value_for_key(env: &mut Environment, this: id, key: &str) -> Option<id> {
+    let class = ObjC::read_isa(this, &env.mem);
+    let capitalized = capitalize(key);
+
+    let getters = [
+        format!("get{}", capitalized),
+        key.to_string(),
+        format!("is{}", capitalized),
+        format!("_{}", key),
+    ];
+    if let Some(sel) = find_accessor(env, class, &getters) {
+        let signature = signature_for_method(env, class, sel);
+        let (return_type, _) = get_types(env, signature);
+        if return_type.is_object() {
+            return Some(msg_send(env, (this, sel)));
+        }
+        let bytes = invoke_with_signature(env, this, sel, signature, &[]);
+        return Some(box_value(env, return_type.unqualified(), &bytes));
+    }
+
+    let (offset, type_) = find_ivar_for_key(env, class, key)?;
+    let ivar: MutVoidPtr = Ptr::from_bits(this.to_bits() + offset);
+    if type_.is_object() {
+        return Some(env.mem.read(ivar.cast()));
+    }
+    let bytes = env.mem.bytes_at(ivar.cast(), type_.size).to_vec();
+    Some(box_value(env, type_.unqualified(), &bytes))
+}
+
+/// The search pattern `setValue:forKey:` uses. Returns [false] if the key is
+/// undefined.
+fn set_value_for_key(env: &mut Environment, this: id, value: id, key: &str) -> bool {
+    let class = ObjC::read_isa(this, &env.mem);
+    let capitalized = capitalize(key);
+
+    let setters = [
+        format!("set{}:", capitalized),
+        format!("_set{}:", capitalized),
+    ];
+    if let Some(sel) = find_accessor(env, class, &setters) {
+        let signature = signature_for_method(env, class, sel);
+        let (_, argument_types) = get_types(env, signature);
+        let type_ = &argument_types[2];
+        if type_.is_object() {
+            () = msg_send(env, (this, sel, value));
+        } else if value == nil {
+            set_nil_value_for_key(env, this, key);
+        } else {
+            let bytes = unbox_value(env, type_.unqualified(), type_.size, value);
+            invoke_with_signature(env, this, sel, signature, &[&bytes]);
+        }
+        return true;
+    }
+
+    let Some((offset, type_)) = find_ivar_for_key(env, class, key) else {
+        return false;
+    };
+    let ivar: MutVoidPtr = Ptr::from_bits(this.to_bits() + offset);
+    if type_.is_object() {
+        let ivar = ivar.cast::<id>();
+        let old_value = env.mem.read(ivar);
+        retain(env, value);
+        env.mem.write(ivar, value);
+        release(env, old_value);
+    } else if value == nil {
+        set_nil_value_for_key(env, this, key);
+    } else {
+        let bytes = unbox_value(env, type_.unqualified(), type_.size, value);
+        env.mem
+            .bytes_at_mut(ivar.cast(), type_.size)
+            .copy_from_slice(&bytes);
+    }
+    true
+}
+
+fn set_nil_value_for_key(env: &mut Environment, this: id, key: &str) {
+    let key = from_rust_string(env, key.to_string());
+    let key = autorelease(env, key);
+    () = msg![env; this setNilValueForKey:key];
+}
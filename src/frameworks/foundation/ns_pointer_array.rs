@@ -0,0 +1,153 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSPointerArray`.
+
+use super::ns_array::check_index;
+use super::ns_pointer_functions::{
+    NSPointerFunctionsOptions, NSPointerFunctionsStrongMemory, NSPointerFunctionsWeakMemory,
+    PointerFunctions,
+};
+use super::NSUInteger;
+use crate::mem::MutVoidPtr;
+use crate::objc::{autorelease, id, msg, nil, objc_classes, ClassExports, HostObject, NSZonePtr};
+
+struct NSPointerArrayHostObject {
+    functions: PointerFunctions,
+    /// May contain null pointers.
+    pointers: Vec<id>,
+}
+impl HostObject for NSPointerArrayHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSPointerArray: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSPointerArrayHostObject {
+        functions: PointerFunctions::from_options(NSPointerFunctionsStrongMemory),
+        pointers: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)pointerArrayWithOptions:(NSPointerFunctionsOptions)options {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithOptions:options];
+    autorelease(env, new)
+}
++ (id)strongObjectsPointerArray {
+    msg![env; this pointerArrayWithOptions:NSPointerFunctionsStrongMemory]
+}
++ (id)weakObjectsPointerArray {
+    msg![env; this pointerArrayWithOptions:NSPointerFunctionsWeakMemory]
+}
+// Deprecated names for the above.
++ (id)pointerArrayWithStrongObjects {
+    msg![env; this strongObjectsPointerArray]
+}
++ (id)pointerArrayWithWeakObjects {
+    msg![env; this weakObjectsPointerArray]
+}
+
+- (id)init {
+    msg![env; this initWithOptions:NSPointerFunctionsStrongMemory]
+}
+- (id)initWithOptions:(NSPointerFunctionsOptions)options {
+    env.objc.borrow_mut::<NSPointerArrayHostObject>(this).functions =
+        PointerFunctions::from_options(options);
+    this
+}
+
+- (())dealloc {
+    let host_object = env.objc.borrow_mut::<NSPointerArrayHostObject>(this);
+    let functions = host_object.functions;
+    for pointer in std::mem::take(&mut host_object.pointers) {
+        functions.relinquish(env, pointer);
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSUInteger)count {
+    env.objc.borrow::<NSPointerArrayHostObject>(this).pointers.len().try_into().unwrap()
+}
+- (())setCount:(NSUInteger)count {
+    let host_object = env.objc.borrow_mut::<NSPointerArrayHostObject>(this);
+    let functions = host_object.functions;
+    let count = count as usize;
+    if count >= host_object.pointers.len() {
+        host_object.pointers.resize(count, nil);
+    } else {
+        for pointer in host_object.pointers.split_off(count) {
+            functions.relinquish(env, pointer);
+        }
+    }
+}
+
+- (MutVoidPtr)pointerAtIndex:(NSUInteger)index {
+    let count = env.objc.borrow::<NSPointerArrayHostObject>(this).pointers.len();
+    if !check_index(env, index, count, /* allow_end: */ false) {
+        return MutVoidPtr::null();
+    }
+    env.objc.borrow::<NSPointerArrayHostObject>(this).pointers[index as usize].cast()
+}
+
+- (())addPointer:(MutVoidPtr)pointer {
+    let functions = env.objc.borrow::<NSPointerArrayHostObject>(this).functions;
+    let pointer = functions.acquire(env, pointer.cast());
+    env.objc.borrow_mut::<NSPointerArrayHostObject>(this).pointers.push(pointer);
+}
+- (())insertPointer:(MutVoidPtr)pointer
+            atIndex:(NSUInteger)index {
+    let count = env.objc.borrow::<NSPointerArrayHostObject>(this).pointers.len();
+    if !check_index(env, index, count, /* allow_end: */ true) {
+        return;
+    }
+    let functions = env.objc.borrow::<NSPointerArrayHostObject>(this).functions;
+    let pointer = functions.acquire(env, pointer.cast());
+    env.objc.borrow_mut::<NSPointerArrayHostObject>(this).pointers.insert(index as usize, pointer);
+}
+- (())removePointerAtIndex:(NSUInteger)index {
+    let count = env.objc.borrow::<NSPointerArrayHostObject>(this).pointers.len();
+    if !check_index(env, index, count, /* allow_end: */ false) {
+        return;
+    }
+    let host_object = env.objc.borrow_mut::<NSPointerArrayHostObject>(this);
+    let functions = host_object.functions;
+    let pointer = host_object.pointers.remove(index as usize);
+    functions.relinquish(env, pointer);
+}
+- (())replacePointerAtIndex:(NSUInteger)index
+                withPointer:(MutVoidPtr)pointer {
+    let count = env.objc.borrow::<NSPointerArrayHostObject>(this).pointers.len();
+    if !check_index(env, index, count, /* allow_end: */ false) {
+        return;
+    }
+    let functions = env.objc.borrow::<NSPointerArrayHostObject>(this).functions;
+    let pointer = functions.acquire(env, pointer.cast());
+    let host_object = env.objc.borrow_mut::<NSPointerArrayHostObject>(this);
+    let old = std::mem::replace(&mut host_object.pointers[index as usize], pointer);
+    functions.relinquish(env, old);
+}
+
+- (())compact {
+    env.objc
+        .borrow_mut::<NSPointerArrayHostObject>(this)
+        .pointers
+        .retain(|pointer| !pointer.is_null());
+}
+
+- (id)allObjects {
+    let host_object = env.objc.borrow::<NSPointerArrayHostObject>(this);
+    let functions = host_object.functions;
+    let pointers = host_object.pointers.clone();
+    functions.to_array(env, pointers.into_iter())
+}
+
+@end
+
+};
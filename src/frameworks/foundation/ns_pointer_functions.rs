@@ -0,0 +1,171 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSPointerFunctionsOptions`, shared by `NSPointerArray`, `NSMapTable` and
+//! `NSHashTable`.
+//!
+//! The `NSPointerFunctions` class itself isn't implemented, only the options.
+//!
+//! touchHLE has no garbage collector and no zeroing weak references, so weak
+//! memory is treated as unsafe-unretained: the pointer isn't retained, but it
+//! also isn't cleared when the object is deallocated. This is the same thing
+//! that non-ARC, non-GC code on the real device gets.
+
+use super::{ns_array, NSUInteger};
+use crate::objc::{autorelease, id, msg, msg_class, release, retain};
+use crate::Environment;
+
+pub type NSPointerFunctionsOptions = NSUInteger;
+
+// Memory options
+pub const NSPointerFunctionsStrongMemory: NSPointerFunctionsOptions = 0;
+pub const NSPointerFunctionsZeroingWeakMemory: NSPointerFunctionsOptions = 1;
+pub const NSPointerFunctionsOpaqueMemory: NSPointerFunctionsOptions = 2;
+pub const NSPointerFunctionsMallocMemory: NSPointerFunctionsOptions = 3;
+pub const NSPointerFunctionsMachVirtualMemory: NSPointerFunctionsOptions = 4;
+pub const NSPointerFunctionsWeakMemory: NSPointerFunctionsOptions = 5;
+const MEMORY_MASK: NSPointerFunctionsOptions = 0xff;
+
+// Personality options. The opaque (1 << 8) and integer (5 << 8) personalities
+// compare pointers directly, which is also what happens for unknown ones.
+pub const NSPointerFunctionsObjectPersonality: NSPointerFunctionsOptions = 0 << 8;
+pub const NSPointerFunctionsObjectPointerPersonality: NSPointerFunctionsOptions = 2 << 8;
+pub const NSPointerFunctionsCStringPersonality: NSPointerFunctionsOptions = 3 << 8;
+pub const NSPointerFunctionsStructPersonality: NSPointerFunctionsOptions = 4 << 8;
+const PERSONALITY_MASK: NSPointerFunctionsOptions = 0xff << 8;
+
+pub const NSPointerFunctionsCopyIn: NSPointerFunctionsOptions = 1 << 16;
+
+/// How a collection should treat the pointers it contains, as determined by
+/// its [NSPointerFunctionsOptions].
+#[derive(Copy, Clone, Debug)]
+pub(super) struct PointerFunctions {
+    /// Retain (or copy) objects when adding them, release when removing.
+    strong: bool,
+    /// Copy objects when adding them.
+    copy_in: bool,
+    /// The pointers are Objective-C objects.
+    is_object: bool,
+    /// Use `hash` and `isEqualTo:`. Otherwise, pointers are compared directly.
+    object_personality: bool,
+    /// Hash and compare as null-terminated C strings.
+    c_string_personality: bool,
+}
+impl PointerFunctions {
+    pub(super) fn from_options(options: NSPointerFunctionsOptions) -> Self {
+        let memory = options & MEMORY_MASK;
+        let personality = options & PERSONALITY_MASK;
+        let unknown = options & !(MEMORY_MASK | PERSONALITY_MASK | NSPointerFunctionsCopyIn);
+        if unknown != 0 {
+            log!(
+                "Warning: unknown NSPointerFunctionsOptions bits {:#x}",
+                unknown
+            );
+        }
+
+        let is_object = matches!(
+            personality,
+            NSPointerFunctionsObjectPersonality | NSPointerFunctionsObjectPointerPersonality
+        );
+        let strong = match memory {
+            NSPointerFunctionsStrongMemory => is_object,
+            // See module documentation.
+            NSPointerFunctionsZeroingWeakMemory | NSPointerFunctionsWeakMemory => false,
+            NSPointerFunctionsOpaqueMemory => false,
+            NSPointerFunctionsMallocMemory | NSPointerFunctionsMachVirtualMemory => {
+                log!(
+                    "TODO: NSPointerFunctionsOptions memory option {}, treating as opaque",
+                    memory
+                );
+                false
+            }
+            _ => {
+                log!(
+                    "Warning: invalid NSPointerFunctionsOptions memory option {}, treating as opaque",
+                    memory
+                );
+                false
+            }
+        };
+        if personality == NSPointerFunctionsStructPersonality {
+            log!("TODO: NSPointerFunctionsStructPersonality, treating as opaque");
+        }
+
+        PointerFunctions {
+            strong,
+            copy_in: strong && options & NSPointerFunctionsCopyIn != 0,
+            is_object,
+            object_personality: personality == NSPointerFunctionsObjectPersonality,
+            c_string_personality: personality == NSPointerFunctionsCStringPersonality,
+        }
+    }
+
+    /// Take ownership of a pointer being added to the collection, returning
+    /// the pointer to actually store.
+    pub(super) fn acquire(&self, env: &mut Environment, pointer: id) -> id {
+        if self.copy_in {
+            msg![env; pointer copy]
+        } else if self.strong {
+            retain(env, pointer)
+        } else {
+            pointer
+        }
+    }
+
+    /// Give up ownership of a pointer being removed from the collection.
+    pub(super) fn relinquish(&self, env: &mut Environment, pointer: id) {
+        if self.strong {
+            release(env, pointer);
+        }
+    }
+
+    /// Create an (autoreleased) `NSArray` of the objects in a collection,
+    /// skipping null pointers. Pointers that aren't objects are boxed in
+    /// `NSValue`s.
+    pub(super) fn to_array(self, env: &mut Environment, pointers: impl Iterator<Item = id>) -> id {
+        let pointers: Vec<id> = pointers.filter(|pointer| !pointer.is_null()).collect();
+        let mut objects = Vec::with_capacity(pointers.len());
+        for pointer in pointers {
+            let object = if self.is_object {
+                pointer
+            } else {
+                msg_class![env; NSValue valueWithPointer:(pointer.cast_void().cast_const())]
+            };
+            objects.push(retain(env, object));
+        }
+        let array = ns_array::from_vec(env, objects);
+        autorelease(env, array)
+    }
+
+    pub(super) fn hash(&self, env: &mut Environment, pointer: id) -> NSUInteger {
+        if pointer.is_null() {
+            0
+        } else if self.object_personality {
+            msg![env; pointer hash]
+        } else if self.c_string_personality {
+            // FNV-1a
+            let bytes = env.mem.cstr_at(pointer.cast::<u8>());
+            bytes.iter().fold(0x811c9dc5u32, |hash, &byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+            })
+        } else {
+            pointer.to_bits()
+        }
+    }
+
+    pub(super) fn is_equal(&self, env: &mut Environment, a: id, b: id) -> bool {
+        if a == b {
+            true
+        } else if a.is_null() || b.is_null() {
+            false
+        } else if self.object_personality {
+            msg![env; a isEqualTo:b]
+        } else if self.c_string_personality {
+            env.mem.cstr_at(a.cast::<u8>()) == env.mem.cstr_at(b.cast::<u8>())
+        } else {
+            false
+        }
+    }
+}
@@ -6,19 +6,42 @@
 //! `NSProcessInfo`.
 
 use super::NSTimeInterval;
-use crate::objc::{objc_classes, ClassExports};
+use crate::objc::{id, msg, objc_classes, ClassExports};
 use std::time::Instant;
 
+#[derive(Default)]
+pub struct State {
+    process_info: Option<id>,
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
 @implementation NSProcessInfo: NSObject
 
++ (id)processInfo {
+    if let Some(existing) = env.framework_state.foundation.ns_process_info.process_info {
+        existing
+    } else {
+        let new: id = msg![env; this new];
+        env.framework_state.foundation.ns_process_info.process_info = Some(new);
+        new
+    }
+}
+
 + (NSTimeInterval)systemUptime {
     Instant::now().duration_since(env.startup_time).as_secs_f64()
 }
 
+- (NSTimeInterval)systemUptime {
+    Instant::now().duration_since(env.startup_time).as_secs_f64()
+}
+
+- (u64)physicalMemory {
+    u64::from(env.options.device_ram_mib().get()) * 1024 * 1024
+}
+
 @end
 
 };
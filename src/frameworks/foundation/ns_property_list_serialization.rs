@@ -1,12 +1,15 @@
 //! `NSPropertyListSerialization`.
 
-use super::{ns_array, ns_dictionary, ns_string, NSUInteger};
+use super::ns_value::NSNumberHostObject;
+use super::{ns_array, ns_data, ns_date, ns_dictionary, ns_string, NSTimeInterval, NSUInteger};
+use crate::frameworks::core_foundation::time::apple_epoch;
 use crate::fs::GuestPath;
-use crate::mem::MutPtr;
-use crate::objc::{id, msg, msg_class, nil, release};
+use crate::mem::{ConstVoidPtr, MutPtr};
+use crate::objc::{autorelease, id, msg, msg_class, nil, release, Class};
 use crate::Environment;
 use plist::Value;
 use std::io::Cursor;
+use std::time::{Duration, SystemTime};
 
 // TODO: Implement reading of property lists other than Info.plist.
 // [NSDictionary contentsOfFile:] and [NSArray contentsOfFile:] in particular.
@@ -41,7 +44,30 @@ pub(super) fn deserialize_plist_from_file(
     deserialize_plist(env, &root)
 }
 
-fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
+/// Internals of `writeToFile:atomically:` on `NSDictionary`: write an object
+/// to a file as an XML property list.
+pub(super) fn serialize_plist_to_file(
+    env: &mut Environment,
+    object: id,
+    path: id, // NSString*
+    atomically: bool,
+) -> bool {
+    let Some(value) = serialize_plist(env, object) else {
+        log!("Couldn't serialize plist: object isn't a property list");
+        return false;
+    };
+    let mut bytes = Vec::new();
+    if let Err(e) = value.to_writer_xml(&mut bytes) {
+        log!("Couldn't serialize plist: {}", e);
+        return false;
+    }
+    let data = ns_data::from_rust_slice(env, &bytes);
+    autorelease(env, data);
+    msg![env; data writeToFile:path atomically:atomically]
+}
+
+/// Create a new object (not autoreleased) from a property list value.
+pub fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
     match value {
         Value::Array(array) => {
             let array = array
@@ -82,8 +108,13 @@ fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
             let data: id = msg_class![env; NSData alloc];
             msg![env; data initWithBytesNoCopy:alloc length:length]
         }
-        Value::Date(_) => {
-            todo!("deserialize plist value: {:?}", value); // TODO
+        Value::Date(date) => {
+            let time = SystemTime::from(*date);
+            let time_interval = match time.duration_since(apple_epoch()) {
+                Ok(duration) => duration.as_secs_f64(),
+                Err(err) => -err.duration().as_secs_f64(),
+            };
+            ns_date::from_time_interval(env, time_interval)
         }
         Value::Integer(int) => {
             let number: id = msg_class![env; NSNumber alloc];
@@ -114,3 +145,85 @@ fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
         }
     }
 }
+
+/// Convert an object to a property list value. Only the property list types
+/// are supported: `NSArray`, `NSDictionary` (with string keys), `NSData`,
+/// `NSDate`, `NSNumber` and `NSString`. Returns [None] if the object is or
+/// contains anything else.
+pub fn serialize_plist(env: &mut Environment, object: id) -> Option<Value> {
+    let array_class: Class = msg_class![env; NSArray class];
+    let dictionary_class: Class = msg_class![env; NSDictionary class];
+    let data_class: Class = msg_class![env; NSData class];
+    let date_class: Class = msg_class![env; NSDate class];
+    let number_class: Class = msg_class![env; NSNumber class];
+    let string_class: Class = msg_class![env; NSString class];
+
+    if msg![env; object isKindOfClass:array_class] {
+        let count: NSUInteger = msg![env; object count];
+        let array = (0..count)
+            .map(|i| {
+                let item: id = msg![env; object objectAtIndex:i];
+                serialize_plist(env, item)
+            })
+            .collect::<Option<_>>()?;
+        Some(Value::Array(array))
+    } else if msg![env; object isKindOfClass:dictionary_class] {
+        let keys: id = msg![env; object allKeys];
+        let keys = ns_array::to_vec(env, keys);
+        let mut dict = plist::Dictionary::new();
+        for key in keys {
+            if !msg![env; key isKindOfClass:string_class] {
+                log_dbg!("Can't serialize plist dictionary key {:?}", key);
+                return None;
+            }
+            let value: id = msg![env; object objectForKey:key];
+            let key = ns_string::to_rust_string(env, key).to_string();
+            let value = serialize_plist(env, value)?;
+            dict.insert(key, value);
+        }
+        Some(Value::Dictionary(dict))
+    } else if msg![env; object isKindOfClass:data_class] {
+        let bytes: ConstVoidPtr = msg![env; object bytes];
+        let length: NSUInteger = msg![env; object length];
+        if length == 0 {
+            Some(Value::Data(Vec::new()))
+        } else {
+            Some(Value::Data(env.mem.bytes_at(bytes.cast(), length).to_vec()))
+        }
+    } else if msg![env; object isKindOfClass:date_class] {
+        let time_interval: NSTimeInterval = msg![env; object timeIntervalSinceReferenceDate];
+        // NaN, infinite and far-off dates can't be represented (XML plists
+        // only have years 1 to 9999), so they fail serialization like
+        // unsupported objects do.
+        let time = Some(time_interval)
+            .filter(|&interval| (-63113904000.0..=252423993599.0).contains(&interval))
+            .and_then(|interval| Duration::try_from_secs_f64(interval.abs()).ok())
+            .and_then(|duration| {
+                if time_interval >= 0.0 {
+                    apple_epoch().checked_add(duration)
+                } else {
+                    apple_epoch().checked_sub(duration)
+                }
+            });
+        let Some(time) = time else {
+            log_dbg!("Can't serialize date {:?} ({})", object, time_interval);
+            return None;
+        };
+        Some(Value::Date(time.into()))
+    } else if msg![env; object isKindOfClass:number_class] {
+        Some(match *env.objc.borrow::<NSNumberHostObject>(object) {
+            NSNumberHostObject::Bool(value) => Value::Boolean(value),
+            NSNumberHostObject::UnsignedLongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::LongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::Float(value) => Value::Real(value.into()),
+            NSNumberHostObject::Double(value) => Value::Real(value),
+        })
+    } else if msg![env; object isKindOfClass:string_class] {
+        Some(Value::String(
+            ns_string::to_rust_string(env, object).to_string(),
+        ))
+    } else {
+        log_dbg!("Can't serialize plist object {:?}", object);
+        None
+    }
+}
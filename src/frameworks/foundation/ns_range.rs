@@ -0,0 +1,45 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Functions from `NSRange.h`. The `NSRange` type itself is in the parent
+//! module.
+
+use super::{ns_string, NSRange, NSUInteger};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::objc::{autorelease, id};
+use crate::Environment;
+
+impl std::fmt::Display for NSRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let &NSRange { location, length } = self;
+        write!(f, "{{{}, {}}}", location, length)
+    }
+}
+
+fn NSStringFromRange(env: &mut Environment, range: NSRange) -> id {
+    let s = ns_string::from_rust_string(env, range.to_string());
+    autorelease(env, s)
+}
+
+/// Apple's documentation says this scans the string for the first two
+/// integers it can find, and fills in zero for any that are missing, so this is
+/// much more lenient than the `CGGeometry` equivalents.
+fn NSRangeFromString(env: &mut Environment, string: id) -> NSRange {
+    // TODO: avoid copy
+    let string = ns_string::to_rust_string(env, string);
+    let mut numbers = string
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<NSUInteger>().unwrap_or(NSUInteger::MAX));
+    NSRange {
+        location: numbers.next().unwrap_or(0),
+        length: numbers.next().unwrap_or(0),
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(NSStringFromRange(_)),
+    export_c_func!(NSRangeFromString(_)),
+];
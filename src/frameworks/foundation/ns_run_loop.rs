@@ -10,12 +10,15 @@
 
 use super::{ns_string, ns_timer};
 use crate::dyld::{ConstantExports, HostConstant};
-use crate::frameworks::audio_toolbox::audio_queue::{handle_audio_queue, AudioQueueRef};
+use crate::frameworks::audio_toolbox::audio_queue::{self, handle_audio_queue, AudioQueueRef};
 use crate::frameworks::core_foundation::cf_run_loop::{
     kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoopRef,
 };
-use crate::frameworks::{core_animation, media_player, uikit};
-use crate::objc::{id, msg, objc_classes, release, retain, ClassExports, HostObject};
+use crate::frameworks::{av_audio, core_animation, media_player, openal, uikit};
+use crate::libc;
+use crate::objc::{
+    id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports, HostObject, SEL,
+};
 use crate::Environment;
 use std::time::{Duration, Instant};
 
@@ -48,9 +51,23 @@ struct NSRunLoopHostObject {
     /// Strong references to `NSTimer*` in no particular order. Timers are owned
     /// by the run loop. The timer must remove itself when invalidated.
     timers: Vec<id>,
+    /// Messages scheduled by `performSelector:withObject:afterDelay:`, in no
+    /// particular order.
+    delayed_performs: Vec<DelayedPerform>,
 }
 impl HostObject for NSRunLoopHostObject {}
 
+/// A message to be sent once a certain time is reached. Real Foundation uses
+/// a timer for this, but keeping them separate makes cancellation easier.
+struct DelayedPerform {
+    /// Strong reference
+    target: id,
+    selector: SEL,
+    /// Strong reference
+    argument: id,
+    due_by: Instant,
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -64,6 +81,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         let host_object = Box::new(NSRunLoopHostObject {
             audio_queues: Vec::new(),
             timers: Vec::new(),
+            delayed_performs: Vec::new(),
         });
         let new = env.objc.alloc_static_object(this, host_object, &mut env.mem);
         env.framework_state.foundation.ns_run_loop.main_thread_run_loop = Some(new);
@@ -159,6 +177,131 @@ pub(super) fn remove_timer(env: &mut Environment, run_loop: id, timer: id) {
     }
 }
 
+/// For use by `NSObject`'s `performSelector:withObject:afterDelay:`.
+pub(super) fn schedule_delayed_perform(
+    env: &mut Environment,
+    run_loop: id,
+    target: id,
+    selector: SEL,
+    argument: id,
+    delay: Duration,
+) {
+    log_dbg!(
+        "Scheduling [{:?} {}] with argument {:?} on run loop {:?} after {:?}",
+        target,
+        selector.as_str(&env.mem),
+        argument,
+        run_loop,
+        delay,
+    );
+    retain(env, target);
+    retain(env, argument);
+    env.objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .delayed_performs
+        .push(DelayedPerform {
+            target,
+            selector,
+            argument,
+            due_by: Instant::now().checked_add(delay).unwrap(),
+        });
+}
+
+/// For use by `NSObject`'s `cancelPreviousPerformRequestsWithTarget:` family.
+/// If `selector` is [None], all requests for the target are cancelled,
+/// regardless of selector or argument. Otherwise, the argument must also
+/// match (using `isEqual:`).
+pub(super) fn cancel_delayed_performs(
+    env: &mut Environment,
+    run_loop: id,
+    target: id,
+    selector_and_argument: Option<(SEL, id)>,
+) {
+    let candidates: Vec<(usize, SEL, id)> = env
+        .objc
+        .borrow::<NSRunLoopHostObject>(run_loop)
+        .delayed_performs
+        .iter()
+        .enumerate()
+        .filter(|(_, perform)| perform.target == target)
+        .map(|(i, perform)| (i, perform.selector, perform.argument))
+        .collect();
+
+    let mut to_cancel = Vec::new();
+    for (i, perform_selector, perform_argument) in candidates {
+        let matches = match selector_and_argument {
+            None => true,
+            Some((selector, argument)) => {
+                perform_selector == selector
+                    && (perform_argument == argument
+                        || (argument != nil && msg![env; argument isEqual:perform_argument]))
+            }
+        };
+        if matches {
+            to_cancel.push(i);
+        }
+    }
+
+    let mut cancelled = Vec::new();
+    let host_object = env.objc.borrow_mut::<NSRunLoopHostObject>(run_loop);
+    // Remove from the end first so the indices stay valid.
+    for i in to_cancel.into_iter().rev() {
+        cancelled.push(host_object.delayed_performs.swap_remove(i));
+    }
+    for DelayedPerform {
+        target, argument, ..
+    } in cancelled
+    {
+        log_dbg!("Cancelled delayed perform for {:?}", target);
+        release(env, target);
+        release(env, argument);
+    }
+}
+
+/// Send any delayed messages that are due. Returns the time when the next one
+/// will be due, if any.
+fn handle_delayed_performs(env: &mut Environment, run_loop: id) -> Option<Instant> {
+    let now = Instant::now();
+    let host_object = env.objc.borrow_mut::<NSRunLoopHostObject>(run_loop);
+    let mut due = Vec::new();
+    let mut i = 0;
+    while i < host_object.delayed_performs.len() {
+        if host_object.delayed_performs[i].due_by <= now {
+            due.push(host_object.delayed_performs.swap_remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    // Messages should be sent in the order they were due.
+    due.sort_by_key(|perform| perform.due_by);
+
+    for DelayedPerform {
+        target,
+        selector,
+        argument,
+        ..
+    } in due
+    {
+        log_dbg!(
+            "Delayed perform is due, sending {:?} message to {:?}",
+            selector.as_str(&env.mem),
+            target
+        );
+        let pool: id = msg_class![env; NSAutoreleasePool new];
+        let _: () = msg_send(env, (target, selector, argument));
+        release(env, target);
+        release(env, argument);
+        release(env, pool);
+    }
+
+    env.objc
+        .borrow::<NSRunLoopHostObject>(run_loop)
+        .delayed_performs
+        .iter()
+        .map(|perform| perform.due_by)
+        .min()
+}
+
 /// Run the run loop for just a single iteration. This is a special mode just
 /// for the app picker, since we don't have `runMode:beforeDate:` or
 /// `runUntilDate:` yet. (TODO: implement those to replace this.)
@@ -206,6 +349,12 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
             limit_sleep_time(&mut sleep_until, next_due);
         }
 
+        let next_due = handle_delayed_performs(env, run_loop);
+        limit_sleep_time(&mut sleep_until, next_due);
+
+        let next_due = libc::dispatch::handle_main_queue(env);
+        limit_sleep_time(&mut sleep_until, next_due);
+
         assert!(audio_queues_tmp.is_empty());
         audio_queues_tmp.extend_from_slice(
             &env.objc
@@ -219,6 +368,15 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
 
         media_player::handle_players(env);
 
+        av_audio::handle_recorders(env);
+
+        openal::handle_device_changes(env);
+
+        if env.poll_power_governor() {
+            openal::handle_power_saving_change(env);
+            audio_queue::handle_power_saving_change(env);
+        }
+
         // Unfortunately, touchHLE has to poll for certain things repeatedly;
         // it can't just wait until the next event appears.
         //
@@ -4,22 +4,66 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! The `NSSet` class cluster, including `NSMutableSet` and `NSCountedSet`.
+//!
+//! Membership uses the `hash` and `isEqualTo:` methods of the members, like
+//! `NSDictionary` does for its keys.
 
-use super::ns_array;
 use super::ns_dictionary::DictionaryHostObject;
-use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
-use super::NSUInteger;
-use crate::mem::MutPtr;
+use super::ns_enumerator::{
+    fast_enumeration_helper, forget_collection, mutations_ptr, note_mutation,
+    NSFastEnumerationState,
+};
+use super::{ns_array, ns_exception, NSUInteger};
+use crate::abi::VaList;
+use crate::mem::{ConstPtr, MutPtr};
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, msg_class, msg_send_discarding_return, nil, objc_classes, release,
+    retain, Class, ClassExports, HostObject, NSZonePtr, SEL,
 };
+use crate::Environment;
+use std::collections::HashMap;
 
-/// Belongs to _touchHLE_NSSet
+/// Belongs to _touchHLE_NSSet, _touchHLE_NSMutableSet and NSCountedSet
 #[derive(Debug, Default)]
 struct SetHostObject {
+    /// The members are the keys. The values are all `NSNull`.
     dict: DictionaryHostObject,
+    /// For `NSCountedSet` only: the number of times each member (the stored
+    /// object, not one equal to it) has been added.
+    counts: Option<HashMap<id, NSUInteger>>,
 }
 impl HostObject for SetHostObject {}
+impl SetHostObject {
+    fn add(&mut self, env: &mut Environment, object: id) {
+        let null: id = msg_class![env; NSNull null];
+        self.dict.insert(env, object, null, /* copy_key: */ false);
+        if let Some(counts) = &mut self.counts {
+            let member = self.dict.lookup_key(env, object);
+            *counts.entry(member).or_insert(0) += 1;
+        }
+    }
+    fn remove(&mut self, env: &mut Environment, object: id) {
+        let member = self.dict.lookup_key(env, object);
+        if member == nil {
+            return;
+        }
+        if let Some(counts) = &mut self.counts {
+            let count = counts.get_mut(&member).unwrap();
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            counts.remove(&member);
+        }
+        self.dict.remove(env, member);
+    }
+    fn remove_all(&mut self, env: &mut Environment) {
+        std::mem::take(&mut self.dict).release(env);
+        if let Some(counts) = &mut self.counts {
+            counts.clear();
+        }
+    }
+}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -31,7 +75,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 // - (NSEnumerator*)objectEnumerator;
 // We can pick whichever subclass we want for the various alloc methods.
 // For the time being, that will always be _touchHLE_NSSet.
-@implementation NSSet: NSObject
+@implementation NSSet: NSObject <NSCopying, NSMutableCopying, NSCoding, NSFastEnumeration>
 
 + (id)allocWithZone:(NSZonePtr)zone {
     // NSSet might be subclassed by something which needs allocWithZone:
@@ -40,18 +84,155 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg_class![env; _touchHLE_NSSet allocWithZone:zone]
 }
 
++ (id)set {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+    autorelease(env, new)
+}
 + (id)setWithObject:(id)object {
     assert!(object != nil);
     let new: id = msg![env; this alloc];
     let new: id = msg![env; new initWithObject:object];
     autorelease(env, new)
 }
++ (id)setWithObjects:(id)first_object, ...dots {
+    let objects = collect_varargs(env, first_object, dots.start());
+    let new: id = msg![env; this alloc];
+    let new = init_with_objects(env, new, &objects);
+    autorelease(env, new)
+}
++ (id)setWithObjects:(ConstPtr<id>)objects
+               count:(NSUInteger)count {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithObjects:objects count:count];
+    autorelease(env, new)
+}
++ (id)setWithArray:(id)array { // NSArray*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithArray:array];
+    autorelease(env, new)
+}
++ (id)setWithSet:(id)set { // NSSet*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithSet:set];
+    autorelease(env, new)
+}
+
+// The init methods assume the instance is one of our private subclasses.
+- (id)init {
+    init_with_objects(env, this, &[])
+}
+- (id)initWithObject:(id)object {
+    init_with_objects(env, this, &[object])
+}
+- (id)initWithObjects:(id)first_object, ...dots {
+    let objects = collect_varargs(env, first_object, dots.start());
+    init_with_objects(env, this, &objects)
+}
+- (id)initWithObjects:(ConstPtr<id>)objects
+                count:(NSUInteger)count {
+    let objects: Vec<id> = (0..count).map(|i| env.mem.read(objects + i)).collect();
+    init_with_objects(env, this, &objects)
+}
+- (id)initWithArray:(id)array { // NSArray*
+    let objects = ns_array::to_vec(env, array);
+    init_with_objects(env, this, &objects)
+}
+- (id)initWithSet:(id)set { // NSSet*
+    let objects = to_vec(env, set);
+    init_with_objects(env, this, &objects)
+}
 
 // NSCopying implementation
 - (id)copyWithZone:(NSZonePtr)_zone {
     retain(env, this)
 }
 
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(NSZonePtr)_zone {
+    let new: id = msg_class![env; NSMutableSet alloc];
+    msg![env; new initWithSet:this]
+}
+
+- (NSUInteger)hash {
+    msg![env; this count]
+}
+- (bool)isEqualTo:(id)other {
+    if this == other {
+        return true;
+    }
+    let class: Class = msg_class![env; NSSet class];
+    if !msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    msg![env; this isEqualToSet:other]
+}
+- (bool)isEqualToSet:(id)other { // NSSet*
+    let count: NSUInteger = msg![env; this count];
+    let other_count: NSUInteger = msg![env; other count];
+    count == other_count && msg![env; this isSubsetOfSet:other]
+}
+
+- (id)anyObject {
+    let enumerator: id = msg![env; this objectEnumerator];
+    msg![env; enumerator nextObject]
+}
+- (id)allObjects { // NSArray*
+    let objects = to_vec(env, this);
+    for &object in &objects {
+        retain(env, object);
+    }
+    let array = ns_array::from_vec(env, objects);
+    autorelease(env, array)
+}
+
+- (bool)containsObject:(id)object {
+    let member: id = msg![env; this member:object];
+    member != nil
+}
+- (bool)isSubsetOfSet:(id)other { // NSSet*
+    let objects = to_vec(env, this);
+    objects.into_iter().all(|object| msg![env; other containsObject:object])
+}
+- (bool)intersectsSet:(id)other { // NSSet*
+    let objects = to_vec(env, this);
+    objects.into_iter().any(|object| msg![env; other containsObject:object])
+}
+
+- (id)setByAddingObject:(id)object {
+    let mut objects = to_vec(env, this);
+    objects.push(object);
+    let new: id = msg_class![env; NSSet alloc];
+    let new = init_with_objects(env, new, &objects);
+    autorelease(env, new)
+}
+- (id)setByAddingObjectsFromSet:(id)other { // NSSet*
+    let mut objects = to_vec(env, this);
+    objects.extend(to_vec(env, other));
+    let new: id = msg_class![env; NSSet alloc];
+    let new = init_with_objects(env, new, &objects);
+    autorelease(env, new)
+}
+- (id)setByAddingObjectsFromArray:(id)other { // NSArray*
+    let mut objects = to_vec(env, this);
+    objects.extend(ns_array::to_vec(env, other));
+    let new: id = msg_class![env; NSSet alloc];
+    let new = init_with_objects(env, new, &objects);
+    autorelease(env, new)
+}
+
+- (())makeObjectsPerformSelector:(SEL)selector {
+    for object in to_vec(env, this) {
+        msg_send_discarding_return(env, (object, selector));
+    }
+}
+- (())makeObjectsPerformSelector:(SEL)selector
+                      withObject:(id)argument {
+    for object in to_vec(env, this) {
+        msg_send_discarding_return(env, (object, selector, argument));
+    }
+}
+
 @end
 
 // NSMutableSet is an abstract class. A subclass must provide everything
@@ -69,9 +250,60 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg_class![env; _touchHLE_NSMutableSet allocWithZone:zone]
 }
 
++ (id)setWithCapacity:(NSUInteger)capacity {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCapacity:capacity];
+    autorelease(env, new)
+}
+
+- (id)initWithCapacity:(NSUInteger)_capacity {
+    init_with_objects(env, this, &[])
+}
+
 // NSCopying implementation
 - (id)copyWithZone:(NSZonePtr)_zone {
-    todo!(); // TODO: this should produce an immutable copy
+    let new: id = msg_class![env; NSSet alloc];
+    msg![env; new initWithSet:this]
+}
+
+- (())addObjectsFromArray:(id)array { // NSArray*
+    for object in ns_array::to_vec(env, array) {
+        () = msg![env; this addObject:object];
+    }
+}
+- (())unionSet:(id)other { // NSSet*
+    for object in to_vec(env, other) {
+        () = msg![env; this addObject:object];
+    }
+}
+- (())minusSet:(id)other { // NSSet*
+    for object in to_vec(env, other) {
+        () = msg![env; this removeObject:object];
+    }
+}
+- (())intersectSet:(id)other { // NSSet*
+    for object in to_vec(env, this) {
+        if !msg![env; other containsObject:object] {
+            () = msg![env; this removeObject:object];
+        }
+    }
+}
+- (())setSet:(id)other { // NSSet*
+    // The new members must be collected first, in case other == this.
+    let objects = to_vec(env, other);
+    for &object in &objects {
+        retain(env, object);
+    }
+    () = msg![env; this removeAllObjects];
+    for object in objects {
+        () = msg![env; this addObject:object];
+        release(env, object);
+    }
+}
+- (())removeAllObjects {
+    for object in to_vec(env, this) {
+        () = msg![env; this removeObject:object];
+    }
 }
 
 @end
@@ -81,46 +313,24 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation _touchHLE_NSSet: NSSet
 
 + (id)allocWithZone:(NSZonePtr)_zone {
-    let host_object = Box::new(SetHostObject {
-        dict: Default::default(),
-    });
+    let host_object = Box::<SetHostObject>::default();
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
-- (id)initWithObject:(id)object {
-    let null: id = msg_class![env; NSNull null];
-
-    let mut dict = <DictionaryHostObject as Default>::default();
-    dict.insert(env, object, null, /* copy_key: */ false);
-
-    env.objc.borrow_mut::<SetHostObject>(this).dict = dict;
-
-    this
-}
-
 - (())dealloc {
     std::mem::take(&mut env.objc.borrow_mut::<SetHostObject>(this).dict).release(env);
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
-// TODO: more init methods, etc
-
-// TODO: accessors
 - (NSUInteger)count {
-    env.objc.borrow_mut::<SetHostObject>(this).dict.count
+    env.objc.borrow::<SetHostObject>(this).dict.count
 }
-
-- (id)anyObject {
-    let object_or_none = env.objc.borrow_mut::<SetHostObject>(this).dict.iter_keys().next();
-    match object_or_none {
-        Some(object) => object,
-        None => nil
-    }
+- (id)member:(id)object {
+    member(env, this, object)
 }
-
-- (id)allObjects {
-    let objects = env.objc.borrow_mut::<SetHostObject>(this).dict.iter_keys().collect();
-    ns_array::from_vec(env, objects)
+- (id)objectEnumerator { // NSEnumerator*
+    let objects = env.objc.borrow::<SetHostObject>(this).dict.iter_keys().collect();
+    ns_array::new_object_enumerator(env, objects)
 }
 
 // NSFastEnumeration implementation
@@ -128,7 +338,7 @@ pub const CLASSES: ClassExports = objc_classes! {
                                   objects:(MutPtr<id>)stackbuf
                                     count:(NSUInteger)len {
     let mut iterator = env.objc.borrow::<SetHostObject>(this).dict.iter_keys();
-    fast_enumeration_helper(&mut env.mem, this, &mut iterator, state, stackbuf, len)
+    fast_enumeration_helper(&mut env.mem, this.cast(), &mut iterator, state, stackbuf, len)
 }
 
 @end
@@ -138,64 +348,129 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation _touchHLE_NSMutableSet: NSMutableSet
 
 + (id)allocWithZone:(NSZonePtr)_zone {
-    let host_object = Box::new(SetHostObject {
-        dict: Default::default(),
-    });
+    let host_object = Box::<SetHostObject>::default();
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
-- (id)initWithObject:(id)object {
-    let null: id = msg_class![env; NSNull null];
-
-    let mut dict = <DictionaryHostObject as Default>::default();
-    dict.insert(env, object, null, /* copy_key: */ false);
-
-    env.objc.borrow_mut::<SetHostObject>(this).dict = dict;
-
-    this
-}
-
 - (())dealloc {
     std::mem::take(&mut env.objc.borrow_mut::<SetHostObject>(this).dict).release(env);
+    forget_collection(env, this);
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
-// TODO: init methods etc
-
 - (NSUInteger)count {
-    env.objc.borrow_mut::<SetHostObject>(this).dict.count
+    env.objc.borrow::<SetHostObject>(this).dict.count
 }
-
-- (id)anyObject {
-    let object_or_none = env.objc.borrow_mut::<SetHostObject>(this).dict.iter_keys().next();
-    match object_or_none {
-        Some(object) => object,
-        None => nil
-    }
+- (id)member:(id)object {
+    member(env, this, object)
 }
-
-- (id)allObjects {
-    let objects = env.objc.borrow_mut::<SetHostObject>(this).dict.iter_keys().collect();
-    ns_array::from_vec(env, objects)
+- (id)objectEnumerator { // NSEnumerator*
+    let objects = env.objc.borrow::<SetHostObject>(this).dict.iter_keys().collect();
+    ns_array::new_object_enumerator(env, objects)
 }
 
 // NSFastEnumeration implementation
 - (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
                                   objects:(MutPtr<id>)stackbuf
                                     count:(NSUInteger)len {
+    let mutations_ptr = mutations_ptr(env, this);
     let mut iterator = env.objc.borrow::<SetHostObject>(this).dict.iter_keys();
-    fast_enumeration_helper(&mut env.mem, this, &mut iterator, state, stackbuf, len)
+    fast_enumeration_helper(&mut env.mem, mutations_ptr, &mut iterator, state, stackbuf, len)
 }
 
-// TODO: more mutation methods
-
 - (())addObject:(id)object {
-    let null: id = msg_class![env; NSNull null];
+    if object == nil {
+        ns_exception::raise(
+            env,
+            "NSInvalidArgumentException",
+            "Can't add nil to a set".to_string(),
+        );
+        return;
+    }
     let mut host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
-    host_obj.dict.insert(env, object, null, /* copy_key: */ false);
+    host_obj.add(env, object);
     *env.objc.borrow_mut(this) = host_obj;
+    note_mutation(env, this);
+}
+- (())removeObject:(id)object {
+    let mut host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.remove(env, object);
+    *env.objc.borrow_mut(this) = host_obj;
+    note_mutation(env, this);
+}
+- (())removeAllObjects {
+    let mut host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.remove_all(env);
+    *env.objc.borrow_mut(this) = host_obj;
+    note_mutation(env, this);
+}
+
+@end
+
+// NSCountedSet is a concrete class on the real iPhone OS. This one reuses the
+// storage of our NSMutableSet implementation, which knows about the counts.
+@implementation NSCountedSet: _touchHLE_NSMutableSet
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(SetHostObject {
+        dict: Default::default(),
+        counts: Some(HashMap::new()),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (NSUInteger)countForObject:(id)object {
+    let host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
+    let member = host_obj.dict.lookup_key(env, object);
+    let count = host_obj.counts.as_ref().unwrap().get(&member).copied().unwrap_or(0);
+    *env.objc.borrow_mut(this) = host_obj;
+    count
 }
 
 @end
 
 };
+
+/// Shared implementation of the init methods for our private subclasses.
+/// The objects are retained.
+fn init_with_objects(env: &mut Environment, this: id, objects: &[id]) -> id {
+    let mut host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.remove_all(env);
+    for &object in objects {
+        host_obj.add(env, object);
+    }
+    *env.objc.borrow_mut(this) = host_obj;
+    this
+}
+
+fn member(env: &mut Environment, this: id, object: id) -> id {
+    let host_obj: SetHostObject = std::mem::take(env.objc.borrow_mut(this));
+    let res = host_obj.dict.lookup_key(env, object);
+    *env.objc.borrow_mut(this) = host_obj;
+    res
+}
+
+/// Get the members of any set, using `objectEnumerator`. The members are not
+/// retained.
+fn to_vec(env: &mut Environment, set: id) -> Vec<id> {
+    let enumerator: id = msg![env; set objectEnumerator];
+    let mut objects = Vec::new();
+    loop {
+        let object: id = msg![env; enumerator nextObject];
+        if object == nil {
+            break;
+        }
+        objects.push(object);
+    }
+    objects
+}
+
+fn collect_varargs(env: &mut Environment, first_object: id, mut va_args: VaList) -> Vec<id> {
+    let mut objects = Vec::new();
+    let mut object = first_object;
+    while object != nil {
+        objects.push(object);
+        object = va_args.next(env);
+    }
+    objects
+}
@@ -10,7 +10,7 @@
 
 mod path_algorithms;
 
-use super::ns_array;
+use super::{ns_array, ns_data, ns_exception};
 use super::{
     NSComparisonResult, NSNotFound, NSOrderedAscending, NSOrderedDescending, NSOrderedSame,
     NSRange, NSUInteger,
@@ -22,10 +22,10 @@ use crate::frameworks::uikit::ui_font::{
 };
 use crate::fs::GuestPath;
 use crate::mach_o::MachO;
-use crate::mem::{guest_size_of, ConstPtr, Mem, MutPtr, Ptr, SafeRead};
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, Mem, MutPtr, Ptr, SafeRead};
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, Class, ClassExports, HostObject,
-    NSZonePtr, ObjC,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
+    HostObject, NSZonePtr, ObjC,
 };
 use crate::Environment;
 use std::borrow::Cow;
@@ -36,7 +36,11 @@ use std::string::FromUtf16Error;
 pub type NSStringEncoding = NSUInteger;
 pub const NSASCIIStringEncoding: NSUInteger = 1;
 pub const NSUTF8StringEncoding: NSUInteger = 4;
+pub const NSISOLatin1StringEncoding: NSUInteger = 5;
+pub const NSShiftJISStringEncoding: NSUInteger = 8;
 pub const NSUnicodeStringEncoding: NSUInteger = 10;
+pub const NSWindowsCP1252StringEncoding: NSUInteger = 12;
+pub const NSMacOSRomanStringEncoding: NSUInteger = 30;
 pub const NSUTF16StringEncoding: NSUInteger = NSUnicodeStringEncoding;
 pub const NSUTF16BigEndianStringEncoding: NSUInteger = 0x90000100;
 pub const NSUTF16LittleEndianStringEncoding: NSUInteger = 0x94000100;
@@ -44,11 +48,19 @@ pub const NSUTF16LittleEndianStringEncoding: NSUInteger = 0x94000100;
 pub type NSStringCompareOptions = NSUInteger;
 pub const NSCaseInsensitiveSearch: NSUInteger = 1;
 pub const NSLiteralSearch: NSUInteger = 2;
+pub const NSBackwardsSearch: NSUInteger = 4;
+pub const NSAnchoredSearch: NSUInteger = 8;
 pub const NSNumericSearch: NSUInteger = 64;
 
 /// Encodings that C strings (null-terminated byte strings) can use.
-const C_STRING_FRIENDLY_ENCODINGS: &[NSStringEncoding] =
-    &[NSASCIIStringEncoding, NSUTF8StringEncoding];
+const C_STRING_FRIENDLY_ENCODINGS: &[NSStringEncoding] = &[
+    NSASCIIStringEncoding,
+    NSUTF8StringEncoding,
+    NSISOLatin1StringEncoding,
+    NSShiftJISStringEncoding,
+    NSWindowsCP1252StringEncoding,
+    NSMacOSRomanStringEncoding,
+];
 
 pub const NSMaximumStringLength: NSUInteger = (i32::MAX - 1) as _;
 
@@ -83,56 +95,69 @@ enum StringHostObject {
 }
 impl HostObject for StringHostObject {}
 impl StringHostObject {
-    fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> StringHostObject {
+    /// Decode a string from bytes in some encoding. Returns [None] if the
+    /// bytes aren't valid in that encoding.
+    fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> Option<StringHostObject> {
         if bytes.len() == 0 {
-            return StringHostObject::Utf8(Cow::Borrowed(""));
+            return Some(StringHostObject::Utf8(Cow::Borrowed("")));
         }
 
-        // TODO: error handling
-
         match encoding {
             NSASCIIStringEncoding => {
-                assert!(bytes.iter().all(|byte| byte.is_ascii()));
-                // Safety: guaranteed by above assertion
+                if !bytes.iter().all(|byte| byte.is_ascii()) {
+                    return None;
+                }
+                // Safety: guaranteed by above check
                 let string = unsafe { String::from_utf8_unchecked(bytes.into_owned()) };
-                StringHostObject::Utf8(Cow::Owned(string))
+                Some(StringHostObject::Utf8(Cow::Owned(string)))
             }
             NSUTF8StringEncoding => {
-                let string = String::from_utf8(bytes.into_owned()).unwrap();
-                StringHostObject::Utf8(Cow::Owned(string))
+                let string = String::from_utf8(bytes.into_owned()).ok()?;
+                Some(StringHostObject::Utf8(Cow::Owned(string)))
+            }
+            NSISOLatin1StringEncoding => {
+                // Latin-1 is the first 256 code points of Unicode.
+                let string = bytes.iter().map(|&byte| char::from(byte)).collect();
+                Some(StringHostObject::Utf8(Cow::Owned(string)))
             }
             NSUTF16StringEncoding
             | NSUTF16BigEndianStringEncoding
             | NSUTF16LittleEndianStringEncoding => {
-                assert!(bytes.len() % 2 == 0);
-
-                let is_big_endian = match encoding {
-                    NSUTF16BigEndianStringEncoding => true,
-                    NSUTF16LittleEndianStringEncoding => false,
-                    NSUTF16StringEncoding => match &bytes[0..2] {
-                        [0xFE, 0xFF] => true,
-                        [0xFF, 0xFE] => false,
-                        // TODO: what does NSUnicodeStringEncoding mean if no
-                        // BOM is present?
-                        _ => unimplemented!("Default endianness"),
+                // Only the generic UTF-16 encoding looks for a BOM (and strips
+                // it). Like Apple's implementation, it assumes big-endian if
+                // there is none. With an explicit byte order, a BOM is kept as
+                // U+FEFF ZERO WIDTH NO-BREAK SPACE.
+                let (is_big_endian, bytes) = match encoding {
+                    NSUTF16BigEndianStringEncoding => (true, &bytes[..]),
+                    NSUTF16LittleEndianStringEncoding => (false, &bytes[..]),
+                    NSUTF16StringEncoding => match &bytes[..] {
+                        [0xFE, 0xFF, rest @ ..] => (true, rest),
+                        [0xFF, 0xFE, rest @ ..] => (false, rest),
+                        _ => (true, &bytes[..]),
                     },
                     _ => unreachable!(),
                 };
-                // TODO: Should the BOM be stripped? Always/sometimes/never?
 
-                StringHostObject::Utf16(if is_big_endian {
-                    bytes
-                        .chunks(2)
+                let chunks = bytes.chunks_exact(2);
+                if !chunks.remainder().is_empty() {
+                    return None;
+                }
+
+                Some(StringHostObject::Utf16(if is_big_endian {
+                    chunks
                         .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()))
                         .collect()
                 } else {
-                    bytes
-                        .chunks(2)
+                    chunks
                         .map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap()))
                         .collect()
-                })
+                }))
+            }
+            _ => {
+                let string = legacy_encoding(encoding)?
+                    .decode_without_bom_handling_and_without_replacement(&bytes)?;
+                Some(StringHostObject::Utf8(Cow::Owned(string.into_owned())))
             }
-            _ => panic!("Unimplemented encoding: {:#x}", encoding),
         }
     }
     fn to_utf8(&self) -> Result<Cow<'static, str>, FromUtf16Error> {
@@ -249,7 +274,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 // - (unichar)characterAtIndex:(NSUInteger)index;
 // We can pick whichever subclass we want for the various alloc methods.
 // For the time being, that will always be _touchHLE_NSString.
-@implementation NSString: NSObject
+@implementation NSString: NSObject <NSCopying, NSMutableCopying, NSCoding>
 
 + (id)allocWithZone:(NSZonePtr)zone {
     // NSString might be subclassed by something which needs allocWithZone:
@@ -334,34 +359,42 @@ pub const CLASSES: ClassExports = objc_classes! {
     utf16[index as usize]
 }
 
+- (NSRange)rangeOfString:(id)search_string { // NSString *
+    msg![env; this rangeOfString:search_string options:0u32]
+}
 - (NSRange)rangeOfString:(id)search_string
                  options:(NSStringCompareOptions)options { // NSString *
-    // TODO: search options
-    log_dbg!("rangeOfString:options: {}", options);
-    let len: NSUInteger = msg![env; this length];
-    let len_search: NSUInteger = msg![env; search_string length];
-    if len_search == 0 {
+    let length: NSUInteger = msg![env; this length];
+    let range = NSRange { location: 0, length };
+    msg![env; this rangeOfString:search_string options:options range:range]
+}
+- (NSRange)rangeOfString:(id)search_string
+                 options:(NSStringCompareOptions)options
+                   range:(NSRange)range { // NSString *
+    let haystack = to_utf16(env, this);
+    let Some(range) = checked_range(env, range, haystack.len()) else {
         return NSRange { location: NSNotFound as NSUInteger, length: 0 };
+    };
+    let needle = to_utf16(env, search_string);
+    match find_code_units(&haystack, &needle, options, range) {
+        Some(location) => NSRange {
+            location: location.try_into().unwrap(),
+            length: needle.len().try_into().unwrap(),
+        },
+        None => NSRange { location: NSNotFound as NSUInteger, length: 0 },
     }
-    for i in 0..len {
-        let mut match_found = true;
-        for j in 0..len_search {
-            if (i + j) >= len {
-                match_found = false;
-                break;
-            }
-            let a_c: u16 = msg![env; this characterAtIndex:(i + j)];
-            let b_c: u16 = msg![env; search_string characterAtIndex:j];
-            if a_c != b_c {
-                match_found = false;
-                break;
-            }
-        }
-        if match_found {
-            return NSRange { location: i, length: len_search }
-        }
-    }
-    NSRange { location: NSNotFound as NSUInteger, length: 0 }
+}
+
+- (bool)hasPrefix:(id)prefix { // NSString*
+    let this_utf16 = to_utf16(env, this);
+    let prefix_utf16 = to_utf16(env, prefix);
+    // Like Apple's implementation, an empty prefix never matches.
+    !prefix_utf16.is_empty() && this_utf16.starts_with(&prefix_utf16)
+}
+- (bool)hasSuffix:(id)suffix { // NSString*
+    let this_utf16 = to_utf16(env, this);
+    let suffix_utf16 = to_utf16(env, suffix);
+    !suffix_utf16.is_empty() && this_utf16.ends_with(&suffix_utf16)
 }
 
 - (id)description {
@@ -401,79 +434,25 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (NSComparisonResult)compare:(id)other options:(NSStringCompareOptions)mask { // NSString*
-    fn ascii_number(iter: &mut Peekable<CodeUnitIterator>, leftmost_digit: char) -> u32 {
-        let mut num = leftmost_digit.to_digit(10).unwrap();
-        while let Some(a_digit_char) = iter.next_if(
-            |&x| char::from_u32(x as u32).map_or(false, |y| y.is_ascii_digit())
-        ) {
-            num = num * 10 + char::from_u32(a_digit_char as u32).unwrap().to_digit(10).unwrap();
-        }
-        num
-    }
-
     // TODO: support foreign subclasses (perhaps via a helper function that
     // copies the string first)
-    let mut a_iter = env.objc.borrow::<StringHostObject>(this).iter_code_units().peekable();
-    let mut b_iter = env.objc.borrow::<StringHostObject>(other).iter_code_units().peekable();
-
-    // By default, no mask is a literal search
-    let mask = if mask == 0 {
-        NSLiteralSearch
-    } else {
-        mask
+    let a_iter = env.objc.borrow::<StringHostObject>(this).iter_code_units();
+    let b_iter = env.objc.borrow::<StringHostObject>(other).iter_code_units();
+    compare_code_units(a_iter, b_iter, mask)
+}
+- (NSComparisonResult)compare:(id)other
+                      options:(NSStringCompareOptions)mask
+                        range:(NSRange)range { // NSString*
+    let this_utf16 = to_utf16(env, this);
+    let Some(range) = checked_range(env, range, this_utf16.len()) else {
+        return NSOrderedSame;
     };
-
-    // TODO: OR'ing of compare options
-    match mask {
-        NSCaseInsensitiveSearch => {
-            loop {
-                let a_next = a_iter.next();
-                let b_next = b_iter.next();
-                let (Some(a_unit), Some(b_unit)) = (a_next, b_next) else {
-                    return from_rust_ordering(a_next.cmp(&b_next));
-                };
-                let (Some(a_c), Some(b_c)) = (char::from_u32(a_unit as u32), char::from_u32(b_unit as u32)) else {
-                    panic!("Invalid chars in the strings!");
-                };
-
-                let insensitive_order = a_c.to_lowercase().cmp(b_c.to_lowercase());
-                if insensitive_order != std::cmp::Ordering::Equal {
-                    return from_rust_ordering(insensitive_order);
-                }
-            }
-        },
-        NSLiteralSearch => {
-            from_rust_ordering(a_iter.cmp(b_iter))
-        },
-        NSNumericSearch => {
-            loop {
-                let a_next = a_iter.next();
-                let b_next = b_iter.next();
-                let (Some(a_unit), Some(b_unit)) = (a_next, b_next) else {
-                    return from_rust_ordering(a_next.cmp(&b_next));
-                };
-                let (Some(a_c), Some(b_c)) = (char::from_u32(a_unit as u32), char::from_u32(b_unit as u32)) else {
-                    panic!("Invalid chars in the strings!");
-                };
-
-                if a_c.is_ascii_digit() && b_c.is_ascii_digit() {
-                    let a_int = ascii_number(&mut a_iter, a_c);
-                    let b_int = ascii_number(&mut b_iter, b_c);
-
-                    let numeric_order = a_int.cmp(&b_int);
-                    if numeric_order != std::cmp::Ordering::Equal {
-                        return from_rust_ordering(numeric_order);
-                    }
-                } else {
-                    let char_order = a_c.cmp(&b_c);
-                    if char_order != std::cmp::Ordering::Equal {
-                        return from_rust_ordering(char_order);
-                    }
-                }
-            }
-        },
-        mask => unimplemented!("Other mask: {mask}"),
-    }
+    let other_utf16 = to_utf16(env, other);
+    compare_code_units(
+        this_utf16[range].iter().copied(),
+        other_utf16.iter().copied(),
+        mask,
+    )
 }
 
 // NSCopying implementation
@@ -485,21 +464,16 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (bool)getCString:(MutPtr<u8>)buffer
          maxLength:(NSUInteger)buffer_size
           encoding:(NSStringEncoding)encoding {
-    // TODO: other encodings
-    assert!(encoding == NSUTF8StringEncoding || encoding == NSASCIIStringEncoding);
-
-    let src = to_rust_string(env, this);
-    if encoding == NSASCIIStringEncoding {
-        assert!(src.as_bytes().iter().all(|byte| byte.is_ascii()));
-    }
+    let Some(mut src) = encode(env, this, encoding, false) else {
+        return false;
+    };
+    src.extend_from_slice(null_terminator(encoding));
     let dest = env.mem.bytes_at_mut(buffer, buffer_size);
-    if dest.len() < src.as_bytes().len() + 1 { // include null terminator
+    if dest.len() < src.len() {
         return false;
     }
 
-    for (i, &byte) in src.as_bytes().iter().chain(b"\0".iter()).enumerate() {
-        dest[i] = byte;
-    }
+    dest[..src.len()].copy_from_slice(&src);
 
     true
 }
@@ -560,10 +534,15 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (ConstPtr<u8>)cStringUsingEncoding:(NSStringEncoding)encoding {
-    // TODO: other encodings
-    assert!(encoding == NSUTF8StringEncoding || encoding == NSASCIIStringEncoding);
-    // FIXME: validate ASCII
-    msg![env; this UTF8String]
+    let Some(mut bytes) = encode(env, this, encoding, false) else {
+        return Ptr::null();
+    };
+    bytes.extend_from_slice(null_terminator(encoding));
+    // NSData will handle releasing the string (it is autoreleased)
+    let data = ns_data::from_rust_slice(env, &bytes);
+    autorelease(env, data);
+    let c_string: ConstVoidPtr = msg![env; data bytes];
+    c_string.cast()
 }
 
 - (ConstPtr<u8>)UTF8String {
@@ -577,6 +556,14 @@ pub const CLASSES: ClassExports = objc_classes! {
     c_string.cast_const()
 }
 
+- (id)substringWithRange:(NSRange)range {
+    let utf16 = to_utf16(env, this);
+    let Some(range) = checked_range(env, range, utf16.len()) else {
+        return nil;
+    };
+    from_utf16(env, utf16[range].to_vec())
+}
+
 - (id)substringToIndex:(NSUInteger)to {
     let mut res_utf16: Utf16String = Vec::with_capacity(to as usize);
 
@@ -632,24 +619,23 @@ pub const CLASSES: ClassExports = objc_classes! {
     assert!(res_end >= res_start);
     let res_length = res_end - res_start;
 
-    let res = if res_length == initial_length {
-        retain(env, this)
+    if res_length == initial_length {
+        let res = retain(env, this);
+        autorelease(env, res)
     } else {
-        // TODO: just call `substringWithRange:` here instead, the only reason
-        // the current code doesn't is that it would require figuring out the
-        // ABI of NSRange.
-        let mut res_utf16: Utf16String = Vec::with_capacity(res_length as usize);
-
-        for_each_code_unit(env, this, |idx, c| {
-            if res_start <= idx && idx < res_end {
-                res_utf16.push(c);
-            }
-        });
+        let range = NSRange { location: res_start, length: res_length };
+        msg![env; this substringWithRange:range]
+    }
+}
 
-        let res = msg_class![env; _touchHLE_NSString alloc];
-        *env.objc.borrow_mut(res) = StringHostObject::Utf16(res_utf16);
-        res
-    };
+- (id)lowercaseString {
+    let string = to_rust_string(env, this).to_lowercase();
+    let res = from_rust_string(env, string);
+    autorelease(env, res)
+}
+- (id)uppercaseString {
+    let string = to_rust_string(env, this).to_uppercase();
+    let res = from_rust_string(env, string);
     autorelease(env, res)
 }
 
@@ -869,14 +855,12 @@ pub const CLASSES: ClassExports = objc_classes! {
          atomically:(bool)use_aux_file
            encoding:(NSStringEncoding)encoding
               error:(MutPtr<id>)error { // NSError**
-    assert!(encoding == NSUTF8StringEncoding || encoding == NSASCIIStringEncoding);
-
-    let string = to_rust_string(env, this);
-    let c_string = env.mem.alloc_and_write_cstr(string.as_bytes());
-    let length: NSUInteger = (string.len() + 1).try_into().unwrap();
-    // NSData will handle releasing the string (it is autoreleased)
-    let data: id = msg_class![env; NSData dataWithBytesNoCopy:(c_string.cast_void())
-                                                    length:length];
+    let Some(bytes) = encode(env, this, encoding, false) else {
+        // TODO: create an NSError if requested
+        return false;
+    };
+    let data = ns_data::from_rust_slice(env, &bytes);
+    autorelease(env, data);
 
     let success: bool = msg![env; data writeToFile:path atomically:use_aux_file];
     if !success && !error.is_null() {
@@ -943,15 +927,24 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (id)initWithBytes:(ConstPtr<u8>)bytes
              length:(NSUInteger)len
            encoding:(NSStringEncoding)encoding {
-    // TODO: error handling
-    let slice = env.mem.bytes_at(bytes, len);
-    let host_object = StringHostObject::decode(Cow::Borrowed(slice), encoding);
+    let slice = if len == 0 { &[] } else { env.mem.bytes_at(bytes, len) };
+    let Some(host_object) = StringHostObject::decode(Cow::Borrowed(slice), encoding) else {
+        release(env, this);
+        return nil;
+    };
 
     *env.objc.borrow_mut(this) = host_object;
 
     this
 }
 
+- (id)initWithData:(id)data // NSData*
+          encoding:(NSStringEncoding)encoding {
+    let bytes: ConstVoidPtr = msg![env; data bytes];
+    let len: NSUInteger = msg![env; data length];
+    msg![env; this initWithBytes:(bytes.cast::<u8>()) length:len encoding:encoding]
+}
+
 - (id)initWithString:(id)string { // NSString *
     // TODO: optimize for more common cases (or maybe just call copy?)
     let mut code_units = Vec::new();
@@ -989,7 +982,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     let path = to_rust_string(env, path);
     let bytes = env.fs.read(GuestPath::new(&path)).unwrap();
 
-    let host_object = StringHostObject::decode(Cow::Owned(bytes), encoding);
+    let Some(host_object) = StringHostObject::decode(Cow::Owned(bytes), encoding) else {
+        release(env, this);
+        return nil;
+    };
 
     *env.objc.borrow_mut(this) = host_object;
 
@@ -1017,14 +1013,16 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (id)dataUsingEncoding:(NSStringEncoding)encoding {
-    assert!(encoding == NSUTF8StringEncoding || encoding == NSASCIIStringEncoding);
-
-    // TODO: refactor with UTF8String method
-    let string = to_rust_string(env, this);
-    let c_string = env.mem.alloc_and_write_cstr(string.as_bytes());
-    let length: NSUInteger = (string.len() + 1).try_into().unwrap();
+    msg![env; this dataUsingEncoding:encoding allowLossyConversion:false]
+}
 
-    msg_class![env; NSData dataWithBytesNoCopy:(c_string.cast_void()) length:length]
+- (id)dataUsingEncoding:(NSStringEncoding)encoding
+   allowLossyConversion:(bool)lossy {
+    let Some(bytes) = encode(env, this, encoding, lossy) else {
+        return nil;
+    };
+    let data = ns_data::from_rust_slice(env, &bytes);
+    autorelease(env, data)
 }
 
 @end
@@ -1166,3 +1164,243 @@ where
             idx += 1;
         });
 }
+
+/// Get a copy of the UTF-16 code units of a string.
+fn to_utf16(env: &mut Environment, string: id) -> Utf16String {
+    let mut utf16 = Vec::new();
+    for_each_code_unit(env, string, |_idx, c| utf16.push(c));
+    utf16
+}
+
+/// Create a new (autoreleased) string from UTF-16 code units.
+fn from_utf16(env: &mut Environment, utf16: Utf16String) -> id {
+    let res = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(res) = StringHostObject::Utf16(utf16);
+    autorelease(env, res)
+}
+
+/// Get the [encoding_rs] equivalent of an encoding that we don't decode or
+/// encode ourselves. Returns [None] if the encoding is unsupported, which
+/// callers treat like a conversion failure.
+fn legacy_encoding(encoding: NSStringEncoding) -> Option<&'static encoding_rs::Encoding> {
+    match encoding {
+        NSShiftJISStringEncoding => Some(encoding_rs::SHIFT_JIS),
+        NSWindowsCP1252StringEncoding => Some(encoding_rs::WINDOWS_1252),
+        NSMacOSRomanStringEncoding => Some(encoding_rs::MACINTOSH),
+        _ => {
+            log!("TODO: Unimplemented encoding: {:#x}", encoding);
+            None
+        }
+    }
+}
+
+/// Encode a string as bytes in some encoding, without a null terminator.
+/// Returns [None] if some character can't be represented in that encoding,
+/// unless `lossy` is [true], in which case it's replaced with `?`.
+fn encode(
+    env: &mut Environment,
+    string: id,
+    encoding: NSStringEncoding,
+    lossy: bool,
+) -> Option<Vec<u8>> {
+    match encoding {
+        NSUTF8StringEncoding => Some(to_rust_string(env, string).into_owned().into_bytes()),
+        NSASCIIStringEncoding | NSISOLatin1StringEncoding => {
+            let max = if encoding == NSASCIIStringEncoding {
+                0x7F
+            } else {
+                0xFF
+            };
+            // TODO: Apple's lossy conversion strips accents etc where possible
+            to_rust_string(env, string)
+                .chars()
+                .map(|c| match u8::try_from(c) {
+                    Ok(byte) if byte <= max => Some(byte),
+                    _ if lossy => Some(b'?'),
+                    _ => None,
+                })
+                .collect()
+        }
+        NSUTF16StringEncoding
+        | NSUTF16BigEndianStringEncoding
+        | NSUTF16LittleEndianStringEncoding => {
+            // The generic UTF-16 encoding gets a BOM and uses the native byte
+            // order, which is little-endian on the iPhone.
+            let bom = (encoding == NSUTF16StringEncoding).then_some(0xFEFF);
+            let is_big_endian = encoding == NSUTF16BigEndianStringEncoding;
+            let utf16 = to_utf16(env, string);
+            Some(
+                bom.into_iter()
+                    .chain(utf16)
+                    .flat_map(|c| {
+                        if is_big_endian {
+                            c.to_be_bytes()
+                        } else {
+                            c.to_le_bytes()
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        _ => {
+            let mut encoder = legacy_encoding(encoding)?.new_encoder();
+            let string = to_rust_string(env, string);
+            let mut remaining: &str = &string;
+            let mut bytes = Vec::new();
+            loop {
+                bytes.reserve(
+                    encoder
+                        .max_buffer_length_from_utf8_without_replacement(remaining.len())
+                        .unwrap(),
+                );
+                let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(
+                    remaining, &mut bytes, /* last: */ true,
+                );
+                remaining = &remaining[read..];
+                match result {
+                    encoding_rs::EncoderResult::InputEmpty => return Some(bytes),
+                    encoding_rs::EncoderResult::OutputFull => (),
+                    encoding_rs::EncoderResult::Unmappable(_) if lossy => bytes.push(b'?'),
+                    encoding_rs::EncoderResult::Unmappable(_) => return None,
+                }
+            }
+        }
+    }
+}
+
+/// The null terminator for a C string in some encoding.
+fn null_terminator(encoding: NSStringEncoding) -> &'static [u8] {
+    match encoding {
+        NSUTF16StringEncoding
+        | NSUTF16BigEndianStringEncoding
+        | NSUTF16LittleEndianStringEncoding => b"\0\0",
+        _ => b"\0",
+    }
+}
+
+/// Check that an [NSRange] is within a string of length `len` and convert it,
+/// or raise `NSRangeException` if it isn't.
+fn checked_range(
+    env: &mut Environment,
+    range: NSRange,
+    len: usize,
+) -> Option<std::ops::Range<usize>> {
+    let NSRange { location, length } = range;
+    let (start, range_len) = (location as usize, length as usize);
+    match start.checked_add(range_len) {
+        Some(end) if end <= len => Some(start..end),
+        _ => {
+            let reason = format!(
+                "Range {{{}, {}}} out of bounds; string length {}",
+                location, length, len
+            );
+            ns_exception::raise(env, "NSRangeException", reason);
+            None
+        }
+    }
+}
+
+/// Simple case folding of a UTF-16 code unit, for case-insensitive comparison
+/// and search. Characters whose lowercase form isn't a single character in the
+/// Basic Multilingual Plane are left alone.
+fn fold_case(c: u16) -> u16 {
+    let Some(ch) = char::from_u32(c.into()) else {
+        return c; // surrogate
+    };
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => u16::try_from(u32::from(lower)).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Find the position of `needle` within `haystack[range]`, for
+/// `rangeOfString:options:range:`. `NSCaseInsensitiveSearch`,
+/// `NSBackwardsSearch` and `NSAnchoredSearch` are supported.
+fn find_code_units(
+    haystack: &[u16],
+    needle: &[u16],
+    options: NSStringCompareOptions,
+    range: std::ops::Range<usize>,
+) -> Option<usize> {
+    // An empty string is never found.
+    if needle.is_empty() || needle.len() > range.len() {
+        return None;
+    }
+    let case_insensitive = options & NSCaseInsensitiveSearch != 0;
+    let matches_at = |i: usize| {
+        let candidate = &haystack[i..i + needle.len()];
+        if case_insensitive {
+            candidate
+                .iter()
+                .zip(needle)
+                .all(|(&a, &b)| fold_case(a) == fold_case(b))
+        } else {
+            candidate == needle
+        }
+    };
+
+    let first = range.start;
+    let last = range.end - needle.len();
+    let backwards = options & NSBackwardsSearch != 0;
+    if options & NSAnchoredSearch != 0 {
+        let i = if backwards { last } else { first };
+        matches_at(i).then_some(i)
+    } else if backwards {
+        (first..=last).rev().find(|&i| matches_at(i))
+    } else {
+        (first..=last).find(|&i| matches_at(i))
+    }
+}
+
+/// Compare strings as code units, for `compare:options:` and related methods.
+/// `NSCaseInsensitiveSearch` and `NSNumericSearch` are supported and can be
+/// combined. Comparison is always literal, so `NSLiteralSearch` has no effect.
+fn compare_code_units<A, B>(a: A, b: B, mask: NSStringCompareOptions) -> NSComparisonResult
+where
+    A: Iterator<Item = u16>,
+    B: Iterator<Item = u16>,
+{
+    fn is_digit(c: u16) -> bool {
+        (u16::from(b'0')..=u16::from(b'9')).contains(&c)
+    }
+    fn ascii_number<I: Iterator<Item = u16>>(iter: &mut Peekable<I>, leftmost_digit: u16) -> u64 {
+        let mut num = u64::from(leftmost_digit - u16::from(b'0'));
+        while let Some(digit) = iter.next_if(|&c| is_digit(c)) {
+            num = num
+                .saturating_mul(10)
+                .saturating_add(u64::from(digit - u16::from(b'0')));
+        }
+        num
+    }
+
+    let case_insensitive = mask & NSCaseInsensitiveSearch != 0;
+    let numeric = mask & NSNumericSearch != 0;
+    let unsupported = mask & !(NSCaseInsensitiveSearch | NSLiteralSearch | NSNumericSearch);
+    if unsupported != 0 {
+        log!("TODO: string comparison options {:#x}", unsupported);
+    }
+
+    let mut a_iter = a.peekable();
+    let mut b_iter = b.peekable();
+    loop {
+        let a_next = a_iter.next();
+        let b_next = b_iter.next();
+        let (Some(a_c), Some(b_c)) = (a_next, b_next) else {
+            return from_rust_ordering(a_next.cmp(&b_next));
+        };
+
+        let order = if numeric && is_digit(a_c) && is_digit(b_c) {
+            let a_int = ascii_number(&mut a_iter, a_c);
+            let b_int = ascii_number(&mut b_iter, b_c);
+            a_int.cmp(&b_int)
+        } else if case_insensitive {
+            fold_case(a_c).cmp(&fold_case(b_c))
+        } else {
+            a_c.cmp(&b_c)
+        };
+        if order != std::cmp::Ordering::Equal {
+            return from_rust_ordering(order);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSTimeZone`.
+//!
+//! This uses the same time zone database as the C library, see
+//! [crate::libc::time::zone].
+
+use super::{ns_string, NSInteger, NSTimeInterval};
+use crate::frameworks::core_foundation::time::apple_epoch;
+use crate::libc::time::local_time_zone;
+use crate::libc::time::zone::TimeZone;
+use crate::objc::{
+    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject,
+};
+use crate::Environment;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+#[derive(Default)]
+pub struct State {
+    system_time_zone: Option<id>,
+    default_time_zone: Option<id>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut State {
+        &mut env.framework_state.foundation.ns_time_zone
+    }
+}
+
+struct NSTimeZoneHostObject {
+    zone: Rc<TimeZone>,
+    /// `NSString*`
+    name: id,
+}
+impl HostObject for NSTimeZoneHostObject {}
+
+/// Create a new (retained) `NSTimeZone`.
+fn new_time_zone(env: &mut Environment, zone: Rc<TimeZone>) -> id {
+    let name = ns_string::from_rust_string(env, zone.name().to_string());
+    let host_object = Box::new(NSTimeZoneHostObject { zone, name });
+    let class = env.objc.get_known_class("NSTimeZone", &mut env.mem);
+    env.objc.alloc_object(class, host_object, &mut env.mem)
+}
+
+/// Get the time zone database entry for an `NSTimeZone`, e.g. for
+/// `CFTimeZoneRef` functions.
+pub fn time_zone_for(env: &mut Environment, time_zone: id) -> Rc<TimeZone> {
+    env.objc
+        .borrow::<NSTimeZoneHostObject>(time_zone)
+        .zone
+        .clone()
+}
+
+/// Convert an `NSDate*` (or [nil] for the current time) to seconds since the
+/// UNIX epoch.
+fn unix_time_for_date(env: &mut Environment, date: id) -> i64 {
+    let unix_epoch_offset = apple_epoch()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    let interval: NSTimeInterval = if date == nil {
+        SystemTime::now()
+            .duration_since(apple_epoch())
+            .unwrap()
+            .as_secs_f64()
+    } else {
+        msg![env; date timeIntervalSinceReferenceDate]
+    };
+    (interval + unix_epoch_offset).floor() as i64
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSTimeZone: NSObject
+
++ (id)systemTimeZone {
+    if let Some(existing) = State::get(env).system_time_zone {
+        return existing;
+    }
+    let zone = local_time_zone(env);
+    let new = new_time_zone(env, zone);
+    State::get(env).system_time_zone = Some(new);
+    new
+}
+
++ (id)localTimeZone {
+    // TODO: this should be a proxy that follows the default time zone.
+    msg![env; this defaultTimeZone]
+}
+
++ (id)defaultTimeZone {
+    if let Some(existing) = State::get(env).default_time_zone {
+        existing
+    } else {
+        msg![env; this systemTimeZone]
+    }
+}
+
++ (())setDefaultTimeZone:(id)time_zone {
+    retain(env, time_zone);
+    if let Some(old) = State::get(env).default_time_zone.replace(time_zone) {
+        release(env, old);
+    }
+}
+
++ (())resetSystemTimeZone {
+    // The host time zone isn't expected to change while running.
+}
+
++ (id)timeZoneWithName:(id)name { // NSString*
+    let name = ns_string::to_rust_string(env, name);
+    let Some(zone) = TimeZone::from_name(&name) else {
+        log!("Warning: unknown time zone {:?}, returning nil", name);
+        return nil;
+    };
+    let new = new_time_zone(env, Rc::new(zone));
+    autorelease(env, new)
+}
+
++ (id)timeZoneWithAbbreviation:(id)abbreviation { // NSString*
+    let abbreviation = ns_string::to_rust_string(env, abbreviation);
+    let zone = match &*abbreviation {
+        "UTC" | "GMT" => TimeZone::from_name(&abbreviation).unwrap(),
+        _ => {
+            log!("TODO: time zone abbreviation {:?}, returning nil", abbreviation);
+            return nil;
+        }
+    };
+    let new = new_time_zone(env, Rc::new(zone));
+    autorelease(env, new)
+}
+
++ (id)timeZoneForSecondsFromGMT:(NSInteger)seconds {
+    let new = new_time_zone(env, Rc::new(TimeZone::with_offset(seconds)));
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let name = env.objc.borrow::<NSTimeZoneHostObject>(this).name;
+    release(env, name);
+    env.objc.dealloc_object(this, &mut env.mem);
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(crate::objc::NSZonePtr)_zone {
+    // Time zones are immutable.
+    retain(env, this)
+}
+
+- (id)name {
+    env.objc.borrow::<NSTimeZoneHostObject>(this).name
+}
+
+- (id)description {
+    env.objc.borrow::<NSTimeZoneHostObject>(this).name
+}
+
+- (id)abbreviation {
+    msg![env; this abbreviationForDate:nil]
+}
+
+- (id)abbreviationForDate:(id)date { // NSDate*
+    let time = unix_time_for_date(env, date);
+    let zone = time_zone_for(env, this);
+    let abbreviation = zone.local_time_type(time).abbreviation.clone();
+    let abbreviation = ns_string::from_rust_string(env, abbreviation);
+    autorelease(env, abbreviation)
+}
+
+- (NSInteger)secondsFromGMT {
+    msg![env; this secondsFromGMTForDate:nil]
+}
+
+- (NSInteger)secondsFromGMTForDate:(id)date { // NSDate*
+    let time = unix_time_for_date(env, date);
+    time_zone_for(env, this).local_time_type(time).utc_offset
+}
+
+- (bool)isDaylightSavingTime {
+    msg![env; this isDaylightSavingTimeForDate:nil]
+}
+
+- (bool)isDaylightSavingTimeForDate:(id)date { // NSDate*
+    let time = unix_time_for_date(env, date);
+    time_zone_for(env, this).local_time_type(time).is_dst
+}
+
+- (bool)isEqualToTimeZone:(id)other {
+    if other == nil {
+        return false;
+    }
+    let this_zone = time_zone_for(env, this);
+    let other_zone = time_zone_for(env, other);
+    Rc::ptr_eq(&this_zone, &other_zone) || this_zone.name() == other_zone.name()
+}
+
+@end
+
+};
@@ -37,7 +37,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
-@implementation NSURL: NSObject
+@implementation NSURL: NSObject <NSCopying, NSCoding>
 
 + (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = NSURLHostObject::FileURL { ns_string: nil, working_directory: env.fs.working_directory().into() };
@@ -117,6 +117,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
 }
 
+- (bool)isFileURL {
+    matches!(env.objc.borrow(this), NSURLHostObject::FileURL { .. })
+}
+
 - (id)path {
     match *env.objc.borrow(this) {
         NSURLHostObject::FileURL { ns_string, .. } => ns_string,
@@ -0,0 +1,174 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSUUID`.
+//!
+//! This is also used to implement `CFUUID`, see
+//! [crate::frameworks::core_foundation::cf_uuid].
+//!
+//! Resources:
+//! - [RFC 4122](https://www.rfc-editor.org/rfc/rfc4122), which defines the
+//!   string format and the version 4 (random) UUIDs generated here.
+
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::NSUInteger;
+use crate::mem::{ConstPtr, MutPtr};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
+    HostObject, NSZonePtr,
+};
+use crate::Environment;
+
+pub type UUIDBytes = [u8; 16];
+
+struct NSUUIDHostObject {
+    bytes: UUIDBytes,
+}
+impl HostObject for NSUUIDHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSUUID: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSUUIDHostObject { bytes: [0; 16] });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)UUID {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+    autorelease(env, new)
+}
+
+- (id)init {
+    let bytes = random_uuid();
+    env.objc.borrow_mut::<NSUUIDHostObject>(this).bytes = bytes;
+    this
+}
+
+- (id)initWithUUIDString:(id)string { // NSString*
+    let string = to_rust_string(env, string);
+    let Some(bytes) = parse_uuid(&string) else {
+        release(env, this);
+        return nil;
+    };
+    env.objc.borrow_mut::<NSUUIDHostObject>(this).bytes = bytes;
+    this
+}
+
+- (id)initWithUUIDBytes:(ConstPtr<u8>)bytes {
+    let bytes = env.mem.bytes_at(bytes, 16).try_into().unwrap();
+    env.objc.borrow_mut::<NSUUIDHostObject>(this).bytes = bytes;
+    this
+}
+
+- (())getUUIDBytes:(MutPtr<u8>)buffer {
+    let bytes = env.objc.borrow::<NSUUIDHostObject>(this).bytes;
+    env.mem.bytes_at_mut(buffer, 16).copy_from_slice(&bytes);
+}
+
+- (id)UUIDString {
+    let string = format_uuid(&env.objc.borrow::<NSUUIDHostObject>(this).bytes);
+    let string = from_rust_string(env, string);
+    autorelease(env, string)
+}
+
+- (id)description {
+    msg![env; this UUIDString]
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(NSZonePtr)_zone {
+    // UUIDs are immutable.
+    retain(env, this)
+}
+
+- (NSUInteger)hash {
+    super::hash_helper(&env.objc.borrow::<NSUUIDHostObject>(this).bytes)
+}
+- (bool)isEqual:(id)other {
+    if this == other {
+        return true;
+    }
+    if other == nil {
+        return false;
+    }
+    let class: Class = msg_class![env; NSUUID class];
+    if !msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    uuid_bytes(env, this) == uuid_bytes(env, other)
+}
+
+@end
+
+};
+
+/// Generate a new random (version 4) UUID. The randomness comes from the host,
+/// so that generating a UUID doesn't change the sequence of random numbers
+/// `arc4random()` gives the app.
+pub fn random_uuid() -> UUIDBytes {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // Each RandomState is keyed differently by the standard library, which
+        // saves us a dependency.
+        let random = RandomState::new().build_hasher().finish();
+        chunk.copy_from_slice(&random.to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+    bytes
+}
+
+/// Format a UUID the way Apple does, e.g.
+/// `"68753A44-4D6F-1226-9C60-0050E4C00067"`.
+pub fn format_uuid(bytes: &UUIDBytes) -> String {
+    let mut string = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            string.push('-');
+        }
+        string.push_str(&format!("{:02X}", byte));
+    }
+    string
+}
+
+/// Parse a UUID in the format produced by [format_uuid]. Lowercase is also
+/// accepted.
+pub fn parse_uuid(string: &str) -> Option<UUIDBytes> {
+    let parts: Vec<&str> = string.split('-').collect();
+    if parts.iter().map(|part| part.len()).collect::<Vec<_>>() != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    let hex = parts.concat();
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let digits = hex.get(i * 2..i * 2 + 2)?;
+        if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Shortcut for host code: create a new `NSUUID` (retained, not autoreleased)
+/// with the given bytes.
+pub fn uuid_with_bytes(env: &mut Environment, bytes: UUIDBytes) -> id {
+    let new: id = msg_class![env; NSUUID alloc];
+    env.objc.borrow_mut::<NSUUIDHostObject>(new).bytes = bytes;
+    new
+}
+
+/// Shortcut for host code: get the bytes of an `NSUUID`.
+pub fn uuid_bytes(env: &mut Environment, uuid: id) -> UUIDBytes {
+    env.objc.borrow::<NSUUIDHostObject>(uuid).bytes
+}
@@ -5,14 +5,42 @@
  */
 //! The `NSValue` class cluster, including `NSNumber`.
 
-use super::NSUInteger;
+use super::ns_method_signature::type_size;
+use super::{ns_exception, NSRange, NSUInteger};
+use crate::frameworks::core_graphics::cg_affine_transform::CGAffineTransform;
+use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
 use crate::frameworks::foundation::ns_string::from_rust_string;
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutVoidPtr};
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
+    autorelease, id, msg, msg_class, nil, objc_classes, retain, Class, ClassExports, HostObject,
     NSZonePtr,
 };
+use crate::Environment;
 
-enum NSNumberHostObject {
+/// Host object for `NSValue` instances that aren't `NSNumber`s. The structure
+/// types that apps are known to box have their own variants, and anything else
+/// is kept as raw bytes.
+#[derive(Clone, PartialEq)]
+enum NSValueHostObject {
+    CGPoint(CGPoint),
+    CGSize(CGSize),
+    CGRect(CGRect),
+    CGAffineTransform(CGAffineTransform),
+    NSRange(NSRange),
+    Bytes {
+        /// The type encoding passed to `valueWithBytes:objCType:`.
+        objc_type: String,
+        bytes: Vec<u8>,
+    },
+}
+impl HostObject for NSValueHostObject {}
+
+fn new_value(env: &mut crate::Environment, class: Class, value: NSValueHostObject) -> id {
+    let new = env.objc.alloc_object(class, Box::new(value), &mut env.mem);
+    autorelease(env, new)
+}
+
+pub(super) enum NSNumberHostObject {
     Bool(bool),
     UnsignedLongLong(u64),
     LongLong(i64),
@@ -25,9 +53,161 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
-// NSValue is an abstract class. None of the things it should provide are
-// implemented here yet (TODO).
-@implementation NSValue: NSObject
+// NSValue is an abstract class, but only NSNumber is a real subclass here, so
+// the structure-boxing methods create NSValue instances directly.
+@implementation NSValue: NSObject <NSCopying, NSCoding>
+
+// These are from UIKit's and Foundation's NSValue categories.
++ (id)valueWithCGPoint:(CGPoint)point {
+    new_value(env, this, NSValueHostObject::CGPoint(point))
+}
++ (id)valueWithCGSize:(CGSize)size {
+    new_value(env, this, NSValueHostObject::CGSize(size))
+}
++ (id)valueWithCGRect:(CGRect)rect {
+    new_value(env, this, NSValueHostObject::CGRect(rect))
+}
++ (id)valueWithCGAffineTransform:(CGAffineTransform)transform {
+    new_value(env, this, NSValueHostObject::CGAffineTransform(transform))
+}
++ (id)valueWithRange:(NSRange)range {
+    new_value(env, this, NSValueHostObject::NSRange(range))
+}
+
++ (id)valueWithPointer:(ConstVoidPtr)pointer {
+    let value = NSValueHostObject::Bytes {
+        objc_type: "^v".to_string(),
+        bytes: pointer.to_bits().to_le_bytes().to_vec(),
+    };
+    new_value(env, this, value)
+}
+
++ (id)valueWithBytes:(ConstVoidPtr)value
+            objCType:(ConstPtr<u8>)type_ {
+    let type_ = String::from_utf8_lossy(env.mem.cstr_at(type_)).into_owned();
+    let Some(size) = type_size(&type_) else {
+        let reason = format!("Unsupported type encoding {:?}", type_);
+        ns_exception::raise(env, "NSInvalidArgumentException", reason);
+        return nil;
+    };
+    let value = match struct_name(&type_) {
+        Some("CGPoint") => NSValueHostObject::CGPoint(env.mem.read(value.cast())),
+        Some("CGSize") => NSValueHostObject::CGSize(env.mem.read(value.cast())),
+        Some("CGRect") => NSValueHostObject::CGRect(env.mem.read(value.cast())),
+        Some("CGAffineTransform") => {
+            NSValueHostObject::CGAffineTransform(env.mem.read(value.cast()))
+        },
+        Some("_NSRange") => NSValueHostObject::NSRange(env.mem.read(value.cast())),
+        _ => NSValueHostObject::Bytes {
+            bytes: env.mem.bytes_at(value.cast(), size).to_vec(),
+            objc_type: type_,
+        },
+    };
+    new_value(env, this, value)
+}
+
+- (CGPoint)CGPointValue {
+    let &NSValueHostObject::CGPoint(point) = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain a CGPoint", this);
+    };
+    point
+}
+- (CGSize)CGSizeValue {
+    let &NSValueHostObject::CGSize(size) = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain a CGSize", this);
+    };
+    size
+}
+- (CGRect)CGRectValue {
+    let &NSValueHostObject::CGRect(rect) = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain a CGRect", this);
+    };
+    rect
+}
+- (CGAffineTransform)CGAffineTransformValue {
+    let &NSValueHostObject::CGAffineTransform(transform) = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain a CGAffineTransform", this);
+    };
+    transform
+}
+- (NSRange)rangeValue {
+    let &NSValueHostObject::NSRange(range) = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain an NSRange", this);
+    };
+    range
+}
+
+- (ConstVoidPtr)pointerValue {
+    let NSValueHostObject::Bytes { ref bytes, .. } = env.objc.borrow(this) else {
+        panic!("NSValue {:?} does not contain a pointer", this);
+    };
+    let Ok(bytes) = bytes[..].try_into() else {
+        panic!("NSValue {:?} does not contain a pointer", this);
+    };
+    ConstVoidPtr::from_bits(u32::from_le_bytes(bytes))
+}
+
+- (())getValue:(MutVoidPtr)buffer {
+    let value: NSValueHostObject = env.objc.borrow::<NSValueHostObject>(this).clone();
+    match value {
+        NSValueHostObject::CGPoint(point) => env.mem.write(buffer.cast(), point),
+        NSValueHostObject::CGSize(size) => env.mem.write(buffer.cast(), size),
+        NSValueHostObject::CGRect(rect) => env.mem.write(buffer.cast(), rect),
+        NSValueHostObject::CGAffineTransform(transform) => {
+            env.mem.write(buffer.cast(), transform)
+        },
+        NSValueHostObject::NSRange(range) => env.mem.write(buffer.cast(), range),
+        NSValueHostObject::Bytes { bytes, .. } => {
+            let size = bytes.len().try_into().unwrap();
+            env.mem
+                .bytes_at_mut(buffer.cast(), size)
+                .copy_from_slice(&bytes)
+        },
+    }
+}
+
+- (id)description {
+    // The prefixes match what Apple's implementation prints.
+    let description = match *env.objc.borrow(this) {
+        NSValueHostObject::CGPoint(point) => format!("NSPoint: {}", point),
+        NSValueHostObject::CGSize(size) => format!("NSSize: {}", size),
+        NSValueHostObject::CGRect(rect) => format!("NSRect: {}", rect),
+        NSValueHostObject::CGAffineTransform(CGAffineTransform { a, b, c, d, tx, ty }) => {
+            format!("CGAffineTransform: {{{{{}, {}, {}, {}}}, {{{}, {}}}}}", a, b, c, d, tx, ty)
+        },
+        NSValueHostObject::NSRange(range) => format!("NSRange: {}", range),
+        // Other types are printed like NSData.
+        NSValueHostObject::Bytes { ref bytes, .. } => {
+            let mut description = String::from("<");
+            for (i, byte) in bytes.iter().enumerate() {
+                if i > 0 && i % 4 == 0 {
+                    description.push(' ');
+                }
+                description.push_str(&format!("{:02x}", byte));
+            }
+            description.push('>');
+            description
+        },
+    };
+    let description = from_rust_string(env, description);
+    autorelease(env, description)
+}
+
+- (bool)isEqualToValue:(id)other {
+    if this == other {
+        return true;
+    }
+    let class: Class = msg_class![env; NSNumber class];
+    if msg![env; this isKindOfClass:class] {
+        return msg![env; this isEqualTo:other];
+    }
+    if msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    let a: &NSValueHostObject = env.objc.borrow(this);
+    let b: &NSValueHostObject = env.objc.borrow(other);
+    a == b
+}
 
 // NSCopying implementation
 - (id)copyWithZone:(NSZonePtr)_zone {
@@ -143,8 +323,172 @@ pub const CLASSES: ClassExports = objc_classes! {
     a == b
 }
 
-// TODO: accessors etc
+- (bool)boolValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value,
+        NSNumberHostObject::UnsignedLongLong(value) => value != 0,
+        NSNumberHostObject::LongLong(value) => value != 0,
+        NSNumberHostObject::Float(value) => value != 0.0,
+        NSNumberHostObject::Double(value) => value != 0.0,
+    }
+}
+
+- (f64)doubleValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value as u8 as f64,
+        NSNumberHostObject::UnsignedLongLong(value) => value as f64,
+        NSNumberHostObject::LongLong(value) => value as f64,
+        NSNumberHostObject::Float(value) => value.into(),
+        NSNumberHostObject::Double(value) => value,
+    }
+}
+- (f32)floatValue {
+    let value: f64 = msg![env; this doubleValue];
+    value as f32
+}
+
+- (i64)longLongValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value.into(),
+        NSNumberHostObject::UnsignedLongLong(value) => value as i64,
+        NSNumberHostObject::LongLong(value) => value,
+        NSNumberHostObject::Float(value) => value as i64,
+        NSNumberHostObject::Double(value) => value as i64,
+    }
+}
+- (i32)intValue {
+    let value: i64 = msg![env; this longLongValue];
+    value as i32
+}
+- (u32)unsignedIntValue {
+    let value: i64 = msg![env; this longLongValue];
+    value as u32
+}
+- (u64)unsignedLongLongValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::UnsignedLongLong(value) => value,
+        _ => {
+            let value: i64 = msg![env; this longLongValue];
+            value as u64
+        }
+    }
+}
+
+// TODO: other accessors etc
 
 @end
 
 };
+
+/// Get the name of a structure type from its encoding, e.g. `"CGPoint"` for
+/// `"{CGPoint=ff}"`.
+fn struct_name(encoding: &str) -> Option<&str> {
+    let encoding = encoding.strip_prefix('{')?;
+    let end = encoding.find(['=', '}'])?;
+    Some(&encoding[..end])
+}
+
+/// Shortcut for host code, used by key-value coding: box a value with the
+/// given (unqualified) type encoding in an `NSNumber` or `NSValue`, which is
+/// autoreleased.
+pub(super) fn box_value(env: &mut Environment, encoding: &str, bytes: &[u8]) -> id {
+    fn word<const N: usize>(bytes: &[u8]) -> [u8; N] {
+        bytes[..N].try_into().unwrap()
+    }
+    match encoding.as_bytes().first() {
+        Some(b'B') => msg_class![env; NSNumber numberWithBool:(bytes[0] != 0)],
+        Some(b'c') => {
+            let value: i64 = i8::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'C') => {
+            let value: i64 = u8::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b's') => {
+            let value: i64 = i16::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'S') => {
+            let value: i64 = u16::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'i' | b'l') => {
+            let value: i64 = i32::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'I' | b'L') => {
+            let value: i64 = u32::from_le_bytes(word(bytes)).into();
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'q') => {
+            let value = i64::from_le_bytes(word(bytes));
+            msg_class![env; NSNumber numberWithLongLong:value]
+        }
+        Some(b'Q') => {
+            let value = u64::from_le_bytes(word(bytes));
+            msg_class![env; NSNumber numberWithUnsignedLongLong:value]
+        }
+        Some(b'f') => {
+            let value = f32::from_le_bytes(word(bytes));
+            msg_class![env; NSNumber numberWithFloat:value]
+        }
+        Some(b'd') => {
+            let value = f64::from_le_bytes(word(bytes));
+            msg_class![env; NSNumber numberWithDouble:value]
+        }
+        // Structures, pointers, selectors, classes and anything else that
+        // isn't a number. NSValue raises an exception if the type is invalid.
+        _ => {
+            let buffer = env.mem.alloc(bytes.len().try_into().unwrap());
+            env.mem
+                .bytes_at_mut(buffer.cast(), bytes.len().try_into().unwrap())
+                .copy_from_slice(bytes);
+            let type_ = env.mem.alloc_and_write_cstr(encoding.as_bytes());
+            let value: id = msg_class![env; NSValue valueWithBytes:(buffer.cast_const())
+                                                          objCType:(type_.cast_const())];
+            env.mem.free(type_.cast());
+            env.mem.free(buffer);
+            value
+        }
+    }
+}
+
+/// Shortcut for host code, used by key-value coding: the reverse of
+/// [box_value]. `size` is the size of the type.
+pub(super) fn unbox_value(
+    env: &mut Environment,
+    encoding: &str,
+    size: GuestUSize,
+    value: id,
+) -> Vec<u8> {
+    match encoding.as_bytes().first() {
+        Some(b'B') => {
+            let value: bool = msg![env; value boolValue];
+            vec![value.into()]
+        }
+        Some(b'c' | b'C' | b's' | b'S' | b'i' | b'I' | b'l' | b'L' | b'q') => {
+            let value: i64 = msg![env; value longLongValue];
+            value.to_le_bytes()[..size as usize].to_vec()
+        }
+        Some(b'Q') => {
+            let value: u64 = msg![env; value unsignedLongLongValue];
+            value.to_le_bytes().to_vec()
+        }
+        Some(b'f') => {
+            let value: f32 = msg![env; value floatValue];
+            value.to_le_bytes().to_vec()
+        }
+        Some(b'd') => {
+            let value: f64 = msg![env; value doubleValue];
+            value.to_le_bytes().to_vec()
+        }
+        _ => {
+            let buffer = env.mem.alloc(size);
+            () = msg![env; value getValue:buffer];
+            let bytes = env.mem.bytes_at(buffer.cast(), size).to_vec();
+            env.mem.free(buffer);
+            bytes
+        }
+    }
+}
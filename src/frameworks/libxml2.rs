@@ -0,0 +1,1679 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! libxml2 (`libxml2.dylib`).
+//!
+//! Like zlib and SQLite, this isn't a framework, but a system library that
+//! apps link to dynamically. Games tend to use it for parsing level data.
+//! Only a commonly-used subset is implemented:
+//!
+//! - Parsing a whole document into a tree. The tree is built in guest memory
+//!   with the same layout as in the real libxml2, because apps walk it
+//!   directly rather than using accessor functions.
+//! - SAX parsing, including the SAX2 namespace-aware callbacks.
+//! - Some accessors for the tree, and XPath queries that are simple location
+//!   paths. Since these work on the guest tree, they see any changes the app
+//!   makes to it.
+//!
+//! The XML parsing itself is done on the host with the `xml-rs` crate, which
+//! doesn't support DTDs, so documents that rely on them won't work.
+//!
+//! Resources:
+//! - [libxml2 API reference](http://xmlsoft.org/html/index.html)
+
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::fs::GuestPath;
+use crate::mem::{
+    guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead,
+    SafeWrite,
+};
+use crate::Environment;
+use std::collections::{BTreeMap, HashMap};
+use xml::common::Position;
+use xml::name::OwnedName;
+use xml::reader::{ParserConfig, XmlEvent};
+
+#[allow(non_camel_case_types)]
+type xmlChar = u8;
+
+/// `xmlElementType`
+const XML_ELEMENT_NODE: u32 = 1;
+const XML_ATTRIBUTE_NODE: u32 = 2;
+const XML_TEXT_NODE: u32 = 3;
+const XML_CDATA_SECTION_NODE: u32 = 4;
+const XML_PI_NODE: u32 = 7;
+const XML_COMMENT_NODE: u32 = 8;
+const XML_DOCUMENT_NODE: u32 = 9;
+const XML_NAMESPACE_DECL: u32 = 18;
+
+/// `xmlParserOption`
+const XML_PARSE_NOBLANKS: i32 = 1 << 8;
+const XML_PARSE_NOCDATA: i32 = 1 << 14;
+
+/// `xmlParserErrors`
+const XML_ERR_OK: i32 = 0;
+const XML_ERR_INTERNAL_ERROR: i32 = 1;
+
+const XML_SAX2_MAGIC: u32 = 0xDEEDBEAF;
+
+/// `xmlXPathObjectType`
+const XPATH_NODESET: u32 = 1;
+const XPATH_NUMBER: u32 = 3;
+
+/// The fields shared by [xmlNode], [xmlAttr] and [xmlDoc], which are always
+/// at the start of those structs.
+#[repr(C, packed)]
+struct NodeCommon {
+    _private: MutVoidPtr,
+    type_: u32,
+    name: ConstPtr<xmlChar>,
+    children: MutPtr<xmlNode>,
+    last: MutPtr<xmlNode>,
+    parent: MutPtr<xmlNode>,
+    next: MutPtr<xmlNode>,
+    prev: MutPtr<xmlNode>,
+    doc: MutPtr<xmlDoc>,
+}
+unsafe impl SafeRead for NodeCommon {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlNs {
+    next: MutPtr<xmlNs>,
+    type_: u32,
+    href: ConstPtr<xmlChar>,
+    prefix: ConstPtr<xmlChar>,
+    _private: MutVoidPtr,
+    context: MutPtr<xmlDoc>,
+}
+unsafe impl SafeRead for xmlNs {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlNode {
+    _private: MutVoidPtr,
+    type_: u32,
+    name: ConstPtr<xmlChar>,
+    children: MutPtr<xmlNode>,
+    last: MutPtr<xmlNode>,
+    parent: MutPtr<xmlNode>,
+    next: MutPtr<xmlNode>,
+    prev: MutPtr<xmlNode>,
+    doc: MutPtr<xmlDoc>,
+    ns: MutPtr<xmlNs>,
+    content: MutPtr<xmlChar>,
+    properties: MutPtr<xmlAttr>,
+    ns_def: MutPtr<xmlNs>,
+    psvi: MutVoidPtr,
+    line: u16,
+    extra: u16,
+}
+unsafe impl SafeRead for xmlNode {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlAttr {
+    _private: MutVoidPtr,
+    type_: u32,
+    name: ConstPtr<xmlChar>,
+    children: MutPtr<xmlNode>,
+    last: MutPtr<xmlNode>,
+    parent: MutPtr<xmlNode>,
+    next: MutPtr<xmlAttr>,
+    prev: MutPtr<xmlAttr>,
+    doc: MutPtr<xmlDoc>,
+    ns: MutPtr<xmlNs>,
+    atype: u32,
+    psvi: MutVoidPtr,
+}
+unsafe impl SafeRead for xmlAttr {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlDoc {
+    _private: MutVoidPtr,
+    type_: u32,
+    name: ConstPtr<u8>,
+    children: MutPtr<xmlNode>,
+    last: MutPtr<xmlNode>,
+    parent: MutPtr<xmlNode>,
+    next: MutPtr<xmlNode>,
+    prev: MutPtr<xmlNode>,
+    doc: MutPtr<xmlDoc>,
+    compression: i32,
+    standalone: i32,
+    int_subset: MutVoidPtr,
+    ext_subset: MutVoidPtr,
+    old_ns: MutPtr<xmlNs>,
+    version: ConstPtr<xmlChar>,
+    encoding: ConstPtr<xmlChar>,
+    ids: MutVoidPtr,
+    refs: MutVoidPtr,
+    url: ConstPtr<xmlChar>,
+    charset: i32,
+    dict: MutVoidPtr,
+    psvi: MutVoidPtr,
+    parse_flags: i32,
+    properties: i32,
+}
+unsafe impl SafeRead for xmlDoc {}
+
+/// `xmlSAXHandler`. Only the callbacks that are used are named.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlSAXHandler {
+    _unused_callbacks: [GuestFunction; 12],
+    start_document: GuestFunction,
+    end_document: GuestFunction,
+    start_element: GuestFunction,
+    end_element: GuestFunction,
+    reference: GuestFunction,
+    characters: GuestFunction,
+    ignorable_whitespace: GuestFunction,
+    processing_instruction: GuestFunction,
+    comment: GuestFunction,
+    warning: GuestFunction,
+    error: GuestFunction,
+    fatal_error: GuestFunction,
+    get_parameter_entity: GuestFunction,
+    cdata_block: GuestFunction,
+    external_subset: GuestFunction,
+    initialized: u32,
+    _private: MutVoidPtr,
+    start_element_ns: GuestFunction,
+    end_element_ns: GuestFunction,
+    serror: GuestFunction,
+}
+unsafe impl SafeRead for xmlSAXHandler {}
+
+/// The start of `xmlParserCtxt`. The real struct is much bigger, but these are
+/// the only fields an app is likely to look at.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlParserCtxt {
+    sax: ConstPtr<xmlSAXHandler>,
+    user_data: MutVoidPtr,
+    my_doc: MutPtr<xmlDoc>,
+    well_formed: i32,
+}
+unsafe impl SafeRead for xmlParserCtxt {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlNodeSet {
+    node_nr: i32,
+    node_max: i32,
+    node_tab: MutPtr<MutPtr<xmlNode>>,
+}
+unsafe impl SafeRead for xmlNodeSet {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlXPathObject {
+    type_: u32,
+    nodesetval: MutPtr<xmlNodeSet>,
+    boolval: i32,
+    floatval: f64,
+    stringval: MutPtr<xmlChar>,
+    user: MutVoidPtr,
+    index: i32,
+    user2: MutVoidPtr,
+    index2: i32,
+}
+unsafe impl SafeRead for xmlXPathObject {}
+
+/// The start of `xmlXPathContext`. The rest of the real struct is about
+/// extensions (variables, functions, namespaces) that aren't supported.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct xmlXPathContext {
+    doc: MutPtr<xmlDoc>,
+    node: MutPtr<xmlNode>,
+}
+unsafe impl SafeRead for xmlXPathContext {}
+
+#[derive(Default)]
+pub struct State {
+    /// Everything allocated for each document's tree, so `xmlFreeDoc` can free
+    /// it.
+    documents: HashMap<MutPtr<xmlDoc>, Vec<MutVoidPtr>>,
+    /// Data received so far by each push parser.
+    push_parsers: HashMap<MutPtr<xmlParserCtxt>, Vec<u8>>,
+    /// Inverse of `xmlKeepBlanksDefault()`.
+    no_blanks_default: bool,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.libxml2
+    }
+}
+
+fn parser_config(options: i32) -> ParserConfig {
+    ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(false)
+        .cdata_to_characters(options & XML_PARSE_NOCDATA != 0)
+        .ignore_comments(false)
+        .coalesce_characters(true)
+}
+
+fn free_if_not_null<T>(mem: &mut Mem, ptr: MutPtr<T>) {
+    if !ptr.is_null() {
+        mem.free(ptr.cast());
+    }
+}
+
+/// Builds a document tree in guest memory.
+struct TreeBuilder<'a> {
+    mem: &'a mut Mem,
+    doc: MutPtr<xmlDoc>,
+    allocations: Vec<MutVoidPtr>,
+    /// Names are shared between nodes, like with libxml2's dictionary.
+    names: HashMap<String, ConstPtr<xmlChar>>,
+    namespaces: HashMap<(Option<String>, String), MutPtr<xmlNs>>,
+}
+impl TreeBuilder<'_> {
+    fn alloc<T: SafeWrite>(&mut self, value: T) -> MutPtr<T> {
+        let ptr = self.mem.alloc_and_write(value);
+        self.allocations.push(ptr.cast());
+        ptr
+    }
+
+    fn alloc_str(&mut self, string: &str) -> MutPtr<xmlChar> {
+        let ptr = self.mem.alloc_and_write_cstr(string.as_bytes());
+        self.allocations.push(ptr.cast());
+        ptr
+    }
+
+    fn name(&mut self, name: &str) -> ConstPtr<xmlChar> {
+        if let Some(&ptr) = self.names.get(name) {
+            return ptr;
+        }
+        let ptr = self.alloc_str(name).cast_const();
+        self.names.insert(name.to_string(), ptr);
+        ptr
+    }
+
+    fn namespace(&mut self, name: &OwnedName) -> MutPtr<xmlNs> {
+        let Some(ref href) = name.namespace else {
+            return Ptr::null();
+        };
+        let key = (name.prefix.clone(), href.clone());
+        if let Some(&ptr) = self.namespaces.get(&key) {
+            return ptr;
+        }
+        let href = self.name(href);
+        let prefix = match name.prefix {
+            Some(ref prefix) => self.name(prefix),
+            None => Ptr::null(),
+        };
+        let ns = xmlNs {
+            next: Ptr::null(),
+            type_: XML_NAMESPACE_DECL,
+            href,
+            prefix,
+            _private: Ptr::null(),
+            context: self.doc,
+        };
+        let ptr = self.alloc(ns);
+        self.namespaces.insert(key, ptr);
+        ptr
+    }
+
+    fn new_node(&mut self, type_: u32, name: ConstPtr<xmlChar>, line: u64) -> MutPtr<xmlNode> {
+        self.alloc(xmlNode {
+            _private: Ptr::null(),
+            type_,
+            name,
+            children: Ptr::null(),
+            last: Ptr::null(),
+            parent: Ptr::null(),
+            next: Ptr::null(),
+            prev: Ptr::null(),
+            doc: self.doc,
+            ns: Ptr::null(),
+            content: Ptr::null(),
+            properties: Ptr::null(),
+            ns_def: Ptr::null(),
+            psvi: Ptr::null(),
+            line: line.try_into().unwrap_or(u16::MAX),
+            extra: 0,
+        })
+    }
+
+    fn new_text_node(
+        &mut self,
+        type_: u32,
+        name: &str,
+        content: &str,
+        line: u64,
+    ) -> MutPtr<xmlNode> {
+        let name = self.name(name);
+        let node = self.new_node(type_, name, line);
+        let content = self.alloc_str(content);
+        let mut node_data = self.mem.read(node);
+        node_data.content = content;
+        self.mem.write(node, node_data);
+        node
+    }
+
+    /// Append `child` to the children of `parent`, which can be any kind of
+    /// node, including the document or an attribute.
+    fn append_child(&mut self, parent: MutPtr<xmlNode>, child: MutPtr<xmlNode>) {
+        let parent_common: MutPtr<NodeCommon> = parent.cast();
+        let child_common: MutPtr<NodeCommon> = child.cast();
+        let mut parent_data = self.mem.read(parent_common);
+        let mut child_data = self.mem.read(child_common);
+        child_data.parent = parent;
+        child_data.prev = parent_data.last;
+        if parent_data.last.is_null() {
+            parent_data.children = child;
+        } else {
+            let last: MutPtr<NodeCommon> = parent_data.last.cast();
+            let mut last_data = self.mem.read(last);
+            last_data.next = child;
+            self.mem.write(last, last_data);
+        }
+        parent_data.last = child;
+        self.mem.write(child_common, child_data);
+        self.mem.write(parent_common, parent_data);
+    }
+
+    fn add_attributes(
+        &mut self,
+        element: MutPtr<xmlNode>,
+        attributes: &[xml::attribute::OwnedAttribute],
+        line: u64,
+    ) {
+        let mut prev: MutPtr<xmlAttr> = Ptr::null();
+        for attribute in attributes {
+            let name = self.name(&attribute.name.local_name);
+            let ns = self.namespace(&attribute.name);
+            let attr = self.alloc(xmlAttr {
+                _private: Ptr::null(),
+                type_: XML_ATTRIBUTE_NODE,
+                name,
+                children: Ptr::null(),
+                last: Ptr::null(),
+                parent: element,
+                next: Ptr::null(),
+                prev,
+                doc: self.doc,
+                ns,
+                atype: 0,
+                psvi: Ptr::null(),
+            });
+            // The value is stored as a text node child of the attribute.
+            let value = self.new_text_node(XML_TEXT_NODE, "text", &attribute.value, line);
+            self.append_child(attr.cast(), value);
+            if prev.is_null() {
+                let mut element_data = self.mem.read(element);
+                element_data.properties = attr;
+                self.mem.write(element, element_data);
+            } else {
+                let mut prev_data = self.mem.read(prev);
+                prev_data.next = attr;
+                self.mem.write(prev, prev_data);
+            }
+            prev = attr;
+        }
+    }
+}
+
+/// Parse a document into a tree in guest memory. Returns `NULL` if the
+/// document is malformed, like the real libxml2.
+fn parse_to_tree(
+    env: &mut Environment,
+    data: &[u8],
+    url: Option<&str>,
+    options: i32,
+) -> MutPtr<xmlDoc> {
+    let no_blanks = options & XML_PARSE_NOBLANKS != 0 || State::get(env).no_blanks_default;
+
+    let doc: MutPtr<xmlDoc> = env.mem.alloc(guest_size_of::<xmlDoc>()).cast();
+    let mut builder = TreeBuilder {
+        mem: &mut env.mem,
+        doc,
+        allocations: vec![doc.cast()],
+        names: HashMap::new(),
+        namespaces: HashMap::new(),
+    };
+    let url = url.map_or(Ptr::null(), |url| builder.alloc_str(url).cast_const());
+    builder.mem.write(
+        doc,
+        xmlDoc {
+            _private: Ptr::null(),
+            type_: XML_DOCUMENT_NODE,
+            name: Ptr::null(),
+            children: Ptr::null(),
+            last: Ptr::null(),
+            parent: Ptr::null(),
+            next: Ptr::null(),
+            prev: Ptr::null(),
+            doc,
+            compression: 0,
+            standalone: -1,
+            int_subset: Ptr::null(),
+            ext_subset: Ptr::null(),
+            old_ns: Ptr::null(),
+            version: Ptr::null(),
+            encoding: Ptr::null(),
+            ids: Ptr::null(),
+            refs: Ptr::null(),
+            url,
+            charset: 1, // XML_CHAR_ENCODING_UTF8
+            dict: Ptr::null(),
+            psvi: Ptr::null(),
+            parse_flags: options,
+            properties: 0,
+        },
+    );
+
+    let mut stack: Vec<MutPtr<xmlNode>> = vec![doc.cast()];
+    let mut reader = parser_config(options).create_reader(data);
+    let error = loop {
+        let event = match reader.next() {
+            Ok(event) => event,
+            Err(e) => break Some(e),
+        };
+        let line = reader.position().row + 1;
+        let &parent = stack.last().unwrap();
+        // Text directly inside the document (i.e. whitespace) is ignored.
+        let in_element = stack.len() > 1;
+        match event {
+            XmlEvent::StartDocument {
+                version,
+                encoding,
+                standalone,
+            } => {
+                let version = builder.alloc_str(&version.to_string()).cast_const();
+                let encoding = builder.alloc_str(&encoding).cast_const();
+                let mut doc_data = builder.mem.read(doc);
+                doc_data.version = version;
+                doc_data.encoding = encoding;
+                doc_data.standalone = standalone.map_or(-1, |standalone| standalone as i32);
+                builder.mem.write(doc, doc_data);
+            }
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let node_name = builder.name(&name.local_name);
+                let node = builder.new_node(XML_ELEMENT_NODE, node_name, line);
+                let ns = builder.namespace(&name);
+                let mut node_data = builder.mem.read(node);
+                node_data.ns = ns;
+                builder.mem.write(node, node_data);
+                builder.add_attributes(node, &attributes, line);
+                builder.append_child(parent, node);
+                stack.push(node);
+            }
+            XmlEvent::EndElement { .. } => {
+                stack.pop();
+            }
+            XmlEvent::Characters(text) if in_element => {
+                let node = builder.new_text_node(XML_TEXT_NODE, "text", &text, line);
+                builder.append_child(parent, node);
+            }
+            XmlEvent::Whitespace(text) if in_element && !no_blanks => {
+                let node = builder.new_text_node(XML_TEXT_NODE, "text", &text, line);
+                builder.append_child(parent, node);
+            }
+            XmlEvent::CData(text) => {
+                let node = builder.new_text_node(XML_CDATA_SECTION_NODE, "", &text, line);
+                builder.append_child(parent, node);
+            }
+            XmlEvent::Comment(text) => {
+                let node = builder.new_text_node(XML_COMMENT_NODE, "comment", &text, line);
+                builder.append_child(parent, node);
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                let data = data.unwrap_or_default();
+                let node = builder.new_text_node(XML_PI_NODE, &name, &data, line);
+                builder.append_child(parent, node);
+            }
+            XmlEvent::EndDocument => break None,
+            _ => (),
+        }
+    };
+
+    let allocations = builder.allocations;
+    if let Some(error) = error {
+        log!(
+            "Warning: libxml2 couldn't parse document {:?}: {}",
+            url_for_log(&env.mem, url),
+            error
+        );
+        for allocation in allocations {
+            env.mem.free(allocation);
+        }
+        return Ptr::null();
+    }
+    State::get(env).documents.insert(doc, allocations);
+    doc
+}
+
+fn url_for_log(mem: &Mem, url: ConstPtr<u8>) -> String {
+    if url.is_null() {
+        "(no URL)".to_string()
+    } else {
+        String::from_utf8_lossy(mem.cstr_at(url)).into_owned()
+    }
+}
+
+/// Read a document from the guest filesystem. libxml2 also accepts URLs, but
+/// only local paths are supported.
+fn read_file(env: &mut Environment, filename: ConstPtr<u8>) -> Option<Vec<u8>> {
+    let path = env.mem.cstr_at_utf8(filename).ok()?;
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    match env.fs.read(GuestPath::new(path)) {
+        Ok(data) => Some(data),
+        Err(_) => {
+            log!("Warning: libxml2 couldn't read {:?}", path);
+            None
+        }
+    }
+}
+
+fn guest_data(env: &Environment, buffer: ConstPtr<u8>, size: i32) -> Vec<u8> {
+    env.mem.bytes_at(buffer, size.try_into().unwrap()).to_vec()
+}
+
+fn xmlInitParser(_env: &mut Environment) {}
+
+fn xmlCleanupParser(_env: &mut Environment) {}
+
+fn xmlKeepBlanksDefault(env: &mut Environment, val: i32) -> i32 {
+    let state = State::get(env);
+    let old = !state.no_blanks_default;
+    state.no_blanks_default = val == 0;
+    old as i32
+}
+
+fn xmlSubstituteEntitiesDefault(_env: &mut Environment, _val: i32) -> i32 {
+    // Entities are always substituted.
+    1
+}
+
+fn xmlReadMemory(
+    env: &mut Environment,
+    buffer: ConstPtr<u8>,
+    size: i32,
+    url: ConstPtr<u8>,
+    _encoding: ConstPtr<u8>,
+    options: i32,
+) -> MutPtr<xmlDoc> {
+    let data = guest_data(env, buffer, size);
+    let url = (!url.is_null()).then(|| env.mem.cstr_at_utf8(url).unwrap().to_string());
+    parse_to_tree(env, &data, url.as_deref(), options)
+}
+
+fn xmlReadDoc(
+    env: &mut Environment,
+    cur: ConstPtr<xmlChar>,
+    url: ConstPtr<u8>,
+    encoding: ConstPtr<u8>,
+    options: i32,
+) -> MutPtr<xmlDoc> {
+    let size = env.mem.cstr_at(cur).len().try_into().unwrap();
+    xmlReadMemory(env, cur, size, url, encoding, options)
+}
+
+fn xmlReadFile(
+    env: &mut Environment,
+    filename: ConstPtr<u8>,
+    _encoding: ConstPtr<u8>,
+    options: i32,
+) -> MutPtr<xmlDoc> {
+    let Some(data) = read_file(env, filename) else {
+        return Ptr::null();
+    };
+    let url = env.mem.cstr_at_utf8(filename).unwrap().to_string();
+    parse_to_tree(env, &data, Some(&url), options)
+}
+
+fn xmlParseMemory(env: &mut Environment, buffer: ConstPtr<u8>, size: i32) -> MutPtr<xmlDoc> {
+    xmlReadMemory(env, buffer, size, Ptr::null(), Ptr::null(), 0)
+}
+
+fn xmlParseDoc(env: &mut Environment, cur: ConstPtr<xmlChar>) -> MutPtr<xmlDoc> {
+    xmlReadDoc(env, cur, Ptr::null(), Ptr::null(), 0)
+}
+
+fn xmlParseFile(env: &mut Environment, filename: ConstPtr<u8>) -> MutPtr<xmlDoc> {
+    xmlReadFile(env, filename, Ptr::null(), 0)
+}
+
+fn xmlFreeDoc(env: &mut Environment, doc: MutPtr<xmlDoc>) {
+    if doc.is_null() {
+        return;
+    }
+    let Some(allocations) = State::get(env).documents.remove(&doc) else {
+        log!("Warning: xmlFreeDoc() on unknown document {:?}", doc);
+        return;
+    };
+    for allocation in allocations {
+        env.mem.free(allocation);
+    }
+}
+
+/// Parse a document and report it to the app's SAX callbacks. `ctx` is the
+/// value passed as the first argument of each callback.
+fn parse_with_sax(
+    env: &mut Environment,
+    sax: ConstPtr<xmlSAXHandler>,
+    ctx: MutVoidPtr,
+    data: &[u8],
+) -> i32 {
+    let handler = env.mem.read(sax);
+    let sax2 = handler.initialized == XML_SAX2_MAGIC;
+    let no_blanks = State::get(env).no_blanks_default;
+
+    // Namespace declarations aren't reported by xml-rs as such, so they're
+    // found by comparing the namespaces in scope for each element with those
+    // of its parent.
+    let mut namespace_stack: Vec<BTreeMap<String, String>> = vec![BTreeMap::new()];
+
+    let mut reader = parser_config(0).create_reader(data);
+    loop {
+        let event = match reader.next() {
+            Ok(event) => event,
+            Err(e) => {
+                log!("Warning: libxml2 SAX parsing failed: {}", e);
+                let callback = if is_set(handler.fatal_error) {
+                    handler.fatal_error
+                } else {
+                    handler.error
+                };
+                if is_set(callback) {
+                    let format = env.mem.alloc_and_write_cstr(b"%s");
+                    let message = env.mem.alloc_and_write_cstr(format!("{}\n", e).as_bytes());
+                    let _: () = callback.call_from_host(env, (ctx, format, message));
+                    env.mem.free(format.cast());
+                    env.mem.free(message.cast());
+                }
+                return XML_ERR_INTERNAL_ERROR;
+            }
+        };
+        match event {
+            XmlEvent::StartDocument { .. } => {
+                if is_set(handler.start_document) {
+                    let _: () = { handler.start_document }.call_from_host(env, (ctx,));
+                }
+            }
+            XmlEvent::EndDocument => {
+                if is_set(handler.end_document) {
+                    let _: () = { handler.end_document }.call_from_host(env, (ctx,));
+                }
+                return XML_ERR_OK;
+            }
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let mut declared = Vec::new();
+                let parent_namespaces = namespace_stack.last().unwrap();
+                for (prefix, uri) in &namespace.0 {
+                    if prefix == "xml" || prefix == "xmlns" || uri.is_empty() {
+                        continue;
+                    }
+                    if parent_namespaces.get(prefix) != Some(uri) {
+                        declared.push((prefix.clone(), uri.clone()));
+                    }
+                }
+                namespace_stack.push(namespace.0);
+
+                if sax2 && is_set(handler.start_element_ns) {
+                    let mut strings = Vec::new();
+                    let mut alloc_str = |env: &mut Environment, s: Option<&str>| {
+                        let Some(s) = s else {
+                            return Ptr::null();
+                        };
+                        let ptr = env.mem.alloc_and_write_cstr(s.as_bytes());
+                        strings.push(ptr);
+                        ptr
+                    };
+                    let localname = alloc_str(env, Some(&name.local_name));
+                    let prefix = alloc_str(env, name.prefix.as_deref());
+                    let uri = alloc_str(env, name.namespace.as_deref());
+
+                    // Pairs of prefix and URI
+                    let namespaces: MutPtr<MutPtr<xmlChar>> =
+                        env.mem.alloc((declared.len() * 2 * 4) as GuestUSize).cast();
+                    for (i, (ns_prefix, ns_uri)) in declared.iter().enumerate() {
+                        let ns_prefix = (!ns_prefix.is_empty()).then_some(ns_prefix.as_str());
+                        let ns_prefix = alloc_str(env, ns_prefix);
+                        let ns_uri = alloc_str(env, Some(ns_uri));
+                        env.mem.write(namespaces + (i * 2) as GuestUSize, ns_prefix);
+                        env.mem
+                            .write(namespaces + (i * 2 + 1) as GuestUSize, ns_uri);
+                    }
+
+                    // Groups of local name, prefix, URI, value start and value
+                    // end
+                    let attrs: MutPtr<MutPtr<xmlChar>> = env
+                        .mem
+                        .alloc((attributes.len() * 5 * 4) as GuestUSize)
+                        .cast();
+                    for (i, attribute) in attributes.iter().enumerate() {
+                        let base = attrs + (i * 5) as GuestUSize;
+                        let attr_name = &attribute.name;
+                        let attr_localname = alloc_str(env, Some(&attr_name.local_name));
+                        let attr_prefix = alloc_str(env, attr_name.prefix.as_deref());
+                        let attr_uri = alloc_str(env, attr_name.namespace.as_deref());
+                        let value = alloc_str(env, Some(&attribute.value));
+                        let value_end = value + attribute.value.len() as GuestUSize;
+                        env.mem.write(base, attr_localname);
+                        env.mem.write(base + 1, attr_prefix);
+                        env.mem.write(base + 2, attr_uri);
+                        env.mem.write(base + 3, value);
+                        env.mem.write(base + 4, value_end);
+                    }
+
+                    let _: () = { handler.start_element_ns }.call_from_host(
+                        env,
+                        (
+                            ctx,
+                            localname,
+                            prefix,
+                            uri,
+                            declared.len() as i32,
+                            namespaces,
+                            attributes.len() as i32,
+                            0i32, // nb_defaulted
+                            attrs,
+                        ),
+                    );
+
+                    env.mem.free(namespaces.cast());
+                    env.mem.free(attrs.cast());
+                    for string in strings {
+                        env.mem.free(string.cast());
+                    }
+                } else if is_set(handler.start_element) {
+                    let qname = env
+                        .mem
+                        .alloc_and_write_cstr(name.borrow().to_repr().as_bytes());
+                    // NULL-terminated list of name and value pairs, or NULL
+                    // if there are no attributes
+                    let attrs: MutPtr<MutPtr<xmlChar>> = if attributes.is_empty() {
+                        Ptr::null()
+                    } else {
+                        env.mem
+                            .alloc(((attributes.len() * 2 + 1) * 4) as GuestUSize)
+                            .cast()
+                    };
+                    for (i, attribute) in attributes.iter().enumerate() {
+                        let attr_name = attribute.name.borrow().to_repr();
+                        let attr_name = env.mem.alloc_and_write_cstr(attr_name.as_bytes());
+                        let value = env.mem.alloc_and_write_cstr(attribute.value.as_bytes());
+                        env.mem.write(attrs + (i * 2) as GuestUSize, attr_name);
+                        env.mem.write(attrs + (i * 2 + 1) as GuestUSize, value);
+                    }
+                    if !attrs.is_null() {
+                        env.mem
+                            .write(attrs + (attributes.len() * 2) as GuestUSize, Ptr::null());
+                    }
+
+                    let _: () = { handler.start_element }.call_from_host(env, (ctx, qname, attrs));
+
+                    env.mem.free(qname.cast());
+                    if !attrs.is_null() {
+                        for i in 0..(attributes.len() * 2) {
+                            let string = env.mem.read(attrs + i as GuestUSize);
+                            env.mem.free(string.cast());
+                        }
+                        env.mem.free(attrs.cast());
+                    }
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                namespace_stack.pop();
+                if sax2 && is_set(handler.end_element_ns) {
+                    let localname = env.mem.alloc_and_write_cstr(name.local_name.as_bytes());
+                    let prefix = name.prefix.as_ref().map_or(Ptr::null(), |prefix| {
+                        env.mem.alloc_and_write_cstr(prefix.as_bytes())
+                    });
+                    let uri = name.namespace.as_ref().map_or(Ptr::null(), |uri| {
+                        env.mem.alloc_and_write_cstr(uri.as_bytes())
+                    });
+                    let _: () = { handler.end_element_ns }
+                        .call_from_host(env, (ctx, localname, prefix, uri));
+                    env.mem.free(localname.cast());
+                    free_if_not_null(&mut env.mem, prefix);
+                    free_if_not_null(&mut env.mem, uri);
+                } else if is_set(handler.end_element) {
+                    let qname = env
+                        .mem
+                        .alloc_and_write_cstr(name.borrow().to_repr().as_bytes());
+                    let _: () = { handler.end_element }.call_from_host(env, (ctx, qname));
+                    env.mem.free(qname.cast());
+                }
+            }
+            XmlEvent::Characters(text) => {
+                sax_characters(env, handler.characters, ctx, &text);
+            }
+            XmlEvent::Whitespace(text) => {
+                let callback = if no_blanks {
+                    handler.ignorable_whitespace
+                } else {
+                    handler.characters
+                };
+                sax_characters(env, callback, ctx, &text);
+            }
+            XmlEvent::CData(text) => {
+                let callback = if is_set(handler.cdata_block) {
+                    handler.cdata_block
+                } else {
+                    handler.characters
+                };
+                sax_characters(env, callback, ctx, &text);
+            }
+            XmlEvent::Comment(text) => {
+                if is_set(handler.comment) {
+                    let value = env.mem.alloc_and_write_cstr(text.as_bytes());
+                    let _: () = { handler.comment }.call_from_host(env, (ctx, value));
+                    env.mem.free(value.cast());
+                }
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                if is_set(handler.processing_instruction) {
+                    let target = env.mem.alloc_and_write_cstr(name.as_bytes());
+                    let data = data.map_or(Ptr::null(), |data| {
+                        env.mem.alloc_and_write_cstr(data.as_bytes())
+                    });
+                    let _: () =
+                        { handler.processing_instruction }.call_from_host(env, (ctx, target, data));
+                    env.mem.free(target.cast());
+                    free_if_not_null(&mut env.mem, data);
+                }
+            }
+        }
+    }
+}
+
+fn is_set(callback: GuestFunction) -> bool {
+    !callback.to_ptr().is_null()
+}
+
+/// Call a SAX callback of the `charactersSAXFunc` type.
+fn sax_characters(env: &mut Environment, callback: GuestFunction, ctx: MutVoidPtr, text: &str) {
+    if !is_set(callback) {
+        return;
+    }
+    let ch = env.mem.alloc_and_write_cstr(text.as_bytes());
+    let len: i32 = text.len().try_into().unwrap();
+    let _: () = callback.call_from_host(env, (ctx, ch, len));
+    env.mem.free(ch.cast());
+}
+
+fn xmlSAXUserParseMemory(
+    env: &mut Environment,
+    sax: ConstPtr<xmlSAXHandler>,
+    user_data: MutVoidPtr,
+    buffer: ConstPtr<u8>,
+    size: i32,
+) -> i32 {
+    // The real libxml2 passes the parser context to the callbacks if
+    // user_data is NULL, but there isn't one here.
+    let data = guest_data(env, buffer, size);
+    parse_with_sax(env, sax, user_data, &data)
+}
+
+fn xmlSAXUserParseFile(
+    env: &mut Environment,
+    sax: ConstPtr<xmlSAXHandler>,
+    user_data: MutVoidPtr,
+    filename: ConstPtr<u8>,
+) -> i32 {
+    let Some(data) = read_file(env, filename) else {
+        return -1;
+    };
+    parse_with_sax(env, sax, user_data, &data)
+}
+
+/// Push parsers are implemented by collecting all the chunks and parsing the
+/// document once the last one is received.
+fn xmlCreatePushParserCtxt(
+    env: &mut Environment,
+    sax: ConstPtr<xmlSAXHandler>,
+    user_data: MutVoidPtr,
+    chunk: ConstPtr<u8>,
+    size: i32,
+    _filename: ConstPtr<u8>,
+) -> MutPtr<xmlParserCtxt> {
+    let data = if chunk.is_null() {
+        Vec::new()
+    } else {
+        guest_data(env, chunk, size)
+    };
+    let ctxt = env.mem.alloc_and_write(xmlParserCtxt {
+        sax,
+        user_data,
+        my_doc: Ptr::null(),
+        well_formed: 1,
+    });
+    State::get(env).push_parsers.insert(ctxt, data);
+    ctxt
+}
+
+fn xmlParseChunk(
+    env: &mut Environment,
+    ctxt: MutPtr<xmlParserCtxt>,
+    chunk: ConstPtr<u8>,
+    size: i32,
+    terminate: i32,
+) -> i32 {
+    let new_data = if chunk.is_null() {
+        Vec::new()
+    } else {
+        guest_data(env, chunk, size)
+    };
+    let data = State::get(env).push_parsers.get_mut(&ctxt).unwrap();
+    data.extend_from_slice(&new_data);
+    if terminate == 0 {
+        return XML_ERR_OK;
+    }
+
+    let data = std::mem::take(data);
+    let mut ctxt_data = env.mem.read(ctxt);
+    let res = if ctxt_data.sax.is_null() {
+        ctxt_data.my_doc = parse_to_tree(env, &data, None, 0);
+        if ctxt_data.my_doc.is_null() {
+            XML_ERR_INTERNAL_ERROR
+        } else {
+            XML_ERR_OK
+        }
+    } else {
+        let ctx = if ctxt_data.user_data.is_null() {
+            ctxt.cast()
+        } else {
+            ctxt_data.user_data
+        };
+        parse_with_sax(env, ctxt_data.sax, ctx, &data)
+    };
+    ctxt_data.well_formed = (res == XML_ERR_OK) as i32;
+    env.mem.write(ctxt, ctxt_data);
+    res
+}
+
+fn xmlFreeParserCtxt(env: &mut Environment, ctxt: MutPtr<xmlParserCtxt>) {
+    if ctxt.is_null() {
+        return;
+    }
+    State::get(env).push_parsers.remove(&ctxt);
+    env.mem.free(ctxt.cast());
+}
+
+fn node_type(mem: &Mem, node: MutPtr<xmlNode>) -> u32 {
+    mem.read(node.cast::<NodeCommon>()).type_
+}
+
+/// Children of a node of any kind, in order.
+fn children(mem: &Mem, node: MutPtr<xmlNode>) -> Vec<MutPtr<xmlNode>> {
+    let mut result = Vec::new();
+    let mut child = mem.read(node.cast::<NodeCommon>()).children;
+    while !child.is_null() {
+        result.push(child);
+        child = mem.read(child.cast::<NodeCommon>()).next;
+    }
+    result
+}
+
+/// Attributes of an element, as node pointers, like in XPath results.
+fn attributes(mem: &Mem, node: MutPtr<xmlNode>) -> Vec<MutPtr<xmlNode>> {
+    let mut result = Vec::new();
+    if node_type(mem, node) != XML_ELEMENT_NODE {
+        return result;
+    }
+    let mut attr = mem.read(node).properties;
+    while !attr.is_null() {
+        result.push(attr.cast());
+        attr = mem.read(attr).next;
+    }
+    result
+}
+
+fn node_name(mem: &Mem, node: MutPtr<xmlNode>) -> &[u8] {
+    let name = mem.read(node.cast::<NodeCommon>()).name;
+    if name.is_null() {
+        b""
+    } else {
+        mem.cstr_at(name)
+    }
+}
+
+/// The text content of a node, as with `xmlNodeGetContent()` or the XPath
+/// string value.
+fn node_content(mem: &Mem, node: MutPtr<xmlNode>, content: &mut Vec<u8>) {
+    match node_type(mem, node) {
+        XML_TEXT_NODE | XML_CDATA_SECTION_NODE | XML_COMMENT_NODE | XML_PI_NODE => {
+            let text = mem.read(node).content;
+            if !text.is_null() {
+                content.extend_from_slice(mem.cstr_at(text));
+            }
+        }
+        XML_ELEMENT_NODE | XML_DOCUMENT_NODE | XML_ATTRIBUTE_NODE => {
+            for child in children(mem, node) {
+                if node_type(mem, child) != XML_COMMENT_NODE && node_type(mem, child) != XML_PI_NODE
+                {
+                    node_content(mem, child, content);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+fn xmlDocGetRootElement(env: &mut Environment, doc: MutPtr<xmlDoc>) -> MutPtr<xmlNode> {
+    if doc.is_null() {
+        return Ptr::null();
+    }
+    children(&env.mem, doc.cast())
+        .into_iter()
+        .find(|&child| node_type(&env.mem, child) == XML_ELEMENT_NODE)
+        .unwrap_or(Ptr::null())
+}
+
+fn xmlHasProp(
+    env: &mut Environment,
+    node: MutPtr<xmlNode>,
+    name: ConstPtr<xmlChar>,
+) -> MutPtr<xmlAttr> {
+    if node.is_null() || name.is_null() {
+        return Ptr::null();
+    }
+    let name = env.mem.cstr_at(name);
+    attributes(&env.mem, node)
+        .into_iter()
+        .find(|&attr| node_name(&env.mem, attr) == name)
+        .map_or(Ptr::null(), |attr| attr.cast())
+}
+
+fn xmlGetProp(
+    env: &mut Environment,
+    node: MutPtr<xmlNode>,
+    name: ConstPtr<xmlChar>,
+) -> MutPtr<xmlChar> {
+    let attr = xmlHasProp(env, node, name);
+    if attr.is_null() {
+        return Ptr::null();
+    }
+    let mut value = Vec::new();
+    node_content(&env.mem, attr.cast(), &mut value);
+    env.mem.alloc_and_write_cstr(&value)
+}
+
+fn xmlNodeGetContent(env: &mut Environment, node: MutPtr<xmlNode>) -> MutPtr<xmlChar> {
+    if node.is_null() {
+        return Ptr::null();
+    }
+    let mut content = Vec::new();
+    node_content(&env.mem, node, &mut content);
+    env.mem.alloc_and_write_cstr(&content)
+}
+
+fn xmlNodeListGetString(
+    env: &mut Environment,
+    _doc: MutPtr<xmlDoc>,
+    list: MutPtr<xmlNode>,
+    _in_line: i32,
+) -> MutPtr<xmlChar> {
+    if list.is_null() {
+        return Ptr::null();
+    }
+    let mut string = Vec::new();
+    let mut node = list;
+    while !node.is_null() {
+        let node_data = env.mem.read(node);
+        if (node_data.type_ == XML_TEXT_NODE || node_data.type_ == XML_CDATA_SECTION_NODE)
+            && !node_data.content.is_null()
+        {
+            string.extend_from_slice(env.mem.cstr_at(node_data.content));
+        }
+        node = node_data.next;
+    }
+    env.mem.alloc_and_write_cstr(&string)
+}
+
+fn xmlNodeIsText(env: &mut Environment, node: MutPtr<xmlNode>) -> i32 {
+    (!node.is_null() && node_type(&env.mem, node) == XML_TEXT_NODE) as i32
+}
+
+fn xmlIsBlankNode(env: &mut Environment, node: MutPtr<xmlNode>) -> i32 {
+    if node.is_null() {
+        return 0;
+    }
+    let node_data = env.mem.read(node);
+    if node_data.type_ != XML_TEXT_NODE && node_data.type_ != XML_CDATA_SECTION_NODE {
+        return 0;
+    }
+    if node_data.content.is_null() {
+        return 1;
+    }
+    env.mem
+        .cstr_at(node_data.content)
+        .iter()
+        .all(|&c| matches!(c, b' ' | b'\t' | b'\n' | b'\r')) as i32
+}
+
+fn xmlStrlen(env: &mut Environment, str: ConstPtr<xmlChar>) -> i32 {
+    if str.is_null() {
+        return 0;
+    }
+    env.mem.cstr_at(str).len().try_into().unwrap()
+}
+
+fn xmlStrdup(env: &mut Environment, cur: ConstPtr<xmlChar>) -> MutPtr<xmlChar> {
+    if cur.is_null() {
+        return Ptr::null();
+    }
+    let string = env.mem.cstr_at(cur).to_vec();
+    env.mem.alloc_and_write_cstr(&string)
+}
+
+fn xmlStrcmp(env: &mut Environment, str1: ConstPtr<xmlChar>, str2: ConstPtr<xmlChar>) -> i32 {
+    match (str1.is_null(), str2.is_null()) {
+        (true, true) => 0,
+        (true, false) => -1,
+        (false, true) => 1,
+        (false, false) => match env.mem.cstr_at(str1).cmp(env.mem.cstr_at(str2)) {
+            std::cmp::Ordering::Less => -1,
+            std::cmp::Ordering::Equal => 0,
+            std::cmp::Ordering::Greater => 1,
+        },
+    }
+}
+
+fn xmlStrEqual(env: &mut Environment, str1: ConstPtr<xmlChar>, str2: ConstPtr<xmlChar>) -> i32 {
+    (xmlStrcmp(env, str1, str2) == 0) as i32
+}
+
+/// A step in an XPath location path. Name tests are `None` for `*`.
+#[derive(Debug)]
+enum XPathAxis {
+    Root,
+    DescendantOrSelf,
+    SelfNode,
+    Parent,
+    Child(Option<Vec<u8>>),
+    Attribute(Option<Vec<u8>>),
+    Text,
+    AnyNode,
+}
+
+#[derive(Debug)]
+enum XPathPredicate {
+    Position(usize),
+    Last,
+    Exists(XPathAxis),
+    Equals(XPathAxis, Vec<u8>),
+}
+
+#[derive(Debug)]
+struct XPathStep {
+    axis: XPathAxis,
+    predicates: Vec<XPathPredicate>,
+}
+
+#[derive(Debug)]
+enum XPathExpr {
+    /// Union of location paths.
+    Paths(Vec<Vec<XPathStep>>),
+    Count(Vec<XPathStep>),
+}
+
+/// Parser for the supported XPath subset: location paths with the
+/// abbreviated syntax, simple predicates, `|` and `count()`.
+struct XPathParser<'a> {
+    expr: &'a [u8],
+    pos: usize,
+}
+impl XPathParser<'_> {
+    fn skip_space(&mut self) {
+        while self
+            .expr
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &[u8]) -> bool {
+        self.skip_space();
+        if self.expr[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn name(&mut self) -> Option<Vec<u8>> {
+        self.skip_space();
+        let start = self.pos;
+        while self.expr.get(self.pos).is_some_and(|&c| {
+            c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.' | b':') || c >= 0x80
+        }) {
+            self.pos += 1;
+        }
+        (self.pos > start).then(|| self.expr[start..self.pos].to_vec())
+    }
+
+    /// Name test. Namespace prefixes are ignored, i.e. only the local name is
+    /// matched, since namespace registration isn't supported.
+    fn name_test(&mut self) -> Option<Option<Vec<u8>>> {
+        if self.eat(b"*") {
+            return Some(None);
+        }
+        let name = self.name()?;
+        let local_name = match name.iter().position(|&c| c == b':') {
+            Some(colon) => name[colon + 1..].to_vec(),
+            None => name,
+        };
+        Some(Some(local_name))
+    }
+
+    fn axis(&mut self) -> Option<XPathAxis> {
+        if self.eat(b"..") {
+            Some(XPathAxis::Parent)
+        } else if self.eat(b".") {
+            Some(XPathAxis::SelfNode)
+        } else if self.eat(b"@") {
+            Some(XPathAxis::Attribute(self.name_test()?))
+        } else if self.eat(b"text()") {
+            Some(XPathAxis::Text)
+        } else if self.eat(b"node()") {
+            Some(XPathAxis::AnyNode)
+        } else {
+            Some(XPathAxis::Child(self.name_test()?))
+        }
+    }
+
+    fn literal(&mut self) -> Option<Vec<u8>> {
+        self.skip_space();
+        let &quote = self.expr.get(self.pos)?;
+        if quote != b'\'' && quote != b'"' {
+            return None;
+        }
+        let start = self.pos + 1;
+        let len = self.expr[start..].iter().position(|&c| c == quote)?;
+        self.pos = start + len + 1;
+        Some(self.expr[start..start + len].to_vec())
+    }
+
+    fn predicate(&mut self) -> Option<XPathPredicate> {
+        self.skip_space();
+        let predicate = if self.expr.get(self.pos)?.is_ascii_digit() {
+            let start = self.pos;
+            while self.expr.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            let position = std::str::from_utf8(&self.expr[start..self.pos]).ok()?;
+            XPathPredicate::Position(position.parse().ok()?)
+        } else if self.eat(b"last()") {
+            XPathPredicate::Last
+        } else {
+            let axis = self.axis()?;
+            if self.eat(b"=") {
+                XPathPredicate::Equals(axis, self.literal()?)
+            } else {
+                XPathPredicate::Exists(axis)
+            }
+        };
+        self.eat(b"]").then_some(predicate)
+    }
+
+    fn step(&mut self) -> Option<XPathStep> {
+        let axis = self.axis()?;
+        let mut predicates = Vec::new();
+        while self.eat(b"[") {
+            predicates.push(self.predicate()?);
+        }
+        Some(XPathStep { axis, predicates })
+    }
+
+    fn path(&mut self) -> Option<Vec<XPathStep>> {
+        let mut steps = Vec::new();
+        let root_step = || XPathStep {
+            axis: XPathAxis::Root,
+            predicates: Vec::new(),
+        };
+        let descendant_step = || XPathStep {
+            axis: XPathAxis::DescendantOrSelf,
+            predicates: Vec::new(),
+        };
+        if self.eat(b"//") {
+            steps.push(root_step());
+            steps.push(descendant_step());
+        } else if self.eat(b"/") {
+            steps.push(root_step());
+            self.skip_space();
+            // "/" on its own selects the document
+            if matches!(self.expr.get(self.pos), None | Some(b'|') | Some(b')')) {
+                return Some(steps);
+            }
+        }
+        loop {
+            steps.push(self.step()?);
+            if self.eat(b"//") {
+                steps.push(descendant_step());
+            } else if !self.eat(b"/") {
+                return Some(steps);
+            }
+        }
+    }
+
+    fn parse(mut self) -> Option<XPathExpr> {
+        let expr = if self.eat(b"count(") {
+            let path = self.path()?;
+            if !self.eat(b")") {
+                return None;
+            }
+            XPathExpr::Count(path)
+        } else {
+            let mut paths = vec![self.path()?];
+            while self.eat(b"|") {
+                paths.push(self.path()?);
+            }
+            XPathExpr::Paths(paths)
+        };
+        self.skip_space();
+        (self.pos == self.expr.len()).then_some(expr)
+    }
+}
+
+fn name_matches(mem: &Mem, node: MutPtr<xmlNode>, name: &Option<Vec<u8>>) -> bool {
+    match name {
+        Some(name) => node_name(mem, node) == &name[..],
+        None => true,
+    }
+}
+
+fn descendants_or_self(mem: &Mem, node: MutPtr<xmlNode>, result: &mut Vec<MutPtr<xmlNode>>) {
+    result.push(node);
+    for child in children(mem, node) {
+        descendants_or_self(mem, child, result);
+    }
+}
+
+fn apply_axis(
+    mem: &Mem,
+    doc: MutPtr<xmlDoc>,
+    node: MutPtr<xmlNode>,
+    axis: &XPathAxis,
+) -> Vec<MutPtr<xmlNode>> {
+    match axis {
+        XPathAxis::Root => vec![doc.cast()],
+        XPathAxis::DescendantOrSelf => {
+            let mut result = Vec::new();
+            descendants_or_self(mem, node, &mut result);
+            result
+        }
+        XPathAxis::SelfNode => vec![node],
+        XPathAxis::Parent => {
+            let parent = mem.read(node.cast::<NodeCommon>()).parent;
+            if parent.is_null() {
+                vec![]
+            } else {
+                vec![parent]
+            }
+        }
+        XPathAxis::Child(name) => children(mem, node)
+            .into_iter()
+            .filter(|&child| {
+                node_type(mem, child) == XML_ELEMENT_NODE && name_matches(mem, child, name)
+            })
+            .collect(),
+        XPathAxis::Attribute(name) => attributes(mem, node)
+            .into_iter()
+            .filter(|&attr| name_matches(mem, attr, name))
+            .collect(),
+        XPathAxis::Text => children(mem, node)
+            .into_iter()
+            .filter(|&child| {
+                let type_ = node_type(mem, child);
+                type_ == XML_TEXT_NODE || type_ == XML_CDATA_SECTION_NODE
+            })
+            .collect(),
+        XPathAxis::AnyNode => children(mem, node),
+    }
+}
+
+fn eval_path(
+    mem: &Mem,
+    doc: MutPtr<xmlDoc>,
+    context_node: MutPtr<xmlNode>,
+    steps: &[XPathStep],
+) -> Vec<MutPtr<xmlNode>> {
+    let mut nodes = vec![context_node];
+    for step in steps {
+        let mut new_nodes = Vec::new();
+        for &node in &nodes {
+            let mut candidates = apply_axis(mem, doc, node, &step.axis);
+            for predicate in &step.predicates {
+                candidates = match predicate {
+                    XPathPredicate::Position(position) => candidates
+                        .get(position.wrapping_sub(1))
+                        .copied()
+                        .into_iter()
+                        .collect(),
+                    XPathPredicate::Last => candidates.last().copied().into_iter().collect(),
+                    XPathPredicate::Exists(axis) => candidates
+                        .into_iter()
+                        .filter(|&candidate| !apply_axis(mem, doc, candidate, axis).is_empty())
+                        .collect(),
+                    XPathPredicate::Equals(axis, value) => candidates
+                        .into_iter()
+                        .filter(|&candidate| {
+                            apply_axis(mem, doc, candidate, axis)
+                                .into_iter()
+                                .any(|node| {
+                                    let mut content = Vec::new();
+                                    node_content(mem, node, &mut content);
+                                    &content == value
+                                })
+                        })
+                        .collect(),
+                };
+            }
+            for candidate in candidates {
+                if !new_nodes.contains(&candidate) {
+                    new_nodes.push(candidate);
+                }
+            }
+        }
+        nodes = new_nodes;
+    }
+    nodes
+}
+
+fn xmlXPathInit(_env: &mut Environment) {}
+
+fn xmlXPathNewContext(env: &mut Environment, doc: MutPtr<xmlDoc>) -> MutPtr<xmlXPathContext> {
+    env.mem.alloc_and_write(xmlXPathContext {
+        doc,
+        node: Ptr::null(),
+    })
+}
+
+fn xmlXPathFreeContext(env: &mut Environment, ctx: MutPtr<xmlXPathContext>) {
+    free_if_not_null(&mut env.mem, ctx);
+}
+
+fn xmlXPathRegisterNs(
+    _env: &mut Environment,
+    _ctx: MutPtr<xmlXPathContext>,
+    _prefix: ConstPtr<xmlChar>,
+    _ns_uri: ConstPtr<xmlChar>,
+) -> i32 {
+    // Namespace prefixes are ignored when evaluating, so there's nothing to do.
+    0
+}
+
+fn xmlXPathEval(
+    env: &mut Environment,
+    str: ConstPtr<xmlChar>,
+    ctx: MutPtr<xmlXPathContext>,
+) -> MutPtr<xmlXPathObject> {
+    let expr_string = env.mem.cstr_at(str).to_vec();
+    let Some(expr) = (XPathParser {
+        expr: &expr_string,
+        pos: 0,
+    })
+    .parse() else {
+        log!(
+            "TODO: XPath expression {:?} (unsupported or invalid), returning NULL",
+            String::from_utf8_lossy(&expr_string)
+        );
+        return Ptr::null();
+    };
+    log_dbg!(
+        "xmlXPathEval({:?}) => {:?}",
+        String::from_utf8_lossy(&expr_string),
+        expr
+    );
+
+    let ctx_data = env.mem.read(ctx);
+    let context_node = if ctx_data.node.is_null() {
+        ctx_data.doc.cast()
+    } else {
+        ctx_data.node
+    };
+
+    let mut object = xmlXPathObject {
+        type_: XPATH_NODESET,
+        nodesetval: Ptr::null(),
+        boolval: 0,
+        floatval: 0.0,
+        stringval: Ptr::null(),
+        user: Ptr::null(),
+        index: 0,
+        user2: Ptr::null(),
+        index2: 0,
+    };
+    match expr {
+        XPathExpr::Count(path) => {
+            let nodes = eval_path(&env.mem, ctx_data.doc, context_node, &path);
+            object.type_ = XPATH_NUMBER;
+            object.floatval = nodes.len() as f64;
+        }
+        XPathExpr::Paths(paths) => {
+            let mut nodes = Vec::new();
+            for path in paths {
+                for node in eval_path(&env.mem, ctx_data.doc, context_node, &path) {
+                    if !nodes.contains(&node) {
+                        nodes.push(node);
+                    }
+                }
+            }
+            let count: GuestUSize = nodes.len().try_into().unwrap();
+            let node_tab: MutPtr<MutPtr<xmlNode>> = if nodes.is_empty() {
+                Ptr::null()
+            } else {
+                env.mem.alloc(count * 4).cast()
+            };
+            for (i, node) in nodes.into_iter().enumerate() {
+                env.mem.write(node_tab + i as GuestUSize, node);
+            }
+            object.nodesetval = env.mem.alloc_and_write(xmlNodeSet {
+                node_nr: count as i32,
+                node_max: count as i32,
+                node_tab,
+            });
+        }
+    }
+    env.mem.alloc_and_write(object)
+}
+
+fn xmlXPathEvalExpression(
+    env: &mut Environment,
+    str: ConstPtr<xmlChar>,
+    ctx: MutPtr<xmlXPathContext>,
+) -> MutPtr<xmlXPathObject> {
+    xmlXPathEval(env, str, ctx)
+}
+
+fn xmlXPathFreeObject(env: &mut Environment, obj: MutPtr<xmlXPathObject>) {
+    if obj.is_null() {
+        return;
+    }
+    let object = env.mem.read(obj);
+    if !object.nodesetval.is_null() {
+        let node_set = env.mem.read(object.nodesetval);
+        free_if_not_null(&mut env.mem, node_set.node_tab);
+        env.mem.free(object.nodesetval.cast());
+    }
+    free_if_not_null(&mut env.mem, object.stringval);
+    env.mem.free(obj.cast());
+}
+
+/// `xmlFree`, `xmlMalloc` etc are global variables containing function
+/// pointers, rather than functions.
+fn allocator_function(env: &mut Environment, symbol: &str) -> ConstVoidPtr {
+    let function = env
+        .dyld
+        .create_proc_address(&mut env.mem, &mut env.cpu, symbol)
+        .unwrap();
+    env.mem.alloc_and_write(function).cast().cast_const()
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_xmlFree",
+        HostConstant::CustomWithEnv(|env| allocator_function(env, "_free")),
+    ),
+    (
+        "_xmlMalloc",
+        HostConstant::CustomWithEnv(|env| allocator_function(env, "_malloc")),
+    ),
+    (
+        "_xmlRealloc",
+        HostConstant::CustomWithEnv(|env| allocator_function(env, "_realloc")),
+    ),
+    (
+        "_xmlMemStrdup",
+        HostConstant::CustomWithEnv(|env| allocator_function(env, "_strdup")),
+    ),
+];
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(xmlInitParser()),
+    export_c_func!(xmlCleanupParser()),
+    export_c_func!(xmlKeepBlanksDefault(_)),
+    export_c_func!(xmlSubstituteEntitiesDefault(_)),
+    export_c_func!(xmlReadMemory(_, _, _, _, _)),
+    export_c_func!(xmlReadDoc(_, _, _, _)),
+    export_c_func!(xmlReadFile(_, _, _)),
+    export_c_func!(xmlParseMemory(_, _)),
+    export_c_func!(xmlParseDoc(_)),
+    export_c_func!(xmlParseFile(_)),
+    export_c_func!(xmlFreeDoc(_)),
+    export_c_func!(xmlSAXUserParseMemory(_, _, _, _)),
+    export_c_func!(xmlSAXUserParseFile(_, _, _)),
+    export_c_func!(xmlCreatePushParserCtxt(_, _, _, _, _)),
+    export_c_func!(xmlParseChunk(_, _, _, _)),
+    export_c_func!(xmlFreeParserCtxt(_)),
+    export_c_func!(xmlDocGetRootElement(_)),
+    export_c_func!(xmlHasProp(_, _)),
+    export_c_func!(xmlGetProp(_, _)),
+    export_c_func!(xmlNodeGetContent(_)),
+    export_c_func!(xmlNodeListGetString(_, _, _)),
+    export_c_func!(xmlNodeIsText(_)),
+    export_c_func!(xmlIsBlankNode(_)),
+    export_c_func!(xmlStrlen(_)),
+    export_c_func!(xmlStrdup(_)),
+    export_c_func!(xmlStrcmp(_, _)),
+    export_c_func!(xmlStrEqual(_, _)),
+    export_c_func!(xmlXPathInit()),
+    export_c_func!(xmlXPathNewContext(_)),
+    export_c_func!(xmlXPathFreeContext(_)),
+    export_c_func!(xmlXPathRegisterNs(_, _, _)),
+    export_c_func!(xmlXPathEval(_, _)),
+    export_c_func!(xmlXPathEvalExpression(_, _)),
+    export_c_func!(xmlXPathFreeObject(_)),
+];
@@ -11,6 +11,7 @@
 //! - [OpenAL 1.1 specification](https://www.openal.org/documentation/openal-1.1-specification.pdf)
 //! - Apple's [Technical Note TN2199: OpenAL FAQ for iPhone OS](https://web.archive.org/web/20090826202158/http://developer.apple.com/iPhone/library/technotes/tn2008/tn2199.html) (also available [here](https://developer.apple.com/library/archive/technotes/tn2199/_index.html))
 
+use crate::audio;
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
@@ -26,6 +27,9 @@ use touchHLE_openal_soft_wrapper::ALC_DEVICE_SPECIFIER;
 pub struct State {
     devices: HashMap<MutPtr<GuestALCdevice>, *mut ALCdevice>,
     contexts: HashMap<MutPtr<GuestALCcontext>, *mut ALCcontext>,
+    /// The listener gain the app has set for each context. The real listener
+    /// gain is this multiplied by the output volume.
+    listener_gains: HashMap<*mut ALCcontext, ALfloat>,
 }
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
@@ -44,6 +48,46 @@ struct GuestALCcontext {
 }
 impl SafeWrite for GuestALCcontext {}
 
+/// For use by [crate::frameworks::foundation::ns_run_loop]: if the app's
+/// devices have been disconnected, switch them to another one.
+pub fn handle_device_changes(env: &mut Environment) {
+    let preferred = env.options.audio_device.as_deref();
+    for &host_device in env.framework_state.openal.devices.values() {
+        audio::reconnect_output_device_if_needed(host_device, preferred);
+    }
+}
+
+/// For use by [crate::frameworks::foundation::ns_run_loop]: apply the
+/// power-saving governor's new audio settings to the app's devices.
+pub fn handle_power_saving_change(env: &mut Environment) {
+    let refresh_rate = env.audio_refresh_rate();
+    for &host_device in env.framework_state.openal.devices.values() {
+        audio::set_output_refresh_rate(host_device, refresh_rate);
+    }
+}
+
+/// For use by [crate::frameworks::audio_toolbox::audio_session]: apply a new
+/// output volume to all the app's contexts.
+pub fn handle_volume_change(env: &mut Environment) {
+    let host_contexts: Vec<_> = State::get(env).contexts.values().copied().collect();
+    let old_context = unsafe { al::alcGetCurrentContext() };
+    for host_context in host_contexts {
+        unsafe { al::alcMakeContextCurrent(host_context) };
+        update_listener_gain(env);
+    }
+    unsafe { al::alcMakeContextCurrent(old_context) };
+}
+
+/// Set the real listener gain for the current context, if it's one of the
+/// app's.
+fn update_listener_gain(env: &mut Environment) {
+    let host_context = unsafe { al::alcGetCurrentContext() };
+    let Some(&gain) = State::get(env).listener_gains.get(&host_context) else {
+        return;
+    };
+    unsafe { al::alListenerf(al::AL_GAIN, gain * env.options.volume) };
+}
+
 // === alc.h ===
 
 fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<GuestALCdevice> {
@@ -56,7 +100,9 @@ fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<Gues
         env.mem.free(d_name.cast_mut().cast());
     }
 
-    let res = unsafe { al::alcOpenDevice(std::ptr::null()) };
+    // The app can only ask for the default device (see above), so this gives
+    // the user the choice.
+    let res = audio::open_output_device(env.options.audio_device.as_deref());
     if res.is_null() {
         log_dbg!("alcOpenDevice(NULL) returned NULL");
         return Ptr::null();
@@ -106,7 +152,8 @@ fn alcCreateContext(
 
     let &host_device = State::get(env).devices.get(&device).unwrap();
 
-    let res = unsafe { al::alcCreateContext(host_device, std::ptr::null()) };
+    let attributes = audio::output_context_attributes(env.audio_refresh_rate());
+    let res = unsafe { al::alcCreateContext(host_device, attributes.as_ptr()) };
     if res.is_null() {
         log_dbg!("alcCreateContext({:?}, NULL) returned NULL", device);
         return Ptr::null();
@@ -114,6 +161,7 @@ fn alcCreateContext(
 
     let guest_res = env.mem.alloc_and_write(GuestALCcontext { _filler: 0 });
     State::get(env).contexts.insert(guest_res, res);
+    State::get(env).listener_gains.insert(res, 1.0);
     log_dbg!(
         "alcCreateContext({:?}, NULL) => {:?} (host: {:?})",
         device,
@@ -124,6 +172,7 @@ fn alcCreateContext(
 }
 fn alcDestroyContext(env: &mut Environment, context: MutPtr<GuestALCcontext>) {
     let host_context = State::get(env).contexts.remove(&context).unwrap();
+    State::get(env).listener_gains.remove(&host_context);
     env.mem.free(context.cast());
     unsafe { al::alcDestroyContext(host_context) };
     log_dbg!("alcDestroyContext({:?})", context);
@@ -146,6 +195,9 @@ fn alcMakeContextCurrent(env: &mut Environment, context: MutPtr<GuestALCcontext>
     };
     let res = unsafe { al::alcMakeContextCurrent(host_context) };
     log_dbg!("alcMakeContextCurrent({:?}) => {:?}", context, res);
+    if res != al::ALC_FALSE {
+        update_listener_gain(env);
+    }
     res != al::ALC_FALSE
 }
 
@@ -238,10 +290,19 @@ fn alIsSource(_env: &mut Environment, source: ALuint) -> ALboolean {
     unsafe { al::alIsSource(source) }
 }
 
-fn alListenerf(_env: &mut Environment, param: ALenum, value: ALfloat) {
+fn alListenerf(env: &mut Environment, param: ALenum, value: ALfloat) {
+    if param == al::AL_GAIN {
+        set_app_listener_gain(env, value);
+        return;
+    }
     unsafe { al::alListenerf(param, value) };
 }
 fn alListenerfv(env: &mut Environment, param: ALenum, values: ConstPtr<ALfloat>) {
+    if param == al::AL_GAIN {
+        let value = env.mem.read(values);
+        set_app_listener_gain(env, value);
+        return;
+    }
     // we assume that at least 1 parameter should be passed
     let values = env.mem.ptr_at(values, 1);
     unsafe { al::alListenerfv(param, values) };
@@ -274,7 +335,31 @@ fn alListeneriv(env: &mut Environment, param: ALenum, values: ConstPtr<ALint>) {
     unsafe { al::alListeneriv(param, values) };
 }
 
+/// The app's listener gain is scaled by the output volume, see
+/// [update_listener_gain].
+fn set_app_listener_gain(env: &mut Environment, value: ALfloat) {
+    let host_context = unsafe { al::alcGetCurrentContext() };
+    match State::get(env).listener_gains.get_mut(&host_context) {
+        // Invalid values are passed through so OpenAL Soft reports the error.
+        Some(gain) if value >= 0.0 => {
+            *gain = value;
+            update_listener_gain(env);
+        }
+        _ => unsafe { al::alListenerf(al::AL_GAIN, value) },
+    }
+}
+fn get_app_listener_gain(env: &mut Environment) -> Option<ALfloat> {
+    let host_context = unsafe { al::alcGetCurrentContext() };
+    State::get(env).listener_gains.get(&host_context).copied()
+}
+
 fn alGetListenerf(env: &mut Environment, param: ALenum, value: MutPtr<ALfloat>) {
+    if param == al::AL_GAIN {
+        if let Some(gain) = get_app_listener_gain(env) {
+            env.mem.write(value, gain);
+            return;
+        }
+    }
     unsafe { al::alGetListenerf(param, env.mem.ptr_at_mut(value, 1)) };
 }
 fn alGetListener3f(
@@ -292,6 +377,12 @@ fn alGetListener3f(
     env.mem.write(value3, values[2]);
 }
 fn alGetListenerfv(env: &mut Environment, param: ALenum, values: MutPtr<ALfloat>) {
+    if param == al::AL_GAIN {
+        if let Some(gain) = get_app_listener_gain(env) {
+            env.mem.write(values, gain);
+            return;
+        }
+    }
     let values = env.mem.ptr_at_mut(values, 3); // upper bound
     unsafe { al::alGetListenerfv(param, values) };
 }
@@ -10,12 +10,31 @@
 //! topic.
 
 pub mod eagl;
+mod frame_skip;
 mod gles_guest;
+mod npot;
+mod scale_hack;
+mod texture_filtering;
 
 use crate::mem::ConstPtr;
+pub use frame_skip::FrameSkip;
 pub use gles_guest::FUNCTIONS;
 use touchHLE_gl_bindings::gles11::types::GLenum;
 
+/// Whether the app's OpenGL ES calls are checked for errors, see the
+/// `--gl-error-check=` option.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GLErrorCheck {
+    /// Errors are only seen by the app, if it calls `glGetError()`.
+    #[default]
+    Off,
+    /// Errors are logged with the call that caused them.
+    Log,
+    /// Like [GLErrorCheck::Log], but errors from misuse that the iPhone's
+    /// PowerVR drivers are known to tolerate are hidden from the app.
+    Permissive,
+}
+
 #[derive(Default)]
 pub struct State {
     /// Current EAGLContext for each thread
@@ -23,6 +42,8 @@ pub struct State {
     /// Which thread's EAGLContext is currently active
     current_ctx_thread: Option<crate::ThreadId>,
     strings_cache: std::collections::HashMap<GLenum, ConstPtr<u8>>,
+    /// Reused by `glReadPixels()` when it has to convert the pixels.
+    read_pixels_buffer: Vec<u8>,
 }
 impl State {
     fn current_ctx_for_thread(&mut self, thread: crate::ThreadId) -> &mut Option<crate::objc::id> {
@@ -52,3 +73,17 @@ fn sync_context<'a>(
 
     gles_ctx
 }
+
+/// Record an error for the current context that the app will get from its next
+/// `glGetError()` call, unless there is already one waiting.
+fn record_error(env: &mut crate::Environment, err: GLenum) {
+    let ctx = env
+        .framework_state
+        .opengles
+        .current_ctx_for_thread(env.current_thread)
+        .unwrap();
+    env.objc
+        .borrow_mut::<eagl::EAGLContextHostObject>(ctx)
+        .pending_error
+        .get_or_insert(err);
+}
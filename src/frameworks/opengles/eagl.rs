@@ -4,10 +4,15 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! EAGL.
+//!
+//! With `--force-msaa=`, the storage of renderbuffers for drawables is
+//! multisampled, and depth and stencil renderbuffers attached alongside them
+//! are made to match (see [match_forced_msaa_attachments]). The app doesn't
+//! need to know: the renderbuffer is resolved when it is presented.
 
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::core_animation::ca_eagl_layer::{
-    find_fullscreen_eagl_layer, get_pixels_vec_for_presenting, present_pixels,
+    drawable_size, find_fullscreen_eagl_layer, get_pixels_vec_for_presenting, present_pixels,
 };
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::NSUInteger;
@@ -16,7 +21,6 @@ use crate::gles::gles11_raw::types::*;
 use crate::gles::present::{present_frame, FpsCounter};
 use crate::gles::{create_gles1_ctx, gles1_on_gl2, GLES};
 use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject};
-use crate::options::Options;
 use crate::window::Window;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
@@ -62,6 +66,14 @@ pub(super) struct EAGLContextHostObject {
     renderbuffer_drawable_bindings: HashMap<GLuint, id>,
     fps_counter: Option<FpsCounter>,
     next_frame_due: Option<Instant>,
+    frame_skip: super::frame_skip::FrameSkipState,
+    /// Error to return from the app's next `glGetError()` call before asking
+    /// the driver. This is used when touchHLE generates an error itself, and
+    /// with `--gl-error-check=`, where errors are taken from the driver after
+    /// each call.
+    pub(super) pending_error: Option<GLenum>,
+    pub(super) npot_textures: super::npot::NpotTextures,
+    pub(super) scale_hack: super::scale_hack::ScaleHackState,
 }
 impl HostObject for EAGLContextHostObject {}
 
@@ -77,6 +89,10 @@ pub const CLASSES: ClassExports = objc_classes! {
         renderbuffer_drawable_bindings: HashMap::new(),
         fps_counter: None,
         next_frame_due: None,
+        frame_skip: Default::default(),
+        pending_error: None,
+        npot_textures: Default::default(),
+        scale_hack: Default::default(),
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -165,19 +181,34 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
     let internalformat = gles11::RGBA8_OES;
 
-    let window = env.window.as_mut().expect("OpenGL ES is not supported in headless mode");
+    let (width, height) = drawable_size(env, drawable);
+    // apply scale hack: give the app a larger framebuffer than it asked for
+    let factor = env.options.scale_hack;
+    let (width, height) = (
+        (width as f32 * factor).round() as u32,
+        (height as f32 * factor).round() as u32,
+    );
+    log_dbg!("[renderbufferStorage:{:?} fromDrawable:{:?}] Allocating {}x{} storage", target, drawable, width, height);
 
-    // FIXME: get width and height from the layer!
-    let (width, height) = window.size_unrotated_scalehacked();
+    let window = env.window.as_mut().expect("OpenGL ES is not supported in headless mode");
 
     // Unclear from documentation if this method requires an appropriate context
     // to already be active, but that seems to be the case in practice?
     let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, window, env.current_thread);
+    let (width, height) = (width.try_into().unwrap(), height.try_into().unwrap());
+    let samples = env.options.force_msaa.map_or(0, |samples| samples.get() as GLsizei);
     let renderbuffer: GLuint = unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width.try_into().unwrap(), height.try_into().unwrap());
+        let samples = if samples > 0 {
+            samples.min(get_int(gles, gles11::MAX_SAMPLES_APPLE))
+        } else {
+            0
+        };
+        renderbuffer_storage(gles, samples, internalformat, width, height);
         let mut renderbuffer = 0;
         gles.GetIntegerv(gles11::RENDERBUFFER_BINDING_OES, &mut renderbuffer);
-        renderbuffer as _
+        let renderbuffer = renderbuffer as _;
+        resize_depth_stencil_attachments(gles, renderbuffer, width, height, samples);
+        renderbuffer
     };
 
     retain(env, drawable);
@@ -197,7 +228,9 @@ pub const CLASSES: ClassExports = objc_classes! {
 
     // The presented frame should be displayed ASAP, but the next one must be
     // delayed, so this needs to be checked before returning.
-    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, &env.options);
+    let frame_due = env.objc.borrow::<EAGLContextHostObject>(this).next_frame_due;
+    let fps_limit = env.fps_limit();
+    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, fps_limit);
 
     if env.options.print_fps {
         env
@@ -208,6 +241,15 @@ pub const CLASSES: ClassExports = objc_classes! {
             .count_frame(format_args!("EAGLContext {:?}", this));
     }
 
+    let frame_skip = env.options.frame_skip;
+    if env.objc.borrow_mut::<EAGLContextHostObject>(this).frame_skip.should_skip(frame_skip, frame_due) {
+        log_dbg!("Skipping presentation of frame for EAGLContext {:?}", this);
+        if let Some(sleep_for) = sleep_for {
+            env.sleep(sleep_for, /* tail_call: */ false);
+        }
+        return true;
+    }
+
     let fullscreen_layer = find_fullscreen_eagl_layer(env);
 
     // Unclear from documentation if this method requires the context to be
@@ -236,10 +278,11 @@ pub const CLASSES: ClassExports = objc_classes! {
             drawable,
             renderbuffer,
         );
+        let force_msaa = env.options.force_msaa.is_some();
         // re-borrow
         let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
         unsafe {
-            present_renderbuffer(gles, env.window.as_mut().unwrap());
+            present_renderbuffer(gles, env.window.as_mut().unwrap(), force_msaa);
         }
     } else {
         if fullscreen_layer != nil {
@@ -270,10 +313,11 @@ pub const CLASSES: ClassExports = objc_classes! {
             drawable,
         );
         let pixels_vec = get_pixels_vec_for_presenting(env, drawable);
+        let force_msaa = env.options.force_msaa.is_some();
         // re-borrow
         let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
         let (pixels_vec, width, height) = unsafe {
-            read_renderbuffer(gles, pixels_vec)
+            read_renderbuffer(gles, pixels_vec, force_msaa)
         };
         present_pixels(env, drawable, pixels_vec, width, height);
     }
@@ -307,8 +351,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 /// an interval's worth of accumulated slop. Allowing infinite accumulation of
 /// slop is not desirable, because if the game is running slowly for a long time
 /// and suddenly speeds back up, it will then run too fast for a long time.
-fn limit_framerate(next_frame_due: &mut Option<Instant>, options: &Options) -> Option<Duration> {
-    let interval = if let Some(fps) = options.fps_limit {
+fn limit_framerate(
+    next_frame_due: &mut Option<Instant>,
+    fps_limit: Option<f64>,
+) -> Option<Duration> {
+    let interval = if let Some(fps) = fps_limit {
         1.0 / fps
     } else {
         return None;
@@ -387,20 +434,245 @@ unsafe fn get_floats<const N: usize>(gles: &mut dyn GLES, pname: GLenum) -> [GLf
     gles.GetFloatv(pname, res.as_mut_ptr());
     res
 }
+unsafe fn get_renderbuffer_int(gles: &mut dyn GLES, pname: GLenum) -> GLint {
+    let mut res = 0;
+    gles.GetRenderbufferParameterivOES(gles11::RENDERBUFFER_OES, pname, &mut res);
+    res
+}
 unsafe fn get_renderbuffer_size(gles: &mut dyn GLES) -> (GLsizei, GLsizei) {
-    let mut width: GLint = 0;
-    let mut height: GLint = 0;
-    gles.GetRenderbufferParameterivOES(
+    (
+        get_renderbuffer_int(gles, gles11::RENDERBUFFER_WIDTH_OES),
+        get_renderbuffer_int(gles, gles11::RENDERBUFFER_HEIGHT_OES),
+    )
+}
+
+/// Get the name of the renderbuffer attached to the framebuffer bound to
+/// `GL_FRAMEBUFFER_OES` at a particular attachment point, if there is one.
+unsafe fn get_attached_renderbuffer(gles: &mut dyn GLES, attachment: GLenum) -> Option<GLuint> {
+    let mut object_type: GLint = 0;
+    gles.GetFramebufferAttachmentParameterivOES(
+        gles11::FRAMEBUFFER_OES,
+        attachment,
+        gles11::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE_OES,
+        &mut object_type,
+    );
+    if object_type as GLenum != gles11::RENDERBUFFER_OES {
+        return None;
+    }
+    let mut name: GLint = 0;
+    gles.GetFramebufferAttachmentParameterivOES(
+        gles11::FRAMEBUFFER_OES,
+        attachment,
+        gles11::FRAMEBUFFER_ATTACHMENT_OBJECT_NAME_OES,
+        &mut name,
+    );
+    Some(name as _)
+}
+
+/// Allocate storage for the renderbuffer bound to `GL_RENDERBUFFER_OES`,
+/// multisampled if `samples` is not zero.
+unsafe fn renderbuffer_storage(
+    gles: &mut dyn GLES,
+    samples: GLsizei,
+    internalformat: GLenum,
+    width: GLsizei,
+    height: GLsizei,
+) {
+    if samples > 0 {
+        gles.RenderbufferStorageMultisampleAPPLE(
+            gles11::RENDERBUFFER_OES,
+            samples,
+            internalformat,
+            width,
+            height,
+        );
+    } else {
+        gles.RenderbufferStorageOES(gles11::RENDERBUFFER_OES, internalformat, width, height);
+    }
+}
+
+/// Called when the storage of a drawable's renderbuffer (bound to
+/// `GL_RENDERBUFFER_BINDING_OES`) has been (re)allocated: if it is the color
+/// attachment of the current framebuffer, resize the depth and stencil
+/// attachments to match, keeping their formats. Their sample count is also
+/// made to match, for `--force-msaa=`.
+///
+/// Apps are meant to do this themselves, but some only allocate their depth
+/// buffer once, or size it from something other than the drawable, and then
+/// the framebuffer is incomplete after e.g. rotation. Doing it here means the
+/// size of everything follows the `CAEAGLLayer`.
+unsafe fn resize_depth_stencil_attachments(
+    gles: &mut dyn GLES,
+    color_renderbuffer: GLuint,
+    width: GLsizei,
+    height: GLsizei,
+    samples: GLsizei,
+) {
+    if get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) == 0
+        || get_attached_renderbuffer(gles, gles11::COLOR_ATTACHMENT0_OES)
+            != Some(color_renderbuffer)
+    {
+        return;
+    }
+
+    let depth = get_attached_renderbuffer(gles, gles11::DEPTH_ATTACHMENT_OES);
+    let stencil = get_attached_renderbuffer(gles, gles11::STENCIL_ATTACHMENT_OES);
+    // A packed depth-stencil renderbuffer is attached at both points.
+    let stencil = stencil.filter(|&stencil| Some(stencil) != depth);
+    for renderbuffer in [depth, stencil].into_iter().flatten() {
+        gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, renderbuffer);
+        if get_renderbuffer_size(gles) == (width, height)
+            // Only query this when needed, in case the driver lacks MSAA.
+            && (samples == 0
+                || get_renderbuffer_int(gles, gles11::RENDERBUFFER_SAMPLES_APPLE) == samples)
+        {
+            continue;
+        }
+        let internalformat = get_renderbuffer_int(gles, gles11::RENDERBUFFER_INTERNAL_FORMAT_OES);
+        log_dbg!(
+            "Resizing renderbuffer {} to {}x{} ({} samples) to match drawable renderbuffer {}",
+            renderbuffer,
+            width,
+            height,
+            samples,
+            color_renderbuffer
+        );
+        renderbuffer_storage(gles, samples, internalformat as GLenum, width, height);
+    }
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, color_renderbuffer);
+}
+
+/// For `--force-msaa=`: called after the app attaches a renderbuffer or
+/// (re)allocates its storage. If the current framebuffer's color attachment
+/// is multisampled, its depth and stencil attachments must be too, or the
+/// framebuffer is incomplete. The app doesn't know the drawable's
+/// renderbuffer is multisampled, so this can't be left to it.
+///
+/// The provided context must be current.
+pub(super) unsafe fn match_forced_msaa_attachments(gles: &mut dyn GLES) {
+    if get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) == 0 {
+        return;
+    }
+    let Some(color_renderbuffer) = get_attached_renderbuffer(gles, gles11::COLOR_ATTACHMENT0_OES)
+    else {
+        return;
+    };
+    let old_renderbuffer: GLuint = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as _;
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, color_renderbuffer);
+    let samples = get_renderbuffer_int(gles, gles11::RENDERBUFFER_SAMPLES_APPLE);
+    if samples > 0 {
+        let (width, height) = get_renderbuffer_size(gles);
+        resize_depth_stencil_attachments(gles, color_renderbuffer, width, height, samples);
+    }
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, old_renderbuffer);
+}
+
+/// For `--force-msaa=`: reading pixels from a multisampled framebuffer is an
+/// error, but the app doesn't know the drawable's renderbuffer is
+/// multisampled. If the bound framebuffer's color attachment is a
+/// multisampled renderbuffer, this resolves it and binds a temporary
+/// framebuffer with the result while `f` is called, so that the app's
+/// `glReadPixels()` etc work. The bindings are restored afterwards.
+/// `force_msaa` should be [true] if `--force-msaa=` is in use, otherwise `f`
+/// is simply called.
+///
+/// The provided context must be current.
+pub(super) unsafe fn with_forced_msaa_resolved<T>(
+    gles: &mut dyn GLES,
+    force_msaa: bool,
+    f: impl FnOnce(&mut dyn GLES) -> T,
+) -> T {
+    if !force_msaa {
+        return f(gles);
+    }
+    let old_framebuffer: GLuint = get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) as _;
+    if old_framebuffer == 0 {
+        return f(gles);
+    }
+    let Some(color_renderbuffer) = get_attached_renderbuffer(gles, gles11::COLOR_ATTACHMENT0_OES)
+    else {
+        return f(gles);
+    };
+    let old_renderbuffer: GLuint = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as _;
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, color_renderbuffer);
+    let (width, height) = get_renderbuffer_size(gles);
+    let resolved_renderbuffer = resolve_renderbuffer(gles, width, height);
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, old_renderbuffer);
+    let Some(resolved_renderbuffer) = resolved_renderbuffer else {
+        return f(gles);
+    };
+
+    let mut framebuffer = 0;
+    gles.GenFramebuffersOES(1, &mut framebuffer);
+    gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, framebuffer);
+    gles.FramebufferRenderbufferOES(
+        gles11::FRAMEBUFFER_OES,
+        gles11::COLOR_ATTACHMENT0_OES,
+        gles11::RENDERBUFFER_OES,
+        resolved_renderbuffer,
+    );
+
+    let result = f(gles);
+
+    gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, old_framebuffer);
+    gles.DeleteFramebuffersOES(1, &framebuffer);
+    gles.DeleteRenderbuffersOES(1, &resolved_renderbuffer);
+    result
+}
+
+/// If the renderbuffer bound to `GL_RENDERBUFFER_BINDING_OES` is multisampled
+/// (see `--force-msaa=`), resolve it to a new single-sampled renderbuffer so
+/// its pixels can be copied, and return the new renderbuffer, which the caller
+/// must delete. Framebuffer bindings are not preserved.
+///
+/// The provided context must be current.
+unsafe fn resolve_renderbuffer(
+    gles: &mut dyn GLES,
+    width: GLsizei,
+    height: GLsizei,
+) -> Option<GLuint> {
+    if get_renderbuffer_int(gles, gles11::RENDERBUFFER_SAMPLES_APPLE) == 0 {
+        return None;
+    }
+    let renderbuffer: GLuint = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as _;
+
+    let mut resolved_renderbuffer = 0;
+    gles.GenRenderbuffersOES(1, &mut resolved_renderbuffer);
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, resolved_renderbuffer);
+    gles.RenderbufferStorageOES(gles11::RENDERBUFFER_OES, gles11::RGBA8_OES, width, height);
+
+    let mut framebuffers = [0; 2];
+    gles.GenFramebuffersOES(2, framebuffers.as_mut_ptr());
+    let [src_framebuffer, dst_framebuffer] = framebuffers;
+    gles.BindFramebufferOES(gles11::READ_FRAMEBUFFER_APPLE, src_framebuffer);
+    gles.FramebufferRenderbufferOES(
+        gles11::READ_FRAMEBUFFER_APPLE,
+        gles11::COLOR_ATTACHMENT0_OES,
         gles11::RENDERBUFFER_OES,
-        gles11::RENDERBUFFER_WIDTH_OES,
-        &mut width,
+        renderbuffer,
     );
-    gles.GetRenderbufferParameterivOES(
+    gles.BindFramebufferOES(gles11::DRAW_FRAMEBUFFER_APPLE, dst_framebuffer);
+    gles.FramebufferRenderbufferOES(
+        gles11::DRAW_FRAMEBUFFER_APPLE,
+        gles11::COLOR_ATTACHMENT0_OES,
         gles11::RENDERBUFFER_OES,
-        gles11::RENDERBUFFER_HEIGHT_OES,
-        &mut height,
+        resolved_renderbuffer,
     );
-    (width, height)
+
+    // The resolve is limited to the scissor box, but the whole renderbuffer
+    // is wanted.
+    let mut scissor_test = gles11::FALSE;
+    gles.GetBooleanv(gles11::SCISSOR_TEST, &mut scissor_test);
+    gles.Disable(gles11::SCISSOR_TEST);
+    gles.ResolveMultisampleFramebufferAPPLE();
+    if scissor_test == gles11::TRUE {
+        gles.Enable(gles11::SCISSOR_TEST);
+    }
+
+    gles.DeleteFramebuffersOES(2, framebuffers.as_ptr());
+    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, renderbuffer);
+
+    Some(resolved_renderbuffer)
 }
 
 /// Copies the pixels in a renderbuffer bound to `GL_RENDERBUFFER_BINDING_OES`
@@ -409,10 +681,15 @@ unsafe fn get_renderbuffer_size(gles: &mut dyn GLES) -> (GLsizei, GLsizei) {
 ///
 /// This uses `glReadPixels()`, with all the associated performance risks. Any
 /// existing content in the [Vec] will bereplaced. The format is RGBA8.
-/// The returned values are the [Vec], the width and height.
+/// The returned values are the [Vec], the width and height. `force_msaa`
+/// should be set if `--force-msaa=` is in use.
 ///
 /// The provided context must be current.
-unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (Vec<u8>, u32, u32) {
+unsafe fn read_renderbuffer(
+    gles: &mut dyn GLES,
+    mut pixel_buffer: Vec<u8>,
+    force_msaa: bool,
+) -> (Vec<u8>, u32, u32) {
     let renderbuffer: GLuint = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as _;
     let (width, height) = get_renderbuffer_size(gles);
     let width_u32: u32 = width.try_into().unwrap();
@@ -422,6 +699,12 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
     // state changes we make.
     let old_framebuffer: GLuint = get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) as _;
 
+    let resolved_renderbuffer = if force_msaa {
+        resolve_renderbuffer(gles, width, height)
+    } else {
+        None
+    };
+
     // Create a framebuffer we can use to read from the renderbuffer
     let mut src_framebuffer = 0;
     gles.GenFramebuffersOES(1, &mut src_framebuffer);
@@ -430,7 +713,7 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
         gles11::FRAMEBUFFER_OES,
         gles11::COLOR_ATTACHMENT0_OES,
         gles11::RENDERBUFFER_OES,
-        renderbuffer,
+        resolved_renderbuffer.unwrap_or(renderbuffer),
     );
 
     // Read the pixels
@@ -461,6 +744,9 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
 
     // Clean up the framebuffer object since we no longer need it.
     gles.DeleteFramebuffersOES(1, &src_framebuffer);
+    if let Some(resolved_renderbuffer) = resolved_renderbuffer {
+        gles.DeleteRenderbuffersOES(1, &resolved_renderbuffer);
+    }
 
     // Restore the framebuffer binding
     gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, old_framebuffer);
@@ -471,10 +757,11 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
 /// Copies the pixels in a renderbuffer bound to `GL_RENDERBUFFER_BINDING_OES`
 /// (which should be provided by the app) to a texture and presents it with
 /// [present_frame], trying to avoid noticeably modifying OpenGL ES state while
-/// doing so. The front and back buffers are then swapped.
+/// doing so. The front and back buffers are then swapped. `force_msaa` should
+/// be set if `--force-msaa=` is in use.
 ///
 /// The provided context must be current.
-unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
+unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window, force_msaa: bool) {
     // We can't directly copy the content of the renderbuffer to the default
     // framebuffer (the window), but if we attach it to a framebuffer object, we
     // can use glCopyTexImage2D() to copy it to a texture, which we can then
@@ -489,6 +776,12 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
     let old_framebuffer: GLuint = get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) as _;
     let old_texture_2d: GLuint = get_int(gles, gles11::TEXTURE_BINDING_2D) as _;
 
+    let resolved_renderbuffer = if force_msaa {
+        resolve_renderbuffer(gles, width, height)
+    } else {
+        None
+    };
+
     // Create a framebuffer we can use to read from the renderbuffer
     let mut src_framebuffer = 0;
     gles.GenFramebuffersOES(1, &mut src_framebuffer);
@@ -497,7 +790,7 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
         gles11::FRAMEBUFFER_OES,
         gles11::COLOR_ATTACHMENT0_OES,
         gles11::RENDERBUFFER_OES,
-        renderbuffer,
+        resolved_renderbuffer.unwrap_or(renderbuffer),
     );
 
     // Create a texture with a copy of the pixels in the framebuffer
@@ -526,6 +819,9 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
     // This also sets the framebuffer bindings back to zero, so rendering
     // will go to the default framebuffer (the window).
     gles.DeleteFramebuffersOES(1, &src_framebuffer);
+    if let Some(resolved_renderbuffer) = resolved_renderbuffer {
+        gles.DeleteRenderbuffersOES(1, &resolved_renderbuffer);
+    }
 
     // Reset various things that could affect the quad or virtual cursor we're
     // going to draw. Back up the old state while doing so, so it can be
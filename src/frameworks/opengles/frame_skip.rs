@@ -0,0 +1,58 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Frame skipping, see the `--frame-skip=` option.
+//!
+//! A skipped frame is one whose `presentRenderbuffer:` call returns without
+//! copying the renderbuffer anywhere. The app still renders it, but on slow
+//! hosts the presentation work (resolving, copying, composition and swapping)
+//! is a significant part of the frame time. Framerate limiting still happens
+//! for skipped frames, so the app's logic and audio run at the usual speed.
+
+use std::num::NonZeroU32;
+use std::time::Instant;
+
+/// Most frames that are skipped in a row in [FrameSkip::Auto] mode, so that
+/// something is still displayed even if the app can never catch up.
+const AUTO_MAX_SKIPPED_IN_A_ROW: u32 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameSkip {
+    /// Skip this many frames after each one that is presented.
+    Fixed(NonZeroU32),
+    /// Skip a frame if it is presented later than the framerate limit allows
+    /// for. This has no effect if the framerate limit is disabled.
+    Auto,
+}
+
+#[derive(Default)]
+pub(super) struct FrameSkipState {
+    skipped_in_a_row: u32,
+}
+
+impl FrameSkipState {
+    /// Decide whether the frame being presented should be skipped.
+    /// `frame_due` is when the framerate limiter expected the frame, if known.
+    pub(super) fn should_skip(
+        &mut self,
+        frame_skip: Option<FrameSkip>,
+        frame_due: Option<Instant>,
+    ) -> bool {
+        let skip = match frame_skip {
+            None => false,
+            Some(FrameSkip::Fixed(count)) => self.skipped_in_a_row < count.get(),
+            Some(FrameSkip::Auto) => {
+                self.skipped_in_a_row < AUTO_MAX_SKIPPED_IN_A_ROW
+                    && frame_due.is_some_and(|due| Instant::now() > due)
+            }
+        };
+        if skip {
+            self.skipped_in_a_row += 1;
+        } else {
+            self.skipped_in_a_row = 0;
+        }
+        skip
+    }
+}
@@ -12,10 +12,16 @@
 //! depending on the value of `pname`, using the upper bound (4 in this case)
 //! every time is never going to cause a problem in practice.
 
+use super::eagl::{
+    match_forced_msaa_attachments, with_forced_msaa_resolved, EAGLContextHostObject,
+};
+use super::{npot, scale_hack, texture_filtering, GLErrorCheck};
+use crate::cpu::Cpu;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::GLES;
-use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, Mem, MutPtr};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, Mem, MutPtr, MutVoidPtr};
+use crate::options::Gpu;
 use crate::Environment;
 
 // These types are the same size in guest code (32-bit) and host code (64-bit).
@@ -41,32 +47,139 @@ where
         env.current_thread,
     );
 
-    //panic_on_gl_errors(&mut **gles);
     let res = f(gles, &mut env.mem);
-    //panic_on_gl_errors(&mut **gles);
-    #[allow(clippy::let_and_return)]
+    if env.options.gl_error_check != GLErrorCheck::Off {
+        check_gl_errors(env);
+    }
     res
 }
 
-/// Useful for debugging
-#[allow(dead_code)]
-fn panic_on_gl_errors(gles: &mut dyn GLES) {
-    let mut did_error = false;
+fn gl_error_name(err: GLenum) -> &'static str {
+    match err {
+        gles11::INVALID_ENUM => "GL_INVALID_ENUM",
+        gles11::INVALID_VALUE => "GL_INVALID_VALUE",
+        gles11::INVALID_OPERATION => "GL_INVALID_OPERATION",
+        gles11::STACK_OVERFLOW => "GL_STACK_OVERFLOW",
+        gles11::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW",
+        gles11::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+        gles11::INVALID_FRAMEBUFFER_OPERATION_OES => "GL_INVALID_FRAMEBUFFER_OPERATION_OES",
+        _ => "unknown error",
+    }
+}
+
+/// Whether an error is from a kind of misuse that the iPhone's PowerVR
+/// drivers accept. See also the "Tolerating" cases in
+/// [crate::gles::gles1_on_gl2].
+fn is_tolerated_by_powervr(function: &str, args: [u32; 4], err: GLenum) -> bool {
+    match (function, err) {
+        // Using glEnable() rather than glEnableClientState() for arrays.
+        ("glEnable" | "glDisable", gles11::INVALID_ENUM) => [
+            gles11::VERTEX_ARRAY,
+            gles11::NORMAL_ARRAY,
+            gles11::COLOR_ARRAY,
+            gles11::TEXTURE_COORD_ARRAY,
+        ]
+        .contains(&args[0]),
+        // Using a source-only factor as the destination factor or vice versa.
+        ("glBlendFunc", gles11::INVALID_ENUM) => {
+            let [sfactor, dfactor, ..] = args;
+            let common_factors = [
+                gles11::ZERO,
+                gles11::ONE,
+                gles11::SRC_ALPHA,
+                gles11::ONE_MINUS_SRC_ALPHA,
+                gles11::DST_ALPHA,
+                gles11::ONE_MINUS_DST_ALPHA,
+            ];
+            let sfactors = [
+                gles11::DST_COLOR,
+                gles11::ONE_MINUS_DST_COLOR,
+                gles11::SRC_ALPHA_SATURATE,
+            ];
+            let dfactors = [gles11::SRC_COLOR, gles11::ONE_MINUS_SRC_COLOR];
+            let is_factor = |factor| {
+                common_factors.contains(&factor)
+                    || sfactors.contains(&factor)
+                    || dfactors.contains(&factor)
+            };
+            is_factor(sfactor)
+                && is_factor(dfactor)
+                && (dfactors.contains(&sfactor) || sfactors.contains(&dfactor))
+        }
+        _ => false,
+    }
+}
+
+/// Implements `--gl-error-check=`: take any errors from the driver after a
+/// call, log them, and keep the first one for the app's next `glGetError()`.
+fn check_gl_errors(env: &mut Environment) {
+    let function = env.current_host_function().unwrap_or("(unknown)");
+    let function = function.strip_prefix('_').unwrap_or(function);
+    if function == "glGetError" {
+        return;
+    }
+    // The host function hasn't returned yet, so the argument registers and
+    // the return address are still intact.
+    let regs = env.cpu.regs();
+    let args = [regs[0], regs[1], regs[2], regs[3]];
+    let return_addr = regs[Cpu::LR];
+
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    let mut errors = Vec::new();
     loop {
         let err = unsafe { gles.GetError() };
-        if err == 0 {
+        if err == gles11::NO_ERROR {
             break;
         }
-        did_error = true;
-        echo!("glGetError() => {:#x}", err);
+        errors.push(err);
     }
-    if did_error {
-        panic!();
+    if errors.is_empty() {
+        return;
+    }
+
+    let call_site = env.describe_code_addr(return_addr);
+    let permissive = env.options.gl_error_check == GLErrorCheck::Permissive;
+    for err in errors {
+        let tolerated = permissive && is_tolerated_by_powervr(function, args, err);
+        log!(
+            "GL error {} ({:#x}) in {}() with r0 = {:#x}, r1 = {:#x}, r2 = {:#x}, r3 = {:#x}, called from {}{}",
+            gl_error_name(err),
+            err,
+            function,
+            args[0],
+            args[1],
+            args[2],
+            args[3],
+            call_site,
+            if tolerated { " (hidden from app)" } else { "" },
+        );
+        if !tolerated {
+            super::record_error(env, err);
+        }
     }
 }
 
 // Generic state manipulation
 fn glGetError(env: &mut Environment) -> GLenum {
+    let ctx = env
+        .framework_state
+        .opengles
+        .current_ctx_for_thread(env.current_thread)
+        .unwrap();
+    if let Some(err) = env
+        .objc
+        .borrow_mut::<EAGLContextHostObject>(ctx)
+        .pending_error
+        .take()
+    {
+        log!("Warning: glGetError() returned {:#x}", err);
+        return err;
+    }
     with_ctx_and_mem(env, |gles, _mem| {
         let err = unsafe { gles.GetError() };
         if err != 0 {
@@ -105,19 +218,82 @@ fn glDisableClientState(env: &mut Environment, array: GLenum) {
         unsafe { gles.DisableClientState(array) };
     });
 }
+/// Some apps use the implementation limits to decide what quality level to
+/// use, so they should be the simulated device's rather than the host's. This
+/// returns the limit to report for `pname`, if it's one of those, for all of
+/// the `glGet*v()` functions.
+fn get_device_limit(env: &mut Environment, pname: GLenum) -> Option<GLint> {
+    let gpu = env.options.device_model.gpu;
+    let device_limit = match pname {
+        gles11::MAX_TEXTURE_SIZE => gpu.max_texture_size(),
+        gles11::MAX_TEXTURE_UNITS => gpu.max_texture_units(),
+        // PowerVR SGX limit. The extension is advertised for all devices.
+        gles11::MAX_SAMPLES_APPLE => 4,
+        _ => return None,
+    };
+    let mut host_limit = 0;
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.GetIntegerv(pname, &mut host_limit)
+    });
+    Some(host_limit.min(device_limit))
+}
+
 fn glGetBooleanv(env: &mut Environment, pname: GLenum, params: MutPtr<GLboolean>) {
+    if let Some(limit) = get_device_limit(env, pname) {
+        let value = if limit != 0 {
+            gles11::TRUE
+        } else {
+            gles11::FALSE
+        };
+        env.mem.write(params, value);
+        return;
+    }
     with_ctx_and_mem(env, |gles, mem| {
         let params = mem.ptr_at_mut(params, 16 /* upper bound */);
         unsafe { gles.GetBooleanv(pname, params) };
     });
 }
 fn glGetFloatv(env: &mut Environment, pname: GLenum, params: MutPtr<GLfloat>) {
+    if let Some(rect) = scale_hack::get_rect(env, pname) {
+        for (i, v) in rect.into_iter().enumerate() {
+            env.mem.write(params + i as GuestUSize, v as GLfloat);
+        }
+        return;
+    }
+    if let Some(limit) = get_device_limit(env, pname) {
+        env.mem.write(params, limit as GLfloat);
+        return;
+    }
     with_ctx_and_mem(env, |gles, mem| {
         let params = mem.ptr_at_mut(params, 16 /* upper bound */);
         unsafe { gles.GetFloatv(pname, params) };
     });
 }
 fn glGetIntegerv(env: &mut Environment, pname: GLenum, params: MutPtr<GLint>) {
+    // The preferred format for glReadPixels(), which the host's driver may not
+    // know about. RGBA/UNSIGNED_BYTE is the one the host can write directly,
+    // without conversion.
+    match pname {
+        IMPLEMENTATION_COLOR_READ_FORMAT_OES => {
+            env.mem.write(params, gles11::RGBA as GLint);
+            return;
+        }
+        IMPLEMENTATION_COLOR_READ_TYPE_OES => {
+            env.mem.write(params, gles11::UNSIGNED_BYTE as GLint);
+            return;
+        }
+        _ => (),
+    }
+    if let Some(rect) = scale_hack::get_rect(env, pname) {
+        for (i, v) in rect.into_iter().enumerate() {
+            env.mem.write(params + i as GuestUSize, v);
+        }
+        return;
+    }
+    if let Some(limit) = get_device_limit(env, pname) {
+        env.mem.write(params, limit);
+        return;
+    }
     with_ctx_and_mem(env, |gles, mem| {
         let params = mem.ptr_at_mut(params, 16 /* upper bound */);
         unsafe { gles.GetIntegerv(pname, params) };
@@ -139,18 +315,27 @@ fn glGetString(env: &mut Environment, name: GLenum) -> ConstPtr<GLubyte> {
     let res = if let Some(&str) = env.framework_state.opengles.strings_cache.get(&name) {
         str
     } else {
+        let sgx = env.options.device_model.gpu == Gpu::PowerVRSGX535;
         let new_str = with_ctx_and_mem(env, |_gles, mem| {
             // Those values are extracted from the iPod touch 2nd gen, iOS 4.2.1
+            // The SGX devices are distinguished by the renderer and the NPOT
+            // extension, see [super::npot].
             let s: &[u8] = match name {
                 gles11::VENDOR => {
                     b"Imagination Technologies"
                 }
+                gles11::RENDERER if sgx => {
+                    b"PowerVR SGX 535"
+                }
                 gles11::RENDERER => {
                     b"PowerVR MBXLite with VGPLite"
                 }
                 gles11::VERSION => {
                     b"OpenGL ES-CM 1.1 (76)"
                 }
+                gles11::EXTENSIONS if sgx => {
+                    b"GL_APPLE_framebuffer_multisample GL_APPLE_texture_2D_limited_npot GL_APPLE_texture_max_level GL_EXT_discard_framebuffer GL_EXT_texture_filter_anisotropic GL_EXT_texture_lod_bias GL_IMG_read_format GL_IMG_texture_compression_pvrtc GL_IMG_texture_format_BGRA8888 GL_OES_blend_subtract GL_OES_compressed_paletted_texture GL_OES_depth24 GL_OES_draw_texture GL_OES_framebuffer_object GL_OES_mapbuffer GL_OES_matrix_palette GL_OES_point_size_array GL_OES_point_sprite GL_OES_read_format GL_OES_rgb8_rgba8 GL_OES_texture_mirrored_repeat GL_OES_vertex_array_object "
+                }
                 gles11::EXTENSIONS => {
                     b"GL_APPLE_framebuffer_multisample GL_APPLE_texture_max_level GL_EXT_discard_framebuffer GL_EXT_texture_filter_anisotropic GL_EXT_texture_lod_bias GL_IMG_read_format GL_IMG_texture_compression_pvrtc GL_IMG_texture_format_BGRA8888 GL_OES_blend_subtract GL_OES_compressed_paletted_texture GL_OES_depth24 GL_OES_draw_texture GL_OES_framebuffer_object GL_OES_mapbuffer GL_OES_matrix_palette GL_OES_point_size_array GL_OES_point_sprite GL_OES_read_format GL_OES_rgb8_rgba8 GL_OES_texture_mirrored_repeat GL_OES_vertex_array_object "
                 }
@@ -223,21 +408,17 @@ fn glShadeModel(env: &mut Environment, mode: GLenum) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ShadeModel(mode) })
 }
 fn glScissor(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
-    // apply scale hack: assume framebuffer's size is larger than the app thinks
-    // and scale scissor appropriately
-    let factor = env.options.scale_hack.get() as GLsizei;
-    let (x, y) = (x * factor, y * factor);
-    let (width, height) = (width * factor, height * factor);
+    if width >= 0 && height >= 0 && scale_hack::set_scissor_box(env, [x, y, width, height]) {
+        return;
+    }
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.Scissor(x, y, width, height)
     })
 }
 fn glViewport(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
-    // apply scale hack: assume framebuffer's size is larger than the app thinks
-    // and scale viewport appropriately
-    let factor = env.options.scale_hack.get() as GLsizei;
-    let (x, y) = (x * factor, y * factor);
-    let (width, height) = (width * factor, height * factor);
+    if width >= 0 && height >= 0 && scale_hack::set_viewport(env, [x, y, width, height]) {
+        return;
+    }
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.Viewport(x, y, width, height)
     })
@@ -494,12 +675,25 @@ fn glVertexPointer(
         gles.VertexPointer(size, type_, stride, pointer)
     })
 }
+fn glPointSizePointerOES(
+    env: &mut Environment,
+    type_: GLenum,
+    stride: GLsizei,
+    pointer: ConstVoidPtr,
+) {
+    with_ctx_and_mem(env, |gles, mem| unsafe {
+        let pointer = translate_pointer_or_offset(gles, mem, pointer, gles11::ARRAY_BUFFER_BINDING);
+        gles.PointSizePointerOES(type_, stride, pointer)
+    })
+}
 
 // Drawing
 fn glDrawArrays(env: &mut Environment, mode: GLenum, first: GLint, count: GLsizei) {
+    let disabled_units = npot::disable_incomplete_units(env);
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.DrawArrays(mode, first, count)
-    })
+    });
+    npot::restore_units(env, disabled_units);
 }
 fn glDrawElements(
     env: &mut Environment,
@@ -508,11 +702,13 @@ fn glDrawElements(
     type_: GLenum,
     indices: ConstVoidPtr,
 ) {
+    let disabled_units = npot::disable_incomplete_units(env);
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let indices =
             translate_pointer_or_offset(gles, mem, indices, gles11::ELEMENT_ARRAY_BUFFER_BINDING);
         gles.DrawElements(mode, count, type_, indices)
-    })
+    });
+    npot::restore_units(env, disabled_units);
 }
 
 // Clearing
@@ -683,6 +879,166 @@ fn glTranslatex(env: &mut Environment, x: GLfixed, y: GLfixed, z: GLfixed) {
 fn glPixelStorei(env: &mut Environment, pname: GLenum, param: GLint) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PixelStorei(pname, param) })
 }
+
+// OES_read_format and IMG_read_format constants, which aren't in our bindings.
+const IMPLEMENTATION_COLOR_READ_TYPE_OES: GLenum = 0x8B9A;
+const IMPLEMENTATION_COLOR_READ_FORMAT_OES: GLenum = 0x8B9B;
+const UNSIGNED_SHORT_4_4_4_4_REV_IMG: GLenum = 0x8365;
+const UNSIGNED_SHORT_1_5_5_5_REV_IMG: GLenum = 0x8366;
+
+/// Size of a pixel in the result of `glReadPixels()`, or the error if the
+/// format and type aren't a valid combination.
+fn read_pixels_pixel_size(format: GLenum, type_: GLenum) -> Result<GuestUSize, GLenum> {
+    match (format, type_) {
+        (gles11::RGBA | gles11::BGRA_EXT, gles11::UNSIGNED_BYTE) => Ok(4),
+        (gles11::RGB, gles11::UNSIGNED_BYTE) => Ok(3),
+        (gles11::LUMINANCE_ALPHA, gles11::UNSIGNED_BYTE) => Ok(2),
+        (gles11::ALPHA | gles11::LUMINANCE, gles11::UNSIGNED_BYTE) => Ok(1),
+        (gles11::RGB, gles11::UNSIGNED_SHORT_5_6_5) => Ok(2),
+        (gles11::RGBA, gles11::UNSIGNED_SHORT_4_4_4_4 | gles11::UNSIGNED_SHORT_5_5_5_1) => Ok(2),
+        (gles11::BGRA_EXT, UNSIGNED_SHORT_4_4_4_4_REV_IMG | UNSIGNED_SHORT_1_5_5_5_REV_IMG) => {
+            Ok(2)
+        }
+        (
+            gles11::RGBA
+            | gles11::BGRA_EXT
+            | gles11::RGB
+            | gles11::LUMINANCE_ALPHA
+            | gles11::ALPHA
+            | gles11::LUMINANCE,
+            gles11::UNSIGNED_SHORT_5_6_5
+            | gles11::UNSIGNED_SHORT_4_4_4_4
+            | gles11::UNSIGNED_SHORT_5_5_5_1
+            | UNSIGNED_SHORT_4_4_4_4_REV_IMG
+            | UNSIGNED_SHORT_1_5_5_5_REV_IMG,
+        ) => Err(gles11::INVALID_OPERATION),
+        _ => Err(gles11::INVALID_ENUM),
+    }
+}
+
+/// Convert an RGBA8 pixel to the format and type requested from
+/// `glReadPixels()`, which must be valid. Packed types are little-endian.
+fn convert_read_pixel(rgba: [u8; 4], format: GLenum, type_: GLenum, out: &mut [u8]) {
+    let [r, g, b, a] = rgba;
+    // Scale an 8-bit component to fewer bits, rounding to nearest.
+    let bits = |x: u8, bits: u32| -> u16 {
+        let max = (1u32 << bits) - 1;
+        ((u32::from(x) * max + 127) / 255) as u16
+    };
+    let packed = match (format, type_) {
+        (gles11::RGBA, gles11::UNSIGNED_BYTE) => return out.copy_from_slice(&rgba),
+        (gles11::BGRA_EXT, gles11::UNSIGNED_BYTE) => return out.copy_from_slice(&[b, g, r, a]),
+        (gles11::RGB, gles11::UNSIGNED_BYTE) => return out.copy_from_slice(&[r, g, b]),
+        (gles11::ALPHA, gles11::UNSIGNED_BYTE) => return out.copy_from_slice(&[a]),
+        (gles11::LUMINANCE | gles11::LUMINANCE_ALPHA, gles11::UNSIGNED_BYTE) => {
+            // The GL spec defines luminance as the clamped sum of the
+            // components, rather than a weighted average.
+            let l = (u16::from(r) + u16::from(g) + u16::from(b)).min(255) as u8;
+            if format == gles11::LUMINANCE {
+                return out.copy_from_slice(&[l]);
+            } else {
+                return out.copy_from_slice(&[l, a]);
+            }
+        }
+        (gles11::RGB, gles11::UNSIGNED_SHORT_5_6_5) => {
+            bits(r, 5) << 11 | bits(g, 6) << 5 | bits(b, 5)
+        }
+        (gles11::RGBA, gles11::UNSIGNED_SHORT_4_4_4_4) => {
+            bits(r, 4) << 12 | bits(g, 4) << 8 | bits(b, 4) << 4 | bits(a, 4)
+        }
+        (gles11::RGBA, gles11::UNSIGNED_SHORT_5_5_5_1) => {
+            bits(r, 5) << 11 | bits(g, 5) << 6 | bits(b, 5) << 1 | bits(a, 1)
+        }
+        // The reversed types put the first component (blue) in the lowest
+        // bits.
+        (gles11::BGRA_EXT, UNSIGNED_SHORT_4_4_4_4_REV_IMG) => {
+            bits(a, 4) << 12 | bits(r, 4) << 8 | bits(g, 4) << 4 | bits(b, 4)
+        }
+        (gles11::BGRA_EXT, UNSIGNED_SHORT_1_5_5_5_REV_IMG) => {
+            bits(a, 1) << 15 | bits(r, 5) << 10 | bits(g, 5) << 5 | bits(b, 5)
+        }
+        _ => unreachable!(),
+    };
+    out.copy_from_slice(&packed.to_le_bytes());
+}
+
+fn glReadPixels(
+    env: &mut Environment,
+    x: GLint,
+    y: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    format: GLenum,
+    type_: GLenum,
+    pixels: MutVoidPtr,
+) {
+    let pixel_size = match read_pixels_pixel_size(format, type_) {
+        Ok(pixel_size) => pixel_size,
+        Err(err) => {
+            log!(
+                "Warning: glReadPixels() with invalid format {:#x} and type {:#x}",
+                format,
+                type_
+            );
+            super::record_error(env, err);
+            return;
+        }
+    };
+    if width < 0 || height < 0 {
+        super::record_error(env, gles11::INVALID_VALUE);
+        return;
+    }
+    if width == 0 || height == 0 {
+        return;
+    }
+    let width_u: GuestUSize = width.try_into().unwrap();
+    let height_u: GuestUSize = height.try_into().unwrap();
+
+    let factor = env.options.scale_hack;
+    let force_msaa = env.options.force_msaa.is_some();
+    let mut buffer = std::mem::take(&mut env.framework_state.opengles.read_pixels_buffer);
+    with_ctx_and_mem(env, |gles, mem| unsafe {
+        with_forced_msaa_resolved(gles, force_msaa, |gles| {
+            let factor = scale_hack::framebuffer_factor(gles, factor);
+            let mut alignment = 0;
+            gles.GetIntegerv(gles11::PACK_ALIGNMENT, &mut alignment);
+            let alignment = alignment as GuestUSize;
+            let row_size = width_u * pixel_size;
+            let stride = row_size.div_ceil(alignment) * alignment;
+            // The last row isn't padded.
+            let size = stride * (height_u - 1) + row_size;
+
+            // Fast path: the host can write the app's format directly,
+            // respecting the app's alignment, with no extra copy.
+            if (format, type_) == (gles11::RGBA, gles11::UNSIGNED_BYTE) && factor == 1.0 {
+                let pixels = mem.ptr_at_mut(pixels.cast::<u8>(), size);
+                gles.ReadPixels(x, y, width, height, format, type_, pixels.cast());
+                return;
+            }
+
+            // Slow path: read tightly-packed RGBA8 into our own buffer, then
+            // convert each row.
+            gles.PixelStorei(gles11::PACK_ALIGNMENT, 4);
+            scale_hack::read_rgba(gles, factor, x, y, width, height, &mut buffer);
+            gles.PixelStorei(gles11::PACK_ALIGNMENT, alignment as GLint);
+
+            let out = mem.bytes_at_mut(pixels.cast(), size);
+            for (row_in, row_out) in buffer
+                .chunks_exact(width_u as usize * 4)
+                .zip(out.chunks_mut(stride as usize))
+            {
+                for (pixel_in, pixel_out) in row_in
+                    .chunks_exact(4)
+                    .zip(row_out.chunks_exact_mut(pixel_size as usize))
+                {
+                    let rgba = pixel_in.try_into().unwrap();
+                    convert_read_pixel(rgba, format, type_, pixel_out);
+                }
+            }
+        })
+    });
+    env.framework_state.opengles.read_pixels_buffer = buffer;
+}
 fn glGenTextures(env: &mut Environment, n: GLsizei, textures: MutPtr<GLuint>) {
     with_ctx_and_mem(env, |gles, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
@@ -691,8 +1047,10 @@ fn glGenTextures(env: &mut Environment, n: GLsizei, textures: MutPtr<GLuint>) {
     })
 }
 fn glDeleteTextures(env: &mut Environment, n: GLsizei, textures: ConstPtr<GLuint>) {
+    let n_usize: GuestUSize = n.try_into().unwrap();
+    let names: Vec<GLuint> = (0..n_usize).map(|i| env.mem.read(textures + i)).collect();
+    npot::forget_textures(env, &names);
     with_ctx_and_mem(env, |gles, mem| {
-        let n_usize: GuestUSize = n.try_into().unwrap();
         let textures = mem.ptr_at(textures, n_usize);
         unsafe { gles.DeleteTextures(n, textures) }
     })
@@ -713,7 +1071,8 @@ fn glTexParameteri(env: &mut Environment, target: GLenum, pname: GLenum, param:
     }
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.TexParameteri(target, pname, param)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
 }
 fn glTexParameterf(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfloat) {
     // See above.
@@ -722,7 +1081,8 @@ fn glTexParameterf(env: &mut Environment, target: GLenum, pname: GLenum, param:
     }
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.TexParameterf(target, pname, param)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
 }
 fn glTexParameterx(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfixed) {
     // See above.
@@ -731,7 +1091,8 @@ fn glTexParameterx(env: &mut Environment, target: GLenum, pname: GLenum, param:
     }
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.TexParameterx(target, pname, param)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
 }
 fn glTexParameteriv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLint>) {
     // See above.
@@ -741,7 +1102,8 @@ fn glTexParameteriv(env: &mut Environment, target: GLenum, pname: GLenum, params
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let params = mem.ptr_at(params, 1 /* upper bound */);
         gles.TexParameteriv(target, pname, params)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
 }
 fn glTexParameterfv(
     env: &mut Environment,
@@ -756,7 +1118,8 @@ fn glTexParameterfv(
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let params = mem.ptr_at(params, 1 /* upper bound */);
         gles.TexParameterfv(target, pname, params)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
 }
 fn glTexParameterxv(
     env: &mut Environment,
@@ -771,7 +1134,19 @@ fn glTexParameterxv(
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let params = mem.ptr_at(params, 1 /* upper bound */);
         gles.TexParameterxv(target, pname, params)
-    })
+    });
+    texture_filtering::after_tex_parameter(env, target, pname);
+}
+fn glGetTexParameteriv(
+    env: &mut Environment,
+    target: GLenum,
+    pname: GLenum,
+    params: MutPtr<GLint>,
+) {
+    with_ctx_and_mem(env, |gles, mem| {
+        let params = mem.ptr_at_mut(params, 1 /* upper bound */);
+        unsafe { gles.GetTexParameteriv(target, pname, params) };
+    });
 }
 fn image_size_estimate(pixel_count: GuestUSize, format: GLenum, type_: GLenum) -> GuestUSize {
     let bytes_per_pixel: GuestUSize = match type_ {
@@ -803,6 +1178,9 @@ fn glTexImage2D(
     type_: GLenum,
     pixels: ConstVoidPtr,
 ) {
+    if !npot::check_upload(env, target, level, width, height) {
+        return;
+    }
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let pixels = if pixels.is_null() {
             std::ptr::null()
@@ -822,7 +1200,8 @@ fn glTexImage2D(
             type_,
             pixels,
         )
-    })
+    });
+    texture_filtering::after_upload(env, target, level);
 }
 fn glTexSubImage2D(
     env: &mut Environment,
@@ -856,6 +1235,9 @@ fn glCompressedTexImage2D(
     image_size: GLsizei,
     data: ConstVoidPtr,
 ) {
+    if !npot::check_upload(env, target, level, width, height) {
+        return;
+    }
     with_ctx_and_mem(env, |gles, mem| unsafe {
         let data = mem
             .ptr_at(data.cast::<u8>(), image_size.try_into().unwrap())
@@ -870,7 +1252,8 @@ fn glCompressedTexImage2D(
             image_size,
             data,
         )
-    })
+    });
+    texture_filtering::after_upload(env, target, level);
 }
 fn glCopyTexImage2D(
     env: &mut Environment,
@@ -883,9 +1266,45 @@ fn glCopyTexImage2D(
     height: GLsizei,
     border: GLint,
 ) {
+    if !npot::check_upload(env, target, level, width, height) {
+        return;
+    }
+    let factor = env.options.scale_hack;
+    let force_msaa = env.options.force_msaa.is_some();
     with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.CopyTexImage2D(target, level, internalformat, x, y, width, height, border)
-    })
+        with_forced_msaa_resolved(gles, force_msaa, |gles| {
+            let factor = scale_hack::framebuffer_factor(gles, factor);
+            let pixels = if factor != 1.0 && width > 0 && height > 0 && border == 0 {
+                let mut rgba = Vec::new();
+                scale_hack::read_rgba(gles, factor, x, y, width, height, &mut rgba);
+                scale_hack::convert_rgba(&rgba, internalformat)
+            } else {
+                None
+            };
+            let Some(pixels) = pixels else {
+                gles.CopyTexImage2D(target, level, internalformat, x, y, width, height, border);
+                return;
+            };
+            // apply scale hack: the framebuffer is larger than the app thinks,
+            // so its pixels have been sampled down to the size the app expects
+            let mut alignment = 0;
+            gles.GetIntegerv(gles11::UNPACK_ALIGNMENT, &mut alignment);
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, 1);
+            gles.TexImage2D(
+                target,
+                level,
+                internalformat as GLint,
+                width,
+                height,
+                border,
+                internalformat,
+                gles11::UNSIGNED_BYTE,
+                pixels.as_ptr().cast(),
+            );
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, alignment);
+        })
+    });
+    texture_filtering::after_upload(env, target, level);
 }
 fn glCopyTexSubImage2D(
     env: &mut Environment,
@@ -898,9 +1317,47 @@ fn glCopyTexSubImage2D(
     width: GLsizei,
     height: GLsizei,
 ) {
+    let factor = env.options.scale_hack;
+    let force_msaa = env.options.force_msaa.is_some();
     with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height)
-    })
+        with_forced_msaa_resolved(gles, force_msaa, |gles| {
+            let factor = scale_hack::framebuffer_factor(gles, factor);
+            if factor == 1.0 || width <= 0 || height <= 0 {
+                gles.CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height);
+                return;
+            }
+            // apply scale hack: the framebuffer is larger than the app thinks,
+            // so its pixels are sampled down to the size the app expects.
+            // TODO: OpenGL ES requires the format to match the texture's, but
+            // the texture's format isn't tracked. Desktop OpenGL accepts RGBA
+            // for any texture.
+            let mut rgba = Vec::new();
+            scale_hack::read_rgba(gles, factor, x, y, width, height, &mut rgba);
+            let mut alignment = 0;
+            gles.GetIntegerv(gles11::UNPACK_ALIGNMENT, &mut alignment);
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, 4);
+            gles.TexSubImage2D(
+                target,
+                level,
+                xoffset,
+                yoffset,
+                width,
+                height,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                rgba.as_ptr().cast(),
+            );
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, alignment);
+        })
+    })
+}
+/// Number of values the `glTexEnv*v()` functions read for a target.
+fn tex_env_param_count(target: GLenum) -> GuestUSize {
+    match target {
+        gles11::TEXTURE_ENV => 4, // upper bound
+        gles11::POINT_SPRITE_OES => 1,
+        _ => unimplemented!("glTexEnv*v() target {:#x}", target),
+    }
 }
 fn glTexEnvf(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfloat) {
     with_ctx_and_mem(env, |gles, _mem| unsafe {
@@ -918,26 +1375,20 @@ fn glTexEnvi(env: &mut Environment, target: GLenum, pname: GLenum, param: GLint)
     })
 }
 fn glTexEnvfv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLfloat>) {
-    // TODO: GL_POINT_SPRITE_OES
-    assert!(target == gles11::TEXTURE_ENV);
     with_ctx_and_mem(env, |gles, mem| {
-        let params = mem.ptr_at(params, 4 /* upper bound */);
+        let params = mem.ptr_at(params, tex_env_param_count(target));
         unsafe { gles.TexEnvfv(target, pname, params) }
     })
 }
 fn glTexEnvxv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLfixed>) {
-    // TODO: GL_POINT_SPRITE_OES
-    assert!(target == gles11::TEXTURE_ENV);
     with_ctx_and_mem(env, |gles, mem| {
-        let params = mem.ptr_at(params, 4 /* upper bound */);
+        let params = mem.ptr_at(params, tex_env_param_count(target));
         unsafe { gles.TexEnvxv(target, pname, params) }
     })
 }
 fn glTexEnviv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLint>) {
-    // TODO: GL_POINT_SPRITE_OES
-    assert!(target == gles11::TEXTURE_ENV);
     with_ctx_and_mem(env, |gles, mem| {
-        let params = mem.ptr_at(params, 4 /* upper bound */);
+        let params = mem.ptr_at(params, tex_env_param_count(target));
         unsafe { gles.TexEnviv(target, pname, params) }
     })
 }
@@ -960,7 +1411,8 @@ fn glGenRenderbuffersOES(env: &mut Environment, n: GLsizei, renderbuffers: MutPt
 fn glBindFramebufferOES(env: &mut Environment, target: GLenum, framebuffer: GLuint) {
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.BindFramebufferOES(target, framebuffer)
-    })
+    });
+    scale_hack::after_framebuffer_change(env);
 }
 fn glBindRenderbufferOES(env: &mut Environment, target: GLenum, renderbuffer: GLuint) {
     with_ctx_and_mem(env, |gles, _mem| unsafe {
@@ -975,10 +1427,17 @@ fn glRenderbufferStorageOES(
     height: GLsizei,
 ) {
     // apply scale hack: give the app a larger framebuffer than it asked for
-    let factor = env.options.scale_hack.get() as GLsizei;
-    let (width, height) = (width * factor, height * factor);
+    let factor = env.options.scale_hack;
+    let (width, height) = (
+        scale_hack::scale_size(factor, width),
+        scale_hack::scale_size(factor, height),
+    );
+    let force_msaa = env.options.force_msaa.is_some();
     with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width, height)
+        gles.RenderbufferStorageOES(target, internalformat, width, height);
+        if force_msaa {
+            match_forced_msaa_attachments(gles);
+        }
     })
 }
 fn glFramebufferRenderbufferOES(
@@ -988,9 +1447,14 @@ fn glFramebufferRenderbufferOES(
     renderbuffertarget: GLenum,
     renderbuffer: GLuint,
 ) {
+    let force_msaa = env.options.force_msaa.is_some();
     with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.FramebufferRenderbufferOES(target, attachment, renderbuffertarget, renderbuffer)
-    })
+        gles.FramebufferRenderbufferOES(target, attachment, renderbuffertarget, renderbuffer);
+        if force_msaa {
+            match_forced_msaa_attachments(gles);
+        }
+    });
+    scale_hack::after_framebuffer_change(env);
 }
 fn glFramebufferTexture2DOES(
     env: &mut Environment,
@@ -1002,7 +1466,8 @@ fn glFramebufferTexture2DOES(
 ) {
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.FramebufferTexture2DOES(target, attachment, textarget, texture, level)
-    })
+    });
+    scale_hack::after_framebuffer_change(env);
 }
 fn glGetRenderbufferParameterivOES(
     env: &mut Environment,
@@ -1010,17 +1475,36 @@ fn glGetRenderbufferParameterivOES(
     pname: GLenum,
     params: MutPtr<GLint>,
 ) {
-    let factor = env.options.scale_hack.get() as GLint;
+    let factor = env.options.scale_hack;
+    let force_msaa = env.options.force_msaa.is_some();
     with_ctx_and_mem(env, |gles, mem| {
         let params = mem.ptr_at_mut(params, 1);
         unsafe { gles.GetRenderbufferParameterivOES(target, pname, params) };
+        // The app doesn't know about forced MSAA, and its renderbuffers are
+        // resolved before any reads, so pretend they aren't multisampled.
+        if force_msaa && pname == gles11::RENDERBUFFER_SAMPLES_APPLE {
+            unsafe { params.write_unaligned(0) }
+        }
         // apply scale hack: scale down the reported size of the framebuffer,
         // assuming the framebuffer's true size is larger than it should be
         if pname == gles11::RENDERBUFFER_WIDTH_OES || pname == gles11::RENDERBUFFER_HEIGHT_OES {
-            unsafe { params.write_unaligned(params.read_unaligned() / factor) }
+            let size = unsafe { params.read_unaligned() };
+            unsafe { params.write_unaligned(scale_hack::unscale_size(factor, size)) }
         }
     })
 }
+fn glGetFramebufferAttachmentParameterivOES(
+    env: &mut Environment,
+    target: GLenum,
+    attachment: GLenum,
+    pname: GLenum,
+    params: MutPtr<GLint>,
+) {
+    with_ctx_and_mem(env, |gles, mem| {
+        let params = mem.ptr_at_mut(params, 1);
+        unsafe { gles.GetFramebufferAttachmentParameterivOES(target, attachment, pname, params) }
+    })
+}
 fn glCheckFramebufferStatusOES(env: &mut Environment, target: GLenum) -> GLenum {
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.CheckFramebufferStatusOES(target)
@@ -1041,9 +1525,37 @@ fn glDeleteRenderbuffersOES(env: &mut Environment, n: GLsizei, renderbuffers: Co
     })
 }
 fn glGenerateMipmapOES(env: &mut Environment, target: GLenum) {
+    if !npot::check_generate_mipmap(env, target) {
+        return;
+    }
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.GenerateMipmapOES(target) })
 }
 
+// APPLE_framebuffer_multisample
+fn glRenderbufferStorageMultisampleAPPLE(
+    env: &mut Environment,
+    target: GLenum,
+    samples: GLsizei,
+    internalformat: GLenum,
+    width: GLsizei,
+    height: GLsizei,
+) {
+    // apply scale hack: give the app a larger framebuffer than it asked for
+    let factor = env.options.scale_hack;
+    let (width, height) = (
+        scale_hack::scale_size(factor, width),
+        scale_hack::scale_size(factor, height),
+    );
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.RenderbufferStorageMultisampleAPPLE(target, samples, internalformat, width, height)
+    })
+}
+fn glResolveMultisampleFramebufferAPPLE(env: &mut Environment) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.ResolveMultisampleFramebufferAPPLE()
+    })
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     // Generic state manipulation
     export_c_func!(glGetError()),
@@ -1117,6 +1629,7 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glNormalPointer(_, _, _)),
     export_c_func!(glTexCoordPointer(_, _, _, _)),
     export_c_func!(glVertexPointer(_, _, _, _)),
+    export_c_func!(glPointSizePointerOES(_, _, _)),
     // Drawing
     export_c_func!(glDrawArrays(_, _, _)),
     export_c_func!(glDrawElements(_, _, _, _)),
@@ -1148,6 +1661,7 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glTranslatex(_, _, _)),
     // Textures
     export_c_func!(glPixelStorei(_, _)),
+    export_c_func!(glReadPixels(_, _, _, _, _, _, _)),
     export_c_func!(glGenTextures(_, _)),
     export_c_func!(glDeleteTextures(_, _)),
     export_c_func!(glActiveTexture(_)),
@@ -1158,6 +1672,7 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glTexParameteriv(_, _, _)),
     export_c_func!(glTexParameterfv(_, _, _)),
     export_c_func!(glTexParameterxv(_, _, _)),
+    export_c_func!(glGetTexParameteriv(_, _, _)),
     export_c_func!(glTexImage2D(_, _, _, _, _, _, _, _, _)),
     export_c_func!(glTexSubImage2D(_, _, _, _, _, _, _, _, _)),
     export_c_func!(glCompressedTexImage2D(_, _, _, _, _, _, _, _)),
@@ -1178,8 +1693,12 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glFramebufferRenderbufferOES(_, _, _, _)),
     export_c_func!(glFramebufferTexture2DOES(_, _, _, _, _)),
     export_c_func!(glGetRenderbufferParameterivOES(_, _, _)),
+    export_c_func!(glGetFramebufferAttachmentParameterivOES(_, _, _, _)),
     export_c_func!(glCheckFramebufferStatusOES(_)),
     export_c_func!(glDeleteFramebuffersOES(_, _)),
     export_c_func!(glDeleteRenderbuffersOES(_, _)),
     export_c_func!(glGenerateMipmapOES(_)),
+    // APPLE_framebuffer_multisample
+    export_c_func!(glRenderbufferStorageMultisampleAPPLE(_, _, _, _, _)),
+    export_c_func!(glResolveMultisampleFramebufferAPPLE()),
 ];
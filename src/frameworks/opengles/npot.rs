@@ -0,0 +1,226 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Emulation of the iPhone's restrictions on non-power-of-two (NPOT) textures,
+//! see the `--enforce-npot-restrictions` option.
+//!
+//! The host's OpenGL (ES) has no restrictions on NPOT textures, but the
+//! PowerVR MBX Lite doesn't support them at all, and the PowerVR SGX 535 only
+//! supports them as described by `GL_APPLE_texture_2D_limited_npot`: an NPOT
+//! texture can't have mipmaps and must use `GL_CLAMP_TO_EDGE` wrapping,
+//! otherwise it is incomplete, and texturing is disabled for any unit it is
+//! bound to.
+//!
+//! Uploads are checked as they happen, so the only state that needs to be kept
+//! is which textures have an NPOT image. Completeness is checked at draw time,
+//! because the app can change the texture parameters at any point.
+
+use super::eagl::EAGLContextHostObject;
+use crate::gles::gles11_raw as gles11; // constants only
+use crate::gles::gles11_raw::types::{GLenum, GLint, GLsizei, GLuint};
+use crate::options::Gpu;
+use crate::Environment;
+use std::collections::HashSet;
+
+#[derive(Default)]
+pub(super) struct NpotTextures {
+    /// Names of textures whose level 0 image has NPOT dimensions.
+    textures: HashSet<GLuint>,
+    /// Textures that a warning about incompleteness has been logged for.
+    warned: HashSet<GLuint>,
+}
+
+fn is_npot(size: GLsizei) -> bool {
+    size > 0 && !(size as u32).is_power_of_two()
+}
+
+fn host_object(env: &mut Environment) -> &mut EAGLContextHostObject {
+    let ctx = env
+        .framework_state
+        .opengles
+        .current_ctx_for_thread(env.current_thread)
+        .unwrap();
+    env.objc.borrow_mut::<EAGLContextHostObject>(ctx)
+}
+
+fn bound_texture(env: &mut Environment) -> GLuint {
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    let mut texture = 0;
+    unsafe { gles.GetIntegerv(gles11::TEXTURE_BINDING_2D, &mut texture) };
+    texture as GLuint
+}
+
+/// Called before the app specifies a texture image (`glTexImage2D()` etc).
+/// Returns [false] if the upload must not happen, in which case an error has
+/// been recorded for the app.
+pub(super) fn check_upload(
+    env: &mut Environment,
+    target: GLenum,
+    level: GLint,
+    width: GLsizei,
+    height: GLsizei,
+) -> bool {
+    if !env.options.enforce_npot_restrictions || target != gles11::TEXTURE_2D {
+        return true;
+    }
+
+    let npot = is_npot(width) || is_npot(height);
+    let gpu = env.options.device_model.gpu;
+    if npot && (gpu == Gpu::PowerVRMBXLite || level != 0) {
+        log!(
+            "Warning: rejecting {}x{} texture image for level {}, the {:?} doesn't support it",
+            width,
+            height,
+            level,
+            gpu,
+        );
+        super::record_error(env, gles11::INVALID_VALUE);
+        return false;
+    }
+    if level != 0 {
+        return true;
+    }
+
+    let texture = bound_texture(env);
+    let state = &mut host_object(env).npot_textures;
+    if npot {
+        state.textures.insert(texture);
+    } else {
+        state.textures.remove(&texture);
+        state.warned.remove(&texture);
+    }
+    true
+}
+
+/// Called before `glGenerateMipmapOES()`. Returns [false] if it must not
+/// happen, in which case an error has been recorded for the app.
+pub(super) fn check_generate_mipmap(env: &mut Environment, target: GLenum) -> bool {
+    if !env.options.enforce_npot_restrictions || target != gles11::TEXTURE_2D {
+        return true;
+    }
+    let texture = bound_texture(env);
+    if !host_object(env).npot_textures.textures.contains(&texture) {
+        return true;
+    }
+    log!(
+        "Warning: can't generate mipmaps for NPOT texture {}",
+        texture
+    );
+    super::record_error(env, gles11::INVALID_OPERATION);
+    false
+}
+
+/// Called when the app deletes textures.
+pub(super) fn forget_textures(env: &mut Environment, textures: &[GLuint]) {
+    let state = &mut host_object(env).npot_textures;
+    for texture in textures {
+        state.textures.remove(texture);
+        state.warned.remove(texture);
+    }
+}
+
+/// Called before a draw call. Disables texturing on each texture unit that has
+/// an incomplete NPOT texture bound. The result must be passed to
+/// [restore_units] after the draw call.
+pub(super) fn disable_incomplete_units(env: &mut Environment) -> Vec<GLenum> {
+    if !env.options.enforce_npot_restrictions {
+        return Vec::new();
+    }
+    // Make sure the context is current.
+    let _ = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    let host_object = host_object(env);
+    let state = &mut host_object.npot_textures;
+    if state.textures.is_empty() {
+        return Vec::new();
+    }
+    let gles = host_object.gles_ctx.as_deref_mut().unwrap();
+
+    let mut disabled_units = Vec::new();
+    unsafe {
+        let mut unit_count = 0;
+        gles.GetIntegerv(gles11::MAX_TEXTURE_UNITS, &mut unit_count);
+        let mut active_texture = 0;
+        gles.GetIntegerv(gles11::ACTIVE_TEXTURE, &mut active_texture);
+
+        for i in 0..(unit_count as GLenum) {
+            let unit = gles11::TEXTURE0 + i;
+            gles.ActiveTexture(unit);
+            if gles.IsEnabled(gles11::TEXTURE_2D) == 0 {
+                continue;
+            }
+            let mut texture = 0;
+            gles.GetIntegerv(gles11::TEXTURE_BINDING_2D, &mut texture);
+            let texture = texture as GLuint;
+            if !state.textures.contains(&texture) {
+                continue;
+            }
+
+            let mut wrap_s = 0;
+            let mut wrap_t = 0;
+            let mut min_filter = 0;
+            gles.GetTexParameteriv(gles11::TEXTURE_2D, gles11::TEXTURE_WRAP_S, &mut wrap_s);
+            gles.GetTexParameteriv(gles11::TEXTURE_2D, gles11::TEXTURE_WRAP_T, &mut wrap_t);
+            gles.GetTexParameteriv(
+                gles11::TEXTURE_2D,
+                gles11::TEXTURE_MIN_FILTER,
+                &mut min_filter,
+            );
+            let complete = wrap_s as GLenum == gles11::CLAMP_TO_EDGE
+                && wrap_t as GLenum == gles11::CLAMP_TO_EDGE
+                && (min_filter as GLenum == gles11::NEAREST
+                    || min_filter as GLenum == gles11::LINEAR);
+            if complete {
+                continue;
+            }
+
+            if state.warned.insert(texture) {
+                log!(
+                    "Warning: NPOT texture {} is incomplete (wrap S {:#x}, wrap T {:#x}, min filter {:#x}), drawing without it",
+                    texture,
+                    wrap_s,
+                    wrap_t,
+                    min_filter,
+                );
+            }
+            gles.Disable(gles11::TEXTURE_2D);
+            disabled_units.push(unit);
+        }
+
+        gles.ActiveTexture(active_texture as GLenum);
+    }
+    disabled_units
+}
+
+/// Undoes [disable_incomplete_units] after a draw call.
+pub(super) fn restore_units(env: &mut Environment, disabled_units: Vec<GLenum>) {
+    if disabled_units.is_empty() {
+        return;
+    }
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    unsafe {
+        let mut active_texture = 0;
+        gles.GetIntegerv(gles11::ACTIVE_TEXTURE, &mut active_texture);
+        for unit in disabled_units {
+            gles.ActiveTexture(unit);
+            gles.Enable(gles11::TEXTURE_2D);
+        }
+        gles.ActiveTexture(active_texture as GLenum);
+    }
+}
@@ -0,0 +1,261 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Rendering at an increased internal resolution, see the `--scale-hack=`
+//! option.
+//!
+//! Renderbuffers are given storage larger than the app asked for, but the
+//! sizes the app sees are scaled back down, so the app shouldn't notice.
+//!
+//! Viewport and scissor rectangles have to be scaled up to match, but only
+//! while a renderbuffer is the color attachment of the bound framebuffer.
+//! Textures keep the size the app gave them, so render-to-texture isn't
+//! scaled. The app's own rectangles are kept so they can be re-applied when
+//! the framebuffer binding or its attachments change, and so they can be
+//! returned to the app unchanged by `glGetIntegerv()` etc.
+//!
+//! Pixels read from a scaled framebuffer (`glReadPixels()`,
+//! `glCopyTexImage2D()` etc) are sampled down to the size the app expects.
+
+use super::eagl::EAGLContextHostObject;
+use crate::gles::gles11_raw as gles11; // constants only
+use crate::gles::gles11_raw::types::{GLenum, GLint, GLsizei};
+use crate::gles::GLES;
+use crate::Environment;
+
+#[derive(Default)]
+pub(super) struct ScaleHackState {
+    /// The rectangle last passed to `glViewport()`, in the app's coordinates.
+    viewport: Option<[GLint; 4]>,
+    /// The rectangle last passed to `glScissor()`, in the app's coordinates.
+    scissor_box: Option<[GLint; 4]>,
+}
+
+fn host_object(env: &mut Environment) -> &mut EAGLContextHostObject {
+    let ctx = env
+        .framework_state
+        .opengles
+        .current_ctx_for_thread(env.current_thread)
+        .unwrap();
+    env.objc.borrow_mut::<EAGLContextHostObject>(ctx)
+}
+
+/// Scale up a renderbuffer size requested by the app.
+pub(super) fn scale_size(factor: f32, size: GLsizei) -> GLsizei {
+    (size as f32 * factor).round() as GLsizei
+}
+
+/// Scale down a renderbuffer size before reporting it to the app. This is the
+/// inverse of [scale_size].
+pub(super) fn unscale_size(factor: f32, size: GLint) -> GLint {
+    (size as f32 / factor).round() as GLint
+}
+
+/// Scale up a rectangle (x, y, width, height). The edges are rounded rather
+/// than the size, so that adjacent rectangles stay adjacent.
+fn scale_rect([x, y, width, height]: [GLint; 4], factor: f32) -> [GLint; 4] {
+    let scale = |v: GLint| (v as f32 * factor).round() as GLint;
+    let (x1, y1) = (scale(x), scale(y));
+    let (x2, y2) = (scale(x + width), scale(y + height));
+    [x1, y1, x2 - x1, y2 - y1]
+}
+
+/// Get the factor by which the bound framebuffer is larger than the app
+/// thinks it is.
+///
+/// # Safety
+/// The context must be current.
+pub(super) unsafe fn framebuffer_factor(gles: &mut dyn GLES, factor: f32) -> f32 {
+    if factor == 1.0 {
+        return 1.0;
+    }
+    let mut framebuffer = 0;
+    gles.GetIntegerv(gles11::FRAMEBUFFER_BINDING_OES, &mut framebuffer);
+    if framebuffer == 0 {
+        // The window's framebuffer is scaled too.
+        return factor;
+    }
+    let mut object_type = 0;
+    gles.GetFramebufferAttachmentParameterivOES(
+        gles11::FRAMEBUFFER_OES,
+        gles11::COLOR_ATTACHMENT0_OES,
+        gles11::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE_OES,
+        &mut object_type,
+    );
+    if object_type as GLenum == gles11::TEXTURE {
+        1.0
+    } else {
+        factor
+    }
+}
+
+/// Apply the app's viewport and scissor rectangles, scaled appropriately for
+/// the bound framebuffer.
+fn apply_rects(env: &mut Environment) {
+    let factor = env.options.scale_hack;
+    let ScaleHackState {
+        viewport,
+        scissor_box,
+    } = host_object(env).scale_hack;
+    if viewport.is_none() && scissor_box.is_none() {
+        return;
+    }
+
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    unsafe {
+        let factor = framebuffer_factor(gles, factor);
+        if let Some(viewport) = viewport {
+            let [x, y, width, height] = scale_rect(viewport, factor);
+            gles.Viewport(x, y, width, height);
+        }
+        if let Some(scissor_box) = scissor_box {
+            let [x, y, width, height] = scale_rect(scissor_box, factor);
+            gles.Scissor(x, y, width, height);
+        }
+    }
+}
+
+/// Called for `glViewport()`. Returns [false] if the scale hack isn't in use
+/// and the call should be passed through unchanged.
+pub(super) fn set_viewport(env: &mut Environment, rect: [GLint; 4]) -> bool {
+    if env.options.scale_hack == 1.0 {
+        return false;
+    }
+    host_object(env).scale_hack.viewport = Some(rect);
+    apply_rects(env);
+    true
+}
+
+/// Called for `glScissor()`. Returns [false] if the scale hack isn't in use
+/// and the call should be passed through unchanged.
+pub(super) fn set_scissor_box(env: &mut Environment, rect: [GLint; 4]) -> bool {
+    if env.options.scale_hack == 1.0 {
+        return false;
+    }
+    host_object(env).scale_hack.scissor_box = Some(rect);
+    apply_rects(env);
+    true
+}
+
+/// Called after the app changes the framebuffer binding or the attachments of
+/// a framebuffer.
+pub(super) fn after_framebuffer_change(env: &mut Environment) {
+    if env.options.scale_hack != 1.0 {
+        apply_rects(env);
+    }
+}
+
+/// Called for `glGetIntegerv()` etc. Returns the rectangle the app should see
+/// for `GL_VIEWPORT` and `GL_SCISSOR_BOX`, if the scale hack is in use.
+pub(super) fn get_rect(env: &mut Environment, pname: GLenum) -> Option<[GLint; 4]> {
+    let factor = env.options.scale_hack;
+    if factor == 1.0 {
+        return None;
+    }
+    let state = &host_object(env).scale_hack;
+    let rect = match pname {
+        gles11::VIEWPORT => state.viewport,
+        gles11::SCISSOR_BOX => state.scissor_box,
+        _ => return None,
+    };
+    if rect.is_some() {
+        return rect;
+    }
+
+    // The app hasn't set this rectangle yet, so the initial value set by the
+    // driver must be scaled down.
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    let mut rect = [0; 4];
+    unsafe { gles.GetIntegerv(pname, rect.as_mut_ptr()) };
+    Some(rect.map(|v| unscale_size(factor, v)))
+}
+
+/// Read a rectangle of the bound framebuffer, given in the app's coordinates,
+/// as tightly-packed `GL_RGBA`/`GL_UNSIGNED_BYTE` pixels, sampling it down to
+/// the size the app expects if the framebuffer is scaled. `factor` should come
+/// from [framebuffer_factor].
+///
+/// # Safety
+/// The context must be current, and `GL_PACK_ALIGNMENT` must be 4 or less.
+pub(super) unsafe fn read_rgba(
+    gles: &mut dyn GLES,
+    factor: f32,
+    x: GLint,
+    y: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    out: &mut Vec<u8>,
+) {
+    let (width_u, height_u) = (width as usize, height as usize);
+    out.clear();
+    out.resize(width_u * height_u * 4, 0);
+    if factor == 1.0 {
+        gles.ReadPixels(
+            x,
+            y,
+            width,
+            height,
+            gles11::RGBA,
+            gles11::UNSIGNED_BYTE,
+            out.as_mut_ptr().cast(),
+        );
+        return;
+    }
+
+    let [x, y, scaled_width, scaled_height] = scale_rect([x, y, width, height], factor);
+    let (scaled_width_u, scaled_height_u) = (scaled_width as usize, scaled_height as usize);
+    let mut scaled = vec![0u8; scaled_width_u * scaled_height_u * 4];
+    gles.ReadPixels(
+        x,
+        y,
+        scaled_width,
+        scaled_height,
+        gles11::RGBA,
+        gles11::UNSIGNED_BYTE,
+        scaled.as_mut_ptr().cast(),
+    );
+
+    // Nearest-neighbor sampling from the center of each pixel.
+    let sample = |i: usize, size: usize, scaled_size: usize| {
+        (((i as f32 + 0.5) * scaled_size as f32 / size as f32) as usize).min(scaled_size - 1)
+    };
+    for (row, row_out) in out.chunks_exact_mut(width_u * 4).enumerate() {
+        let row_in = sample(row, height_u, scaled_height_u);
+        let row_in = &scaled[row_in * scaled_width_u * 4..][..scaled_width_u * 4];
+        for (col, pixel_out) in row_out.chunks_exact_mut(4).enumerate() {
+            let col_in = sample(col, width_u, scaled_width_u);
+            pixel_out.copy_from_slice(&row_in[col_in * 4..][..4]);
+        }
+    }
+}
+
+/// Convert tightly-packed `GL_RGBA`/`GL_UNSIGNED_BYTE` pixels from
+/// [read_rgba] to one of the formats `glCopyTexImage2D()` accepts. Returns
+/// [None] if the format isn't one of those.
+pub(super) fn convert_rgba(rgba: &[u8], format: GLenum) -> Option<Vec<u8>> {
+    let channels: &[usize] = match format {
+        gles11::RGBA => return Some(rgba.to_vec()),
+        gles11::RGB => &[0, 1, 2],
+        gles11::LUMINANCE_ALPHA => &[0, 3],
+        gles11::LUMINANCE => &[0],
+        gles11::ALPHA => &[3],
+        _ => return None,
+    };
+    Some(
+        rgba.chunks_exact(4)
+            .flat_map(|pixel| channels.iter().map(|&c| pixel[c]))
+            .collect(),
+    )
+}
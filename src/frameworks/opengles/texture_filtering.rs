@@ -0,0 +1,98 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Texture filtering enhancements, see the `--force-anisotropic-filtering=`
+//! and `--force-trilinear-filtering` options.
+//!
+//! Filtering state belongs to each texture, so the overrides are applied to
+//! the bound texture whenever the app specifies its level 0 image, and again
+//! whenever the app sets one of the parameters involved, so that the app
+//! can't undo them.
+//!
+//! Trilinear filtering is only forced for textures the app already uses a
+//! mipmap filter with. Switching a texture that might not have mipmaps to a
+//! mipmap filter would make it incomplete, and it would not be drawn.
+
+use crate::gles::gles11_raw as gles11; // constants only
+use crate::gles::gles11_raw::types::{GLenum, GLfloat, GLint};
+use crate::gles::GLES;
+use crate::Environment;
+use std::ffi::CStr;
+
+/// Called after the app specifies a texture image (`glTexImage2D()` etc).
+pub(super) fn after_upload(env: &mut Environment, target: GLenum, level: GLint) {
+    if level == 0 {
+        apply_overrides(env, target);
+    }
+}
+
+/// Called after the app sets a texture parameter (`glTexParameteri()` etc).
+pub(super) fn after_tex_parameter(env: &mut Environment, target: GLenum, pname: GLenum) {
+    if pname == gles11::TEXTURE_MIN_FILTER || pname == gles11::TEXTURE_MAX_ANISOTROPY_EXT {
+        apply_overrides(env, target);
+    }
+}
+
+fn apply_overrides(env: &mut Environment, target: GLenum) {
+    let anisotropy = env.options.force_anisotropic_filtering;
+    let trilinear = env.options.force_trilinear_filtering;
+    if (anisotropy.is_none() && !trilinear) || target != gles11::TEXTURE_2D {
+        return;
+    }
+
+    let gles = super::sync_context(
+        &mut env.framework_state.opengles,
+        &mut env.objc,
+        env.window.as_mut().unwrap(),
+        env.current_thread,
+    );
+    unsafe {
+        if trilinear {
+            let mut min_filter = 0;
+            gles.GetTexParameteriv(target, gles11::TEXTURE_MIN_FILTER, &mut min_filter);
+            if matches!(
+                min_filter as GLenum,
+                gles11::NEAREST_MIPMAP_NEAREST
+                    | gles11::LINEAR_MIPMAP_NEAREST
+                    | gles11::NEAREST_MIPMAP_LINEAR
+            ) {
+                gles.TexParameteri(
+                    target,
+                    gles11::TEXTURE_MIN_FILTER,
+                    gles11::LINEAR_MIPMAP_LINEAR as _,
+                );
+            }
+        }
+        // Querying the limit without the extension would raise an error the
+        // app could see with glGetError().
+        if let Some(anisotropy) = anisotropy.filter(|_| host_has_anisotropic_filtering(gles)) {
+            let mut max_anisotropy: GLfloat = 0.0;
+            gles.GetFloatv(gles11::MAX_TEXTURE_MAX_ANISOTROPY_EXT, &mut max_anisotropy);
+            if max_anisotropy < 1.0 {
+                return;
+            }
+            let anisotropy = (anisotropy.get() as GLfloat).min(max_anisotropy);
+            gles.TexParameterf(target, gles11::TEXTURE_MAX_ANISOTROPY_EXT, anisotropy);
+        }
+    }
+}
+
+/// Whether the host supports anisotropic filtering. The ARB extension uses the
+/// same constants as the EXT one.
+///
+/// The provided context must be current.
+unsafe fn host_has_anisotropic_filtering(gles: &mut dyn GLES) -> bool {
+    let extensions = gles.GetString(gles11::EXTENSIONS);
+    if extensions.is_null() {
+        return false;
+    }
+    CStr::from_ptr(extensions.cast())
+        .to_bytes()
+        .split(|&c| c == b' ')
+        .any(|name| {
+            name == b"GL_EXT_texture_filter_anisotropic"
+                || name == b"GL_ARB_texture_filter_anisotropic"
+        })
+}
@@ -0,0 +1,16 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! The Security framework.
+//!
+//! Only random number generation and the keychain item API are implemented.
+
+pub mod sec_item;
+pub mod sec_random;
+
+#[derive(Default)]
+pub struct State {
+    sec_item: sec_item::State,
+}
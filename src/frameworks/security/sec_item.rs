@@ -0,0 +1,517 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `SecItem.h`, the keychain item API.
+//!
+//! On iPhone OS, the keychain is a database shared by all apps, though each app
+//! can only see its own items. Here, each app's items are kept in a property
+//! list file in the app's sandbox directory on the host, next to `Documents`,
+//! so they persist across runs but aren't visible in the app's own files.
+//!
+//! The dictionaries the app passes in are converted to property list values,
+//! and an item is stored as a dictionary of its attributes, keyed by the
+//! strings behind the `kSec...` constants (e.g. `acct` for `kSecAttrAccount`),
+//! with its data under `v_Data` (`kSecValueData`).
+//!
+//! Only generic and internet passwords are supported. Certificates, keys and
+//! identities can't be created without the rest of the Security framework.
+
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::frameworks::carbon_core::OSStatus;
+use crate::frameworks::core_foundation::cf_dictionary::CFDictionaryRef;
+use crate::frameworks::core_foundation::CFTypeRef;
+use crate::frameworks::foundation::ns_property_list_serialization::{
+    deserialize_plist, serialize_plist,
+};
+use crate::mem::MutPtr;
+use crate::objc::nil;
+use crate::paths;
+use crate::Environment;
+use plist::{Dictionary, Value};
+use std::path::PathBuf;
+
+const errSecSuccess: OSStatus = 0;
+const errSecUnimplemented: OSStatus = -4;
+const errSecParam: OSStatus = -50;
+const errSecDuplicateItem: OSStatus = -25299;
+const errSecItemNotFound: OSStatus = -25300;
+
+const kSecClass: &str = "class";
+const kSecClassGenericPassword: &str = "genp";
+const kSecClassInternetPassword: &str = "inet";
+const kSecClassCertificate: &str = "cert";
+const kSecClassKey: &str = "keys";
+const kSecClassIdentity: &str = "idnt";
+
+const kSecAttrAccessible: &str = "pdmn";
+const kSecAttrAccessibleWhenUnlocked: &str = "ak";
+const kSecAttrAccessibleAfterFirstUnlock: &str = "ck";
+const kSecAttrAccessibleAlways: &str = "dk";
+const kSecAttrAccessibleWhenUnlockedThisDeviceOnly: &str = "aku";
+const kSecAttrAccessibleAfterFirstUnlockThisDeviceOnly: &str = "cku";
+const kSecAttrAccessibleAlwaysThisDeviceOnly: &str = "dku";
+const kSecAttrAccessGroup: &str = "agrp";
+const kSecAttrCreationDate: &str = "cdat";
+const kSecAttrModificationDate: &str = "mdat";
+const kSecAttrDescription: &str = "desc";
+const kSecAttrComment: &str = "icmt";
+const kSecAttrCreator: &str = "crtr";
+const kSecAttrType: &str = "type";
+const kSecAttrLabel: &str = "labl";
+const kSecAttrIsInvisible: &str = "invi";
+const kSecAttrIsNegative: &str = "nega";
+const kSecAttrAccount: &str = "acct";
+const kSecAttrService: &str = "svce";
+const kSecAttrGeneric: &str = "gena";
+const kSecAttrSecurityDomain: &str = "sdmn";
+const kSecAttrServer: &str = "srvr";
+const kSecAttrProtocol: &str = "ptcl";
+const kSecAttrAuthenticationType: &str = "atyp";
+const kSecAttrPort: &str = "port";
+const kSecAttrPath: &str = "path";
+
+const kSecMatchLimit: &str = "m_Limit";
+const kSecMatchLimitOne: &str = "m_LimitOne";
+const kSecMatchLimitAll: &str = "m_LimitAll";
+
+const kSecReturnData: &str = "r_Data";
+const kSecReturnAttributes: &str = "r_Attributes";
+const kSecReturnRef: &str = "r_Ref";
+const kSecReturnPersistentRef: &str = "r_PersistentRef";
+
+const kSecValueData: &str = "v_Data";
+const kSecValueRef: &str = "v_Ref";
+const kSecValuePersistentRef: &str = "v_PersistentRef";
+
+pub const CONSTANTS: ConstantExports = &[
+    ("_kSecClass", HostConstant::NSString(kSecClass)),
+    (
+        "_kSecClassGenericPassword",
+        HostConstant::NSString(kSecClassGenericPassword),
+    ),
+    (
+        "_kSecClassInternetPassword",
+        HostConstant::NSString(kSecClassInternetPassword),
+    ),
+    (
+        "_kSecClassCertificate",
+        HostConstant::NSString(kSecClassCertificate),
+    ),
+    ("_kSecClassKey", HostConstant::NSString(kSecClassKey)),
+    (
+        "_kSecClassIdentity",
+        HostConstant::NSString(kSecClassIdentity),
+    ),
+    (
+        "_kSecAttrAccessible",
+        HostConstant::NSString(kSecAttrAccessible),
+    ),
+    (
+        "_kSecAttrAccessibleWhenUnlocked",
+        HostConstant::NSString(kSecAttrAccessibleWhenUnlocked),
+    ),
+    (
+        "_kSecAttrAccessibleAfterFirstUnlock",
+        HostConstant::NSString(kSecAttrAccessibleAfterFirstUnlock),
+    ),
+    (
+        "_kSecAttrAccessibleAlways",
+        HostConstant::NSString(kSecAttrAccessibleAlways),
+    ),
+    (
+        "_kSecAttrAccessibleWhenUnlockedThisDeviceOnly",
+        HostConstant::NSString(kSecAttrAccessibleWhenUnlockedThisDeviceOnly),
+    ),
+    (
+        "_kSecAttrAccessibleAfterFirstUnlockThisDeviceOnly",
+        HostConstant::NSString(kSecAttrAccessibleAfterFirstUnlockThisDeviceOnly),
+    ),
+    (
+        "_kSecAttrAccessibleAlwaysThisDeviceOnly",
+        HostConstant::NSString(kSecAttrAccessibleAlwaysThisDeviceOnly),
+    ),
+    (
+        "_kSecAttrAccessGroup",
+        HostConstant::NSString(kSecAttrAccessGroup),
+    ),
+    (
+        "_kSecAttrCreationDate",
+        HostConstant::NSString(kSecAttrCreationDate),
+    ),
+    (
+        "_kSecAttrModificationDate",
+        HostConstant::NSString(kSecAttrModificationDate),
+    ),
+    (
+        "_kSecAttrDescription",
+        HostConstant::NSString(kSecAttrDescription),
+    ),
+    ("_kSecAttrComment", HostConstant::NSString(kSecAttrComment)),
+    ("_kSecAttrCreator", HostConstant::NSString(kSecAttrCreator)),
+    ("_kSecAttrType", HostConstant::NSString(kSecAttrType)),
+    ("_kSecAttrLabel", HostConstant::NSString(kSecAttrLabel)),
+    (
+        "_kSecAttrIsInvisible",
+        HostConstant::NSString(kSecAttrIsInvisible),
+    ),
+    (
+        "_kSecAttrIsNegative",
+        HostConstant::NSString(kSecAttrIsNegative),
+    ),
+    ("_kSecAttrAccount", HostConstant::NSString(kSecAttrAccount)),
+    ("_kSecAttrService", HostConstant::NSString(kSecAttrService)),
+    ("_kSecAttrGeneric", HostConstant::NSString(kSecAttrGeneric)),
+    (
+        "_kSecAttrSecurityDomain",
+        HostConstant::NSString(kSecAttrSecurityDomain),
+    ),
+    ("_kSecAttrServer", HostConstant::NSString(kSecAttrServer)),
+    (
+        "_kSecAttrProtocol",
+        HostConstant::NSString(kSecAttrProtocol),
+    ),
+    (
+        "_kSecAttrAuthenticationType",
+        HostConstant::NSString(kSecAttrAuthenticationType),
+    ),
+    ("_kSecAttrPort", HostConstant::NSString(kSecAttrPort)),
+    ("_kSecAttrPath", HostConstant::NSString(kSecAttrPath)),
+    ("_kSecMatchLimit", HostConstant::NSString(kSecMatchLimit)),
+    (
+        "_kSecMatchLimitOne",
+        HostConstant::NSString(kSecMatchLimitOne),
+    ),
+    (
+        "_kSecMatchLimitAll",
+        HostConstant::NSString(kSecMatchLimitAll),
+    ),
+    ("_kSecReturnData", HostConstant::NSString(kSecReturnData)),
+    (
+        "_kSecReturnAttributes",
+        HostConstant::NSString(kSecReturnAttributes),
+    ),
+    ("_kSecReturnRef", HostConstant::NSString(kSecReturnRef)),
+    (
+        "_kSecReturnPersistentRef",
+        HostConstant::NSString(kSecReturnPersistentRef),
+    ),
+    ("_kSecValueData", HostConstant::NSString(kSecValueData)),
+    ("_kSecValueRef", HostConstant::NSString(kSecValueRef)),
+    (
+        "_kSecValuePersistentRef",
+        HostConstant::NSString(kSecValuePersistentRef),
+    ),
+];
+
+#[derive(Default)]
+pub struct State {
+    /// The app's keychain items, loaded from disk on first use.
+    items: Option<Vec<Dictionary>>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.security.sec_item
+    }
+}
+
+fn keychain_path(env: &Environment) -> PathBuf {
+    paths::user_data_base_path()
+        .join(paths::SANDBOX_DIR)
+        .join(env.bundle.bundle_identifier())
+        .join("keychain.plist")
+}
+
+fn items(env: &mut Environment) -> &mut Vec<Dictionary> {
+    if State::get(env).items.is_none() {
+        let path = keychain_path(env);
+        let items = if path.exists() {
+            match Value::from_file(&path) {
+                Ok(Value::Array(items)) => items
+                    .into_iter()
+                    .filter_map(Value::into_dictionary)
+                    .collect(),
+                _ => {
+                    log!("Warning: couldn't read keychain from {:?}", path);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        log_dbg!("Loaded {} keychain items from {:?}", items.len(), path);
+        State::get(env).items = Some(items);
+    }
+    State::get(env).items.as_mut().unwrap()
+}
+
+fn save_items(env: &mut Environment) {
+    if env.fs.is_read_only_mode() {
+        return;
+    }
+    let path = keychain_path(env);
+    let items = items(env).iter().cloned().map(Value::Dictionary).collect();
+    if let Err(e) = Value::Array(items).to_file_xml(&path) {
+        log!("Warning: couldn't write keychain to {:?}: {}", path, e);
+    }
+}
+
+/// A dictionary passed to one of the `SecItem` functions, split into the item
+/// attributes and the flags controlling what is returned.
+struct Query {
+    /// Attributes, including the class and possibly `kSecValueData`.
+    attributes: Dictionary,
+    return_data: bool,
+    return_attributes: bool,
+    match_all: bool,
+}
+
+fn is_true(value: &Value) -> bool {
+    match value {
+        Value::Boolean(value) => *value,
+        Value::Integer(value) => value.as_signed() != Some(0),
+        _ => panic!("Expected boolean, got {:?}", value),
+    }
+}
+
+fn parse_query(env: &mut Environment, dictionary: CFDictionaryRef) -> Result<Query, OSStatus> {
+    if dictionary == nil {
+        return Err(errSecParam);
+    }
+    let Some(Value::Dictionary(dictionary)) = serialize_plist(env, dictionary) else {
+        return Err(errSecParam);
+    };
+    let mut query = Query {
+        attributes: Dictionary::new(),
+        return_data: false,
+        return_attributes: false,
+        match_all: false,
+    };
+    for (key, value) in dictionary {
+        match key.as_str() {
+            kSecReturnData => query.return_data = is_true(&value),
+            kSecReturnAttributes => query.return_attributes = is_true(&value),
+            kSecReturnRef | kSecReturnPersistentRef if is_true(&value) => {
+                log!("TODO: keychain query option {:?}", key);
+                return Err(errSecUnimplemented);
+            }
+            kSecReturnRef | kSecReturnPersistentRef => (),
+            kSecMatchLimit => {
+                query.match_all = match value {
+                    Value::String(ref limit) if limit == kSecMatchLimitOne => false,
+                    Value::String(ref limit) if limit == kSecMatchLimitAll => true,
+                    Value::Integer(limit) => limit.as_signed() != Some(1),
+                    _ => return Err(errSecParam),
+                }
+            }
+            _ if key.starts_with("m_") || key.starts_with("u_") => {
+                log!("Warning: ignoring keychain query option {:?}", key);
+            }
+            _ => {
+                query.attributes.insert(key, value);
+            }
+        }
+    }
+    match query.attributes.get(kSecClass).and_then(Value::as_string) {
+        Some(kSecClassGenericPassword | kSecClassInternetPassword) => (),
+        Some(class @ (kSecClassCertificate | kSecClassKey | kSecClassIdentity)) => {
+            log!("TODO: keychain item class {:?}", class);
+            return Err(errSecUnimplemented);
+        }
+        _ => return Err(errSecParam),
+    }
+    Ok(query)
+}
+
+fn matches(item: &Dictionary, query: &Query) -> bool {
+    query
+        .attributes
+        .iter()
+        .filter(|&(key, _)| key != kSecValueData)
+        .all(|(key, value)| item.get(key) == Some(value))
+}
+
+/// Whether two items would be the same item, i.e. they have the same class and
+/// the same values for the attributes that identify an item of that class.
+fn is_duplicate(a: &Dictionary, b: &Dictionary) -> bool {
+    let class = a.get(kSecClass).and_then(Value::as_string).unwrap();
+    if b.get(kSecClass).and_then(Value::as_string) != Some(class) {
+        return false;
+    }
+    let key_attributes: &[&str] = match class {
+        kSecClassGenericPassword => &[kSecAttrAccessGroup, kSecAttrAccount, kSecAttrService],
+        kSecClassInternetPassword => &[
+            kSecAttrAccessGroup,
+            kSecAttrAccount,
+            kSecAttrSecurityDomain,
+            kSecAttrServer,
+            kSecAttrProtocol,
+            kSecAttrAuthenticationType,
+            kSecAttrPort,
+            kSecAttrPath,
+        ],
+        // Other classes are refused by parse_query().
+        _ => return false,
+    };
+    key_attributes.iter().all(|&key| a.get(key) == b.get(key))
+}
+
+/// Write the result the app asked for, if any, for the items found.
+fn write_result(
+    env: &mut Environment,
+    query: &Query,
+    found: &[Dictionary],
+    result: MutPtr<CFTypeRef>,
+) {
+    if result.is_null() {
+        return;
+    }
+    if !query.return_data && !query.return_attributes {
+        env.mem.write(result, nil);
+        return;
+    }
+    let mut values: Vec<Value> = found
+        .iter()
+        .map(|item| {
+            if query.return_attributes {
+                let mut attributes = item.clone();
+                if !query.return_data {
+                    attributes.remove(kSecValueData);
+                }
+                Value::Dictionary(attributes)
+            } else {
+                item.get(kSecValueData)
+                    .cloned()
+                    .unwrap_or(Value::Data(Vec::new()))
+            }
+        })
+        .collect();
+    let value = if query.match_all {
+        Value::Array(values)
+    } else {
+        values.swap_remove(0)
+    };
+    // deserialize_plist() returns a new object, as required for a "Copy"
+    // function.
+    let object = deserialize_plist(env, &value);
+    env.mem.write(result, object);
+}
+
+fn SecItemAdd(
+    env: &mut Environment,
+    attributes: CFDictionaryRef,
+    result: MutPtr<CFTypeRef>,
+) -> OSStatus {
+    let query = match parse_query(env, attributes) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let new_item = query.attributes.clone();
+    if items(env).iter().any(|item| is_duplicate(item, &new_item)) {
+        log_dbg!("SecItemAdd({:?}) => errSecDuplicateItem", new_item);
+        return errSecDuplicateItem;
+    }
+    log_dbg!("SecItemAdd({:?}) => errSecSuccess", new_item);
+    items(env).push(new_item.clone());
+    save_items(env);
+    write_result(env, &query, &[new_item], result);
+    errSecSuccess
+}
+
+fn SecItemCopyMatching(
+    env: &mut Environment,
+    query: CFDictionaryRef,
+    result: MutPtr<CFTypeRef>,
+) -> OSStatus {
+    let query = match parse_query(env, query) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let mut found: Vec<Dictionary> = items(env)
+        .iter()
+        .filter(|item| matches(item, &query))
+        .cloned()
+        .collect();
+    log_dbg!(
+        "SecItemCopyMatching({:?}) found {} items",
+        query.attributes,
+        found.len()
+    );
+    if found.is_empty() {
+        return errSecItemNotFound;
+    }
+    if !query.match_all {
+        found.truncate(1);
+    }
+    write_result(env, &query, &found, result);
+    errSecSuccess
+}
+
+fn SecItemUpdate(
+    env: &mut Environment,
+    query: CFDictionaryRef,
+    attributes_to_update: CFDictionaryRef,
+) -> OSStatus {
+    let query = match parse_query(env, query) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let Some(Value::Dictionary(update)) = serialize_plist(env, attributes_to_update) else {
+        return errSecParam;
+    };
+
+    let mut new_items = items(env).clone();
+    let mut updated_count = 0;
+    for item in new_items.iter_mut().filter(|item| matches(item, &query)) {
+        for (key, value) in update.iter() {
+            item.insert(key.clone(), value.clone());
+        }
+        updated_count += 1;
+    }
+    log_dbg!(
+        "SecItemUpdate({:?}, {:?}) updated {} items",
+        query.attributes,
+        update,
+        updated_count
+    );
+    if updated_count == 0 {
+        return errSecItemNotFound;
+    }
+    for (i, a) in new_items.iter().enumerate() {
+        if new_items[i + 1..].iter().any(|b| is_duplicate(a, b)) {
+            return errSecDuplicateItem;
+        }
+    }
+    *items(env) = new_items;
+    save_items(env);
+    errSecSuccess
+}
+
+fn SecItemDelete(env: &mut Environment, query: CFDictionaryRef) -> OSStatus {
+    let query = match parse_query(env, query) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let items = items(env);
+    let old_count = items.len();
+    items.retain(|item| !matches(item, &query));
+    let deleted_count = old_count - items.len();
+    log_dbg!(
+        "SecItemDelete({:?}) deleted {} items",
+        query.attributes,
+        deleted_count
+    );
+    if deleted_count == 0 {
+        return errSecItemNotFound;
+    }
+    save_items(env);
+    errSecSuccess
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(SecItemAdd(_, _)),
+    export_c_func!(SecItemCopyMatching(_, _)),
+    export_c_func!(SecItemUpdate(_, _)),
+    export_c_func!(SecItemDelete(_)),
+];
@@ -0,0 +1,31 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `SecRandom.h`
+
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::libc::stdlib::arc4random_buf;
+use crate::mem::{ConstVoidPtr, GuestUSize, MutPtr};
+use crate::Environment;
+
+/// Opaque type, there's only one instance and it's `NULL`.
+type SecRandomRef = ConstVoidPtr;
+
+pub const CONSTANTS: ConstantExports = &[("_kSecRandomDefault", HostConstant::NullPtr)];
+
+/// This uses the same generator as `arc4random()`, so it isn't suitable for
+/// real cryptography, but it does respect `--random-seed=`.
+fn SecRandomCopyBytes(
+    env: &mut Environment,
+    rnd: SecRandomRef,
+    count: GuestUSize,
+    bytes: MutPtr<u8>,
+) -> i32 {
+    assert!(rnd.is_null()); // kSecRandomDefault
+    arc4random_buf(env, bytes.cast(), count);
+    0
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(SecRandomCopyBytes(_, _, _))];
@@ -0,0 +1,766 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! SQLite (`libsqlite3.dylib`).
+//!
+//! Like zlib, this isn't a framework, but a system library that apps link to
+//! dynamically, usually for save data. The guest API is implemented by
+//! forwarding to a host copy of SQLite (via the `libsqlite3-sys` crate).
+//! Guest pointers for databases and statements are opaque handles that map to
+//! the host SQLite objects, and any strings or blobs SQLite returns are copied
+//! into guest memory.
+//!
+//! SQLite does its own file I/O, so it can only write to databases that are
+//! real files on the host, i.e. ones in the app's sandbox. Databases inside
+//! the app bundle are loaded into memory instead, so changes to them are lost.
+//!
+//! Resources:
+//! - [SQLite C API reference](https://www.sqlite.org/c3ref/intro.html)
+
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::fs::{GuestOpenOptions, GuestPath};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::Environment;
+use libsqlite3_sys as ffi;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr, CString};
+
+const SQLITE_OK: i32 = ffi::SQLITE_OK;
+const SQLITE_MISUSE: i32 = ffi::SQLITE_MISUSE;
+const SQLITE_CANTOPEN: i32 = ffi::SQLITE_CANTOPEN;
+const SQLITE_ROW: i32 = ffi::SQLITE_ROW;
+const SQLITE_ABORT: i32 = ffi::SQLITE_ABORT;
+
+/// Special values of `sqlite3_destructor_type`.
+const SQLITE_STATIC: u32 = 0;
+const SQLITE_TRANSIENT: u32 = u32::MAX;
+
+/// Opaque guest type for `sqlite3*`.
+#[allow(non_camel_case_types)]
+pub struct sqlite3 {}
+/// Opaque guest type for `sqlite3_stmt*`.
+#[allow(non_camel_case_types)]
+pub struct sqlite3_stmt {}
+
+struct Database {
+    raw: *mut ffi::sqlite3,
+    /// Guest copy of the last string returned by `sqlite3_errmsg`.
+    errmsg: Option<MutPtr<u8>>,
+}
+
+struct Statement {
+    raw: *mut ffi::sqlite3_stmt,
+    db: MutPtr<sqlite3>,
+    /// Guest copies of column values for the current row, freed when the
+    /// statement is stepped, reset or finalized.
+    row_values: Vec<MutVoidPtr>,
+    /// Guest copies of column names, freed when the statement is finalized.
+    column_names: HashMap<c_int, MutPtr<u8>>,
+}
+
+#[derive(Default)]
+pub struct State {
+    databases: HashMap<MutPtr<sqlite3>, Database>,
+    statements: HashMap<MutPtr<sqlite3_stmt>, Statement>,
+    libversion: Option<ConstPtr<u8>>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.sqlite3
+    }
+}
+
+/// Get the host database for a guest handle. SQLite accepts `NULL` in most
+/// places, so that's what is returned for an unknown handle.
+fn db_raw(env: &mut Environment, db: MutPtr<sqlite3>) -> *mut ffi::sqlite3 {
+    match State::get(env).databases.get(&db) {
+        Some(database) => database.raw,
+        None => {
+            if !db.is_null() {
+                log!("Warning: unknown SQLite database handle {:?}", db);
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Like [db_raw] but for statements.
+fn stmt_raw(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> *mut ffi::sqlite3_stmt {
+    match State::get(env).statements.get(&stmt) {
+        Some(statement) => statement.raw,
+        None => {
+            if !stmt.is_null() {
+                log!("Warning: unknown SQLite statement handle {:?}", stmt);
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Free the guest copies of the current row's values.
+fn free_row_values(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) {
+    let Some(statement) = State::get(env).statements.get_mut(&stmt) else {
+        return;
+    };
+    for ptr in std::mem::take(&mut statement.row_values) {
+        env.mem.free(ptr);
+    }
+}
+
+/// Copy a string returned by host SQLite into a new guest allocation.
+fn copy_cstr_to_guest(env: &mut Environment, ptr: *const c_char) -> MutPtr<u8> {
+    if ptr.is_null() {
+        return Ptr::null();
+    }
+    let bytes = unsafe { CStr::from_ptr(ptr) }.to_bytes();
+    env.mem.alloc_and_write_cstr(bytes)
+}
+
+/// Make a host copy of a guest SQL string. `n_byte` has the same meaning as
+/// for `sqlite3_prepare`.
+fn sql_from_guest(env: &Environment, sql: ConstPtr<u8>, n_byte: i32) -> CString {
+    let bytes = if n_byte < 0 {
+        env.mem.cstr_at(sql)
+    } else {
+        let bytes = env.mem.bytes_at(sql, n_byte as GuestUSize);
+        // The string ends at the first null byte even if n_byte is larger.
+        match bytes.iter().position(|&b| b == b'\0') {
+            Some(len) => &bytes[..len],
+            None => bytes,
+        }
+    };
+    CString::new(bytes).unwrap()
+}
+
+fn sqlite3_libversion(env: &mut Environment) -> ConstPtr<u8> {
+    if let Some(ptr) = State::get(env).libversion {
+        return ptr;
+    }
+    let ptr = copy_cstr_to_guest(env, unsafe { ffi::sqlite3_libversion() }).cast_const();
+    State::get(env).libversion = Some(ptr);
+    ptr
+}
+
+fn sqlite3_libversion_number(_env: &mut Environment) -> i32 {
+    unsafe { ffi::sqlite3_libversion_number() }
+}
+
+fn sqlite3_open(
+    env: &mut Environment,
+    filename: ConstPtr<u8>,
+    pp_db: MutPtr<MutPtr<sqlite3>>,
+) -> i32 {
+    sqlite3_open_v2(
+        env,
+        filename,
+        pp_db,
+        ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE,
+        Ptr::null(),
+    )
+}
+
+fn sqlite3_open_v2(
+    env: &mut Environment,
+    filename: ConstPtr<u8>,
+    pp_db: MutPtr<MutPtr<sqlite3>>,
+    flags: i32,
+    _vfs: ConstPtr<u8>,
+) -> i32 {
+    let filename = env.mem.cstr_at_utf8(filename).unwrap().to_owned();
+
+    // Work out where the host copy of SQLite should open the database.
+    // In-memory and temporary databases need no special handling.
+    let mut bundle_data = None;
+    let host_filename = if filename.is_empty() || filename == ":memory:" {
+        filename.clone()
+    } else {
+        let path = GuestPath::new(&filename);
+        if !env.fs.exists(path) && (flags & ffi::SQLITE_OPEN_CREATE) != 0 {
+            // Create the file through the guest filesystem so it knows about
+            // it, then let SQLite fill it in.
+            let mut options = GuestOpenOptions::new();
+            options.write().create();
+            let _ = env.fs.open_with_options(path, options);
+        }
+        if let Some(host_path) = env.fs.writeable_file_host_path(path) {
+            host_path.to_str().unwrap().to_owned()
+        } else if let Ok(data) = env.fs.read(path) {
+            log!(
+                "Warning: SQLite database {:?} is read-only, it will be loaded into memory and any changes will be lost",
+                filename
+            );
+            bundle_data = Some(data);
+            ":memory:".to_owned()
+        } else {
+            log!("Warning: couldn't open SQLite database {:?}", filename);
+            env.mem.write(pp_db, Ptr::null());
+            return SQLITE_CANTOPEN;
+        }
+    };
+
+    let host_filename = CString::new(host_filename).unwrap();
+    let mut raw = std::ptr::null_mut();
+    let mut res =
+        unsafe { ffi::sqlite3_open_v2(host_filename.as_ptr(), &mut raw, flags, std::ptr::null()) };
+    if let (SQLITE_OK, Some(data)) = (res, bundle_data) {
+        unsafe {
+            // SQLite takes ownership of this buffer.
+            let buffer = ffi::sqlite3_malloc64(data.len() as u64) as *mut u8;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+            res = ffi::sqlite3_deserialize(
+                raw,
+                c"main".as_ptr(),
+                buffer,
+                data.len() as i64,
+                data.len() as i64,
+                (ffi::SQLITE_DESERIALIZE_FREEONCLOSE | ffi::SQLITE_DESERIALIZE_RESIZEABLE) as u32,
+            );
+        }
+    }
+
+    // Like the real SQLite, a handle is returned even on failure, so that the
+    // app can get the error message.
+    let db = env.mem.alloc(4).cast();
+    State::get(env)
+        .databases
+        .insert(db, Database { raw, errmsg: None });
+    env.mem.write(pp_db, db);
+    log_dbg!(
+        "sqlite3_open_v2({:?}, {:#x}) => {} ({:?})",
+        filename,
+        flags,
+        res,
+        db
+    );
+    res
+}
+
+fn sqlite3_close(env: &mut Environment, db: MutPtr<sqlite3>) -> i32 {
+    if db.is_null() {
+        return SQLITE_OK;
+    }
+    let raw = db_raw(env, db);
+    if raw.is_null() {
+        return SQLITE_MISUSE;
+    }
+    let res = unsafe { ffi::sqlite3_close(raw) };
+    if res != SQLITE_OK {
+        // Probably SQLITE_BUSY because of unfinalized statements, the handle
+        // remains valid.
+        return res;
+    }
+    let database = State::get(env).databases.remove(&db).unwrap();
+    if let Some(errmsg) = database.errmsg {
+        env.mem.free(errmsg.cast());
+    }
+    env.mem.free(db.cast());
+    SQLITE_OK
+}
+
+fn sqlite3_errcode(env: &mut Environment, db: MutPtr<sqlite3>) -> i32 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_errcode(raw) }
+}
+
+fn sqlite3_extended_errcode(env: &mut Environment, db: MutPtr<sqlite3>) -> i32 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_extended_errcode(raw) }
+}
+
+fn sqlite3_errmsg(env: &mut Environment, db: MutPtr<sqlite3>) -> ConstPtr<u8> {
+    let raw = db_raw(env, db);
+    let errmsg = copy_cstr_to_guest(env, unsafe { ffi::sqlite3_errmsg(raw) });
+    // The string only needs to remain valid until the next call.
+    if let Some(database) = State::get(env).databases.get_mut(&db) {
+        if let Some(old) = database.errmsg.replace(errmsg) {
+            env.mem.free(old.cast());
+        }
+    }
+    errmsg.cast_const()
+}
+
+fn sqlite3_free(env: &mut Environment, ptr: MutVoidPtr) {
+    // Strings SQLite returns to the app are allocated with the guest
+    // allocator.
+    if !ptr.is_null() {
+        env.mem.free(ptr);
+    }
+}
+
+fn sqlite3_last_insert_rowid(env: &mut Environment, db: MutPtr<sqlite3>) -> i64 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_last_insert_rowid(raw) }
+}
+
+fn sqlite3_changes(env: &mut Environment, db: MutPtr<sqlite3>) -> i32 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_changes(raw) }
+}
+
+fn sqlite3_total_changes(env: &mut Environment, db: MutPtr<sqlite3>) -> i32 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_total_changes(raw) }
+}
+
+fn sqlite3_busy_timeout(env: &mut Environment, db: MutPtr<sqlite3>, ms: i32) -> i32 {
+    let raw = db_raw(env, db);
+    unsafe { ffi::sqlite3_busy_timeout(raw, ms) }
+}
+
+/// Prepare a statement from a host string, returning the handle and the offset
+/// of the remaining SQL.
+fn prepare(
+    env: &mut Environment,
+    db: MutPtr<sqlite3>,
+    sql: &CStr,
+) -> (i32, MutPtr<sqlite3_stmt>, usize) {
+    let raw_db = db_raw(env, db);
+    let mut raw = std::ptr::null_mut();
+    let mut tail = std::ptr::null();
+    let res = unsafe { ffi::sqlite3_prepare_v2(raw_db, sql.as_ptr(), -1, &mut raw, &mut tail) };
+    let tail_offset = if tail.is_null() {
+        sql.to_bytes().len()
+    } else {
+        tail as usize - sql.as_ptr() as usize
+    };
+    // A null statement is produced for SQL with only whitespace or comments.
+    if raw.is_null() {
+        return (res, Ptr::null(), tail_offset);
+    }
+    let stmt = env.mem.alloc(4).cast();
+    State::get(env).statements.insert(
+        stmt,
+        Statement {
+            raw,
+            db,
+            row_values: Vec::new(),
+            column_names: HashMap::new(),
+        },
+    );
+    (res, stmt, tail_offset)
+}
+
+fn sqlite3_prepare(
+    env: &mut Environment,
+    db: MutPtr<sqlite3>,
+    sql: ConstPtr<u8>,
+    n_byte: i32,
+    pp_stmt: MutPtr<MutPtr<sqlite3_stmt>>,
+    pz_tail: MutPtr<ConstPtr<u8>>,
+) -> i32 {
+    // The differences between the legacy and v2 interfaces are only in error
+    // reporting and handling of schema changes, so the same thing is used.
+    sqlite3_prepare_v2(env, db, sql, n_byte, pp_stmt, pz_tail)
+}
+
+fn sqlite3_prepare_v2(
+    env: &mut Environment,
+    db: MutPtr<sqlite3>,
+    sql: ConstPtr<u8>,
+    n_byte: i32,
+    pp_stmt: MutPtr<MutPtr<sqlite3_stmt>>,
+    pz_tail: MutPtr<ConstPtr<u8>>,
+) -> i32 {
+    let host_sql = sql_from_guest(env, sql, n_byte);
+    let (res, stmt, tail_offset) = prepare(env, db, &host_sql);
+    log_dbg!(
+        "sqlite3_prepare_v2({:?}, {:?}) => {} ({:?})",
+        db,
+        host_sql,
+        res,
+        stmt
+    );
+    env.mem.write(pp_stmt, stmt);
+    if !pz_tail.is_null() {
+        env.mem.write(pz_tail, sql + tail_offset as GuestUSize);
+    }
+    res
+}
+
+fn sqlite3_step(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    free_row_values(env, stmt);
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_step(raw) }
+}
+
+fn sqlite3_reset(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    free_row_values(env, stmt);
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_reset(raw) }
+}
+
+fn sqlite3_finalize(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    if stmt.is_null() {
+        return SQLITE_OK;
+    }
+    free_row_values(env, stmt);
+    let Some(statement) = State::get(env).statements.remove(&stmt) else {
+        log!("Warning: unknown SQLite statement handle {:?}", stmt);
+        return SQLITE_MISUSE;
+    };
+    for name in statement.column_names.into_values() {
+        env.mem.free(name.cast());
+    }
+    env.mem.free(stmt.cast());
+    unsafe { ffi::sqlite3_finalize(statement.raw) }
+}
+
+fn sqlite3_db_handle(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> MutPtr<sqlite3> {
+    State::get(env)
+        .statements
+        .get(&stmt)
+        .map_or(Ptr::null(), |statement| statement.db)
+}
+
+fn sqlite3_clear_bindings(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_clear_bindings(raw) }
+}
+
+fn sqlite3_bind_parameter_count(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_bind_parameter_count(raw) }
+}
+
+fn sqlite3_bind_parameter_index(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    name: ConstPtr<u8>,
+) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    let name = CString::new(env.mem.cstr_at(name)).unwrap();
+    unsafe { ffi::sqlite3_bind_parameter_index(raw, name.as_ptr()) }
+}
+
+fn sqlite3_bind_null(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_bind_null(raw, index) }
+}
+
+fn sqlite3_bind_int(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+    value: i32,
+) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_bind_int(raw, index, value) }
+}
+
+fn sqlite3_bind_int64(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+    value: i64,
+) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_bind_int64(raw, index, value) }
+}
+
+fn sqlite3_bind_double(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+    value: f64,
+) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_bind_double(raw, index, value) }
+}
+
+/// Host SQLite always gets its own copy of bound text and blobs, so the guest
+/// destructor can be called straight away.
+fn call_bind_destructor(env: &mut Environment, destructor: u32, value: ConstVoidPtr) {
+    if destructor != SQLITE_STATIC && destructor != SQLITE_TRANSIENT {
+        let destructor = GuestFunction::from_addr_with_thumb_bit(destructor);
+        () = destructor.call_from_host(env, (value,));
+    }
+}
+
+fn sqlite3_bind_text(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+    value: ConstPtr<u8>,
+    n_byte: i32,
+    destructor: u32, // void (*)(void *)
+) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    let res = if value.is_null() {
+        unsafe { ffi::sqlite3_bind_null(raw, index) }
+    } else {
+        let bytes = if n_byte < 0 {
+            env.mem.cstr_at(value)
+        } else {
+            env.mem.bytes_at(value, n_byte as GuestUSize)
+        };
+        unsafe {
+            ffi::sqlite3_bind_text(
+                raw,
+                index,
+                bytes.as_ptr() as *const c_char,
+                bytes.len() as c_int,
+                ffi::SQLITE_TRANSIENT(),
+            )
+        }
+    };
+    call_bind_destructor(env, destructor, value.cast());
+    res
+}
+
+fn sqlite3_bind_blob(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+    value: ConstVoidPtr,
+    n_byte: i32,
+    destructor: u32, // void (*)(void *)
+) -> i32 {
+    // SQLite with SQLITE_ENABLE_API_ARMOR refuses this too, without calling
+    // the destructor.
+    if n_byte < 0 {
+        log!("Warning: sqlite3_bind_blob() with negative size {}", n_byte);
+        return SQLITE_MISUSE;
+    }
+    let raw = stmt_raw(env, stmt);
+    let res = if value.is_null() {
+        unsafe { ffi::sqlite3_bind_null(raw, index) }
+    } else {
+        let bytes = env.mem.bytes_at(value.cast(), n_byte as GuestUSize);
+        unsafe {
+            ffi::sqlite3_bind_blob(
+                raw,
+                index,
+                bytes.as_ptr().cast(),
+                bytes.len() as c_int,
+                ffi::SQLITE_TRANSIENT(),
+            )
+        }
+    };
+    call_bind_destructor(env, destructor, value);
+    res
+}
+
+fn sqlite3_column_count(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_count(raw) }
+}
+
+fn sqlite3_data_count(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_data_count(raw) }
+}
+
+fn sqlite3_column_name(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+) -> ConstPtr<u8> {
+    if let Some(&name) = State::get(env)
+        .statements
+        .get(&stmt)
+        .and_then(|statement| statement.column_names.get(&index))
+    {
+        return name.cast_const();
+    }
+    let raw = stmt_raw(env, stmt);
+    let name = copy_cstr_to_guest(env, unsafe { ffi::sqlite3_column_name(raw, index) });
+    if let Some(statement) = State::get(env).statements.get_mut(&stmt) {
+        if !name.is_null() {
+            statement.column_names.insert(index, name);
+        }
+    }
+    name.cast_const()
+}
+
+fn sqlite3_column_type(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_type(raw, index) }
+}
+
+fn sqlite3_column_int(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_int(raw, index) }
+}
+
+fn sqlite3_column_int64(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> i64 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_int64(raw, index) }
+}
+
+fn sqlite3_column_double(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> f64 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_double(raw, index) }
+}
+
+fn sqlite3_column_bytes(env: &mut Environment, stmt: MutPtr<sqlite3_stmt>, index: i32) -> i32 {
+    let raw = stmt_raw(env, stmt);
+    unsafe { ffi::sqlite3_column_bytes(raw, index) }
+}
+
+/// Copy a text or blob column value into guest memory. The copy is
+/// null-terminated in both cases, like in SQLite.
+fn column_value_to_guest(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    ptr: *const u8,
+    len: c_int,
+) -> ConstVoidPtr {
+    if ptr.is_null() {
+        return Ptr::null();
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len as usize) };
+    let guest_ptr = env.mem.alloc_and_write_cstr(bytes).cast();
+    if let Some(statement) = State::get(env).statements.get_mut(&stmt) {
+        statement.row_values.push(guest_ptr);
+    }
+    guest_ptr.cast_const()
+}
+
+fn sqlite3_column_text(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+) -> ConstPtr<u8> {
+    let raw = stmt_raw(env, stmt);
+    // The text must be fetched before the length, see the SQLite docs.
+    let (ptr, len) = unsafe {
+        let ptr = ffi::sqlite3_column_text(raw, index);
+        (ptr, ffi::sqlite3_column_bytes(raw, index))
+    };
+    column_value_to_guest(env, stmt, ptr, len).cast()
+}
+
+fn sqlite3_column_blob(
+    env: &mut Environment,
+    stmt: MutPtr<sqlite3_stmt>,
+    index: i32,
+) -> ConstVoidPtr {
+    let raw = stmt_raw(env, stmt);
+    let (ptr, len) = unsafe {
+        let ptr = ffi::sqlite3_column_blob(raw, index);
+        (ptr as *const u8, ffi::sqlite3_column_bytes(raw, index))
+    };
+    column_value_to_guest(env, stmt, ptr, len)
+}
+
+fn sqlite3_exec(
+    env: &mut Environment,
+    db: MutPtr<sqlite3>,
+    sql: ConstPtr<u8>,
+    callback: GuestFunction, // int (*)(void *, int, char **, char **)
+    callback_arg: MutVoidPtr,
+    errmsg: MutPtr<MutPtr<u8>>,
+) -> i32 {
+    // This is a reimplementation of sqlite3_exec() in terms of the other
+    // functions, so that the callback can be a guest function.
+    let host_sql = sql_from_guest(env, sql, -1);
+    log_dbg!("sqlite3_exec({:?}, {:?})", db, host_sql);
+    let mut remaining = host_sql.as_c_str();
+    let mut res = SQLITE_OK;
+    let mut aborted = false;
+    while !remaining.to_bytes().is_empty() {
+        let (prepare_res, stmt, tail_offset) = prepare(env, db, remaining);
+        res = prepare_res;
+        if res != SQLITE_OK {
+            break;
+        }
+        remaining =
+            CStr::from_bytes_with_nul(&remaining.to_bytes_with_nul()[tail_offset..]).unwrap();
+        if stmt.is_null() {
+            continue;
+        }
+        loop {
+            res = sqlite3_step(env, stmt);
+            if res != SQLITE_ROW {
+                break;
+            }
+            if callback.to_ptr().is_null() {
+                continue;
+            }
+            let column_count = sqlite3_column_count(env, stmt);
+            let values: MutPtr<MutPtr<u8>> = env.mem.alloc(column_count as GuestUSize * 4).cast();
+            let names: MutPtr<MutPtr<u8>> = env.mem.alloc(column_count as GuestUSize * 4).cast();
+            for i in 0..column_count {
+                let value = sqlite3_column_text(env, stmt, i);
+                env.mem.write(values + i as GuestUSize, value.cast_mut());
+                let name = sqlite3_column_name(env, stmt, i);
+                env.mem.write(names + i as GuestUSize, name.cast_mut());
+            }
+            let callback_res: i32 =
+                callback.call_from_host(env, (callback_arg, column_count, values, names));
+            env.mem.free(values.cast());
+            env.mem.free(names.cast());
+            if callback_res != 0 {
+                aborted = true;
+                break;
+            }
+        }
+        sqlite3_finalize(env, stmt);
+        if aborted {
+            res = SQLITE_ABORT;
+            break;
+        }
+        if res != ffi::SQLITE_DONE {
+            break;
+        }
+        res = SQLITE_OK;
+    }
+
+    if !errmsg.is_null() {
+        let message = if res == SQLITE_OK {
+            Ptr::null()
+        } else if aborted {
+            env.mem.alloc_and_write_cstr(b"query aborted")
+        } else {
+            let raw = db_raw(env, db);
+            copy_cstr_to_guest(env, unsafe { ffi::sqlite3_errmsg(raw) })
+        };
+        env.mem.write(errmsg, message);
+    }
+    res
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(sqlite3_libversion()),
+    export_c_func!(sqlite3_libversion_number()),
+    export_c_func!(sqlite3_open(_, _)),
+    export_c_func!(sqlite3_open_v2(_, _, _, _)),
+    export_c_func!(sqlite3_close(_)),
+    export_c_func!(sqlite3_errcode(_)),
+    export_c_func!(sqlite3_extended_errcode(_)),
+    export_c_func!(sqlite3_errmsg(_)),
+    export_c_func!(sqlite3_free(_)),
+    export_c_func!(sqlite3_last_insert_rowid(_)),
+    export_c_func!(sqlite3_changes(_)),
+    export_c_func!(sqlite3_total_changes(_)),
+    export_c_func!(sqlite3_busy_timeout(_, _)),
+    export_c_func!(sqlite3_prepare(_, _, _, _, _)),
+    export_c_func!(sqlite3_prepare_v2(_, _, _, _, _)),
+    export_c_func!(sqlite3_step(_)),
+    export_c_func!(sqlite3_reset(_)),
+    export_c_func!(sqlite3_finalize(_)),
+    export_c_func!(sqlite3_db_handle(_)),
+    export_c_func!(sqlite3_clear_bindings(_)),
+    export_c_func!(sqlite3_bind_parameter_count(_)),
+    export_c_func!(sqlite3_bind_parameter_index(_, _)),
+    export_c_func!(sqlite3_bind_null(_, _)),
+    export_c_func!(sqlite3_bind_int(_, _, _)),
+    export_c_func!(sqlite3_bind_int64(_, _, _)),
+    export_c_func!(sqlite3_bind_double(_, _, _)),
+    export_c_func!(sqlite3_bind_text(_, _, _, _, _)),
+    export_c_func!(sqlite3_bind_blob(_, _, _, _, _)),
+    export_c_func!(sqlite3_column_count(_)),
+    export_c_func!(sqlite3_data_count(_)),
+    export_c_func!(sqlite3_column_name(_, _)),
+    export_c_func!(sqlite3_column_type(_, _)),
+    export_c_func!(sqlite3_column_int(_, _)),
+    export_c_func!(sqlite3_column_int64(_, _)),
+    export_c_func!(sqlite3_column_double(_, _)),
+    export_c_func!(sqlite3_column_bytes(_, _)),
+    export_c_func!(sqlite3_column_text(_, _)),
+    export_c_func!(sqlite3_column_blob(_, _)),
+    export_c_func!(sqlite3_exec(_, _, _, _, _)),
+];
@@ -9,10 +9,12 @@
 //! likely to use UIKit in very simple and limited ways, so this implementation
 //! will probably take a lot of shortcuts.
 
+use crate::frameworks::audio_toolbox::audio_session;
 use crate::Environment;
 use std::time::Instant;
 
 pub mod ui_accelerometer;
+pub mod ui_accessibility;
 pub mod ui_activity_indicator_view;
 pub mod ui_application;
 pub mod ui_color;
@@ -33,6 +35,7 @@ pub mod ui_view_controller;
 #[derive(Default)]
 pub struct State {
     ui_accelerometer: ui_accelerometer::State,
+    ui_accessibility: ui_accessibility::State,
     ui_application: ui_application::State,
     ui_color: ui_color::State,
     ui_device: ui_device::State,
@@ -41,6 +44,7 @@ pub struct State {
     ui_screen: ui_screen::State,
     ui_touch: ui_touch::State,
     pub ui_view: ui_view::State,
+    ui_view_controller: ui_view_controller::State,
 }
 
 /// For use by `NSRunLoop`: handles any events that have queued up.
@@ -96,8 +100,16 @@ pub fn handle_events(env: &mut Environment) -> Option<Instant> {
                     log!("Ignoring EnterDebugger event: no debugger connected.");
                 }
             }
+            Event::MemoryWarning => {
+                log!("Handling MemoryWarning event.");
+                ui_application::send_memory_warning(env);
+            }
+            Event::VolumeDown => audio_session::press_volume_button(env, /* up: */ false),
+            Event::VolumeUp => audio_session::press_volume_button(env, /* up: */ true),
         }
     }
 
+    ui_application::handle_memory_pressure(env);
+
     ui_accelerometer::handle_accelerometer(env)
 }
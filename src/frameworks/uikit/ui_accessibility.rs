@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! UIAccessibility.
+//!
+//! touchHLE has no screen reader, so the accessibility attributes apps set on
+//! views (see [super::ui_view]) are only stored, and VoiceOver is never
+//! running. Announcements are spoken if `--text-to-speech` is used (see
+//! [crate::audio::Speech]), since some apps use them for voice prompts.
+//!
+//! Resources:
+//! - Apple's [Accessibility Programming Guide for iOS](https://developer.apple.com/library/archive/documentation/UserExperience/Conceptual/iPhoneAccessibility/Introduction/Introduction.html)
+
+use crate::audio::Speech;
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::frameworks::foundation::ns_string::to_rust_string;
+use crate::mem::{ConstVoidPtr, Mem};
+use crate::objc::{id, nil};
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    /// Created on first use.
+    speech: Option<Speech>,
+}
+
+pub type UIAccessibilityTraits = u64;
+pub const UIAccessibilityTraitNone: UIAccessibilityTraits = 0;
+pub const UIAccessibilityTraitButton: UIAccessibilityTraits = 1 << 0;
+pub const UIAccessibilityTraitLink: UIAccessibilityTraits = 1 << 1;
+pub const UIAccessibilityTraitImage: UIAccessibilityTraits = 1 << 2;
+pub const UIAccessibilityTraitSelected: UIAccessibilityTraits = 1 << 3;
+pub const UIAccessibilityTraitPlaysSound: UIAccessibilityTraits = 1 << 4;
+pub const UIAccessibilityTraitKeyboardKey: UIAccessibilityTraits = 1 << 5;
+pub const UIAccessibilityTraitStaticText: UIAccessibilityTraits = 1 << 6;
+pub const UIAccessibilityTraitSummaryElement: UIAccessibilityTraits = 1 << 7;
+pub const UIAccessibilityTraitNotEnabled: UIAccessibilityTraits = 1 << 8;
+pub const UIAccessibilityTraitUpdatesFrequently: UIAccessibilityTraits = 1 << 9;
+pub const UIAccessibilityTraitSearchField: UIAccessibilityTraits = 1 << 10;
+pub const UIAccessibilityTraitStartsMediaSession: UIAccessibilityTraits = 1 << 11;
+pub const UIAccessibilityTraitAdjustable: UIAccessibilityTraits = 1 << 12;
+pub const UIAccessibilityTraitAllowsDirectInteraction: UIAccessibilityTraits = 1 << 13;
+pub const UIAccessibilityTraitCausesPageTurn: UIAccessibilityTraits = 1 << 14;
+pub const UIAccessibilityTraitHeader: UIAccessibilityTraits = 1 << 16;
+
+pub type UIAccessibilityNotifications = u32;
+pub const UIAccessibilityScreenChangedNotification: UIAccessibilityNotifications = 1000;
+pub const UIAccessibilityLayoutChangedNotification: UIAccessibilityNotifications = 1001;
+pub const UIAccessibilityAnnouncementNotification: UIAccessibilityNotifications = 1008;
+pub const UIAccessibilityPageScrolledNotification: UIAccessibilityNotifications = 1009;
+
+/// The traits and notifications are variables rather than macros, so they
+/// have to be in guest memory.
+macro_rules! constant {
+    ($name:ident) => {
+        (
+            concat!("_", stringify!($name)),
+            HostConstant::Custom(|mem: &mut Mem| -> ConstVoidPtr {
+                mem.alloc_and_write($name).cast().cast_const()
+            }),
+        )
+    };
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    constant!(UIAccessibilityTraitNone),
+    constant!(UIAccessibilityTraitButton),
+    constant!(UIAccessibilityTraitLink),
+    constant!(UIAccessibilityTraitImage),
+    constant!(UIAccessibilityTraitSelected),
+    constant!(UIAccessibilityTraitPlaysSound),
+    constant!(UIAccessibilityTraitKeyboardKey),
+    constant!(UIAccessibilityTraitStaticText),
+    constant!(UIAccessibilityTraitSummaryElement),
+    constant!(UIAccessibilityTraitNotEnabled),
+    constant!(UIAccessibilityTraitUpdatesFrequently),
+    constant!(UIAccessibilityTraitSearchField),
+    constant!(UIAccessibilityTraitStartsMediaSession),
+    constant!(UIAccessibilityTraitAdjustable),
+    constant!(UIAccessibilityTraitAllowsDirectInteraction),
+    constant!(UIAccessibilityTraitCausesPageTurn),
+    constant!(UIAccessibilityTraitHeader),
+    constant!(UIAccessibilityScreenChangedNotification),
+    constant!(UIAccessibilityLayoutChangedNotification),
+    constant!(UIAccessibilityAnnouncementNotification),
+    constant!(UIAccessibilityPageScrolledNotification),
+];
+
+fn UIAccessibilityPostNotification(
+    env: &mut Environment,
+    notification: UIAccessibilityNotifications,
+    argument: id,
+) {
+    match notification {
+        UIAccessibilityAnnouncementNotification => {
+            if argument == nil {
+                return;
+            }
+            let text = to_rust_string(env, argument);
+            let text_to_speech = env.options.text_to_speech;
+            env.framework_state
+                .uikit
+                .ui_accessibility
+                .speech
+                .get_or_insert_with(|| Speech::new(text_to_speech))
+                .speak(&text);
+        }
+        UIAccessibilityScreenChangedNotification
+        | UIAccessibilityLayoutChangedNotification
+        | UIAccessibilityPageScrolledNotification => {
+            // There's no screen reader to tell about this.
+            log_dbg!(
+                "UIAccessibilityPostNotification({}, {:?})",
+                notification,
+                argument
+            );
+        }
+        _ => {
+            log!(
+                "Warning: Ignoring unknown UIAccessibilityPostNotification({}, {:?})",
+                notification,
+                argument
+            );
+        }
+    }
+}
+
+fn UIAccessibilityIsVoiceOverRunning(_env: &mut Environment) -> bool {
+    false
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(UIAccessibilityPostNotification(_, _)),
+    export_c_func!(UIAccessibilityIsVoiceOverRunning()),
+];
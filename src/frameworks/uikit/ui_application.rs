@@ -6,13 +6,16 @@
 //! `UIApplication` and `UIApplicationMain`.
 
 use super::ui_device::*;
+use super::{ui_font, ui_touch};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::foundation::{ns_array, ns_string};
+use crate::dyld::{ConstantExports, HostConstant};
+use crate::frameworks::foundation::ns_string::get_static_str;
+use crate::frameworks::foundation::{ns_array, ns_cache, ns_string};
 use crate::frameworks::uikit::ui_nib::load_main_nib_file;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject, NSZonePtr, SEL,
 };
 use crate::window::DeviceOrientation;
 use crate::Environment;
@@ -22,6 +25,9 @@ pub struct State {
     /// [UIApplication sharedApplication]
     shared_application: Option<id>,
     pub(super) status_bar_hidden: bool,
+    /// Set once a memory warning has been sent because of high memory usage,
+    /// and cleared once usage is back down.
+    memory_warning_sent: bool,
 }
 
 struct UIApplicationHostObject {
@@ -32,6 +38,15 @@ impl HostObject for UIApplicationHostObject {}
 
 type UIInterfaceOrientation = UIDeviceOrientation;
 
+pub const UIApplicationDidReceiveMemoryWarningNotification: &str =
+    "UIApplicationDidReceiveMemoryWarningNotification";
+
+/// `NSNotificationName` values.
+pub const CONSTANTS: ConstantExports = &[(
+    "_UIApplicationDidReceiveMemoryWarningNotification",
+    HostConstant::NSString(UIApplicationDidReceiveMemoryWarningNotification),
+)];
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -114,9 +129,19 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.window_mut().set_screen_saver_enabled(!disabled);
 }
 
+- (bool)canOpenURL:(id)url { // NSURL
+    let ns_string = msg![env; url absoluteString];
+    let url_string = ns_string::to_rust_string(env, ns_string);
+    can_open_url(env, &url_string)
+}
+
 - (bool)openURL:(id)url { // NSURL
     let ns_string = msg![env; url absoluteString];
     let url_string = ns_string::to_rust_string(env, ns_string);
+    if !can_open_url(env, &url_string) {
+        log!("App tried to open URL {:?}, which a stock device can't open.", url_string);
+        return false;
+    }
     if let Err(e) = crate::window::open_url(&url_string) {
         echo!("App opened URL {:?} unsuccessfully ({}), exiting.", url_string, e);
     } else {
@@ -131,6 +156,90 @@ pub const CLASSES: ClassExports = objc_classes! {
     true
 }
 
+- (id)nextResponder {
+    // The delegate is the end of the responder chain, if it's a responder.
+    let delegate: id = msg![env; this delegate];
+    let ui_responder_class = env.objc.get_known_class("UIResponder", &mut env.mem);
+    if delegate != nil && msg![env; delegate isKindOfClass:ui_responder_class] {
+        delegate
+    } else {
+        nil
+    }
+}
+
+// Apps can override this to see or intercept all actions sent by controls.
+- (bool)sendAction:(SEL)action
+                to:(id)target
+              from:(id)sender
+          forEvent:(id)event { // UIEvent*
+    let target = if target != nil {
+        target
+    } else {
+        // With no target, the action goes to the first object in the
+        // responder chain that can handle it.
+        // TODO: The chain should start at the first responder.
+        let mut responder = sender;
+        while responder != nil && !msg![env; responder respondsToSelector:action] {
+            responder = msg![env; responder nextResponder];
+        }
+        if responder == nil {
+            log_dbg!(
+                "No responder for action {:?} from {:?}, ignoring",
+                action.as_str(&env.mem),
+                sender
+            );
+            return false;
+        }
+        responder
+    };
+
+    let sel_str = action.as_str(&env.mem);
+    let colon_count = sel_str.bytes().filter(|&b| b == b':').count();
+    match colon_count {
+        // - (IBAction)action;
+        0 => {
+            log_dbg!(
+                "Sending {:?} ({:?}) message to {:?} (no args)",
+                action,
+                sel_str,
+                target
+            );
+            () = msg_send(env, (target, action));
+        }
+        // - (IBAction)action:(id)sender;
+        1 => {
+            log_dbg!(
+                "Sending {:?} ({:?}) message to {:?} (one arg: {:?})",
+                action,
+                sel_str,
+                target,
+                sender
+            );
+            () = msg_send(env, (target, action, sender));
+        }
+        // - (IBAction)action:(id)sender forEvent:(UIEvent*)event;
+        2 => {
+            log_dbg!(
+                "Sending {:?} ({:?}) message to {:?} (two args: {:?}, {:?})",
+                action,
+                sel_str,
+                target,
+                sender,
+                event
+            );
+            () = msg_send(env, (target, action, sender, event));
+        }
+        _ => panic!("Action {:?} has too many arguments", sel_str),
+    };
+    true
+}
+
+// Apps can override this to see or intercept all events.
+- (())sendEvent:(id)event { // UIEvent*
+    // TODO: this should go via -[UIWindow sendEvent:]
+    ui_touch::deliver_touches(env, event);
+}
+
 // TODO: ignore touches
 -(())beginIgnoringInteractionEvents {
     log!("TODO: ignoring beginIgnoringInteractionEvents");
@@ -178,12 +287,27 @@ pub(super) fn UIApplicationMain(
     let ui_application = {
         let pool: id = msg_class![env; NSAutoreleasePool new];
 
-        let principal_class = if principal_class_name != nil {
-            let name = ns_string::to_rust_string(env, principal_class_name);
-            env.objc.get_known_class(&name, &mut env.mem)
+        // The principal class can be a guest subclass of UIApplication. If
+        // UIApplicationMain wasn't given one, Info.plist may specify it.
+        let principal_class_name = if principal_class_name != nil {
+            ns_string::to_rust_string(env, principal_class_name).to_string()
         } else {
-            env.objc.get_known_class("UIApplication", &mut env.mem)
+            env.bundle
+                .principal_class()
+                .unwrap_or("UIApplication")
+                .to_string()
         };
+        log_dbg!("Principal class is {:?}", principal_class_name);
+        let principal_class = env
+            .objc
+            .get_known_class(&principal_class_name, &mut env.mem);
+        let ui_application_class = env.objc.get_known_class("UIApplication", &mut env.mem);
+        assert!(
+            env.objc
+                .class_is_subclass_of(principal_class, ui_application_class),
+            "Principal class {} is not a subclass of UIApplication",
+            principal_class_name
+        );
         let ui_application: id = msg![env; principal_class new];
 
         load_main_nib_file(env, ui_application);
@@ -199,10 +323,20 @@ pub(super) fn UIApplicationMain(
             retain(env, delegate);
         } else {
             // We have to construct the delegate.
-            assert!(delegate_class_name != nil);
+            assert!(
+                delegate_class_name != nil,
+                "No application delegate in the main nib file and no delegate class name given"
+            );
             let name = ns_string::to_rust_string(env, delegate_class_name);
+            log_dbg!("Delegate class is {:?}", name);
             let class = env.objc.get_known_class(&name, &mut env.mem);
-            let delegate: id = msg![env; class new];
+            // Some apps make their UIApplication subclass its own delegate.
+            // There can only be one UIApplication, so don't create another.
+            let delegate: id = if class == principal_class {
+                ui_application
+            } else {
+                msg![env; class new]
+            };
             let _: () = msg![env; ui_application setDelegate:delegate];
             assert!(delegate != nil);
         };
@@ -300,7 +434,122 @@ pub(super) fn exit(env: &mut Environment) {
         let _: () = msg![env; pool drain];
     };
 
-    std::process::exit(0);
+    // UIKit calls exit() at this point.
+    crate::libc::cxxabi::run_exit_handlers(env);
+    env.exit(0);
+}
+
+/// Tell the app the system is running low on memory.
+pub(super) fn send_memory_warning(env: &mut Environment) {
+    let Some(ui_application) = env.framework_state.uikit.ui_application.shared_application else {
+        log!("App hasn't started yet, not sending memory warning.");
+        return;
+    };
+
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+
+    let delegate: id = msg![env; ui_application delegate];
+    if env
+        .objc
+        .object_has_method_named(&env.mem, delegate, "applicationDidReceiveMemoryWarning:")
+    {
+        () = msg![env; delegate applicationDidReceiveMemoryWarning:ui_application];
+    }
+
+    let center: id = msg_class![env; NSNotificationCenter defaultCenter];
+    let name = get_static_str(env, UIApplicationDidReceiveMemoryWarningNotification);
+    () = msg![env; center postNotificationName:name object:ui_application];
+
+    // On a real device, view controllers observe the notification.
+    let view_controllers = env
+        .framework_state
+        .uikit
+        .ui_view_controller
+        .view_controllers
+        .clone();
+    for view_controller in view_controllers {
+        () = msg![env; view_controller didReceiveMemoryWarning];
+    }
+
+    ns_cache::handle_memory_warning(env);
+    ui_font::handle_memory_warning(env);
+
+    let _: () = msg![env; pool drain];
+}
+
+/// For use by [super::handle_events]: check how much memory the app is using
+/// relative to the simulated device's RAM, and send a memory warning or
+/// terminate the app (like jetsam would) if it's using too much.
+pub(super) fn handle_memory_pressure(env: &mut Environment) {
+    let device_ram = u64::from(env.options.device_ram_mib().get()) * 1024 * 1024;
+    let used = u64::from(env.mem.allocated_bytes());
+
+    // These are rough approximations. The real limits depend on what else is
+    // running on the device.
+    let warning_threshold = device_ram / 2;
+    let jetsam_threshold = device_ram / 4 * 3;
+
+    if env.options.jetsam && used > jetsam_threshold {
+        echo!(
+            "App is using {} MiB of memory, over the limit of {} MiB. Terminating it, as jetsam would.",
+            used / (1024 * 1024),
+            jetsam_threshold / (1024 * 1024),
+        );
+        env.exit(1);
+    }
+
+    if !env.options.memory_warnings {
+        return;
+    }
+    let state = &mut env.framework_state.uikit.ui_application;
+    if used > warning_threshold {
+        if !state.memory_warning_sent {
+            state.memory_warning_sent = true;
+            log!(
+                "App is using {} MiB of memory, over the threshold of {} MiB. Sending memory warning.",
+                used / (1024 * 1024),
+                warning_threshold / (1024 * 1024),
+            );
+            send_memory_warning(env);
+        }
+    } else if used < warning_threshold / 10 * 9 {
+        // Some leeway so that hovering around the threshold doesn't produce a
+        // flood of warnings.
+        state.memory_warning_sent = false;
+    }
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(UIApplicationMain(_, _, _, _))];
+
+/// URL schemes handled by the apps built into a stock device.
+const STOCK_URL_SCHEMES: &[&str] = &[
+    "http",
+    "https",
+    "mailto",
+    "tel",
+    "sms",
+    "maps",
+    "itms",
+    "itms-apps",
+];
+
+/// URL schemes of apps that are only found on jailbroken devices. Apps check
+/// whether these can be opened to detect a jailbreak.
+const JAILBREAK_URL_SCHEMES: &[&str] = &["cydia", "sileo", "zbra", "filza", "activator"];
+
+/// Whether a URL can be opened, for `canOpenURL:` and `openURL:`. Only the
+/// schemes of the built-in apps can be opened, plus those of jailbreak apps if
+/// the `--jailbroken` option is used.
+fn can_open_url(env: &Environment, url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return false;
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    if STOCK_URL_SCHEMES.contains(&scheme.as_str()) {
+        true
+    } else if JAILBREAK_URL_SCHEMES.contains(&scheme.as_str()) {
+        env.options.jailbroken
+    } else {
+        false
+    }
+}
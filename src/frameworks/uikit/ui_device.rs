@@ -8,9 +8,14 @@
 use crate::dyld::ConstantExports;
 use crate::dyld::HostConstant;
 use crate::frameworks::foundation::ns_string;
+use crate::frameworks::foundation::ns_uuid::{
+    format_uuid, parse_uuid, random_uuid, uuid_with_bytes, UUIDBytes,
+};
 use crate::frameworks::foundation::NSInteger;
-use crate::objc::{id, objc_classes, ClassExports, TrivialHostObject};
+use crate::libc::sysctl::PRODUCT_VERSION;
+use crate::objc::{autorelease, id, objc_classes, ClassExports, TrivialHostObject};
 use crate::window::DeviceOrientation;
+use crate::{paths, Environment};
 
 pub const UIDeviceOrientationDidChangeNotification: &str =
     "UIDeviceOrientationDidChangeNotification";
@@ -31,6 +36,8 @@ pub const UIDeviceOrientationFaceDown: UIDeviceOrientation = 6;
 #[derive(Default)]
 pub struct State {
     current_device: Option<id>,
+    /// See [app_uuid].
+    app_uuid: Option<UUIDBytes>,
 }
 
 pub const CONSTANTS: ConstantExports = &[(
@@ -65,21 +72,31 @@ pub const CLASSES: ClassExports = objc_classes! {
     log!("TODO: endGeneratingDeviceOrientationNotifications");
 }
 - (id)model {
-    // TODO: Hardcoded to iPhone for now
-    ns_string::get_static_str(env, "iPhone")
+    ns_string::get_static_str(env, env.options.device_model.name)
 }
 
 // NSString
 - (id)systemVersion {
-    ns_string::get_static_str(env, "2.0")
+    ns_string::get_static_str(env, PRODUCT_VERSION)
 }
 
 - (id)uniqueIdentifier {
+    if env.options.persistent_device_id {
+        let string = format_uuid(&app_uuid(env));
+        let string = ns_string::from_rust_string(env, string);
+        return autorelease(env, string);
+    }
     // Aspen Simulator returns (null) here
     // TODO: what should be a correct value?
     ns_string::get_static_str(env, "touchHLEdevice")
 }
 
+- (id)identifierForVendor {
+    let bytes = app_uuid(env);
+    let uuid = uuid_with_bytes(env, bytes);
+    autorelease(env, uuid)
+}
+
 - (bool)isMultitaskingSupported {
     false
 }
@@ -95,3 +112,35 @@ pub const CLASSES: ClassExports = objc_classes! {
 @end
 
 };
+
+/// Get the UUID that identifies the device to this app. With
+/// `--persistent-device-id`, this is stored in the app's sandbox directory so
+/// that it stays the same between runs, otherwise a new one is made each run.
+fn app_uuid(env: &mut Environment) -> UUIDBytes {
+    if let Some(uuid) = env.framework_state.uikit.ui_device.app_uuid {
+        return uuid;
+    }
+
+    let uuid = if env.options.persistent_device_id {
+        let path = paths::user_data_base_path()
+            .join(paths::SANDBOX_DIR)
+            .join(env.bundle.bundle_identifier())
+            .join("touchHLE_device_id.txt");
+        let existing = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|string| parse_uuid(string.trim()));
+        if let Some(uuid) = existing {
+            uuid
+        } else {
+            let uuid = random_uuid();
+            if let Err(e) = std::fs::write(&path, format_uuid(&uuid)) {
+                log!("Warning: Could not save device ID to {:?}: {:?}", path, e);
+            }
+            uuid
+        }
+    } else {
+        random_uuid()
+    };
+    env.framework_state.uikit.ui_device.app_uuid = Some(uuid);
+    uuid
+}
@@ -16,6 +16,9 @@ use crate::Environment;
 pub(super) struct UIEventHostObject {
     /// `NSSet<UITouch*>*`
     touches: id,
+    /// Views and the `NSSet<UITouch*>*` of touches for each, to be delivered
+    /// by `-[UIApplication sendEvent:]`. The sets are owned by the event.
+    pub(super) view_touches: Vec<(id, id)>,
 }
 impl HostObject for UIEventHostObject {}
 
@@ -28,17 +31,24 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = Box::new(UIEventHostObject {
         touches: nil,
+        view_touches: Vec::new(),
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
 - (())dealloc {
-    let &UIEventHostObject { touches } = env.objc.borrow(this);
+    let host_object = env.objc.borrow_mut::<UIEventHostObject>(this);
+    let touches = host_object.touches;
+    let view_touches = std::mem::take(&mut host_object.view_touches);
     release(env, touches);
+    for (_view, view_touches) in view_touches {
+        release(env, view_touches);
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
 }
 
 - (id)touchesForView:(id)view_ {
-    let &UIEventHostObject { touches } = env.objc.borrow(this);
+    let touches = env.objc.borrow::<UIEventHostObject>(this).touches;
 
     let touches_for_view: id = msg_class![env; NSMutableSet allocWithZone:(MutVoidPtr::null())];
 
@@ -59,7 +69,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (id)allTouches {
-    let &UIEventHostObject { touches } = env.objc.borrow(this);
+    let touches = env.objc.borrow::<UIEventHostObject>(this).touches;
     touches
 }
 
@@ -107,6 +107,23 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Free cached glyphs of all loaded fonts, in response to a memory warning.
+pub(super) fn handle_memory_warning(env: &mut Environment) {
+    let state = &env.framework_state.uikit.ui_font;
+    for font in [
+        &state.regular,
+        &state.bold,
+        &state.italic,
+        &state.regular_ja,
+        &state.bold_ja,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        font.clear_glyph_cache();
+    }
+}
+
 fn convert_line_break_mode(ui_mode: UILineBreakMode) -> WrapMode {
     match ui_mode {
         UILineBreakModeWordWrap => WrapMode::Word,
@@ -2,13 +2,61 @@
 //!
 //! See also [crate::frameworks::core_graphics::cg_geometry].
 
+use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::core_graphics::cg_affine_transform::{
+    CGAffineTransform, CGAffineTransformIdentity,
+};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::frameworks::foundation::ns_string;
+use crate::mem::SafeRead;
 use crate::objc::{autorelease, id};
 use crate::Environment;
 
-// Apple's documentation says all of these return zeroes if the input is not
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C, packed)]
+pub struct UIEdgeInsets {
+    pub top: CGFloat,
+    pub left: CGFloat,
+    pub bottom: CGFloat,
+    pub right: CGFloat,
+}
+unsafe impl SafeRead for UIEdgeInsets {}
+impl_GuestRet_for_large_struct!(UIEdgeInsets);
+impl GuestArg for UIEdgeInsets {
+    const REG_COUNT: usize = 4;
+
+    fn from_regs(regs: &[u32]) -> Self {
+        UIEdgeInsets {
+            top: GuestArg::from_regs(&regs[0..1]),
+            left: GuestArg::from_regs(&regs[1..2]),
+            bottom: GuestArg::from_regs(&regs[2..3]),
+            right: GuestArg::from_regs(&regs[3..4]),
+        }
+    }
+    fn to_regs(self, regs: &mut [u32]) {
+        self.top.to_regs(&mut regs[0..1]);
+        self.left.to_regs(&mut regs[1..2]);
+        self.bottom.to_regs(&mut regs[2..3]);
+        self.right.to_regs(&mut regs[3..4]);
+    }
+}
+
+/// Equivalent of `UIEdgeInsetsInsetRect`, which is an inline function.
+pub fn inset_rect(rect: CGRect, insets: UIEdgeInsets) -> CGRect {
+    CGRect {
+        origin: CGPoint {
+            x: rect.origin.x + insets.left,
+            y: rect.origin.y + insets.top,
+        },
+        size: CGSize {
+            width: rect.size.width - insets.left - insets.right,
+            height: rect.size.height - insets.top - insets.bottom,
+        },
+    }
+}
+
+// Apple's documentation says these return zeroes if the input is not
 // well-formed.
 pub fn CGPointFromString(env: &mut Environment, string: id) -> CGPoint {
     // TODO: avoid copy
@@ -29,6 +77,15 @@ pub fn CGRectFromString(env: &mut Environment, string: id) -> CGRect {
         .unwrap_or_default()
 }
 
+// Unlike the others, this returns the identity transform if the input is not
+// well-formed.
+pub fn CGAffineTransformFromString(env: &mut Environment, string: id) -> CGAffineTransform {
+    // TODO: avoid copy
+    ns_string::to_rust_string(env, string)
+        .parse()
+        .unwrap_or(CGAffineTransformIdentity)
+}
+
 pub fn NSStringFromCGPoint(env: &mut Environment, point: CGPoint) -> id {
     let s = ns_string::from_rust_string(env, point.to_string());
     autorelease(env, s)
@@ -41,12 +98,18 @@ pub fn NSStringFromCGRect(env: &mut Environment, rect: CGRect) -> id {
     let s = ns_string::from_rust_string(env, rect.to_string());
     autorelease(env, s)
 }
+pub fn NSStringFromCGAffineTransform(env: &mut Environment, transform: CGAffineTransform) -> id {
+    let s = ns_string::from_rust_string(env, transform.to_string());
+    autorelease(env, s)
+}
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(CGPointFromString(_)),
     export_c_func!(CGSizeFromString(_)),
     export_c_func!(CGRectFromString(_)),
+    export_c_func!(CGAffineTransformFromString(_)),
     export_c_func!(NSStringFromCGPoint(_)),
     export_c_func!(NSStringFromCGSize(_)),
     export_c_func!(NSStringFromCGRect(_)),
+    export_c_func!(NSStringFromCGAffineTransform(_)),
 ];
@@ -5,7 +5,7 @@
  */
 //! `UIResponder`.
 
-use crate::objc::{id, objc_classes, ClassExports};
+use crate::objc::{id, nil, objc_classes, ClassExports};
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -15,6 +15,12 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // TODO: real responder implementation etc
 
+// Subclasses override this to build the responder chain, which is used to find
+// the target for actions with a nil target.
+- (id)nextResponder {
+    nil
+}
+
 // These methods print debug logs because they are only likely to get called if
 // a subclass didn't override them, which might mean we delivered the event to
 // the wrong object or it is unhandled.
@@ -5,8 +5,12 @@
  */
 //! `UIScreen`.
 
-use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::objc::{id, msg, objc_classes, ClassExports, TrivialHostObject};
+use crate::window::DeviceOrientation;
+use crate::Environment;
+
+const STATUS_BAR_HEIGHT: CGFloat = 20.0;
 
 #[derive(Default)]
 pub struct State {
@@ -50,7 +54,6 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 - (CGRect)applicationFrame {
     let mut bounds: CGRect = msg![env; this bounds];
-    const STATUS_BAR_HEIGHT: f32 = 20.0;
     if !env.framework_state.uikit.ui_application.status_bar_hidden {
         bounds.origin.y += STATUS_BAR_HEIGHT;
         bounds.size.height -= STATUS_BAR_HEIGHT;
@@ -61,3 +64,13 @@ pub const CLASSES: ClassExports = objc_classes! {
 @end
 
 };
+
+/// Check whether a point (in screen co-ordinates) is on the status bar. This
+/// is always false if the status bar is hidden.
+pub(super) fn point_is_on_status_bar(env: &Environment, point: CGPoint) -> bool {
+    // TODO: handle the status bar in landscape orientations. Touch
+    // co-ordinates aren't rotated, so checking the top edge would be wrong.
+    !env.framework_state.uikit.ui_application.status_bar_hidden
+        && env.window().current_rotation() == DeviceOrientation::Portrait
+        && point.y < STATUS_BAR_HEIGHT
+}
@@ -5,7 +5,9 @@
  */
 //! `UITouch`.
 
-use super::ui_event;
+use super::ui_event::{self, UIEventHostObject};
+use super::ui_screen::point_is_on_status_bar;
+use super::ui_view::ui_scroll_view;
 use crate::frameworks::core_graphics::{CGPoint, CGRect};
 use crate::frameworks::foundation::{NSInteger, NSTimeInterval, NSUInteger};
 use crate::mem::MutVoidPtr;
@@ -27,6 +29,9 @@ pub const UITouchPhaseEnded: UITouchPhase = 3;
 #[derive(Default)]
 pub struct State {
     current_touches: HashMap<FingerId, id>,
+    /// Fingers that touched down on the status bar. These aren't seen by the
+    /// app, but lifting one scrolls to the top (see [ui_scroll_view]).
+    status_bar_touches: HashSet<FingerId>,
 }
 
 pub(super) struct UITouchHostObject {
@@ -121,7 +126,80 @@ pub fn handle_event(env: &mut Environment, event: Event) {
     }
 }
 
-fn handle_touches_down(env: &mut Environment, map: HashMap<FingerId, Coords>) {
+/// Send a touch event to the app via `-[UIApplication sendEvent:]`, which the
+/// app may override. `view_touches` maps each view to the set of touches that
+/// will be delivered to it.
+fn send_event(env: &mut Environment, event: id, view_touches: HashMap<id, id>) {
+    env.objc.borrow_mut::<UIEventHostObject>(event).view_touches =
+        view_touches.into_iter().collect();
+    let ui_application: id = msg_class![env; UIApplication sharedApplication];
+    log_dbg!("Sending [{:?} sendEvent:{:?}]", ui_application, event);
+    let _: () = msg![env; ui_application sendEvent:event];
+}
+
+/// For use by `-[UIApplication sendEvent:]`: deliver a touch event's touches
+/// to their views.
+pub(super) fn deliver_touches(env: &mut Environment, event: id) {
+    let view_touches = env
+        .objc
+        .borrow::<UIEventHostObject>(event)
+        .view_touches
+        .clone();
+    for (view, touches) in view_touches {
+        // All the touches delivered together are in the same phase.
+        let touch: id = msg![env; touches anyObject];
+        let phase = env.objc.borrow::<UITouchHostObject>(touch).phase;
+        match phase {
+            UITouchPhaseBegan => {
+                log_dbg!(
+                    "Sending [{:?} touchesBegan:{:?} withEvent:{:?}]",
+                    view,
+                    touches,
+                    event
+                );
+                let _: () = msg![env; view touchesBegan:touches withEvent:event];
+            }
+            UITouchPhaseMoved => {
+                log_dbg!(
+                    "Sending [{:?} touchesMoved:{:?} withEvent:{:?}]",
+                    view,
+                    touches,
+                    event
+                );
+                let _: () = msg![env; view touchesMoved:touches withEvent:event];
+            }
+            UITouchPhaseEnded => {
+                log_dbg!(
+                    "Sending [{:?} touchesEnded:{:?} withEvent:{:?}]",
+                    view,
+                    touches,
+                    event
+                );
+                let _: () = msg![env; view touchesEnded:touches withEvent:event];
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn handle_touches_down(env: &mut Environment, mut map: HashMap<FingerId, Coords>) {
+    // The status bar is on top of the app's windows, so touches on it never
+    // reach the app.
+    let status_bar_fingers: Vec<FingerId> = map
+        .iter()
+        .filter(|&(_, &(x, y))| point_is_on_status_bar(env, CGPoint { x, y }))
+        .map(|(&finger_id, _)| finger_id)
+        .collect();
+    for finger_id in status_bar_fingers {
+        log_dbg!("Finger {:?} touch down on status bar", finger_id);
+        map.remove(&finger_id);
+        let state = &mut env.framework_state.uikit.ui_touch;
+        state.status_bar_touches.insert(finger_id);
+    }
+    if map.is_empty() {
+        return;
+    }
+
     // Assumes the last window in the list is the one on top.
     // TODO: this is not correct once we support zPosition.
     let Some(&top_window) = env
@@ -283,20 +361,18 @@ fn handle_touches_down(env: &mut Environment, map: HashMap<FingerId, Coords>) {
         }
     }
 
-    for (view, touches) in view_touches {
-        log_dbg!(
-            "Sending [{:?} touchesBegan:{:?} withEvent:{:?}]",
-            view,
-            touches,
-            event
-        );
-        let _: () = msg![env; view touchesBegan:touches withEvent:event];
-    }
+    send_event(env, event, view_touches);
 
     release(env, pool);
 }
 
-fn handle_touches_move(env: &mut Environment, map: HashMap<FingerId, Coords>) {
+fn handle_touches_move(env: &mut Environment, mut map: HashMap<FingerId, Coords>) {
+    let status_bar_touches = &env.framework_state.uikit.ui_touch.status_bar_touches;
+    map.retain(|finger_id, _| !status_bar_touches.contains(finger_id));
+    if map.is_empty() {
+        return;
+    }
+
     let pool: id = msg_class![env; NSAutoreleasePool new];
 
     let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
@@ -349,22 +425,37 @@ fn handle_touches_move(env: &mut Environment, map: HashMap<FingerId, Coords>) {
     let event = ui_event::new_event(env, touches);
     autorelease(env, event);
 
-    for (view, touches) in view_touches {
-        log_dbg!(
-            "Sending [{:?} touchesMoved:{:?} withEvent:{:?}]",
-            view,
-            touches,
-            event
-        );
-        let _: () = msg![env; view touchesMoved:touches withEvent:event];
-    }
+    send_event(env, event, view_touches);
 
     release(env, pool);
 }
 
-fn handle_touches_up(env: &mut Environment, map: HashMap<FingerId, Coords>) {
+fn handle_touches_up(env: &mut Environment, mut map: HashMap<FingerId, Coords>) {
     let pool: id = msg_class![env; NSAutoreleasePool new];
 
+    let status_bar_touches = &mut env.framework_state.uikit.ui_touch.status_bar_touches;
+    let len_before = map.len();
+    map.retain(|finger_id, _| !status_bar_touches.remove(finger_id));
+    if map.len() != len_before {
+        // Tapping the status bar scrolls the top window's scroll view (if any)
+        // to the top.
+        // TODO: this should use the key window.
+        if let Some(&top_window) = env
+            .framework_state
+            .uikit
+            .ui_view
+            .ui_window
+            .visible_windows
+            .last()
+        {
+            ui_scroll_view::scroll_to_top(env, top_window);
+        }
+    }
+    if map.is_empty() {
+        release(env, pool);
+        return;
+    }
+
     let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
 
     let touches: id = msg_class![env; NSMutableSet allocWithZone:(MutVoidPtr::null())];
@@ -423,15 +514,7 @@ fn handle_touches_up(env: &mut Environment, map: HashMap<FingerId, Coords>) {
     let event = ui_event::new_event(env, touches);
     autorelease(env, event);
 
-    for (view, touches) in view_touches {
-        log_dbg!(
-            "Sending [{:?} touchesEnded:{:?} withEvent:{:?}]",
-            view,
-            touches,
-            event
-        );
-        let _: () = msg![env; view touchesEnded:touches withEvent:event];
-    }
+    send_event(env, event, view_touches);
 
     release(env, pool);
 }
@@ -12,9 +12,13 @@ pub mod ui_alert_view;
 pub mod ui_control;
 pub mod ui_image_view;
 pub mod ui_label;
+pub mod ui_scroll_view;
 pub mod ui_window;
 
+use super::ui_accessibility::{UIAccessibilityTraitNone, UIAccessibilityTraits};
 use super::ui_graphics::{UIGraphicsPopContext, UIGraphicsPushContext};
+use super::ui_view_controller::view_controller_for_view;
+use crate::frameworks::core_animation::ca_layer::ContentsGravity;
 use crate::frameworks::core_graphics::cg_affine_transform::CGAffineTransform;
 use crate::frameworks::core_graphics::cg_context::{CGContextClearRect, CGContextRef};
 use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect};
@@ -33,6 +37,41 @@ pub struct State {
     pub ui_window: ui_window::State,
 }
 
+pub type UIViewContentMode = NSInteger;
+pub const UIViewContentModeScaleToFill: UIViewContentMode = 0;
+pub const UIViewContentModeScaleAspectFit: UIViewContentMode = 1;
+pub const UIViewContentModeScaleAspectFill: UIViewContentMode = 2;
+pub const UIViewContentModeRedraw: UIViewContentMode = 3;
+pub const UIViewContentModeCenter: UIViewContentMode = 4;
+pub const UIViewContentModeTop: UIViewContentMode = 5;
+pub const UIViewContentModeBottom: UIViewContentMode = 6;
+pub const UIViewContentModeLeft: UIViewContentMode = 7;
+pub const UIViewContentModeRight: UIViewContentMode = 8;
+pub const UIViewContentModeTopLeft: UIViewContentMode = 9;
+pub const UIViewContentModeTopRight: UIViewContentMode = 10;
+pub const UIViewContentModeBottomLeft: UIViewContentMode = 11;
+pub const UIViewContentModeBottomRight: UIViewContentMode = 12;
+
+/// Get the layer `contentsGravity` for a view content mode. Core Animation's
+/// "top" and "bottom" are upside-down compared to UIKit's.
+fn contents_gravity_for_content_mode(content_mode: UIViewContentMode) -> Option<ContentsGravity> {
+    Some(match content_mode {
+        UIViewContentModeScaleToFill | UIViewContentModeRedraw => ContentsGravity::Resize,
+        UIViewContentModeScaleAspectFit => ContentsGravity::ResizeAspect,
+        UIViewContentModeScaleAspectFill => ContentsGravity::ResizeAspectFill,
+        UIViewContentModeCenter => ContentsGravity::Center,
+        UIViewContentModeTop => ContentsGravity::Bottom,
+        UIViewContentModeBottom => ContentsGravity::Top,
+        UIViewContentModeLeft => ContentsGravity::Left,
+        UIViewContentModeRight => ContentsGravity::Right,
+        UIViewContentModeTopLeft => ContentsGravity::BottomLeft,
+        UIViewContentModeTopRight => ContentsGravity::BottomRight,
+        UIViewContentModeBottomLeft => ContentsGravity::TopLeft,
+        UIViewContentModeBottomRight => ContentsGravity::TopRight,
+        _ => return None,
+    })
+}
+
 pub(super) struct UIViewHostObject {
     /// CALayer or subclass.
     layer: id,
@@ -43,6 +82,15 @@ pub(super) struct UIViewHostObject {
     clears_context_before_drawing: bool,
     user_interaction_enabled: bool,
     multiple_touch_enabled: bool,
+    content_mode: UIViewContentMode,
+    is_accessibility_element: bool,
+    /// `NSString*`
+    accessibility_label: id,
+    /// `NSString*`
+    accessibility_hint: id,
+    /// `NSString*`
+    accessibility_value: id,
+    accessibility_traits: UIAccessibilityTraits,
 }
 impl HostObject for UIViewHostObject {}
 impl Default for UIViewHostObject {
@@ -56,6 +104,12 @@ impl Default for UIViewHostObject {
             clears_context_before_drawing: true,
             user_interaction_enabled: true,
             multiple_touch_enabled: false,
+            content_mode: UIViewContentModeScaleToFill,
+            is_accessibility_element: false,
+            accessibility_label: nil,
+            accessibility_hint: nil,
+            accessibility_value: nil,
+            accessibility_traits: UIAccessibilityTraitNone,
         }
     }
 }
@@ -68,6 +122,15 @@ impl Default for UIViewHostObject {
 fn init_common(env: &mut Environment, this: id) -> id {
     let view_class: Class = msg![env; this class];
     let layer_class: Class = msg![env; view_class layerClass];
+    // Apps commonly override layerClass (e.g. for an EAGLView), and might
+    // return their own subclass of a layer class.
+    let ca_layer_class = env.objc.get_known_class("CALayer", &mut env.mem);
+    assert!(
+        layer_class != nil && env.objc.class_is_subclass_of(layer_class, ca_layer_class),
+        "+[{} layerClass] returned {:?}, which is not a subclass of CALayer",
+        env.objc.get_class_name(view_class),
+        layer_class,
+    );
     let layer: id = msg![env; layer_class layer];
 
     // CALayer is not opaque by default, but UIView is
@@ -140,6 +203,9 @@ pub const CLASSES: ClassExports = objc_classes! {
     let key_ns_string = get_static_str(env, "UIOpaque");
     let opaque: bool = msg![env; coder decodeBoolForKey:key_ns_string];
 
+    let key_ns_string = get_static_str(env, "UIContentMode");
+    let content_mode: UIViewContentMode = msg![env; coder decodeIntegerForKey:key_ns_string];
+
     let key_ns_string = get_static_str(env, "UISubviews");
     let subviews: id = msg![env; coder decodeObjectForKey:key_ns_string];
     let subview_count: NSUInteger = msg![env; subviews count];
@@ -159,6 +225,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     () = msg![env; this setCenter:center];
     () = msg![env; this setHidden:hidden];
     () = msg![env; this setOpaque:opaque];
+    () = msg![env; this setContentMode:content_mode];
 
     for i in 0..subview_count {
         let subview: id = msg![env; subviews objectAtIndex:i];
@@ -182,6 +249,48 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow_mut::<UIViewHostObject>(this).multiple_touch_enabled = enabled;
 }
 
+// UIAccessibility informal protocol. There's no screen reader, so these are
+// only stored (see super::ui_accessibility).
+- (bool)isAccessibilityElement {
+    env.objc.borrow::<UIViewHostObject>(this).is_accessibility_element
+}
+- (())setIsAccessibilityElement:(bool)is_element {
+    env.objc.borrow_mut::<UIViewHostObject>(this).is_accessibility_element = is_element;
+}
+- (id)accessibilityLabel {
+    env.objc.borrow::<UIViewHostObject>(this).accessibility_label
+}
+- (())setAccessibilityLabel:(id)label { // NSString*
+    let label: id = msg![env; label copy];
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    let old = std::mem::replace(&mut host_object.accessibility_label, label);
+    release(env, old);
+}
+- (id)accessibilityHint {
+    env.objc.borrow::<UIViewHostObject>(this).accessibility_hint
+}
+- (())setAccessibilityHint:(id)hint { // NSString*
+    let hint: id = msg![env; hint copy];
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    let old = std::mem::replace(&mut host_object.accessibility_hint, hint);
+    release(env, old);
+}
+- (id)accessibilityValue {
+    env.objc.borrow::<UIViewHostObject>(this).accessibility_value
+}
+- (())setAccessibilityValue:(id)value { // NSString*
+    let value: id = msg![env; value copy];
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    let old = std::mem::replace(&mut host_object.accessibility_value, value);
+    release(env, old);
+}
+- (UIAccessibilityTraits)accessibilityTraits {
+    env.objc.borrow::<UIViewHostObject>(this).accessibility_traits
+}
+- (())setAccessibilityTraits:(UIAccessibilityTraits)traits {
+    env.objc.borrow_mut::<UIViewHostObject>(this).accessibility_traits = traits;
+}
+
 - (())layoutSubviews {
     // On iOS 5.1 and earlier, the default implementation of this method does
     // nothing.
@@ -191,6 +300,14 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<UIViewHostObject>(this).superview
 }
 
+- (id)nextResponder {
+    // A view controller's root view is followed by the controller.
+    if let Some(view_controller) = view_controller_for_view(env, this) {
+        return view_controller;
+    }
+    env.objc.borrow::<UIViewHostObject>(this).superview
+}
+
 - (id)subviews {
     let views = env.objc.borrow::<UIViewHostObject>(this).subviews.clone();
     for view in &views {
@@ -274,9 +391,18 @@ pub const CLASSES: ClassExports = objc_classes! {
         clears_context_before_drawing: _,
         user_interaction_enabled: _,
         multiple_touch_enabled: _,
+        content_mode: _,
+        is_accessibility_element: _,
+        accessibility_label,
+        accessibility_hint,
+        accessibility_value,
+        accessibility_traits: _,
     } = std::mem::take(env.objc.borrow_mut(this));
 
     release(env, layer);
+    release(env, accessibility_label);
+    release(env, accessibility_hint);
+    release(env, accessibility_value);
     assert!(superview == nil);
     for subview in subviews {
         env.objc.borrow_mut::<UIViewHostObject>(subview).superview = nil;
@@ -370,8 +496,20 @@ pub const CLASSES: ClassExports = objc_classes! {
     log!("TODO: [{:?} setTransform:{:?}]", this, transform);
 }
 
-- (())setContentMode:(NSInteger)content_mode { // should be UIViewContentMode
-    log!("TODO: [UIView {:?} setContentMode:{:?}] => ()", this, content_mode);
+- (UIViewContentMode)contentMode {
+    env.objc.borrow::<UIViewHostObject>(this).content_mode
+}
+- (())setContentMode:(UIViewContentMode)content_mode {
+    let Some(gravity) = contents_gravity_for_content_mode(content_mode) else {
+        log!("Warning: ignoring unknown content mode {} for {:?}", content_mode, this);
+        return;
+    };
+    // TODO: UIViewContentModeRedraw should redraw the view when its bounds
+    // change.
+    env.objc.borrow_mut::<UIViewHostObject>(this).content_mode = content_mode;
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let gravity = get_static_str(env, gravity.name());
+    () = msg![env; layer setContentsGravity:gravity];
 }
 
 - (bool)clearsContextBeforeDrawing {
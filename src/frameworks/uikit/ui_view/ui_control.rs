@@ -12,10 +12,10 @@ pub mod ui_button;
 pub mod ui_text_field;
 
 use crate::frameworks::core_graphics::CGPoint;
-use crate::frameworks::foundation::NSUInteger;
+use crate::frameworks::foundation::{ns_array, ns_string, NSUInteger};
 use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_send, msg_super, nil, objc_classes, release,
-    retain, ClassExports, NSZonePtr, SEL,
+    autorelease, id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes,
+    release, retain, ClassExports, NSZonePtr, SEL,
 };
 use crate::Environment;
 
@@ -38,7 +38,8 @@ struct UIControlHostObject {
     tracked_touch: id,
     tracking: bool,
     /// See `addTarget:action:forControlEvents:`. The target is a weak
-    /// reference!
+    /// reference! It may be [nil], meaning the responder chain is searched.
+    /// Each target-action pair only appears once.
     action_targets: Vec<(id, SEL, UIControlEvents)>,
 }
 impl_HostObject_with_superclass!(UIControlHostObject);
@@ -80,8 +81,6 @@ fn send_actions(env: &mut Environment, this: id, event: id, control_event: UICon
         .collect();
 
     for (target, action) in action_targets {
-        assert!(target != nil); // TODO
-
         () = msg![env; this sendAction:action to:target forEvent:event];
     }
 }
@@ -221,6 +220,8 @@ pub const CLASSES: ClassExports = objc_classes! {
         env.objc.borrow_mut::<UIControlHostObject>(this).tracked_touch = nil;
         env.objc.borrow_mut::<UIControlHostObject>(this).tracking = false;
         () = msg![env; this setHighlighted:false];
+        // The touch is no longer being tracked, so no drag events.
+        return;
     }
 
     let old_pos: CGPoint = msg![env; touch previousLocationInView:this];
@@ -261,17 +262,6 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (())addTarget:(id)target
          action:(SEL)action
 forControlEvents:(UIControlEvents)events {
-    if target == nil {
-        // TODO: when the target is nil, the responder chain is searched for
-        // a suitable target
-        log!(
-            "TODO: [{:?} addTarget:nil action:{:?} forControlEvents:{:?}] (ignored)",
-            target,
-            action,
-            events,
-        );
-        return;
-    }
     // The target is a *weak* reference!
 
     // The selector must be for a method with zero to two arguments
@@ -279,52 +269,99 @@ forControlEvents:(UIControlEvents)events {
     let colon_count = sel_str.bytes().filter(|&b| b == b':').count();
     assert!([0, 1, 2].contains(&colon_count));
 
-    env.objc.borrow_mut::<UIControlHostObject>(this).action_targets.push((target, action, events));
+    let action_targets = &mut env.objc.borrow_mut::<UIControlHostObject>(this).action_targets;
+    // Adding the same target-action pair again adds to its events.
+    if let Some(existing) = action_targets
+        .iter_mut()
+        .find(|&&mut (t, a, _)| t == target && a == action)
+    {
+        existing.2 |= events;
+    } else {
+        action_targets.push((target, action, events));
+    }
+}
+
+- (())removeTarget:(id)target
+            action:(SEL)action
+  forControlEvents:(UIControlEvents)events {
+    // A nil target matches all targets and a NULL action matches all actions.
+    let action_targets = &mut env.objc.borrow_mut::<UIControlHostObject>(this).action_targets;
+    for (t, a, for_control_events) in action_targets.iter_mut() {
+        if (target == nil || *t == target) && (action.is_null() || *a == action) {
+            *for_control_events &= !events;
+        }
+    }
+    action_targets.retain(|&(_, _, for_control_events)| for_control_events != 0);
+}
+
+- (id)allTargets {
+    let targets: Vec<id> = env
+        .objc
+        .borrow::<UIControlHostObject>(this)
+        .action_targets
+        .iter()
+        .map(|&(target, _, _)| target)
+        .collect();
+    let set: id = msg_class![env; NSMutableSet new];
+    for target in targets {
+        // NSNull stands in for the responder chain.
+        let target = if target == nil {
+            msg_class![env; NSNull null]
+        } else {
+            target
+        };
+        () = msg![env; set addObject:target];
+    }
+    autorelease(env, set)
 }
 
+- (UIControlEvents)allControlEvents {
+    env.objc
+        .borrow::<UIControlHostObject>(this)
+        .action_targets
+        .iter()
+        .fold(0, |events, &(_, _, for_control_events)| events | for_control_events)
+}
+
+- (id)actionsForTarget:(id)target
+       forControlEvent:(UIControlEvents)event { // NSArray* of NSString*
+    let actions: Vec<SEL> = env
+        .objc
+        .borrow::<UIControlHostObject>(this)
+        .action_targets
+        .iter()
+        .filter(|&&(t, _, for_control_events)| t == target && (for_control_events & event) != 0)
+        .map(|&(_, action, _)| action)
+        .collect();
+    if actions.is_empty() {
+        return nil;
+    }
+    let actions = actions
+        .into_iter()
+        .map(|action| {
+            let name = action.as_str(&env.mem).to_string();
+            ns_string::from_rust_string(env, name)
+        })
+        .collect();
+    let array = ns_array::from_vec(env, actions);
+    autorelease(env, array)
+}
+
+- (())sendActionsForControlEvents:(UIControlEvents)events {
+    send_actions(env, this, nil, events);
+}
+
+// Subclasses can override this to intercept actions. The default
+// implementation sends them via the UIApplication, which can also be
+// overridden.
 - (())sendAction:(SEL)action
               to:(id)target
         forEvent:(id)event { // UIEvent*
-    assert!(target != nil); // TODO
-
-    let sel_str = action.as_str(&env.mem);
-    let colon_count = sel_str.bytes().filter(|&b| b == b':').count();
-    match colon_count {
-        // - (IBAction)action;
-        0 => {
-            log_dbg!(
-                "Sending {:?} ({:?}) message to {:?} (no args)",
-                action,
-                sel_str,
-                target
-            );
-            () = msg_send(env, (target, action));
-        }
-        // - (IBAction)action:(id)sender;
-        1 => {
-            log_dbg!(
-                "Sending {:?} ({:?}) message to {:?} (one arg: {:?})",
-                action,
-                sel_str,
-                target,
-                this
-            );
-            () = msg_send(env, (target, action, this));
-        }
-        // - (IBAction)action:(id)sender forEvent:(UIEvent*)event;
-        2 => {
-            log_dbg!(
-                "Sending {:?} ({:?}) message to {:?} (two args: {:?}, {:?})",
-                action,
-                sel_str,
-                target,
-                this,
-                event
-            );
-            () = msg_send(env, (target, action, this, event));
-        }
-        _ => panic!(),
-    };
+    let ui_application: id = msg_class![env; UIApplication sharedApplication];
+    let _: bool = msg![env; ui_application sendAction:action
+                                                  to:target
+                                                from:this
+                                            forEvent:event];
 }
 
 // TODO: more triggers/targets/actions stuff
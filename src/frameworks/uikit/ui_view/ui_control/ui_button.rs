@@ -5,10 +5,17 @@
  */
 //! `UIButton`.
 
-use super::{UIControlState, UIControlStateNormal};
-use crate::frameworks::core_graphics::{CGPoint, CGRect};
+use super::{
+    UIControlState, UIControlStateDisabled, UIControlStateHighlighted, UIControlStateNormal,
+};
+use crate::frameworks::core_graphics::cg_context::{
+    CGContextFillRect, CGContextRef, CGContextSetRGBFillColor,
+};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::frameworks::foundation::NSInteger;
 use crate::frameworks::uikit::ui_font::UITextAlignmentCenter;
+use crate::frameworks::uikit::ui_geometry::{inset_rect, UIEdgeInsets};
+use crate::frameworks::uikit::ui_graphics::UIGraphicsGetCurrentContext;
 use crate::objc::{
     autorelease, id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes,
     release, retain, ClassExports, NSZonePtr,
@@ -28,6 +35,9 @@ const UIButtonTypeInfoDark: UIButtonType = 4;
 #[allow(dead_code)]
 const UIButtonTypeContactAdd: UIButtonType = 5;
 
+/// Corner radius of `UIButtonTypeRoundedRect` buttons.
+const ROUNDED_RECT_CORNER_RADIUS: CGFloat = 9.0;
+
 pub struct UIButtonHostObject {
     superclass: super::UIControlHostObject,
     type_: UIButtonType,
@@ -37,6 +47,9 @@ pub struct UIButtonHostObject {
     image_view: id,
     /// `UIImageView*`
     background_image_view: id,
+    /// `_touchHLE_UIButtonRoundedRectBackground*`, only for
+    /// [UIButtonTypeRoundedRect] buttons.
+    rounded_rect_background: id,
     /// Values are `UIString*`
     titles_for_states: HashMap<UIControlState, id>,
     /// Values are `UIColor*`
@@ -45,6 +58,11 @@ pub struct UIButtonHostObject {
     images_for_states: HashMap<UIControlState, id>,
     /// Values are `UIImage*`
     background_images_for_states: HashMap<UIControlState, id>,
+    content_edge_insets: UIEdgeInsets,
+    title_edge_insets: UIEdgeInsets,
+    image_edge_insets: UIEdgeInsets,
+    adjusts_image_when_highlighted: bool,
+    adjusts_image_when_disabled: bool,
 }
 impl_HostObject_with_superclass!(UIButtonHostObject);
 impl Default for UIButtonHostObject {
@@ -55,14 +73,51 @@ impl Default for UIButtonHostObject {
             title_label: nil,
             image_view: nil,
             background_image_view: nil,
+            rounded_rect_background: nil,
             titles_for_states: HashMap::new(),
             title_colors_for_states: HashMap::new(),
             images_for_states: HashMap::new(),
             background_images_for_states: HashMap::new(),
+            content_edge_insets: Default::default(),
+            title_edge_insets: Default::default(),
+            image_edge_insets: Default::default(),
+            adjusts_image_when_highlighted: true,
+            adjusts_image_when_disabled: true,
         }
     }
 }
 
+/// Look up the value for a state. Values that weren't set for a state (or were
+/// set to [nil]) fall back to the value for [UIControlStateNormal].
+fn value_for_state(values: &HashMap<UIControlState, id>, state: UIControlState) -> id {
+    values
+        .get(&state)
+        .copied()
+        .filter(|&value| value != nil)
+        .or_else(|| values.get(&UIControlStateNormal).copied())
+        .unwrap_or(nil)
+}
+
+/// Check whether a value was specifically set for a state.
+fn has_value_for_state(values: &HashMap<UIControlState, id>, state: UIControlState) -> bool {
+    values.get(&state).is_some_and(|&value| value != nil)
+}
+
+fn set_value_for_state(
+    env: &mut Environment,
+    this: id,
+    get_values: fn(&mut UIButtonHostObject) -> &mut HashMap<UIControlState, id>,
+    value: id,
+    state: UIControlState,
+) {
+    retain(env, value);
+    let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(this);
+    if let Some(old) = get_values(host_obj).insert(state, value) {
+        release(env, old);
+    }
+    update(env, this);
+}
+
 fn update(env: &mut Environment, this: id) {
     let title_label: id = msg![env; this titleLabel];
     let title: id = msg![env; this currentTitle];
@@ -77,6 +132,79 @@ fn update(env: &mut Environment, this: id) {
     let background_image_view: id = msg![env; this backgroundImageView];
     let background_image: id = msg![env; this currentBackgroundImage];
     () = msg![env; background_image_view setImage:background_image];
+
+    // Images are dimmed when highlighted or disabled, unless the app provided
+    // images for that state. The real UIKit darkens the image. Making it
+    // semi-transparent is similar when the background is dark, which it
+    // usually is in games.
+    let state: UIControlState = msg![env; this state];
+    let host_obj = env.objc.borrow::<UIButtonHostObject>(this);
+    let dim_for = |dimmed_state: UIControlState, adjusts: bool| {
+        adjusts
+            && (state & dimmed_state) != 0
+            && !has_value_for_state(&host_obj.images_for_states, dimmed_state)
+            && !has_value_for_state(&host_obj.background_images_for_states, dimmed_state)
+    };
+    let dimmed = dim_for(
+        UIControlStateHighlighted,
+        host_obj.adjusts_image_when_highlighted,
+    ) || dim_for(UIControlStateDisabled, host_obj.adjusts_image_when_disabled);
+    let alpha: CGFloat = if dimmed { 0.5 } else { 1.0 };
+    () = msg![env; image_view setAlpha:alpha];
+    () = msg![env; background_image_view setAlpha:alpha];
+
+    // The title and image sizes may have changed.
+    () = msg![env; this layoutSubviews];
+    // The rounded rect background depends on the state.
+    let rounded_rect_background = env
+        .objc
+        .borrow::<UIButtonHostObject>(this)
+        .rounded_rect_background;
+    if rounded_rect_background != nil {
+        () = msg![env; rounded_rect_background setNeedsDisplay];
+    }
+}
+
+/// Fill a rectangle with rounded corners. Core Graphics paths aren't supported
+/// yet, so this is done one row at a time.
+fn fill_rounded_rect(env: &mut Environment, context: CGContextRef, rect: CGRect, radius: CGFloat) {
+    let radius = radius
+        .min(rect.size.width / 2.0)
+        .min(rect.size.height / 2.0)
+        .max(0.0);
+    let rows = radius.ceil() as u32;
+    for row in 0..rows {
+        let row = row as CGFloat;
+        let height = (radius - row).min(1.0);
+        // Distance from the corner's center to the middle of this row.
+        let dy = radius - (row + height / 2.0);
+        let dx = radius - (radius * radius - dy * dy).max(0.0).sqrt();
+        let width = rect.size.width - 2.0 * dx;
+        for y in [
+            rect.origin.y + row,
+            rect.origin.y + rect.size.height - row - height,
+        ] {
+            let row_rect = CGRect {
+                origin: CGPoint {
+                    x: rect.origin.x + dx,
+                    y,
+                },
+                size: CGSize { width, height },
+            };
+            CGContextFillRect(env, context, row_rect);
+        }
+    }
+    let middle = CGRect {
+        origin: CGPoint {
+            x: rect.origin.x,
+            y: rect.origin.y + radius,
+        },
+        size: CGSize {
+            width: rect.size.width,
+            height: rect.size.height - 2.0 * radius,
+        },
+    };
+    CGContextFillRect(env, context, middle);
 }
 
 pub const CLASSES: ClassExports = objc_classes! {
@@ -95,19 +223,43 @@ pub const CLASSES: ClassExports = objc_classes! {
     match type_ {
         UIButtonTypeCustom => (),
         UIButtonTypeRoundedRect => {
-            let bg_color: id = msg_class![env; UIColor whiteColor];
-            // TODO: set blue background image in highlighted state
-            // TODO: image highlighting?
-            () = msg![env; button setBackgroundColor:bg_color];
-            // On the real iPhone OS, this is a semi-dark, desaturated blue.
-            // Should we match it?
-            let text_color: id = msg_class![env; UIColor blackColor];
+            // The white background with a grey border and rounded corners is
+            // drawn by a separate view behind the others, so that UIButton
+            // itself doesn't need to override drawRect:. It turns blue when
+            // highlighted.
+            let background: id = msg_class![env; _touchHLE_UIButtonRoundedRectBackground new];
+            () = msg![env; background setOpaque:false];
+            let bg_color: id = msg_class![env; UIColor clearColor];
+            () = msg![env; background setBackgroundColor:bg_color];
+            () = msg![env; button addSubview:background];
+            let &UIButtonHostObject {
+                title_label,
+                image_view,
+                background_image_view,
+                ..
+            } = env.objc.borrow(button);
+            for subview in [background_image_view, title_label, image_view] {
+                () = msg![env; button bringSubviewToFront:subview];
+            }
+            let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(button);
+            host_obj.type_ = type_;
+            host_obj.rounded_rect_background = background;
+            () = msg![env; button layoutSubviews];
+
+            // This is the semi-dark, desaturated blue of the real iPhone OS.
+            let text_color: id = msg_class![env; UIColor colorWithRed:(0.196 as CGFloat)
+                                                                green:(0.310 as CGFloat)
+                                                                 blue:(0.522 as CGFloat)
+                                                                alpha:(1.0 as CGFloat)];
             () = msg![env; button setTitleColor:text_color
                                        forState:UIControlStateNormal];
-            // TODO: set border and corner rounding, once supported
+            let highlighted_text_color: id = msg_class![env; UIColor whiteColor];
+            () = msg![env; button setTitleColor:highlighted_text_color
+                                       forState:UIControlStateHighlighted];
         },
         _ => {
             log!("TODO: UIButtonType {}", type_);
+            env.objc.borrow_mut::<UIButtonHostObject>(button).type_ = type_;
         }
     }
     autorelease(env, button)
@@ -125,6 +277,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     () = msg![env; title_label setTextAlignment:UITextAlignmentCenter];
 
     let text_color: id = msg_class![env; UIColor whiteColor];
+    retain(env, text_color);
 
     let image_view: id = msg_class![env; UIImageView new];
     let background_image_view: id = msg_class![env; UIImageView new];
@@ -155,15 +308,22 @@ pub const CLASSES: ClassExports = objc_classes! {
         title_label,
         image_view,
         background_image_view,
+        rounded_rect_background,
         titles_for_states,
         title_colors_for_states,
         images_for_states,
-        background_images_for_states
+        background_images_for_states,
+        content_edge_insets: _,
+        title_edge_insets: _,
+        image_edge_insets: _,
+        adjusts_image_when_highlighted: _,
+        adjusts_image_when_disabled: _,
     } = std::mem::take(env.objc.borrow_mut(this));
 
     release(env, title_label);
     release(env, image_view);
     release(env, background_image_view);
+    release(env, rounded_rect_background);
     for (_state, title) in titles_for_states {
         release(env, title);
     }
@@ -179,15 +339,92 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg_super![env; this dealloc]
 }
 
+- (())setFrame:(CGRect)frame {
+    () = msg_super![env; this setFrame:frame];
+    () = msg![env; this layoutSubviews];
+}
+- (())setBounds:(CGRect)bounds {
+    () = msg_super![env; this setBounds:bounds];
+    () = msg![env; this layoutSubviews];
+}
+
 - (())layoutSubviews {
-    let label = env.objc.borrow_mut::<UIButtonHostObject>(this).title_label;
-    let background_image_view = env.objc.borrow_mut::<UIButtonHostObject>(this).background_image_view;
+    let &UIButtonHostObject {
+        title_label,
+        image_view,
+        background_image_view,
+        rounded_rect_background,
+        content_edge_insets,
+        title_edge_insets,
+        image_edge_insets,
+        ..
+    } = env.objc.borrow(this);
+    if title_label == nil {
+        // Not initialized yet.
+        return;
+    }
     let bounds: CGRect = msg![env; this bounds];
 
+    // The background image is stretched to fill the button.
     () = msg![env; background_image_view setFrame:bounds];
-    () = msg![env; label setFrame:bounds];
-    // TODO: layout for image
+    if rounded_rect_background != nil {
+        let old_bounds: CGRect = msg![env; rounded_rect_background bounds];
+        () = msg![env; rounded_rect_background setFrame:bounds];
+        // The corners would be distorted if it were stretched.
+        if old_bounds.size != bounds.size {
+            () = msg![env; rounded_rect_background setNeedsDisplay];
+        }
+    }
 
+    // The image and title are placed side by side, and centered together
+    // within the content area.
+    let content = inset_rect(bounds, content_edge_insets);
+
+    let image: id = msg![env; this currentImage];
+    let image_size = if image != nil {
+        let size: CGSize = msg![env; image size];
+        // Images that are too large are shrunk to fit.
+        CGSize {
+            width: size.width.min(content.size.width).max(0.0),
+            height: size.height.min(content.size.height).max(0.0),
+        }
+    } else {
+        CGSize::default()
+    };
+
+    let title: id = msg![env; this currentTitle];
+    let font: id = msg![env; title_label font];
+    let max_title_width = (content.size.width - image_size.width).max(0.0);
+    let title_width = if title == nil {
+        0.0
+    } else if image == nil || font == nil {
+        max_title_width
+    } else {
+        let size: CGSize = msg![env; title sizeWithFont:font];
+        size.width.min(max_title_width)
+    };
+
+    let x = content.origin.x + (content.size.width - image_size.width - title_width) / 2.0;
+    let image_frame = CGRect {
+        origin: CGPoint {
+            x,
+            y: content.origin.y + (content.size.height - image_size.height) / 2.0,
+        },
+        size: image_size,
+    };
+    // UILabel centers the text vertically itself.
+    let title_frame = CGRect {
+        origin: CGPoint {
+            x: x + image_size.width,
+            y: content.origin.y,
+        },
+        size: CGSize {
+            width: title_width,
+            height: content.size.height,
+        },
+    };
+    () = msg![env; image_view setFrame:(inset_rect(image_frame, image_edge_insets))];
+    () = msg![env; title_label setFrame:(inset_rect(title_frame, title_edge_insets))];
 }
 
 - (UIButtonType)buttonType {
@@ -219,24 +456,57 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 // TODO: observe focussing somehow
 
+- (UIEdgeInsets)contentEdgeInsets {
+    env.objc.borrow::<UIButtonHostObject>(this).content_edge_insets
+}
+- (())setContentEdgeInsets:(UIEdgeInsets)insets {
+    env.objc.borrow_mut::<UIButtonHostObject>(this).content_edge_insets = insets;
+    () = msg![env; this layoutSubviews];
+}
+- (UIEdgeInsets)titleEdgeInsets {
+    env.objc.borrow::<UIButtonHostObject>(this).title_edge_insets
+}
+- (())setTitleEdgeInsets:(UIEdgeInsets)insets {
+    env.objc.borrow_mut::<UIButtonHostObject>(this).title_edge_insets = insets;
+    () = msg![env; this layoutSubviews];
+}
+- (UIEdgeInsets)imageEdgeInsets {
+    env.objc.borrow::<UIButtonHostObject>(this).image_edge_insets
+}
+- (())setImageEdgeInsets:(UIEdgeInsets)insets {
+    env.objc.borrow_mut::<UIButtonHostObject>(this).image_edge_insets = insets;
+    () = msg![env; this layoutSubviews];
+}
+
+- (bool)adjustsImageWhenHighlighted {
+    env.objc.borrow::<UIButtonHostObject>(this).adjusts_image_when_highlighted
+}
+- (())setAdjustsImageWhenHighlighted:(bool)adjusts {
+    env.objc.borrow_mut::<UIButtonHostObject>(this).adjusts_image_when_highlighted = adjusts;
+    update(env, this);
+}
+- (bool)adjustsImageWhenDisabled {
+    env.objc.borrow::<UIButtonHostObject>(this).adjusts_image_when_disabled
+}
+- (())setAdjustsImageWhenDisabled:(bool)adjusts {
+    env.objc.borrow_mut::<UIButtonHostObject>(this).adjusts_image_when_disabled = adjusts;
+    update(env, this);
+}
+
 - (id)currentTitle {
     let state: UIControlState = msg![env; this state];
     msg![env; this titleForState:state]
 }
 - (id)titleForState:(UIControlState)state {
     let host_obj = env.objc.borrow::<UIButtonHostObject>(this);
-    host_obj.titles_for_states.get(&state).or_else(|| {
-        host_obj.titles_for_states.get(&UIControlStateNormal)
-    }).copied().unwrap()
+    value_for_state(&host_obj.titles_for_states, state)
 }
 - (())setTitle:(id)title // NSString*
       forState:(UIControlState)state {
-    retain(env, title);
-    let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(this);
-    if let Some(old) = host_obj.titles_for_states.insert(state, title) {
-        release(env, old);
-    }
-    update(env, this);
+    // The title is copied, since it might be mutable.
+    let title: id = msg![env; title copy];
+    set_value_for_state(env, this, |h| &mut h.titles_for_states, title, state);
+    release(env, title);
 }
 
 - (id)currentBackgroundImage {
@@ -245,17 +515,10 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 - (id)backgroundImageForState:(UIControlState)state {
     let host_obj = env.objc.borrow::<UIButtonHostObject>(this);
-    host_obj.background_images_for_states.get(&state).or_else(|| {
-        host_obj.background_images_for_states.get(&UIControlStateNormal)
-    }).copied().unwrap()
+    value_for_state(&host_obj.background_images_for_states, state)
 }
 - (())setBackgroundImage:(id)image forState:(UIControlState)state {
-    retain(env,image);
-    let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(this);
-    if let Some(old) = host_obj.background_images_for_states.insert(state, image) {
-        release(env, old);
-    }
-    update(env, this);
+    set_value_for_state(env, this, |h| &mut h.background_images_for_states, image, state);
 }
 
 - (id)currentTitleColor {
@@ -264,18 +527,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 - (id)titleColorForState:(UIControlState)state {
     let host_obj = env.objc.borrow::<UIButtonHostObject>(this);
-    host_obj.title_colors_for_states.get(&state).or_else(|| {
-        host_obj.title_colors_for_states.get(&UIControlStateNormal)
-    }).copied().unwrap()
+    value_for_state(&host_obj.title_colors_for_states, state)
 }
 - (())setTitleColor:(id)color // UIColor*
       forState:(UIControlState)state {
-    retain(env, color);
-    let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(this);
-    if let Some(old) = host_obj.title_colors_for_states.insert(state, color) {
-        release(env, old);
-    }
-    update(env, this);
+    set_value_for_state(env, this, |h| &mut h.title_colors_for_states, color, state);
 }
 
 - (id)currentImage {
@@ -284,22 +540,13 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 - (id)imageForState:(UIControlState)state {
     let host_obj = env.objc.borrow::<UIButtonHostObject>(this);
-    host_obj.images_for_states.get(&state).or_else(|| {
-        host_obj.images_for_states.get(&UIControlStateNormal)
-    }).copied().unwrap()
+    value_for_state(&host_obj.images_for_states, state)
 }
 - (())setImage:(id)image // UIImage*
       forState:(UIControlState)state {
-    retain(env, image);
-    let host_obj = env.objc.borrow_mut::<UIButtonHostObject>(this);
-    if let Some(old) = host_obj.images_for_states.insert(state, image) {
-        release(env, old);
-    }
-    update(env, this);
+    set_value_for_state(env, this, |h| &mut h.images_for_states, image, state);
 }
 
-// TODO: actions, etc
-
 - (id)hitTest:(CGPoint)point
     withEvent:(id)event { // UIEvent* (possibly nil)
     // Hide subviews from hit testing so event goes straight to this control
@@ -312,4 +559,34 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @end
 
+// Background of UIButtonTypeRoundedRect buttons. Its superview is the button.
+@implementation _touchHLE_UIButtonRoundedRectBackground: UIView
+
+- (())drawRect:(CGRect)_rect {
+    let bounds: CGRect = msg![env; this bounds];
+    let button: id = msg![env; this superview];
+    let state: UIControlState = msg![env; button state];
+    let context = UIGraphicsGetCurrentContext(env);
+
+    // Grey border
+    CGContextSetRGBFillColor(env, context, 0.6, 0.6, 0.6, 1.0);
+    fill_rounded_rect(env, context, bounds, ROUNDED_RECT_CORNER_RADIUS);
+
+    // White fill, or blue if highlighted
+    if (state & UIControlStateHighlighted) != 0 {
+        CGContextSetRGBFillColor(env, context, 0.09, 0.45, 0.92, 1.0);
+    } else {
+        CGContextSetRGBFillColor(env, context, 1.0, 1.0, 1.0, 1.0);
+    }
+    let inner = inset_rect(bounds, UIEdgeInsets {
+        top: 1.0,
+        left: 1.0,
+        bottom: 1.0,
+        right: 1.0,
+    });
+    fill_rounded_rect(env, context, inner, ROUNDED_RECT_CORNER_RADIUS - 1.0);
+}
+
+@end
+
 };
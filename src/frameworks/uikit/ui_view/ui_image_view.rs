@@ -7,18 +7,109 @@
 
 use crate::frameworks::core_graphics::cg_image::CGImageRef;
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::{NSInteger, NSTimeInterval, NSUInteger};
 use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_super, objc_classes, release, retain,
-    ClassExports, NSZonePtr,
+    id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes, release,
+    retain, ClassExports, HostObject, NSZonePtr,
 };
+use crate::Environment;
+use std::time::Instant;
+
+/// Frame rate used for animations when the app doesn't set a duration.
+const DEFAULT_ANIMATION_FPS: f64 = 30.0;
 
-#[derive(Default)]
 struct UIImageViewHostObject {
     superclass: super::UIViewHostObject,
     /// `UIImage*`
     image: id,
+    /// `UIImage*`
+    highlighted_image: id,
+    highlighted: bool,
+    /// `NSArray*` of `UIImage*`
+    animation_images: id,
+    animation_duration: NSTimeInterval,
+    animation_repeat_count: NSInteger,
+    /// `NSTimer*`, only set while animating.
+    animation_timer: id,
+    /// When the current animation started.
+    animation_start: Option<Instant>,
+    /// Index in `animation_images` of the image being shown.
+    animation_frame: NSUInteger,
 }
 impl_HostObject_with_superclass!(UIImageViewHostObject);
+impl Default for UIImageViewHostObject {
+    fn default() -> Self {
+        UIImageViewHostObject {
+            superclass: Default::default(),
+            image: nil,
+            highlighted_image: nil,
+            highlighted: false,
+            animation_images: nil,
+            animation_duration: 0.0,
+            animation_repeat_count: 0,
+            animation_timer: nil,
+            animation_start: None,
+            animation_frame: 0,
+        }
+    }
+}
+
+/// Belongs to `_touchHLE_UIImageViewAnimationTarget`.
+struct AnimationTargetHostObject {
+    /// `UIImageView*`. This is a weak reference: the image view stops the
+    /// timer before it is deallocated.
+    image_view: id,
+}
+impl HostObject for AnimationTargetHostObject {}
+
+/// Get the duration of one frame of the animation, and the number of frames.
+fn animation_timing(env: &mut Environment, this: id) -> (NSTimeInterval, NSUInteger) {
+    let &UIImageViewHostObject {
+        animation_images,
+        animation_duration,
+        ..
+    } = env.objc.borrow(this);
+    let count: NSUInteger = msg![env; animation_images count];
+    let frame_duration = if animation_duration > 0.0 && count > 0 {
+        animation_duration / f64::from(count)
+    } else {
+        1.0 / DEFAULT_ANIMATION_FPS
+    };
+    (frame_duration, count)
+}
+
+/// Called by the animation timer: advance to the frame that should be shown
+/// now, or stop once the animation has been repeated enough times.
+fn advance_animation(env: &mut Environment, this: id) {
+    let (frame_duration, count) = animation_timing(env, this);
+    let host_obj = env.objc.borrow::<UIImageViewHostObject>(this);
+    let Some(start) = host_obj.animation_start else {
+        return;
+    };
+    let repeat_count = host_obj.animation_repeat_count;
+    if count == 0 {
+        () = msg![env; this stopAnimating];
+        return;
+    }
+
+    let frames_elapsed = (start.elapsed().as_secs_f64() / frame_duration) as u64;
+    if repeat_count > 0 && frames_elapsed >= u64::from(count) * (repeat_count as u64) {
+        () = msg![env; this stopAnimating];
+        return;
+    }
+
+    let frame = (frames_elapsed % u64::from(count)) as NSUInteger;
+    let host_obj = env.objc.borrow_mut::<UIImageViewHostObject>(this);
+    if std::mem::replace(&mut host_obj.animation_frame, frame) != frame {
+        let layer: id = msg![env; this layer];
+        () = msg![env; layer setNeedsDisplay];
+    }
+}
+
+fn set_needs_display(env: &mut Environment, this: id) {
+    let layer: id = msg![env; this layer];
+    () = msg![env; layer setNeedsDisplay];
+}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -40,11 +131,16 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (())dealloc {
+    () = msg![env; this stopAnimating];
     let &UIImageViewHostObject {
-        superclass: _,
         image,
+        highlighted_image,
+        animation_images,
+        ..
     } = env.objc.borrow(this);
     release(env, image);
+    release(env, highlighted_image);
+    release(env, animation_images);
     msg_super![env; this dealloc]
 }
 
@@ -64,6 +160,13 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
+- (id)initWithImage:(id)image // UIImage*
+   highlightedImage:(id)highlighted_image { // UIImage*
+    let this: id = msg![env; this initWithImage:image];
+    () = msg![env; this setHighlightedImage:highlighted_image];
+    this
+}
+
 - (id)image {
     env.objc.borrow::<UIImageViewHostObject>(this).image
 }
@@ -73,9 +176,102 @@ pub const CLASSES: ClassExports = objc_classes! {
     let old_image = std::mem::replace(&mut host_obj.image, new_image);
     retain(env, new_image);
     release(env, old_image);
+    set_needs_display(env, this);
+}
 
-    let layer: id = msg![env; this layer];
-    () = msg![env; layer setNeedsDisplay];
+- (id)highlightedImage {
+    env.objc.borrow::<UIImageViewHostObject>(this).highlighted_image
+}
+- (())setHighlightedImage:(id)new_image { // UIImage*
+    let host_obj = env.objc.borrow_mut::<UIImageViewHostObject>(this);
+    let old_image = std::mem::replace(&mut host_obj.highlighted_image, new_image);
+    retain(env, new_image);
+    release(env, old_image);
+    set_needs_display(env, this);
+}
+
+- (bool)isHighlighted {
+    env.objc.borrow::<UIImageViewHostObject>(this).highlighted
+}
+- (())setHighlighted:(bool)highlighted {
+    env.objc.borrow_mut::<UIImageViewHostObject>(this).highlighted = highlighted;
+    set_needs_display(env, this);
+}
+
+- (id)animationImages {
+    env.objc.borrow::<UIImageViewHostObject>(this).animation_images
+}
+- (())setAnimationImages:(id)images { // NSArray* of UIImage*
+    let images: id = msg![env; images copy];
+    let host_obj = env.objc.borrow_mut::<UIImageViewHostObject>(this);
+    let old_images = std::mem::replace(&mut host_obj.animation_images, images);
+    host_obj.animation_frame = 0;
+    release(env, old_images);
+    let count: NSUInteger = msg![env; images count];
+    if count == 0 {
+        () = msg![env; this stopAnimating];
+    }
+    set_needs_display(env, this);
+}
+
+- (NSTimeInterval)animationDuration {
+    env.objc.borrow::<UIImageViewHostObject>(this).animation_duration
+}
+- (())setAnimationDuration:(NSTimeInterval)duration {
+    env.objc.borrow_mut::<UIImageViewHostObject>(this).animation_duration = duration;
+}
+
+- (NSInteger)animationRepeatCount {
+    env.objc.borrow::<UIImageViewHostObject>(this).animation_repeat_count
+}
+- (())setAnimationRepeatCount:(NSInteger)count {
+    env.objc.borrow_mut::<UIImageViewHostObject>(this).animation_repeat_count = count;
+}
+
+- (())startAnimating {
+    let animation_images = env.objc.borrow::<UIImageViewHostObject>(this).animation_images;
+    // Also covers nil.
+    let count: NSUInteger = msg![env; animation_images count];
+    if count == 0 {
+        return;
+    }
+    // Restart from the first frame, like the real UIKit.
+    () = msg![env; this stopAnimating];
+
+    let (frame_duration, _count) = animation_timing(env, this);
+    let target: id = msg_class![env; _touchHLE_UIImageViewAnimationTarget alloc];
+    let target: id = msg![env; target initWithImageView:this];
+    let selector = env.objc.lookup_selector("animationTimerFired:").unwrap();
+    let timer: id = msg_class![env; NSTimer scheduledTimerWithTimeInterval:frame_duration
+                                                                    target:target
+                                                                  selector:selector
+                                                                  userInfo:nil
+                                                                   repeats:true];
+    // The timer retains the target.
+    release(env, target);
+    retain(env, timer);
+
+    let host_obj = env.objc.borrow_mut::<UIImageViewHostObject>(this);
+    host_obj.animation_timer = timer;
+    host_obj.animation_start = Some(Instant::now());
+    host_obj.animation_frame = 0;
+    set_needs_display(env, this);
+}
+
+- (())stopAnimating {
+    let host_obj = env.objc.borrow_mut::<UIImageViewHostObject>(this);
+    let timer = std::mem::replace(&mut host_obj.animation_timer, nil);
+    host_obj.animation_start = None;
+    if timer == nil {
+        return;
+    }
+    () = msg![env; timer invalidate];
+    release(env, timer);
+    set_needs_display(env, this);
+}
+
+- (bool)isAnimating {
+    env.objc.borrow::<UIImageViewHostObject>(this).animation_timer != nil
 }
 
 // Normally a UIKit view is drawn into a CGContextRef by drawRect:, which is
@@ -85,11 +281,48 @@ pub const CLASSES: ClassExports = objc_classes! {
 // drawLayer:inContext: doesn't get called, so I assume this is what the real
 // UIKit does?
 - (())displayLayer:(id)layer {
-    let image: id = msg![env; this image];
+    let &UIImageViewHostObject {
+        image,
+        highlighted_image,
+        highlighted,
+        animation_images,
+        animation_timer,
+        animation_frame,
+        ..
+    } = env.objc.borrow(this);
+    let count: NSUInteger = msg![env; animation_images count];
+    let image: id = if animation_timer != nil && animation_frame < count {
+        msg![env; animation_images objectAtIndex:animation_frame]
+    } else if highlighted && highlighted_image != nil {
+        highlighted_image
+    } else {
+        image
+    };
     let cg_image: CGImageRef = msg![env; image CGImage];
     () = msg![env; layer setContents:cg_image];
 }
 
 @end
 
+// Target for the animation timer, so that the timer doesn't need to retain
+// the image view.
+@implementation _touchHLE_UIImageViewAnimationTarget: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(AnimationTargetHostObject { image_view: nil });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (id)initWithImageView:(id)image_view {
+    env.objc.borrow_mut::<AnimationTargetHostObject>(this).image_view = image_view;
+    this
+}
+
+- (())animationTimerFired:(id)_timer { // NSTimer*
+    let image_view = env.objc.borrow::<AnimationTargetHostObject>(this).image_view;
+    advance_animation(env, image_view);
+}
+
+@end
+
 };
@@ -0,0 +1,214 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `UIScrollView`.
+//!
+//! Only programmatic scrolling and scroll-to-top (tapping the status bar) are
+//! implemented so far. Dragging doesn't scroll the view yet.
+
+use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::NSUInteger;
+use crate::frameworks::uikit::ui_geometry::UIEdgeInsets;
+use crate::objc::{
+    id, impl_HostObject_with_superclass, msg, nil, objc_classes, ClassExports, NSZonePtr,
+};
+use crate::Environment;
+
+pub struct UIScrollViewHostObject {
+    superclass: super::UIViewHostObject,
+    content_size: CGSize,
+    content_inset: UIEdgeInsets,
+    /// Weak reference.
+    delegate: id,
+    scroll_enabled: bool,
+    scrolls_to_top: bool,
+}
+impl_HostObject_with_superclass!(UIScrollViewHostObject);
+impl Default for UIScrollViewHostObject {
+    fn default() -> Self {
+        UIScrollViewHostObject {
+            superclass: Default::default(),
+            content_size: CGSize {
+                width: 0.0,
+                height: 0.0,
+            },
+            content_inset: Default::default(),
+            delegate: nil,
+            scroll_enabled: true,
+            scrolls_to_top: true,
+        }
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation UIScrollView: UIView
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::<UIScrollViewHostObject>::default();
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+// TODO: decode the scroll view properties in initWithCoder:
+
+- (id)delegate {
+    env.objc.borrow::<UIScrollViewHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate { // something implementing UIScrollViewDelegate
+    env.objc.borrow_mut::<UIScrollViewHostObject>(this).delegate = delegate;
+}
+
+- (CGSize)contentSize {
+    env.objc.borrow::<UIScrollViewHostObject>(this).content_size
+}
+- (())setContentSize:(CGSize)size {
+    env.objc.borrow_mut::<UIScrollViewHostObject>(this).content_size = size;
+}
+
+- (UIEdgeInsets)contentInset {
+    env.objc.borrow::<UIScrollViewHostObject>(this).content_inset
+}
+- (())setContentInset:(UIEdgeInsets)insets {
+    env.objc.borrow_mut::<UIScrollViewHostObject>(this).content_inset = insets;
+}
+
+// The content offset is the origin of the bounds.
+- (CGPoint)contentOffset {
+    let bounds: CGRect = msg![env; this bounds];
+    bounds.origin
+}
+- (())setContentOffset:(CGPoint)offset {
+    let mut bounds: CGRect = msg![env; this bounds];
+    bounds.origin = offset;
+    () = msg![env; this setBounds:bounds];
+
+    let delegate = env.objc.borrow::<UIScrollViewHostObject>(this).delegate;
+    if delegate != nil
+        && env.objc.object_has_method_named(&env.mem, delegate, "scrollViewDidScroll:")
+    {
+        () = msg![env; delegate scrollViewDidScroll:this];
+    }
+}
+- (())setContentOffset:(CGPoint)offset
+              animated:(bool)_animated {
+    // TODO: animation
+    msg![env; this setContentOffset:offset]
+}
+
+- (())scrollRectToVisible:(CGRect)rect
+                 animated:(bool)animated {
+    let bounds: CGRect = msg![env; this bounds];
+    let mut offset = bounds.origin;
+    // Scroll by the smallest amount that makes the rect visible, preferring
+    // its top-left corner if it's bigger than the view.
+    if rect.origin.x < offset.x {
+        offset.x = rect.origin.x;
+    } else if rect.origin.x + rect.size.width > offset.x + bounds.size.width {
+        offset.x = (rect.origin.x + rect.size.width - bounds.size.width).min(rect.origin.x);
+    }
+    if rect.origin.y < offset.y {
+        offset.y = rect.origin.y;
+    } else if rect.origin.y + rect.size.height > offset.y + bounds.size.height {
+        offset.y = (rect.origin.y + rect.size.height - bounds.size.height).min(rect.origin.y);
+    }
+    if offset != bounds.origin {
+        () = msg![env; this setContentOffset:offset animated:animated];
+    }
+}
+
+- (bool)isScrollEnabled {
+    env.objc.borrow::<UIScrollViewHostObject>(this).scroll_enabled
+}
+- (())setScrollEnabled:(bool)enabled {
+    env.objc.borrow_mut::<UIScrollViewHostObject>(this).scroll_enabled = enabled;
+}
+
+- (bool)scrollsToTop {
+    env.objc.borrow::<UIScrollViewHostObject>(this).scrolls_to_top
+}
+- (())setScrollsToTop:(bool)scrolls_to_top {
+    env.objc.borrow_mut::<UIScrollViewHostObject>(this).scrolls_to_top = scrolls_to_top;
+}
+
+// Scroll indicators are never drawn, so these are ignored.
+- (())setShowsHorizontalScrollIndicator:(bool)_shows {}
+- (())setShowsVerticalScrollIndicator:(bool)_shows {}
+- (())flashScrollIndicators {}
+
+@end
+
+};
+
+/// Collect the visible scroll views with `scrollsToTop` set in a view's
+/// hierarchy.
+fn find_scroll_to_top_views(env: &mut Environment, view: id, found: &mut Vec<id>) {
+    let hidden: bool = msg![env; view isHidden];
+    if hidden {
+        return;
+    }
+
+    let ui_scroll_view_class = env.objc.get_known_class("UIScrollView", &mut env.mem);
+    if msg![env; view isKindOfClass:ui_scroll_view_class] && msg![env; view scrollsToTop] {
+        found.push(view);
+    }
+
+    let subviews: id = msg![env; view subviews];
+    let count: NSUInteger = msg![env; subviews count];
+    for i in 0..count {
+        let subview: id = msg![env; subviews objectAtIndex:i];
+        find_scroll_to_top_views(env, subview, &mut *found);
+    }
+}
+
+/// Handle a tap on the status bar: scroll the window's scroll view to the top.
+/// Like on the real device, nothing happens unless there is exactly one
+/// visible scroll view with `scrollsToTop` set.
+pub fn scroll_to_top(env: &mut Environment, window: id) {
+    let mut found = Vec::new();
+    find_scroll_to_top_views(env, window, &mut found);
+    let &[scroll_view] = &found[..] else {
+        log_dbg!(
+            "Status bar tapped, but {} scroll views in {:?} have scrollsToTop set, ignoring",
+            found.len(),
+            window
+        );
+        return;
+    };
+
+    let delegate = env
+        .objc
+        .borrow::<UIScrollViewHostObject>(scroll_view)
+        .delegate;
+    let should_scroll = if delegate != nil
+        && env
+            .objc
+            .object_has_method_named(&env.mem, delegate, "scrollViewShouldScrollToTop:")
+    {
+        msg![env; delegate scrollViewShouldScrollToTop:scroll_view]
+    } else {
+        true
+    };
+
+    if should_scroll {
+        log_dbg!("Status bar tapped, scrolling {:?} to the top", scroll_view);
+        let offset: CGPoint = msg![env; scroll_view contentOffset];
+        let inset: UIEdgeInsets = msg![env; scroll_view contentInset];
+        let offset = CGPoint {
+            x: offset.x,
+            y: -inset.top,
+        };
+        () = msg![env; scroll_view setContentOffset:offset animated:true];
+
+        if delegate != nil
+            && env
+                .objc
+                .object_has_method_named(&env.mem, delegate, "scrollViewDidScrollToTop:")
+        {
+            () = msg![env; delegate scrollViewDidScrollToTop:scroll_view];
+        }
+    }
+}
@@ -6,7 +6,7 @@
 //! `UIWindow`.
 
 use crate::frameworks::core_graphics::CGRect;
-use crate::objc::{id, msg, msg_super, objc_classes, ClassExports};
+use crate::objc::{id, msg, msg_class, msg_super, objc_classes, ClassExports};
 
 #[derive(Default)]
 pub struct State {
@@ -24,6 +24,10 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // TODO: more?
 
+- (id)nextResponder {
+    msg_class![env; UIApplication sharedApplication]
+}
+
 - (id)initWithFrame:(CGRect)frame {
     // setHidden: may get called during the super call and panics if the window
     // is not in the list, so it must be added to it before that call.
@@ -9,6 +9,13 @@ use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::objc::{
     id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
 };
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    /// List of view controllers for internal purposes. Non-retaining!
+    pub(super) view_controllers: Vec<id>,
+}
 
 #[derive(Default)]
 struct UIViewControllerHostObject {
@@ -24,7 +31,9 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 + (id)allocWithZone:(NSZonePtr)_zone {
     let host_object = Box::<UIViewControllerHostObject>::default();
-    env.objc.alloc_object(this, host_object, &mut env.mem)
+    let new = env.objc.alloc_object(this, host_object, &mut env.mem);
+    env.framework_state.uikit.ui_view_controller.view_controllers.push(new);
+    new
 }
 
 - (id)initWithCoder:(id)coder {
@@ -41,6 +50,9 @@ pub const CLASSES: ClassExports = objc_classes! {
 
     release(env, view);
 
+    let view_controllers = &mut env.framework_state.uikit.ui_view_controller.view_controllers;
+    view_controllers.swap_remove(view_controllers.iter().position(|&vc| vc == this).unwrap());
+
     env.objc.dealloc_object(this, &mut env.mem);
 }
 
@@ -68,6 +80,20 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
 }
 
+- (id)nextResponder {
+    let &UIViewControllerHostObject { view } = env.objc.borrow(this);
+    if view == nil {
+        nil
+    } else {
+        msg![env; view superview]
+    }
+}
+
+- (())didReceiveMemoryWarning {
+    // TODO: release the view if it has no superview (and call viewDidUnload)
+    log_dbg!("[(UIViewController*){:?} didReceiveMemoryWarning]", this);
+}
+
 - (())setEditing:(bool)editing {
     log!("TODO: [(UIViewController*){:?} setEditing:{}]", this, editing); // TODO
 }
@@ -79,3 +105,15 @@ pub const CLASSES: ClassExports = objc_classes! {
 @end
 
 };
+
+/// For use by `-[UIView nextResponder]`: find the view controller that manages
+/// a view, if any.
+pub(super) fn view_controller_for_view(env: &mut Environment, view: id) -> Option<id> {
+    env.framework_state
+        .uikit
+        .ui_view_controller
+        .view_controllers
+        .iter()
+        .copied()
+        .find(|&vc| env.objc.borrow::<UIViewControllerHostObject>(vc).view == view)
+}
@@ -0,0 +1,113 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! The private VoiceServices framework.
+//!
+//! A few apps use this for voice prompts, since there was no public speech
+//! synthesis API before iOS 7. Speech goes to the host's speech synthesizer
+//! (see [crate::audio::Speech]), so it's silent unless `--text-to-speech` is
+//! used.
+//!
+//! Resources:
+//! - There's no documentation, but class dumps of `VSSpeechSynthesizer` can be
+//!   found online.
+
+use super::foundation::ns_string::to_rust_string;
+use crate::audio::Speech;
+use crate::objc::{id, nil, objc_classes, ClassExports, HostObject, NSZonePtr};
+
+struct VSSpeechSynthesizerHostObject {
+    speech: Speech,
+    /// Weak reference
+    delegate: id,
+    rate: f32,
+    pitch: f32,
+    volume: f32,
+}
+impl HostObject for VSSpeechSynthesizerHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation VSSpeechSynthesizer: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(VSSpeechSynthesizerHostObject {
+        speech: Speech::new(env.options.text_to_speech),
+        delegate: nil,
+        rate: 1.0,
+        pitch: 0.5,
+        volume: 1.0,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (bool)isSystemSpeaking {
+    false
+}
+
+- (id)delegate {
+    env.objc.borrow::<VSSpeechSynthesizerHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate {
+    // TODO: call speechSynthesizer:didFinishSpeaking:withError:
+    env.objc.borrow_mut::<VSSpeechSynthesizerHostObject>(this).delegate = delegate;
+}
+
+// The setters and the speaking methods return an NSError* on failure.
+
+- (f32)rate {
+    env.objc.borrow::<VSSpeechSynthesizerHostObject>(this).rate
+}
+- (id)setRate:(f32)rate {
+    // TODO: pass this on to the host
+    env.objc.borrow_mut::<VSSpeechSynthesizerHostObject>(this).rate = rate;
+    nil
+}
+- (f32)pitch {
+    env.objc.borrow::<VSSpeechSynthesizerHostObject>(this).pitch
+}
+- (id)setPitch:(f32)pitch {
+    // TODO: pass this on to the host
+    env.objc.borrow_mut::<VSSpeechSynthesizerHostObject>(this).pitch = pitch;
+    nil
+}
+- (f32)volume {
+    env.objc.borrow::<VSSpeechSynthesizerHostObject>(this).volume
+}
+- (id)setVolume:(f32)volume {
+    // TODO: pass this on to the host
+    env.objc.borrow_mut::<VSSpeechSynthesizerHostObject>(this).volume = volume;
+    nil
+}
+
+- (id)startSpeakingString:(id)string { // NSString*
+    let text = to_rust_string(env, string);
+    env.objc
+        .borrow_mut::<VSSpeechSynthesizerHostObject>(this)
+        .speech
+        .speak(&text);
+    nil
+}
+
+- (bool)isSpeaking {
+    env.objc
+        .borrow_mut::<VSSpeechSynthesizerHostObject>(this)
+        .speech
+        .is_speaking()
+}
+
+- (id)stopSpeakingAtNextBoundary:(i32)_boundary {
+    env.objc
+        .borrow_mut::<VSSpeechSynthesizerHostObject>(this)
+        .speech
+        .stop();
+    nil
+}
+
+@end
+
+};
@@ -0,0 +1,818 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! zlib (`libz.dylib`).
+//!
+//! This isn't a framework, but iPhone OS provides it as a system library and
+//! many apps link to it dynamically, usually for decompressing their assets.
+//! The actual compression is done by the Rust `flate2` crate.
+//!
+//! Resources:
+//! - [zlib 1.2.3 manual](https://www.zlib.net/manual.html) (iPhone OS 2 has
+//!   version 1.2.3)
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::fs::{GuestPath, GuestPathBuf};
+use crate::mem::{ConstPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::Environment;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const ZLIB_VERSION: &str = "1.2.3";
+
+const Z_NO_FLUSH: i32 = 0;
+const Z_PARTIAL_FLUSH: i32 = 1;
+const Z_SYNC_FLUSH: i32 = 2;
+const Z_FULL_FLUSH: i32 = 3;
+const Z_FINISH: i32 = 4;
+
+const Z_OK: i32 = 0;
+const Z_STREAM_END: i32 = 1;
+const Z_STREAM_ERROR: i32 = -2;
+const Z_DATA_ERROR: i32 = -3;
+const Z_BUF_ERROR: i32 = -5;
+const Z_VERSION_ERROR: i32 = -6;
+
+const Z_DEFAULT_COMPRESSION: i32 = -1;
+const Z_DEFLATED: i32 = 8;
+
+#[allow(non_camel_case_types)]
+#[derive(Default)]
+#[repr(C, packed)]
+struct z_stream {
+    next_in: ConstPtr<u8>,
+    avail_in: u32,
+    total_in: u32,
+    next_out: MutPtr<u8>,
+    avail_out: u32,
+    total_out: u32,
+    msg: ConstPtr<u8>,
+    /// We use this as the key for the stream's host state.
+    state: MutVoidPtr,
+    /// Custom allocators are ignored, the state is kept on the host.
+    zalloc: MutVoidPtr,
+    zfree: MutVoidPtr,
+    opaque: MutVoidPtr,
+    data_type: i32,
+    adler: u32,
+    reserved: u32,
+}
+unsafe impl SafeRead for z_stream {}
+
+/// Header format of a compressed stream.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Wrapper {
+    /// No header or trailer ("raw deflate").
+    Raw,
+    Zlib,
+    /// Only supported for decompression.
+    Gzip,
+    /// Detect zlib or gzip from the first byte (decompression only).
+    Auto,
+}
+
+enum Stream {
+    Inflate {
+        /// Created once the header format is known.
+        decompress: Option<Decompress>,
+        wrapper: Wrapper,
+        /// Whether the stream turned out to have a gzip header.
+        gzip: bool,
+        /// Bytes of the gzip trailer that still need to be skipped.
+        trailer_remaining: u32,
+    },
+    Deflate(Compress),
+}
+
+struct GzFile {
+    path: GuestPathBuf,
+    /// For reading, the decompressed file contents. For writing, the
+    /// uncompressed data written so far, which is compressed on closing.
+    data: Vec<u8>,
+    position: usize,
+    /// `None` if the file was opened for reading.
+    write_level: Option<Compression>,
+}
+
+#[derive(Default)]
+pub struct State {
+    streams: HashMap<MutVoidPtr, Stream>,
+    gz_files: HashMap<MutVoidPtr, GzFile>,
+    version_string: Option<ConstPtr<u8>>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.zlib
+    }
+}
+
+/// Table for [crc32_update], generated at compile time.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc = CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn adler32_update(adler: u32, bytes: &[u8]) -> u32 {
+    const BASE: u32 = 65521;
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    // 5552 is the most bytes that can be summed before b could overflow.
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= BASE;
+        b %= BASE;
+    }
+    (b << 16) | a
+}
+
+fn compression_level(level: i32) -> Option<Compression> {
+    match level {
+        Z_DEFAULT_COMPRESSION => Some(Compression::default()),
+        0..=9 => Some(Compression::new(level as u32)),
+        _ => None,
+    }
+}
+
+/// Get the length of a gzip header at the start of `bytes`, if there's a
+/// complete and valid one.
+fn gzip_header_len(bytes: &[u8]) -> Option<usize> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if bytes.len() < 10 || bytes[0..3] != [0x1f, 0x8b, 8] {
+        return None;
+    }
+    let flags = bytes[3];
+    let mut len = 10;
+    if flags & FEXTRA != 0 {
+        let extra_len = u16::from_le_bytes(bytes.get(len..len + 2)?.try_into().unwrap());
+        len += 2 + usize::from(extra_len);
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            len += bytes.get(len..)?.iter().position(|&b| b == b'\0')? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+    (len <= bytes.len()).then_some(len)
+}
+
+fn zlibVersion(env: &mut Environment) -> ConstPtr<u8> {
+    if let Some(ptr) = State::get_mut(env).version_string {
+        return ptr;
+    }
+    let ptr = env
+        .mem
+        .alloc_and_write_cstr(ZLIB_VERSION.as_bytes())
+        .cast_const();
+    State::get_mut(env).version_string = Some(ptr);
+    ptr
+}
+
+/// Check the arguments every `*Init_` function gets, to make sure the app was
+/// compiled against a compatible zlib.
+fn check_version(env: &Environment, version: ConstPtr<u8>, stream_size: i32) -> bool {
+    !version.is_null() && env.mem.read(version) == b'1' && stream_size == guest_size_of_z_stream()
+}
+
+fn guest_size_of_z_stream() -> i32 {
+    crate::mem::guest_size_of::<z_stream>() as i32
+}
+
+/// Register a new stream's host state and reset the fields of the `z_stream`.
+/// `adler` is the initial value of the running checksum, which is Adler-32
+/// for zlib and raw streams but CRC-32 for gzip streams.
+fn init_stream(env: &mut Environment, strm: MutPtr<z_stream>, stream: Stream, adler: u32) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    let state = env.mem.alloc(4);
+    State::get_mut(env).streams.insert(state, stream);
+    z_stream.state = state;
+    z_stream.total_in = 0;
+    z_stream.total_out = 0;
+    z_stream.msg = Ptr::null();
+    z_stream.data_type = 0;
+    z_stream.adler = adler;
+    env.mem.write(strm, z_stream);
+    Z_OK
+}
+
+fn end_stream(env: &mut Environment, strm: MutPtr<z_stream>) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    if State::get_mut(env)
+        .streams
+        .remove(&{ z_stream.state })
+        .is_none()
+    {
+        return Z_STREAM_ERROR;
+    }
+    env.mem.free(z_stream.state);
+    z_stream.state = Ptr::null();
+    env.mem.write(strm, z_stream);
+    Z_OK
+}
+
+/// Update the `z_stream` after some input was consumed and output produced.
+#[allow(clippy::assign_op_pattern)] // `+=` would need an unaligned reference
+fn advance(z_stream: &mut z_stream, consumed: u32, produced: u32) {
+    z_stream.next_in = z_stream.next_in + consumed;
+    z_stream.avail_in -= consumed;
+    z_stream.total_in += consumed;
+    z_stream.next_out = z_stream.next_out + produced;
+    z_stream.avail_out -= produced;
+    z_stream.total_out += produced;
+}
+
+fn inflateInit_(
+    env: &mut Environment,
+    strm: MutPtr<z_stream>,
+    version: ConstPtr<u8>,
+    stream_size: i32,
+) -> i32 {
+    inflateInit2_(env, strm, 15, version, stream_size)
+}
+
+fn inflateInit2_(
+    env: &mut Environment,
+    strm: MutPtr<z_stream>,
+    window_bits: i32,
+    version: ConstPtr<u8>,
+    stream_size: i32,
+) -> i32 {
+    if !check_version(env, version, stream_size) {
+        return Z_VERSION_ERROR;
+    }
+    // The window size doesn't matter for decompression: the Rust
+    // implementation always supports the maximum.
+    let wrapper = match window_bits {
+        -15..=-8 => Wrapper::Raw,
+        8..=15 => Wrapper::Zlib,
+        24..=31 => Wrapper::Gzip,
+        40..=47 => Wrapper::Auto,
+        _ => return Z_STREAM_ERROR,
+    };
+    let decompress = match wrapper {
+        Wrapper::Raw => Some(Decompress::new(false)),
+        Wrapper::Zlib => Some(Decompress::new(true)),
+        Wrapper::Gzip | Wrapper::Auto => None,
+    };
+    init_stream(
+        env,
+        strm,
+        Stream::Inflate {
+            decompress,
+            wrapper,
+            gzip: false,
+            trailer_remaining: 0,
+        },
+        if wrapper == Wrapper::Zlib || wrapper == Wrapper::Raw {
+            1
+        } else {
+            0
+        },
+    )
+}
+
+fn inflate(env: &mut Environment, strm: MutPtr<z_stream>, _flush: i32) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    if z_stream.next_out.is_null() || (z_stream.next_in.is_null() && z_stream.avail_in != 0) {
+        return Z_STREAM_ERROR;
+    }
+    let mut input = env
+        .mem
+        .bytes_at(z_stream.next_in, z_stream.avail_in)
+        .to_vec();
+    let Some(Stream::Inflate {
+        decompress,
+        wrapper,
+        gzip,
+        trailer_remaining,
+    }) = env
+        .framework_state
+        .zlib
+        .streams
+        .get_mut(&{ z_stream.state })
+    else {
+        return Z_STREAM_ERROR;
+    };
+
+    // Finish skipping the trailer of a finished gzip stream.
+    if *trailer_remaining > 0 {
+        let skipped = (*trailer_remaining).min(z_stream.avail_in);
+        *trailer_remaining -= skipped;
+        advance(&mut z_stream, skipped, 0);
+        env.mem.write(strm, z_stream);
+        return Z_STREAM_END;
+    }
+
+    // Work out the header format and skip any gzip header.
+    let mut header_len = 0;
+    if decompress.is_none() {
+        *gzip = match *wrapper {
+            Wrapper::Gzip => true,
+            Wrapper::Auto => {
+                let Some(&first) = input.first() else {
+                    return Z_BUF_ERROR;
+                };
+                first == 0x1f
+            }
+            Wrapper::Raw | Wrapper::Zlib => unreachable!(),
+        };
+        if *gzip {
+            let Some(len) = gzip_header_len(&input) else {
+                // TODO: support headers split across multiple calls
+                log!("TODO: inflate() with incomplete or invalid gzip header");
+                return Z_DATA_ERROR;
+            };
+            header_len = len;
+            input.drain(..len);
+        }
+        if !*gzip {
+            z_stream.adler = 1;
+        }
+        *decompress = Some(Decompress::new(!*gzip));
+    }
+    let decompress = decompress.as_mut().unwrap();
+    let is_gzip = *gzip;
+
+    let before_in = decompress.total_in();
+    let before_out = decompress.total_out();
+    let output = env.mem.bytes_at_mut(z_stream.next_out, z_stream.avail_out);
+    // zlib treats Z_FINISH as a hint, but the Rust implementation would need
+    // all of the output to fit, so it's ignored.
+    let result = decompress.decompress(&input, output, FlushDecompress::None);
+    let consumed = (decompress.total_in() - before_in) as u32;
+    let produced = (decompress.total_out() - before_out) as u32;
+
+    let output = env.mem.bytes_at(z_stream.next_out.cast_const(), produced);
+    z_stream.adler = if is_gzip {
+        crc32_update(z_stream.adler, output)
+    } else {
+        adler32_update(z_stream.adler, output)
+    };
+    let consumed = consumed + header_len as u32;
+    advance(&mut z_stream, consumed, produced);
+
+    let res = match result {
+        Ok(Status::StreamEnd) => {
+            if is_gzip {
+                // Skip the CRC-32 and size. They aren't checked.
+                let skipped = 8.min(z_stream.avail_in);
+                advance(&mut z_stream, skipped, 0);
+                let Some(Stream::Inflate {
+                    trailer_remaining, ..
+                }) = env
+                    .framework_state
+                    .zlib
+                    .streams
+                    .get_mut(&{ z_stream.state })
+                else {
+                    unreachable!();
+                };
+                *trailer_remaining = 8 - skipped;
+            }
+            Z_STREAM_END
+        }
+        Ok(Status::BufError) => Z_BUF_ERROR,
+        Ok(Status::Ok) if consumed == 0 && produced == 0 => Z_BUF_ERROR,
+        Ok(Status::Ok) => Z_OK,
+        Err(e) => {
+            log_dbg!("inflate() failed: {}", e);
+            Z_DATA_ERROR
+        }
+    };
+    env.mem.write(strm, z_stream);
+    res
+}
+
+fn inflateReset(env: &mut Environment, strm: MutPtr<z_stream>) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    let Some(Stream::Inflate {
+        decompress,
+        wrapper,
+        gzip,
+        trailer_remaining,
+    }) = env
+        .framework_state
+        .zlib
+        .streams
+        .get_mut(&{ z_stream.state })
+    else {
+        return Z_STREAM_ERROR;
+    };
+    *gzip = false;
+    match wrapper {
+        Wrapper::Raw | Wrapper::Zlib => {
+            decompress
+                .as_mut()
+                .unwrap()
+                .reset(*wrapper == Wrapper::Zlib);
+            z_stream.adler = 1;
+        }
+        Wrapper::Gzip | Wrapper::Auto => {
+            *decompress = None;
+            z_stream.adler = 0;
+        }
+    }
+    *trailer_remaining = 0;
+    z_stream.total_in = 0;
+    z_stream.total_out = 0;
+    z_stream.msg = Ptr::null();
+    env.mem.write(strm, z_stream);
+    Z_OK
+}
+
+fn inflateEnd(env: &mut Environment, strm: MutPtr<z_stream>) -> i32 {
+    end_stream(env, strm)
+}
+
+fn deflateInit_(
+    env: &mut Environment,
+    strm: MutPtr<z_stream>,
+    level: i32,
+    version: ConstPtr<u8>,
+    stream_size: i32,
+) -> i32 {
+    deflateInit2_(env, strm, level, Z_DEFLATED, 15, 8, 0, version, stream_size)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn deflateInit2_(
+    env: &mut Environment,
+    strm: MutPtr<z_stream>,
+    level: i32,
+    method: i32,
+    window_bits: i32,
+    _mem_level: i32,
+    _strategy: i32,
+    version: ConstPtr<u8>,
+    stream_size: i32,
+) -> i32 {
+    if !check_version(env, version, stream_size) {
+        return Z_VERSION_ERROR;
+    }
+    let Some(level) = compression_level(level) else {
+        return Z_STREAM_ERROR;
+    };
+    if method != Z_DEFLATED {
+        return Z_STREAM_ERROR;
+    }
+    // The window size and memory level only affect how well the data is
+    // compressed, so they're ignored.
+    let zlib_header = match window_bits {
+        -15..=-8 => false,
+        8..=15 => true,
+        24..=31 => {
+            log!("TODO: deflateInit2_() with gzip header");
+            return Z_STREAM_ERROR;
+        }
+        _ => return Z_STREAM_ERROR,
+    };
+    init_stream(
+        env,
+        strm,
+        Stream::Deflate(Compress::new(level, zlib_header)),
+        1,
+    )
+}
+
+fn deflate(env: &mut Environment, strm: MutPtr<z_stream>, flush: i32) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    if z_stream.next_out.is_null() || (z_stream.next_in.is_null() && z_stream.avail_in != 0) {
+        return Z_STREAM_ERROR;
+    }
+    let flush = match flush {
+        Z_NO_FLUSH => FlushCompress::None,
+        Z_PARTIAL_FLUSH => FlushCompress::Partial,
+        Z_SYNC_FLUSH => FlushCompress::Sync,
+        Z_FULL_FLUSH => FlushCompress::Full,
+        Z_FINISH => FlushCompress::Finish,
+        _ => return Z_STREAM_ERROR,
+    };
+    let input = env
+        .mem
+        .bytes_at(z_stream.next_in, z_stream.avail_in)
+        .to_vec();
+    let Some(Stream::Deflate(compress)) = env
+        .framework_state
+        .zlib
+        .streams
+        .get_mut(&{ z_stream.state })
+    else {
+        return Z_STREAM_ERROR;
+    };
+
+    let before_in = compress.total_in();
+    let before_out = compress.total_out();
+    let output = env.mem.bytes_at_mut(z_stream.next_out, z_stream.avail_out);
+    let result = compress.compress(&input, output, flush);
+    let consumed = (compress.total_in() - before_in) as u32;
+    let produced = (compress.total_out() - before_out) as u32;
+
+    z_stream.adler = adler32_update(z_stream.adler, &input[..consumed as usize]);
+    advance(&mut z_stream, consumed, produced);
+    env.mem.write(strm, z_stream);
+
+    match result {
+        Ok(Status::StreamEnd) => Z_STREAM_END,
+        Ok(Status::BufError) => Z_BUF_ERROR,
+        Ok(Status::Ok) => Z_OK,
+        Err(_) => Z_STREAM_ERROR,
+    }
+}
+
+fn deflateReset(env: &mut Environment, strm: MutPtr<z_stream>) -> i32 {
+    if strm.is_null() {
+        return Z_STREAM_ERROR;
+    }
+    let mut z_stream = env.mem.read(strm);
+    let Some(Stream::Deflate(compress)) = env
+        .framework_state
+        .zlib
+        .streams
+        .get_mut(&{ z_stream.state })
+    else {
+        return Z_STREAM_ERROR;
+    };
+    compress.reset();
+    z_stream.total_in = 0;
+    z_stream.total_out = 0;
+    z_stream.msg = Ptr::null();
+    z_stream.adler = 1;
+    env.mem.write(strm, z_stream);
+    Z_OK
+}
+
+fn deflateEnd(env: &mut Environment, strm: MutPtr<z_stream>) -> i32 {
+    end_stream(env, strm)
+}
+
+fn deflateBound(_env: &mut Environment, _strm: MutPtr<z_stream>, source_len: u32) -> u32 {
+    // This is the conservative bound from zlib 1.2.3.
+    source_len + ((source_len + 7) >> 3) + ((source_len + 63) >> 6) + 11
+}
+
+fn compressBound(_env: &mut Environment, source_len: u32) -> u32 {
+    source_len + (source_len >> 12) + (source_len >> 14) + 11
+}
+
+fn compress(
+    env: &mut Environment,
+    dest: MutPtr<u8>,
+    dest_len: MutPtr<u32>,
+    source: ConstPtr<u8>,
+    source_len: u32,
+) -> i32 {
+    compress2(
+        env,
+        dest,
+        dest_len,
+        source,
+        source_len,
+        Z_DEFAULT_COMPRESSION,
+    )
+}
+
+fn compress2(
+    env: &mut Environment,
+    dest: MutPtr<u8>,
+    dest_len: MutPtr<u32>,
+    source: ConstPtr<u8>,
+    source_len: u32,
+    level: i32,
+) -> i32 {
+    let Some(level) = compression_level(level) else {
+        return Z_STREAM_ERROR;
+    };
+    let input = env.mem.bytes_at(source, source_len).to_vec();
+    let output_len = env.mem.read(dest_len);
+    let output = env.mem.bytes_at_mut(dest, output_len);
+    let mut compress = Compress::new(level, true);
+    let result = compress.compress(&input, output, FlushCompress::Finish);
+    env.mem.write(dest_len, compress.total_out() as u32);
+    match result {
+        Ok(Status::StreamEnd) => Z_OK,
+        _ => Z_BUF_ERROR,
+    }
+}
+
+fn uncompress(
+    env: &mut Environment,
+    dest: MutPtr<u8>,
+    dest_len: MutPtr<u32>,
+    source: ConstPtr<u8>,
+    source_len: u32,
+) -> i32 {
+    let input = env.mem.bytes_at(source, source_len).to_vec();
+    let output_len = env.mem.read(dest_len);
+    let output = env.mem.bytes_at_mut(dest, output_len);
+    let mut decompress = Decompress::new(true);
+    let result = decompress.decompress(&input, output, FlushDecompress::Finish);
+    let total_out = decompress.total_out() as u32;
+    env.mem.write(dest_len, total_out);
+    match result {
+        Ok(Status::StreamEnd) => Z_OK,
+        // Either the output didn't fit, or the input was cut off.
+        _ if total_out == output_len => Z_BUF_ERROR,
+        _ => Z_DATA_ERROR,
+    }
+}
+
+fn crc32(env: &mut Environment, crc: u32, buf: ConstPtr<u8>, len: u32) -> u32 {
+    if buf.is_null() {
+        return 0;
+    }
+    crc32_update(crc, env.mem.bytes_at(buf, len))
+}
+
+fn adler32(env: &mut Environment, adler: u32, buf: ConstPtr<u8>, len: u32) -> u32 {
+    if buf.is_null() {
+        return 1;
+    }
+    adler32_update(adler, env.mem.bytes_at(buf, len))
+}
+
+fn gzopen(env: &mut Environment, path: ConstPtr<u8>, mode: ConstPtr<u8>) -> MutVoidPtr {
+    let path_str = env.mem.cstr_at_utf8(path).unwrap();
+    let path = GuestPath::new(path_str).to_owned();
+    let mode = env.mem.cstr_at_utf8(mode).unwrap().to_string();
+
+    let file = if mode.contains('r') {
+        let Ok(compressed) = env.fs.read(&path) else {
+            log!("Warning: gzopen() couldn't read {:?}", path);
+            return Ptr::null();
+        };
+        let data = if compressed.starts_with(&[0x1f, 0x8b]) {
+            let mut data = Vec::new();
+            let mut decoder = flate2::read::MultiGzDecoder::new(&compressed[..]);
+            if let Err(e) = decoder.read_to_end(&mut data) {
+                log!("Warning: gzopen() couldn't decompress {:?}: {}", path, e);
+                return Ptr::null();
+            }
+            data
+        } else {
+            // Like the real zlib, files that aren't compressed can be read
+            // too.
+            compressed
+        };
+        GzFile {
+            path,
+            data,
+            position: 0,
+            write_level: None,
+        }
+    } else if mode.contains('w') || mode.contains('a') {
+        if mode.contains('a') {
+            log!("TODO: gzopen() in append mode, the file will be overwritten");
+        }
+        let level = mode
+            .chars()
+            .find_map(|c| c.to_digit(10))
+            .map_or(Compression::default(), Compression::new);
+        // Make sure the file can be written now, rather than failing later.
+        if env.fs.write(&path, &[]).is_err() {
+            log!("Warning: gzopen() couldn't create {:?}", path);
+            return Ptr::null();
+        }
+        GzFile {
+            path,
+            data: Vec::new(),
+            position: 0,
+            write_level: Some(level),
+        }
+    } else {
+        return Ptr::null();
+    };
+
+    log_dbg!("gzopen({:?}, {:?})", file.path, mode);
+    let handle = env.mem.alloc(4);
+    State::get_mut(env).gz_files.insert(handle, file);
+    handle
+}
+
+fn gzread(env: &mut Environment, file: MutVoidPtr, buf: MutVoidPtr, len: u32) -> i32 {
+    let Some(gz_file) = env.framework_state.zlib.gz_files.get_mut(&file) else {
+        return -1;
+    };
+    if gz_file.write_level.is_some() {
+        return -1;
+    }
+    let remaining = &gz_file.data[gz_file.position..];
+    let count = remaining.len().min(len as usize);
+    env.mem
+        .bytes_at_mut(buf.cast(), count as GuestUSize)
+        .copy_from_slice(&remaining[..count]);
+    gz_file.position += count;
+    count as i32
+}
+
+fn gzwrite(env: &mut Environment, file: MutVoidPtr, buf: ConstPtr<u8>, len: u32) -> i32 {
+    let Some(gz_file) = env.framework_state.zlib.gz_files.get_mut(&file) else {
+        return 0;
+    };
+    if gz_file.write_level.is_none() {
+        return 0;
+    }
+    gz_file
+        .data
+        .extend_from_slice(env.mem.bytes_at(buf.cast(), len));
+    len as i32
+}
+
+fn gzeof(env: &mut Environment, file: MutVoidPtr) -> i32 {
+    let Some(gz_file) = State::get_mut(env).gz_files.get(&file) else {
+        return 0;
+    };
+    (gz_file.write_level.is_none() && gz_file.position == gz_file.data.len()).into()
+}
+
+fn gzclose(env: &mut Environment, file: MutVoidPtr) -> i32 {
+    let Some(gz_file) = State::get_mut(env).gz_files.remove(&file) else {
+        return Z_STREAM_ERROR;
+    };
+    env.mem.free(file);
+    let Some(level) = gz_file.write_level else {
+        return Z_OK;
+    };
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+    encoder.write_all(&gz_file.data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    if env.fs.write(&gz_file.path, &compressed).is_err() {
+        log!("Warning: gzclose() couldn't write {:?}", gz_file.path);
+        return -1; // Z_ERRNO
+    }
+    Z_OK
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(zlibVersion()),
+    export_c_func!(inflateInit_(_, _, _)),
+    export_c_func!(inflateInit2_(_, _, _, _)),
+    export_c_func!(inflate(_, _)),
+    export_c_func!(inflateReset(_)),
+    export_c_func!(inflateEnd(_)),
+    export_c_func!(deflateInit_(_, _, _, _)),
+    export_c_func!(deflateInit2_(_, _, _, _, _, _, _, _)),
+    export_c_func!(deflate(_, _)),
+    export_c_func!(deflateReset(_)),
+    export_c_func!(deflateEnd(_)),
+    export_c_func!(deflateBound(_, _)),
+    export_c_func!(compressBound(_)),
+    export_c_func!(compress(_, _, _, _)),
+    export_c_func!(compress2(_, _, _, _, _)),
+    export_c_func!(uncompress(_, _, _, _)),
+    export_c_func!(crc32(_, _, _)),
+    export_c_func!(adler32(_, _, _)),
+    export_c_func!(gzopen(_, _)),
+    export_c_func!(gzread(_, _, _)),
+    export_c_func!(gzwrite(_, _, _)),
+    export_c_func!(gzeof(_)),
+    export_c_func!(gzclose(_)),
+];
@@ -14,10 +14,11 @@
 //!
 //! All files in the guest filesystem must have a corresponding file in the host
 //! filesystem, or a corresponding file inside a `.ipa` file (ZIP archive) in
-//! the host filesystem. Accessing a file requires traversing the guest
-//! filesystem's directory structure to find out the host path, or ZIP file
-//! member. After that point, the underlying file is accessed directly; there is
-//! no virtualization of file I/O.
+//! the host filesystem, except for a few stand-ins for OS files, whose
+//! contents are generated by touchHLE (see [system_files]). Accessing a file
+//! requires traversing the guest filesystem's directory structure to find out
+//! the host path, or ZIP file member. After that point, the underlying file is
+//! accessed directly; there is no virtualization of file I/O.
 //!
 //! Directories only need a corresponding directory in the host filesystem if
 //! they are writeable (i.e. if new files can be created in them).
@@ -25,6 +26,7 @@
 //! See also [crate::paths], which has paths for host files used by touchHLE.
 
 mod bundle;
+mod system_files;
 
 pub use bundle::BundleData;
 
@@ -32,8 +34,9 @@ use crate::fs::bundle::{IpaFile, IpaFileRef};
 use crate::paths;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Seek, Write};
+use std::io::{Cursor, Read, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 /// The actual location of a file outside the virtual filesystem, e.g. a host
 /// file path.
@@ -45,6 +48,8 @@ enum FileLocation {
     IpaFileRef(IpaFileRef),
     /// Name of a resource file bundled with touchHLE. Read only.
     ResourceFilePath(String),
+    /// Contents of a file generated by touchHLE. Read only.
+    Generated(Rc<[u8]>),
 }
 
 #[derive(Debug)]
@@ -131,6 +136,12 @@ impl FsNode {
             writeable: false,
         }
     }
+    fn generated_file<T: AsRef<[u8]>>(contents: T) -> Self {
+        FsNode::File {
+            location: FileLocation::Generated(contents.as_ref().into()),
+            writeable: false,
+        }
+    }
 }
 
 // Put well-known paths in the guest filesystem here.
@@ -341,12 +352,26 @@ fn handle_open_err<T, E: std::fmt::Display, P: std::fmt::Debug>(
     }
 }
 
+/// Like [std::fs::Metadata] but for the guest filesystem. Only the information
+/// that the guest filesystem can provide is included.
+#[derive(Debug)]
+pub struct GuestMetadata {
+    pub is_dir: bool,
+    pub writeable: bool,
+    /// Size in bytes. Always zero for directories.
+    pub len: u64,
+    /// Time of last modification, if known. This is only available for things
+    /// backed by a host file or directory.
+    pub modified: Option<std::time::SystemTime>,
+}
+
 /// Like [File] but for the guest filesystem.
 #[derive(Debug)]
 pub enum GuestFile {
     File(File),
     IpaBundleFile(IpaFile),
     ResourceFile(paths::ResourceFile),
+    GeneratedFile(Cursor<Rc<[u8]>>),
 }
 
 impl GuestFile {
@@ -362,10 +387,16 @@ impl GuestFile {
         GuestFile::ResourceFile(file)
     }
 
+    fn from_generated_file(contents: &Rc<[u8]>) -> GuestFile {
+        GuestFile::GeneratedFile(Cursor::new(contents.clone()))
+    }
+
     pub fn sync_all(&self) -> std::io::Result<()> {
         match self {
             GuestFile::File(file) => file.sync_all(),
-            GuestFile::IpaBundleFile(_) | GuestFile::ResourceFile(_) => Ok(()),
+            GuestFile::IpaBundleFile(_)
+            | GuestFile::ResourceFile(_)
+            | GuestFile::GeneratedFile(_) => Ok(()),
         }
     }
     pub fn set_len(&self, len: u64) -> std::io::Result<()> {
@@ -377,6 +408,39 @@ impl GuestFile {
             GuestFile::ResourceFile(file) => {
                 panic!("Attempt to resize a read-only file: {:?}", file)
             }
+            GuestFile::GeneratedFile(file) => {
+                panic!("Attempt to resize a read-only file: {:?}", file)
+            }
+        }
+    }
+
+    /// Like [File::metadata] but for the guest filesystem.
+    pub fn metadata(&mut self) -> std::io::Result<GuestMetadata> {
+        match self {
+            GuestFile::File(file) => {
+                let metadata = file.metadata()?;
+                Ok(GuestMetadata {
+                    is_dir: false,
+                    writeable: !metadata.permissions().readonly(),
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                })
+            }
+            GuestFile::IpaBundleFile(_)
+            | GuestFile::ResourceFile(_)
+            | GuestFile::GeneratedFile(_) => {
+                // TODO: Use the stream_len() method if that ever gets
+                // stabilized.
+                let old_pos = self.stream_position()?;
+                let len = self.seek(std::io::SeekFrom::End(0))?;
+                self.seek(std::io::SeekFrom::Start(old_pos))?;
+                Ok(GuestMetadata {
+                    is_dir: false,
+                    writeable: false,
+                    len,
+                    modified: None,
+                })
+            }
         }
     }
 }
@@ -387,6 +451,7 @@ impl Read for GuestFile {
             GuestFile::File(file) => file.read(buf),
             GuestFile::IpaBundleFile(file) => file.read(buf),
             GuestFile::ResourceFile(file) => file.get().read(buf),
+            GuestFile::GeneratedFile(file) => file.read(buf),
         }
     }
 }
@@ -401,6 +466,9 @@ impl Write for GuestFile {
             GuestFile::ResourceFile(file) => {
                 panic!("Attempt to write to a read-only file: {:?}", file)
             }
+            GuestFile::GeneratedFile(file) => {
+                panic!("Attempt to write to a read-only file: {:?}", file)
+            }
         }
     }
 
@@ -413,6 +481,9 @@ impl Write for GuestFile {
             GuestFile::ResourceFile(file) => {
                 panic!("Attempt to flush a read-only file: {:?}", file)
             }
+            GuestFile::GeneratedFile(file) => {
+                panic!("Attempt to flush a read-only file: {:?}", file)
+            }
         }
     }
 }
@@ -423,6 +494,7 @@ impl Seek for GuestFile {
             GuestFile::File(file) => file.seek(pos),
             GuestFile::IpaBundleFile(file) => file.seek(pos),
             GuestFile::ResourceFile(file) => file.get().seek(pos),
+            GuestFile::GeneratedFile(file) => file.seek(pos),
         }
     }
 }
@@ -433,6 +505,7 @@ pub struct Fs {
     root: FsNode,
     working_directory: GuestPathBuf,
     home_directory: GuestPathBuf,
+    read_only_mode: bool,
 }
 impl Fs {
     /// Construct a filesystem containing a home directory for the app, its
@@ -527,6 +600,7 @@ impl Fs {
                 ),
             )
             .with_child("usr", FsNode::dir().with_child("lib", usr_lib));
+        let root = system_files::add_system_files(root);
 
         log_dbg!("Initial filesystem layout: {:#?}", root);
 
@@ -534,20 +608,35 @@ impl Fs {
             root,
             working_directory,
             home_directory,
+            read_only_mode,
         };
         assert!(fs.lookup_node(&bundle_guest_path).is_some());
         (fs, bundle_guest_path)
     }
 
+    /// Add the files that apps commonly look for to detect a jailbroken device
+    /// (`--jailbroken` option). See [system_files].
+    pub fn add_jailbreak_files(&mut self) {
+        system_files::add_jailbreak_files(&mut self.root);
+    }
+
     /// Create a fake filesystem (see [crate::Environment::new_without_app]).
     pub fn new_fake_fs() -> Fs {
         Fs {
             root: FsNode::dir(),
             working_directory: GuestPathBuf::from(String::new()),
             home_directory: GuestPathBuf::from(String::new()),
+            read_only_mode: true,
         }
     }
 
+    /// Whether the filesystem was created in read-only mode (see [Fs::new]),
+    /// in which case nothing should be written to the app's sandbox directory
+    /// on the host.
+    pub fn is_read_only_mode(&self) -> bool {
+        self.read_only_mode
+    }
+
     /// Get the absolute path of the guest app's (sandboxed) home directory.
     pub fn home_directory(&self) -> &GuestPath {
         &self.home_directory
@@ -599,8 +688,25 @@ impl Fs {
     }
 
     /// Get the node at a given path, if it exists.
+    ///
+    /// Failed lookups of OS paths outside the app's home directory are logged,
+    /// because touchHLE only provides a few OS files (see [system_files]) and
+    /// a missing one might explain an app's behaviour.
     fn lookup_node(&self, path: &GuestPath) -> Option<&FsNode> {
-        self.lookup_node_inner(&resolve_path(path, Some(&self.working_directory)))
+        let resolved = resolve_path(path, Some(&self.working_directory));
+        let node = self.lookup_node_inner(&resolved);
+        if node.is_none()
+            && resolved
+                .first()
+                .is_some_and(|&dir| system_files::SYSTEM_DIRS.contains(&dir))
+            && !resolved.starts_with(&resolve_path(&self.home_directory, None))
+        {
+            log!(
+                "Warning: app tried to access nonexistent system path {:?}",
+                path
+            );
+        }
+        node
     }
 
     /// Get the parent of the node at a given path, if it exists, and return it
@@ -649,6 +755,46 @@ impl Fs {
         }
     }
 
+    /// Like [std::fs::metadata] but for the guest filesystem.
+    pub fn metadata<P: AsRef<GuestPath>>(&self, path: P) -> Result<GuestMetadata, ()> {
+        let node = self.lookup_node(path.as_ref()).ok_or(())?;
+        Ok(match node {
+            FsNode::File {
+                location,
+                writeable,
+            } => {
+                let (len, modified) = match location {
+                    FileLocation::Path(host_path) => {
+                        let metadata = handle_open_err(std::fs::metadata(host_path), host_path);
+                        (metadata.len(), metadata.modified().ok())
+                    }
+                    FileLocation::IpaFileRef(file) => (file.size(), None),
+                    FileLocation::ResourceFilePath(name) => {
+                        let mut file = handle_open_err(paths::ResourceFile::open(name), name);
+                        let len = file.get().seek(std::io::SeekFrom::End(0)).unwrap();
+                        (len, None)
+                    }
+                    FileLocation::Generated(contents) => (contents.len() as u64, None),
+                };
+                GuestMetadata {
+                    is_dir: false,
+                    writeable: *writeable,
+                    len,
+                    modified,
+                }
+            }
+            FsNode::Directory { writeable, .. } => GuestMetadata {
+                is_dir: true,
+                writeable: writeable.is_some(),
+                len: 0,
+                modified: writeable
+                    .as_ref()
+                    .and_then(|host_path| std::fs::metadata(host_path).ok())
+                    .and_then(|metadata| metadata.modified().ok()),
+            },
+        })
+    }
+
     /// Like [Path::is_file] but for the guest filesystem.
     pub fn is_file(&self, path: &GuestPath) -> bool {
         matches!(self.lookup_node(path), Some(FsNode::File { .. }))
@@ -659,6 +805,19 @@ impl Fs {
         matches!(self.lookup_node(path), Some(FsNode::Directory { .. }))
     }
 
+    /// Get the host path of a writeable file. This is only for host libraries
+    /// that must do their own file I/O (e.g. SQLite), and it should be avoided
+    /// otherwise.
+    pub fn writeable_file_host_path(&self, path: &GuestPath) -> Option<&Path> {
+        match self.lookup_node(path) {
+            Some(FsNode::File {
+                location: FileLocation::Path(host_path),
+                writeable: true,
+            }) => Some(host_path),
+            _ => None,
+        }
+    }
+
     /// Get an iterator over the names of files/directories in a directory.
     pub fn enumerate<P: AsRef<GuestPath>>(
         &self,
@@ -740,6 +899,7 @@ impl Fs {
                     let resource_file = handle_open_err(paths::ResourceFile::open(name), name);
                     Ok(GuestFile::from_resource_file(resource_file))
                 }
+                FileLocation::Generated(contents) => Ok(GuestFile::from_generated_file(contents)),
             },
             FsNode::Directory { .. } => Err(()),
         }
@@ -807,6 +967,10 @@ impl Fs {
                                 handle_open_err(paths::ResourceFile::open(name), name);
                             return Ok(GuestFile::from_resource_file(resource_file));
                         }
+                        FileLocation::Generated(contents) => {
+                            assert!(!(writeable || append || write));
+                            return Ok(GuestFile::from_generated_file(contents));
+                        }
                     }
                 }
                 FsNode::Directory { .. } => {
@@ -901,7 +1065,9 @@ impl Fs {
 
                 let host_path = match location {
                     FileLocation::Path(host_path) => host_path,
-                    FileLocation::IpaFileRef(_) | FileLocation::ResourceFilePath(_) => panic!(),
+                    FileLocation::IpaFileRef(_)
+                    | FileLocation::ResourceFilePath(_)
+                    | FileLocation::Generated(_) => panic!(),
                 };
 
                 handle_open_err(std::fs::remove_file(host_path), host_path);
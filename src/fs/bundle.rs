@@ -5,13 +5,21 @@
  */
 //! IPA file format support, allowing it to be used as part of the guest
 //! filesystem.
+//!
+//! Normally, files are read directly from the IPA (a zip file) when the app
+//! opens them, using the archive's index to find them, so nothing is
+//! extracted up-front. Alternatively, with `--extract-ipa`, the whole app
+//! bundle can be extracted to the host filesystem once, in parallel, and the
+//! extracted copy reused on later runs (see [BundleData::extract_to_cache]).
 use crate::fs::{FsNode, GuestPath};
+use crate::paths;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use zip::result::ZipError;
 use zip::ZipArchive;
 
@@ -71,6 +79,8 @@ pub enum BundleData {
     HostDirectory(PathBuf),
     Zip {
         zip: ZipArchive<std::fs::File>,
+        /// Host path of the zip file.
+        ipa_path: PathBuf,
         /// Path to the app bundle inside the zip file.
         /// It should be `"Payload/<app name>.app"` (no trailing slash!).
         bundle_path: String,
@@ -95,6 +105,14 @@ impl BundleData {
         Err("no app bundle found in the IPA archive".to_string())
     }
 
+    /// Get the path of an archive entry relative to the app bundle, with a
+    /// leading `/`, or [None] if it's outside the bundle. `"Payload/X.app2/"`
+    /// is not inside `"Payload/X.app"`.
+    fn path_in_bundle<'a>(name: &'a str, bundle_path: &str) -> Option<&'a str> {
+        name.strip_prefix(bundle_path)
+            .filter(|path| path.is_empty() || path.starts_with('/'))
+    }
+
     pub fn bundle_name(&self) -> &str {
         match self {
             BundleData::HostDirectory(bundle_path) => {
@@ -119,7 +137,11 @@ impl BundleData {
         let mut zip =
             ZipArchive::new(file).map_err(|e| format!("Could not open IPA archive: {e}"))?;
         let bundle_path = Self::find_bundle_path_in_archive(&mut zip)?;
-        Ok(BundleData::Zip { zip, bundle_path })
+        Ok(BundleData::Zip {
+            zip,
+            ipa_path: path.to_path_buf(),
+            bundle_path,
+        })
     }
 
     pub fn open_any(path: &Path) -> Result<BundleData, String> {
@@ -143,7 +165,9 @@ impl BundleData {
     pub(super) fn into_fs_node(self) -> FsNode {
         match self {
             BundleData::HostDirectory(path) => FsNode::from_host_dir(&path, false),
-            BundleData::Zip { zip, bundle_path } => {
+            BundleData::Zip {
+                zip, bundle_path, ..
+            } => {
                 let archive = Rc::new(RefCell::new(zip));
                 let archive_cache = Rc::new(RefCell::new(HashMap::new()));
 
@@ -153,7 +177,7 @@ impl BundleData {
                 for i in 0..archive_guard.len() {
                     let file = archive_guard.by_index(i).unwrap(); // TODO: report IO error?
                     let name = file.name();
-                    if let Some(path) = name.strip_prefix(&bundle_path) {
+                    if let Some(path) = Self::path_in_bundle(name, &bundle_path) {
                         let path = GuestPath::new(path);
                         if file.is_dir() {
                             builder.add_directory(path);
@@ -162,8 +186,9 @@ impl BundleData {
                                 path,
                                 FsNode::bundle_zip_file(IpaFileRef {
                                     archive: archive.clone(),
-                                    archive_cursor_cache: archive_cache.clone(),
+                                    archive_cache: archive_cache.clone(),
                                     index: i,
+                                    size: file.size(),
                                 }),
                             );
                         }
@@ -181,7 +206,9 @@ impl BundleData {
                     format!("Could not read Info.plist from the app bundle directory: {e}")
                 })
             }
-            BundleData::Zip { zip, bundle_path } => {
+            BundleData::Zip {
+                zip, bundle_path, ..
+            } => {
                 let mut file = zip
                     .by_name(&format!("{bundle_path}/Info.plist"))
                     .map_err(|e| format!("Could not open Info.plist from the IPA archive: {e}"))?;
@@ -192,14 +219,160 @@ impl BundleData {
             }
         }
     }
+
+    /// Extract the app bundle from an IPA to the host filesystem, or reuse a
+    /// previous extraction of the same IPA, and return the extracted bundle.
+    /// Does nothing for a bundle that is already a host directory.
+    ///
+    /// The extraction is cached in [paths::IPA_CACHE_DIR], keyed by a hash of
+    /// the archive's contents (see [Self::contents_hash]), so a modified IPA
+    /// gets a new extraction even if it has the same file name and layout.
+    /// The files are decompressed in parallel, with one thread per CPU core.
+    pub fn extract_to_cache(self) -> Result<BundleData, String> {
+        let BundleData::Zip {
+            mut zip,
+            ipa_path,
+            bundle_path,
+        } = self
+        else {
+            return Ok(self);
+        };
+
+        let key = Self::contents_hash(&mut zip)?;
+        let cache_dir = paths::user_data_base_path().join(paths::IPA_CACHE_DIR);
+        let extracted_path = cache_dir.join(format!("{key:016x}"));
+        if !extracted_path.is_dir() {
+            echo!(
+                "Extracting {} to {}...",
+                ipa_path.display(),
+                extracted_path.display()
+            );
+            // Extract to a temporary directory first, so that an interrupted
+            // extraction can't be mistaken for a complete one.
+            let partial_path = cache_dir.join(format!("{key:016x}.partial"));
+            if partial_path.exists() {
+                std::fs::remove_dir_all(&partial_path).map_err(|e| {
+                    format!("Could not remove incomplete extraction of the IPA: {e}")
+                })?;
+            }
+            Self::extract_in_parallel(&mut zip, &ipa_path, &bundle_path, &partial_path)?;
+            std::fs::rename(&partial_path, &extracted_path)
+                .map_err(|e| format!("Could not finish extraction of the IPA: {e}"))?;
+        }
+        Self::open_host_dir(&extracted_path.join(bundle_path))
+    }
+
+    /// Hash the archive's contents, using FNV-1a so the result is the same
+    /// between touchHLE versions. Each entry contributes its name, size and
+    /// CRC-32 of its data, which the archive records, so nothing has to be
+    /// decompressed.
+    fn contents_hash(zip: &mut ZipArchive<std::fs::File>) -> Result<u64, String> {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut feed = |bytes: &[u8]| {
+            for &byte in bytes {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for i in 0..zip.len() {
+            let file = zip
+                .by_index_raw(i)
+                .map_err(|e| format!("Could not open IPA archive entry: {e}"))?;
+            feed(file.name().as_bytes());
+            feed(&[0]);
+            feed(&file.crc32().to_le_bytes());
+            feed(&file.size().to_le_bytes());
+        }
+        Ok(hash)
+    }
+
+    /// Extract all the files in the app bundle to `dest_path`. Each thread
+    /// opens the IPA separately, because the archive reader needs exclusive
+    /// access to the file.
+    fn extract_in_parallel(
+        zip: &mut ZipArchive<std::fs::File>,
+        ipa_path: &Path,
+        bundle_path: &str,
+        dest_path: &Path,
+    ) -> Result<(), String> {
+        let mut files = Vec::new();
+        for i in 0..zip.len() {
+            let file = zip
+                .by_index_raw(i)
+                .map_err(|e| format!("Could not open IPA archive entry: {e}"))?;
+            let Some(path) = Self::path_in_bundle(file.name(), bundle_path) else {
+                continue;
+            };
+            // Don't let a malicious archive write outside the destination.
+            if path
+                .split('/')
+                .any(|part| part == ".." || part.contains('\\') || part.contains(':'))
+            {
+                return Err(format!("Unexpected path in IPA archive: {:?}", file.name()));
+            }
+            let host_path = dest_path
+                .join(bundle_path)
+                .join(path.trim_start_matches('/'));
+            if file.is_dir() {
+                std::fs::create_dir_all(&host_path)
+                    .map_err(|e| format!("Could not create directory {host_path:?}: {e}"))?;
+            } else {
+                files.push((i, host_path, file.size()));
+            }
+        }
+        // Start with the biggest files so that the threads finish at around
+        // the same time.
+        files.sort_by_key(|&(_, _, size)| std::cmp::Reverse(size));
+
+        let thread_count = std::thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(files.len().max(1));
+        let next_file = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..thread_count)
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), String> {
+                        let file = std::fs::File::open(ipa_path)
+                            .map_err(|e| format!("Could not open IPA file: {e}"))?;
+                        let mut zip = ZipArchive::new(file)
+                            .map_err(|e| format!("Could not open IPA archive: {e}"))?;
+                        loop {
+                            let Some((index, host_path, _)) =
+                                files.get(next_file.fetch_add(1, Ordering::Relaxed))
+                            else {
+                                return Ok(());
+                            };
+                            let mut file = zip
+                                .by_index(*index)
+                                .map_err(|e| format!("Could not open IPA archive entry: {e}"))?;
+                            if let Some(parent) = host_path.parent() {
+                                std::fs::create_dir_all(parent).map_err(|e| {
+                                    format!("Could not create directory {parent:?}: {e}")
+                                })?;
+                            }
+                            let mut out = std::fs::File::create(host_path)
+                                .map_err(|e| format!("Could not create {host_path:?}: {e}"))?;
+                            std::io::copy(&mut file, &mut out)
+                                .map_err(|e| format!("Could not extract {host_path:?}: {e}"))?;
+                        }
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .try_for_each(|thread| thread.join().unwrap())
+        })
+    }
 }
 
 /// Represents a file inside an IPA bundle that can be opened.
 #[derive(Debug)]
 pub struct IpaFileRef {
     archive: Rc<RefCell<ZipArchive<std::fs::File>>>,
-    archive_cursor_cache: Rc<RefCell<HashMap<usize, std::io::Cursor<Vec<u8>>>>>,
+    archive_cache: Rc<RefCell<HashMap<usize, Rc<[u8]>>>>,
     index: usize,
+    /// Uncompressed size, recorded when indexing the archive.
+    size: u64,
 }
 
 impl IpaFileRef {
@@ -210,8 +383,10 @@ impl IpaFileRef {
         // done each time, which is extremely slow.
         // The solution here is to cache unzipped data in memory, which should
         // be OK as early iOS IPA files are relatively small in size.
-        let mut archive_cache = (*self.archive_cursor_cache).borrow_mut();
-        archive_cache.entry(self.index).or_insert_with(|| {
+        // The data is shared between all the open files, so opening a file
+        // again doesn't copy it.
+        let mut archive_cache = (*self.archive_cache).borrow_mut();
+        let data = archive_cache.entry(self.index).or_insert_with(|| {
             let mut archive = (*self.archive).borrow_mut();
             let mut file = match archive.by_index(self.index) {
                 Ok(file) => file,
@@ -224,13 +399,18 @@ impl IpaFileRef {
                 // always have a valid index
                 Err(e) => panic!("BUG: could not open file from IPA bundle: {e}"),
             };
-            let mut buf = Vec::new();
+            let mut buf = Vec::with_capacity(self.size as usize);
             file.read_to_end(&mut buf).unwrap();
-            std::io::Cursor::new(buf)
+            buf.into()
         });
-        let mut cursor = archive_cache.get(&self.index).unwrap().clone();
-        cursor.set_position(0);
-        IpaFile { file: cursor }
+        IpaFile {
+            file: std::io::Cursor::new(data.clone()),
+        }
+    }
+
+    /// Get the uncompressed size of the file without decompressing it.
+    pub fn size(&self) -> u64 {
+        self.size
     }
 }
 
@@ -240,7 +420,7 @@ pub struct IpaFile {
     // and, generally, seeking in compressed files is hard to achieve
     // the simplest way to do it is to read the whole file into memory
     // the target apps should be small enough to fit in memory, right?
-    file: std::io::Cursor<Vec<u8>>,
+    file: std::io::Cursor<Rc<[u8]>>,
 }
 
 impl Debug for IpaFile {
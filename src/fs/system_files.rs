@@ -0,0 +1,168 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Read-only stand-ins for files that are part of iPhone OS itself.
+//!
+//! Apps sometimes read these to find out the OS version, or check whether they
+//! exist to detect a jailbreak, so they should look like they would on an
+//! unmodified device. Only a handful of files are provided; accesses to other
+//! system paths are logged (see [super::Fs::lookup_node]).
+//!
+//! With the `--jailbroken` option, files that are typical of a jailbroken
+//! device are added too (see [add_jailbreak_files]). The other parts of that
+//! option are in [crate::libc::unistd] (`fork()`) and
+//! [crate::frameworks::uikit::ui_application] (URL schemes).
+
+use super::FsNode;
+use crate::libc::sysctl::{PRODUCT_BUILD_VERSION, PRODUCT_VERSION};
+
+/// Top-level directories that belong to the OS rather than the app. Failed
+/// lookups of paths in these are logged, except in the app's home directory.
+pub(super) const SYSTEM_DIRS: &[&str] = &[
+    "Applications",
+    "Library",
+    "System",
+    "bin",
+    "dev",
+    "etc",
+    "private",
+    "sbin",
+    "usr",
+    "var",
+];
+
+const PASSWD: &str = "\
+##
+# User Database
+#
+# This file is the authoritative user database.
+##
+nobody:*:-2:-2:Unprivileged User:/var/empty:/usr/bin/false
+root:*:0:0:System Administrator:/var/root:/bin/sh
+mobile:*:501:501:Mobile User:/var/mobile:/bin/sh
+daemon:*:1:1:System Services:/var/root:/usr/bin/false
+_securityd:*:64:64:securityd:/var/empty:/usr/bin/false
+_mdnsresponder:*:65:65:mDNSResponder:/var/empty:/usr/bin/false
+_sshd:*:75:75:sshd Privilege separation:/var/empty:/usr/bin/false
+_unknown:*:99:99:Unknown User:/var/empty:/usr/bin/false
+";
+
+const GROUP: &str = "\
+##
+# Group Database
+##
+nobody:*:-2:
+nogroup:*:-1:
+wheel:*:0:root
+daemon:*:1:root
+kmem:*:2:root
+sys:*:3:root
+tty:*:4:root
+operator:*:5:root
+mobile:*:501:
+staff:*:20:root
+admin:*:80:root
+_unknown:*:99:
+";
+
+const HOSTS: &str = "\
+##
+# Host Database
+#
+# localhost is used to configure the loopback interface
+# when the system is booting.  Do not change this entry.
+##
+127.0.0.1\tlocalhost
+255.255.255.255\tbroadcasthost
+::1             localhost
+";
+
+const FSTAB: &str = "\
+/dev/disk0s1 / hfs ro 0 1
+/dev/disk0s2 /private/var hfs rw,nosuid,nodev 0 2
+";
+
+fn system_version_plist() -> String {
+    format!(
+        "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+<plist version=\"1.0\">
+<dict>
+\t<key>ProductBuildVersion</key>
+\t<string>{}</string>
+\t<key>ProductCopyright</key>
+\t<string>1983-2008 Apple Inc.</string>
+\t<key>ProductName</key>
+\t<string>iPhone OS</string>
+\t<key>ProductVersion</key>
+\t<string>{}</string>
+</dict>
+</plist>
+",
+        PRODUCT_BUILD_VERSION, PRODUCT_VERSION
+    )
+}
+
+fn etc() -> FsNode {
+    FsNode::dir()
+        .with_child("passwd", FsNode::generated_file(PASSWD))
+        .with_child("group", FsNode::generated_file(GROUP))
+        .with_child("hosts", FsNode::generated_file(HOSTS))
+        .with_child("fstab", FsNode::generated_file(FSTAB))
+}
+
+/// Add the system files to the root directory. `/etc` is a symlink to
+/// `/private/etc` on the device, so its contents appear in both places.
+pub(super) fn add_system_files(root: FsNode) -> FsNode {
+    root.with_child(
+        "System",
+        FsNode::dir().with_child(
+            "Library",
+            FsNode::dir().with_child(
+                "CoreServices",
+                FsNode::dir().with_child(
+                    "SystemVersion.plist",
+                    FsNode::generated_file(system_version_plist()),
+                ),
+            ),
+        ),
+    )
+    .with_child("etc", etc())
+    .with_child("private", FsNode::dir().with_child("etc", etc()))
+}
+
+/// Files whose existence apps check to detect a jailbroken device.
+const JAILBREAK_FILES: &[&str] = &[
+    "/Applications/Cydia.app/Info.plist",
+    "/Library/MobileSubstrate/MobileSubstrate.dylib",
+    "/bin/bash",
+    "/bin/sh",
+    "/etc/apt/sources.list.d/cydia.list",
+    "/private/var/lib/apt/extended_states",
+    "/usr/bin/ssh",
+    "/usr/sbin/sshd",
+];
+
+/// Add files typical of a jailbroken device. Their contents are empty, since
+/// apps only check whether they exist.
+pub(super) fn add_jailbreak_files(root: &mut FsNode) {
+    for path in JAILBREAK_FILES {
+        let (dir_path, name) = path.rsplit_once('/').unwrap();
+        let mut dir = &mut *root;
+        for component in dir_path.split('/').filter(|c| !c.is_empty()) {
+            let FsNode::Directory { children, .. } = dir else {
+                panic!();
+            };
+            dir = children
+                .entry(component.to_string())
+                .or_insert_with(FsNode::dir);
+        }
+        let FsNode::Directory { children, .. } = dir else {
+            panic!();
+        };
+        children.insert(name.to_string(), FsNode::generated_file(b""));
+    }
+}
@@ -18,6 +18,8 @@ fn main() {
         Fallbacks::None,
         [
             "GL_EXT_framebuffer_object",
+            "GL_EXT_framebuffer_multisample",
+            "GL_EXT_framebuffer_blit",
             "GL_EXT_texture_filter_anisotropic",
             "GL_EXT_texture_lod_bias",
             "GL_ARB_matrix_palette",
@@ -35,6 +37,7 @@ fn main() {
         Fallbacks::None,
         [
             "GL_OES_framebuffer_object",
+            "GL_APPLE_framebuffer_multisample",
             "GL_OES_rgb8_rgba8",
             "GL_EXT_texture_filter_anisotropic",
             "GL_IMG_texture_compression_pvrtc",
@@ -44,6 +47,8 @@ fn main() {
             // Part of the OpenGL ES 1.1 common profile.
             "GL_OES_compressed_paletted_texture",
             "GL_OES_matrix_palette",
+            "GL_OES_point_size_array",
+            "GL_OES_point_sprite",
         ],
     )
     .write_bindings(GlobalGenerator, &mut file)
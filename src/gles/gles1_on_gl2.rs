@@ -27,7 +27,7 @@ use super::util::{
 };
 use super::GLES;
 use crate::window::{GLContext, GLVersion, Window};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
 
 /// List of capabilities shared by OpenGL ES 1.1 and OpenGL 2.1.
@@ -88,11 +88,12 @@ struct ArrayStateBackup {
     size: Option<GLint>,
     stride: GLsizei,
     pointer: *const GLvoid,
+    buffer_binding: GLuint,
 }
 
 /// List of arrays shared by OpenGL ES 1.1 and OpenGL 2.1.
 ///
-/// TODO: GL_POINT_SIZE_ARRAY_OES?
+/// `GL_POINT_SIZE_ARRAY_OES` is not shared, see [PointSizeArray].
 pub const ARRAYS: &[ArrayInfo] = &[
     ArrayInfo {
         name: gl21::COLOR_ARRAY,
@@ -215,7 +216,7 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::POINT_DISTANCE_ATTENUATION, ParamType::Float, 3),
     (gl21::POINT_FADE_THRESHOLD_SIZE, ParamType::Float, 1),
     (gl21::POINT_SIZE, ParamType::Float, 1),
-    // TODO: POINT_SIZE_ARRAY_OES etc? (not shared)
+    // POINT_SIZE_ARRAY_OES etc are not shared, see PointSizeArray
     (gl21::POINT_SIZE_MAX, ParamType::Float, 1),
     (gl21::POINT_SIZE_MIN, ParamType::Float, 1),
     (gl21::POINT_SIZE_RANGE, ParamType::Float, 2),
@@ -271,6 +272,10 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     // OES_framebuffer_object -> EXT_framebuffer_object
     (gl21::FRAMEBUFFER_BINDING_EXT, ParamType::Int, 1),
     (gl21::RENDERBUFFER_BINDING_EXT, ParamType::Int, 1),
+    // APPLE_framebuffer_multisample -> EXT_framebuffer_multisample and
+    // EXT_framebuffer_blit (DRAW_FRAMEBUFFER_BINDING is FRAMEBUFFER_BINDING)
+    (gl21::MAX_SAMPLES_EXT, ParamType::Int, 1),
+    (gl21::READ_FRAMEBUFFER_BINDING_EXT, ParamType::Int, 1),
     // EXT_texture_lod_bias
     (gl21::MAX_TEXTURE_LOD_BIAS_EXT, ParamType::Float, 1),
     // OES_matrix_palette -> ARB_matrix_palette
@@ -359,143 +364,316 @@ const TEX_PARAMS: ParamTable = ParamTable(&[
     (gl21::MAX_TEXTURE_MAX_ANISOTROPY_EXT, ParamType::Float, 1),
 ]);
 
+/// State of the `GL_OES_point_size_array` array. OpenGL 2.1 has no equivalent
+/// of this (only shaders can vary the point size per vertex), so it's tracked
+/// here and emulated at draw time by [GLES1OnGL2::draw_points_with_sizes].
+struct PointSizeArray {
+    enabled: bool,
+    type_: GLenum,
+    stride: GLsizei,
+    /// Pointer, or offset if `buffer_binding` is not 0.
+    pointer: *const GLvoid,
+    /// Value of `GL_ARRAY_BUFFER_BINDING` at the time of the
+    /// `glPointSizePointerOES` call.
+    buffer_binding: GLuint,
+}
+impl Default for PointSizeArray {
+    fn default() -> Self {
+        PointSizeArray {
+            enabled: false,
+            type_: gl21::FLOAT,
+            stride: 0,
+            pointer: std::ptr::null(),
+            buffer_binding: 0,
+        }
+    }
+}
+
+/// Gets `len` bytes at `pointer_or_offset`, which is an offset into `buffer`
+/// bound to `target` if `buffer` is not 0, or a pointer otherwise. Data from a
+/// buffer object is copied into `staging`, which is reused between draw calls
+/// so that this doesn't allocate each time.
+unsafe fn array_data(
+    target: GLenum,
+    binding: GLenum,
+    buffer: GLuint,
+    pointer_or_offset: *const GLvoid,
+    len: usize,
+    staging: &mut Vec<u8>,
+) -> &[u8] {
+    if buffer == 0 {
+        return std::slice::from_raw_parts(pointer_or_offset.cast::<u8>(), len);
+    }
+    let mut old_buffer = 0;
+    gl21::GetIntegerv(binding, &mut old_buffer);
+    gl21::BindBuffer(target, buffer);
+    staging.clear();
+    staging.resize(len, 0);
+    gl21::GetBufferSubData(
+        target,
+        pointer_or_offset as GLintptr,
+        len as GLsizeiptr,
+        staging.as_mut_ptr().cast(),
+    );
+    gl21::BindBuffer(target, old_buffer as GLuint);
+    staging
+}
+
+/// Calls `f`, which should set an array pointer, while `buffer` is bound to
+/// `GL_ARRAY_BUFFER`. OpenGL takes an array's buffer binding from that at the
+/// time its pointer is set, so this is how it is changed.
+unsafe fn with_array_buffer(buffer: GLuint, f: impl FnOnce()) {
+    let mut old_buffer = 0;
+    gl21::GetIntegerv(gl21::ARRAY_BUFFER_BINDING, &mut old_buffer);
+    let old_buffer = old_buffer as GLuint;
+    if old_buffer == buffer {
+        return f();
+    }
+    gl21::BindBuffer(gl21::ARRAY_BUFFER, buffer);
+    f();
+    gl21::BindBuffer(gl21::ARRAY_BUFFER, old_buffer);
+}
+
+/// Size in bytes of a component of an array type that needs translation, see
+/// [GLES1OnGL2::translate_arrays].
+fn emulated_component_size(type_: GLenum) -> usize {
+    match type_ {
+        gles11::FIXED => 4,
+        gl21::BYTE => 1,
+        _ => unreachable!(),
+    }
+}
+
 pub struct GLES1OnGL2 {
     gl_ctx: GLContext,
-    pointer_is_fixed_point: [bool; ARRAYS.len()],
-    fixed_point_texture_units: HashSet<GLenum>,
-    fixed_point_translation_buffers: [Vec<GLfloat>; ARRAYS.len()],
+    /// For each array in [ARRAYS], the type of its data if OpenGL 2.1 doesn't
+    /// support it, in which case the pointer given to OpenGL is declared as
+    /// `GL_FLOAT` and the data is translated at draw time. Texture
+    /// co-ordinates use [Self::emulated_texture_coord_types] instead.
+    emulated_array_types: [Option<GLenum>; ARRAYS.len()],
+    /// Like [Self::emulated_array_types], but for the texture co-ordinates
+    /// array of each texture unit.
+    emulated_texture_coord_types: HashMap<GLenum, GLenum>,
+    translation_buffers: [Vec<GLfloat>; ARRAYS.len()],
+    /// Reused for data read back from buffer objects, see [array_data].
+    staging_buffer: Vec<u8>,
+    /// Indices of the current `glDrawElements` call, see
+    /// [Self::read_indices].
+    index_list: Vec<usize>,
+    /// Point sizes for the current draw call, see [Self::read_point_sizes].
+    point_sizes: Vec<GLfloat>,
+    point_size_array: PointSizeArray,
+    /// Textures with `GL_GENERATE_MIPMAP` set. The parameter isn't passed on
+    /// to OpenGL, where it is deprecated. Instead, the mipmaps are generated
+    /// explicitly by [Self::generate_mipmap_after_upload].
+    generate_mipmap_textures: HashSet<GLuint>,
 }
 impl GLES1OnGL2 {
-    /// If any arrays with fixed-point data are in use at the time of a draw
-    /// call, this function will convert the data to floating-point and
-    /// replace the pointers. [Self::restore_fixed_point_arrays] can be called
-    /// after to restore the original state.
-    unsafe fn translate_fixed_point_arrays(
+    unsafe fn bound_texture() -> GLuint {
+        let mut texture = 0;
+        gl21::GetIntegerv(gl21::TEXTURE_BINDING_2D, &mut texture);
+        texture as GLuint
+    }
+
+    /// Handles setting `GL_GENERATE_MIPMAP` for the bound texture.
+    unsafe fn set_generate_mipmap(&mut self, enabled: bool) {
+        let texture = Self::bound_texture();
+        if enabled {
+            self.generate_mipmap_textures.insert(texture);
+        } else {
+            self.generate_mipmap_textures.remove(&texture);
+        }
+    }
+
+    /// Must be called after anything that changes the contents of a texture
+    /// image. If the level 0 image of a texture with `GL_GENERATE_MIPMAP` set
+    /// was changed, this regenerates the other levels, like OpenGL ES does.
+    unsafe fn generate_mipmap_after_upload(&mut self, target: GLenum, level: GLint) {
+        if level == 0
+            && !self.generate_mipmap_textures.is_empty()
+            && self
+                .generate_mipmap_textures
+                .contains(&Self::bound_texture())
+        {
+            gl21::GenerateMipmapEXT(target);
+        }
+    }
+
+    /// Whether any array currently has data that needs translation.
+    fn has_emulated_arrays(&self) -> bool {
+        self.emulated_array_types.iter().any(Option::is_some)
+            || !self.emulated_texture_coord_types.is_empty()
+    }
+
+    /// If any arrays with data types OpenGL 2.1 doesn't support (fixed-point,
+    /// or bytes for vertices and texture co-ordinates) are in use at the time
+    /// of a draw call, this function will convert the data for the vertices
+    /// in the range `first..(first + count)` to floating-point and replace the
+    /// pointers. [Self::restore_translated_arrays] can be called after to
+    /// restore the original state.
+    ///
+    /// The data can come from client memory or from a buffer object. Either
+    /// way, it is converted into buffers that are reused between draw calls.
+    /// Arrays of supported types are left for OpenGL to read directly.
+    unsafe fn translate_arrays(
         &mut self,
         first: GLint,
         count: GLsizei,
     ) -> [Option<ArrayStateBackup>; ARRAYS.len()] {
         let mut backups: [Option<ArrayStateBackup>; ARRAYS.len()] = Default::default();
+        if !self.has_emulated_arrays() {
+            return backups;
+        }
         for (i, array_info) in ARRAYS.iter().enumerate() {
             // Decide whether we need to do anything for this array
 
-            if !self.pointer_is_fixed_point[i] {
-                continue;
-            }
-
             // There is one texture co-ordinates pointer per texture unit.
-            let old_client_active_texture = if array_info.name == gl21::TEXTURE_COORD_ARRAY {
-                // Is the texture unit involved in this draw call fixed-point?
-                // If not, we don't need to do anything.
+            let (type_, old_client_active_texture) = if array_info.name == gl21::TEXTURE_COORD_ARRAY
+            {
+                // Does the texture unit involved in this draw call need
+                // translation? If not, we don't need to do anything.
                 let mut active_texture: GLenum = 0;
                 gl21::GetIntegerv(
                     gl21::ACTIVE_TEXTURE,
                     &mut active_texture as *mut _ as *mut _,
                 );
-                if !self.fixed_point_texture_units.contains(&active_texture) {
+                let Some(&type_) = self.emulated_texture_coord_types.get(&active_texture) else {
                     continue;
-                }
+                };
 
-                // Make sure our glTexCoordPointer call will affect that unit.
+                // Make sure our glTexCoordPointer call will affect that
+                // unit.
                 let mut old_client_active_texture: GLenum = 0;
                 gl21::GetIntegerv(
                     gl21::CLIENT_ACTIVE_TEXTURE,
                     &mut old_client_active_texture as *mut _ as *mut _,
                 );
                 gl21::ClientActiveTexture(active_texture);
-                Some(old_client_active_texture)
+                (type_, Some(old_client_active_texture))
             } else {
-                None
+                let Some(type_) = self.emulated_array_types[i] else {
+                    continue;
+                };
+                (type_, None)
             };
 
             let mut is_active = gl21::FALSE;
             gl21::GetBooleanv(array_info.name, &mut is_active);
-            if is_active != gl21::TRUE {
-                continue;
+            if is_active == gl21::TRUE {
+                backups[i] = Some(self.translate_array(i, type_, first, count));
             }
 
-            let mut buffer_binding = 0;
-            gl21::GetIntegerv(array_info.buffer_binding, &mut buffer_binding);
-            // TODO: translation for bound array buffers
-            assert!(buffer_binding == 0);
+            if let Some(old_client_active_texture) = old_client_active_texture {
+                gl21::ClientActiveTexture(old_client_active_texture);
+            }
+        }
+        backups
+    }
+    /// Does the work of [Self::translate_arrays] for a single enabled array.
+    unsafe fn translate_array(
+        &mut self,
+        i: usize,
+        type_: GLenum,
+        first: GLint,
+        count: GLsizei,
+    ) -> ArrayStateBackup {
+        let array_info = &ARRAYS[i];
 
-            // Get and back up data
+        // Get and back up data
 
-            let size = array_info.size.map(|size_enum| {
-                let mut size: GLint = 0;
-                gl21::GetIntegerv(size_enum, &mut size);
-                size
-            });
-            let mut stride: GLsizei = 0;
-            gl21::GetIntegerv(array_info.stride, &mut stride);
-            let mut pointer: *mut GLvoid = std::ptr::null_mut();
-            // The second argument to glGetPointerv must be a mutable pointer,
-            // but gl_generator generates the wrong signature by mistake, see
-            // https://github.com/brendanzab/gl-rs/issues/541
-            #[allow(clippy::unnecessary_mut_passed)]
-            gl21::GetPointerv(array_info.pointer, &mut pointer);
-            let pointer = pointer.cast_const();
-
-            backups[i] = Some(ArrayStateBackup {
-                size,
-                stride,
-                pointer,
-            });
+        let mut buffer_binding = 0;
+        gl21::GetIntegerv(array_info.buffer_binding, &mut buffer_binding);
+        let buffer_binding = buffer_binding as GLuint;
+        let size = array_info.size.map(|size_enum| {
+            let mut size: GLint = 0;
+            gl21::GetIntegerv(size_enum, &mut size);
+            size
+        });
+        let mut stride: GLsizei = 0;
+        gl21::GetIntegerv(array_info.stride, &mut stride);
+        let mut pointer: *mut GLvoid = std::ptr::null_mut();
+        // The second argument to glGetPointerv must be a mutable pointer,
+        // but gl_generator generates the wrong signature by mistake, see
+        // https://github.com/brendanzab/gl-rs/issues/541
+        #[allow(clippy::unnecessary_mut_passed)]
+        gl21::GetPointerv(array_info.pointer, &mut pointer);
+        let pointer = pointer.cast_const();
 
-            // Create translated array and substitute pointer
+        let backup = ArrayStateBackup {
+            size,
+            stride,
+            pointer,
+            buffer_binding,
+        };
 
-            let size = size.unwrap_or_else(|| {
-                assert!(array_info.name == gl21::NORMAL_ARRAY);
-                3
-            });
-            let stride = if stride == 0 {
-                // tightly packed mode
-                size * 4 // sizeof(gl::FLOAT)
-            } else {
-                stride
-            };
+        // Create translated array and substitute pointer
 
-            let buffer = &mut self.fixed_point_translation_buffers[i];
-            buffer.clear();
-            buffer.resize(((first + count) * size).try_into().unwrap(), 0.0);
+        let size = size.unwrap_or_else(|| {
+            assert!(array_info.name == gl21::NORMAL_ARRAY);
+            3
+        });
+        assert!(first >= 0 && count >= 0 && size >= 0 && stride >= 0);
+        let first = first as usize;
+        let count = count as usize;
+        let size = size as usize;
+        let component_size = emulated_component_size(type_);
+        let stride = if stride == 0 {
+            // tightly packed mode
+            size * component_size
+        } else {
+            stride as usize
+        };
 
-            {
-                assert!(first >= 0 && count >= 0 && size >= 0 && stride >= 0);
-                let first = first as usize;
-                let count = count as usize;
-                let size = size as usize;
-                let stride = stride as usize;
-                for j in first..(first + count) {
-                    let vector_ptr: *const GLvoid = pointer.add(j * stride);
-                    let vector_ptr: *const GLfixed = vector_ptr.cast();
-                    for k in 0..size {
-                        buffer[j * size + k] = fixed_to_float(vector_ptr.add(k).read_unaligned());
-                    }
-                }
-            }
+        let buffer = &mut self.translation_buffers[i];
+        buffer.clear();
+        buffer.resize((first + count) * size, 0.0);
 
-            let buffer_ptr: *const GLfloat = buffer.as_ptr();
-            let buffer_ptr: *const GLvoid = buffer_ptr.cast();
-            match array_info.name {
-                gl21::COLOR_ARRAY => gl21::ColorPointer(size, gl21::FLOAT, 0, buffer_ptr),
-                gl21::NORMAL_ARRAY => {
-                    assert!(size == 3);
-                    gl21::NormalPointer(gl21::FLOAT, 0, buffer_ptr)
-                }
-                gl21::TEXTURE_COORD_ARRAY => {
-                    gl21::TexCoordPointer(size, gl21::FLOAT, 0, buffer_ptr)
+        if count > 0 {
+            let data = array_data(
+                gl21::ARRAY_BUFFER,
+                gl21::ARRAY_BUFFER_BINDING,
+                buffer_binding,
+                (pointer as usize + first * stride) as *const GLvoid,
+                (count - 1) * stride + size * component_size,
+                &mut self.staging_buffer,
+            );
+            for j in 0..count {
+                let vector = &data[j * stride..];
+                let translated_vector = &mut buffer[(first + j) * size..][..size];
+                for (k, component) in translated_vector.iter_mut().enumerate() {
+                    *component = match type_ {
+                        gles11::FIXED => {
+                            let bytes = vector[k * 4..][..4].try_into().unwrap();
+                            fixed_to_float(GLfixed::from_ne_bytes(bytes))
+                        }
+                        // Not normalized, unlike bytes in a color array.
+                        gl21::BYTE => vector[k] as i8 as GLfloat,
+                        _ => unreachable!(),
+                    };
                 }
-                gl21::VERTEX_ARRAY => gl21::VertexPointer(size, gl21::FLOAT, 0, buffer_ptr),
-                _ => unreachable!(),
             }
+        }
 
-            if let Some(old_client_active_texture) = old_client_active_texture {
-                gl21::ClientActiveTexture(old_client_active_texture);
+        let buffer_ptr: *const GLfloat = buffer.as_ptr();
+        let buffer_ptr: *const GLvoid = buffer_ptr.cast();
+        // The translated data is in client memory, so the array must not have
+        // a buffer bound while it is used.
+        with_array_buffer(0, || match array_info.name {
+            gl21::COLOR_ARRAY => gl21::ColorPointer(size as GLint, gl21::FLOAT, 0, buffer_ptr),
+            gl21::NORMAL_ARRAY => {
+                assert!(size == 3);
+                gl21::NormalPointer(gl21::FLOAT, 0, buffer_ptr)
             }
-        }
-        backups
+            gl21::TEXTURE_COORD_ARRAY => {
+                gl21::TexCoordPointer(size as GLint, gl21::FLOAT, 0, buffer_ptr)
+            }
+            gl21::VERTEX_ARRAY => gl21::VertexPointer(size as GLint, gl21::FLOAT, 0, buffer_ptr),
+            _ => unreachable!(),
+        });
+
+        backup
     }
-    unsafe fn restore_fixed_point_arrays(
+    unsafe fn restore_translated_arrays(
         &mut self,
         from_backup: [Option<ArrayStateBackup>; ARRAYS.len()],
     ) {
@@ -505,12 +683,13 @@ impl GLES1OnGL2 {
                 size,
                 stride,
                 pointer,
+                buffer_binding,
             }) = backup
             else {
                 continue;
             };
 
-            match array_info.name {
+            with_array_buffer(buffer_binding, || match array_info.name {
                 gl21::COLOR_ARRAY => {
                     gl21::ColorPointer(size.unwrap(), gl21::FLOAT, stride, pointer)
                 }
@@ -524,7 +703,9 @@ impl GLES1OnGL2 {
                         gl21::ACTIVE_TEXTURE,
                         &mut active_texture as *mut _ as *mut _,
                     );
-                    assert!(self.fixed_point_texture_units.contains(&active_texture));
+                    assert!(self
+                        .emulated_texture_coord_types
+                        .contains_key(&active_texture));
                     let mut old_client_active_texture: GLenum = 0;
                     gl21::GetIntegerv(
                         gl21::CLIENT_ACTIVE_TEXTURE,
@@ -538,7 +719,7 @@ impl GLES1OnGL2 {
                     gl21::VertexPointer(size.unwrap(), gl21::FLOAT, stride, pointer)
                 }
                 _ => unreachable!(),
-            }
+            });
         }
     }
 
@@ -571,6 +752,113 @@ impl GLES1OnGL2 {
             gl21::Fogf(gl21::FOG_END, fogEnd);
         }
     }
+
+    /// Whether a draw call with this mode needs `GL_OES_point_size_array`
+    /// emulation.
+    fn uses_point_size_array(&self, mode: GLenum) -> bool {
+        mode == gl21::POINTS && self.point_size_array.enabled
+    }
+
+    /// Reads the indices for a `glDrawElements` call into
+    /// [Self::index_list], from client memory or the bound
+    /// `GL_ELEMENT_ARRAY_BUFFER`. OpenGL 2.1 supports both of the index types
+    /// OpenGL ES 1.1 has, so the indices never need converting; they are only
+    /// needed to find which vertices are used.
+    unsafe fn read_indices(&mut self, count: usize, type_: GLenum, indices: *const GLvoid) {
+        let index_size = match type_ {
+            gl21::UNSIGNED_BYTE => 1,
+            gl21::UNSIGNED_SHORT => 2,
+            _ => unreachable!(),
+        };
+        let mut index_buffer_binding = 0;
+        gl21::GetIntegerv(
+            gl21::ELEMENT_ARRAY_BUFFER_BINDING,
+            &mut index_buffer_binding,
+        );
+        let index_data = array_data(
+            gl21::ELEMENT_ARRAY_BUFFER,
+            gl21::ELEMENT_ARRAY_BUFFER_BINDING,
+            index_buffer_binding as GLuint,
+            indices,
+            count * index_size,
+            &mut self.staging_buffer,
+        );
+        self.index_list.clear();
+        if index_size == 1 {
+            self.index_list
+                .extend(index_data.iter().map(|&index| index as usize));
+        } else {
+            self.index_list.extend(
+                index_data
+                    .chunks_exact(2)
+                    .map(|index| GLushort::from_ne_bytes([index[0], index[1]]) as usize),
+            );
+        }
+    }
+
+    /// Reads the entries of the point size array for the vertices with
+    /// indices in the range `first..(first + count)` into
+    /// [Self::point_sizes].
+    unsafe fn read_point_sizes(&mut self, first: usize, count: usize) {
+        self.point_sizes.clear();
+        if count == 0 {
+            return;
+        }
+        let PointSizeArray {
+            type_,
+            stride,
+            pointer,
+            buffer_binding,
+            ..
+        } = self.point_size_array;
+        // Both GL_FLOAT and GL_FIXED are 4 bytes.
+        let stride = if stride == 0 { 4 } else { stride as usize };
+        let data = array_data(
+            gl21::ARRAY_BUFFER,
+            gl21::ARRAY_BUFFER_BINDING,
+            buffer_binding,
+            (pointer as usize + first * stride) as *const GLvoid,
+            (count - 1) * stride + 4,
+            &mut self.staging_buffer,
+        );
+        self.point_sizes.extend((0..count).map(|i| {
+            let bytes: [u8; 4] = data[i * stride..][..4].try_into().unwrap();
+            match type_ {
+                gl21::FLOAT => GLfloat::from_ne_bytes(bytes),
+                gles11::FIXED => fixed_to_float(GLfixed::from_ne_bytes(bytes)),
+                _ => unreachable!(),
+            }
+        }));
+    }
+
+    /// Emulates `GL_OES_point_size_array` by splitting a draw call of `count`
+    /// points into runs of consecutive points with the same size, setting the
+    /// point size before each one. `size_at` gives the size of a point, and
+    /// `draw` is called with the start and length of each run.
+    unsafe fn draw_points_with_sizes(
+        count: usize,
+        size_at: impl Fn(usize) -> GLfloat,
+        mut draw: impl FnMut(usize, usize),
+    ) {
+        let mut old_size: GLfloat = 0.0;
+        gl21::GetFloatv(gl21::POINT_SIZE, &mut old_size);
+        let mut run_start = 0;
+        while run_start < count {
+            let size = size_at(run_start);
+            // Comparing the bits means a NaN size still makes a run.
+            let run_len = (run_start..count)
+                .take_while(|&i| size_at(i).to_bits() == size.to_bits())
+                .count();
+            // OpenGL rejects sizes that aren't positive, but such points
+            // wouldn't cover any pixels anyway.
+            if size > 0.0 {
+                gl21::PointSize(size);
+                draw(run_start, run_len);
+            }
+            run_start += run_len;
+        }
+        gl21::PointSize(old_size);
+    }
 }
 impl GLES for GLES1OnGL2 {
     fn description() -> &'static str {
@@ -580,9 +868,14 @@ impl GLES for GLES1OnGL2 {
     fn new(window: &mut Window) -> Result<Self, String> {
         Ok(Self {
             gl_ctx: window.create_gl_context(GLVersion::GL21Compat)?,
-            pointer_is_fixed_point: [false; ARRAYS.len()],
-            fixed_point_texture_units: HashSet::new(),
-            fixed_point_translation_buffers: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            emulated_array_types: [None; ARRAYS.len()],
+            emulated_texture_coord_types: HashMap::new(),
+            translation_buffers: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            staging_buffer: Vec::new(),
+            index_list: Vec::new(),
+            point_sizes: Vec::new(),
+            point_size_array: Default::default(),
+            generate_mipmap_textures: HashSet::new(),
         })
     }
 
@@ -632,14 +925,26 @@ impl GLES for GLES1OnGL2 {
         gl21::ClientActiveTexture(texture);
     }
     unsafe fn EnableClientState(&mut self, array: GLenum) {
+        if array == gles11::POINT_SIZE_ARRAY_OES {
+            self.point_size_array.enabled = true;
+            return;
+        }
         assert!(ARRAYS.iter().any(|&ArrayInfo { name, .. }| name == array));
         gl21::EnableClientState(array);
     }
     unsafe fn DisableClientState(&mut self, array: GLenum) {
+        if array == gles11::POINT_SIZE_ARRAY_OES {
+            self.point_size_array.enabled = false;
+            return;
+        }
         assert!(ARRAYS.iter().any(|&ArrayInfo { name, .. }| name == array));
         gl21::DisableClientState(array);
     }
     unsafe fn GetBooleanv(&mut self, pname: GLenum, params: *mut GLboolean) {
+        if pname == gles11::POINT_SIZE_ARRAY_OES {
+            params.write(self.point_size_array.enabled.into());
+            return;
+        }
         let (type_, _count) = GET_PARAMS.get_type_info(pname);
         // TODO: type conversion
         assert!(type_ == ParamType::Boolean);
@@ -653,18 +958,38 @@ impl GLES for GLES1OnGL2 {
         gl21::GetFloatv(pname, params);
     }
     unsafe fn GetIntegerv(&mut self, pname: GLenum, params: *mut GLint) {
+        match pname {
+            gles11::POINT_SIZE_ARRAY_TYPE_OES => {
+                return params.write(self.point_size_array.type_ as GLint);
+            }
+            gles11::POINT_SIZE_ARRAY_STRIDE_OES => {
+                return params.write(self.point_size_array.stride);
+            }
+            gles11::POINT_SIZE_ARRAY_BUFFER_BINDING_OES => {
+                return params.write(self.point_size_array.buffer_binding as GLint);
+            }
+            _ => (),
+        }
         let (type_, _count) = GET_PARAMS.get_type_info(pname);
         // TODO: type conversion
         assert!(type_ == ParamType::Int);
         gl21::GetIntegerv(pname, params);
     }
     unsafe fn GetTexEnviv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint) {
+        if target == gl21::POINT_SPRITE {
+            assert!(pname == gl21::COORD_REPLACE);
+            return gl21::GetTexEnviv(target, pname, params);
+        }
         let (type_, _count) = TEX_ENV_PARAMS.get_type_info(pname);
         assert!(type_ == ParamType::Int);
         assert_eq!(target, gl21::TEXTURE_ENV);
         gl21::GetTexEnviv(target, pname, params);
     }
     unsafe fn GetPointerv(&mut self, pname: GLenum, params: *mut *const GLvoid) {
+        if pname == gles11::POINT_SIZE_ARRAY_POINTER_OES {
+            params.write(self.point_size_array.pointer);
+            return;
+        }
         assert!(ARRAYS
             .iter()
             .any(|&ArrayInfo { pointer, .. }| pname == pointer));
@@ -986,22 +1311,22 @@ impl GLES for GLES1OnGL2 {
         assert!(size == 4);
         if type_ == gles11::FIXED {
             // Translation deferred until draw call
-            self.pointer_is_fixed_point[0] = true;
+            self.emulated_array_types[0] = Some(type_);
             gl21::ColorPointer(size, gl21::FLOAT, stride, pointer)
         } else {
             assert!(type_ == gl21::UNSIGNED_BYTE || type_ == gl21::FLOAT);
-            self.pointer_is_fixed_point[0] = false;
+            self.emulated_array_types[0] = None;
             gl21::ColorPointer(size, type_, stride, pointer)
         }
     }
     unsafe fn NormalPointer(&mut self, type_: GLenum, stride: GLsizei, pointer: *const GLvoid) {
         if type_ == gles11::FIXED {
             // Translation deferred until draw call
-            self.pointer_is_fixed_point[1] = true;
+            self.emulated_array_types[1] = Some(type_);
             gl21::NormalPointer(gl21::FLOAT, stride, pointer)
         } else {
             assert!(type_ == gl21::BYTE || type_ == gl21::SHORT || type_ == gl21::FLOAT);
-            self.pointer_is_fixed_point[1] = false;
+            self.emulated_array_types[1] = None;
             gl21::NormalPointer(type_, stride, pointer)
         }
     }
@@ -1018,19 +1343,16 @@ impl GLES for GLES1OnGL2 {
             gl21::CLIENT_ACTIVE_TEXTURE,
             &mut active_texture as *mut _ as *mut _,
         );
-        if type_ == gles11::FIXED {
+        // OpenGL 2.1 doesn't support GL_BYTE texture co-ordinates.
+        if type_ == gles11::FIXED || type_ == gl21::BYTE {
             // Translation deferred until draw call.
             // There is one texture co-ordinates pointer per texture unit.
-            self.fixed_point_texture_units.insert(active_texture);
-            self.pointer_is_fixed_point[2] = true;
+            self.emulated_texture_coord_types
+                .insert(active_texture, type_);
             gl21::TexCoordPointer(size, gl21::FLOAT, stride, pointer)
         } else {
-            // TODO: byte
             assert!(type_ == gl21::SHORT || type_ == gl21::FLOAT);
-            self.fixed_point_texture_units.remove(&active_texture);
-            if self.fixed_point_texture_units.is_empty() {
-                self.pointer_is_fixed_point[2] = false;
-            }
+            self.emulated_texture_coord_types.remove(&active_texture);
             gl21::TexCoordPointer(size, type_, stride, pointer)
         }
     }
@@ -1042,17 +1364,35 @@ impl GLES for GLES1OnGL2 {
         pointer: *const GLvoid,
     ) {
         assert!(size == 2 || size == 3 || size == 4);
-        if type_ == gles11::FIXED {
+        // OpenGL 2.1 doesn't support GL_BYTE vertices.
+        if type_ == gles11::FIXED || type_ == gl21::BYTE {
             // Translation deferred until draw call
-            self.pointer_is_fixed_point[3] = true;
+            self.emulated_array_types[3] = Some(type_);
             gl21::VertexPointer(size, gl21::FLOAT, stride, pointer)
         } else {
-            // TODO: byte
             assert!(type_ == gl21::SHORT || type_ == gl21::FLOAT);
-            self.pointer_is_fixed_point[3] = false;
+            self.emulated_array_types[3] = None;
             gl21::VertexPointer(size, type_, stride, pointer)
         }
     }
+    unsafe fn PointSizePointerOES(
+        &mut self,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: *const GLvoid,
+    ) {
+        assert!(type_ == gl21::FLOAT || type_ == gles11::FIXED);
+        assert!(stride >= 0);
+        let mut buffer_binding = 0;
+        gl21::GetIntegerv(gl21::ARRAY_BUFFER_BINDING, &mut buffer_binding);
+        self.point_size_array = PointSizeArray {
+            enabled: self.point_size_array.enabled,
+            type_,
+            stride,
+            pointer,
+            buffer_binding: buffer_binding as GLuint,
+        };
+    }
 
     // Drawing
     unsafe fn DrawArrays(&mut self, mode: GLenum, first: GLint, count: GLsizei) {
@@ -1068,12 +1408,25 @@ impl GLES for GLES1OnGL2 {
         .contains(&mode));
 
         let fog_state_backup = self.clamp_fog_state_values();
-        let fixed_point_arrays_state_backup = self.translate_fixed_point_arrays(first, count);
+        let translated_arrays_state_backup = self.translate_arrays(first, count);
 
-        gl21::DrawArrays(mode, first, count);
+        if self.uses_point_size_array(mode) {
+            assert!(first >= 0 && count >= 0);
+            self.read_point_sizes(first as usize, count as usize);
+            let sizes = &self.point_sizes;
+            Self::draw_points_with_sizes(
+                sizes.len(),
+                |i| sizes[i],
+                |run_start, run_len| {
+                    gl21::DrawArrays(mode, first + run_start as GLint, run_len as GLsizei)
+                },
+            );
+        } else {
+            gl21::DrawArrays(mode, first, count);
+        }
 
         self.restore_fog_state_values(fog_state_backup);
-        self.restore_fixed_point_arrays(fixed_point_arrays_state_backup);
+        self.restore_translated_arrays(translated_arrays_state_backup);
     }
     unsafe fn DrawElements(
         &mut self,
@@ -1095,65 +1448,49 @@ impl GLES for GLES1OnGL2 {
         assert!(type_ == gl21::UNSIGNED_BYTE || type_ == gl21::UNSIGNED_SHORT);
 
         let fog_state_backup = self.clamp_fog_state_values();
-        let fixed_point_arrays_state_backup =
-            if self.pointer_is_fixed_point.iter().any(|&is_fixed| is_fixed) {
-                // Scan the index buffer to find the range of data that may need
-                // fixed-point translation.
-                // TODO: Would it be more efficient to turn this into a
-                // non-indexed draw-call instead?
-
-                let mut index_buffer_binding = 0;
-                gl21::GetIntegerv(
-                    gl21::ELEMENT_ARRAY_BUFFER_BINDING,
-                    &mut index_buffer_binding,
-                );
-                // TODO: handling of bound index array buffers
-                assert!(index_buffer_binding == 0);
-
-                let mut first = usize::MAX;
-                let mut last = usize::MIN;
-                assert!(count >= 0);
-                match type_ {
-                    gl21::UNSIGNED_BYTE => {
-                        let indices_ptr: *const GLubyte = indices.cast();
-                        for i in 0..(count as usize) {
-                            let index = indices_ptr.add(i).read_unaligned();
-                            first = first.min(index as usize);
-                            last = last.max(index as usize);
-                        }
-                    }
-                    gl21::UNSIGNED_SHORT => {
-                        let indices_ptr: *const GLushort = indices.cast();
-                        for i in 0..(count as usize) {
-                            let index = indices_ptr.add(i).read_unaligned();
-                            first = first.min(index as usize);
-                            last = last.max(index as usize);
-                        }
-                    }
-                    _ => unreachable!(),
-                }
-
-                let (first, count) = if first == usize::MAX && last == usize::MIN {
-                    assert!(count == 0);
-                    (0, 0)
-                } else {
-                    (
-                        first.try_into().unwrap(),
-                        (last + 1 - first).try_into().unwrap(),
-                    )
-                };
-
-                Some(self.translate_fixed_point_arrays(first, count))
-            } else {
-                None
-            };
+        let uses_point_size_array = self.uses_point_size_array(mode);
+        let mut first = 0;
+        let translated_arrays_state_backup = if self.has_emulated_arrays() || uses_point_size_array
+        {
+            // Scan the indices to find the range of data that may need
+            // translation.
+            // TODO: Would it be more efficient to turn this into a
+            // non-indexed draw-call instead?
+            assert!(count >= 0);
+            self.read_indices(count as usize, type_, indices);
+            first = self.index_list.iter().copied().min().unwrap_or(0);
+            let end = self
+                .index_list
+                .iter()
+                .copied()
+                .max()
+                .map_or(0, |last| last + 1);
+            if uses_point_size_array {
+                self.read_point_sizes(first, end - first);
+            }
+            self.translate_arrays(first.try_into().unwrap(), (end - first).try_into().unwrap())
+        } else {
+            Default::default()
+        };
 
-        gl21::DrawElements(mode, count, type_, indices);
+        if uses_point_size_array {
+            let index_size = if type_ == gl21::UNSIGNED_BYTE { 1 } else { 2 };
+            let index_list = &self.index_list;
+            let sizes = &self.point_sizes;
+            Self::draw_points_with_sizes(
+                index_list.len(),
+                |i| sizes[index_list[i] - first],
+                |run_start, run_len| {
+                    let run_indices = (indices as usize + run_start * index_size) as *const GLvoid;
+                    gl21::DrawElements(mode, run_len as GLsizei, type_, run_indices)
+                },
+            );
+        } else {
+            gl21::DrawElements(mode, count, type_, indices);
+        }
 
         self.restore_fog_state_values(fog_state_backup);
-        if let Some(fixed_point_arrays_state_backup) = fixed_point_arrays_state_backup {
-            self.restore_fixed_point_arrays(fixed_point_arrays_state_backup);
-        }
+        self.restore_translated_arrays(translated_arrays_state_backup);
     }
 
     // Clearing
@@ -1219,6 +1556,11 @@ impl GLES for GLES1OnGL2 {
         gl21::GenTextures(n, textures)
     }
     unsafe fn DeleteTextures(&mut self, n: GLsizei, textures: *const GLuint) {
+        if n > 0 {
+            for texture in std::slice::from_raw_parts(textures, n as usize) {
+                self.generate_mipmap_textures.remove(texture);
+            }
+        }
         gl21::DeleteTextures(n, textures)
     }
     unsafe fn ActiveTexture(&mut self, texture: GLenum) {
@@ -1231,15 +1573,24 @@ impl GLES for GLES1OnGL2 {
     unsafe fn TexParameteri(&mut self, target: GLenum, pname: GLenum, param: GLint) {
         assert!(target == gl21::TEXTURE_2D);
         TEX_PARAMS.assert_known_param(pname);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(param != 0);
+        }
         gl21::TexParameteri(target, pname, param);
     }
     unsafe fn TexParameterf(&mut self, target: GLenum, pname: GLenum, param: GLfloat) {
         assert!(target == gl21::TEXTURE_2D);
         TEX_PARAMS.assert_known_param(pname);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(param != 0.0);
+        }
         gl21::TexParameterf(target, pname, param);
     }
     unsafe fn TexParameterx(&mut self, target: GLenum, pname: GLenum, param: GLfixed) {
         assert!(target == gl21::TEXTURE_2D);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(param != 0);
+        }
         TEX_PARAMS.setx(
             |param| gl21::TexParameterf(target, pname, param),
             |param| gl21::TexParameteri(target, pname, param),
@@ -1250,15 +1601,24 @@ impl GLES for GLES1OnGL2 {
     unsafe fn TexParameteriv(&mut self, target: GLenum, pname: GLenum, params: *const GLint) {
         assert!(target == gl21::TEXTURE_2D);
         TEX_PARAMS.assert_known_param(pname);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(params.read() != 0);
+        }
         gl21::TexParameteriv(target, pname, params);
     }
     unsafe fn TexParameterfv(&mut self, target: GLenum, pname: GLenum, params: *const GLfloat) {
         assert!(target == gl21::TEXTURE_2D);
         TEX_PARAMS.assert_known_param(pname);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(params.read() != 0.0);
+        }
         gl21::TexParameterfv(target, pname, params);
     }
     unsafe fn TexParameterxv(&mut self, target: GLenum, pname: GLenum, params: *const GLfixed) {
         assert!(target == gl21::TEXTURE_2D);
+        if pname == gl21::GENERATE_MIPMAP {
+            return self.set_generate_mipmap(params.read() != 0);
+        }
         TEX_PARAMS.setxv(
             |params| gl21::TexParameterfv(target, pname, params),
             |params| gl21::TexParameteriv(target, pname, params),
@@ -1266,6 +1626,19 @@ impl GLES for GLES1OnGL2 {
             params,
         )
     }
+    unsafe fn GetTexParameteriv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint) {
+        assert!(target == gl21::TEXTURE_2D);
+        let (type_, _count) = TEX_PARAMS.get_type_info(pname);
+        assert!(type_ == ParamType::Int);
+        if pname == gl21::GENERATE_MIPMAP {
+            let enabled = self
+                .generate_mipmap_textures
+                .contains(&Self::bound_texture());
+            params.write(enabled.into());
+            return;
+        }
+        gl21::GetTexParameteriv(target, pname, params);
+    }
     unsafe fn TexImage2D(
         &mut self,
         target: GLenum,
@@ -1312,7 +1685,8 @@ impl GLES for GLES1OnGL2 {
             format,
             type_,
             pixels,
-        )
+        );
+        self.generate_mipmap_after_upload(target, level);
     }
     unsafe fn TexSubImage2D(
         &mut self,
@@ -1343,7 +1717,8 @@ impl GLES for GLES1OnGL2 {
         );
         gl21::TexSubImage2D(
             target, level, xoffset, yoffset, width, height, format, type_, pixels,
-        )
+        );
+        self.generate_mipmap_after_upload(target, level);
     }
     unsafe fn CompressedTexImage2D(
         &mut self,
@@ -1434,7 +1809,8 @@ impl GLES for GLES1OnGL2 {
                 palette_entry_format,
                 palette_entry_type,
                 decoded.as_ptr() as *const _,
-            )
+            );
+            self.generate_mipmap_after_upload(target, level);
         } else {
             unimplemented!("CompressedTexImage2D internalformat: {:#x}", internalformat);
         }
@@ -1460,7 +1836,8 @@ impl GLES for GLES1OnGL2 {
                 || internalformat as GLenum == gl21::LUMINANCE_ALPHA
         );
         assert!(border == 0);
-        gl21::CopyTexImage2D(target, level, internalformat, x, y, width, height, border)
+        gl21::CopyTexImage2D(target, level, internalformat, x, y, width, height, border);
+        self.generate_mipmap_after_upload(target, level);
     }
     unsafe fn CopyTexSubImage2D(
         &mut self,
@@ -1475,7 +1852,8 @@ impl GLES for GLES1OnGL2 {
     ) {
         assert!(target == gl21::TEXTURE_2D);
         assert!(level >= 0);
-        gl21::CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height)
+        gl21::CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height);
+        self.generate_mipmap_after_upload(target, level);
     }
     unsafe fn TexEnvf(&mut self, target: GLenum, pname: GLenum, param: GLfloat) {
         match target {
@@ -1759,6 +2137,15 @@ impl GLES for GLES1OnGL2 {
     ) {
         gl21::GetRenderbufferParameterivEXT(target, pname, params)
     }
+    unsafe fn GetFramebufferAttachmentParameterivOES(
+        &mut self,
+        target: GLenum,
+        attachment: GLenum,
+        pname: GLenum,
+        params: *mut GLint,
+    ) {
+        gl21::GetFramebufferAttachmentParameterivEXT(target, attachment, pname, params)
+    }
     unsafe fn CheckFramebufferStatusOES(&mut self, target: GLenum) -> GLenum {
         gl21::CheckFramebufferStatusEXT(target)
     }
@@ -1771,4 +2158,70 @@ impl GLES for GLES1OnGL2 {
     unsafe fn GenerateMipmapOES(&mut self, target: GLenum) {
         gl21::GenerateMipmapEXT(target)
     }
+
+    // APPLE_framebuffer_multisample -> EXT_framebuffer_multisample and
+    // EXT_framebuffer_blit
+    unsafe fn RenderbufferStorageMultisampleAPPLE(
+        &mut self,
+        target: GLenum,
+        samples: GLsizei,
+        internalformat: GLenum,
+        width: GLsizei,
+        height: GLsizei,
+    ) {
+        gl21::RenderbufferStorageMultisampleEXT(target, samples, internalformat, width, height)
+    }
+    unsafe fn ResolveMultisampleFramebufferAPPLE(&mut self) {
+        // glResolveMultisampleFramebufferAPPLE() has no parameters: it resolves
+        // the whole read framebuffer to the draw framebuffer, which must be
+        // the same size. glBlitFramebufferEXT() needs the rectangle, so get it
+        // from the read framebuffer's color attachment, which is always a
+        // renderbuffer because textures can't be multisampled.
+        let mut object_type = 0;
+        gl21::GetFramebufferAttachmentParameterivEXT(
+            gl21::READ_FRAMEBUFFER_EXT,
+            gl21::COLOR_ATTACHMENT0_EXT,
+            gl21::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE_EXT,
+            &mut object_type,
+        );
+        if object_type as GLenum != gl21::RENDERBUFFER_EXT {
+            log!("Warning: glResolveMultisampleFramebufferAPPLE() called without a multisample renderbuffer to resolve, ignoring");
+            return;
+        }
+        let mut renderbuffer = 0;
+        gl21::GetFramebufferAttachmentParameterivEXT(
+            gl21::READ_FRAMEBUFFER_EXT,
+            gl21::COLOR_ATTACHMENT0_EXT,
+            gl21::FRAMEBUFFER_ATTACHMENT_OBJECT_NAME_EXT,
+            &mut renderbuffer,
+        );
+        let mut old_renderbuffer = 0;
+        gl21::GetIntegerv(gl21::RENDERBUFFER_BINDING_EXT, &mut old_renderbuffer);
+        gl21::BindRenderbufferEXT(gl21::RENDERBUFFER_EXT, renderbuffer as _);
+        let (mut width, mut height) = (0, 0);
+        gl21::GetRenderbufferParameterivEXT(
+            gl21::RENDERBUFFER_EXT,
+            gl21::RENDERBUFFER_WIDTH_EXT,
+            &mut width,
+        );
+        gl21::GetRenderbufferParameterivEXT(
+            gl21::RENDERBUFFER_EXT,
+            gl21::RENDERBUFFER_HEIGHT_EXT,
+            &mut height,
+        );
+        gl21::BindRenderbufferEXT(gl21::RENDERBUFFER_EXT, old_renderbuffer as _);
+        // Like the APPLE function, this is affected by the scissor test.
+        gl21::BlitFramebufferEXT(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            width,
+            height,
+            gl21::COLOR_BUFFER_BIT,
+            gl21::NEAREST,
+        );
+    }
 }
@@ -8,11 +8,16 @@
 //! Implemented as a wrapper around the C library stb_image, since it supports
 //! "CgBI" PNG files (an Apple proprietary extension used in iPhone OS apps).
 //!
+//! There is also a very simple PNG encoder, for exporting images to the host.
+//!
 //! This module also exposes decompression for Imagination Technologies' PVRTC
 //! format, implementing as a wrapper around their decoder from the PowerVR
 //! SDK.
 
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
 use std::ffi::{c_int, c_uchar, CStr};
+use std::io::Write;
 
 use touchHLE_pvrt_decompress_wrapper::*;
 use touchHLE_stb_image_wrapper::*;
@@ -174,6 +179,55 @@ impl Image {
             }
         }
     }
+
+    /// Encode the image as an ordinary (not CgBI) PNG file. No filtering is
+    /// done, so the file is not as small as it could be.
+    pub fn to_png(&self) -> Vec<u8> {
+        let (width, height) = self.dimensions;
+
+        // Each row is preceded by its filter type (0 = none), and PNG doesn't
+        // use premultiplied alpha.
+        let mut scanlines = Vec::with_capacity((width as usize * 4 + 1) * height as usize);
+        for row in self.pixels().chunks(width as usize * 4) {
+            scanlines.push(0);
+            for rgba in row.chunks(4) {
+                let a = rgba[3];
+                for &channel in &rgba[..3] {
+                    scanlines.push(if a == 0 {
+                        0
+                    } else {
+                        ((channel as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                    });
+                }
+                scanlines.push(a);
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&scanlines).unwrap();
+        let image_data = encoder.finish().unwrap();
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8 bits per channel, RGBA, default compression/filter/interlacing
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (chunk_type, data) in [
+            (b"IHDR", &header[..]),
+            (b"IDAT", &image_data[..]),
+            (b"IEND", &[][..]),
+        ] {
+            let mut crc = Crc::new();
+            crc.update(chunk_type);
+            crc.update(data);
+            png.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+            png.extend_from_slice(chunk_type);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&crc.sum().to_be_bytes());
+        }
+        png
+    }
 }
 
 impl Drop for Image {
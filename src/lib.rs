@@ -28,10 +28,12 @@
 #[macro_use]
 mod log;
 mod abi;
+mod api_stats;
 mod app_picker;
 mod audio;
 mod bundle;
 mod cpu;
+mod crash_report;
 mod debug;
 mod dyld;
 mod environment;
@@ -49,6 +51,8 @@ mod mem;
 mod objc;
 mod options;
 mod paths;
+mod probe;
+mod shortcut;
 mod stack;
 mod window;
 
@@ -57,7 +61,7 @@ mod window;
 // probably shouldn't be, but they need a new home (TODO).
 // Unlike its siblings, this module should be considered private and only used
 // via re-exports.
-use environment::{Environment, MutexId, MutexType, ThreadId, PTHREAD_MUTEX_DEFAULT};
+use environment::{Environment, MutexId, MutexType, PowerSaving, ThreadId, PTHREAD_MUTEX_DEFAULT};
 
 use std::path::PathBuf;
 
@@ -114,6 +118,18 @@ Special options:
 
     --info
         Print basic information about the app bundle without running the app.
+
+    --probe
+        Load the app without running it, and print a report of things that
+        are likely to stop it from working, such as missing functions and
+        classes. The last line of output is the report in JSON format.
+
+    --create-shortcut
+    --create-shortcut=...
+        Create a desktop shortcut that runs the app with touchHLE, instead of
+        running the app. Any other options given are included in the shortcut.
+        The shortcut is created in the default location for the host OS, or in
+        the directory specified, e.g. --create-shortcut=/home/me/Desktop.
 ";
 
 pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
@@ -132,6 +148,8 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
 
     let mut bundle_path: Option<PathBuf> = None;
     let mut just_info = false;
+    let mut just_probe = false;
+    let mut shortcut_dir: Option<Option<PathBuf>> = None;
     let mut option_args = Vec::new();
 
     for arg in args {
@@ -144,6 +162,12 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
             return Ok(());
         } else if arg == "--info" {
             just_info = true;
+        } else if arg == "--probe" {
+            just_probe = true;
+        } else if arg == "--create-shortcut" {
+            shortcut_dir = Some(None);
+        } else if let Some(dir) = arg.strip_prefix("--create-shortcut=") {
+            shortcut_dir = Some(Some(PathBuf::from(dir)));
         // Parse an option but discard the value, to test whether it's valid.
         // We don't want to apply it immediately, because then options loaded
         // from a file would take precedence over options from the command line.
@@ -233,6 +257,24 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         return Ok(());
     }
 
+    if just_probe {
+        probe::probe(&bundle, &fs);
+        return Ok(());
+    }
+
+    if let Some(shortcut_dir) = shortcut_dir {
+        let shortcut_path = shortcut::create_shortcut(
+            &bundle,
+            &fs,
+            &bundle_path,
+            &option_args,
+            shortcut_dir.as_deref(),
+        )
+        .map_err(|e| format!("Could not create shortcut: {}", e))?;
+        echo!("Created shortcut: {}", shortcut_path.display());
+        return Ok(());
+    }
+
     let mut options = options::Options::default();
 
     // Apply options from files
@@ -290,6 +332,21 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         assert!(parse_result == Ok(true));
     }
 
+    // The IPA is read directly until now, because the option to extract it can
+    // come from an app-specific options file.
+    let (bundle, fs) = if options.extract_ipa {
+        let bundle_data = fs::BundleData::open_any(&bundle_path)
+            .and_then(fs::BundleData::extract_to_cache)
+            .map_err(|e| format!("Could not extract app bundle: {e}"))?;
+        bundle::Bundle::new_bundle_and_fs_from_host_path(
+            bundle_data,
+            /* read_only_mode: */ false,
+        )
+        .map_err(|e| format!("Application bundle error: {e}"))?
+    } else {
+        (bundle, fs)
+    };
+
     let mut env = Environment::new(bundle, fs, options, env_for_salvage)?;
     env.run();
     Ok(())
@@ -11,19 +11,26 @@
 
 mod generic_char;
 
+pub mod assert;
 pub mod clocale;
 pub mod ctype;
 pub mod cxxabi;
 pub mod dirent;
+pub mod dispatch;
 pub mod dlfcn;
+pub mod err;
 pub mod errno;
 pub mod ifaddrs;
 pub mod keymgr;
+pub mod mach_semaphore;
+pub mod mach_task;
 pub mod mach_thread_info;
 pub mod mach_time;
 pub mod math;
 pub mod mmap;
 pub mod net;
+pub mod netdb;
+pub mod notify;
 pub mod posix_io;
 pub mod pthread;
 pub mod semaphore;
@@ -34,21 +41,33 @@ pub mod stdlib;
 pub mod string;
 pub mod sys;
 pub mod sysctl;
+pub mod syslog;
 pub mod time;
 pub mod unistd;
+pub mod unwind;
 pub mod wchar;
 
 /// Container for state of various child modules
 #[derive(Default)]
 pub struct State {
+    cxxabi: cxxabi::State,
     dirent: dirent::State,
+    dispatch: dispatch::State,
+    dlfcn: dlfcn::State,
     keymgr: keymgr::State,
+    mmap: mmap::State,
+    netdb: netdb::State,
+    notify: notify::State,
     posix_io: posix_io::State,
-    pthread: pthread::State,
+    pub pthread: pthread::State,
     pub semaphore: semaphore::State,
+    signal: signal::State,
     stdlib: stdlib::State,
     string: string::State,
+    syslog: syslog::State,
     time: time::State,
+    unwind: unwind::State,
+    wchar: wchar::State,
     errno: errno::State,
     clocale: clocale::State,
 }
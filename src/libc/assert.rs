@@ -0,0 +1,46 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `assert.h`
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::stdlib::abort_with_reason;
+use crate::mem::ConstPtr;
+use crate::Environment;
+
+/// Called by the `assert()` macro when an assertion fails.
+fn __assert_rtn(
+    env: &mut Environment,
+    func: ConstPtr<u8>,
+    file: ConstPtr<u8>,
+    line: i32,
+    failed_expr: ConstPtr<u8>,
+) {
+    let read_str = |ptr: ConstPtr<u8>| String::from_utf8_lossy(env.mem.cstr_at(ptr)).into_owned();
+    let failed_expr = read_str(failed_expr);
+    // Apple's implementation uses a line number of -1 to mean that the
+    // "expression" is the whole message.
+    let message = if line == -1 {
+        failed_expr
+    } else if func.is_null() {
+        format!(
+            "Assertion failed: ({}), file {}, line {}.",
+            failed_expr,
+            read_str(file),
+            line
+        )
+    } else {
+        format!(
+            "Assertion failed: ({}), function {}, file {}, line {}.",
+            failed_expr,
+            read_str(func),
+            read_str(file),
+            line
+        )
+    };
+    abort_with_reason(env, &message)
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(__assert_rtn(_, _, _, _))];
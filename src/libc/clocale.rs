@@ -4,13 +4,17 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `clocale.h`
-
-use std::collections::hash_map::Entry;
+//!
+//! Only the numeric conventions (decimal point etc) and the multibyte encoding
+//! of locales are actually implemented, since that's what affects parsing and
+//! printing of numbers and text.
 
 use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
-use crate::mem::{ConstPtr, MutPtr};
+use crate::libc::stdlib::get_env_var;
+use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use std::collections::HashMap;
 
 pub type LocaleCategory = i32;
 pub const LC_ALL: LocaleCategory = 0;
@@ -21,9 +25,180 @@ pub const LC_NUMERIC: LocaleCategory = 4;
 pub const LC_TIME: LocaleCategory = 5;
 pub const LC_MESSAGES: LocaleCategory = 6;
 
-#[derive(Default)]
+/// Names of the environment variables for each category, in order.
+const CATEGORY_NAMES: [&str; 6] = [
+    "LC_COLLATE",
+    "LC_CTYPE",
+    "LC_MONETARY",
+    "LC_NUMERIC",
+    "LC_TIME",
+    "LC_MESSAGES",
+];
+
+/// `char` value meaning "not available in this locale".
+const CHAR_MAX: u8 = 127;
+
 pub struct State {
-    locale: std::collections::HashMap<LocaleCategory, MutPtr<u8>>,
+    /// Current locale name for each category except [LC_ALL], in order.
+    names: [String; 6],
+    /// Guest copies of the strings returned by `setlocale()`, which stay valid
+    /// until the next call for the same category.
+    returned_names: HashMap<LocaleCategory, MutPtr<u8>>,
+    /// Static storage for the return value of `localeconv()`.
+    lconv: Option<MutPtr<lconv>>,
+    /// Guest copies of the strings referenced by [Self::lconv]. These are never
+    /// freed, so pointers the app got from an old `localeconv()` result stay
+    /// valid.
+    lconv_strings: HashMap<&'static [u8], MutPtr<u8>>,
+}
+impl Default for State {
+    fn default() -> Self {
+        State {
+            names: std::array::from_fn(|_| "C".to_string()),
+            returned_names: HashMap::new(),
+            lconv: None,
+            lconv_strings: HashMap::new(),
+        }
+    }
+}
+
+/// Numeric formatting conventions for a locale, see `localeconv()`.
+#[derive(Clone, Copy)]
+struct NumericConventions {
+    decimal_point: &'static [u8],
+    thousands_sep: &'static [u8],
+    grouping: &'static [u8],
+}
+
+const C_NUMERIC_CONVENTIONS: NumericConventions = NumericConventions {
+    decimal_point: b".",
+    thousands_sep: b"",
+    grouping: b"",
+};
+
+/// Languages that use a comma as the decimal separator. The other languages
+/// we might reasonably encounter use a point.
+const COMMA_DECIMAL_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr",
+    "uk", "vi",
+];
+/// Exceptions to [COMMA_DECIMAL_LANGUAGES] for particular regions.
+const POINT_DECIMAL_LOCALES: &[(&str, &str)] = &[
+    ("de", "CH"),
+    ("de", "LI"),
+    ("it", "CH"),
+    ("es", "MX"),
+    ("es", "US"),
+    ("es", "PR"),
+];
+/// Languages with comma decimal separators that group thousands with spaces
+/// rather than points.
+const SPACE_GROUPING_LANGUAGES: &[&str] = &[
+    "bg", "cs", "et", "fi", "fr", "hu", "lt", "lv", "nb", "nn", "no", "pl", "ru", "sk", "sv", "uk",
+];
+
+/// Split a locale name like `de_DE.UTF-8@euro` into its language and region,
+/// or return [None] if it's not a valid locale name. `C` and `POSIX` have no
+/// language.
+fn parse_locale_name(name: &str) -> Option<(Option<&str>, Option<&str>)> {
+    if name == "C" || name == "POSIX" {
+        return Some((None, None));
+    }
+    let name = name.split_once('@').map_or(name, |(name, _modifier)| name);
+    let name = name.split_once('.').map_or(name, |(name, _encoding)| name);
+    let (language, region) = match name.split_once('_') {
+        Some((language, region)) => (language, Some(region)),
+        None => (name, None),
+    };
+    if !(2..=3).contains(&language.len()) || !language.bytes().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+    if let Some(region) = region {
+        let valid = (region.len() == 2 && region.bytes().all(|c| c.is_ascii_uppercase()))
+            || (region.len() == 3 && region.bytes().all(|c| c.is_ascii_digit()));
+        if !valid {
+            return None;
+        }
+    }
+    Some((Some(language), region))
+}
+
+fn numeric_conventions_for(name: &str) -> NumericConventions {
+    let Some((Some(language), region)) = parse_locale_name(name) else {
+        return C_NUMERIC_CONVENTIONS;
+    };
+    let point_decimal = !COMMA_DECIMAL_LANGUAGES.contains(&language)
+        || region.is_some_and(|region| POINT_DECIMAL_LOCALES.contains(&(language, region)));
+    if point_decimal {
+        let thousands_sep: &[u8] = if region == Some("CH") { b"'" } else { b"," };
+        NumericConventions {
+            decimal_point: b".",
+            thousands_sep,
+            grouping: b"\x03\x03",
+        }
+    } else {
+        let thousands_sep: &[u8] = if SPACE_GROUPING_LANGUAGES.contains(&language) {
+            b" "
+        } else {
+            b"."
+        };
+        NumericConventions {
+            decimal_point: b",",
+            thousands_sep,
+            grouping: b"\x03\x03",
+        }
+    }
+}
+
+fn category_index(category: LocaleCategory) -> usize {
+    (category - 1) as usize
+}
+
+/// Get the decimal point character for the current `LC_NUMERIC` locale. This
+/// is used by `printf()`, `strtod()` etc.
+pub fn decimal_point(env: &mut Environment) -> u8 {
+    let name = &env.libc_state.clocale.names[category_index(LC_NUMERIC)];
+    numeric_conventions_for(name).decimal_point[0]
+}
+
+/// Check whether the current `LC_CTYPE` locale uses UTF-8 as its multibyte
+/// encoding. Other locales are treated like the C locale, where each byte is
+/// one character. This is used by `mbrtowc()`, `wcrtomb()` etc.
+pub fn ctype_is_utf8(env: &mut Environment) -> bool {
+    let name = &env.libc_state.clocale.names[category_index(LC_CTYPE)];
+    let name = name
+        .split_once('@')
+        .map_or(&name[..], |(name, _modifier)| name);
+    match name.split_once('.') {
+        Some((_, encoding)) => encoding.eq_ignore_ascii_case("UTF-8") || encoding == "utf8",
+        // iPhone OS locales are UTF-8 unless they say otherwise.
+        None => !matches!(parse_locale_name(name), Some((None, None))),
+    }
+}
+
+/// Get the locale name `setlocale(category, "")` should use, based on the
+/// environment variables.
+fn locale_name_from_environment(env: &mut Environment, category: LocaleCategory) -> String {
+    ["LC_ALL", CATEGORY_NAMES[category_index(category)], "LANG"]
+        .into_iter()
+        .filter_map(|var| get_env_var(env, var))
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "C".to_string())
+}
+
+/// Get the name for a category, as returned by `setlocale()`.
+fn current_locale_name(env: &mut Environment, category: LocaleCategory) -> String {
+    let names = &env.libc_state.clocale.names;
+    if category != LC_ALL {
+        return names[category_index(category)].clone();
+    }
+    if names.iter().all(|name| name == &names[0]) {
+        names[0].clone()
+    } else {
+        // Apple's format for mixed locales.
+        names.join("/")
+    }
 }
 
 pub fn setlocale(
@@ -31,24 +206,167 @@ pub fn setlocale(
     category: LocaleCategory,
     locale: ConstPtr<u8>,
 ) -> MutPtr<u8> {
-    assert!(matches!(
+    if !matches!(
         category,
         LC_ALL | LC_COLLATE | LC_CTYPE | LC_MONETARY | LC_NUMERIC | LC_TIME | LC_MESSAGES
-    ));
+    ) {
+        return Ptr::null();
+    }
+
     if !locale.is_null() {
-        // TODO: Handle empty locale string and ensure the combination of
-        // category and locale is valid.
-        let locale_cstr = env.mem.cstr_at(locale).to_owned();
-        assert_ne!(locale_cstr.len(), 0);
-        let new_locale = env.mem.alloc_and_write_cstr(locale_cstr.as_slice());
-        if let Some(old_locale) = env.libc_state.clocale.locale.insert(category, new_locale) {
-            env.mem.free(old_locale.cast())
+        let Ok(locale) = env.mem.cstr_at_utf8(locale).map(|s| s.to_string()) else {
+            return Ptr::null();
+        };
+        let categories = if category == LC_ALL {
+            LC_COLLATE..=LC_MESSAGES
+        } else {
+            category..=category
         };
-    } else if let Entry::Vacant(entry) = env.libc_state.clocale.locale.entry(category) {
-        let default_locale = env.mem.alloc_and_write_cstr(b"C");
-        entry.insert(default_locale);
+        // A mixed locale name returned by a previous call can be passed back.
+        let split_names: Vec<&str> = locale.split('/').collect();
+        let mixed = category == LC_ALL && split_names.len() == CATEGORY_NAMES.len();
+        let mut new_names = Vec::new();
+        for category in categories {
+            let name = if mixed {
+                split_names[category_index(category)]
+            } else {
+                &locale
+            };
+            let name = if name.is_empty() {
+                locale_name_from_environment(env, category)
+            } else {
+                name.to_string()
+            };
+            if parse_locale_name(&name).is_none() {
+                log!(
+                    "Warning: setlocale() for unsupported locale {:?}, returning NULL",
+                    name
+                );
+                return Ptr::null();
+            }
+            new_names.push((category, name));
+        }
+        for (category, name) in new_names {
+            env.libc_state.clocale.names[category_index(category)] = name;
+        }
     }
-    env.libc_state.clocale.locale.get(&category).unwrap().cast()
+
+    let name = current_locale_name(env, category);
+    log_dbg!("setlocale({}, {:?}) => {:?}", category, locale, name);
+    let new_name = env.mem.alloc_and_write_cstr(name.as_bytes());
+    if let Some(old_name) = env
+        .libc_state
+        .clocale
+        .returned_names
+        .insert(category, new_name)
+    {
+        env.mem.free(old_name.cast());
+    }
+    new_name
+}
+
+/// `struct lconv`
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct lconv {
+    decimal_point: MutPtr<u8>,
+    thousands_sep: MutPtr<u8>,
+    grouping: MutPtr<u8>,
+    int_curr_symbol: MutPtr<u8>,
+    currency_symbol: MutPtr<u8>,
+    mon_decimal_point: MutPtr<u8>,
+    mon_thousands_sep: MutPtr<u8>,
+    mon_grouping: MutPtr<u8>,
+    positive_sign: MutPtr<u8>,
+    negative_sign: MutPtr<u8>,
+    int_frac_digits: u8,
+    frac_digits: u8,
+    p_cs_precedes: u8,
+    p_sep_by_space: u8,
+    n_cs_precedes: u8,
+    n_sep_by_space: u8,
+    p_sign_posn: u8,
+    n_sign_posn: u8,
+    int_p_cs_precedes: u8,
+    int_n_cs_precedes: u8,
+    int_p_sep_by_space: u8,
+    int_n_sep_by_space: u8,
+    int_p_sign_posn: u8,
+    int_n_sign_posn: u8,
+    _padding: [u8; 2],
+}
+unsafe impl SafeRead for lconv {}
+
+fn lconv_string(env: &mut Environment, string: &'static [u8]) -> MutPtr<u8> {
+    if let Some(&ptr) = env.libc_state.clocale.lconv_strings.get(string) {
+        return ptr;
+    }
+    let ptr = env.mem.alloc_and_write_cstr(string);
+    env.libc_state.clocale.lconv_strings.insert(string, ptr);
+    ptr
+}
+
+fn localeconv(env: &mut Environment) -> MutPtr<lconv> {
+    let names = &env.libc_state.clocale.names;
+    let numeric = numeric_conventions_for(&names[category_index(LC_NUMERIC)]);
+    // TODO: Currency symbols and positions. For now, only the separators and
+    // number of digits differ from the C locale.
+    let monetary_name = &names[category_index(LC_MONETARY)];
+    let (monetary, frac_digits, negative_sign) = match parse_locale_name(monetary_name) {
+        Some((Some(_), _)) => (numeric_conventions_for(monetary_name), 2, &b"-"[..]),
+        _ => (
+            NumericConventions {
+                decimal_point: b"",
+                thousands_sep: b"",
+                grouping: b"",
+            },
+            CHAR_MAX,
+            &b""[..],
+        ),
+    };
+
+    let empty = lconv_string(env, b"");
+    let new_lconv = lconv {
+        decimal_point: lconv_string(env, numeric.decimal_point),
+        thousands_sep: lconv_string(env, numeric.thousands_sep),
+        grouping: lconv_string(env, numeric.grouping),
+        int_curr_symbol: empty,
+        currency_symbol: empty,
+        mon_decimal_point: lconv_string(env, monetary.decimal_point),
+        mon_thousands_sep: lconv_string(env, monetary.thousands_sep),
+        mon_grouping: lconv_string(env, monetary.grouping),
+        positive_sign: empty,
+        negative_sign: lconv_string(env, negative_sign),
+        int_frac_digits: frac_digits,
+        frac_digits,
+        p_cs_precedes: CHAR_MAX,
+        p_sep_by_space: CHAR_MAX,
+        n_cs_precedes: CHAR_MAX,
+        n_sep_by_space: CHAR_MAX,
+        p_sign_posn: CHAR_MAX,
+        n_sign_posn: CHAR_MAX,
+        int_p_cs_precedes: CHAR_MAX,
+        int_n_cs_precedes: CHAR_MAX,
+        int_p_sep_by_space: CHAR_MAX,
+        int_n_sep_by_space: CHAR_MAX,
+        int_p_sign_posn: CHAR_MAX,
+        int_n_sign_posn: CHAR_MAX,
+        _padding: [0; 2],
+    };
+
+    let ptr = match env.libc_state.clocale.lconv {
+        Some(ptr) => ptr,
+        None => {
+            let ptr = env.mem.alloc_and_write(new_lconv);
+            env.libc_state.clocale.lconv = Some(ptr);
+            return ptr;
+        }
+    };
+    env.mem.write(ptr, new_lconv);
+    ptr
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(setlocale(_, _))];
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(setlocale(_, _)),
+    export_c_func!(localeconv()),
+];
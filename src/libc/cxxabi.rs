@@ -5,33 +5,79 @@
  */
 //! `cxxabi.h`
 //!
+//! Most of the C++ runtime (`operator new`, exceptions, RTTI, guard variables
+//! for static initialization, etc) is provided by the bundled libstdc++ and
+//! libgcc binaries (see `touchHLE_dylibs/`), which are real ARM code. Only the
+//! parts that are part of libSystem on iPhone OS are implemented here.
+//!
 //! Resources:
 //! - [Itanium C++ ABI specification](https://itanium-cxx-abi.github.io/cxx-abi/abi.html#dso-dtor-runtime-api)
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::MutVoidPtr;
+use crate::mem::{MutVoidPtr, Ptr};
 use crate::Environment;
 
+/// A function registered with `__cxa_atexit()` or `atexit()`.
+struct ExitHandler {
+    func: GuestFunction, // void (*func)(void *)
+    arg: MutVoidPtr,
+    dso_handle: MutVoidPtr,
+}
+
+#[derive(Default)]
+pub struct State {
+    /// In order of registration. They are called in reverse order.
+    exit_handlers: Vec<ExitHandler>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.cxxabi
+    }
+}
+
 fn __cxa_atexit(
-    _env: &mut Environment,
+    env: &mut Environment,
     func: GuestFunction, // void (*func)(void *)
-    p: MutVoidPtr,
-    d: MutVoidPtr,
+    arg: MutVoidPtr,
+    dso_handle: MutVoidPtr,
 ) -> i32 {
-    // TODO: when this is implemented, make sure it's properly compatible with
-    // C atexit.
-    log!(
-        "TODO: __cxa_atexit({:?}, {:?}, {:?}) (unimplemented)",
+    log_dbg!("__cxa_atexit({:?}, {:?}, {:?})", func, arg, dso_handle);
+    State::get(env).exit_handlers.push(ExitHandler {
         func,
-        p,
-        d
-    );
+        arg,
+        dso_handle,
+    });
     0 // success
 }
 
-fn __cxa_finalize(_env: &mut Environment, d: MutVoidPtr) {
-    log!("TODO: __cxa_finalize({:?}) (unimplemented)", d);
+/// `atexit()` is the same as `__cxa_atexit()` with no argument or DSO handle.
+/// Passing an argument to a function that doesn't take one is harmless.
+pub fn atexit(env: &mut Environment, func: GuestFunction) -> i32 {
+    __cxa_atexit(env, func, Ptr::null(), Ptr::null())
+}
+
+fn __cxa_finalize(env: &mut Environment, dso_handle: MutVoidPtr) {
+    // Handlers can register further handlers, so the list must be checked
+    // again after each call.
+    loop {
+        let handlers = &mut State::get(env).exit_handlers;
+        let Some(idx) = handlers
+            .iter()
+            .rposition(|handler| dso_handle.is_null() || handler.dso_handle == dso_handle)
+        else {
+            break;
+        };
+        let ExitHandler { func, arg, .. } = handlers.remove(idx);
+        log_dbg!("Calling exit handler {:?}({:?})", func, arg);
+        () = func.call_from_host(env, (arg,));
+    }
+}
+
+/// Called by `exit()` and equivalents. Calls all the registered exit handlers,
+/// which includes destructors for C++ static objects.
+pub fn run_exit_handlers(env: &mut Environment) {
+    __cxa_finalize(env, Ptr::null());
 }
 
 pub const FUNCTIONS: FunctionExports = &[
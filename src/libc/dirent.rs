@@ -7,7 +7,8 @@
 
 use crate::dyld::FunctionExports;
 use crate::fs::GuestPath;
-use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::libc::errno::{set_errno, ENOENT, ENOTDIR};
+use crate::mem::{guest_size_of, ConstPtr, MutPtr, Ptr, SafeRead};
 use crate::{export_c_func, impl_GuestRet_for_large_struct, Environment};
 use std::collections::HashMap;
 
@@ -23,6 +24,9 @@ unsafe impl SafeRead for DIR {}
 // While early iOS is 32-bit system, underling file system uses 64-bit inodes!
 const MAXPATHLEN: usize = 1024;
 
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
 #[repr(C, packed)]
@@ -37,10 +41,18 @@ struct dirent {
 unsafe impl SafeRead for dirent {}
 impl_GuestRet_for_large_struct!(dirent);
 
+struct OpenDir {
+    /// Names and `d_type` values of the directory's entries, including the
+    /// `.` and `..` entries.
+    entries: Vec<(String, u8)>,
+    /// Buffer returned by `readdir`, which is overwritten by each call for the
+    /// same directory stream, like on a real system.
+    dirent: MutPtr<dirent>,
+}
+
 #[derive(Default)]
 pub struct State {
-    open_dirs: HashMap<MutPtr<DIR>, Vec<String>>,
-    read_dirs: HashMap<MutPtr<DIR>, Vec<MutPtr<dirent>>>,
+    open_dirs: HashMap<MutPtr<DIR>, OpenDir>,
 }
 impl State {
     fn get_mut(env: &mut Environment) -> &mut Self {
@@ -52,69 +64,104 @@ fn opendir(env: &mut Environment, filename: ConstPtr<u8>) -> MutPtr<DIR> {
     let path_string = env.mem.cstr_at_utf8(filename).unwrap().to_owned();
     log_dbg!("opendir: filename {}", path_string);
     let guest_path = GuestPath::new(&path_string);
-    let is_dir = env.fs.is_dir(guest_path);
-    if is_dir {
-        let dir = env.mem.alloc_and_write(DIR { idx: 0 });
-        log_dbg!("opendir: new DIR ptr: {:?}", dir);
-        let iter = env.fs.enumerate(guest_path).unwrap();
-        let vec = iter.map(|str| str.to_string()).collect();
-        assert!(!State::get_mut(env).open_dirs.contains_key(&dir));
-        State::get_mut(env).open_dirs.insert(dir, vec);
-        assert!(!State::get_mut(env).read_dirs.contains_key(&dir));
-        State::get_mut(env).read_dirs.insert(dir, Vec::new());
-        dir
-    } else {
-        Ptr::null()
+    if !env.fs.is_dir(guest_path) {
+        let errno = if env.fs.exists(guest_path) {
+            ENOTDIR
+        } else {
+            ENOENT
+        };
+        set_errno(env, errno);
+        return Ptr::null();
     }
+
+    let mut entries = vec![(".".to_string(), DT_DIR), ("..".to_string(), DT_DIR)];
+    for name in env.fs.enumerate(guest_path).unwrap() {
+        let d_type = if env.fs.is_dir(&guest_path.join(name)) {
+            DT_DIR
+        } else {
+            DT_REG
+        };
+        entries.push((name.to_string(), d_type));
+    }
+
+    let dir = env.mem.alloc_and_write(DIR { idx: 0 });
+    log_dbg!("opendir: new DIR ptr: {:?}", dir);
+    let dirent = env.mem.alloc(guest_size_of::<dirent>()).cast();
+    let old = State::get_mut(env)
+        .open_dirs
+        .insert(dir, OpenDir { entries, dirent });
+    assert!(old.is_none());
+    dir
 }
 
-// TODO: return '.' and '..' entries as well
-fn readdir(env: &mut Environment, dirp: MutPtr<DIR>) -> MutPtr<dirent> {
+/// Shared implementation of `readdir` and `readdir_r`. Writes the next entry,
+/// if any, to `entry`.
+fn read_next_entry(env: &mut Environment, dirp: MutPtr<DIR>, entry: MutPtr<dirent>) -> bool {
     let mut dir = env.mem.read(dirp);
-    let vec = env.libc_state.dirent.open_dirs.get(&dirp).unwrap();
+    let open_dir = State::get_mut(env).open_dirs.get(&dirp).unwrap();
     log_dbg!(
         "readdir: dirp {:?}, idx {}, entry '{:?}'",
         dirp,
         dir.idx,
-        vec.get(dir.idx)
+        open_dir.entries.get(dir.idx)
     );
-    if let Some(str) = vec.get(dir.idx) {
-        dir.idx += 1;
-        env.mem.write(dirp, dir);
-
-        let len = str.len();
-        // TODO: fill other fields
-        let mut dirent = dirent {
-            d_ino: 0,
-            d_seekoff: 0,
-            d_reclen: 0,
-            d_namlen: len as u16,
-            d_type: 0,
-            d_name: [b'\0'; MAXPATHLEN],
-        };
-        dirent.d_name[..len].copy_from_slice(str.as_bytes());
-        let res = env.mem.alloc_and_write(dirent);
-        env.libc_state
-            .dirent
-            .read_dirs
-            .get_mut(&dirp)
-            .unwrap()
-            .push(res);
-        res
+    let Some((name, d_type)) = open_dir.entries.get(dir.idx) else {
+        return false;
+    };
+
+    let len = name.len();
+    // TODO: fill in d_ino
+    let mut new_entry = dirent {
+        d_ino: 0,
+        d_seekoff: dir.idx as u64,
+        d_reclen: std::mem::size_of::<dirent>() as u16,
+        d_namlen: len as u16,
+        d_type: *d_type,
+        d_name: [b'\0'; MAXPATHLEN],
+    };
+    new_entry.d_name[..len].copy_from_slice(name.as_bytes());
+    env.mem.write(entry, new_entry);
+
+    dir.idx += 1;
+    env.mem.write(dirp, dir);
+    true
+}
+
+fn readdir(env: &mut Environment, dirp: MutPtr<DIR>) -> MutPtr<dirent> {
+    let entry = State::get_mut(env).open_dirs.get(&dirp).unwrap().dirent;
+    if read_next_entry(env, dirp, entry) {
+        entry
     } else {
         Ptr::null()
     }
 }
 
+fn readdir_r(
+    env: &mut Environment,
+    dirp: MutPtr<DIR>,
+    entry: MutPtr<dirent>,
+    result: MutPtr<MutPtr<dirent>>,
+) -> i32 {
+    let next = if read_next_entry(env, dirp, entry) {
+        entry
+    } else {
+        Ptr::null()
+    };
+    env.mem.write(result, next);
+    0 // Success
+}
+
+fn rewinddir(env: &mut Environment, dirp: MutPtr<DIR>) {
+    log_dbg!("rewinddir: dirp {:?}", dirp);
+    assert!(State::get_mut(env).open_dirs.contains_key(&dirp));
+    env.mem.write(dirp, DIR { idx: 0 });
+}
+
 fn closedir(env: &mut Environment, dirp: MutPtr<DIR>) -> i32 {
     log_dbg!("closedir: dirp {:?}", dirp);
-    if let Some(vec) = env.libc_state.dirent.read_dirs.remove(&dirp) {
-        for dirent in vec {
-            env.mem.free(dirent.cast());
-        }
-    }
-    if env.libc_state.dirent.open_dirs.remove(&dirp).is_some() {
-        // this avoid double free if closedir() is called twice
+    // this avoid double free if closedir() is called twice
+    if let Some(open_dir) = State::get_mut(env).open_dirs.remove(&dirp) {
+        env.mem.free(open_dir.dirent.cast());
         env.mem.free(dirp.cast());
     }
     0 // Success
@@ -123,5 +170,7 @@ fn closedir(env: &mut Environment, dirp: MutPtr<DIR>) -> i32 {
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(opendir(_)),
     export_c_func!(readdir(_)),
+    export_c_func!(readdir_r(_, _, _)),
+    export_c_func!(rewinddir(_)),
     export_c_func!(closedir(_)),
 ];
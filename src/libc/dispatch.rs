@@ -0,0 +1,924 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `dispatch/dispatch.h` (Grand Central Dispatch, libdispatch)
+//!
+//! This is built on top of ordinary guest threads. Queues other than the main
+//! queue get worker threads (created with `pthread_create()`) when work is
+//! submitted to them, which exit once the queue is empty. A serial queue has
+//! at most one worker, a concurrent queue has up to [MAX_CONCURRENT_WORKERS].
+//! The main queue and `dispatch_after()` timers are instead handled by the
+//! main thread's run loop (see [handle_main_queue]).
+//!
+//! Functions that need to wait (`dispatch_sync()`, `dispatch_group_wait()`,
+//! `dispatch_semaphore_wait()`, etc) poll their condition while running other
+//! threads, since there's no way to make the scheduler wait for these things.
+//!
+//! Resources:
+//! - [libdispatch source code](https://github.com/apple-oss-distributions/libdispatch)
+
+use super::mach_time::mach_absolute_time;
+use super::pthread::thread::{
+    pthread_attr_init, pthread_attr_setdetachstate, pthread_attr_t, pthread_create, pthread_t,
+    PTHREAD_CREATE_DETACHED,
+};
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant, HostFunction};
+use crate::environment::ThreadId;
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::objc::{_Block_copy, _Block_release, block_invoke_function, id, msg_class, release};
+use crate::Environment;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+#[allow(non_camel_case_types)]
+pub type dispatch_object_t = MutVoidPtr;
+#[allow(non_camel_case_types)]
+pub type dispatch_queue_t = dispatch_object_t;
+#[allow(non_camel_case_types)]
+pub type dispatch_group_t = dispatch_object_t;
+#[allow(non_camel_case_types)]
+pub type dispatch_semaphore_t = dispatch_object_t;
+#[allow(non_camel_case_types)]
+type dispatch_queue_attr_t = ConstVoidPtr;
+/// `void (^dispatch_block_t)(void)`
+#[allow(non_camel_case_types)]
+type dispatch_block_t = ConstVoidPtr;
+/// `void (*dispatch_function_t)(void *)`
+#[allow(non_camel_case_types)]
+type dispatch_function_t = GuestFunction;
+/// Nanoseconds on the [mach_absolute_time] clock.
+#[allow(non_camel_case_types)]
+type dispatch_time_t = u64;
+#[allow(non_camel_case_types)]
+type dispatch_once_t = i32;
+
+const DISPATCH_TIME_NOW: dispatch_time_t = 0;
+const DISPATCH_TIME_FOREVER: dispatch_time_t = !0;
+
+const DISPATCH_QUEUE_PRIORITY_HIGH: i32 = 2;
+const DISPATCH_QUEUE_PRIORITY_DEFAULT: i32 = 0;
+const DISPATCH_QUEUE_PRIORITY_LOW: i32 = -2;
+const DISPATCH_QUEUE_PRIORITY_BACKGROUND: i32 = i16::MIN as i32;
+
+/// Values of a `dispatch_once_t` predicate. Apps initialize it to zero.
+const ONCE_NOT_STARTED: dispatch_once_t = 0;
+const ONCE_RUNNING: dispatch_once_t = 1;
+const ONCE_DONE: dispatch_once_t = !0;
+
+/// The maximum number of worker threads for a concurrent queue. Original iOS
+/// devices only have one core, so there's no point having lots of these.
+const MAX_CONCURRENT_WORKERS: u32 = 4;
+
+/// How often a waiting thread checks whether it can continue.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+enum Work {
+    /// Heap copy of the block, released after it is run.
+    Block(MutVoidPtr),
+    /// Like [Work::Block], but for a block that takes an `int` argument, e.g.
+    /// a `notify_handler_t`.
+    BlockWithInt { block: MutVoidPtr, arg: i32 },
+    Function {
+        function: dispatch_function_t,
+        context: MutVoidPtr,
+    },
+}
+
+struct WorkItem {
+    work: Work,
+    /// Group to leave once the work is done (`dispatch_group_async()`).
+    group: Option<dispatch_group_t>,
+}
+
+struct Queue {
+    label: MutPtr<u8>,
+    /// Maximum number of items that can be run at the same time.
+    width: u32,
+    items: VecDeque<WorkItem>,
+    /// Number of threads currently running items from this queue.
+    running: u32,
+    /// Number of items ever submitted to this queue.
+    submitted: u64,
+    /// Number of items from this queue that have finished running.
+    finished: u64,
+}
+
+struct Group {
+    count: u32,
+    notify: Vec<(dispatch_queue_t, WorkItem)>,
+}
+
+struct Semaphore {
+    value: i32,
+    waiters: u32,
+}
+
+enum DispatchObjectKind {
+    Queue(Queue),
+    Group(Group),
+    Semaphore(Semaphore),
+}
+
+struct DispatchObject {
+    kind: DispatchObjectKind,
+    /// [None] for the main and global queues, which can't be freed.
+    refcount: Option<u32>,
+    context: MutVoidPtr,
+}
+
+struct Timer {
+    due_by: Instant,
+    queue: dispatch_queue_t,
+    item: WorkItem,
+}
+
+#[derive(Default)]
+pub struct State {
+    objects: HashMap<dispatch_object_t, DispatchObject>,
+    main_queue: Option<dispatch_queue_t>,
+    global_queues: HashMap<i32, dispatch_queue_t>,
+    /// Value of `DISPATCH_QUEUE_CONCURRENT`.
+    concurrent_attr: Option<ConstVoidPtr>,
+    timers: Vec<Timer>,
+    worker_function: Option<GuestFunction>,
+    /// Queue whose item each thread is currently running, if any.
+    current_queues: HashMap<ThreadId, dispatch_queue_t>,
+    /// Prevents re-entry if main queue work runs a nested run loop.
+    draining_main_queue: bool,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.dispatch
+    }
+    fn queue(&mut self, queue: dispatch_queue_t) -> &mut Queue {
+        match self.objects.get_mut(&queue) {
+            Some(DispatchObject {
+                kind: DispatchObjectKind::Queue(queue),
+                ..
+            }) => queue,
+            _ => panic!("{:?} is not a dispatch queue", queue),
+        }
+    }
+    fn group(&mut self, group: dispatch_group_t) -> &mut Group {
+        match self.objects.get_mut(&group) {
+            Some(DispatchObject {
+                kind: DispatchObjectKind::Group(group),
+                ..
+            }) => group,
+            _ => panic!("{:?} is not a dispatch group", group),
+        }
+    }
+    fn semaphore(&mut self, semaphore: dispatch_semaphore_t) -> &mut Semaphore {
+        match self.objects.get_mut(&semaphore) {
+            Some(DispatchObject {
+                kind: DispatchObjectKind::Semaphore(semaphore),
+                ..
+            }) => semaphore,
+            _ => panic!("{:?} is not a dispatch semaphore", semaphore),
+        }
+    }
+}
+
+/// Allocate the guest-visible handle for a new dispatch object. The contents
+/// are never read, only the address matters.
+fn create_object(env: &mut Environment, kind: DispatchObjectKind, refcounted: bool) -> MutVoidPtr {
+    let object = env.mem.alloc(4);
+    State::get(env).objects.insert(
+        object,
+        DispatchObject {
+            kind,
+            refcount: refcounted.then_some(1),
+            context: Ptr::null(),
+        },
+    );
+    object
+}
+
+fn create_queue(env: &mut Environment, label: &[u8], width: u32, refcounted: bool) -> MutVoidPtr {
+    let label = env.mem.alloc_and_write_cstr(label);
+    let queue = Queue {
+        label,
+        width,
+        items: VecDeque::new(),
+        running: 0,
+        submitted: 0,
+        finished: 0,
+    };
+    create_object(env, DispatchObjectKind::Queue(queue), refcounted)
+}
+
+fn main_queue(env: &mut Environment) -> dispatch_queue_t {
+    if let Some(queue) = State::get(env).main_queue {
+        return queue;
+    }
+    let queue = create_queue(env, b"com.apple.main-thread", 1, false);
+    State::get(env).main_queue = Some(queue);
+    queue
+}
+
+fn concurrent_attr(env: &mut Environment) -> ConstVoidPtr {
+    if let Some(attr) = State::get(env).concurrent_attr {
+        return attr;
+    }
+    let attr = env.mem.alloc(4).cast_const();
+    State::get(env).concurrent_attr = Some(attr);
+    attr
+}
+
+/// Convert a `dispatch_time_t` to a deadline. [None] means forever.
+fn deadline(env: &Environment, time: dispatch_time_t) -> Option<Instant> {
+    (time != DISPATCH_TIME_FOREVER).then(|| env.startup_time + Duration::from_nanos(time))
+}
+
+/// Run other threads until `condition` is true, or until the deadline passes,
+/// in which case [false] is returned.
+fn wait_until(
+    env: &mut Environment,
+    deadline: Option<Instant>,
+    mut condition: impl FnMut(&mut Environment) -> bool,
+) -> bool {
+    loop {
+        if condition(env) {
+            return true;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+        env.sleep(WAIT_POLL_INTERVAL, /* tail_call: */ false);
+    }
+}
+
+fn block_work(env: &mut Environment, block: dispatch_block_t) -> Work {
+    assert!(!block.is_null());
+    Work::Block(_Block_copy(env, block))
+}
+
+/// Run a work item from `queue` on the current thread.
+fn run_item(env: &mut Environment, queue: dispatch_queue_t, item: WorkItem) {
+    let thread = env.current_thread;
+    let previous_queue = State::get(env).current_queues.insert(thread, queue);
+
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+    match item.work {
+        Work::Block(block) => {
+            log_dbg!("Running block {:?} from dispatch queue {:?}", block, queue);
+            let invoke = block_invoke_function(env, block.cast());
+            () = invoke.call_from_host(env, (block,));
+            _Block_release(env, block.cast_const());
+        }
+        Work::BlockWithInt { block, arg } => {
+            log_dbg!(
+                "Running block {:?}({}) from dispatch queue {:?}",
+                block,
+                arg,
+                queue
+            );
+            let invoke = block_invoke_function(env, block.cast());
+            () = invoke.call_from_host(env, (block, arg));
+            _Block_release(env, block.cast_const());
+        }
+        Work::Function { function, context } => {
+            log_dbg!(
+                "Running {:?}({:?}) from dispatch queue {:?}",
+                function,
+                context,
+                queue
+            );
+            () = function.call_from_host(env, (context,));
+        }
+    }
+    release(env, pool);
+
+    let state = State::get(env);
+    if let Some(previous_queue) = previous_queue {
+        state.current_queues.insert(thread, previous_queue);
+    } else {
+        state.current_queues.remove(&thread);
+    }
+    state.queue(queue).finished += 1;
+
+    if let Some(group) = item.group {
+        dispatch_group_leave(env, group);
+    }
+}
+
+/// Add a work item to a queue, starting a worker thread for it if possible.
+/// Returns the item's sequence number (see [Queue::finished]).
+fn submit(env: &mut Environment, queue: dispatch_queue_t, item: WorkItem) -> u64 {
+    let queue_obj = State::get(env).queue(queue);
+    queue_obj.items.push_back(item);
+    queue_obj.submitted += 1;
+    let sequence_number = queue_obj.submitted;
+    start_worker_if_needed(env, queue);
+    sequence_number
+}
+
+/// Start a worker thread for a queue if it has pending items and fewer items
+/// running than its width allows.
+fn start_worker_if_needed(env: &mut Environment, queue: dispatch_queue_t) {
+    let is_main_queue = Some(queue) == State::get(env).main_queue;
+    let queue_obj = State::get(env).queue(queue);
+    // The main queue is drained by the run loop instead.
+    if is_main_queue || queue_obj.items.is_empty() || queue_obj.running >= queue_obj.width {
+        return;
+    }
+    queue_obj.running += 1;
+
+    let worker_function = match State::get(env).worker_function {
+        Some(f) => f,
+        None => {
+            let symb = "__touchHLE_dispatch_worker";
+            let hf: HostFunction = &(_touchHLE_dispatch_worker as fn(&mut Environment, _));
+            let gf = env.dyld.create_guest_function(&mut env.mem, symb, hf);
+            State::get(env).worker_function = Some(gf);
+            gf
+        }
+    };
+
+    let attr: MutPtr<pthread_attr_t> = env.mem.alloc(guest_size_of::<pthread_attr_t>()).cast();
+    pthread_attr_init(env, attr);
+    pthread_attr_setdetachstate(env, attr, PTHREAD_CREATE_DETACHED);
+    let thread_ptr: MutPtr<pthread_t> = env.mem.alloc(guest_size_of::<pthread_t>()).cast();
+    let res = pthread_create(env, thread_ptr, attr.cast_const(), worker_function, queue);
+    assert_eq!(res, 0);
+    env.mem.free(thread_ptr.cast());
+    env.mem.free(attr.cast());
+}
+
+/// Start routine of worker threads.
+fn _touchHLE_dispatch_worker(env: &mut Environment, queue: dispatch_queue_t) {
+    log_dbg!("Dispatch worker started for queue {:?}", queue);
+    loop {
+        let queue_obj = State::get(env).queue(queue);
+        let Some(item) = queue_obj.items.pop_front() else {
+            queue_obj.running -= 1;
+            break;
+        };
+        run_item(env, queue, item);
+    }
+    log_dbg!("Dispatch worker for queue {:?} exiting", queue);
+}
+
+/// Run a work item synchronously on the current thread, while respecting the
+/// queue's width.
+fn run_sync(env: &mut Environment, queue: dispatch_queue_t, work: Work) {
+    let item = WorkItem { work, group: None };
+    let thread = env.current_thread;
+    let state = State::get(env);
+    let current_queue = state.current_queues.get(&thread).copied();
+    let is_main_queue = Some(queue) == state.main_queue;
+
+    if is_main_queue {
+        // Real libdispatch would deadlock in this case.
+        assert!(
+            env.current_thread != 0,
+            "dispatch_sync() to the main queue from the main thread"
+        );
+        let sequence_number = submit(env, queue, item);
+        wait_until(env, None, |env| {
+            State::get(env).queue(queue).finished >= sequence_number
+        });
+        return;
+    }
+
+    if State::get(env).queue(queue).width == 1 {
+        // Real libdispatch would deadlock in this case.
+        assert!(
+            current_queue != Some(queue),
+            "dispatch_sync() to the current serial queue"
+        );
+        // Wait for the queue to become idle, then occupy it.
+        wait_until(env, None, |env| {
+            let queue = State::get(env).queue(queue);
+            queue.running == 0 && queue.items.is_empty()
+        });
+    }
+    let queue_obj = State::get(env).queue(queue);
+    queue_obj.running += 1;
+    queue_obj.submitted += 1;
+    run_item(env, queue, item);
+    State::get(env).queue(queue).running -= 1;
+    // Items submitted while this one was running might not have a worker yet.
+    start_worker_if_needed(env, queue);
+}
+
+/// Called by the main thread's run loop. Runs items from the main queue, and
+/// submits `dispatch_after()` work that is due. Returns the time the next
+/// timer is due, if any.
+pub fn handle_main_queue(env: &mut Environment) -> Option<Instant> {
+    if env.current_thread != 0 || State::get(env).draining_main_queue {
+        return None;
+    }
+
+    let now = Instant::now();
+    let mut i = 0;
+    while i < State::get(env).timers.len() {
+        if State::get(env).timers[i].due_by <= now {
+            let Timer { queue, item, .. } = State::get(env).timers.swap_remove(i);
+            submit(env, queue, item);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(queue) = State::get(env).main_queue {
+        State::get(env).draining_main_queue = true;
+        // Only run the items that were already there, so that an item that
+        // re-submits itself doesn't stall the run loop.
+        let count = State::get(env).queue(queue).items.len();
+        for _ in 0..count {
+            let Some(item) = State::get(env).queue(queue).items.pop_front() else {
+                break;
+            };
+            run_item(env, queue, item);
+        }
+        State::get(env).draining_main_queue = false;
+    }
+
+    State::get(env)
+        .timers
+        .iter()
+        .map(|timer| timer.due_by)
+        .min()
+}
+
+fn dispatch_get_global_queue(
+    env: &mut Environment,
+    priority: i32,
+    flags: GuestUSize,
+) -> dispatch_queue_t {
+    assert_eq!(flags, 0);
+    let label: &[u8] = match priority {
+        DISPATCH_QUEUE_PRIORITY_HIGH => b"com.apple.root.high-priority",
+        DISPATCH_QUEUE_PRIORITY_DEFAULT => b"com.apple.root.default-priority",
+        DISPATCH_QUEUE_PRIORITY_LOW => b"com.apple.root.low-priority",
+        DISPATCH_QUEUE_PRIORITY_BACKGROUND => b"com.apple.root.background-priority",
+        _ => panic!("Unknown dispatch queue priority: {}", priority),
+    };
+    if let Some(&queue) = State::get(env).global_queues.get(&priority) {
+        return queue;
+    }
+    let queue = create_queue(env, label, MAX_CONCURRENT_WORKERS, false);
+    State::get(env).global_queues.insert(priority, queue);
+    queue
+}
+
+fn dispatch_queue_create(
+    env: &mut Environment,
+    label: ConstPtr<u8>,
+    attr: dispatch_queue_attr_t,
+) -> dispatch_queue_t {
+    let width = if attr.is_null() {
+        1
+    } else if attr == concurrent_attr(env) {
+        MAX_CONCURRENT_WORKERS
+    } else {
+        panic!("Unknown dispatch queue attribute: {:?}", attr);
+    };
+    let label_bytes = if label.is_null() {
+        Vec::new()
+    } else {
+        env.mem.cstr_at(label).to_vec()
+    };
+    let queue = create_queue(env, &label_bytes, width, true);
+    log_dbg!(
+        "dispatch_queue_create({:?} {:?}, {:?}) => {:?}",
+        label,
+        String::from_utf8_lossy(&label_bytes),
+        attr,
+        queue
+    );
+    queue
+}
+
+fn dispatch_get_current_queue(env: &mut Environment) -> dispatch_queue_t {
+    let thread = env.current_thread;
+    if let Some(&queue) = State::get(env).current_queues.get(&thread) {
+        return queue;
+    }
+    if env.current_thread == 0 {
+        main_queue(env)
+    } else {
+        dispatch_get_global_queue(env, DISPATCH_QUEUE_PRIORITY_DEFAULT, 0)
+    }
+}
+
+fn dispatch_queue_get_label(env: &mut Environment, queue: dispatch_queue_t) -> ConstPtr<u8> {
+    let queue = if queue.is_null() {
+        dispatch_get_current_queue(env)
+    } else {
+        queue
+    };
+    State::get(env).queue(queue).label.cast_const()
+}
+
+fn dispatch_retain(env: &mut Environment, object: dispatch_object_t) {
+    let object = State::get(env).objects.get_mut(&object).unwrap();
+    if let Some(refcount) = &mut object.refcount {
+        *refcount += 1;
+    }
+}
+
+fn dispatch_release(env: &mut Environment, object: dispatch_object_t) {
+    let state = State::get(env);
+    let object_obj = state.objects.get_mut(&object).unwrap();
+    let Some(refcount) = &mut object_obj.refcount else {
+        return;
+    };
+    *refcount -= 1;
+    if *refcount > 0 {
+        return;
+    }
+    if let DispatchObjectKind::Queue(ref queue) = object_obj.kind {
+        if queue.running > 0 || !queue.items.is_empty() {
+            // TODO: free the queue once it becomes idle
+            log_dbg!("Leaking released dispatch queue {:?}, it's busy", object);
+            return;
+        }
+    }
+    log_dbg!("Freeing dispatch object {:?}", object);
+    let object_obj = state.objects.remove(&object).unwrap();
+    if let DispatchObjectKind::Queue(queue) = object_obj.kind {
+        env.mem.free(queue.label.cast());
+    }
+    env.mem.free(object);
+}
+
+fn dispatch_set_context(env: &mut Environment, object: dispatch_object_t, context: MutVoidPtr) {
+    State::get(env).objects.get_mut(&object).unwrap().context = context;
+}
+
+fn dispatch_get_context(env: &mut Environment, object: dispatch_object_t) -> MutVoidPtr {
+    State::get(env).objects.get(&object).unwrap().context
+}
+
+fn dispatch_async(env: &mut Environment, queue: dispatch_queue_t, block: dispatch_block_t) {
+    let work = block_work(env, block);
+    submit(env, queue, WorkItem { work, group: None });
+}
+
+/// For host code: like `dispatch_async()`, but for a block that takes an
+/// `int` argument, e.g. a `notify_handler_t`.
+pub fn async_block_with_int(
+    env: &mut Environment,
+    queue: dispatch_queue_t,
+    block: ConstVoidPtr,
+    arg: i32,
+) {
+    assert!(!block.is_null());
+    let block = _Block_copy(env, block);
+    let work = Work::BlockWithInt { block, arg };
+    submit(env, queue, WorkItem { work, group: None });
+}
+
+fn dispatch_async_f(
+    env: &mut Environment,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    let work = Work::Function { function, context };
+    submit(env, queue, WorkItem { work, group: None });
+}
+
+fn dispatch_sync(env: &mut Environment, queue: dispatch_queue_t, block: dispatch_block_t) {
+    let work = block_work(env, block);
+    run_sync(env, queue, work);
+}
+
+fn dispatch_sync_f(
+    env: &mut Environment,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    run_sync(env, queue, Work::Function { function, context });
+}
+
+/// `dispatch_apply()` is allowed to run the iterations in parallel, but running
+/// them serially on the current thread is also correct.
+fn dispatch_apply(
+    env: &mut Environment,
+    iterations: GuestUSize,
+    queue: dispatch_queue_t,
+    block: ConstVoidPtr, // void (^block)(size_t)
+) {
+    log_dbg!("dispatch_apply({}, {:?}, {:?})", iterations, queue, block);
+    let invoke = block_invoke_function(env, block.cast_mut().cast());
+    for i in 0..iterations {
+        () = invoke.call_from_host(env, (block, i));
+    }
+}
+
+fn dispatch_apply_f(
+    env: &mut Environment,
+    iterations: GuestUSize,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: GuestFunction, // void (*function)(void *, size_t)
+) {
+    log_dbg!(
+        "dispatch_apply_f({}, {:?}, {:?}, {:?})",
+        iterations,
+        queue,
+        context,
+        function
+    );
+    for i in 0..iterations {
+        () = function.call_from_host(env, (context, i));
+    }
+}
+
+fn run_once(
+    env: &mut Environment,
+    predicate: MutPtr<dispatch_once_t>,
+    work: impl FnOnce(&mut Environment),
+) {
+    match env.mem.read(predicate) {
+        ONCE_DONE => (),
+        ONCE_NOT_STARTED => {
+            env.mem.write(predicate, ONCE_RUNNING);
+            work(env);
+            env.mem.write(predicate, ONCE_DONE);
+        }
+        ONCE_RUNNING => {
+            log_dbg!("Waiting for another thread's dispatch_once() to finish");
+            wait_until(env, None, |env| env.mem.read(predicate) == ONCE_DONE);
+        }
+        other => panic!("Invalid dispatch_once_t value: {}", other),
+    }
+}
+
+fn dispatch_once(
+    env: &mut Environment,
+    predicate: MutPtr<dispatch_once_t>,
+    block: dispatch_block_t,
+) {
+    run_once(env, predicate, |env| {
+        let invoke = block_invoke_function(env, block.cast_mut().cast());
+        () = invoke.call_from_host(env, (block,));
+    });
+}
+
+fn dispatch_once_f(
+    env: &mut Environment,
+    predicate: MutPtr<dispatch_once_t>,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    run_once(env, predicate, |env| {
+        () = function.call_from_host(env, (context,));
+    });
+}
+
+fn dispatch_time(env: &mut Environment, when: dispatch_time_t, delta: i64) -> dispatch_time_t {
+    if when == DISPATCH_TIME_FOREVER {
+        return DISPATCH_TIME_FOREVER;
+    }
+    let when = if when == DISPATCH_TIME_NOW {
+        mach_absolute_time(env)
+    } else {
+        when
+    };
+    when.saturating_add_signed(delta)
+        .min(DISPATCH_TIME_FOREVER - 1)
+}
+
+fn dispatch_walltime(
+    env: &mut Environment,
+    when: ConstVoidPtr, // const struct timespec *
+    delta: i64,
+) -> dispatch_time_t {
+    // TODO: support other start times. There's no wall clock distinction here,
+    // since touchHLE's clocks don't jump.
+    assert!(when.is_null());
+    dispatch_time(env, DISPATCH_TIME_NOW, delta)
+}
+
+fn schedule_after(
+    env: &mut Environment,
+    when: dispatch_time_t,
+    queue: dispatch_queue_t,
+    work: Work,
+) {
+    let item = WorkItem { work, group: None };
+    let Some(due_by) = deadline(env, when) else {
+        // Never runs.
+        return;
+    };
+    State::get(env).timers.push(Timer {
+        due_by,
+        queue,
+        item,
+    });
+}
+
+fn dispatch_after(
+    env: &mut Environment,
+    when: dispatch_time_t,
+    queue: dispatch_queue_t,
+    block: dispatch_block_t,
+) {
+    let work = block_work(env, block);
+    schedule_after(env, when, queue, work);
+}
+
+fn dispatch_after_f(
+    env: &mut Environment,
+    when: dispatch_time_t,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    schedule_after(env, when, queue, Work::Function { function, context });
+}
+
+fn dispatch_semaphore_create(env: &mut Environment, value: i32) -> dispatch_semaphore_t {
+    if value < 0 {
+        return Ptr::null();
+    }
+    let semaphore = Semaphore { value, waiters: 0 };
+    create_object(env, DispatchObjectKind::Semaphore(semaphore), true)
+}
+
+/// Returns nonzero if a waiting thread was woken.
+fn dispatch_semaphore_signal(env: &mut Environment, semaphore: dispatch_semaphore_t) -> i32 {
+    let semaphore = State::get(env).semaphore(semaphore);
+    semaphore.value += 1;
+    (semaphore.waiters > 0).into()
+}
+
+/// Returns nonzero on timeout.
+fn dispatch_semaphore_wait(
+    env: &mut Environment,
+    semaphore: dispatch_semaphore_t,
+    timeout: dispatch_time_t,
+) -> i32 {
+    let deadline = deadline(env, timeout);
+    State::get(env).semaphore(semaphore).waiters += 1;
+    let acquired = wait_until(env, deadline, |env| {
+        let semaphore = State::get(env).semaphore(semaphore);
+        if semaphore.value > 0 {
+            semaphore.value -= 1;
+            true
+        } else {
+            false
+        }
+    });
+    State::get(env).semaphore(semaphore).waiters -= 1;
+    if acquired {
+        0
+    } else {
+        1
+    }
+}
+
+fn dispatch_group_create(env: &mut Environment) -> dispatch_group_t {
+    let group = Group {
+        count: 0,
+        notify: Vec::new(),
+    };
+    create_object(env, DispatchObjectKind::Group(group), true)
+}
+
+fn dispatch_group_enter(env: &mut Environment, group: dispatch_group_t) {
+    State::get(env).group(group).count += 1;
+}
+
+fn dispatch_group_leave(env: &mut Environment, group: dispatch_group_t) {
+    let group_obj = State::get(env).group(group);
+    group_obj.count = group_obj
+        .count
+        .checked_sub(1)
+        .expect("Unbalanced dispatch_group_leave()");
+    if group_obj.count != 0 {
+        return;
+    }
+    let notify = std::mem::take(&mut group_obj.notify);
+    for (queue, item) in notify {
+        submit(env, queue, item);
+    }
+}
+
+fn dispatch_group_async(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    queue: dispatch_queue_t,
+    block: dispatch_block_t,
+) {
+    dispatch_group_enter(env, group);
+    let work = block_work(env, block);
+    let group = Some(group);
+    submit(env, queue, WorkItem { work, group });
+}
+
+fn dispatch_group_async_f(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    dispatch_group_enter(env, group);
+    let work = Work::Function { function, context };
+    let group = Some(group);
+    submit(env, queue, WorkItem { work, group });
+}
+
+/// Returns nonzero on timeout.
+fn dispatch_group_wait(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    timeout: dispatch_time_t,
+) -> GuestUSize {
+    let deadline = deadline(env, timeout);
+    let finished = wait_until(env, deadline, |env| State::get(env).group(group).count == 0);
+    if finished {
+        0
+    } else {
+        1
+    }
+}
+
+fn group_notify(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    queue: dispatch_queue_t,
+    work: Work,
+) {
+    let item = WorkItem { work, group: None };
+    let group_obj = State::get(env).group(group);
+    if group_obj.count == 0 {
+        submit(env, queue, item);
+    } else {
+        group_obj.notify.push((queue, item));
+    }
+}
+
+fn dispatch_group_notify(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    queue: dispatch_queue_t,
+    block: dispatch_block_t,
+) {
+    let work = block_work(env, block);
+    group_notify(env, group, queue, work);
+}
+
+fn dispatch_group_notify_f(
+    env: &mut Environment,
+    group: dispatch_group_t,
+    queue: dispatch_queue_t,
+    context: MutVoidPtr,
+    function: dispatch_function_t,
+) {
+    group_notify(env, group, queue, Work::Function { function, context });
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "__dispatch_main_q",
+        HostConstant::CustomWithEnv(|env| main_queue(env).cast_const()),
+    ),
+    (
+        "__dispatch_queue_attr_concurrent",
+        HostConstant::CustomWithEnv(concurrent_attr),
+    ),
+];
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(dispatch_get_global_queue(_, _)),
+    export_c_func!(dispatch_queue_create(_, _)),
+    export_c_func!(dispatch_get_current_queue()),
+    export_c_func!(dispatch_queue_get_label(_)),
+    export_c_func!(dispatch_retain(_)),
+    export_c_func!(dispatch_release(_)),
+    export_c_func!(dispatch_set_context(_, _)),
+    export_c_func!(dispatch_get_context(_)),
+    export_c_func!(dispatch_async(_, _)),
+    export_c_func!(dispatch_async_f(_, _, _)),
+    export_c_func!(dispatch_sync(_, _)),
+    export_c_func!(dispatch_sync_f(_, _, _)),
+    export_c_func!(dispatch_apply(_, _, _)),
+    export_c_func!(dispatch_apply_f(_, _, _, _)),
+    export_c_func!(dispatch_once(_, _)),
+    export_c_func!(dispatch_once_f(_, _, _)),
+    export_c_func!(dispatch_time(_, _)),
+    export_c_func!(dispatch_walltime(_, _)),
+    export_c_func!(dispatch_after(_, _, _)),
+    export_c_func!(dispatch_after_f(_, _, _, _)),
+    export_c_func!(dispatch_semaphore_create(_)),
+    export_c_func!(dispatch_semaphore_signal(_)),
+    export_c_func!(dispatch_semaphore_wait(_, _)),
+    export_c_func!(dispatch_group_create()),
+    export_c_func!(dispatch_group_enter(_)),
+    export_c_func!(dispatch_group_leave(_)),
+    export_c_func!(dispatch_group_async(_, _, _)),
+    export_c_func!(dispatch_group_async_f(_, _, _, _)),
+    export_c_func!(dispatch_group_wait(_, _)),
+    export_c_func!(dispatch_group_notify(_, _, _)),
+    export_c_func!(dispatch_group_notify_f(_, _, _, _)),
+];
@@ -4,47 +4,345 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `dlfcn.h` (`dlopen()` and friends)
+//!
+//! Libraries with host implementations can be "opened", in which case symbols
+//! are looked up among the host functions. Dynamic libraries shipped inside the
+//! app bundle are really loaded and linked, like the ones the app binary
+//! depends on at startup (see [crate::Environment::new]). Nothing is ever
+//! unloaded: iPhone OS's dyld can't unload libraries containing Objective-C
+//! code either.
 
-use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, MutVoidPtr, Ptr};
+use crate::dyld::{export_c_func, Dyld, FunctionExports};
+use crate::fs::{GuestPath, GuestPathBuf};
+use crate::mach_o::{MachO, SectionType};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::objc::call_load_methods;
 use crate::Environment;
+use std::collections::HashMap;
 
-const ALLOWED_LIBRARIES: [Result<&str, &[u8]>; 2] = [
-    Ok("/usr/lib/libSystem.B.dylib"),
-    Ok("/System/Library/Frameworks/OpenAL.framework/OpenAL"),
+const HOST_LIBRARIES: &[&str] = &[
+    "/usr/lib/libSystem.B.dylib",
+    "/System/Library/Frameworks/OpenAL.framework/OpenAL",
 ];
 
-fn dlopen(env: &mut Environment, path: ConstPtr<u8>, _mode: i32) -> MutVoidPtr {
-    // TODO: dlopen() support for real dynamic libraries, and support for all
-    // libraries with host implementations.
-    assert!(ALLOWED_LIBRARIES.contains(&env.mem.cstr_at_utf8(path)));
-    // For convenience, use the path as the handle.
+// Special handles from Apple's dlfcn.h. These all mean the symbol should be
+// searched for everywhere, which isn't quite right for RTLD_NEXT and
+// RTLD_SELF, but close enough since we don't track which library the caller
+// is in.
+const RTLD_NEXT: u32 = -1i32 as u32;
+const RTLD_DEFAULT: u32 = -2i32 as u32;
+const RTLD_SELF: u32 = -3i32 as u32;
+const RTLD_MAIN_ONLY: u32 = -5i32 as u32;
+
+const RTLD_NOLOAD: i32 = 0x10;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct Dl_info {
+    dli_fname: ConstPtr<u8>,
+    dli_fbase: ConstVoidPtr,
+    dli_sname: ConstPtr<u8>,
+    dli_saddr: ConstVoidPtr,
+}
+unsafe impl SafeRead for Dl_info {}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Library {
+    /// `dlopen(NULL, ...)`, symbols are searched for everywhere.
+    MainProgram,
+    /// Library with a host implementation.
+    Host,
+    /// Library loaded from a Mach-O file, with this index in
+    /// [Environment::bins].
+    Guest(usize),
+}
+
+struct OpenLibrary {
+    /// Path, after resolving `@executable_path` etc. Empty for
+    /// [Library::MainProgram].
+    path: String,
+    library: Library,
+    /// The handle returned by `dlopen()`, which is a copy of the path.
+    handle: MutVoidPtr,
+}
+
+#[derive(Default)]
+pub struct State {
+    libraries: Vec<OpenLibrary>,
+    /// Message for the next `dlerror()` call.
+    error: Option<String>,
+    /// The string last returned by `dlerror()`, which is freed by the next
+    /// call.
+    error_string: Option<MutPtr<u8>>,
+    /// Strings returned by `dladdr()`, which must live forever.
+    dladdr_strings: HashMap<String, ConstPtr<u8>>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.dlfcn
+    }
+}
+
+fn set_error(env: &mut Environment, error: String) {
+    log!("Warning: {}", error);
+    State::get(env).error = Some(error);
+}
+
+/// Guest path of a binary in [Environment::bins].
+fn bin_path(env: &Environment, bin_idx: usize) -> String {
+    if bin_idx == 0 {
+        return env.bundle.executable_path().as_str().to_string();
+    }
+    let name = &env.bins[bin_idx].name;
+    if let Some(open_library) = env
+        .libc_state
+        .dlfcn
+        .libraries
+        .iter()
+        .find(|open_library| open_library.library == Library::Guest(bin_idx))
+    {
+        return open_library.path.clone();
+    }
+    env.bins[0]
+        .dynamic_libraries
+        .iter()
+        .find(|path| GuestPath::new(path).file_name() == Some(name))
+        .cloned()
+        .unwrap_or_else(|| name.clone())
+}
+
+fn resolve_path(env: &Environment, path: &str) -> GuestPathBuf {
+    // The library that's loading is usually the app binary, which is at the
+    // root of the bundle, so @loader_path is treated like @executable_path.
+    for prefix in ["@executable_path/", "@loader_path/"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            return env.bundle.bundle_path().join(rest);
+        }
+    }
+    GuestPathBuf::from(path.to_string())
+}
+
+/// Finds an already-loaded dynamic library, which might be one the app binary
+/// depends on, loaded at startup.
+fn find_guest_library(env: &Environment, path: &GuestPath) -> Option<usize> {
+    (1..env.bins.len()).find(|&bin_idx| bin_path(env, bin_idx) == path.as_str())
+}
+
+/// Loads and links a dynamic library, and runs its static initializers.
+/// Returns its index in [Environment::bins].
+fn load_guest_library(env: &mut Environment, path: &GuestPath) -> Result<usize, String> {
+    let bin = MachO::load_from_file(path, &env.fs, &mut env.mem, /* may_slide: */ true)
+        .map_err(|e| format!("Could not load {:?}: {}", path.as_str(), e))?;
+    for section in &bin.sections {
+        env.cpu.invalidate_cache_range(section.addr, section.size);
+    }
+    let bin_idx = env.bins.len();
+    env.bins.push(bin);
+    env.dyld
+        .do_runtime_linking(&env.bins, &mut env.mem, &mut env.objc);
+    Dyld::do_late_linking(env);
+    call_load_methods(env);
+
+    if let Some(section) = env.bins[bin_idx].get_section(SectionType::ModInitFuncPointers) {
+        log_dbg!("Calling static initializers for {:?}", path);
+        assert!(section.size % 4 == 0);
+        let base: ConstPtr<crate::abi::GuestFunction> = Ptr::from_bits(section.addr);
+        let count = section.size / 4;
+        for i in 0..count {
+            let func = env.mem.read(base + i);
+            func.call(env);
+        }
+        log_dbg!("Static initialization done");
+    }
+
+    Ok(bin_idx)
+}
+
+fn dlopen(env: &mut Environment, path: ConstPtr<u8>, mode: i32) -> MutVoidPtr {
+    // Libraries are identified by their (resolved) path, so opening the same
+    // library twice gives the same handle.
+    let path = if path.is_null() {
+        String::new()
+    } else {
+        let path = env.mem.cstr_at_utf8(path).unwrap();
+        if HOST_LIBRARIES.contains(&path) {
+            path.to_string()
+        } else {
+            resolve_path(env, path).into()
+        }
+    };
+
+    if let Some(open_library) = State::get(env)
+        .libraries
+        .iter()
+        .find(|open_library| open_library.path == path)
+    {
+        return open_library.handle;
+    }
+
+    let library = if path.is_empty() {
+        Library::MainProgram
+    } else if HOST_LIBRARIES.contains(&&*path) {
+        Library::Host
+    } else if !env.fs.is_file(GuestPath::new(&path)) {
+        set_error(env, format!("dlopen({}, {}): image not found", path, mode));
+        return Ptr::null();
+    } else if let Some(bin_idx) = find_guest_library(env, GuestPath::new(&path)) {
+        Library::Guest(bin_idx)
+    } else if mode & RTLD_NOLOAD != 0 {
+        return Ptr::null();
+    } else {
+        match load_guest_library(env, GuestPath::new(&path)) {
+            Ok(bin_idx) => Library::Guest(bin_idx),
+            Err(e) => {
+                set_error(env, format!("dlopen({}, {}): {}", path, mode, e));
+                return Ptr::null();
+            }
+        }
+    };
+
+    // For convenience, use a copy of the path as the handle.
     // TODO: Find out whether the handle is truly opaque on iPhone OS, and if
     // not, where it points.
-    path.cast_mut().cast()
+    let handle = env.mem.alloc_and_write_cstr(path.as_bytes()).cast();
+    State::get(env).libraries.push(OpenLibrary {
+        path,
+        library,
+        handle,
+    });
+    handle
 }
 
 fn dlsym(env: &mut Environment, handle: MutVoidPtr, symbol: ConstPtr<u8>) -> MutVoidPtr {
-    assert!(ALLOWED_LIBRARIES.contains(&env.mem.cstr_at_utf8(handle.cast())));
+    let library = match handle.to_bits() {
+        RTLD_NEXT | RTLD_DEFAULT | RTLD_SELF | RTLD_MAIN_ONLY => Library::MainProgram,
+        _ => {
+            let Some(open_library) = State::get(env)
+                .libraries
+                .iter()
+                .find(|open_library| open_library.handle == handle)
+            else {
+                set_error(env, format!("dlsym({:?}): invalid handle", handle));
+                return Ptr::null();
+            };
+            open_library.library
+        }
+    };
+
     // For some reason, the symbols passed to dlsym() don't have the leading _.
     let symbol = format!("_{}", env.mem.cstr_at_utf8(symbol).unwrap());
-    // TODO: error handling. dlsym() should just return NULL in this case, but
-    // currently it's probably more useful to have the emulator crash if there's
-    // no symbol found, since it most likely indicates a missing host function.
-    let addr = env
-        .dyld
-        .create_proc_address(&mut env.mem, &mut env.cpu, &symbol)
-        .unwrap_or_else(|_| panic!("dlsym() for unimplemented function {}", symbol));
-    Ptr::from_bits(addr.addr_with_thumb_bit())
+
+    let guest_bins = match library {
+        Library::MainProgram => 0..env.bins.len(),
+        Library::Host => 0..0,
+        Library::Guest(bin_idx) => bin_idx..(bin_idx + 1),
+    };
+    for bin_idx in guest_bins {
+        if let Some(&addr) = env.bins[bin_idx].exported_symbols.get(&symbol) {
+            return Ptr::from_bits(addr);
+        }
+    }
+
+    if matches!(library, Library::MainProgram | Library::Host) {
+        if let Ok(addr) = env
+            .dyld
+            .create_proc_address(&mut env.mem, &mut env.cpu, &symbol)
+        {
+            return Ptr::from_bits(addr.addr_with_thumb_bit());
+        }
+    }
+
+    // When the app asks a library with a host implementation for a specific
+    // symbol, it's probably a missing host function, and it's more useful to
+    // have the emulator crash than for the app to get NULL.
+    if library == Library::Host {
+        panic!("dlsym() for unimplemented function {}", symbol);
+    }
+    set_error(
+        env,
+        format!("dlsym({:?}, {}): symbol not found", handle, &symbol[1..]),
+    );
+    Ptr::null()
 }
 
 fn dlclose(env: &mut Environment, handle: MutVoidPtr) -> i32 {
-    assert!(ALLOWED_LIBRARIES.contains(&env.mem.cstr_at_utf8(handle.cast())));
-    0 // success
+    if State::get(env)
+        .libraries
+        .iter()
+        .any(|open_library| open_library.handle == handle)
+    {
+        0 // success
+    } else {
+        set_error(env, format!("dlclose({:?}): invalid handle", handle));
+        -1
+    }
+}
+
+fn dlerror(env: &mut Environment) -> ConstPtr<u8> {
+    if let Some(old_string) = State::get(env).error_string.take() {
+        env.mem.free(old_string.cast());
+    }
+    let Some(error) = State::get(env).error.take() else {
+        return Ptr::null();
+    };
+    let string = env.mem.alloc_and_write_cstr(error.as_bytes());
+    State::get(env).error_string = Some(string);
+    string.cast_const()
+}
+
+fn dladdr_string(env: &mut Environment, string: String) -> ConstPtr<u8> {
+    if let Some(&ptr) = State::get(env).dladdr_strings.get(&string) {
+        return ptr;
+    }
+    let ptr = env.mem.alloc_and_write_cstr(string.as_bytes()).cast_const();
+    State::get(env).dladdr_strings.insert(string, ptr);
+    ptr
+}
+
+fn dladdr(env: &mut Environment, addr: ConstVoidPtr, info: MutPtr<Dl_info>) -> i32 {
+    let addr = addr.to_bits() & !1;
+    let Some(bin_idx) = env.bins.iter().position(|bin| {
+        bin.sections
+            .iter()
+            .any(|section| (section.addr..section.addr + section.size).contains(&addr))
+    }) else {
+        return 0;
+    };
+    let bin = &env.bins[bin_idx];
+
+    let fbase = bin.header_addr.unwrap_or(0);
+    // Nearest preceding symbol, like in Environment::describe_code_addr().
+    let symbol = bin
+        .exported_symbols
+        .iter()
+        .filter(|&(_, &symbol_addr)| symbol_addr & !1 <= addr)
+        .max_by_key(|&(_, &symbol_addr)| symbol_addr & !1)
+        .map(|(name, &symbol_addr)| (name.clone(), symbol_addr));
+
+    let fname = bin_path(env, bin_idx);
+    let dli_fname = dladdr_string(env, fname);
+    let (dli_sname, dli_saddr) = if let Some((name, symbol_addr)) = symbol {
+        let name = name.strip_prefix('_').unwrap_or(&name).to_string();
+        (dladdr_string(env, name), Ptr::from_bits(symbol_addr))
+    } else {
+        (Ptr::null(), Ptr::null())
+    };
+    env.mem.write(
+        info,
+        Dl_info {
+            dli_fname,
+            dli_fbase: Ptr::from_bits(fbase),
+            dli_sname,
+            dli_saddr,
+        },
+    );
+    1
 }
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(dlopen(_, _)),
     export_c_func!(dlsym(_, _)),
     export_c_func!(dlclose(_)),
+    export_c_func!(dlerror()),
+    export_c_func!(dladdr(_, _)),
 ];
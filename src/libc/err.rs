@@ -0,0 +1,77 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `err.h`
+
+use crate::abi::{DotDotDot, VaList};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{describe_errno, get_errno};
+use crate::libc::stdio::printf::printf_inner;
+use crate::libc::syslog::process_name;
+use crate::mem::ConstPtr;
+use crate::Environment;
+
+/// Shared implementation of the `warn` family. Prints
+/// `"progname: message: strerror(errno)"`, omitting the parts that don't
+/// apply.
+fn warn_inner(env: &mut Environment, format: ConstPtr<u8>, arg: VaList, with_errno: bool) {
+    // Read errno first, the formatting could clobber it.
+    let errno = get_errno(env);
+    let mut message = format!("{}: ", process_name(env)).into_bytes();
+    if !format.is_null() {
+        let res = printf_inner::<false, _>(env, |mem, idx| mem.read(format + idx), arg);
+        message.extend_from_slice(&res);
+        if with_errno {
+            message.extend_from_slice(b": ");
+        }
+    }
+    if with_errno {
+        message.extend_from_slice(describe_errno(errno).as_bytes());
+    }
+    echo!("{}", String::from_utf8_lossy(&message));
+}
+
+fn vwarn(env: &mut Environment, format: ConstPtr<u8>, arg: VaList) {
+    warn_inner(env, format, arg, true)
+}
+fn vwarnx(env: &mut Environment, format: ConstPtr<u8>, arg: VaList) {
+    warn_inner(env, format, arg, false)
+}
+fn warn(env: &mut Environment, format: ConstPtr<u8>, args: DotDotDot) {
+    vwarn(env, format, args.start())
+}
+fn warnx(env: &mut Environment, format: ConstPtr<u8>, args: DotDotDot) {
+    vwarnx(env, format, args.start())
+}
+
+fn verr(env: &mut Environment, eval: i32, format: ConstPtr<u8>, arg: VaList) {
+    vwarn(env, format, arg);
+    echo!("App called err(), exiting.");
+    super::cxxabi::run_exit_handlers(env);
+    env.exit(eval);
+}
+fn verrx(env: &mut Environment, eval: i32, format: ConstPtr<u8>, arg: VaList) {
+    vwarnx(env, format, arg);
+    echo!("App called errx(), exiting.");
+    super::cxxabi::run_exit_handlers(env);
+    env.exit(eval);
+}
+fn err(env: &mut Environment, eval: i32, format: ConstPtr<u8>, args: DotDotDot) {
+    verr(env, eval, format, args.start())
+}
+fn errx(env: &mut Environment, eval: i32, format: ConstPtr<u8>, args: DotDotDot) {
+    verrx(env, eval, format, args.start())
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(warn(_, _)),
+    export_c_func!(warnx(_, _)),
+    export_c_func!(vwarn(_, _)),
+    export_c_func!(vwarnx(_, _)),
+    export_c_func!(err(_, _, _)),
+    export_c_func!(errx(_, _, _)),
+    export_c_func!(verr(_, _, _)),
+    export_c_func!(verrx(_, _, _)),
+];
@@ -4,57 +4,181 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `errno.h`
+//!
+//! Each thread has its own `errno`, allocated in guest memory the first time
+//! it's needed. Host code sets it with [set_errno].
 
 use crate::dyld::FunctionExports;
 use crate::export_c_func;
-use crate::mem::{ConstPtr, MutPtr};
-use crate::Environment;
+use crate::mem::{ConstPtr, Mem, MutPtr};
+use crate::{Environment, ThreadId};
+use std::collections::HashMap;
 use std::io::Write;
 
 pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
+pub const ESRCH: i32 = 3;
+pub const EINTR: i32 = 4;
+pub const EIO: i32 = 5;
+pub const EBADF: i32 = 9;
 pub const EDEADLK: i32 = 11;
+pub const ENOMEM: i32 = 12;
+pub const EACCES: i32 = 13;
+pub const EFAULT: i32 = 14;
 pub const EBUSY: i32 = 16;
+pub const EEXIST: i32 = 17;
+pub const ENOTDIR: i32 = 20;
+pub const EISDIR: i32 = 21;
 pub const EINVAL: i32 = 22;
+pub const EMFILE: i32 = 24;
+pub const ENOTTY: i32 = 25;
+pub const ENOSPC: i32 = 28;
+pub const ESPIPE: i32 = 29;
+pub const EROFS: i32 = 30;
+pub const ERANGE: i32 = 34;
+pub const EAGAIN: i32 = 35;
+pub const ENOTSUP: i32 = 45;
+pub const ETIMEDOUT: i32 = 60;
+pub const ENAMETOOLONG: i32 = 63;
+pub const ENOTEMPTY: i32 = 66;
+pub const ENOSYS: i32 = 78;
+pub const EILSEQ: i32 = 92;
 
 #[derive(Default)]
 pub struct State {
-    errnos: std::collections::HashMap<crate::ThreadId, MutPtr<i32>>,
+    errnos: HashMap<ThreadId, MutPtr<i32>>,
+    strerror_strings: HashMap<i32, ConstPtr<u8>>,
 }
 impl State {
-    fn errno_for_thread(
-        &mut self,
-        mem: &mut crate::mem::Mem,
-        thread: crate::ThreadId,
-    ) -> MutPtr<i32> {
-        *self.errnos.entry(thread).or_insert_with(|| {
-            log!(
-                "TODO: errno accessed on thread {} (will always be 0)",
-                thread
-            );
-            mem.alloc_and_write(0i32)
-        })
+    fn errno_for_thread(&mut self, mem: &mut Mem, thread: ThreadId) -> MutPtr<i32> {
+        *self
+            .errnos
+            .entry(thread)
+            .or_insert_with(|| mem.alloc_and_write(0i32))
     }
 }
 
-fn __error(env: &mut Environment) -> MutPtr<i32> {
+fn errno_ptr(env: &mut Environment) -> MutPtr<i32> {
     env.libc_state
         .errno
         .errno_for_thread(&mut env.mem, env.current_thread)
 }
 
+/// Set the current thread's `errno`. Functions should call this whenever they
+/// report failure with a return value that means "check `errno`".
+pub fn set_errno(env: &mut Environment, errno: i32) {
+    log_dbg!(
+        "Setting errno to {} on thread {}",
+        errno,
+        env.current_thread
+    );
+    let ptr = errno_ptr(env);
+    env.mem.write(ptr, errno);
+}
+
+/// Get the current thread's `errno`.
+pub fn get_errno(env: &mut Environment) -> i32 {
+    let ptr = errno_ptr(env);
+    env.mem.read(ptr)
+}
+
+/// Pick the closest `errno` value for a host I/O error.
+pub fn errno_for_io_error(err: &std::io::Error) -> i32 {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::PermissionDenied => EACCES,
+        ErrorKind::AlreadyExists => EEXIST,
+        ErrorKind::WouldBlock => EAGAIN,
+        ErrorKind::InvalidInput => EINVAL,
+        ErrorKind::Interrupted => EINTR,
+        ErrorKind::Unsupported => ENOTSUP,
+        ErrorKind::OutOfMemory => ENOMEM,
+        _ => EIO,
+    }
+}
+
+/// Cleanup for a thread that has exited.
+pub fn thread_exited(env: &mut Environment, thread: ThreadId) {
+    if let Some(ptr) = env.libc_state.errno.errnos.remove(&thread) {
+        env.mem.free(ptr.cast());
+    }
+}
+
+fn __error(env: &mut Environment) -> MutPtr<i32> {
+    errno_ptr(env)
+}
+
+/// Messages match Apple's.
+fn message_for_errno(errno: i32) -> Option<&'static str> {
+    Some(match errno {
+        0 => "Undefined error: 0",
+        EPERM => "Operation not permitted",
+        ENOENT => "No such file or directory",
+        ESRCH => "No such process",
+        EINTR => "Interrupted system call",
+        EIO => "Input/output error",
+        EBADF => "Bad file descriptor",
+        EDEADLK => "Resource deadlock avoided",
+        ENOMEM => "Cannot allocate memory",
+        EACCES => "Permission denied",
+        EFAULT => "Bad address",
+        EBUSY => "Resource busy",
+        EEXIST => "File exists",
+        ENOTDIR => "Not a directory",
+        EISDIR => "Is a directory",
+        EINVAL => "Invalid argument",
+        EMFILE => "Too many open files",
+        ENOTTY => "Inappropriate ioctl for device",
+        ENOSPC => "No space left on device",
+        ESPIPE => "Illegal seek",
+        EROFS => "Read-only file system",
+        ERANGE => "Result too large",
+        EAGAIN => "Resource temporarily unavailable",
+        ENOTSUP => "Operation not supported",
+        ETIMEDOUT => "Operation timed out",
+        ENAMETOOLONG => "File name too long",
+        ENOTEMPTY => "Directory not empty",
+        ENOSYS => "Function not implemented",
+        EILSEQ => "Illegal byte sequence",
+        _ => return None,
+    })
+}
+
+/// Get the message `strerror()` would return.
+pub fn describe_errno(errno: i32) -> String {
+    message_for_errno(errno)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Unknown error: {}", errno))
+}
+
+fn strerror(env: &mut Environment, errnum: i32) -> ConstPtr<u8> {
+    if let Some(&str) = env.libc_state.errno.strerror_strings.get(&errnum) {
+        return str;
+    }
+    let msg = describe_errno(errnum);
+    let str = env.mem.alloc_and_write_cstr(msg.as_bytes()).cast_const();
+    env.libc_state.errno.strerror_strings.insert(errnum, str);
+    str
+}
+
 fn perror(env: &mut Environment, s: ConstPtr<u8>) {
-    // TODO: errno mapping
-    let errno_msg = "<TODO: errno>\n";
+    let errno = get_errno(env);
+    let errno_msg = describe_errno(errno);
     let msg = if !s.is_null() {
         if let Ok(str) = env.mem.cstr_at_utf8(s) {
-            format!("{}: {}", str, errno_msg)
+            format!("{}: {}\n", str, errno_msg)
         } else {
-            errno_msg.to_string()
+            format!("{}\n", errno_msg)
         }
     } else {
-        errno_msg.to_string()
+        format!("{}\n", errno_msg)
     };
     let _ = std::io::stderr().write_all(msg.as_bytes());
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(__error()), export_c_func!(perror(_))];
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(__error()),
+    export_c_func!(strerror(_)),
+    export_c_func!(perror(_)),
+];
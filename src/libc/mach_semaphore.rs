@@ -0,0 +1,150 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `mach/semaphore.h` (and `semaphore_create`/`semaphore_destroy` from
+//! `mach/task.h`).
+//!
+//! Mach semaphores are implemented on top of the POSIX ones (see
+//! [super::semaphore]). A Mach semaphore's port name is the address of the
+//! underlying `sem_t`.
+
+#![allow(non_camel_case_types)]
+
+use super::semaphore::{open_unnamed, sem_close, sem_t};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{MutPtr, Ptr};
+use crate::Environment;
+use std::time::{Duration, Instant};
+
+type kern_return_t = i32;
+const KERN_SUCCESS: kern_return_t = 0;
+const KERN_INVALID_ARGUMENT: kern_return_t = 4;
+const KERN_OPERATION_TIMED_OUT: kern_return_t = 49;
+
+type mach_port_t = u32;
+type task_t = mach_port_t;
+type semaphore_t = mach_port_t;
+
+/// `SYNC_POLICY_FIFO` etc. Only one thread is woken at a time anyway, so it
+/// doesn't matter.
+type sync_policy_t = i32;
+
+fn sem_for_semaphore(env: &mut Environment, semaphore: semaphore_t) -> Option<MutPtr<sem_t>> {
+    let sem = Ptr::from_bits(semaphore);
+    env.libc_state
+        .semaphore
+        .open_semaphores
+        .contains_key(&sem)
+        .then_some(sem)
+}
+
+fn semaphore_create(
+    env: &mut Environment,
+    task: task_t,
+    semaphore: MutPtr<semaphore_t>,
+    policy: sync_policy_t,
+    value: i32,
+) -> kern_return_t {
+    if value < 0 {
+        return KERN_INVALID_ARGUMENT;
+    }
+    let sem = open_unnamed(env, value);
+    env.mem.write(semaphore, sem.to_bits());
+    log_dbg!(
+        "semaphore_create({:#x}, {:?}, {}, {}) => KERN_SUCCESS ({:#x})",
+        task,
+        semaphore,
+        policy,
+        value,
+        sem.to_bits()
+    );
+    KERN_SUCCESS
+}
+
+fn semaphore_destroy(env: &mut Environment, task: task_t, semaphore: semaphore_t) -> kern_return_t {
+    let Some(sem) = sem_for_semaphore(env, semaphore) else {
+        return KERN_INVALID_ARGUMENT;
+    };
+    log_dbg!("semaphore_destroy({:#x}, {:#x})", task, semaphore);
+    sem_close(env, sem);
+    KERN_SUCCESS
+}
+
+fn semaphore_signal(env: &mut Environment, semaphore: semaphore_t) -> kern_return_t {
+    let Some(sem) = sem_for_semaphore(env, semaphore) else {
+        return KERN_INVALID_ARGUMENT;
+    };
+    env.sem_increment(sem);
+    KERN_SUCCESS
+}
+
+fn semaphore_signal_all(env: &mut Environment, semaphore: semaphore_t) -> kern_return_t {
+    let Some(sem) = sem_for_semaphore(env, semaphore) else {
+        return KERN_INVALID_ARGUMENT;
+    };
+    // Wake every waiting thread, but don't let a later wait succeed.
+    while env.libc_state.semaphore.open_semaphores[&sem]
+        .borrow()
+        .value
+        < 0
+    {
+        env.sem_increment(sem);
+    }
+    KERN_SUCCESS
+}
+
+fn semaphore_wait(env: &mut Environment, semaphore: semaphore_t) -> kern_return_t {
+    let Some(sem) = sem_for_semaphore(env, semaphore) else {
+        return KERN_INVALID_ARGUMENT;
+    };
+    env.sem_decrement(sem, /* wait_on_lock: */ true);
+    KERN_SUCCESS
+}
+
+/// The timeout is a `mach_timespec_t` passed by value, which is the same as
+/// passing its two fields separately.
+fn semaphore_timedwait(
+    env: &mut Environment,
+    semaphore: semaphore_t,
+    wait_time_sec: u32,
+    wait_time_nsec: i32,
+) -> kern_return_t {
+    let Some(sem) = sem_for_semaphore(env, semaphore) else {
+        return KERN_INVALID_ARGUMENT;
+    };
+    let timeout = Duration::from_secs(wait_time_sec.into())
+        + Duration::from_nanos(wait_time_nsec.max(0) as u64);
+    let deadline = Instant::now() + timeout;
+    // The thread scheduler can't wake a thread blocked on a semaphore when a
+    // timeout is reached, so this polls instead, letting other threads run
+    // in between.
+    loop {
+        if env.sem_decrement(sem, /* wait_on_lock: */ false) {
+            return KERN_SUCCESS;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            log_dbg!(
+                "semaphore_timedwait({:#x}, {:?}) => KERN_OPERATION_TIMED_OUT",
+                semaphore,
+                timeout
+            );
+            return KERN_OPERATION_TIMED_OUT;
+        }
+        env.sleep(
+            remaining.min(Duration::from_millis(1)),
+            /* tail_call: */ false,
+        );
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(semaphore_create(_, _, _, _)),
+    export_c_func!(semaphore_destroy(_, _)),
+    export_c_func!(semaphore_signal(_)),
+    export_c_func!(semaphore_signal_all(_)),
+    export_c_func!(semaphore_wait(_)),
+    export_c_func!(semaphore_timedwait(_, _, _)),
+];
@@ -0,0 +1,390 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Mach task and host functions (`mach/mach_init.h`, `mach/task.h`,
+//! `mach/mach_host.h`), as used by middleware to get memory and CPU statistics.
+//!
+//! There's only one task (the app) and one host, so their ports are just
+//! arbitrary constants. Thread ports are thread IDs, like in
+//! [super::mach_thread_info]. The memory statistics are based on the same
+//! numbers as memory warnings (see `--device-ram=`).
+
+#![allow(non_camel_case_types)]
+
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::mem::{guest_size_of, GuestUSize, Mem, MutPtr, SafeRead};
+use crate::Environment;
+use std::time::Instant;
+
+type kern_return_t = i32;
+const KERN_SUCCESS: kern_return_t = 0;
+const KERN_INVALID_ARGUMENT: kern_return_t = 4;
+
+type mach_port_t = u32;
+type task_t = mach_port_t;
+type host_t = mach_port_t;
+type thread_t = mach_port_t;
+type mach_port_name_t = mach_port_t;
+
+type natural_t = u32;
+type integer_t = i32;
+type vm_size_t = GuestUSize;
+type mach_msg_type_number_t = natural_t;
+type policy_t = i32;
+
+/// Arbitrarily-chosen port name for the app's task (not Apple's).
+const TASK_SELF: task_t = 0x103;
+/// Arbitrarily-chosen port name for the host (not Apple's).
+const HOST_SELF: host_t = 0x107;
+
+const PAGE_SIZE: vm_size_t = 0x1000;
+
+fn mach_task_self(_env: &mut Environment) -> task_t {
+    TASK_SELF
+}
+
+fn mach_host_self(_env: &mut Environment) -> host_t {
+    HOST_SELF
+}
+
+fn mach_thread_self(env: &mut Environment) -> thread_t {
+    env.current_thread.try_into().unwrap()
+}
+
+fn mach_port_deallocate(
+    _env: &mut Environment,
+    _task: task_t,
+    _name: mach_port_name_t,
+) -> kern_return_t {
+    // Nothing to do, since none of the ports are really allocated.
+    KERN_SUCCESS
+}
+
+/// Check and update the output size of a `*_info` or `*_statistics` function.
+fn check_info_count<T>(
+    env: &mut Environment,
+    info_out_count: MutPtr<mach_msg_type_number_t>,
+) -> bool {
+    let count = guest_size_of::<T>() / guest_size_of::<integer_t>();
+    if env.mem.read(info_out_count) < count {
+        return false;
+    }
+    env.mem.write(info_out_count, count);
+    true
+}
+
+type task_flavor_t = natural_t;
+const TASK_THREAD_TIMES_INFO: task_flavor_t = 3;
+const TASK_BASIC_INFO: task_flavor_t = 5;
+
+#[repr(C, packed)]
+struct time_value_t {
+    seconds: integer_t,
+    microseconds: integer_t,
+}
+unsafe impl SafeRead for time_value_t {}
+
+#[repr(C, packed)]
+struct task_basic_info {
+    suspend_count: integer_t,
+    virtual_size: vm_size_t,
+    resident_size: vm_size_t,
+    user_time: time_value_t,
+    system_time: time_value_t,
+    policy: policy_t,
+}
+unsafe impl SafeRead for task_basic_info {}
+
+#[repr(C, packed)]
+struct task_thread_times_info {
+    user_time: time_value_t,
+    system_time: time_value_t,
+}
+unsafe impl SafeRead for task_thread_times_info {}
+
+const POLICY_TIMESHARE: policy_t = 1;
+
+/// There's no way to know how much CPU time the app really used, so pretend it
+/// has been running the whole time since launch. Apps use this to measure
+/// their own load, so the absolute value matters less than it increasing.
+fn app_cpu_time(env: &Environment) -> time_value_t {
+    let elapsed = Instant::now().duration_since(env.startup_time);
+    time_value_t {
+        seconds: elapsed.as_secs().try_into().unwrap(),
+        microseconds: elapsed.subsec_micros().try_into().unwrap(),
+    }
+}
+
+fn task_info(
+    env: &mut Environment,
+    task: task_t,
+    flavor: task_flavor_t,
+    task_info_out: MutPtr<integer_t>,
+    task_info_out_count: MutPtr<mach_msg_type_number_t>,
+) -> kern_return_t {
+    if task != TASK_SELF {
+        return KERN_INVALID_ARGUMENT;
+    }
+    match flavor {
+        TASK_BASIC_INFO => {
+            if !check_info_count::<task_basic_info>(env, task_info_out_count) {
+                return KERN_INVALID_ARGUMENT;
+            }
+            let allocated = env.mem.allocated_bytes();
+            env.mem.write(
+                task_info_out.cast(),
+                task_basic_info {
+                    suspend_count: 0,
+                    // The app's binaries etc aren't counted, but the
+                    // allocations are what apps usually care about.
+                    virtual_size: allocated,
+                    resident_size: allocated,
+                    user_time: time_value_t {
+                        seconds: 0,
+                        microseconds: 0,
+                    },
+                    system_time: time_value_t {
+                        seconds: 0,
+                        microseconds: 0,
+                    },
+                    policy: POLICY_TIMESHARE,
+                },
+            );
+            KERN_SUCCESS
+        }
+        TASK_THREAD_TIMES_INFO => {
+            if !check_info_count::<task_thread_times_info>(env, task_info_out_count) {
+                return KERN_INVALID_ARGUMENT;
+            }
+            let user_time = app_cpu_time(env);
+            env.mem.write(
+                task_info_out.cast(),
+                task_thread_times_info {
+                    user_time,
+                    system_time: time_value_t {
+                        seconds: 0,
+                        microseconds: 0,
+                    },
+                },
+            );
+            KERN_SUCCESS
+        }
+        _ => {
+            log!("TODO: task_info() flavor {}", flavor);
+            KERN_INVALID_ARGUMENT
+        }
+    }
+}
+
+fn host_page_size(
+    env: &mut Environment,
+    host: host_t,
+    out_page_size: MutPtr<vm_size_t>,
+) -> kern_return_t {
+    if host != HOST_SELF {
+        return KERN_INVALID_ARGUMENT;
+    }
+    env.mem.write(out_page_size, PAGE_SIZE);
+    KERN_SUCCESS
+}
+
+type host_flavor_t = integer_t;
+const HOST_BASIC_INFO: host_flavor_t = 1;
+const HOST_VM_INFO: host_flavor_t = 2;
+const HOST_CPU_LOAD_INFO: host_flavor_t = 3;
+
+type cpu_type_t = integer_t;
+type cpu_subtype_t = integer_t;
+type cpu_threadtype_t = integer_t;
+const CPU_TYPE_ARM: cpu_type_t = 12;
+/// The original iPhone and iPhone 3G have an ARM11, which is ARMv6.
+const CPU_SUBTYPE_ARM_V6: cpu_subtype_t = 6;
+const CPU_THREADTYPE_NONE: cpu_threadtype_t = 0;
+
+#[repr(C, packed)]
+struct host_basic_info {
+    max_cpus: integer_t,
+    avail_cpus: integer_t,
+    memory_size: natural_t,
+    cpu_type: cpu_type_t,
+    cpu_subtype: cpu_subtype_t,
+    cpu_threadtype: cpu_threadtype_t,
+    physical_cpu: integer_t,
+    physical_cpu_max: integer_t,
+    logical_cpu: integer_t,
+    logical_cpu_max: integer_t,
+    max_mem: u64,
+}
+unsafe impl SafeRead for host_basic_info {}
+
+fn host_info(
+    env: &mut Environment,
+    host: host_t,
+    flavor: host_flavor_t,
+    host_info_out: MutPtr<integer_t>,
+    host_info_out_count: MutPtr<mach_msg_type_number_t>,
+) -> kern_return_t {
+    if host != HOST_SELF {
+        return KERN_INVALID_ARGUMENT;
+    }
+    match flavor {
+        HOST_BASIC_INFO => {
+            if !check_info_count::<host_basic_info>(env, host_info_out_count) {
+                return KERN_INVALID_ARGUMENT;
+            }
+            let ram_bytes = u64::from(env.options.device_ram_mib().get()) * 1024 * 1024;
+            env.mem.write(
+                host_info_out.cast(),
+                host_basic_info {
+                    max_cpus: 1,
+                    avail_cpus: 1,
+                    // This is a 32-bit int, so it would be wrong for 4GiB and
+                    // above, but max_mem has the full value.
+                    memory_size: ram_bytes.min(u32::MAX.into()) as natural_t,
+                    cpu_type: CPU_TYPE_ARM,
+                    cpu_subtype: CPU_SUBTYPE_ARM_V6,
+                    cpu_threadtype: CPU_THREADTYPE_NONE,
+                    physical_cpu: 1,
+                    physical_cpu_max: 1,
+                    logical_cpu: 1,
+                    logical_cpu_max: 1,
+                    max_mem: ram_bytes,
+                },
+            );
+            KERN_SUCCESS
+        }
+        _ => {
+            log!("TODO: host_info() flavor {}", flavor);
+            KERN_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct vm_statistics {
+    free_count: natural_t,
+    active_count: natural_t,
+    inactive_count: natural_t,
+    wire_count: natural_t,
+    zero_fill_count: natural_t,
+    reactivations: natural_t,
+    pageins: natural_t,
+    pageouts: natural_t,
+    faults: natural_t,
+    cow_faults: natural_t,
+    lookups: natural_t,
+    hits: natural_t,
+    purgeable_count: natural_t,
+    purges: natural_t,
+    speculative_count: natural_t,
+}
+unsafe impl SafeRead for vm_statistics {}
+
+/// `host_cpu_load_info`, which is really an array indexed by `CPU_STATE_USER`
+/// etc.
+#[repr(C, packed)]
+struct host_cpu_load_info {
+    user_ticks: natural_t,
+    system_ticks: natural_t,
+    idle_ticks: natural_t,
+    nice_ticks: natural_t,
+}
+unsafe impl SafeRead for host_cpu_load_info {}
+
+/// Ticks per second for [host_cpu_load_info] (`CLK_TCK`).
+const CPU_TICKS_PER_SECOND: u128 = 100;
+
+fn host_statistics(
+    env: &mut Environment,
+    host: host_t,
+    flavor: host_flavor_t,
+    host_info_out: MutPtr<integer_t>,
+    host_info_out_count: MutPtr<mach_msg_type_number_t>,
+) -> kern_return_t {
+    if host != HOST_SELF {
+        return KERN_INVALID_ARGUMENT;
+    }
+    match flavor {
+        HOST_VM_INFO => {
+            if !check_info_count::<vm_statistics>(env, host_info_out_count) {
+                return KERN_INVALID_ARGUMENT;
+            }
+            // Like for memory warnings, half of the device's RAM is assumed to
+            // be used by the system, and the rest is available to the app.
+            // Apps usually compute the free memory from free_count, so this
+            // drops to zero around the time a memory warning would be sent.
+            let total_pages = env.options.device_ram_mib().get() * (1024 * 1024 / PAGE_SIZE);
+            let wire_count = total_pages / 2;
+            let active_count = env.mem.allocated_bytes().div_ceil(PAGE_SIZE);
+            let free_count = total_pages.saturating_sub(wire_count + active_count);
+            env.mem.write(
+                host_info_out.cast(),
+                vm_statistics {
+                    free_count,
+                    active_count,
+                    inactive_count: 0,
+                    wire_count,
+                    zero_fill_count: 0,
+                    reactivations: 0,
+                    pageins: 0,
+                    pageouts: 0,
+                    faults: 0,
+                    cow_faults: 0,
+                    lookups: 0,
+                    hits: 0,
+                    purgeable_count: 0,
+                    purges: 0,
+                    speculative_count: 0,
+                },
+            );
+            KERN_SUCCESS
+        }
+        HOST_CPU_LOAD_INFO => {
+            if !check_info_count::<host_cpu_load_info>(env, host_info_out_count) {
+                return KERN_INVALID_ARGUMENT;
+            }
+            // Like for task_info(), the app is assumed to be using the CPU
+            // all the time.
+            let elapsed = Instant::now().duration_since(env.startup_time);
+            let ticks = elapsed.as_millis() * CPU_TICKS_PER_SECOND / 1000;
+            env.mem.write(
+                host_info_out.cast(),
+                host_cpu_load_info {
+                    user_ticks: ticks as natural_t,
+                    system_ticks: 0,
+                    idle_ticks: 0,
+                    nice_ticks: 0,
+                },
+            );
+            KERN_SUCCESS
+        }
+        _ => {
+            log!("TODO: host_statistics() flavor {}", flavor);
+            KERN_INVALID_ARGUMENT
+        }
+    }
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_mach_task_self_",
+        HostConstant::Custom(|mem: &mut Mem| mem.alloc_and_write(TASK_SELF).cast().cast_const()),
+    ),
+    (
+        "_vm_page_size",
+        HostConstant::Custom(|mem: &mut Mem| mem.alloc_and_write(PAGE_SIZE).cast().cast_const()),
+    ),
+];
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(mach_task_self()),
+    export_c_func!(mach_host_self()),
+    export_c_func!(mach_thread_self()),
+    export_c_func!(mach_port_deallocate(_, _)),
+    export_c_func!(task_info(_, _, _, _)),
+    export_c_func!(host_page_size(_, _)),
+    export_c_func!(host_info(_, _, _, _)),
+    export_c_func!(host_statistics(_, _, _, _)),
+];
@@ -8,7 +8,7 @@
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{MutPtr, SafeRead};
 use crate::Environment;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[repr(C, packed)]
 struct struct_mach_timebase_info {
@@ -38,7 +38,7 @@ fn mach_timebase_info(
 /// The result of this function, multiplied by the constant from
 /// [mach_timebase_info], should be the absolute time in nanoseconds.
 /// The absolute time is a monotonic clock with an arbitrary starting point.
-fn mach_absolute_time(env: &mut Environment) -> u64 {
+pub(super) fn mach_absolute_time(env: &mut Environment) -> u64 {
     let now = Instant::now();
     now.duration_since(env.startup_time)
         .as_nanos()
@@ -46,7 +46,18 @@ fn mach_absolute_time(env: &mut Environment) -> u64 {
         .unwrap()
 }
 
+/// Sleep until [mach_absolute_time] reaches `deadline`. Some engines use this
+/// to pace their frames.
+fn mach_wait_until(env: &mut Environment, deadline: u64) -> kern_return_t {
+    let now = mach_absolute_time(env);
+    if deadline > now {
+        env.sleep(Duration::from_nanos(deadline - now), true);
+    }
+    KERN_SUCCESS
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(mach_timebase_info(_)),
     export_c_func!(mach_absolute_time()),
+    export_c_func!(mach_wait_until(_)),
 ];
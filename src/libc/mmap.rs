@@ -3,38 +3,176 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! `sys/mman.h`
+//!
+//! There is no memory protection or virtual memory in touchHLE's guest memory,
+//! so mappings are simply allocations, and file-backed mappings are populated
+//! by reading the file into them.
 
 use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
+use crate::libc::errno::{set_errno, EINVAL, ENOTSUP};
 use crate::libc::posix_io;
-use crate::libc::posix_io::{off_t, FileDescriptor, SEEK_SET};
-use crate::mem::{GuestUSize, MutVoidPtr};
+use crate::libc::posix_io::{off_t, FileDescriptor, SEEK_CUR, SEEK_SET};
+use crate::mem::{GuestUSize, MutVoidPtr, Ptr};
+use std::collections::HashMap;
 
+const PAGE_SIZE: GuestUSize = 0x1000;
+
+#[allow(dead_code)]
+const PROT_NONE: i32 = 0x00;
+#[allow(dead_code)]
+const PROT_READ: i32 = 0x01;
+const PROT_WRITE: i32 = 0x02;
+#[allow(dead_code)]
+const PROT_EXEC: i32 = 0x04;
+
+const MAP_SHARED: i32 = 0x0001;
+#[allow(dead_code)]
+const MAP_PRIVATE: i32 = 0x0002;
+const MAP_FIXED: i32 = 0x0010;
 #[allow(dead_code)]
 const MAP_FILE: i32 = 0x0000;
 const MAP_ANON: i32 = 0x1000;
 
-/// Our implementation of mmap is really simple: it's just load entirety of
-/// file in memory!
+const MAP_FAILED: MutVoidPtr = Ptr::from_bits(u32::MAX);
+
+#[derive(Default)]
+pub struct State {
+    /// Mappings by their page-aligned start address.
+    mappings: HashMap<MutVoidPtr, Mapping>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.mmap
+    }
+}
+
+struct Mapping {
+    /// The underlying allocation, which is larger than the mapping so that the
+    /// mapping can be page-aligned.
+    allocation: MutVoidPtr,
+    len: GuestUSize,
+}
+
 fn mmap(
     env: &mut Environment,
     addr: MutVoidPtr,
     len: GuestUSize,
-    _prot: i32,
+    prot: i32,
     flags: i32,
     fd: FileDescriptor,
     offset: off_t,
 ) -> MutVoidPtr {
-    assert!(addr.is_null());
-    assert_eq!(offset, 0);
-    assert_eq!((flags & MAP_ANON), 0);
-    let new_offset = posix_io::lseek(env, fd, offset, SEEK_SET);
-    assert_eq!(new_offset, offset);
-    let ptr = env.mem.alloc(len);
-    let read = posix_io::read(env, fd, ptr, len);
-    assert_eq!(read as u32, len);
+    if len == 0 || offset % off_t::from(PAGE_SIZE) != 0 {
+        log!(
+            "Warning: mmap() with invalid length {:#x} or offset {:#x}",
+            len,
+            offset
+        );
+        set_errno(env, EINVAL);
+        return MAP_FAILED;
+    }
+    if flags & MAP_FIXED != 0 {
+        // There's no virtual memory to remap.
+        log!("TODO: mmap() with MAP_FIXED ({:?})", addr);
+        set_errno(env, EINVAL);
+        return MAP_FAILED;
+    }
+    // Non-fixed addresses are only hints and can be ignored.
+
+    let is_anonymous = flags & MAP_ANON != 0;
+    if !is_anonymous && flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 {
+        // Changes wouldn't be written back to the file.
+        log!("TODO: writeable shared mmap() of fd {}", fd);
+        set_errno(env, ENOTSUP);
+        return MAP_FAILED;
+    }
+
+    // mmap() doesn't affect the file position. Getting it also checks the file
+    // descriptor is valid: if it isn't, lseek() sets errno to EBADF.
+    let old_offset = if is_anonymous {
+        0
+    } else {
+        let old_offset = posix_io::lseek(env, fd, 0, SEEK_CUR);
+        if old_offset < 0 {
+            log!("Warning: mmap() of invalid fd {}", fd);
+            return MAP_FAILED;
+        }
+        old_offset
+    };
+
+    let aligned_len = len.checked_add(PAGE_SIZE - 1).unwrap() & !(PAGE_SIZE - 1);
+    let allocation = env.mem.alloc(aligned_len + PAGE_SIZE - 1);
+    let ptr = Ptr::from_bits((allocation.to_bits() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
+
+    if !is_anonymous {
+        let new_offset = posix_io::lseek(env, fd, offset, SEEK_SET);
+        assert_eq!(new_offset, offset);
+        // The file may be shorter than the mapping. The rest of the mapping is
+        // zero-filled, which new allocations already are.
+        let read = posix_io::read(env, fd, ptr, len);
+        assert!(read >= 0);
+        posix_io::lseek(env, fd, old_offset, SEEK_SET);
+    }
+
+    log_dbg!(
+        "mmap({:?}, {:#x}, {:#x}, {:#x}, {}, {:#x}) => {:?}",
+        addr,
+        len,
+        prot,
+        flags,
+        fd,
+        offset,
+        ptr
+    );
+    State::get_mut(env).mappings.insert(
+        ptr,
+        Mapping {
+            allocation,
+            len: aligned_len,
+        },
+    );
     ptr
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(mmap(_, _, _, _, _, _))];
+fn munmap(env: &mut Environment, addr: MutVoidPtr, len: GuestUSize) -> i32 {
+    let Some(mapping) = State::get_mut(env).mappings.get(&addr) else {
+        log!("Warning: munmap() of unknown mapping {:?}, ignoring", addr);
+        return 0;
+    };
+    if len < mapping.len {
+        // The allocator can't free part of an allocation.
+        log!(
+            "TODO: munmap() of part of a mapping ({:?}, {:#x}), ignoring",
+            addr,
+            len
+        );
+        return 0;
+    }
+    let mapping = State::get_mut(env).mappings.remove(&addr).unwrap();
+    env.mem.free(mapping.allocation);
+    log_dbg!("munmap({:?}, {:#x}) => 0", addr, len);
+    0 // success
+}
+
+fn mprotect(_env: &mut Environment, addr: MutVoidPtr, len: GuestUSize, prot: i32) -> i32 {
+    // Guest memory has no protection, so this is always successful.
+    log_dbg!("mprotect({:?}, {:#x}, {:#x}) => 0", addr, len, prot);
+    0 // success
+}
+
+fn msync(_env: &mut Environment, addr: MutVoidPtr, len: GuestUSize, flags: i32) -> i32 {
+    // Writeable shared mappings aren't supported, so there's never anything to
+    // write back.
+    log_dbg!("msync({:?}, {:#x}, {:#x}) => 0", addr, len, flags);
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(mmap(_, _, _, _, _, _)),
+    export_c_func!(munmap(_, _)),
+    export_c_func!(mprotect(_, _, _)),
+    export_c_func!(msync(_, _, _)),
+];
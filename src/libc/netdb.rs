@@ -0,0 +1,390 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `netdb.h` (host name resolution)
+//!
+//! Lookups are done with the host OS's resolver. Host names can be redirected
+//! with the `--redirect-host=` option, which is useful for apps whose servers
+//! no longer exist but have a community-run replacement.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{
+    guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr, SafeRead,
+};
+use crate::Environment;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+const AF_UNSPEC: i32 = 0;
+const AF_INET: i32 = 2;
+const AF_INET6: i32 = 30;
+
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+
+const IPPROTO_TCP: i32 = 6;
+const IPPROTO_UDP: i32 = 17;
+
+const AI_PASSIVE: i32 = 0x1;
+const AI_CANONNAME: i32 = 0x2;
+const AI_NUMERICHOST: i32 = 0x4;
+
+const EAI_FAMILY: i32 = 5;
+const EAI_NONAME: i32 = 8;
+const EAI_SERVICE: i32 = 9;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct hostent {
+    h_name: MutPtr<u8>,
+    h_aliases: MutPtr<MutPtr<u8>>,
+    h_addrtype: i32,
+    h_length: i32,
+    h_addr_list: MutPtr<MutPtr<u8>>,
+}
+unsafe impl SafeRead for hostent {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct addrinfo {
+    ai_flags: i32,
+    ai_family: i32,
+    ai_socktype: i32,
+    ai_protocol: i32,
+    ai_addrlen: GuestUSize,
+    ai_canonname: MutPtr<u8>,
+    ai_addr: MutVoidPtr,
+    ai_next: MutPtr<addrinfo>,
+}
+unsafe impl SafeRead for addrinfo {}
+
+/// The port and address are in network byte order (big-endian).
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct sockaddr_in {
+    sin_len: u8,
+    sin_family: u8,
+    sin_port: [u8; 2],
+    sin_addr: [u8; 4],
+    sin_zero: [u8; 8],
+}
+unsafe impl SafeRead for sockaddr_in {}
+
+/// The port and address are in network byte order (big-endian).
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct sockaddr_in6 {
+    sin6_len: u8,
+    sin6_family: u8,
+    sin6_port: [u8; 2],
+    sin6_flowinfo: u32,
+    sin6_addr: [u8; 16],
+    sin6_scope_id: u32,
+}
+unsafe impl SafeRead for sockaddr_in6 {}
+
+#[derive(Default)]
+pub struct State {
+    /// Allocations backing the `hostent` last returned by `gethostbyname()` or
+    /// `gethostbyaddr()`, which is overwritten by each call, like on a real
+    /// system.
+    hostent_allocations: Vec<MutVoidPtr>,
+    gai_strerror_strings: HashMap<i32, ConstPtr<u8>>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.netdb
+    }
+}
+
+/// Look up the addresses for a host name, after applying any redirect from the
+/// options. Returns [None] if the lookup fails.
+fn resolve(env: &Environment, name: &str, numeric_only: bool) -> Option<Vec<IpAddr>> {
+    let name = match env.options.host_redirects.get(&name.to_ascii_lowercase()) {
+        Some(new_name) => {
+            log!("Redirecting host {:?} to {:?}", name, new_name);
+            new_name.as_str()
+        }
+        None => name,
+    };
+    if let Ok(addr) = name.parse::<IpAddr>() {
+        return Some(vec![addr]);
+    }
+    if numeric_only {
+        return None;
+    }
+    match (name, 0).to_socket_addrs() {
+        Ok(socket_addrs) => {
+            let mut addrs = Vec::new();
+            for socket_addr in socket_addrs {
+                if !addrs.contains(&socket_addr.ip()) {
+                    addrs.push(socket_addr.ip());
+                }
+            }
+            Some(addrs)
+        }
+        Err(e) => {
+            log!("Could not resolve host {:?}: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Replace the static `hostent` with a new one.
+fn set_hostent(env: &mut Environment, name: &str, addrs: &[Ipv4Addr]) -> MutPtr<hostent> {
+    for old in std::mem::take(&mut State::get_mut(env).hostent_allocations) {
+        env.mem.free(old);
+    }
+
+    let h_name = env.mem.alloc_and_write_cstr(name.as_bytes());
+    // No aliases, just the terminating NULL.
+    let h_aliases = env.mem.alloc_and_write(Ptr::null());
+    let h_addr_list: MutPtr<MutPtr<u8>> = env
+        .mem
+        .alloc((addrs.len() as GuestUSize + 1) * guest_size_of::<MutPtr<u8>>())
+        .cast();
+    let mut allocations = vec![h_name.cast(), h_aliases.cast(), h_addr_list.cast()];
+    for (i, addr) in addrs.iter().enumerate() {
+        let addr_ptr: MutPtr<u8> = env.mem.alloc(4).cast();
+        env.mem
+            .bytes_at_mut(addr_ptr, 4)
+            .copy_from_slice(&addr.octets());
+        env.mem.write(h_addr_list + i as GuestUSize, addr_ptr);
+        allocations.push(addr_ptr.cast());
+    }
+    env.mem
+        .write(h_addr_list + addrs.len() as GuestUSize, Ptr::null());
+
+    let hostent = env.mem.alloc_and_write(hostent {
+        h_name,
+        h_aliases,
+        h_addrtype: AF_INET,
+        h_length: 4,
+        h_addr_list,
+    });
+    allocations.push(hostent.cast());
+    State::get_mut(env).hostent_allocations = allocations;
+    hostent
+}
+
+fn gethostbyname(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<hostent> {
+    let name_str = env.mem.cstr_at_utf8(name).unwrap().to_string();
+    let addrs: Vec<Ipv4Addr> = resolve(env, &name_str, false)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|addr| match addr {
+            IpAddr::V4(addr) => Some(addr),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    log_dbg!("gethostbyname({:?}) => {:?}", name_str, addrs);
+    if addrs.is_empty() {
+        // TODO: set h_errno to HOST_NOT_FOUND
+        return Ptr::null();
+    }
+    set_hostent(env, &name_str, &addrs)
+}
+
+fn gethostbyaddr(
+    env: &mut Environment,
+    addr: ConstVoidPtr,
+    len: GuestUSize,
+    type_: i32,
+) -> MutPtr<hostent> {
+    if type_ != AF_INET || len != 4 {
+        log!("TODO: gethostbyaddr() with type {}, length {}", type_, len);
+        return Ptr::null();
+    }
+    let octets: [u8; 4] = env.mem.bytes_at(addr.cast(), 4).try_into().unwrap();
+    let addr = Ipv4Addr::from(octets);
+    // The Rust standard library has no reverse lookup, so only the loopback
+    // address, whose name is always known, can be looked up.
+    if !addr.is_loopback() {
+        log!("TODO: gethostbyaddr({}) (reverse DNS lookup)", addr);
+        // TODO: set h_errno to HOST_NOT_FOUND
+        return Ptr::null();
+    }
+    log_dbg!("gethostbyaddr({}) => \"localhost\"", addr);
+    set_hostent(env, "localhost", &[addr])
+}
+
+/// Write a socket address to a new allocation, returning it and its size.
+fn alloc_sockaddr(env: &mut Environment, addr: IpAddr, port: u16) -> (MutVoidPtr, GuestUSize) {
+    match addr {
+        IpAddr::V4(addr) => {
+            let size = guest_size_of::<sockaddr_in>();
+            let ptr = env.mem.alloc_and_write(sockaddr_in {
+                sin_len: size as u8,
+                sin_family: AF_INET as u8,
+                sin_port: port.to_be_bytes(),
+                sin_addr: addr.octets(),
+                sin_zero: [0; 8],
+            });
+            (ptr.cast(), size)
+        }
+        IpAddr::V6(addr) => {
+            let size = guest_size_of::<sockaddr_in6>();
+            let ptr = env.mem.alloc_and_write(sockaddr_in6 {
+                sin6_len: size as u8,
+                sin6_family: AF_INET6 as u8,
+                sin6_port: port.to_be_bytes(),
+                sin6_flowinfo: 0,
+                sin6_addr: addr.octets(),
+                sin6_scope_id: 0,
+            });
+            (ptr.cast(), size)
+        }
+    }
+}
+
+fn getaddrinfo(
+    env: &mut Environment,
+    node: ConstPtr<u8>,
+    service: ConstPtr<u8>,
+    hints: ConstPtr<addrinfo>,
+    res: MutPtr<MutPtr<addrinfo>>,
+) -> i32 {
+    let (flags, family, socktype, protocol) = if hints.is_null() {
+        (0, AF_UNSPEC, 0, 0)
+    } else {
+        let hints = env.mem.read(hints);
+        (
+            hints.ai_flags,
+            hints.ai_family,
+            hints.ai_socktype,
+            hints.ai_protocol,
+        )
+    };
+    if ![AF_UNSPEC, AF_INET, AF_INET6].contains(&family) {
+        return EAI_FAMILY;
+    }
+
+    let node = (!node.is_null()).then(|| env.mem.cstr_at_utf8(node).unwrap().to_string());
+    let service = (!service.is_null()).then(|| env.mem.cstr_at_utf8(service).unwrap().to_string());
+    log_dbg!(
+        "getaddrinfo({:?}, {:?}, flags {:#x}, family {}, socktype {}, protocol {})",
+        node,
+        service,
+        flags,
+        family,
+        socktype,
+        protocol
+    );
+    if node.is_none() && service.is_none() {
+        return EAI_NONAME;
+    }
+
+    let port: u16 = match service.as_deref() {
+        None => 0,
+        Some(service) => match service.parse() {
+            Ok(port) => port,
+            Err(_) => {
+                log!("TODO: getaddrinfo() service name {:?}", service);
+                return EAI_SERVICE;
+            }
+        },
+    };
+
+    let addrs = match node.as_deref() {
+        Some(node) => {
+            let Some(addrs) = resolve(env, node, flags & AI_NUMERICHOST != 0) else {
+                return EAI_NONAME;
+            };
+            addrs
+        }
+        None if flags & AI_PASSIVE != 0 => {
+            vec![Ipv6Addr::UNSPECIFIED.into(), Ipv4Addr::UNSPECIFIED.into()]
+        }
+        None => vec![Ipv6Addr::LOCALHOST.into(), Ipv4Addr::LOCALHOST.into()],
+    };
+    let addrs: Vec<IpAddr> = addrs
+        .into_iter()
+        .filter(|addr| match family {
+            AF_INET => addr.is_ipv4(),
+            AF_INET6 => addr.is_ipv6(),
+            _ => true,
+        })
+        .collect();
+    if addrs.is_empty() {
+        return EAI_NONAME;
+    }
+
+    // Like on a real system, there's one result per socket type if none was
+    // specified.
+    let socket_types: &[(i32, i32)] = match socktype {
+        0 => &[(SOCK_STREAM, IPPROTO_TCP), (SOCK_DGRAM, IPPROTO_UDP)],
+        SOCK_STREAM => &[(SOCK_STREAM, IPPROTO_TCP)],
+        SOCK_DGRAM => &[(SOCK_DGRAM, IPPROTO_UDP)],
+        _ => &[(socktype, protocol)],
+    };
+
+    // Build the list backwards so each node can point to the next.
+    let mut next: MutPtr<addrinfo> = Ptr::null();
+    for (i, &addr) in addrs.iter().enumerate().rev() {
+        for (j, &(ai_socktype, ai_protocol)) in socket_types.iter().enumerate().rev() {
+            let (ai_addr, ai_addrlen) = alloc_sockaddr(env, addr, port);
+            // Only the first result has the canonical name.
+            let ai_canonname = match node.as_deref() {
+                Some(node) if i == 0 && j == 0 && flags & AI_CANONNAME != 0 => {
+                    env.mem.alloc_and_write_cstr(node.as_bytes())
+                }
+                _ => Ptr::null(),
+            };
+            next = env.mem.alloc_and_write(addrinfo {
+                ai_flags: flags,
+                ai_family: if addr.is_ipv4() { AF_INET } else { AF_INET6 },
+                ai_socktype,
+                ai_protocol: if protocol != 0 { protocol } else { ai_protocol },
+                ai_addrlen,
+                ai_canonname,
+                ai_addr,
+                ai_next: next,
+            });
+        }
+    }
+    env.mem.write(res, next);
+    0 // success
+}
+
+fn freeaddrinfo(env: &mut Environment, ai: MutPtr<addrinfo>) {
+    let mut ai = ai;
+    while !ai.is_null() {
+        let addrinfo {
+            ai_canonname,
+            ai_addr,
+            ai_next,
+            ..
+        } = env.mem.read(ai);
+        if !ai_canonname.is_null() {
+            env.mem.free(ai_canonname.cast());
+        }
+        env.mem.free(ai_addr);
+        env.mem.free(ai.cast());
+        ai = ai_next;
+    }
+}
+
+fn gai_strerror(env: &mut Environment, ecode: i32) -> ConstPtr<u8> {
+    if let Some(&str) = State::get_mut(env).gai_strerror_strings.get(&ecode) {
+        return str;
+    }
+    let msg: &[u8] = match ecode {
+        EAI_FAMILY => b"ai_family not supported",
+        EAI_NONAME => b"nodename nor servname provided, or not known",
+        EAI_SERVICE => b"servname not supported for ai_socktype",
+        _ => b"Unknown error",
+    };
+    let str = env.mem.alloc_and_write_cstr(msg).cast_const();
+    State::get_mut(env).gai_strerror_strings.insert(ecode, str);
+    str
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(gethostbyname(_)),
+    export_c_func!(gethostbyaddr(_, _, _)),
+    export_c_func!(getaddrinfo(_, _, _, _)),
+    export_c_func!(freeaddrinfo(_)),
+    export_c_func!(gai_strerror(_)),
+];
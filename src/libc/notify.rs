@@ -0,0 +1,183 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `notify.h` (Darwin notifications).
+//!
+//! On a real device these are system-wide, but there are no other processes
+//! here, so this is just a registry that notifications posted by the app
+//! itself are delivered to. `CFNotificationCenterGetDarwinNotifyCenter()` is
+//! built on top of this.
+
+use super::dispatch::{self, dispatch_queue_t};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::cf_notification_center;
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr};
+use crate::objc::{_Block_copy, _Block_release};
+use crate::Environment;
+use std::collections::HashMap;
+
+const NOTIFY_STATUS_OK: u32 = 0;
+const NOTIFY_STATUS_INVALID_NAME: u32 = 1;
+const NOTIFY_STATUS_INVALID_TOKEN: u32 = 2;
+
+#[derive(Default)]
+pub struct State {
+    next_token: i32,
+    registrations: HashMap<i32, Registration>,
+    /// The state values set with `notify_set_state()`, by name.
+    states: HashMap<String, u64>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.notify
+    }
+}
+
+struct Registration {
+    name: String,
+    /// For `notify_check()`.
+    posted: bool,
+    /// Set for `notify_register_dispatch()`: the heap copy of the handler
+    /// block, and the queue to run it on.
+    handler: Option<(ConstVoidPtr, dispatch_queue_t)>,
+}
+
+fn register(
+    env: &mut Environment,
+    name: ConstPtr<u8>,
+    handler: Option<(ConstVoidPtr, dispatch_queue_t)>,
+) -> Option<i32> {
+    if name.is_null() {
+        return None;
+    }
+    let name = env.mem.cstr_at_utf8(name).unwrap().to_string();
+    let state = State::get_mut(env);
+    let token = state.next_token;
+    state.next_token += 1;
+    state.registrations.insert(
+        token,
+        Registration {
+            name,
+            // The first notify_check() always reports a notification.
+            posted: true,
+            handler,
+        },
+    );
+    Some(token)
+}
+
+/// Deliver a notification to everything registered for it, including
+/// observers on the Darwin notification center.
+pub fn post(env: &mut Environment, name: &str) {
+    let mut handlers = Vec::new();
+    for (&token, registration) in State::get_mut(env).registrations.iter_mut() {
+        if registration.name == name {
+            registration.posted = true;
+            if let Some((handler, queue)) = registration.handler {
+                handlers.push((token, handler, queue));
+            }
+        }
+    }
+    for (token, handler, queue) in handlers {
+        log_dbg!(
+            "Dispatching notify handler {:?} for {:?} (token {}) on queue {:?}",
+            handler,
+            name,
+            token,
+            queue
+        );
+        dispatch::async_block_with_int(env, queue, handler, token);
+    }
+    cf_notification_center::deliver_darwin_notification(env, name);
+}
+
+fn notify_post(env: &mut Environment, name: ConstPtr<u8>) -> u32 {
+    if name.is_null() {
+        return NOTIFY_STATUS_INVALID_NAME;
+    }
+    let name = env.mem.cstr_at_utf8(name).unwrap().to_string();
+    log_dbg!("notify_post({:?})", name);
+    post(env, &name);
+    NOTIFY_STATUS_OK
+}
+
+fn notify_register_check(env: &mut Environment, name: ConstPtr<u8>, out_token: MutPtr<i32>) -> u32 {
+    let Some(token) = register(env, name, None) else {
+        return NOTIFY_STATUS_INVALID_NAME;
+    };
+    log_dbg!("notify_register_check({:?}) => token {}", name, token);
+    env.mem.write(out_token, token);
+    NOTIFY_STATUS_OK
+}
+
+fn notify_register_dispatch(
+    env: &mut Environment,
+    name: ConstPtr<u8>,
+    out_token: MutPtr<i32>,
+    queue: dispatch_queue_t,
+    handler: ConstVoidPtr, // notify_handler_t (a block)
+) -> u32 {
+    if name.is_null() {
+        return NOTIFY_STATUS_INVALID_NAME;
+    }
+    let handler = _Block_copy(env, handler).cast_const();
+    let token = register(env, name, Some((handler, queue))).unwrap();
+    log_dbg!("notify_register_dispatch({:?}) => token {}", name, token);
+    env.mem.write(out_token, token);
+    NOTIFY_STATUS_OK
+}
+
+fn notify_check(env: &mut Environment, token: i32, out_check: MutPtr<i32>) -> u32 {
+    let Some(registration) = State::get_mut(env).registrations.get_mut(&token) else {
+        return NOTIFY_STATUS_INVALID_TOKEN;
+    };
+    let posted = std::mem::take(&mut registration.posted);
+    env.mem.write(out_check, posted.into());
+    NOTIFY_STATUS_OK
+}
+
+fn notify_cancel(env: &mut Environment, token: i32) -> u32 {
+    let Some(registration) = State::get_mut(env).registrations.remove(&token) else {
+        return NOTIFY_STATUS_INVALID_TOKEN;
+    };
+    if let Some((handler, _queue)) = registration.handler {
+        _Block_release(env, handler);
+    }
+    NOTIFY_STATUS_OK
+}
+
+fn notify_is_valid_token(env: &mut Environment, token: i32) -> bool {
+    State::get_mut(env).registrations.contains_key(&token)
+}
+
+fn notify_get_state(env: &mut Environment, token: i32, out_state: MutPtr<u64>) -> u32 {
+    let state = State::get_mut(env);
+    let Some(registration) = state.registrations.get(&token) else {
+        return NOTIFY_STATUS_INVALID_TOKEN;
+    };
+    let value = state.states.get(&registration.name).copied().unwrap_or(0);
+    env.mem.write(out_state, value);
+    NOTIFY_STATUS_OK
+}
+
+fn notify_set_state(env: &mut Environment, token: i32, value: u64) -> u32 {
+    let state = State::get_mut(env);
+    let Some(registration) = state.registrations.get(&token) else {
+        return NOTIFY_STATUS_INVALID_TOKEN;
+    };
+    state.states.insert(registration.name.clone(), value);
+    NOTIFY_STATUS_OK
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(notify_post(_)),
+    export_c_func!(notify_register_check(_, _)),
+    export_c_func!(notify_register_dispatch(_, _, _, _)),
+    export_c_func!(notify_check(_, _)),
+    export_c_func!(notify_cancel(_)),
+    export_c_func!(notify_is_valid_token(_)),
+    export_c_func!(notify_get_state(_, _)),
+    export_c_func!(notify_set_state(_, _)),
+];
@@ -5,31 +5,76 @@
  */
 //! POSIX I/O functions (`fcntl.h`, parts of `unistd.h`, etc)
 
+pub mod poll;
 pub mod stat;
 
 use crate::abi::DotDotDot;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::{GuestFile, GuestOpenOptions, GuestPath};
+use crate::libc::errno::{
+    errno_for_io_error, set_errno, EACCES, EBADF, EFAULT, EILSEQ, EINVAL, EISDIR, ENOENT, ENOTDIR,
+    ENOTTY, ERANGE,
+};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, MutPtr, MutVoidPtr, Ptr};
 use crate::Environment;
+use poll::KqueueHostObject;
 use std::io::{Read, Seek, SeekFrom, Write};
 
 #[derive(Default)]
 pub struct State {
     /// File descriptors _other than stdin, stdout, and stderr_
-    files: Vec<Option<PosixFileHostObject>>,
+    files: Vec<Option<FdHostObject>>,
 }
 impl State {
     fn file_for_fd(&mut self, fd: FileDescriptor) -> Option<&mut PosixFileHostObject> {
+        match self.fd_host_object(fd) {
+            Some(FdHostObject::File(file)) => Some(file),
+            _ => None,
+        }
+    }
+    /// Get the kqueue for a file descriptor, see [poll].
+    fn kqueue_for_fd(&mut self, fd: FileDescriptor) -> Option<&mut KqueueHostObject> {
+        match self.fd_host_object(fd) {
+            Some(FdHostObject::Kqueue(kqueue)) => Some(kqueue),
+            _ => None,
+        }
+    }
+    fn fd_host_object(&mut self, fd: FileDescriptor) -> Option<&mut FdHostObject> {
+        if fd < NORMAL_FILENO_BASE {
+            return None;
+        }
         self.files
             .get_mut(fd_to_file_idx(fd))
             .and_then(|file_or_none| file_or_none.as_mut())
     }
+    /// Allocate the lowest free file descriptor.
+    fn alloc_fd(&mut self, host_object: FdHostObject) -> FileDescriptor {
+        let idx = if let Some(free_idx) = self.files.iter().position(|f| f.is_none()) {
+            self.files[free_idx] = Some(host_object);
+            free_idx
+        } else {
+            let idx = self.files.len();
+            self.files.push(Some(host_object));
+            idx
+        };
+        file_idx_to_fd(idx)
+    }
+}
+
+/// Things a file descriptor can refer to.
+enum FdHostObject {
+    File(PosixFileHostObject),
+    Kqueue(KqueueHostObject),
 }
 
 struct PosixFileHostObject {
     file: GuestFile,
     reached_eof: bool,
+    /// The access mode and status flags ([O_NONBLOCK] and [O_APPEND]), see
+    /// [fcntl].
+    status_flags: OpenFlag,
+    /// [FD_CLOEXEC] is set. There's no `exec`, so this does nothing.
+    close_on_exec: bool,
 }
 
 // TODO: stdin/stdout/stderr handling somehow
@@ -100,7 +145,8 @@ pub fn open_direct(env: &mut Environment, path: ConstPtr<u8>, flags: i32) -> Fil
 
     if path.is_null() {
         log_dbg!("open({:?}, {:#x}) => -1", path, flags);
-        return -1; // TODO: set errno to EFAULT
+        set_errno(env, EFAULT);
+        return -1;
     }
 
     // TODO: respect the mode (in the variadic arguments) when creating a file
@@ -130,7 +176,7 @@ pub fn open_direct(env: &mut Environment, path: ConstPtr<u8>, flags: i32) -> Fil
                 path,
                 err
             );
-            // TODO: set errno
+            set_errno(env, EILSEQ);
             return -1;
         }
     };
@@ -146,26 +192,27 @@ pub fn open_direct(env: &mut Environment, path: ConstPtr<u8>, flags: i32) -> Fil
             let host_object = PosixFileHostObject {
                 file,
                 reached_eof: false,
+                status_flags: flags & (O_ACCMODE | O_NONBLOCK | O_APPEND),
+                close_on_exec: false,
             };
-
-            let idx = if let Some(free_idx) = env
-                .libc_state
+            env.libc_state
                 .posix_io
-                .files
-                .iter()
-                .position(|f| f.is_none())
+                .alloc_fd(FdHostObject::File(host_object))
+        }
+        Err(()) => {
+            // The guest filesystem doesn't say why it failed, so guess.
+            let path = GuestPath::new(&path_string);
+            let errno = if env.fs.is_dir(path) {
+                EISDIR
+            } else if !env.fs.exists(path)
+                && (flags & O_CREAT == 0
+                    || !path.parent().is_some_and(|parent| env.fs.is_dir(parent)))
             {
-                env.libc_state.posix_io.files[free_idx] = Some(host_object);
-                free_idx
+                ENOENT
             } else {
-                let idx = env.libc_state.posix_io.files.len();
-                env.libc_state.posix_io.files.push(Some(host_object));
-                idx
+                EACCES
             };
-            file_idx_to_fd(idx)
-        }
-        Err(()) => {
-            // TODO: set errno
+            set_errno(env, errno);
             -1
         }
     };
@@ -189,8 +236,11 @@ pub fn read(
     buffer: MutVoidPtr,
     size: GuestUSize,
 ) -> GuestISize {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        log!("Warning: read() called with unknown fd {}", fd);
+        set_errno(env, EBADF);
+        return -1;
+    };
 
     let buffer_slice = env.mem.bytes_at_mut(buffer.cast(), size);
     match file.file.read(buffer_slice) {
@@ -219,7 +269,7 @@ pub fn read(
             bytes_read.try_into().unwrap()
         }
         Err(e) => {
-            // TODO: set errno
+            set_errno(env, errno_for_io_error(&e));
             log!(
                 "Warning: read({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
                 fd,
@@ -248,8 +298,11 @@ pub fn write(
     buffer: ConstVoidPtr,
     size: GuestUSize,
 ) -> GuestISize {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        log!("Warning: write() called with unknown fd {}", fd);
+        set_errno(env, EBADF);
+        return -1;
+    };
 
     let buffer_slice = env.mem.bytes_at(buffer.cast(), size);
     match file.file.write(buffer_slice) {
@@ -274,7 +327,7 @@ pub fn write(
             bytes_written.try_into().unwrap()
         }
         Err(e) => {
-            // TODO: set errno
+            set_errno(env, errno_for_io_error(&e));
             log!(
                 "Warning: write({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
                 fd,
@@ -293,8 +346,10 @@ pub const SEEK_SET: i32 = 0;
 pub const SEEK_CUR: i32 = 1;
 pub const SEEK_END: i32 = 2;
 pub fn lseek(env: &mut Environment, fd: FileDescriptor, offset: off_t, whence: i32) -> off_t {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
 
     let from = match whence {
         // not sure whether offset is treated as signed or unsigned when using
@@ -313,21 +368,36 @@ pub fn lseek(env: &mut Environment, fd: FileDescriptor, offset: off_t, whence: i
 
             new_offset.try_into().unwrap()
         }
-        // TODO: set errno
-        Err(_) => -1,
+        Err(e) => {
+            set_errno(env, errno_for_io_error(&e));
+            -1
+        }
     };
     log_dbg!("lseek({:?}, {:#x}, {}) => {}", fd, offset, whence, res);
     res
 }
 
 pub fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
-    // TODO: error handling for unknown fd?
-    if fd < 0 || matches!(fd, STDOUT_FILENO | STDERR_FILENO) {
+    if matches!(fd, STDIN_FILENO | STDOUT_FILENO | STDERR_FILENO) {
         return 0;
     }
+    if fd < 0 {
+        set_errno(env, EBADF);
+        return -1;
+    }
 
-    match env.libc_state.posix_io.files[fd_to_file_idx(fd)].take() {
-        Some(file) => {
+    match env
+        .libc_state
+        .posix_io
+        .files
+        .get_mut(fd_to_file_idx(fd))
+        .and_then(Option::take)
+    {
+        Some(FdHostObject::Kqueue(_)) => {
+            log_dbg!("close({:?}) => 0", fd);
+            0
+        }
+        Some(FdHostObject::File(file)) => {
             // The actual closing of the file happens implicitly when `file`
             // falls out of scope. The return value is about whether flushing
             // succeeds.
@@ -336,15 +406,15 @@ pub fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
                     log_dbg!("close({:?}) => 0", fd);
                     0
                 }
-                Err(_) => {
-                    // TODO: set errno
+                Err(e) => {
+                    set_errno(env, errno_for_io_error(&e));
                     log!("Warning: close({:?}) failed, returning -1", fd);
                     -1
                 }
             }
         }
         None => {
-            // TODO: set errno
+            set_errno(env, EBADF);
             log!("Warning: close({:?}) failed, returning -1", fd);
             -1
         }
@@ -354,7 +424,7 @@ pub fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
 fn getcwd(env: &mut Environment, buf_ptr: MutPtr<u8>, buf_size: GuestUSize) -> MutPtr<u8> {
     let working_directory = env.fs.working_directory();
     if !env.fs.is_dir(working_directory) {
-        // TODO: set errno to ENOENT
+        set_errno(env, ENOENT);
         log!(
             "Warning: getcwd({:?}, {:#x}) failed, returning NULL",
             buf_ptr,
@@ -377,7 +447,7 @@ fn getcwd(env: &mut Environment, buf_ptr: MutPtr<u8>, buf_size: GuestUSize) -> M
     let res_size: GuestUSize = u32::try_from(working_directory.len()).unwrap() + 1;
 
     if buf_size < res_size {
-        // TODO: set errno to EINVAL or ERANGE as appropriate
+        set_errno(env, if buf_size == 0 { EINVAL } else { ERANGE });
         log!(
             "Warning: getcwd({:?}, {:#x}) failed, returning NULL",
             buf_ptr,
@@ -414,7 +484,7 @@ fn chdir(env: &mut Environment, path_ptr: ConstPtr<u8>) -> i32 {
         }
         Err(()) => {
             log!("Warning: chdir({:?}) failed, could not change working directory to {:?}, returning -1", path_ptr, path);
-            // TODO: set errno
+            set_errno(env, if env.fs.exists(path) { ENOTDIR } else { ENOENT });
             -1
         }
     }
@@ -426,11 +496,156 @@ fn flock(_env: &mut Environment, fd: FileDescriptor, operation: FLockFlag) -> i3
     0
 }
 
+pub const F_GETFD: i32 = 1;
+pub const F_SETFD: i32 = 2;
+pub const F_GETFL: i32 = 3;
+pub const F_SETFL: i32 = 4;
+
+pub const FD_CLOEXEC: i32 = 1;
+
+fn fcntl(env: &mut Environment, fd: FileDescriptor, cmd: i32, args: DotDotDot) -> i32 {
+    let arg = match cmd {
+        F_SETFD | F_SETFL => args.start().next(env),
+        _ => 0,
+    };
+    fcntl_direct(env, fd, cmd, arg)
+}
+
+/// Special extension for host code: [fcntl] without the [DotDotDot]. `arg` is
+/// ignored for commands that don't take an argument.
+fn fcntl_direct(env: &mut Environment, fd: FileDescriptor, cmd: i32, arg: i32) -> i32 {
+    let res = match env.libc_state.posix_io.fd_host_object(fd) {
+        Some(FdHostObject::File(file)) => match cmd {
+            F_GETFD => {
+                if file.close_on_exec {
+                    Ok(FD_CLOEXEC)
+                } else {
+                    Ok(0)
+                }
+            }
+            F_SETFD => {
+                file.close_on_exec = arg & FD_CLOEXEC != 0;
+                Ok(0)
+            }
+            F_GETFL => Ok(file.status_flags),
+            F_SETFL => {
+                if (arg ^ file.status_flags) & O_APPEND != 0 {
+                    log!("TODO: changing O_APPEND of open file {} with fcntl()", fd);
+                }
+                // Note: O_NONBLOCK has no effect, assumption is all file I/O
+                // is fast.
+                file.status_flags = (file.status_flags & !O_NONBLOCK) | (arg & O_NONBLOCK);
+                Ok(0)
+            }
+            _ => {
+                log!("TODO: fcntl({}, {}, {:#x})", fd, cmd, arg);
+                Err(EINVAL)
+            }
+        },
+        Some(FdHostObject::Kqueue(_)) => fcntl_without_flags(fd, cmd, arg, O_RDONLY),
+        None if fd == STDIN_FILENO => fcntl_without_flags(fd, cmd, arg, O_RDONLY),
+        None if fd == STDOUT_FILENO || fd == STDERR_FILENO => {
+            fcntl_without_flags(fd, cmd, arg, O_WRONLY)
+        }
+        None => Err(EBADF),
+    };
+    log_dbg!("fcntl({}, {}, {:#x}) => {:?}", fd, cmd, arg, res);
+    res.unwrap_or_else(|errno| {
+        set_errno(env, errno);
+        -1
+    })
+}
+
+/// [fcntl] for stdin, stdout, stderr and kqueues, which don't have any flags
+/// that mean anything here, so changes are ignored. The error is an `errno`
+/// value.
+fn fcntl_without_flags(
+    fd: FileDescriptor,
+    cmd: i32,
+    arg: i32,
+    access_mode: OpenFlag,
+) -> Result<i32, i32> {
+    match cmd {
+        F_GETFD => Ok(0),
+        F_GETFL => Ok(access_mode),
+        F_SETFD | F_SETFL => {
+            log_dbg!("Ignoring fcntl({}, {}, {:#x})", fd, cmd, arg);
+            Ok(0)
+        }
+        _ => {
+            log!("TODO: fcntl({}, {}, {:#x})", fd, cmd, arg);
+            Err(EINVAL)
+        }
+    }
+}
+
+/// `_IOW('f', 126, int)`
+pub const FIONBIO: u32 = 0x8004667e;
+/// `_IOR('f', 127, int)`
+pub const FIONREAD: u32 = 0x4004667f;
+
+fn ioctl(env: &mut Environment, fd: FileDescriptor, request: u32, args: DotDotDot) -> i32 {
+    let arg: MutPtr<i32> = args.start().next(env);
+    let res = match request {
+        FIONBIO => {
+            let non_blocking = env.mem.read(arg.cast_const()) != 0;
+            let flags = fcntl_direct(env, fd, F_GETFL, 0);
+            if flags == -1 {
+                -1
+            } else {
+                let flags = if non_blocking {
+                    flags | O_NONBLOCK
+                } else {
+                    flags & !O_NONBLOCK
+                };
+                fcntl_direct(env, fd, F_SETFL, flags)
+            }
+        }
+        FIONREAD => {
+            let available = match env.libc_state.posix_io.fd_host_object(fd) {
+                Some(FdHostObject::File(file)) => {
+                    let position = file.file.stream_position();
+                    let len = file.file.metadata().map(|metadata| metadata.len);
+                    match (position, len) {
+                        (Ok(position), Ok(len)) => {
+                            Some(len.saturating_sub(position).min(i32::MAX as u64) as i32)
+                        }
+                        _ => None,
+                    }
+                }
+                // There's never any input on stdin.
+                None if fd == STDIN_FILENO => Some(0),
+                _ => None,
+            };
+            if let Some(available) = available {
+                env.mem.write(arg, available);
+                0
+            } else {
+                set_errno(env, EBADF);
+                -1
+            }
+        }
+        _ => {
+            log!("TODO: ioctl({}, {:#x}, {:?})", fd, request, arg);
+            set_errno(env, ENOTTY);
+            -1
+        }
+    };
+    log_dbg!("ioctl({}, {:#x}, {:?}) => {}", fd, request, arg, res);
+    res
+}
+
 fn ftruncate(env: &mut Environment, fd: FileDescriptor, len: off_t) -> i32 {
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
     match file.file.set_len(len as u64) {
         Ok(()) => 0,
-        Err(_) => -1, // TODO: set errno
+        Err(e) => {
+            set_errno(env, errno_for_io_error(&e));
+            -1
+        }
     }
 }
 
@@ -444,4 +659,6 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(chdir(_)),
     export_c_func!(flock(_, _)),
     export_c_func!(ftruncate(_, _)),
+    export_c_func!(fcntl(_, _, _)),
+    export_c_func!(ioctl(_, _, _)),
 ];
@@ -0,0 +1,492 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! I/O multiplexing: `poll.h`, `sys/select.h` and a subset of `sys/event.h`
+//! (kqueue).
+//!
+//! The only file descriptors touchHLE has are for regular files, which are
+//! always ready, and stdin, stdout and stderr, whose readiness never changes.
+//! So there is no need to watch anything: if nothing is ready to begin with,
+//! nothing will become ready later, and the thread just sleeps until the
+//! timeout. The exception is kqueue timers, which are checked after sleeping.
+
+use super::{FdHostObject, FileDescriptor, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EBADF, EINVAL, ENOENT};
+use crate::libc::time::{timespec, timeval};
+use crate::mem::{ConstPtr, GuestUSize, MutPtr, MutVoidPtr, SafeRead};
+use crate::Environment;
+use std::time::{Duration, Instant};
+
+/// Used in place of an infinite timeout, since nothing that could end the wait
+/// is implemented. 100 years is long enough.
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Readiness of a file descriptor as `(readable, writable)`, or [None] if it's
+/// not open.
+fn readiness(env: &mut Environment, fd: FileDescriptor) -> Option<(bool, bool)> {
+    match fd {
+        // There's never any input.
+        STDIN_FILENO => Some((false, false)),
+        STDOUT_FILENO | STDERR_FILENO => Some((false, true)),
+        _ => match env.libc_state.posix_io.fd_host_object(fd)? {
+            FdHostObject::File(_) => Some((true, true)),
+            // TODO: report a kqueue as readable when it has pending events
+            FdHostObject::Kqueue(_) => Some((false, false)),
+        },
+    }
+}
+
+/// Sleep the current thread for the timeout, which must be the last thing
+/// done before the host function returns.
+fn sleep_for_timeout(env: &mut Environment, timeout: Option<Duration>, func: &str) {
+    let timeout = timeout.unwrap_or_else(|| {
+        log!(
+            "Warning: {}() with no timeout and nothing ready, this thread will never wake up!",
+            func
+        );
+        FOREVER
+    });
+    if !timeout.is_zero() {
+        env.sleep(timeout, /* tail_call: */ true);
+    }
+}
+
+// poll.h
+
+const POLLIN: i16 = 0x1;
+const POLLOUT: i16 = 0x4;
+const POLLNVAL: i16 = 0x20;
+const POLLRDNORM: i16 = 0x40;
+const POLLWRNORM: i16 = POLLOUT;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct pollfd {
+    fd: FileDescriptor,
+    events: i16,
+    revents: i16,
+}
+unsafe impl SafeRead for pollfd {}
+
+fn poll(env: &mut Environment, fds: MutPtr<pollfd>, nfds: u32, timeout_ms: i32) -> i32 {
+    let mut ready_count = 0;
+    for i in 0..nfds {
+        let pollfd { fd, events, .. } = env.mem.read(fds + i);
+        let revents = if fd < 0 {
+            // Negative file descriptors are ignored.
+            0
+        } else if let Some((readable, writable)) = readiness(env, fd) {
+            let mut revents = 0;
+            if readable {
+                revents |= events & (POLLIN | POLLRDNORM);
+            }
+            if writable {
+                revents |= events & (POLLOUT | POLLWRNORM);
+            }
+            revents
+        } else {
+            POLLNVAL
+        };
+        if revents != 0 {
+            ready_count += 1;
+        }
+        env.mem.write(
+            fds + i,
+            pollfd {
+                fd,
+                events,
+                revents,
+            },
+        );
+    }
+    log_dbg!(
+        "poll({:?}, {}, {}) => {}",
+        fds,
+        nfds,
+        timeout_ms,
+        ready_count
+    );
+    if ready_count == 0 {
+        let timeout = (timeout_ms >= 0).then(|| Duration::from_millis(timeout_ms as u64));
+        sleep_for_timeout(env, timeout, "poll");
+    }
+    ready_count
+}
+
+// sys/select.h
+
+/// `FD_SETSIZE`
+const FD_SETSIZE: i32 = 1024;
+
+/// The bitfield used for `fd_set`.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct fd_set {
+    fds_bits: [u32; FD_SETSIZE as usize / 32],
+}
+unsafe impl SafeRead for fd_set {}
+impl fd_set {
+    fn is_set(&self, fd: FileDescriptor) -> bool {
+        let bits = self.fds_bits;
+        bits[fd as usize / 32] & (1 << (fd % 32)) != 0
+    }
+    fn clear(&mut self, fd: FileDescriptor) {
+        let mut bits = self.fds_bits;
+        bits[fd as usize / 32] &= !(1 << (fd % 32));
+        self.fds_bits = bits;
+    }
+}
+
+fn select(
+    env: &mut Environment,
+    nfds: i32,
+    readfds: MutPtr<fd_set>,
+    writefds: MutPtr<fd_set>,
+    errorfds: MutPtr<fd_set>,
+    timeout: ConstPtr<timeval>,
+) -> i32 {
+    if !(0..=FD_SETSIZE).contains(&nfds) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    let read_fd_set =
+        |env: &mut Environment, set: MutPtr<fd_set>| (!set.is_null()).then(|| env.mem.read(set));
+    let mut read_set = read_fd_set(env, readfds);
+    let mut write_set = read_fd_set(env, writefds);
+    let mut error_set = read_fd_set(env, errorfds);
+
+    let mut ready_count = 0;
+    for fd in 0..nfds {
+        let wants_read = read_set.as_ref().is_some_and(|set| set.is_set(fd));
+        let wants_write = write_set.as_ref().is_some_and(|set| set.is_set(fd));
+        let wants_error = error_set.as_ref().is_some_and(|set| set.is_set(fd));
+        if !(wants_read || wants_write || wants_error) {
+            continue;
+        }
+        let Some((readable, writable)) = readiness(env, fd) else {
+            log_dbg!("select(): {} is not open, returning -1", fd);
+            set_errno(env, EBADF);
+            return -1;
+        };
+        if wants_read && !readable {
+            read_set.as_mut().unwrap().clear(fd);
+        }
+        if wants_write && !writable {
+            write_set.as_mut().unwrap().clear(fd);
+        }
+        if wants_error {
+            // There are never any exceptional conditions.
+            error_set.as_mut().unwrap().clear(fd);
+        }
+        ready_count += (wants_read && readable) as i32 + (wants_write && writable) as i32;
+    }
+
+    for (ptr, set) in [
+        (readfds, read_set),
+        (writefds, write_set),
+        (errorfds, error_set),
+    ] {
+        if let Some(set) = set {
+            env.mem.write(ptr, set);
+        }
+    }
+
+    log_dbg!(
+        "select({}, {:?}, {:?}, {:?}, {:?}) => {}",
+        nfds,
+        readfds,
+        writefds,
+        errorfds,
+        timeout,
+        ready_count
+    );
+    if ready_count == 0 {
+        let timeout = (!timeout.is_null()).then(|| {
+            let timeval { tv_sec, tv_usec } = env.mem.read(timeout);
+            Duration::from_secs(tv_sec.max(0) as u64) + Duration::from_micros(tv_usec.max(0) as u64)
+        });
+        sleep_for_timeout(env, timeout, "select");
+    }
+    ready_count
+}
+
+// sys/event.h
+
+const EVFILT_READ: i16 = -1;
+const EVFILT_WRITE: i16 = -2;
+const EVFILT_TIMER: i16 = -7;
+
+const EV_ADD: u16 = 0x1;
+const EV_DELETE: u16 = 0x2;
+const EV_ENABLE: u16 = 0x4;
+const EV_DISABLE: u16 = 0x8;
+const EV_ONESHOT: u16 = 0x10;
+const EV_ERROR: u16 = 0x4000;
+
+/// `NOTE_SECONDS` etc for [EVFILT_TIMER]. Milliseconds are the default.
+const NOTE_SECONDS: u32 = 0x1;
+const NOTE_USECONDS: u32 = 0x2;
+const NOTE_NSECONDS: u32 = 0x4;
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct kevent {
+    ident: GuestUSize,
+    filter: i16,
+    flags: u16,
+    fflags: u32,
+    data: i32,
+    udata: MutVoidPtr,
+}
+unsafe impl SafeRead for kevent {}
+
+struct Registration {
+    /// The event as it was added, for `ident`, `filter`, `flags`, `fflags` and
+    /// `udata`.
+    event: kevent,
+    enabled: bool,
+    /// For [EVFILT_TIMER]: the period and the next expiry.
+    timer: Option<(Duration, Instant)>,
+}
+
+#[derive(Default)]
+pub struct KqueueHostObject {
+    registrations: Vec<Registration>,
+}
+
+fn kqueue(env: &mut Environment) -> FileDescriptor {
+    let fd = env
+        .libc_state
+        .posix_io
+        .alloc_fd(FdHostObject::Kqueue(Default::default()));
+    log_dbg!("kqueue() => {}", fd);
+    fd
+}
+
+fn timer_period(data: i32, fflags: u32) -> Duration {
+    let data = data.max(0) as u64;
+    if fflags & NOTE_SECONDS != 0 {
+        Duration::from_secs(data)
+    } else if fflags & NOTE_USECONDS != 0 {
+        Duration::from_micros(data)
+    } else if fflags & NOTE_NSECONDS != 0 {
+        Duration::from_nanos(data)
+    } else {
+        Duration::from_millis(data)
+    }
+}
+
+/// Apply a change to a kqueue. Returns an errno value on failure.
+fn apply_change(env: &mut Environment, kq: FileDescriptor, change: kevent) -> Result<(), i32> {
+    let kevent {
+        ident,
+        filter,
+        flags,
+        fflags,
+        data,
+        ..
+    } = change;
+    match filter {
+        EVFILT_READ | EVFILT_WRITE => {
+            if readiness(env, ident as FileDescriptor).is_none() {
+                return Err(EBADF);
+            }
+        }
+        EVFILT_TIMER => (),
+        _ => {
+            log!("TODO: kevent() filter {}", filter);
+            return Err(EINVAL);
+        }
+    }
+
+    let kqueue = env.libc_state.posix_io.kqueue_for_fd(kq).unwrap();
+    let existing = kqueue
+        .registrations
+        .iter()
+        .position(|r| r.event.ident == ident && r.event.filter == filter);
+    if flags & EV_DELETE != 0 {
+        let Some(idx) = existing else {
+            return Err(ENOENT);
+        };
+        kqueue.registrations.remove(idx);
+        return Ok(());
+    }
+    let idx = match existing {
+        Some(idx) => {
+            if flags & EV_ADD != 0 {
+                kqueue.registrations[idx].event = change;
+            }
+            idx
+        }
+        None if flags & EV_ADD != 0 => {
+            kqueue.registrations.push(Registration {
+                event: change,
+                enabled: true,
+                timer: None,
+            });
+            kqueue.registrations.len() - 1
+        }
+        None => return Err(ENOENT),
+    };
+    let registration = &mut kqueue.registrations[idx];
+    if flags & EV_ENABLE != 0 {
+        registration.enabled = true;
+    }
+    if flags & EV_DISABLE != 0 {
+        registration.enabled = false;
+    }
+    if filter == EVFILT_TIMER && flags & EV_ADD != 0 {
+        let period = timer_period(data, fflags);
+        registration.timer = Some((period, Instant::now() + period));
+    }
+    Ok(())
+}
+
+/// Collect the triggered events, up to `max` of them.
+fn collect_events(env: &mut Environment, kq: FileDescriptor, max: usize) -> Vec<kevent> {
+    let kqueue = env.libc_state.posix_io.kqueue_for_fd(kq).unwrap();
+    let registrations = std::mem::take(&mut kqueue.registrations);
+    let now = Instant::now();
+    let mut events = Vec::new();
+    let mut kept = Vec::new();
+    for mut registration in registrations {
+        if !registration.enabled || events.len() == max {
+            kept.push(registration);
+            continue;
+        }
+        let triggered = match registration.event.filter {
+            EVFILT_READ | EVFILT_WRITE => {
+                // Checked when the event was added, and regular files are
+                // always ready.
+                // TODO: data should be the number of bytes available to read
+                Some(0)
+            }
+            EVFILT_TIMER => {
+                let (period, next) = registration.timer.as_mut().unwrap();
+                if now < *next {
+                    None
+                } else {
+                    // Report how many times the timer expired.
+                    let mut expirations = 0;
+                    while now >= *next && expirations < i32::MAX {
+                        expirations += 1;
+                        if period.is_zero() {
+                            break;
+                        }
+                        *next += *period;
+                    }
+                    Some(expirations)
+                }
+            }
+            _ => unreachable!(),
+        };
+        if let Some(data) = triggered {
+            let mut event = registration.event;
+            event.data = data;
+            events.push(event);
+            if registration.event.flags & EV_ONESHOT != 0 {
+                continue;
+            }
+        }
+        kept.push(registration);
+    }
+    let kqueue = env.libc_state.posix_io.kqueue_for_fd(kq).unwrap();
+    kqueue.registrations = kept;
+    events
+}
+
+/// The time until the next enabled timer expires, if any.
+fn time_until_next_timer(env: &mut Environment, kq: FileDescriptor) -> Option<Duration> {
+    let kqueue = env.libc_state.posix_io.kqueue_for_fd(kq).unwrap();
+    let now = Instant::now();
+    kqueue
+        .registrations
+        .iter()
+        .filter(|r| r.enabled)
+        .filter_map(|r| r.timer.map(|(_, next)| next.saturating_duration_since(now)))
+        .min()
+}
+
+fn kevent(
+    env: &mut Environment,
+    kq: FileDescriptor,
+    changelist: ConstPtr<kevent>,
+    nchanges: i32,
+    eventlist: MutPtr<kevent>,
+    nevents: i32,
+    timeout: ConstPtr<timespec>,
+) -> i32 {
+    if env.libc_state.posix_io.kqueue_for_fd(kq).is_none() {
+        set_errno(env, EBADF);
+        return -1;
+    }
+
+    let mut out_events = Vec::new();
+    for i in 0..nchanges.max(0) as GuestUSize {
+        let change = env.mem.read(changelist + i);
+        if let Err(errno) = apply_change(env, kq, change) {
+            // Errors are reported in the event list if there's room.
+            if (out_events.len() as i32) < nevents {
+                let mut event = change;
+                event.flags = EV_ERROR;
+                event.data = errno;
+                out_events.push(event);
+            } else {
+                set_errno(env, errno);
+                return -1;
+            }
+        }
+    }
+
+    let timeout = (!timeout.is_null()).then(|| {
+        let timespec { tv_sec, tv_nsec } = env.mem.read(timeout);
+        Duration::from_secs(tv_sec.max(0) as u64) + Duration::from_nanos(tv_nsec.max(0) as u64)
+    });
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    if out_events.is_empty() && nevents > 0 {
+        loop {
+            out_events = collect_events(env, kq, nevents as usize);
+            if !out_events.is_empty() {
+                break;
+            }
+            let until_deadline = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if until_deadline.is_some_and(|d| d.is_zero()) {
+                break;
+            }
+            let Some(until_timer) = time_until_next_timer(env, kq) else {
+                // Nothing can happen before the timeout.
+                sleep_for_timeout(env, until_deadline, "kevent");
+                break;
+            };
+            // Let other threads run while waiting for the timer.
+            let duration = until_deadline.map_or(until_timer, |d| d.min(until_timer));
+            env.sleep(duration, /* tail_call: */ false);
+        }
+    }
+
+    for (i, event) in out_events.iter().enumerate() {
+        env.mem.write(eventlist + i as GuestUSize, *event);
+    }
+    log_dbg!(
+        "kevent({}, {:?}, {}, {:?}, {}, {:?}) => {}",
+        kq,
+        changelist,
+        nchanges,
+        eventlist,
+        nevents,
+        timeout,
+        out_events.len()
+    );
+    out_events.len() as i32
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(poll(_, _, _)),
+    export_c_func!(select(_, _, _, _, _)),
+    export_c_func!(kqueue()),
+    export_c_func!(kevent(_, _, _, _, _, _)),
+];
@@ -3,18 +3,136 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! POSIX `sys/stat.h`
+//! POSIX `sys/stat.h` and `sys/mount.h` (`statfs`)
 
 use super::{off_t, FileDescriptor};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::fs::GuestPath;
-use crate::mem::{ConstPtr, MutVoidPtr};
+use crate::fs::{GuestMetadata, GuestPath};
+use crate::libc::errno::{set_errno, EBADF, EEXIST, EFAULT, ENOENT};
+use crate::libc::time::{time_t, timespec};
+use crate::mem::{ConstPtr, MutPtr, SafeRead};
 use crate::Environment;
-use std::io::{Seek, SeekFrom};
+use std::time::SystemTime;
 
 #[allow(non_camel_case_types)]
 pub type mode_t = u16;
 
+pub const S_IFDIR: mode_t = 0o040000;
+pub const S_IFREG: mode_t = 0o100000;
+
+#[allow(non_camel_case_types)]
+type dev_t = i32;
+#[allow(non_camel_case_types)]
+type ino_t = u64;
+#[allow(non_camel_case_types)]
+type nlink_t = u16;
+#[allow(non_camel_case_types)]
+type uid_t = u32;
+#[allow(non_camel_case_types)]
+type gid_t = u32;
+#[allow(non_camel_case_types)]
+type blkcnt_t = i64;
+#[allow(non_camel_case_types)]
+type blksize_t = i32;
+
+/// `struct stat`. iPhone OS always uses the 64-bit inode variant of this struct
+/// (the one that is `struct stat64` on Mac OS X).
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct stat {
+    st_dev: dev_t,
+    st_mode: mode_t,
+    st_nlink: nlink_t,
+    st_ino: ino_t,
+    st_uid: uid_t,
+    st_gid: gid_t,
+    st_rdev: dev_t,
+    st_atimespec: timespec,
+    st_mtimespec: timespec,
+    st_ctimespec: timespec,
+    st_birthtimespec: timespec,
+    st_size: off_t,
+    st_blocks: blkcnt_t,
+    st_blksize: blksize_t,
+    st_flags: u32,
+    st_gen: u32,
+    st_lspare: i32,
+    st_qspare: [i64; 2],
+}
+unsafe impl SafeRead for stat {}
+const _: () = assert!(std::mem::size_of::<stat>() == 108);
+
+/// Block size reported for all files and filesystems.
+const BLOCK_SIZE: u32 = 4096;
+
+/// Device number reported for all files, since there's only one filesystem.
+const FAKE_DEV: dev_t = 1;
+
+/// The user and group ID that the app runs as (`mobile`).
+const MOBILE_UID: uid_t = 501;
+const MOBILE_GID: gid_t = 501;
+
+fn to_timespec(time: Option<SystemTime>) -> timespec {
+    let Some(duration) = time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+    else {
+        return timespec::default();
+    };
+    timespec {
+        tv_sec: duration.as_secs() as time_t,
+        tv_nsec: duration.subsec_nanos() as i32,
+    }
+}
+
+/// There are no real inode numbers in the guest filesystem, so a hash of the
+/// path is used to make a number that's at least stable and probably unique.
+fn fake_inode_number(path: Option<&GuestPath>) -> ino_t {
+    use std::hash::{Hash, Hasher};
+    let Some(path) = path else {
+        // TODO: remember paths of open files so fstat() can do better
+        return 0;
+    };
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    path.as_str().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn metadata_to_stat(metadata: GuestMetadata, path: Option<&GuestPath>) -> stat {
+    let GuestMetadata {
+        is_dir,
+        writeable,
+        len,
+        modified,
+    } = metadata;
+    let mode = match (is_dir, writeable) {
+        (true, true) => S_IFDIR | 0o755,
+        (true, false) => S_IFDIR | 0o555,
+        (false, true) => S_IFREG | 0o644,
+        (false, false) => S_IFREG | 0o444,
+    };
+    let len: off_t = len.try_into().unwrap();
+    stat {
+        st_dev: FAKE_DEV,
+        st_mode: mode,
+        st_nlink: 1,
+        st_ino: fake_inode_number(path),
+        st_uid: MOBILE_UID,
+        st_gid: MOBILE_GID,
+        st_rdev: 0,
+        st_atimespec: to_timespec(modified),
+        st_mtimespec: to_timespec(modified),
+        st_ctimespec: to_timespec(modified),
+        st_birthtimespec: to_timespec(modified),
+        st_size: len,
+        // st_blocks is always in units of 512 bytes
+        st_blocks: (len + 511) / 512,
+        st_blksize: BLOCK_SIZE as blksize_t,
+        st_flags: 0,
+        st_gen: 0,
+        st_lspare: 0,
+        st_qspare: [0; 2],
+    }
+}
+
 fn mkdir(env: &mut Environment, path: ConstPtr<u8>, mode: mode_t) -> i32 {
     // TODO: respect the mode
     match env
@@ -26,7 +144,10 @@ fn mkdir(env: &mut Environment, path: ConstPtr<u8>, mode: mode_t) -> i32 {
             0
         }
         Err(()) => {
-            // TODO: set errno
+            let exists = env
+                .fs
+                .exists(GuestPath::new(env.mem.cstr_at_utf8(path).unwrap()));
+            set_errno(env, if exists { EEXIST } else { ENOENT });
             log!(
                 "Warning: mkdir({:?}, {:#x}) failed, returning -1",
                 path,
@@ -37,24 +158,126 @@ fn mkdir(env: &mut Environment, path: ConstPtr<u8>, mode: mode_t) -> i32 {
     }
 }
 
-fn fstat(env: &mut Environment, fd: FileDescriptor, buf: MutVoidPtr) -> i32 {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+fn stat(env: &mut Environment, path: ConstPtr<u8>, buf: MutPtr<stat>) -> i32 {
+    if path.is_null() {
+        set_errno(env, EFAULT);
+        return -1;
+    }
+    let path_string = env.mem.cstr_at_utf8(path).unwrap().to_owned();
+    let guest_path = GuestPath::new(&path_string);
+    let Ok(metadata) = env.fs.metadata(guest_path) else {
+        set_errno(env, ENOENT);
+        log_dbg!("stat({:?} {:?}, {:?}) => -1", path, path_string, buf);
+        return -1;
+    };
+    log_dbg!("stat({:?} {:?}, {:?}) => 0", path, path_string, buf);
+    let stat = metadata_to_stat(metadata, Some(guest_path));
+    env.mem.write(buf, stat);
+    0 // success
+}
 
-    log!("Warning: fstat() call, this function is mostly unimplemented");
-    // FIXME: This implementation is highly incomplete. fstat() returns a huge
-    // struct with many kinds of data in it. This code is assuming the caller
-    // only wants the file size.
-    let st_size_ptr = (buf + 0x3c).cast::<off_t>();
+fn lstat(env: &mut Environment, path: ConstPtr<u8>, buf: MutPtr<stat>) -> i32 {
+    // There are no symlinks in the guest filesystem, so this is the same as
+    // stat().
+    stat(env, path, buf)
+}
 
-    // TODO: Use the stream_len() method if that ever gets stabilized.
-    let old_pos = file.file.stream_position().unwrap();
-    let full_size = file.file.seek(SeekFrom::End(0)).unwrap();
-    file.file.seek(SeekFrom::Start(old_pos)).unwrap();
+fn fstat(env: &mut Environment, fd: FileDescriptor, buf: MutPtr<stat>) -> i32 {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        log!("Warning: fstat() called with unknown fd {}", fd);
+        return -1;
+    };
+    let metadata = file.file.metadata().unwrap();
+    log_dbg!("fstat({:?}, {:?}) => 0", fd, buf);
+    let stat = metadata_to_stat(metadata, None);
+    env.mem.write(buf, stat);
+    0 // success
+}
 
-    env.mem.write(st_size_ptr, full_size.try_into().unwrap());
+/// `struct statfs` (64-bit inode variant, like [stat]).
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct statfs {
+    f_bsize: u32,
+    f_iosize: i32,
+    f_blocks: u64,
+    f_bfree: u64,
+    f_bavail: u64,
+    f_files: u64,
+    f_ffree: u64,
+    f_fsid: [i32; 2],
+    f_owner: uid_t,
+    f_type: u32,
+    f_flags: u32,
+    f_fssubtype: u32,
+    f_fstypename: [u8; 16],
+    f_mntonname: [u8; 1024],
+    f_mntfromname: [u8; 1024],
+    f_reserved: [u32; 8],
+}
+unsafe impl SafeRead for statfs {}
+const _: () = assert!(std::mem::size_of::<statfs>() == 2168);
+
+fn fake_statfs() -> statfs {
+    // The host's free space isn't a meaningful thing to report, so this
+    // pretends to be a fairly empty 8GB device.
+    let total_blocks = (8 * 1024 * 1024 * 1024) / u64::from(BLOCK_SIZE);
+    let free_blocks = total_blocks / 2;
 
+    let mut fs = statfs {
+        f_bsize: BLOCK_SIZE,
+        f_iosize: BLOCK_SIZE as i32,
+        f_blocks: total_blocks,
+        f_bfree: free_blocks,
+        f_bavail: free_blocks,
+        f_files: total_blocks,
+        f_ffree: free_blocks,
+        f_fsid: [FAKE_DEV, 0],
+        f_owner: 0,
+        f_type: 0,
+        f_flags: 0,
+        f_fssubtype: 0,
+        f_fstypename: [0; 16],
+        f_mntonname: [0; 1024],
+        f_mntfromname: [0; 1024],
+        f_reserved: [0; 8],
+    };
+    let fstypename = b"hfs";
+    fs.f_fstypename[..fstypename.len()].copy_from_slice(fstypename);
+    let mntonname = b"/";
+    fs.f_mntonname[..mntonname.len()].copy_from_slice(mntonname);
+    let mntfromname = b"/dev/disk0s1";
+    fs.f_mntfromname[..mntfromname.len()].copy_from_slice(mntfromname);
+    fs
+}
+
+fn statfs(env: &mut Environment, path: ConstPtr<u8>, buf: MutPtr<statfs>) -> i32 {
+    let path_string = env.mem.cstr_at_utf8(path).unwrap().to_owned();
+    if !env.fs.exists(GuestPath::new(&path_string)) {
+        set_errno(env, ENOENT);
+        log_dbg!("statfs({:?} {:?}, {:?}) => -1", path, path_string, buf);
+        return -1;
+    }
+    log_dbg!("statfs({:?} {:?}, {:?}) => 0", path, path_string, buf);
+    env.mem.write(buf, fake_statfs());
+    0 // success
+}
+
+fn fstatfs(env: &mut Environment, fd: FileDescriptor, buf: MutPtr<statfs>) -> i32 {
+    if env.libc_state.posix_io.file_for_fd(fd).is_none() {
+        set_errno(env, EBADF);
+        return -1;
+    }
+    env.mem.write(buf, fake_statfs());
     0 // success
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(mkdir(_, _)), export_c_func!(fstat(_, _))];
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(mkdir(_, _)),
+    export_c_func!(stat(_, _)),
+    export_c_func!(lstat(_, _)),
+    export_c_func!(fstat(_, _)),
+    export_c_func!(statfs(_, _)),
+    export_c_func!(fstatfs(_, _)),
+];
@@ -28,13 +28,17 @@ macro_rules! check_magic {
     }
 }
 
+pub mod cond;
 pub mod key;
 pub mod mutex;
 pub mod once;
+pub mod rwlock;
 pub mod thread;
 
 #[derive(Default)]
 pub struct State {
+    cond: cond::State,
     key: key::State,
+    pub rwlock: rwlock::State,
     thread: thread::State,
 }
@@ -0,0 +1,221 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Condition variables.
+//!
+//! Waiting and waking is done by the thread scheduler, see
+//! [Environment::wait_on_condition] and [Environment::signal_condition].
+
+use super::mutex::{mutex_id_for, pthread_mutex_t};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{EBUSY, ETIMEDOUT};
+use crate::libc::time::timespec;
+use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::Environment;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+pub struct State {
+    cond_count: CondId,
+}
+
+/// Unique identifier for condition variables.
+pub type CondId = u64;
+
+/// Apple's implementation is a 4-byte magic number followed by a 4-byte opaque
+/// region. We only have to match the size theirs has.
+#[repr(C, packed)]
+pub struct pthread_condattr_t {
+    /// Magic number (must be [MAGIC_CONDATTR])
+    magic: u32,
+    _unused: [u8; 4],
+}
+unsafe impl SafeRead for pthread_condattr_t {}
+
+/// Apple's implementation is a 4-byte magic number followed by a 24-byte
+/// opaque region. The state of waiting threads is stored on the host.
+#[repr(C, packed)]
+pub struct pthread_cond_t {
+    /// Magic number (must be [MAGIC_COND])
+    magic: u32,
+    cond_id: CondId,
+    _unused: [u32; 4],
+}
+unsafe impl SafeRead for pthread_cond_t {}
+
+/// Arbitrarily-chosen magic number for `pthread_condattr_t` (not Apple's).
+const MAGIC_CONDATTR: u32 = u32::from_be_bytes(*b"CoAt");
+/// Arbitrarily-chosen magic number for `pthread_cond_t` (not Apple's).
+const MAGIC_COND: u32 = u32::from_be_bytes(*b"COND");
+/// Magic number used by `PTHREAD_COND_INITIALIZER`. This is part of the ABI!
+const MAGIC_COND_STATIC: u32 = 0x3CB0B1BB;
+
+fn pthread_condattr_init(env: &mut Environment, attr: MutPtr<pthread_condattr_t>) -> i32 {
+    env.mem.write(
+        attr,
+        pthread_condattr_t {
+            magic: MAGIC_CONDATTR,
+            _unused: [0; 4],
+        },
+    );
+    0 // success
+}
+fn pthread_condattr_destroy(env: &mut Environment, attr: MutPtr<pthread_condattr_t>) -> i32 {
+    check_magic!(env, attr, MAGIC_CONDATTR);
+    env.mem.write(
+        attr,
+        pthread_condattr_t {
+            magic: 0,
+            _unused: [0; 4],
+        },
+    );
+    0 // success
+}
+
+fn pthread_cond_init(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    attr: ConstPtr<pthread_condattr_t>,
+) -> i32 {
+    if !attr.is_null() {
+        check_magic!(env, attr, MAGIC_CONDATTR);
+    }
+    let state = &mut env.libc_state.pthread.cond;
+    let cond_id = state.cond_count;
+    state.cond_count = state.cond_count.checked_add(1).unwrap();
+    log_dbg!("Condition variable #{} created ({:?})", cond_id, cond);
+    env.mem.write(
+        cond,
+        pthread_cond_t {
+            magic: MAGIC_COND,
+            cond_id,
+            _unused: [0; 4],
+        },
+    );
+    0 // success
+}
+
+fn cond_id_for(env: &mut Environment, cond: MutPtr<pthread_cond_t>) -> CondId {
+    let magic: u32 = env.mem.read(cond.cast());
+    // This is a statically-initialized condition variable, we need to register
+    // it, and change the magic number in the process.
+    if magic == MAGIC_COND_STATIC {
+        pthread_cond_init(env, cond, Ptr::null());
+    } else {
+        // See check_or_register_mutex().
+        assert_eq!(magic, MAGIC_COND);
+    }
+    env.mem.read(cond).cond_id
+}
+
+fn wait(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    mutex: MutPtr<pthread_mutex_t>,
+    until: Option<Instant>,
+) -> i32 {
+    let cond_id = cond_id_for(env, cond);
+    let mutex_id = mutex_id_for(env, mutex);
+    if let Err(err) = env.unlock_mutex(mutex_id) {
+        return err;
+    }
+    env.wait_on_condition(cond_id, mutex_id, until);
+    // If the wait times out, the scheduler replaces this with ETIMEDOUT.
+    0 // success
+}
+
+fn pthread_cond_wait(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    mutex: MutPtr<pthread_mutex_t>,
+) -> i32 {
+    wait(env, cond, mutex, None)
+}
+
+fn timed_wait(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    mutex: MutPtr<pthread_mutex_t>,
+    timeout: Duration,
+) -> i32 {
+    if timeout.is_zero() {
+        // Make sure the condition variable is still checked for validity.
+        cond_id_for(env, cond);
+        return ETIMEDOUT;
+    }
+    wait(env, cond, mutex, Some(Instant::now() + timeout))
+}
+
+fn pthread_cond_timedwait(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    mutex: MutPtr<pthread_mutex_t>,
+    abstime: ConstPtr<timespec>,
+) -> i32 {
+    let timespec { tv_sec, tv_nsec } = env.mem.read(abstime);
+    let deadline = UNIX_EPOCH + Duration::new(tv_sec.try_into().unwrap(), tv_nsec as u32);
+    let timeout = deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    timed_wait(env, cond, mutex, timeout)
+}
+
+fn pthread_cond_timedwait_relative_np(
+    env: &mut Environment,
+    cond: MutPtr<pthread_cond_t>,
+    mutex: MutPtr<pthread_mutex_t>,
+    reltime: ConstPtr<timespec>,
+) -> i32 {
+    let timespec { tv_sec, tv_nsec } = env.mem.read(reltime);
+    let timeout = Duration::new(tv_sec.try_into().unwrap(), tv_nsec as u32);
+    timed_wait(env, cond, mutex, timeout)
+}
+
+fn pthread_cond_signal(env: &mut Environment, cond: MutPtr<pthread_cond_t>) -> i32 {
+    let cond_id = cond_id_for(env, cond);
+    env.signal_condition(cond_id, /* broadcast: */ false);
+    0 // success
+}
+
+fn pthread_cond_broadcast(env: &mut Environment, cond: MutPtr<pthread_cond_t>) -> i32 {
+    let cond_id = cond_id_for(env, cond);
+    env.signal_condition(cond_id, /* broadcast: */ true);
+    0 // success
+}
+
+fn pthread_cond_destroy(env: &mut Environment, cond: MutPtr<pthread_cond_t>) -> i32 {
+    let magic: u32 = env.mem.read(cond.cast());
+    if magic == MAGIC_COND {
+        let cond_id = env.mem.read(cond).cond_id;
+        if env.threads_waiting_on_condition(cond_id) {
+            log_dbg!("Attempted to destroy condition variable with waiters, returning EBUSY!");
+            return EBUSY;
+        }
+    } else {
+        // Never used, so never registered.
+        assert_eq!(magic, MAGIC_COND_STATIC);
+    }
+    env.mem.write(
+        cond,
+        pthread_cond_t {
+            magic: 0,
+            cond_id: CondId::MAX,
+            _unused: [0; 4],
+        },
+    );
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(pthread_condattr_init(_)),
+    export_c_func!(pthread_condattr_destroy(_)),
+    export_c_func!(pthread_cond_init(_, _)),
+    export_c_func!(pthread_cond_wait(_, _)),
+    export_c_func!(pthread_cond_timedwait(_, _, _)),
+    export_c_func!(pthread_cond_timedwait_relative_np(_, _, _)),
+    export_c_func!(pthread_cond_signal(_)),
+    export_c_func!(pthread_cond_broadcast(_)),
+    export_c_func!(pthread_cond_destroy(_)),
+];
@@ -5,8 +5,9 @@
  */
 //! Thread-specific data keys.
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::EINVAL;
 use crate::mem::{ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
@@ -15,8 +16,8 @@ use std::collections::HashMap;
 pub struct State {
     /// The `pthread_key_t` value, with 1 subtracted, is the index into this
     /// vector. The tuple contains the map of thread-specific data pointers plus
-    /// the destructor pointer.
-    keys: Vec<(HashMap<ThreadId, MutVoidPtr>, GuestFunction)>,
+    /// the destructor pointer. Deleted keys are [None].
+    keys: Vec<Option<(HashMap<ThreadId, MutVoidPtr>, GuestFunction)>>,
 }
 
 fn get_state(env: &mut Environment) -> &mut State {
@@ -25,6 +26,10 @@ fn get_state(env: &mut Environment) -> &mut State {
 
 type pthread_key_t = u32;
 
+/// Maximum number of times destructors are run on thread exit, in case they
+/// set new values.
+const PTHREAD_DESTRUCTOR_ITERATIONS: usize = 4;
+
 fn pthread_key_create(
     env: &mut Environment,
     key_ptr: MutPtr<pthread_key_t>,
@@ -32,16 +37,32 @@ fn pthread_key_create(
 ) -> i32 {
     let idx = get_state(env).keys.len();
     let key: pthread_key_t = (idx + 1).try_into().unwrap();
-    get_state(env).keys.push((HashMap::new(), destructor));
+    get_state(env).keys.push(Some((HashMap::new(), destructor)));
     env.mem.write(key_ptr, key);
     0 // success
 }
 
+fn pthread_key_delete(env: &mut Environment, key: pthread_key_t) -> i32 {
+    let Some(idx) = key.checked_sub(1) else {
+        return EINVAL;
+    };
+    // Destructors are not called for the remaining values.
+    match get_state(env).keys.get_mut(idx as usize) {
+        Some(slot @ Some(_)) => {
+            *slot = None;
+            0 // success
+        }
+        _ => EINVAL,
+    }
+}
+
 fn pthread_getspecific(env: &mut Environment, key: pthread_key_t) -> MutVoidPtr {
     // Use of invalid key is undefined, panicking is fine.
     let idx: usize = key.checked_sub(1).unwrap().try_into().unwrap();
     let current_thread = env.current_thread;
     get_state(env).keys[idx]
+        .as_ref()
+        .unwrap()
         .0
         .get(&current_thread)
         .copied()
@@ -53,13 +74,55 @@ fn pthread_setspecific(env: &mut Environment, key: pthread_key_t, value: ConstVo
     let idx: usize = key.checked_sub(1).unwrap().try_into().unwrap();
     let current_thread = env.current_thread;
     get_state(env).keys[idx]
+        .as_mut()
+        .unwrap()
         .0
         .insert(current_thread, value.cast_mut());
     0 // success
 }
 
+/// Called when a thread exits, to run the destructors for its thread-specific
+/// data and then forget it. This must be called on the exiting thread.
+pub fn run_destructors(env: &mut Environment) {
+    let current_thread = env.current_thread;
+    for _ in 0..PTHREAD_DESTRUCTOR_ITERATIONS {
+        // Values are set to NULL before the destructor is called, so that
+        // setting a new value from a destructor can be detected.
+        let mut to_destroy = Vec::new();
+        for (data, destructor) in get_state(env).keys.iter_mut().flatten() {
+            let Some(value) = data.remove(&current_thread) else {
+                continue;
+            };
+            if !value.is_null() && !destructor.to_ptr().is_null() {
+                to_destroy.push((*destructor, value));
+            }
+        }
+        if to_destroy.is_empty() {
+            return;
+        }
+        for (destructor, value) in to_destroy {
+            log_dbg!(
+                "Thread {} exiting, calling destructor {:?} with {:?}",
+                current_thread,
+                destructor,
+                value
+            );
+            () = destructor.call_from_host(env, (value,));
+        }
+    }
+    log!(
+        "Warning: thread {} still has thread-specific data after {} rounds of destructors",
+        current_thread,
+        PTHREAD_DESTRUCTOR_ITERATIONS
+    );
+    for (data, _) in get_state(env).keys.iter_mut().flatten() {
+        data.remove(&current_thread);
+    }
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(pthread_key_create(_, _)),
+    export_c_func!(pthread_key_delete(_)),
     export_c_func!(pthread_getspecific(_)),
     export_c_func!(pthread_setspecific(_, _)),
 ];
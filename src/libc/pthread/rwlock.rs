@@ -0,0 +1,239 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Read-write locks.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{EBUSY, EDEADLK, EPERM};
+use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::{Environment, ThreadId};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Accessed by the thread scheduler, see [Environment::block_on_rwlock].
+    pub rwlocks: HashMap<RwLockId, RwLockHostObject>,
+    rwlock_count: RwLockId,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.pthread.rwlock
+    }
+}
+
+/// Unique identifier for read-write locks.
+pub type RwLockId = u64;
+
+#[derive(Default)]
+pub struct RwLockHostObject {
+    /// Threads holding a read lock. A thread can appear more than once.
+    readers: Vec<ThreadId>,
+    writer: Option<ThreadId>,
+}
+impl RwLockHostObject {
+    /// Try to lock for reading or writing, returning [true] on success.
+    pub fn try_lock(&mut self, thread: ThreadId, write: bool) -> bool {
+        if self.writer.is_some() {
+            return false;
+        }
+        if write {
+            if !self.readers.is_empty() {
+                return false;
+            }
+            self.writer = Some(thread);
+        } else {
+            self.readers.push(thread);
+        }
+        true
+    }
+}
+
+/// Apple's implementation is a 4-byte magic number followed by a 12-byte
+/// opaque region. We only have to match the size theirs has.
+#[repr(C, packed)]
+pub struct pthread_rwlockattr_t {
+    /// Magic number (must be [MAGIC_RWLOCKATTR])
+    magic: u32,
+    _unused: [u32; 3],
+}
+unsafe impl SafeRead for pthread_rwlockattr_t {}
+
+/// Apple's implementation is a 4-byte magic number followed by a 124-byte
+/// opaque region. We will store the actual data on the host, determined by a
+/// lock identifier.
+#[repr(C, packed)]
+pub struct pthread_rwlock_t {
+    /// Magic number (must be [MAGIC_RWLOCK])
+    magic: u32,
+    rwlock_id: RwLockId,
+    _unused: [u32; 29],
+}
+unsafe impl SafeRead for pthread_rwlock_t {}
+
+/// Arbitrarily-chosen magic number for `pthread_rwlockattr_t` (not Apple's).
+const MAGIC_RWLOCKATTR: u32 = u32::from_be_bytes(*b"RwAt");
+/// Arbitrarily-chosen magic number for `pthread_rwlock_t` (not Apple's).
+const MAGIC_RWLOCK: u32 = u32::from_be_bytes(*b"RWLK");
+/// Magic number used by `PTHREAD_RWLOCK_INITIALIZER`. This is part of the ABI!
+const MAGIC_RWLOCK_STATIC: u32 = 0x2DA8B3B4;
+
+fn pthread_rwlockattr_init(env: &mut Environment, attr: MutPtr<pthread_rwlockattr_t>) -> i32 {
+    env.mem.write(
+        attr,
+        pthread_rwlockattr_t {
+            magic: MAGIC_RWLOCKATTR,
+            _unused: [0; 3],
+        },
+    );
+    0 // success
+}
+fn pthread_rwlockattr_destroy(env: &mut Environment, attr: MutPtr<pthread_rwlockattr_t>) -> i32 {
+    check_magic!(env, attr, MAGIC_RWLOCKATTR);
+    env.mem.write(
+        attr,
+        pthread_rwlockattr_t {
+            magic: 0,
+            _unused: [0; 3],
+        },
+    );
+    0 // success
+}
+
+fn pthread_rwlock_init(
+    env: &mut Environment,
+    rwlock: MutPtr<pthread_rwlock_t>,
+    attr: ConstPtr<pthread_rwlockattr_t>,
+) -> i32 {
+    if !attr.is_null() {
+        check_magic!(env, attr, MAGIC_RWLOCKATTR);
+    }
+    let state = State::get_mut(env);
+    let rwlock_id = state.rwlock_count;
+    state.rwlock_count = state.rwlock_count.checked_add(1).unwrap();
+    state.rwlocks.insert(rwlock_id, Default::default());
+    log_dbg!("Read-write lock #{} created ({:?})", rwlock_id, rwlock);
+    env.mem.write(
+        rwlock,
+        pthread_rwlock_t {
+            magic: MAGIC_RWLOCK,
+            rwlock_id,
+            _unused: [0; 29],
+        },
+    );
+    0 // success
+}
+
+fn rwlock_id_for(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> RwLockId {
+    let magic: u32 = env.mem.read(rwlock.cast());
+    // This is a statically-initialized lock, we need to register it, and
+    // change the magic number in the process.
+    if magic == MAGIC_RWLOCK_STATIC {
+        pthread_rwlock_init(env, rwlock, Ptr::null());
+    } else {
+        // See check_or_register_mutex().
+        assert_eq!(magic, MAGIC_RWLOCK);
+    }
+    env.mem.read(rwlock).rwlock_id
+}
+
+/// Shared implementation of the lock functions. `block` is [false] for the
+/// `try` variants.
+fn lock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>, write: bool, block: bool) -> i32 {
+    let rwlock_id = rwlock_id_for(env, rwlock);
+    let current_thread = env.current_thread;
+    let host_obj = State::get_mut(env).rwlocks.get_mut(&rwlock_id).unwrap();
+    if host_obj.writer == Some(current_thread)
+        || (write && host_obj.readers.contains(&current_thread))
+    {
+        log_dbg!(
+            "Thread {} would deadlock on read-write lock #{}, returning EDEADLK!",
+            current_thread,
+            rwlock_id
+        );
+        return EDEADLK;
+    }
+    if host_obj.try_lock(current_thread, write) {
+        log_dbg!(
+            "Thread {} locked read-write lock #{} (write: {}).",
+            current_thread,
+            rwlock_id,
+            write
+        );
+        0 // success
+    } else if block {
+        env.block_on_rwlock(rwlock_id, write);
+        0 // success
+    } else {
+        EBUSY
+    }
+}
+
+fn pthread_rwlock_rdlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock(env, rwlock, false, true)
+}
+fn pthread_rwlock_tryrdlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock(env, rwlock, false, false)
+}
+fn pthread_rwlock_wrlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock(env, rwlock, true, true)
+}
+fn pthread_rwlock_trywrlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock(env, rwlock, true, false)
+}
+
+fn pthread_rwlock_unlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    let rwlock_id = rwlock_id_for(env, rwlock);
+    let current_thread = env.current_thread;
+    let host_obj = State::get_mut(env).rwlocks.get_mut(&rwlock_id).unwrap();
+    if host_obj.writer == Some(current_thread) {
+        host_obj.writer = None;
+    } else if let Some(idx) = host_obj.readers.iter().position(|&t| t == current_thread) {
+        host_obj.readers.swap_remove(idx);
+    } else {
+        log_dbg!(
+            "Thread {} doesn't hold read-write lock #{}, returning EPERM!",
+            current_thread,
+            rwlock_id
+        );
+        return EPERM;
+    }
+    log_dbg!(
+        "Thread {} unlocked read-write lock #{}.",
+        current_thread,
+        rwlock_id
+    );
+    0 // success
+}
+
+fn pthread_rwlock_destroy(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    let rwlock_id = rwlock_id_for(env, rwlock);
+    let host_obj = State::get_mut(env).rwlocks.get(&rwlock_id).unwrap();
+    if host_obj.writer.is_some() || !host_obj.readers.is_empty() {
+        log_dbg!("Attempted to destroy currently locked read-write lock, returning EBUSY!");
+        return EBUSY;
+    }
+    State::get_mut(env).rwlocks.remove(&rwlock_id);
+    env.mem.write(
+        rwlock,
+        pthread_rwlock_t {
+            magic: 0,
+            rwlock_id: RwLockId::MAX,
+            _unused: [0; 29],
+        },
+    );
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(pthread_rwlockattr_init(_)),
+    export_c_func!(pthread_rwlockattr_destroy(_)),
+    export_c_func!(pthread_rwlock_init(_, _)),
+    export_c_func!(pthread_rwlock_rdlock(_)),
+    export_c_func!(pthread_rwlock_tryrdlock(_)),
+    export_c_func!(pthread_rwlock_wrlock(_)),
+    export_c_func!(pthread_rwlock_trywrlock(_)),
+    export_c_func!(pthread_rwlock_unlock(_)),
+    export_c_func!(pthread_rwlock_destroy(_)),
+];
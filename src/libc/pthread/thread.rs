@@ -7,8 +7,8 @@
 
 use crate::abi::GuestFunction;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::libc::errno::{EDEADLK, EINVAL};
-use crate::mem::{ConstPtr, MutPtr, MutVoidPtr, SafeRead};
+use crate::libc::errno::{EDEADLK, EINVAL, ESRCH};
+use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, SafeRead};
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -31,14 +31,16 @@ pub struct pthread_attr_t {
     /// Magic number (must be [MAGIC_ATTR])
     magic: u32,
     detachstate: i32,
-    _unused: [u32; 8],
+    stacksize: GuestUSize,
+    _unused: [u32; 7],
 }
 unsafe impl SafeRead for pthread_attr_t {}
 
 const DEFAULT_ATTR: pthread_attr_t = pthread_attr_t {
     magic: MAGIC_ATTR,
     detachstate: PTHREAD_CREATE_JOINABLE,
-    _unused: [0; 8],
+    stacksize: Mem::SECONDARY_THREAD_STACK_SIZE,
+    _unused: [0; 7],
 };
 
 /// Apple's implementation is a 4-byte magic number followed by a massive
@@ -55,7 +57,7 @@ pub type pthread_t = MutPtr<OpaqueThread>;
 struct ThreadHostObject {
     thread_id: ThreadId,
     joined_by: Option<ThreadId>,
-    _attr: pthread_attr_t,
+    attr: pthread_attr_t,
 }
 
 /// Arbitrarily-chosen magic number for `pthread_attr_t` (not Apple's).
@@ -68,6 +70,9 @@ type DetachState = i32;
 const PTHREAD_CREATE_JOINABLE: DetachState = 1;
 pub const PTHREAD_CREATE_DETACHED: DetachState = 2;
 
+const PTHREAD_STACK_MIN: GuestUSize = 0x4000;
+const PAGE_SIZE: GuestUSize = 0x1000;
+
 pub fn pthread_attr_init(env: &mut Environment, attr: MutPtr<pthread_attr_t>) -> i32 {
     env.mem.write(attr, DEFAULT_ATTR);
     0 // success
@@ -84,6 +89,45 @@ pub fn pthread_attr_setdetachstate(
     env.mem.write(attr, attr_copy);
     0 // success
 }
+fn pthread_attr_getdetachstate(
+    env: &mut Environment,
+    attr: ConstPtr<pthread_attr_t>,
+    detachstate: MutPtr<DetachState>,
+) -> i32 {
+    check_magic!(env, attr, MAGIC_ATTR);
+    let attr = env.mem.read(attr);
+    env.mem.write(detachstate, attr.detachstate);
+    0 // success
+}
+fn pthread_attr_setstacksize(
+    env: &mut Environment,
+    attr: MutPtr<pthread_attr_t>,
+    stacksize: GuestUSize,
+) -> i32 {
+    check_magic!(env, attr, MAGIC_ATTR);
+    if stacksize < PTHREAD_STACK_MIN || stacksize & (PAGE_SIZE - 1) != 0 {
+        log_dbg!(
+            "pthread_attr_setstacksize({:?}, {:#x}): invalid size, returning EINVAL!",
+            attr,
+            stacksize
+        );
+        return EINVAL;
+    }
+    let mut attr_copy = env.mem.read(attr);
+    attr_copy.stacksize = stacksize;
+    env.mem.write(attr, attr_copy);
+    0 // success
+}
+fn pthread_attr_getstacksize(
+    env: &mut Environment,
+    attr: ConstPtr<pthread_attr_t>,
+    stacksize: MutPtr<GuestUSize>,
+) -> i32 {
+    check_magic!(env, attr, MAGIC_ATTR);
+    let attr = env.mem.read(attr);
+    env.mem.write(stacksize, attr.stacksize);
+    0 // success
+}
 fn pthread_attr_destroy(env: &mut Environment, attr: MutPtr<pthread_attr_t>) -> i32 {
     check_magic!(env, attr, MAGIC_ATTR);
     env.mem.write(
@@ -91,6 +135,7 @@ fn pthread_attr_destroy(env: &mut Environment, attr: MutPtr<pthread_attr_t>) ->
         pthread_attr_t {
             magic: 0,
             detachstate: 0,
+            stacksize: 0,
             _unused: Default::default(),
         },
     );
@@ -111,7 +156,7 @@ pub fn pthread_create(
         DEFAULT_ATTR
     };
 
-    let thread_id = env.new_thread(start_routine, user_data);
+    let thread_id = env.new_thread(start_routine, user_data, attr.stacksize);
 
     let opaque = env.mem.alloc_and_write(OpaqueThread {
         magic: MAGIC_THREAD,
@@ -124,7 +169,7 @@ pub fn pthread_create(
         ThreadHostObject {
             thread_id,
             joined_by: None,
-            _attr: attr,
+            attr,
         },
     );
 
@@ -151,7 +196,7 @@ fn pthread_self(env: &mut Environment) -> pthread_t {
             ThreadHostObject {
                 thread_id: 0,
                 joined_by: None,
-                _attr: DEFAULT_ATTR,
+                attr: DEFAULT_ATTR,
             },
         );
         log_dbg!(
@@ -206,7 +251,7 @@ fn pthread_join(env: &mut Environment, thread: pthread_t, retval: MutPtr<MutVoid
 
     // Deattached threads cannot be joined with.
     let host_obj_joinee = State::get(env).threads.get_mut(&thread).unwrap();
-    if host_obj_joinee._attr.detachstate == PTHREAD_CREATE_DETACHED {
+    if host_obj_joinee.attr.detachstate == PTHREAD_CREATE_DETACHED {
         log_dbg!("Thread attempted join with deattached thread, returning EINVAL!");
         return EINVAL;
     }
@@ -217,6 +262,28 @@ fn pthread_join(env: &mut Environment, thread: pthread_t, retval: MutPtr<MutVoid
     env.join_with_thread(joinee_thread, retval);
     0
 }
+fn pthread_detach(env: &mut Environment, thread: pthread_t) -> i32 {
+    let Some(host_obj) = State::get(env).threads.get_mut(&thread) else {
+        log_dbg!(
+            "pthread_detach({:?}): unknown thread, returning ESRCH!",
+            thread
+        );
+        return ESRCH;
+    };
+    if host_obj.attr.detachstate == PTHREAD_CREATE_DETACHED || host_obj.joined_by.is_some() {
+        log_dbg!(
+            "pthread_detach({:?}): already detached or joined, returning EINVAL!",
+            thread
+        );
+        return EINVAL;
+    }
+    // TODO: Clean up the host object once the thread finishes. Joinable
+    // threads' objects aren't cleaned up either currently.
+    host_obj.attr.detachstate = PTHREAD_CREATE_DETACHED;
+    log_dbg!("pthread_detach({:?}) => 0", thread);
+    0 // success
+}
+
 fn pthread_setcanceltype(_env: &mut Environment, _type: i32, _oldtype: MutPtr<i32>) -> i32 {
     // TODO
     0
@@ -234,10 +301,14 @@ fn pthread_mach_thread_np(env: &mut Environment, thread: pthread_t) -> mach_port
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(pthread_attr_init(_)),
     export_c_func!(pthread_attr_setdetachstate(_, _)),
+    export_c_func!(pthread_attr_getdetachstate(_, _)),
+    export_c_func!(pthread_attr_setstacksize(_, _)),
+    export_c_func!(pthread_attr_getstacksize(_, _)),
     export_c_func!(pthread_attr_destroy(_)),
     export_c_func!(pthread_create(_, _, _, _)),
     export_c_func!(pthread_self()),
     export_c_func!(pthread_join(_, _)),
+    export_c_func!(pthread_detach(_)),
     export_c_func!(pthread_setcanceltype(_, _)),
     export_c_func!(pthread_mach_thread_np(_)),
 ];
@@ -6,6 +6,7 @@
 //! `semaphore.h`
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EEXIST, ENOENT};
 use crate::libc::posix_io::stat::mode_t;
 use crate::libc::posix_io::{O_CREAT, O_EXCL};
 use crate::mem::{ConstPtr, MutPtr};
@@ -52,7 +53,7 @@ fn sem_open(
     let host_sem_rc =
         if let Some(existing_host_sem_rc) = State::get(env).named_semaphores.get(sem_name) {
             if (oflag & O_EXCL) == 0 {
-                // TODO: set errno
+                set_errno(env, EEXIST);
                 return SEM_FAILED;
             }
             let existing_host_sem = (*existing_host_sem_rc).borrow();
@@ -62,7 +63,7 @@ fn sem_open(
             existing_host_sem_rc.clone()
         } else {
             if (oflag & O_CREAT) == 0 {
-                // TODO: set errno
+                set_errno(env, ENOENT);
                 return SEM_FAILED;
             }
             let host_sem_rc = Rc::new(RefCell::new(SemaphoreHostObject {
@@ -83,6 +84,20 @@ fn sem_open(
     sem
 }
 
+/// Create a semaphore that has no name, for other semaphore APIs to build on
+/// (see [super::mach_semaphore]). It can be used with [sem_post] etc, and must
+/// be destroyed with [sem_close].
+pub(super) fn open_unnamed(env: &mut Environment, value: i32) -> MutPtr<sem_t> {
+    let sem = env.mem.alloc_and_write(0);
+    let host_sem_rc = Rc::new(RefCell::new(SemaphoreHostObject {
+        value,
+        waiting: HashSet::new(),
+        guest_sem: Some(sem),
+    }));
+    State::get_mut(env).open_semaphores.insert(sem, host_sem_rc);
+    sem
+}
+
 fn sem_post(env: &mut Environment, sem: MutPtr<sem_t>) -> i32 {
     env.sem_increment(sem);
     0 // success
@@ -101,7 +116,7 @@ fn sem_trywait(env: &mut Environment, sem: MutPtr<sem_t>) -> i32 {
     }
 }
 
-fn sem_close(env: &mut Environment, sem: MutPtr<sem_t>) -> i32 {
+pub(super) fn sem_close(env: &mut Environment, sem: MutPtr<sem_t>) -> i32 {
     let host_sem_rc = env
         .libc_state
         .semaphore
@@ -5,11 +5,11 @@
  */
 //! `setjmp.h`.
 //!
-//! We don't have a real implementation for this right now. It could be quite
-//! tricky to write one, considering that we would need to unwind through host
-//! code, and somehow do so selectively since we have a mix of stack frames from
-//! different guest threads. For the moment, we simply pray the app never throws
-//! exceptions.
+//! We don't have a real implementation for this right now. `longjmp()` would
+//! need to unwind through host code, and somehow do so selectively since we
+//! have a mix of stack frames from different guest threads.
+//! [Environment::long_jump] can do this, and is used for exception unwinding
+//! (see [crate::libc::unwind]), but it's not yet hooked up here.
 //!
 //! Note that `setjmp` and `longjmp` are defined as macros in the C standard,
 //! but it seems like the implementation of these on iPhone OS uses real
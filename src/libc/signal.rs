@@ -3,15 +3,436 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! `signal.h`
+//!
+//! There are no other processes, so the only signals are the ones the app
+//! raises itself, and the ones touchHLE synthesizes when the app crashes (see
+//! [deliver_fault_signal]). Signals are delivered synchronously to the thread
+//! that raised them.
 
-use crate::dyld::FunctionExports;
+#![allow(non_camel_case_types)]
+
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
 use crate::environment::Environment;
-use crate::export_c_func;
-use crate::mem::{ConstVoidPtr, MutVoidPtr};
+use crate::libc::errno::{set_errno, EINVAL, ESRCH};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::ThreadId;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Actions installed with [sigaction] or [signal]. Other signals have the
+    /// default action.
+    actions: HashMap<i32, sigaction>,
+    /// Each thread's signal mask.
+    masks: HashMap<ThreadId, sigset_t>,
+    /// Signals that were raised while blocked, for each thread.
+    pending: HashMap<ThreadId, sigset_t>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.signal
+    }
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGBUS: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGURG: i32 = 16;
+pub const SIGSTOP: i32 = 17;
+pub const SIGCONT: i32 = 19;
+pub const SIGCHLD: i32 = 20;
+pub const SIGIO: i32 = 23;
+pub const SIGWINCH: i32 = 28;
+pub const SIGINFO: i32 = 29;
+pub const SIGUSR1: i32 = 30;
+pub const SIGUSR2: i32 = 31;
+/// One more than the highest signal number.
+const NSIG: i32 = 32;
+
+fn signal_name(sig: i32) -> &'static str {
+    match sig {
+        SIGHUP => "SIGHUP",
+        SIGINT => "SIGINT",
+        SIGQUIT => "SIGQUIT",
+        SIGILL => "SIGILL",
+        SIGTRAP => "SIGTRAP",
+        SIGABRT => "SIGABRT",
+        SIGFPE => "SIGFPE",
+        SIGKILL => "SIGKILL",
+        SIGBUS => "SIGBUS",
+        SIGSEGV => "SIGSEGV",
+        SIGPIPE => "SIGPIPE",
+        SIGALRM => "SIGALRM",
+        SIGTERM => "SIGTERM",
+        SIGUSR1 => "SIGUSR1",
+        SIGUSR2 => "SIGUSR2",
+        _ => "signal",
+    }
+}
+
+type sigset_t = u32;
+
+fn sigmask(sig: i32) -> sigset_t {
+    1 << (sig - 1)
+}
+
+/// `SIG_DFL`, as a handler address.
+const SIG_DFL: u32 = 0;
+/// `SIG_IGN`, as a handler address.
+const SIG_IGN: u32 = 1;
+/// `SIG_ERR`, as a handler address.
+const SIG_ERR: u32 = u32::MAX;
+
+const SA_RESETHAND: i32 = 0x4;
+const SA_NODEFER: i32 = 0x10;
+const SA_SIGINFO: i32 = 0x40;
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct sigaction {
+    /// `sa_handler` or `sa_sigaction`, depending on [SA_SIGINFO].
+    handler: GuestFunction,
+    sa_mask: sigset_t,
+    sa_flags: i32,
+}
+unsafe impl SafeRead for sigaction {}
+
+#[repr(C, packed)]
+struct siginfo_t {
+    si_signo: i32,
+    si_errno: i32,
+    si_code: i32,
+    si_pid: i32,
+    si_uid: u32,
+    si_status: i32,
+    si_addr: MutVoidPtr,
+    si_value: u32,
+    si_band: i32,
+    _pad: [u32; 7],
+}
+unsafe impl SafeRead for siginfo_t {}
+
+fn is_valid(sig: i32) -> bool {
+    (1..NSIG).contains(&sig)
+}
+
+fn action_for(env: &mut Environment, sig: i32) -> sigaction {
+    State::get_mut(env)
+        .actions
+        .get(&sig)
+        .copied()
+        .unwrap_or(sigaction {
+            handler: GuestFunction::from_addr_with_thumb_bit(SIG_DFL),
+            sa_mask: 0,
+            sa_flags: 0,
+        })
+}
+
+fn current_mask(env: &mut Environment) -> sigset_t {
+    let current_thread = env.current_thread;
+    State::get_mut(env)
+        .masks
+        .get(&current_thread)
+        .copied()
+        .unwrap_or(0)
+}
+
+fn set_current_mask(env: &mut Environment, mask: sigset_t) {
+    // SIGKILL and SIGSTOP can't be blocked.
+    let mask = mask & !(sigmask(SIGKILL) | sigmask(SIGSTOP));
+    let current_thread = env.current_thread;
+    State::get_mut(env).masks.insert(current_thread, mask);
+}
+
+/// Run the handler or the default action for a signal on the current thread.
+/// `si_addr` is used for `siginfo_t` if the handler wants one.
+fn deliver(env: &mut Environment, sig: i32, si_addr: MutVoidPtr) {
+    let action = action_for(env, sig);
+    let handler = action.handler;
+    if handler.addr_with_thumb_bit() == SIG_IGN {
+        log_dbg!("Ignoring {} ({})", signal_name(sig), sig);
+        return;
+    }
+    if handler.addr_with_thumb_bit() == SIG_DFL {
+        match sig {
+            SIGURG | SIGCONT | SIGCHLD | SIGIO | SIGWINCH | SIGINFO => {
+                log_dbg!("Ignoring {} ({}) by default", signal_name(sig), sig);
+                return;
+            }
+            _ => panic!(
+                "App terminated by {} ({}), which has no handler.",
+                signal_name(sig),
+                sig
+            ),
+        }
+    }
+
+    log!(
+        "Delivering {} ({}) to handler {:?}",
+        signal_name(sig),
+        sig,
+        handler
+    );
+    let sa_flags = action.sa_flags;
+    if sa_flags & SA_RESETHAND != 0 {
+        State::get_mut(env).actions.remove(&sig);
+    }
+    let old_mask = current_mask(env);
+    let mut handler_mask = old_mask | action.sa_mask;
+    if sa_flags & SA_NODEFER == 0 {
+        handler_mask |= sigmask(sig);
+    }
+    set_current_mask(env, handler_mask);
+
+    if sa_flags & SA_SIGINFO != 0 {
+        let info = env.mem.alloc_and_write(siginfo_t {
+            si_signo: sig,
+            si_errno: 0,
+            si_code: 0,
+            si_pid: 1, // see getpid()
+            si_uid: 0,
+            si_status: 0,
+            si_addr,
+            si_value: 0,
+            si_band: 0,
+            _pad: [0; 7],
+        });
+        // TODO: ucontext_t with the register state
+        let context: MutVoidPtr = Ptr::null();
+        () = handler.call_from_host(env, (sig, info, context));
+        env.mem.free(info.cast());
+    } else {
+        () = handler.call_from_host(env, (sig,));
+    }
 
-fn sigaction(_env: &mut Environment, signum: i32, act: ConstVoidPtr, oldact: MutVoidPtr) -> i32 {
-    log!("TODO: sigaction({:?}, {:?}, {:?})", signum, act, oldact);
+    set_current_mask(env, old_mask);
+}
+
+/// Deliver pending signals that are no longer blocked.
+fn deliver_pending(env: &mut Environment) {
+    let current_thread = env.current_thread;
+    loop {
+        let mask = current_mask(env);
+        let pending = State::get_mut(env)
+            .pending
+            .entry(current_thread)
+            .or_default();
+        let deliverable = *pending & !mask;
+        if deliverable == 0 {
+            return;
+        }
+        let sig = deliverable.trailing_zeros() as i32 + 1;
+        *pending &= !sigmask(sig);
+        deliver(env, sig, Ptr::null());
+    }
+}
+
+/// Raise a signal on the current thread. If it is blocked, it is delivered
+/// once it is unblocked.
+pub fn raise(env: &mut Environment, sig: i32) -> i32 {
+    if !is_valid(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    log_dbg!("raise({})", sig);
+    if current_mask(env) & sigmask(sig) != 0 {
+        let current_thread = env.current_thread;
+        *State::get_mut(env)
+            .pending
+            .entry(current_thread)
+            .or_default() |= sigmask(sig);
+        return 0;
+    }
+    deliver(env, sig, Ptr::null());
+    0 // success
+}
+
+/// Called by the thread scheduler when the guest CPU has a fatal error, e.g.
+/// a bad memory access (`SIGSEGV`) or an undefined instruction (`SIGILL`). If
+/// the app has a handler for the signal, and it isn't blocked, it is run, e.g.
+/// so that the app's crash reporter can log something. touchHLE can't resume
+/// execution at the faulting instruction, so afterwards the caller should stop
+/// as if there was no handler.
+pub fn deliver_fault_signal(env: &mut Environment, sig: i32) {
+    let handler = action_for(env, sig).handler.addr_with_thumb_bit();
+    if handler == SIG_DFL || handler == SIG_IGN || current_mask(env) & sigmask(sig) != 0 {
+        return;
+    }
+    // The handler might clobber any register, and it's useful to have the
+    // original state for debugging.
+    let regs = *env.cpu.regs();
+    let cpsr = env.cpu.cpsr();
+    // TODO: fault address (it isn't known for most faults)
+    deliver(env, sig, Ptr::null());
+    *env.cpu.regs_mut() = regs;
+    env.cpu.set_cpsr(cpsr);
+    echo!(
+        "The app's {} handler returned, but execution can't continue after the fault.",
+        signal_name(sig)
+    );
+}
+
+/// Returns the previous handler (a function pointer, hence [ConstVoidPtr]).
+fn signal(env: &mut Environment, sig: i32, handler: GuestFunction) -> ConstVoidPtr {
+    if !is_valid(sig) || sig == SIGKILL || sig == SIGSTOP {
+        set_errno(env, EINVAL);
+        return Ptr::from_bits(SIG_ERR);
+    }
+    let old_handler = action_for(env, sig).handler;
+    log_dbg!("signal({}, {:?}) => {:?}", sig, handler, old_handler);
+    State::get_mut(env).actions.insert(
+        sig,
+        sigaction {
+            handler,
+            sa_mask: 0,
+            sa_flags: 0,
+        },
+    );
+    old_handler.to_ptr()
+}
+
+fn sigaction(
+    env: &mut Environment,
+    sig: i32,
+    act: ConstPtr<sigaction>,
+    oldact: MutPtr<sigaction>,
+) -> i32 {
+    if !is_valid(sig) || (!act.is_null() && (sig == SIGKILL || sig == SIGSTOP)) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    if !oldact.is_null() {
+        let old_action = action_for(env, sig);
+        env.mem.write(oldact, old_action);
+    }
+    if !act.is_null() {
+        let action = env.mem.read(act);
+        log_dbg!(
+            "sigaction({}, {:?} (handler {:?}, flags {:#x}), {:?})",
+            sig,
+            act,
+            { action.handler },
+            { action.sa_flags },
+            oldact
+        );
+        State::get_mut(env).actions.insert(sig, action);
+    }
+    0 // success
+}
+
+const SIG_BLOCK: i32 = 1;
+const SIG_UNBLOCK: i32 = 2;
+const SIG_SETMASK: i32 = 3;
+
+fn sigprocmask(
+    env: &mut Environment,
+    how: i32,
+    set: ConstPtr<sigset_t>,
+    oldset: MutPtr<sigset_t>,
+) -> i32 {
+    let old_mask = current_mask(env);
+    if !set.is_null() {
+        let set = env.mem.read(set);
+        let new_mask = match how {
+            SIG_BLOCK => old_mask | set,
+            SIG_UNBLOCK => old_mask & !set,
+            SIG_SETMASK => set,
+            _ => {
+                set_errno(env, EINVAL);
+                return -1;
+            }
+        };
+        set_current_mask(env, new_mask);
+    }
+    if !oldset.is_null() {
+        env.mem.write(oldset, old_mask);
+    }
+    deliver_pending(env);
+    0 // success
+}
+
+fn pthread_sigmask(
+    env: &mut Environment,
+    how: i32,
+    set: ConstPtr<sigset_t>,
+    oldset: MutPtr<sigset_t>,
+) -> i32 {
+    // Signal masks are already per-thread.
+    match sigprocmask(env, how, set, oldset) {
+        0 => 0,
+        _ => EINVAL,
+    }
+}
+
+fn kill(env: &mut Environment, pid: i32, sig: i32) -> i32 {
+    // See getpid(). There are no other processes.
+    if pid != 1 {
+        log!("TODO: kill({}, {})", pid, sig);
+        set_errno(env, ESRCH);
+        return -1;
+    }
+    if sig == 0 {
+        return 0;
+    }
+    raise(env, sig)
+}
+
+fn sigemptyset(env: &mut Environment, set: MutPtr<sigset_t>) -> i32 {
+    env.mem.write(set, 0);
     0
 }
+fn sigfillset(env: &mut Environment, set: MutPtr<sigset_t>) -> i32 {
+    env.mem.write(set, !0);
+    0
+}
+fn sigaddset(env: &mut Environment, set: MutPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    let value = env.mem.read(set);
+    env.mem.write(set, value | sigmask(sig));
+    0
+}
+fn sigdelset(env: &mut Environment, set: MutPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    let value = env.mem.read(set);
+    env.mem.write(set, value & !sigmask(sig));
+    0
+}
+fn sigismember(env: &mut Environment, set: ConstPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    (env.mem.read(set) & sigmask(sig) != 0).into()
+}
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(sigaction(_, _, _))];
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(signal(_, _)),
+    export_c_func!(sigaction(_, _, _)),
+    export_c_func!(raise(_)),
+    export_c_func!(kill(_, _)),
+    export_c_func!(sigprocmask(_, _, _)),
+    export_c_func!(pthread_sigmask(_, _, _)),
+    export_c_func!(sigemptyset(_)),
+    export_c_func!(sigfillset(_)),
+    export_c_func!(sigaddset(_, _)),
+    export_c_func!(sigdelset(_, _)),
+    export_c_func!(sigismember(_, _)),
+];
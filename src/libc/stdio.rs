@@ -11,6 +11,7 @@ use super::posix_io::{
 };
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
 use crate::fs::GuestPath;
+use crate::libc::errno::{set_errno, EFAULT, ENOENT, ENOTEMPTY};
 use crate::libc::string::strlen;
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::Environment;
@@ -275,7 +276,7 @@ fn putchar(_env: &mut Environment, c: u8) -> i32 {
 
 fn remove(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
     if Ptr::is_null(path) {
-        // TODO: set errno
+        set_errno(env, EFAULT);
         log!("remove({:?}) => -1, attempted to remove null", path);
         return -1;
     }
@@ -289,7 +290,11 @@ fn remove(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
             0
         }
         Err(_) => {
-            // TODO: set errno
+            let exists = env
+                .fs
+                .exists(GuestPath::new(env.mem.cstr_at_utf8(path).unwrap()));
+            // A directory must be empty to be removed.
+            set_errno(env, if exists { ENOTEMPTY } else { ENOENT });
             log!("Warning: remove({:?}) failed, returning -1", path);
             -1
         }
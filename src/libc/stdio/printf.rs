@@ -8,12 +8,12 @@
 use crate::abi::{DotDotDot, VaList};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::foundation::{ns_string, unichar};
-use crate::libc::clocale::{setlocale, LC_CTYPE};
+use crate::libc::clocale::decimal_point;
 use crate::libc::posix_io::{STDERR_FILENO, STDOUT_FILENO};
 use crate::libc::stdio::FILE;
 use crate::libc::stdlib::atoi_inner;
 use crate::libc::wchar::wchar_t;
-use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr};
+use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, MutVoidPtr};
 use crate::objc::{id, msg, nil};
 use crate::Environment;
 use std::collections::HashSet;
@@ -38,6 +38,9 @@ pub fn printf_inner<const NS_LOG: bool, F: Fn(&Mem, GuestUSize) -> u8>(
 
     let mut format_char_idx = 0;
 
+    // NSLog and NSString formatting aren't affected by the C locale.
+    let decimal_point = if NS_LOG { b'.' } else { decimal_point(env) };
+
     loop {
         let c = get_format_char(&env.mem, format_char_idx);
         format_char_idx += 1;
@@ -105,31 +108,48 @@ pub fn printf_inner<const NS_LOG: bool, F: Fn(&Mem, GuestUSize) -> u8>(
             )
         }
 
+        let specifier_start = res.len();
         match specifier {
             // Integer specifiers
+            b'c' if length_modifier == Some(b'l') => {
+                let c: wchar_t = args.next(env);
+                // TODO: This should use the locale's multibyte encoding rather
+                // than always UTF-8.
+                let c = u32::try_from(c)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                write!(&mut res, "{}", c).unwrap();
+            }
             b'c' => {
                 // TODO: support length modifier
                 assert!(length_modifier.is_none());
                 let c: u8 = args.next(env);
-                assert!(pad_char == ' ' && pad_width == 0); // TODO
                 res.push(c);
             }
             // Apple extension? Seemingly works in both NSLog and printf.
             b'C' => {
                 assert!(length_modifier.is_none());
                 let c: unichar = args.next(env);
-                // TODO
-                assert!(pad_char == ' ' && pad_width == 0);
                 // This will panic if it's a surrogate! This isn't good if
                 // targeting UTF-16 ([NSString stringWithFormat:] etc).
                 let c = char::from_u32(c.into()).unwrap();
                 write!(&mut res, "{}", c).unwrap();
             }
+            b's' if length_modifier == Some(b'l') => {
+                let wc_string: ConstPtr<wchar_t> = args.next(env);
+                // TODO: This should use the locale's multibyte encoding rather
+                // than always UTF-8.
+                if !wc_string.is_null() {
+                    res.extend_from_slice(env.mem.wcstr_at(wc_string).as_bytes());
+                } else {
+                    res.extend_from_slice("(null)".as_bytes());
+                }
+            }
             b's' => {
                 // TODO: support length modifier
                 assert!(length_modifier.is_none());
                 let c_string: ConstPtr<u8> = args.next(env);
-                assert!(pad_char == ' ' && pad_width == 0); // TODO
                 if !c_string.is_null() {
                     res.extend_from_slice(env.mem.cstr_at(c_string));
                 } else {
@@ -332,6 +352,32 @@ pub fn printf_inner<const NS_LOG: bool, F: Fn(&Mem, GuestUSize) -> u8>(
                 format_char_idx
             ),
         }
+
+        if matches!(specifier, b'c' | b'C' | b's') && pad_width > 0 {
+            // Wide characters are counted as characters rather than bytes,
+            // which is what vswprintf() needs.
+            let is_wide = specifier == b'C' || length_modifier == Some(b'l');
+            let width = if is_wide {
+                String::from_utf8_lossy(&res[specifier_start..])
+                    .chars()
+                    .count()
+            } else {
+                res.len() - specifier_start
+            };
+            let padding = (pad_width as usize).saturating_sub(width);
+            res.splice(
+                specifier_start..specifier_start,
+                std::iter::repeat_n(pad_char as u8, padding),
+            );
+        }
+
+        if decimal_point != b'.' && FLOAT_SPECIFIERS.contains(&specifier) {
+            for c in &mut res[specifier_start..] {
+                if *c == b'.' {
+                    *c = decimal_point;
+                }
+            }
+        }
     }
 
     log_dbg!("=> {:?}", std::str::from_utf8(&res));
@@ -438,19 +484,28 @@ fn swprintf(
     format: ConstPtr<wchar_t>,
     args: DotDotDot,
 ) -> i32 {
-    // TODO: support other locales
-    let ctype_locale = setlocale(env, LC_CTYPE, Ptr::null());
-    assert_eq!(env.mem.read(ctype_locale), b'C');
+    vswprintf(env, ws, n, format, args.start())
+}
 
+fn vswprintf(
+    env: &mut Environment,
+    ws: MutPtr<wchar_t>,
+    n: GuestUSize,
+    format: ConstPtr<wchar_t>,
+    arg: VaList,
+) -> i32 {
     let wcstr_format = env.mem.wcstr_at(format);
     log_dbg!(
-        "swprintf({:?}, {}, {:?} ({:?}), ...)",
+        "vswprintf({:?}, {}, {:?} ({:?}), ...)",
         ws,
         n,
         format,
         wcstr_format
     );
 
+    // The formatting is done on UTF-8 and then converted back to wide
+    // characters, so non-ASCII characters in the format string and in %ls
+    // arguments survive.
     let wcstr_format_bytes = wcstr_format.as_bytes();
     let len: GuestUSize = wcstr_format_bytes.len() as GuestUSize;
     let res = printf_inner::<false, _>(
@@ -462,16 +517,28 @@ fn swprintf(
                 wcstr_format_bytes[idx as usize]
             }
         },
-        args.start(),
+        arg,
     );
+    let res: Vec<wchar_t> = String::from_utf8_lossy(&res)
+        .chars()
+        .map(|c| c as wchar_t)
+        .collect();
 
-    let to_write = n.min(res.len() as GuestUSize);
+    if n == 0 {
+        return -1;
+    }
+    // Unlike snprintf(), swprintf() fails if the output doesn't fit, though
+    // the truncated output is still written.
+    let to_write = (n - 1).min(res.len() as GuestUSize);
     for i in 0..to_write {
-        env.mem.write(ws + i, res[i as usize] as wchar_t);
+        env.mem.write(ws + i, res[i as usize]);
     }
-    assert!(to_write < n);
     env.mem.write(ws + to_write, wchar_t::default());
-    to_write as i32
+    if (res.len() as GuestUSize) < n {
+        to_write as i32
+    } else {
+        -1
+    }
 }
 
 fn printf(env: &mut Environment, format: ConstPtr<u8>, args: DotDotDot) -> i32 {
@@ -659,6 +726,7 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(vsprintf(_, _, _)),
     export_c_func!(sprintf(_, _, _)),
     export_c_func!(swprintf(_, _, _, _)),
+    export_c_func!(vswprintf(_, _, _, _)),
     export_c_func!(printf(_, _)),
     export_c_func!(fprintf(_, _, _)),
 ];
@@ -6,10 +6,10 @@
 //! `stdlib.h`
 
 use crate::abi::{CallFromHost, GuestFunction};
-use crate::dyld::{export_c_func, FunctionExports};
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::libc::errno::{set_errno, EINVAL};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
 use crate::Environment;
-use std::collections::HashMap;
 use std::str::FromStr;
 
 pub mod qsort;
@@ -18,10 +18,30 @@ pub mod qsort;
 pub struct State {
     rand: u32,
     random: u32,
-    arc4random: u32,
-    env: HashMap<Vec<u8>, MutPtr<u8>>,
+    /// Seeded on first use, see [arc4random_state].
+    arc4random: Option<u32>,
+    /// The 48-bit state for `drand48()` and friends, if it has been seeded.
+    rand48: Option<u64>,
+    /// The `environ` variable (`char **environ`), created on first use. Like
+    /// in a real libc, the array it points to is the source of truth, so the
+    /// app can also modify the environment directly.
+    environ: Option<MutPtr<MutPtr<MutPtr<u8>>>>,
+    /// The array most recently allocated for `environ`, which can be freed
+    /// once it's replaced. Anything else was allocated by the app.
+    environ_array: Option<MutPtr<MutPtr<u8>>>,
 }
 
+/// Environment variables an app would find on iPhone OS, other than the ones
+/// that depend on the app's location (see [environ_ptr]).
+const DEFAULT_ENVIRONMENT: &[(&str, &str)] = &[
+    ("PATH", "/usr/bin:/bin:/usr/sbin:/sbin"),
+    ("USER", "mobile"),
+    ("LOGNAME", "mobile"),
+    ("SHELL", "/bin/sh"),
+    ("LANG", "en_US.UTF-8"),
+    ("__CF_USER_TEXT_ENCODING", "0x1F5:0:0"),
+];
+
 // Sizes of zero are implementation-defined. macOS will happily give you back
 // an allocation for any of these, so presumably iPhone OS does too.
 // (touchHLE's allocator will round up allocations to at least 16 bytes.)
@@ -51,13 +71,10 @@ fn free(env: &mut Environment, ptr: MutVoidPtr) {
 }
 
 fn atexit(
-    _env: &mut Environment,
+    env: &mut Environment,
     func: GuestFunction, // void (*func)(void)
 ) -> i32 {
-    // TODO: when this is implemented, make sure it's properly compatible with
-    // __cxa_atexit.
-    log!("TODO: atexit({:?}) (unimplemented)", func);
-    0 // success
+    super::cxxabi::atexit(env, func)
 }
 
 fn skip_whitespace(env: &mut Environment, s: ConstPtr<u8>) -> ConstPtr<u8> {
@@ -131,28 +148,237 @@ fn random(env: &mut Environment) -> i32 {
     (env.libc_state.stdlib.random as i32) & RAND_MAX
 }
 
+/// Get the state for `arc4random()`, seeding it if needed. Unlike `rand()`,
+/// apps expect this to be unpredictable without seeding it, so the seed comes
+/// from the host, unless `--random-seed=` was used to make runs reproducible.
+fn arc4random_state(env: &mut Environment) -> &mut u32 {
+    let seed = env.options.random_seed;
+    env.libc_state.stdlib.arc4random.get_or_insert_with(|| {
+        seed.unwrap_or_else(|| {
+            use std::collections::hash_map::RandomState;
+            use std::hash::{BuildHasher, Hasher};
+            // RandomState is randomly keyed by the standard library, which
+            // saves us a dependency.
+            RandomState::new().build_hasher().finish() as u32
+        })
+    })
+}
+
 fn arc4random(env: &mut Environment) -> u32 {
-    env.libc_state.stdlib.arc4random = prng(env.libc_state.stdlib.arc4random);
-    env.libc_state.stdlib.arc4random
+    let state = arc4random_state(env);
+    *state = prng(*state);
+    *state
+}
+fn arc4random_uniform(env: &mut Environment, upper_bound: u32) -> u32 {
+    if upper_bound < 2 {
+        return 0;
+    }
+    // Values below this would make some results more likely than others.
+    let min = upper_bound.wrapping_neg() % upper_bound;
+    loop {
+        let value = arc4random(env);
+        if value >= min {
+            return value % upper_bound;
+        }
+    }
+}
+pub fn arc4random_buf(env: &mut Environment, buf: MutVoidPtr, nbytes: GuestUSize) {
+    for i in 0..nbytes {
+        let byte = arc4random(env) as u8;
+        env.mem.write(buf.cast::<u8>() + i, byte);
+    }
+}
+fn arc4random_stir(_env: &mut Environment) {
+    // Our arc4random() has no need for stirring.
+}
+fn arc4random_addrandom(_env: &mut Environment, _dat: ConstPtr<u8>, _datlen: i32) {
+    // Ignored, like arc4random_stir().
+}
+
+fn rand_r(env: &mut Environment, seedp: MutPtr<u32>) -> i32 {
+    let state = prng(env.mem.read(seedp));
+    env.mem.write(seedp, state);
+    (state as i32) & RAND_MAX
+}
+
+// The drand48() family uses a 48-bit linear congruential generator, which is
+// fully specified by POSIX, so apps could rely on the exact sequence.
+const RAND48_A: u64 = 0x5DEECE66D;
+const RAND48_C: u64 = 0xB;
+const RAND48_MASK: u64 = (1 << 48) - 1;
+/// The state used if `srand48()` isn't called.
+const RAND48_DEFAULT: u64 = 0x1234ABCD330E;
+
+fn rand48_step(state: u64) -> u64 {
+    (state.wrapping_mul(RAND48_A).wrapping_add(RAND48_C)) & RAND48_MASK
+}
+/// Advance the internal state and return the new one.
+fn rand48_next(env: &mut Environment) -> u64 {
+    let state = env.libc_state.stdlib.rand48.get_or_insert(RAND48_DEFAULT);
+    *state = rand48_step(*state);
+    *state
+}
+/// Advance the state in an app-provided `unsigned short xsubi[3]` and return
+/// the new one.
+fn rand48_next_xsubi(env: &mut Environment, xsubi: MutPtr<u16>) -> u64 {
+    let state = (0..3).fold(0u64, |state, i| {
+        state | (u64::from(env.mem.read(xsubi + i)) << (16 * i))
+    });
+    let state = rand48_step(state);
+    for i in 0..3 {
+        env.mem.write(xsubi + i, (state >> (16 * i)) as u16);
+    }
+    state
+}
+fn rand48_to_double(state: u64) -> f64 {
+    state as f64 / (1u64 << 48) as f64
+}
+
+fn srand48(env: &mut Environment, seed: i32) {
+    env.libc_state.stdlib.rand48 = Some(((seed as u32 as u64) << 16) | 0x330E);
+}
+fn drand48(env: &mut Environment) -> f64 {
+    rand48_to_double(rand48_next(env))
+}
+fn erand48(env: &mut Environment, xsubi: MutPtr<u16>) -> f64 {
+    rand48_to_double(rand48_next_xsubi(env, xsubi))
+}
+fn lrand48(env: &mut Environment) -> i32 {
+    (rand48_next(env) >> 17) as i32
+}
+fn nrand48(env: &mut Environment, xsubi: MutPtr<u16>) -> i32 {
+    (rand48_next_xsubi(env, xsubi) >> 17) as i32
+}
+fn mrand48(env: &mut Environment) -> i32 {
+    (rand48_next(env) >> 16) as u32 as i32
+}
+fn jrand48(env: &mut Environment, xsubi: MutPtr<u16>) -> i32 {
+    (rand48_next_xsubi(env, xsubi) >> 16) as u32 as i32
+}
+
+/// Get the address of the `environ` variable, setting up the environment on
+/// first use. User-provided variables (`--env=`) override the defaults.
+fn environ_ptr(env: &mut Environment) -> MutPtr<MutPtr<MutPtr<u8>>> {
+    if let Some(environ) = env.libc_state.stdlib.environ {
+        return environ;
+    }
+
+    let home = env.fs.home_directory().as_str().to_string();
+    let mut vars: Vec<(String, String)> = vec![
+        ("HOME".to_string(), home.clone()),
+        ("CFFIXED_USER_HOME".to_string(), home.clone()),
+        ("TMPDIR".to_string(), format!("{}/tmp/", home)),
+    ];
+    vars.extend(
+        DEFAULT_ENVIRONMENT
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string())),
+    );
+    for (name, value) in &env.options.environment_variables {
+        if let Some(var) = vars.iter_mut().find(|(n, _)| n == name) {
+            var.1 = value.clone();
+        } else {
+            vars.push((name.clone(), value.clone()));
+        }
+    }
+    log_dbg!("Initial environment: {:?}", vars);
+
+    let entries: Vec<MutPtr<u8>> = vars
+        .iter()
+        .map(|(name, value)| {
+            env.mem
+                .alloc_and_write_cstr(format!("{}={}", name, value).as_bytes())
+        })
+        .collect();
+    let array = alloc_environ_array(env, &entries);
+    let environ = env.mem.alloc_and_write(array);
+    env.libc_state.stdlib.environ = Some(environ);
+    environ
+}
+
+/// Allocate a null-terminated array of `NAME=value` strings for `environ`.
+fn alloc_environ_array(env: &mut Environment, entries: &[MutPtr<u8>]) -> MutPtr<MutPtr<u8>> {
+    let size = GuestUSize::try_from(entries.len() + 1).unwrap() * 4;
+    let array: MutPtr<MutPtr<u8>> = env.mem.alloc(size).cast();
+    write_environ_array(env, array, entries);
+    env.libc_state.stdlib.environ_array = Some(array);
+    array
+}
+
+fn write_environ_array(env: &mut Environment, array: MutPtr<MutPtr<u8>>, entries: &[MutPtr<u8>]) {
+    for (i, &entry) in entries
+        .iter()
+        .chain(std::iter::once(&Ptr::null()))
+        .enumerate()
+    {
+        env.mem.write(array + i.try_into().unwrap(), entry);
+    }
+}
+
+/// Read the current entries of `environ`.
+fn environ_entries(env: &mut Environment) -> Vec<MutPtr<u8>> {
+    let environ = environ_ptr(env);
+    let array = env.mem.read(environ);
+    let mut entries = Vec::new();
+    if array.is_null() {
+        return entries;
+    }
+    loop {
+        let entry = env.mem.read(array + entries.len().try_into().unwrap());
+        if entry.is_null() {
+            return entries;
+        }
+        entries.push(entry);
+    }
+}
+
+/// Find the entry for a variable, returning its index in `environ` and a
+/// pointer to its value.
+fn find_env_var(env: &mut Environment, name: &[u8]) -> Option<(usize, MutPtr<u8>)> {
+    let entries = environ_entries(env);
+    entries.into_iter().enumerate().find_map(|(i, entry)| {
+        let entry_cstr = env.mem.cstr_at(entry);
+        (entry_cstr.len() > name.len()
+            && entry_cstr.starts_with(name)
+            && entry_cstr[name.len()] == b'=')
+            .then(|| (i, entry + (name.len() + 1).try_into().unwrap()))
+    })
+}
+
+/// Get the value of an environment variable, for host code that respects the
+/// app's environment (e.g. `setlocale()`).
+pub fn get_env_var(env: &mut Environment, name: &str) -> Option<String> {
+    let (_, value) = find_env_var(env, name.as_bytes())?;
+    env.mem.cstr_at_utf8(value).ok().map(str::to_string)
+}
+
+/// Check a name passed to `setenv()` or `unsetenv()`, setting `errno` if it's
+/// invalid.
+fn check_env_var_name(env: &mut Environment, name: ConstPtr<u8>) -> Option<Vec<u8>> {
+    let name_cstr = (!name.is_null()).then(|| env.mem.cstr_at(name).to_vec());
+    match name_cstr {
+        Some(name_cstr) if !name_cstr.is_empty() && !name_cstr.contains(&b'=') => Some(name_cstr),
+        _ => {
+            set_errno(env, EINVAL);
+            None
+        }
+    }
 }
 
 fn getenv(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<u8> {
-    let name_cstr = env.mem.cstr_at(name);
-    // TODO: Provide all the system environment variables an app might expect to
-    // find. Currently the only environment variables that can be found are
-    // those put there by the app (Crash Bandicoot Nitro Kart 3D uses this).
-    let Some(&value) = env.libc_state.stdlib.env.get(name_cstr) else {
+    let name_cstr = env.mem.cstr_at(name).to_vec();
+    let Some((_, value)) = find_env_var(env, &name_cstr) else {
         log!(
             "Warning: getenv() for {:?} ({:?}) unhandled",
             name,
-            std::str::from_utf8(name_cstr)
+            std::str::from_utf8(&name_cstr)
         );
         return Ptr::null();
     };
     log_dbg!(
         "getenv({:?} ({:?})) => {:?} ({:?})",
         name,
-        name_cstr,
+        std::str::from_utf8(&name_cstr),
         value,
         env.mem.cstr_at_utf8(value),
     );
@@ -160,28 +386,87 @@ fn getenv(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<u8> {
     value
 }
 fn setenv(env: &mut Environment, name: ConstPtr<u8>, value: ConstPtr<u8>, overwrite: i32) -> i32 {
-    let name_cstr = env.mem.cstr_at(name);
-    if let Some(&existing) = env.libc_state.stdlib.env.get(name_cstr) {
-        if overwrite == 0 {
-            return 0; // success
-        }
-        env.mem.free(existing.cast());
+    let Some(name_cstr) = check_env_var_name(env, name) else {
+        return -1;
     };
-    let value = super::string::strdup(env, value);
-    let name_cstr = env.mem.cstr_at(name); // reborrow
-    env.libc_state.stdlib.env.insert(name_cstr.to_vec(), value);
+    let existing = find_env_var(env, &name_cstr);
+    if existing.is_some() && overwrite == 0 {
+        return 0; // success
+    }
+
+    let mut entry_bytes = name_cstr.clone();
+    entry_bytes.push(b'=');
+    entry_bytes.extend_from_slice(env.mem.cstr_at(value));
+    let entry = env.mem.alloc_and_write_cstr(&entry_bytes);
+    // The old entry isn't freed, since the app might still be using a pointer
+    // returned by getenv(). Apple's libc leaks it too.
+    if let Some((idx, _)) = existing {
+        let environ = environ_ptr(env);
+        let array = env.mem.read(environ);
+        env.mem.write(array + idx.try_into().unwrap(), entry);
+    } else {
+        let mut entries = environ_entries(env);
+        entries.push(entry);
+        let environ = environ_ptr(env);
+        let old_array = env.mem.read(environ);
+        let old_array_is_ours = env.libc_state.stdlib.environ_array == Some(old_array);
+        let new_array = alloc_environ_array(env, &entries);
+        env.mem.write(environ, new_array);
+        if old_array_is_ours {
+            env.mem.free(old_array.cast());
+        }
+    }
     log_dbg!(
-        "Stored new value {:?} ({:?}) for environment variable {:?}",
-        value,
+        "Stored new value {:?} for environment variable {:?}",
         env.mem.cstr_at_utf8(value),
-        std::str::from_utf8(name_cstr),
+        std::str::from_utf8(&name_cstr),
+    );
+    0 // success
+}
+fn unsetenv(env: &mut Environment, name: ConstPtr<u8>) -> i32 {
+    let Some(name_cstr) = check_env_var_name(env, name) else {
+        return -1;
+    };
+    let mut entries = environ_entries(env);
+    let old_len = entries.len();
+    // The variable could appear more than once if the app modified environ.
+    while let Some((idx, _)) = find_env_var(env, &name_cstr) {
+        entries.remove(idx);
+        let environ = environ_ptr(env);
+        let array = env.mem.read(environ);
+        write_environ_array(env, array, &entries);
+    }
+    log_dbg!(
+        "Removed {} entries for environment variable {:?}",
+        old_len - entries.len(),
+        std::str::from_utf8(&name_cstr),
     );
     0 // success
 }
 
-fn exit(_env: &mut Environment, exit_code: i32) {
+fn _NSGetEnviron(env: &mut Environment) -> MutPtr<MutPtr<MutPtr<u8>>> {
+    environ_ptr(env)
+}
+
+fn exit(env: &mut Environment, exit_code: i32) {
     echo!("App called exit(), exiting.");
-    std::process::exit(exit_code);
+    super::cxxabi::run_exit_handlers(env);
+    env.exit(exit_code);
+}
+
+fn abort(env: &mut Environment) {
+    abort_with_reason(env, "App called abort().");
+}
+
+/// Implementation of `abort()` for host code that terminates the app on its
+/// behalf (e.g. for a failed assertion). `reason` is used for the crash report.
+pub fn abort_with_reason(env: &mut Environment, reason: &str) -> ! {
+    // The app might have a SIGABRT handler, e.g. a crash reporter. abort()
+    // can't return, so the process is terminated even if the handler returns
+    // or the signal is ignored.
+    // TODO: unblock SIGABRT first
+    super::signal::raise(env, super::signal::SIGABRT);
+    crate::crash_report::report_crash(env, reason)
 }
 
 fn bsearch(
@@ -241,6 +526,11 @@ fn strtoul(env: &mut Environment, str: ConstPtr<u8>, endptr: MutPtr<MutPtr<u8>>,
     res
 }
 
+pub const CONSTANTS: ConstantExports = &[(
+    "_environ",
+    HostConstant::CustomWithEnv(|env| environ_ptr(env).cast().cast_const()),
+)];
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(malloc(_)),
     export_c_func!(calloc(_, _)),
@@ -256,9 +546,24 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(srandom(_)),
     export_c_func!(random()),
     export_c_func!(arc4random()),
+    export_c_func!(arc4random_uniform(_)),
+    export_c_func!(arc4random_buf(_, _)),
+    export_c_func!(arc4random_stir()),
+    export_c_func!(arc4random_addrandom(_, _)),
+    export_c_func!(rand_r(_)),
+    export_c_func!(srand48(_)),
+    export_c_func!(drand48()),
+    export_c_func!(erand48(_)),
+    export_c_func!(lrand48()),
+    export_c_func!(nrand48(_)),
+    export_c_func!(mrand48()),
+    export_c_func!(jrand48(_)),
     export_c_func!(getenv(_)),
     export_c_func!(setenv(_, _, _)),
+    export_c_func!(unsetenv(_)),
+    export_c_func!(_NSGetEnviron()),
     export_c_func!(exit(_)),
+    export_c_func!(abort()),
     export_c_func!(bsearch(_, _, _, _, _)),
     export_c_func!(strtof(_, _)),
     export_c_func!(strtoul(_, _, _)),
@@ -279,7 +584,10 @@ fn atof_inner(env: &mut Environment, s: ConstPtr<u8>) -> Result<(f64, u32), <f64
     while env.mem.read(start + len).is_ascii_digit() {
         len += 1;
     }
-    if env.mem.read(start + len) == b'.' {
+    let decimal_point = super::clocale::decimal_point(env);
+    let mut decimal_point_idx = None;
+    if env.mem.read(start + len) == decimal_point {
+        decimal_point_idx = Some(len as usize);
         len += 1;
         while env.mem.read(start + len).is_ascii_digit() {
             len += 1;
@@ -296,7 +604,12 @@ fn atof_inner(env: &mut Environment, s: ConstPtr<u8>) -> Result<(f64, u32), <f64
         }
     }
 
-    let s = std::str::from_utf8(env.mem.bytes_at(start, len)).unwrap();
+    let mut bytes = env.mem.bytes_at(start, len).to_vec();
+    // Rust always expects a point.
+    if let Some(idx) = decimal_point_idx {
+        bytes[idx] = b'.';
+    }
+    let s = std::str::from_utf8(&bytes).unwrap();
     s.parse().map(|result| (result, whitespace_len + len))
 }
 
@@ -8,16 +8,41 @@
 use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
-use crate::mem::MutPtr;
+use crate::libc::errno::{set_errno, EFAULT};
+use crate::libc::sysctl::{HOST_NAME, OS_RELEASE, OS_TYPE, OS_VERSION};
+use crate::mem::{GuestUSize, MutPtr};
 
-// TODO: struct definition
+/// Size of each field of `struct utsname`.
+const _SYS_NAMELEN: GuestUSize = 256;
+
+/// `struct utsname` is five `char[_SYS_NAMELEN]` fields: `sysname`,
+/// `nodename`, `release`, `version` and `machine`. Arrays can't be used in
+/// guest structs, so the fields are written directly.
 #[allow(non_camel_case_types)]
 struct utsname {}
 
-fn uname(_env: &mut Environment, name: MutPtr<utsname>) -> i32 {
-    log!("TODO: uname({:?}), returning -1", name);
-    // TODO: set errno
-    -1
+fn uname(env: &mut Environment, name: MutPtr<utsname>) -> i32 {
+    if name.is_null() {
+        set_errno(env, EFAULT);
+        return -1;
+    }
+    let fields = [
+        OS_TYPE,
+        HOST_NAME,
+        OS_RELEASE,
+        OS_VERSION,
+        env.options.device_model.identifier,
+    ];
+    for (i, field) in fields.into_iter().enumerate() {
+        let field_ptr = name.cast::<u8>() + (i as GuestUSize) * _SYS_NAMELEN;
+        let bytes = env.mem.bytes_at_mut(field_ptr, _SYS_NAMELEN);
+        // Truncate if needed, always leaving room for the null terminator.
+        let len = field.len().min(bytes.len() - 1);
+        bytes[..len].copy_from_slice(&field.as_bytes()[..len]);
+        bytes[len..].fill(0);
+    }
+    log_dbg!("uname({:?}) => machine {:?}", name, fields[4]);
+    0 // success
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(uname(_))];
@@ -6,9 +6,94 @@
 //! `sys/sysctl.h`
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, ENOENT, ENOMEM};
 use crate::mem::{ConstPtr, GuestUSize, MutPtr, MutVoidPtr};
 use crate::Environment;
 
+const CTL_KERN: i32 = 1;
+const KERN_OSTYPE: i32 = 1;
+const KERN_OSRELEASE: i32 = 2;
+const KERN_VERSION: i32 = 4;
+const KERN_HOSTNAME: i32 = 10;
+const KERN_OSVERSION: i32 = 65;
+const CTL_HW: i32 = 6;
+const HW_MACHINE: i32 = 1;
+const HW_MODEL: i32 = 2;
+const HW_NCPU: i32 = 3;
+const HW_PHYSMEM: i32 = 5;
+const HW_CPU_FREQ: i32 = 15;
+const HW_MEMSIZE: i32 = 24;
+
+// The kernel of iPhone OS 2.2.1, the version touchHLE mostly targets.
+pub const OS_TYPE: &str = "Darwin";
+pub const OS_RELEASE: &str = "9.4.1";
+pub const OS_VERSION: &str = "Darwin Kernel Version 9.4.1: Mon Dec  8 20:59:30 PST 2008; root:xnu-1228.7.37~4/RELEASE_ARM_S5L8900X";
+pub const HOST_NAME: &str = "touchHLE";
+// The matching iPhone OS version and build, also reported by UIDevice and
+// SystemVersion.plist (see [crate::fs]).
+pub const PRODUCT_VERSION: &str = "2.2.1";
+pub const PRODUCT_BUILD_VERSION: &str = "5H11";
+
+fn c_string(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(b'\0');
+    bytes
+}
+
+/// Get the value of a variable we know about, by its name.
+fn value_for_name(env: &Environment, name: &str) -> Option<Vec<u8>> {
+    let ram_bytes = u64::from(env.options.device_ram_mib().get()) * 1024 * 1024;
+    match name {
+        "kern.ostype" => Some(c_string(OS_TYPE)),
+        "kern.osrelease" => Some(c_string(OS_RELEASE)),
+        "kern.version" => Some(c_string(OS_VERSION)),
+        "kern.osversion" => Some(c_string(PRODUCT_BUILD_VERSION)),
+        "kern.hostname" => Some(c_string(HOST_NAME)),
+        "hw.machine" => Some(c_string(env.options.device_model.identifier)),
+        "hw.model" => Some(c_string(env.options.device_model.board)),
+        // All devices touchHLE simulates are single-core.
+        "hw.ncpu" => Some(1i32.to_le_bytes().to_vec()),
+        // This is a 32-bit int, so it would be wrong for 4GiB and above.
+        "hw.physmem" => Some(
+            (ram_bytes.min(u32::MAX.into()) as u32)
+                .to_le_bytes()
+                .to_vec(),
+        ),
+        "hw.memsize" => Some(ram_bytes.to_le_bytes().to_vec()),
+        "hw.cpufrequency" => Some(
+            (env.options.device_model.cpu_mhz * 1_000_000)
+                .to_le_bytes()
+                .to_vec(),
+        ),
+        _ => None,
+    }
+}
+
+/// Shared part of [sysctl] and [sysctlbyname]: copy out the value, or just its
+/// size if `oldp` is NULL.
+fn write_value(
+    env: &mut Environment,
+    value: &[u8],
+    oldp: MutVoidPtr,
+    oldlenp: MutPtr<GuestUSize>,
+    newp: MutVoidPtr,
+) -> i32 {
+    assert!(newp.is_null()); // TODO
+    assert!(!oldlenp.is_null()); // TODO
+    let len: GuestUSize = value.len().try_into().unwrap();
+    if !oldp.is_null() {
+        if env.mem.read(oldlenp) < len {
+            set_errno(env, ENOMEM);
+            return -1;
+        }
+        env.mem
+            .bytes_at_mut(oldp.cast(), len)
+            .copy_from_slice(value);
+    }
+    env.mem.write(oldlenp, len);
+    0 // success
+}
+
 fn sysctl(
     env: &mut Environment,
     name: MutPtr<i32>,
@@ -18,9 +103,30 @@ fn sysctl(
     newp: MutVoidPtr,
     newlen: GuestUSize,
 ) -> i32 {
+    let mib: Vec<i32> = (0..name_len).map(|i| env.mem.read(name + i)).collect();
+    let name_str = match mib[..] {
+        [CTL_KERN, KERN_OSTYPE] => Some("kern.ostype"),
+        [CTL_KERN, KERN_OSRELEASE] => Some("kern.osrelease"),
+        [CTL_KERN, KERN_VERSION] => Some("kern.version"),
+        [CTL_KERN, KERN_HOSTNAME] => Some("kern.hostname"),
+        [CTL_KERN, KERN_OSVERSION] => Some("kern.osversion"),
+        [CTL_HW, HW_MACHINE] => Some("hw.machine"),
+        [CTL_HW, HW_MODEL] => Some("hw.model"),
+        [CTL_HW, HW_NCPU] => Some("hw.ncpu"),
+        [CTL_HW, HW_PHYSMEM] => Some("hw.physmem"),
+        [CTL_HW, HW_MEMSIZE] => Some("hw.memsize"),
+        [CTL_HW, HW_CPU_FREQ] => Some("hw.cpufrequency"),
+        _ => None,
+    };
+    if let Some(name_str) = name_str {
+        log_dbg!("sysctl({:?} ({}), ...)", mib, name_str);
+        let value = value_for_name(env, name_str).unwrap();
+        return write_value(env, &value, oldp, oldlenp, newp);
+    }
+
     log!(
         "TODO: sysctl({:?}, {:#x}, {:?}, {:?}, {:?}, {:x})",
-        name,
+        mib,
         name_len,
         oldp,
         oldlenp,
@@ -41,29 +147,21 @@ fn sysctlbyname(
     newp: MutVoidPtr,
     newlen: GuestUSize,
 ) -> i32 {
-    let name_str = env.mem.cstr_at_utf8(name).unwrap();
+    let name_str = env.mem.cstr_at_utf8(name).unwrap().to_string();
     log_dbg!(
-        "TODO: sysctlbyname({:?}, {:?}, {:?}, {:?}, {:x})",
+        "sysctlbyname({:?}, {:?}, {:?}, {:?}, {:x})",
         name_str,
         oldp,
         oldlenp,
         newp,
         newlen
     );
-    assert_eq!(name_str, "hw.machine");
-    if oldp.is_null() && newp.is_null() {
-        // "iPhone1,1"
-        env.mem.write(oldlenp, 10);
-        return 0;
-    }
-    assert!(!oldp.is_null() && !oldlenp.is_null());
-    assert!(newp.is_null());
-    let hw_machine_str = env.mem.alloc_and_write_cstr(b"iPhone1,1");
-    assert_eq!(env.mem.read(oldlenp), 10);
-    env.mem
-        .memmove(oldp, hw_machine_str.cast().cast_const(), 10);
-    env.mem.free(hw_machine_str.cast());
-    0 // success
+    let Some(value) = value_for_name(env, &name_str) else {
+        log!("TODO: sysctlbyname({:?}) is not supported", name_str);
+        set_errno(env, ENOENT);
+        return -1;
+    };
+    write_value(env, &value, oldp, oldlenp, newp)
 }
 
 pub const FUNCTIONS: FunctionExports = &[
@@ -0,0 +1,289 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `syslog.h` and `asl.h` (Apple System Log).
+//!
+//! There's no system log for the app to write to, so messages are printed
+//! the same way as `NSLog()` output (which on a real device also ends up in
+//! the system log).
+
+use crate::abi::{DotDotDot, VaList};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{describe_errno, get_errno};
+use crate::libc::stdio::printf::printf_inner;
+use crate::mem::{ConstPtr, MutVoidPtr};
+use crate::Environment;
+use std::collections::HashMap;
+
+/// Default level for ASL messages.
+const LOG_NOTICE: i32 = 5;
+
+/// Mask for the priority part of a `syslog()` priority value. The rest is the
+/// facility, which we don't care about.
+const LOG_PRIMASK: i32 = 0x07;
+
+/// Names for the priority levels, as used in the system log.
+const LEVEL_NAMES: [&str; 8] = [
+    "Emergency",
+    "Alert",
+    "Critical",
+    "Error",
+    "Warning",
+    "Notice",
+    "Info",
+    "Debug",
+];
+
+pub struct State {
+    /// Set by `openlog()`.
+    ident: Option<String>,
+    /// Set by `setlogmask()`. Bit N is set if priority level N is logged.
+    log_mask: i32,
+    /// Key-value pairs for `aslmsg` objects created by `asl_new()`.
+    asl_messages: HashMap<MutVoidPtr, HashMap<String, String>>,
+}
+impl Default for State {
+    fn default() -> Self {
+        State {
+            ident: None,
+            log_mask: 0xff,
+            asl_messages: HashMap::new(),
+        }
+    }
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.syslog
+    }
+}
+
+/// The name the app's process would have, i.e. its executable's file name.
+pub fn process_name(env: &Environment) -> String {
+    env.bundle
+        .executable_path()
+        .file_name()
+        .unwrap()
+        .to_string()
+}
+
+/// Print a message logged by the app. This is shared by `NSLog()`, `syslog()`
+/// and `asl_log()`. `level` is the priority level, if the logging function
+/// has one.
+pub fn log_app_message(env: &mut Environment, sender: &str, level: Option<i32>, message: &[u8]) {
+    let message = String::from_utf8_lossy(message);
+    // syslog() and friends add a newline if there isn't already one.
+    let message = message.strip_suffix('\n').unwrap_or(&message);
+    // TODO: Should we include a timestamp, like the real system log?
+    if let Some(level) = level {
+        echo!(
+            "{}[{}] <{}>: {}",
+            sender,
+            env.current_thread,
+            LEVEL_NAMES[(level & LOG_PRIMASK) as usize],
+            message
+        );
+    } else {
+        echo!("{}[{}] {}", sender, env.current_thread, message);
+    }
+}
+
+/// Format a message for `syslog()` or `asl_log()`. Unlike `printf()`, these
+/// support a `%m` specifier that's replaced with the message for `errno`.
+fn format_log_message(env: &mut Environment, format: ConstPtr<u8>, args: VaList) -> Vec<u8> {
+    let errno_message = describe_errno(get_errno(env)).replace('%', "%%");
+
+    // Substitute %m before doing the printf-style formatting, so that the
+    // arguments still line up.
+    let mut format_string = Vec::new();
+    let mut chars = env.mem.cstr_at(format).iter().copied();
+    while let Some(c) = chars.next() {
+        if c != b'%' {
+            format_string.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(b'm') => format_string.extend_from_slice(errno_message.as_bytes()),
+            Some(c) => format_string.extend_from_slice(&[b'%', c]),
+            None => format_string.push(b'%'),
+        }
+    }
+
+    printf_inner::<false, _>(
+        env,
+        |_, idx| {
+            if idx as usize == format_string.len() {
+                b'\0'
+            } else {
+                format_string[idx as usize]
+            }
+        },
+        args,
+    )
+}
+
+fn should_log(env: &mut Environment, priority: i32) -> bool {
+    State::get_mut(env).log_mask & (1 << (priority & LOG_PRIMASK)) != 0
+}
+
+fn openlog(env: &mut Environment, ident: ConstPtr<u8>, _option: i32, _facility: i32) {
+    let ident = (!ident.is_null()).then(|| env.mem.cstr_at_utf8(ident).unwrap().to_string());
+    log_dbg!("openlog({:?}, ...)", ident);
+    State::get_mut(env).ident = ident;
+}
+
+fn closelog(env: &mut Environment) {
+    State::get_mut(env).ident = None;
+}
+
+fn setlogmask(env: &mut Environment, mask: i32) -> i32 {
+    let state = State::get_mut(env);
+    let old_mask = state.log_mask;
+    // A mask of zero only queries the current mask.
+    if mask != 0 {
+        state.log_mask = mask;
+    }
+    old_mask
+}
+
+fn syslog(env: &mut Environment, priority: i32, format: ConstPtr<u8>, args: DotDotDot) {
+    vsyslog(env, priority, format, args.start())
+}
+
+fn vsyslog(env: &mut Environment, priority: i32, format: ConstPtr<u8>, arg: VaList) {
+    if !should_log(env, priority) {
+        return;
+    }
+    let message = format_log_message(env, format, arg);
+    let sender = State::get_mut(env)
+        .ident
+        .clone()
+        .unwrap_or_else(|| process_name(env));
+    log_app_message(env, &sender, Some(priority), &message);
+}
+
+/// `aslclient` is an opaque pointer type, and we don't need to store anything
+/// for it, so a unique dummy allocation is handed out.
+fn asl_open(
+    env: &mut Environment,
+    ident: ConstPtr<u8>,
+    facility: ConstPtr<u8>,
+    _opts: u32, // ASL_OPT_STDERR etc, irrelevant since we always print
+) -> MutVoidPtr {
+    log_dbg!(
+        "asl_open({:?}, {:?}, ...)",
+        env.mem.cstr_at_utf8(ident),
+        env.mem.cstr_at_utf8(facility)
+    );
+    env.mem.alloc(4)
+}
+
+fn asl_close(env: &mut Environment, client: MutVoidPtr) {
+    if !client.is_null() {
+        env.mem.free(client);
+    }
+}
+
+fn asl_add_log_file(_env: &mut Environment, _client: MutVoidPtr, fd: i32) -> i32 {
+    // TODO: actually write messages to this file
+    log!("TODO: asl_add_log_file(..., {}) (ignored)", fd);
+    0 // success
+}
+
+fn asl_new(env: &mut Environment, type_: u32) -> MutVoidPtr {
+    // 0 is ASL_TYPE_MSG, 1 is ASL_TYPE_QUERY.
+    assert_eq!(type_, 0);
+    let msg = env.mem.alloc(4);
+    State::get_mut(env).asl_messages.insert(msg, HashMap::new());
+    msg
+}
+
+fn asl_free(env: &mut Environment, msg: MutVoidPtr) {
+    if msg.is_null() {
+        return;
+    }
+    State::get_mut(env).asl_messages.remove(&msg).unwrap();
+    env.mem.free(msg);
+}
+
+fn asl_set(env: &mut Environment, msg: MutVoidPtr, key: ConstPtr<u8>, value: ConstPtr<u8>) -> i32 {
+    let key = env.mem.cstr_at_utf8(key).unwrap().to_string();
+    let value = env.mem.cstr_at_utf8(value).unwrap().to_string();
+    let Some(pairs) = State::get_mut(env).asl_messages.get_mut(&msg) else {
+        return -1;
+    };
+    pairs.insert(key, value);
+    0 // success
+}
+
+/// Get the sender and level from an `aslmsg`, if any.
+fn asl_message_info(env: &mut Environment, msg: MutVoidPtr) -> (String, i32) {
+    let pairs = State::get_mut(env).asl_messages.get(&msg);
+    let sender = pairs.and_then(|p| p.get("Sender")).cloned();
+    let level = pairs
+        .and_then(|p| p.get("Level"))
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(LOG_NOTICE);
+    let sender = sender.unwrap_or_else(|| process_name(env));
+    (sender, level)
+}
+
+fn asl_send(env: &mut Environment, _client: MutVoidPtr, msg: MutVoidPtr) -> i32 {
+    let (sender, level) = asl_message_info(env, msg);
+    let Some(message) = State::get_mut(env)
+        .asl_messages
+        .get(&msg)
+        .and_then(|p| p.get("Message"))
+        .cloned()
+    else {
+        return -1;
+    };
+    log_app_message(env, &sender, Some(level), message.as_bytes());
+    0 // success
+}
+
+fn asl_log(
+    env: &mut Environment,
+    client: MutVoidPtr,
+    msg: MutVoidPtr,
+    level: i32,
+    format: ConstPtr<u8>,
+    args: DotDotDot,
+) -> i32 {
+    asl_vlog(env, client, msg, level, format, args.start())
+}
+
+fn asl_vlog(
+    env: &mut Environment,
+    _client: MutVoidPtr,
+    msg: MutVoidPtr,
+    level: i32,
+    format: ConstPtr<u8>,
+    arg: VaList,
+) -> i32 {
+    if format.is_null() {
+        return -1;
+    }
+    let message = format_log_message(env, format, arg);
+    let (sender, _) = asl_message_info(env, msg);
+    log_app_message(env, &sender, Some(level), &message);
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(openlog(_, _, _)),
+    export_c_func!(closelog()),
+    export_c_func!(setlogmask(_)),
+    export_c_func!(syslog(_, _, _)),
+    export_c_func!(vsyslog(_, _, _)),
+    export_c_func!(asl_open(_, _, _)),
+    export_c_func!(asl_close(_)),
+    export_c_func!(asl_add_log_file(_, _)),
+    export_c_func!(asl_new(_)),
+    export_c_func!(asl_free(_)),
+    export_c_func!(asl_set(_, _, _)),
+    export_c_func!(asl_send(_, _)),
+    export_c_func!(asl_log(_, _, _, _, _)),
+    export_c_func!(asl_vlog(_, _, _, _, _)),
+];
@@ -5,10 +5,15 @@
  */
 //! `time.h` (C) and `sys/time.h` (POSIX)
 
-use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{guest_size_of, ConstPtr, MutPtr, Ptr, SafeRead};
+pub mod zone;
+
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, Ptr, SafeRead};
 use crate::Environment;
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::{Duration, Instant, SystemTime};
+use zone::TimeZone;
 
 #[derive(Default)]
 pub struct State {
@@ -16,6 +21,45 @@ pub struct State {
     /// Temporary static storage for the return value of `gmtime` or
     /// `localtime`. The standard allows calls to either to overwrite it.
     gmtime_tmp: Option<MutPtr<tm>>,
+    /// Loaded on first use, see [local_time_zone].
+    local_time_zone: Option<Rc<TimeZone>>,
+    /// Guest copies of time zone abbreviations, for `tm_zone`. These are never
+    /// freed, like in a real libc.
+    zone_abbreviations: HashMap<String, ConstPtr<u8>>,
+}
+
+/// Get the local time zone. This is also used by Foundation's `NSTimeZone`.
+pub fn local_time_zone(env: &mut Environment) -> Rc<TimeZone> {
+    env.libc_state
+        .time
+        .local_time_zone
+        .get_or_insert_with(|| {
+            // The name was already checked when parsing the option.
+            let zone = env
+                .options
+                .time_zone
+                .as_deref()
+                .and_then(TimeZone::from_name)
+                .unwrap_or_else(TimeZone::host);
+            log_dbg!("Local time zone is {:?}", zone.name());
+            Rc::new(zone)
+        })
+        .clone()
+}
+
+fn zone_abbreviation_ptr(env: &mut Environment, abbreviation: &str) -> ConstPtr<u8> {
+    if let Some(&ptr) = env.libc_state.time.zone_abbreviations.get(abbreviation) {
+        return ptr;
+    }
+    let ptr = env
+        .mem
+        .alloc_and_write_cstr(abbreviation.as_bytes())
+        .cast_const();
+    env.libc_state
+        .time
+        .zone_abbreviations
+        .insert(abbreviation.to_string(), ptr);
+    ptr
 }
 
 // time.h (C)
@@ -70,20 +114,21 @@ pub struct tm {
     /// year with 1900 subtracted from it
     pub tm_year: i32,
     /// day of the week (where Sunday is the first day)
-    tm_wday: i32,
+    pub tm_wday: i32,
     /// day of the year
-    tm_yday: i32,
+    pub tm_yday: i32,
     /// 1 if daylight saving time is in effect
-    tm_isdst: i32,
+    pub tm_isdst: i32,
     /// timezone offset from UTC in seconds
-    tm_gmtoff: i32,
+    pub tm_gmtoff: i32,
     /// abbreviated timezone name (not `const` in C but why not?)
-    tm_zone: ConstPtr<u8>,
+    pub tm_zone: ConstPtr<u8>,
 }
 unsafe impl SafeRead for tm {}
 
 // Helpers for timestamp to calendar date conversion, all of these are our own
 // original implementation details.
+const DAY_SECONDS: i64 = 24 * 60 * 60;
 const fn is_leap_year(year: i32) -> bool {
     year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
 }
@@ -128,6 +173,31 @@ const fn calc_month_to_day(leap_year: bool) -> [i32; 12] {
     }
     table
 }
+/// Number of days between the UNIX epoch and a date in the proleptic Gregorian
+/// calendar. The month counts from 1, and the day may be out of range (it
+/// overflows into other months).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Based on Howard Hinnant's algorithm, with March as the first month so
+    // that leap days are at the end of the year.
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * i64::from(CYCLE_DAYS) + day_of_era - 719468
+}
+/// Inverse of [days_from_civil], but only the year.
+fn year_for_days(days: i64) -> i64 {
+    let days = days + 719468;
+    let era = days.div_euclid(i64::from(CYCLE_DAYS));
+    let day_of_era = days - era * i64::from(CYCLE_DAYS);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    era * 400 + year_of_era + (month_from_march >= 10) as i64
+}
 pub fn timestamp_to_calendar_date(timestamp: time_t) -> tm {
     let seconds_since_unix_epoch: i32 = timestamp;
 
@@ -180,10 +250,9 @@ pub fn timestamp_to_calendar_date(timestamp: time_t) -> tm {
         tm_year: year - 1900,
         tm_wday: day_of_the_week,
         tm_yday: day_in_year,
-        // This function always returns UTC
+        // This function always returns UTC, callers can adjust these.
         tm_isdst: 0,
         tm_gmtoff: 0,
-        // TODO: this probably shouldn't be NULL?
         tm_zone: Ptr::null(),
     }
 }
@@ -241,30 +310,559 @@ fn test_timestamp_to_calendar_date() {
     do_test("Sat, 1955-03-26T20:47:45", -466053135);
 }
 
+#[cfg(test)]
+#[test]
+fn test_days_from_civil() {
+    for (year, month, day, days) in [
+        (1970, 1, 1, 0),
+        (2000, 3, 1, 11017),
+        (1969, 12, 31, -1),
+        (2024, 2, 29, 19782),
+        (1900, 1, 1, -25567),
+        // Days overflow into the next month.
+        (2023, 1, 32, 19389),
+    ] {
+        assert_eq!(days_from_civil(year, month, day), days);
+        assert_eq!(year_for_days(days), if day == 32 { 2023 } else { year });
+    }
+}
+
+/// Shared implementation of `gmtime_r` and `localtime_r`. [None] means UTC.
+fn calendar_date_in_zone(env: &mut Environment, timestamp: time_t, zone: Option<&TimeZone>) -> tm {
+    let Some(zone) = zone else {
+        let mut tm = timestamp_to_calendar_date(timestamp);
+        tm.tm_zone = zone_abbreviation_ptr(env, "UTC");
+        return tm;
+    };
+    let local_time_type = zone.local_time_type(timestamp.into());
+    let mut tm = timestamp_to_calendar_date(timestamp.wrapping_add(local_time_type.utc_offset));
+    tm.tm_isdst = local_time_type.is_dst.into();
+    tm.tm_gmtoff = local_time_type.utc_offset;
+    tm.tm_zone = zone_abbreviation_ptr(env, &local_time_type.abbreviation);
+    tm
+}
+
 fn gmtime_r(env: &mut Environment, timestamp: ConstPtr<time_t>, res: MutPtr<tm>) -> MutPtr<tm> {
     let timestamp = env.mem.read(timestamp);
-    let calendar_date = timestamp_to_calendar_date(timestamp);
+    let calendar_date = calendar_date_in_zone(env, timestamp, None);
     env.mem.write(res, calendar_date);
     res
 }
-fn gmtime(env: &mut Environment, timestamp: ConstPtr<time_t>) -> MutPtr<tm> {
-    let tmp = *env
-        .libc_state
+fn gmtime_tmp(env: &mut Environment) -> MutPtr<tm> {
+    // This doesn't have to be a unique temporary, gmtime and localtime are
+    // allowed to share it.
+    *env.libc_state
         .time
         .gmtime_tmp
-        .get_or_insert_with(|| env.mem.alloc(guest_size_of::<tm>()).cast());
+        .get_or_insert_with(|| env.mem.alloc(guest_size_of::<tm>()).cast())
+}
+fn gmtime(env: &mut Environment, timestamp: ConstPtr<time_t>) -> MutPtr<tm> {
+    let tmp = gmtime_tmp(env);
     gmtime_r(env, timestamp, tmp)
 }
 
 fn localtime_r(env: &mut Environment, timestamp: ConstPtr<time_t>, res: MutPtr<tm>) -> MutPtr<tm> {
-    // TODO: don't assume local time is UTC?
-    gmtime_r(env, timestamp, res)
+    let timestamp = env.mem.read(timestamp);
+    let zone = local_time_zone(env);
+    let calendar_date = calendar_date_in_zone(env, timestamp, Some(&zone));
+    env.mem.write(res, calendar_date);
+    res
 }
 fn localtime(env: &mut Environment, timestamp: ConstPtr<time_t>) -> MutPtr<tm> {
-    // TODO: don't assume local time is UTC?
-    // This doesn't have to be a unique temporary, gmtime and localtime are
-    // allowed to share it.
-    gmtime(env, timestamp)
+    let tmp = gmtime_tmp(env);
+    localtime_r(env, timestamp, tmp)
+}
+
+/// Shared implementation of `mktime` and `timegm`. [None] means UTC.
+fn calendar_date_to_timestamp(
+    env: &mut Environment,
+    tm_ptr: MutPtr<tm>,
+    zone: Option<&TimeZone>,
+) -> time_t {
+    let tm {
+        tm_sec,
+        tm_min,
+        tm_hour,
+        tm_mday,
+        tm_mon,
+        tm_year,
+        tm_isdst,
+        ..
+    } = env.mem.read(tm_ptr);
+    // Out-of-range fields are allowed and overflow into the next field.
+    let year = i64::from(tm_year) + 1900 + i64::from(tm_mon).div_euclid(12);
+    let month = i64::from(tm_mon).rem_euclid(12) + 1;
+    let local = days_from_civil(year, month, tm_mday.into()) * DAY_SECONDS
+        + i64::from(tm_hour) * 3600
+        + i64::from(tm_min) * 60
+        + i64::from(tm_sec);
+    let timestamp = match zone {
+        Some(zone) => {
+            let is_dst = match tm_isdst {
+                0 => Some(false),
+                1.. => Some(true),
+                _ => None,
+            };
+            zone.time_for_local(local, is_dst)
+        }
+        None => local,
+    };
+    let Ok(timestamp) = time_t::try_from(timestamp) else {
+        log!("Warning: time {} doesn't fit in time_t", timestamp);
+        return -1;
+    };
+    // The fields are normalized as a side-effect.
+    let calendar_date = calendar_date_in_zone(env, timestamp, zone);
+    env.mem.write(tm_ptr, calendar_date);
+    timestamp
+}
+
+fn mktime(env: &mut Environment, tm_ptr: MutPtr<tm>) -> time_t {
+    let zone = local_time_zone(env);
+    let res = calendar_date_to_timestamp(env, tm_ptr, Some(&zone));
+    log_dbg!("mktime({:?}) => {}", tm_ptr, res);
+    res
+}
+fn timegm(env: &mut Environment, tm_ptr: MutPtr<tm>) -> time_t {
+    calendar_date_to_timestamp(env, tm_ptr, None)
+}
+
+fn tzset(env: &mut Environment) {
+    local_time_zone(env);
+}
+
+/// Create the `tzname` array: the abbreviations for standard time and daylight
+/// saving time in the local time zone. Like in Apple's libc, the second entry
+/// is the standard time abbreviation if the zone doesn't currently have DST.
+fn create_tzname(env: &mut Environment) -> ConstVoidPtr {
+    let zone = local_time_zone(env);
+    let now: i64 = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .try_into()
+        .unwrap();
+    // Look at a year's worth of dates so both halves of the year are seen.
+    let types: Vec<_> = (0..12)
+        .map(|month| zone.local_time_type(now + month * 31 * DAY_SECONDS))
+        .collect();
+    let standard = types.iter().find(|t| !t.is_dst).unwrap_or(&types[0]);
+    let daylight = types.iter().find(|t| t.is_dst).unwrap_or(standard);
+    let standard = zone_abbreviation_ptr(env, &standard.abbreviation);
+    let daylight = zone_abbreviation_ptr(env, &daylight.abbreviation);
+    let tzname: MutPtr<ConstPtr<u8>> = env.mem.alloc(guest_size_of::<ConstPtr<u8>>() * 2).cast();
+    env.mem.write(tzname, standard);
+    env.mem.write(tzname + 1, daylight);
+    tzname.cast().cast_const()
+}
+
+fn difftime(_env: &mut Environment, time1: time_t, time0: time_t) -> f64 {
+    f64::from(time1) - f64::from(time0)
+}
+
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// ISO 8601 week-based year and week number.
+fn iso_week(year: i32, yday: i32, wday: i32) -> (i32, i32) {
+    fn weeks_in_year(year: i32) -> i32 {
+        let p =
+            |y: i32| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)).rem_euclid(7);
+        if p(year) == 4 || p(year - 1) == 3 {
+            53
+        } else {
+            52
+        }
+    }
+    // Monday is 1, Sunday is 7.
+    let iso_wday = if wday == 0 { 7 } else { wday };
+    let week = (yday + 1 - iso_wday + 10) / 7;
+    if week < 1 {
+        (year - 1, weeks_in_year(year - 1))
+    } else if week > weeks_in_year(year) {
+        (year + 1, 1)
+    } else {
+        (year, week)
+    }
+}
+
+/// Format a calendar date the way `strftime` does in the C locale.
+/// `zone_abbreviation` is the string `tm_zone` points to.
+fn format_calendar_date(tm: &tm, zone_abbreviation: &str, format: &[u8], out: &mut Vec<u8>) {
+    use std::io::Write;
+
+    let &tm {
+        tm_sec,
+        tm_min,
+        tm_hour,
+        tm_mday,
+        tm_mon,
+        tm_year,
+        tm_wday,
+        tm_yday,
+        tm_gmtoff,
+        ..
+    } = tm;
+    let year = tm_year + 1900;
+    let weekday = WEEKDAY_NAMES.get(tm_wday as usize).copied().unwrap_or("?");
+    let month = MONTH_NAMES.get(tm_mon as usize).copied().unwrap_or("?");
+    let hour_12 = match tm_hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+
+    let mut format = format.iter().copied();
+    while let Some(c) = format.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        let mut specifier = format.next();
+        // The E and O modifiers select alternative representations, which
+        // don't exist in the C locale.
+        if let Some(b'E' | b'O') = specifier {
+            specifier = format.next();
+        }
+        let _ = match specifier {
+            Some(b'a') => write!(out, "{}", weekday.get(..3).unwrap_or(weekday)),
+            Some(b'A') => write!(out, "{}", weekday),
+            Some(b'b' | b'h') => write!(out, "{}", month.get(..3).unwrap_or(month)),
+            Some(b'B') => write!(out, "{}", month),
+            Some(b'c') => {
+                format_calendar_date(tm, zone_abbreviation, b"%a %b %e %H:%M:%S %Y", out);
+                Ok(())
+            }
+            Some(b'C') => write!(out, "{:02}", year.div_euclid(100)),
+            Some(b'd') => write!(out, "{:02}", tm_mday),
+            Some(b'D' | b'x') => {
+                format_calendar_date(tm, zone_abbreviation, b"%m/%d/%y", out);
+                Ok(())
+            }
+            Some(b'e') => write!(out, "{:2}", tm_mday),
+            Some(b'F') => {
+                format_calendar_date(tm, zone_abbreviation, b"%Y-%m-%d", out);
+                Ok(())
+            }
+            Some(b'g') => write!(
+                out,
+                "{:02}",
+                iso_week(year, tm_yday, tm_wday).0.rem_euclid(100)
+            ),
+            Some(b'G') => write!(out, "{}", iso_week(year, tm_yday, tm_wday).0),
+            Some(b'H') => write!(out, "{:02}", tm_hour),
+            Some(b'I') => write!(out, "{:02}", hour_12),
+            Some(b'j') => write!(out, "{:03}", tm_yday + 1),
+            Some(b'k') => write!(out, "{:2}", tm_hour),
+            Some(b'l') => write!(out, "{:2}", hour_12),
+            Some(b'm') => write!(out, "{:02}", tm_mon + 1),
+            Some(b'M') => write!(out, "{:02}", tm_min),
+            Some(b'n') => {
+                out.push(b'\n');
+                Ok(())
+            }
+            Some(b'p') => write!(out, "{}", if tm_hour < 12 { "AM" } else { "PM" }),
+            Some(b'r') => {
+                format_calendar_date(tm, zone_abbreviation, b"%I:%M:%S %p", out);
+                Ok(())
+            }
+            Some(b'R') => {
+                format_calendar_date(tm, zone_abbreviation, b"%H:%M", out);
+                Ok(())
+            }
+            Some(b's') => {
+                let local = days_from_civil(year.into(), i64::from(tm_mon) + 1, tm_mday.into())
+                    * DAY_SECONDS
+                    + i64::from(tm_hour) * 3600
+                    + i64::from(tm_min) * 60
+                    + i64::from(tm_sec);
+                write!(out, "{}", local - i64::from(tm_gmtoff))
+            }
+            Some(b'S') => write!(out, "{:02}", tm_sec),
+            Some(b't') => {
+                out.push(b'\t');
+                Ok(())
+            }
+            Some(b'T' | b'X') => {
+                format_calendar_date(tm, zone_abbreviation, b"%H:%M:%S", out);
+                Ok(())
+            }
+            Some(b'u') => write!(out, "{}", if tm_wday == 0 { 7 } else { tm_wday }),
+            Some(b'U') => write!(out, "{:02}", (tm_yday + 7 - tm_wday) / 7),
+            Some(b'V') => write!(out, "{:02}", iso_week(year, tm_yday, tm_wday).1),
+            Some(b'w') => write!(out, "{}", tm_wday),
+            Some(b'W') => write!(out, "{:02}", (tm_yday + 7 - (tm_wday + 6) % 7) / 7),
+            Some(b'y') => write!(out, "{:02}", year.rem_euclid(100)),
+            Some(b'Y') => write!(out, "{}", year),
+            Some(b'z') => {
+                let offset = tm_gmtoff;
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.unsigned_abs() / 60;
+                write!(out, "{}{:02}{:02}", sign, offset / 60, offset % 60)
+            }
+            Some(b'Z') => write!(out, "{}", zone_abbreviation),
+            Some(b'+') => {
+                format_calendar_date(tm, zone_abbreviation, b"%a %b %e %H:%M:%S %Z %Y", out);
+                Ok(())
+            }
+            Some(b'%') => write!(out, "%"),
+            Some(other) => {
+                log!("Warning: unknown strftime() conversion %{}", other as char);
+                write!(out, "%{}", other as char)
+            }
+            None => write!(out, "%"),
+        };
+    }
+}
+
+fn strftime(
+    env: &mut Environment,
+    s: MutPtr<u8>,
+    maxsize: GuestUSize,
+    format: ConstPtr<u8>,
+    tm_ptr: ConstPtr<tm>,
+) -> GuestUSize {
+    let tm = env.mem.read(tm_ptr);
+    let zone_abbreviation = if tm.tm_zone.is_null() {
+        String::new()
+    } else {
+        env.mem.cstr_at_utf8(tm.tm_zone).unwrap_or("").to_string()
+    };
+    let mut out = Vec::new();
+    format_calendar_date(&tm, &zone_abbreviation, env.mem.cstr_at(format), &mut out);
+    log_dbg!(
+        "strftime({:?}, {:#x}, {:?}, {:?}) => {:?}",
+        s,
+        maxsize,
+        env.mem.cstr_at_utf8(format),
+        tm_ptr,
+        std::str::from_utf8(&out)
+    );
+    let len: GuestUSize = out.len().try_into().unwrap();
+    // The size includes the null terminator. If it doesn't fit, the contents
+    // of the buffer are unspecified.
+    if len >= maxsize {
+        return 0;
+    }
+    out.push(b'\0');
+    env.mem.bytes_at_mut(s, len + 1).copy_from_slice(&out);
+    len
+}
+
+/// Parse a date according to a `strptime` format string. Returns the number of
+/// bytes consumed, or [None] if the input doesn't match.
+fn parse_calendar_date(input: &[u8], format: &[u8], tm: &mut tm) -> Option<usize> {
+    fn skip_whitespace(input: &[u8], pos: &mut usize) {
+        while input.get(*pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            *pos += 1;
+        }
+    }
+    fn number(input: &[u8], pos: &mut usize, max_digits: usize, range: (i32, i32)) -> Option<i32> {
+        skip_whitespace(input, pos);
+        let negative = input.get(*pos) == Some(&b'-');
+        if negative || input.get(*pos) == Some(&b'+') {
+            *pos += 1;
+        }
+        let digits = input[*pos..]
+            .iter()
+            .take(max_digits)
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        let value: i32 = std::str::from_utf8(&input[*pos..*pos + digits])
+            .unwrap()
+            .parse()
+            .ok()?;
+        let value = if negative { -value } else { value };
+        *pos += digits;
+        (range.0..=range.1).contains(&value).then_some(value)
+    }
+    fn name(input: &[u8], pos: &mut usize, names: &[&str]) -> Option<i32> {
+        for (i, name) in names.iter().enumerate() {
+            // Full names are tried first, since abbreviations are prefixes.
+            for candidate in [name.as_bytes(), &name.as_bytes()[..3]] {
+                if input
+                    .get(*pos..*pos + candidate.len())
+                    .is_some_and(|s| s.eq_ignore_ascii_case(candidate))
+                {
+                    *pos += candidate.len();
+                    return Some(i as i32);
+                }
+            }
+        }
+        None
+    }
+    fn parse(
+        input: &[u8],
+        pos: &mut usize,
+        format: &[u8],
+        tm: &mut tm,
+        pm: &mut Option<bool>,
+        have_date: &mut [bool; 3],
+    ) -> Option<()> {
+        let mut format = format.iter().copied().peekable();
+        while let Some(c) = format.next() {
+            if c.is_ascii_whitespace() {
+                skip_whitespace(input, pos);
+                continue;
+            }
+            if c != b'%' {
+                if input.get(*pos) != Some(&c) {
+                    return None;
+                }
+                *pos += 1;
+                continue;
+            }
+            let mut specifier = format.next()?;
+            if matches!(specifier, b'E' | b'O') {
+                specifier = format.next()?;
+            }
+            match specifier {
+                b'a' | b'A' => tm.tm_wday = name(input, pos, &WEEKDAY_NAMES)?,
+                b'b' | b'B' | b'h' => {
+                    tm.tm_mon = name(input, pos, &MONTH_NAMES)?;
+                    have_date[1] = true;
+                }
+                b'C' => {
+                    let century = number(input, pos, 2, (0, 99))?;
+                    tm.tm_year = century * 100 + tm.tm_year.rem_euclid(100) - 1900;
+                }
+                b'd' | b'e' => {
+                    tm.tm_mday = number(input, pos, 2, (1, 31))?;
+                    have_date[2] = true;
+                }
+                b'D' => parse(input, pos, b"%m/%d/%y", tm, pm, have_date)?,
+                b'F' => parse(input, pos, b"%Y-%m-%d", tm, pm, have_date)?,
+                b'H' | b'k' => tm.tm_hour = number(input, pos, 2, (0, 23))?,
+                b'I' | b'l' => tm.tm_hour = number(input, pos, 2, (1, 12))?,
+                b'j' => tm.tm_yday = number(input, pos, 3, (1, 366))? - 1,
+                b'm' => {
+                    tm.tm_mon = number(input, pos, 2, (1, 12))? - 1;
+                    have_date[1] = true;
+                }
+                b'M' => tm.tm_min = number(input, pos, 2, (0, 59))?,
+                b'n' | b't' => skip_whitespace(input, pos),
+                b'p' => {
+                    skip_whitespace(input, pos);
+                    let am_pm = input.get(*pos..*pos + 2)?;
+                    *pm = Some(if am_pm.eq_ignore_ascii_case(b"AM") {
+                        false
+                    } else if am_pm.eq_ignore_ascii_case(b"PM") {
+                        true
+                    } else {
+                        return None;
+                    });
+                    *pos += 2;
+                }
+                b'r' => parse(input, pos, b"%I:%M:%S %p", tm, pm, have_date)?,
+                b'R' => parse(input, pos, b"%H:%M", tm, pm, have_date)?,
+                b'S' => tm.tm_sec = number(input, pos, 2, (0, 60))?,
+                b'T' => parse(input, pos, b"%H:%M:%S", tm, pm, have_date)?,
+                b'y' => {
+                    let year = number(input, pos, 2, (0, 99))?;
+                    // POSIX: 69-99 are 1969-1999, 0-68 are 2000-2068.
+                    tm.tm_year = if year >= 69 { year } else { year + 100 };
+                    have_date[0] = true;
+                }
+                b'Y' => {
+                    tm.tm_year = number(input, pos, 4, (0, 9999))? - 1900;
+                    have_date[0] = true;
+                }
+                b'z' => {
+                    skip_whitespace(input, pos);
+                    let sign = match input.get(*pos)? {
+                        b'+' => 1,
+                        b'-' => -1,
+                        _ => return None,
+                    };
+                    *pos += 1;
+                    let hhmm = number(input, pos, 4, (0, 2400))?;
+                    tm.tm_gmtoff = sign * ((hhmm / 100) * 3600 + (hhmm % 100) * 60);
+                }
+                b'Z' => {
+                    // Time zone names are accepted but ignored.
+                    while input.get(*pos).is_some_and(|c| c.is_ascii_alphabetic()) {
+                        *pos += 1;
+                    }
+                }
+                b'%' => {
+                    if input.get(*pos) != Some(&b'%') {
+                        return None;
+                    }
+                    *pos += 1;
+                }
+                other => {
+                    log!("TODO: strptime() conversion %{}", other as char);
+                    return None;
+                }
+            }
+        }
+        Some(())
+    }
+
+    let mut pos = 0;
+    let mut pm = None;
+    let mut have_date = [false; 3];
+    parse(input, &mut pos, format, tm, &mut pm, &mut have_date)?;
+    if let Some(pm) = pm {
+        tm.tm_hour = tm.tm_hour % 12 + if pm { 12 } else { 0 };
+    }
+    // Like Apple's implementation, fill in the day of the week and year if the
+    // date is fully known.
+    if have_date == [true; 3] {
+        let year = i64::from(tm.tm_year) + 1900;
+        let days = days_from_civil(year, i64::from(tm.tm_mon) + 1, tm.tm_mday.into());
+        tm.tm_wday = (days + 4).rem_euclid(7) as i32;
+        tm.tm_yday = (days - days_from_civil(year, 1, 1)) as i32;
+    }
+    Some(pos)
+}
+
+fn strptime(
+    env: &mut Environment,
+    buf: ConstPtr<u8>,
+    format: ConstPtr<u8>,
+    tm_ptr: MutPtr<tm>,
+) -> ConstPtr<u8> {
+    let mut tm = env.mem.read(tm_ptr);
+    let input = env.mem.cstr_at(buf);
+    let format_bytes = env.mem.cstr_at(format);
+    let res = parse_calendar_date(input, format_bytes, &mut tm);
+    log_dbg!(
+        "strptime({:?} {:?}, {:?}, {:?}) => {:?}",
+        buf,
+        std::str::from_utf8(input),
+        std::str::from_utf8(format_bytes),
+        tm_ptr,
+        res
+    );
+    let Some(consumed) = res else {
+        return Ptr::null();
+    };
+    env.mem.write(tm_ptr, tm);
+    buf + consumed.try_into().unwrap()
 }
 
 // sys/time.h (POSIX)
@@ -274,17 +872,18 @@ type suseconds_t = i32;
 
 #[allow(non_camel_case_types)]
 #[repr(C, packed)]
-struct timeval {
-    tv_sec: time_t,
-    tv_usec: suseconds_t,
+pub struct timeval {
+    pub tv_sec: time_t,
+    pub tv_usec: suseconds_t,
 }
 unsafe impl SafeRead for timeval {}
 
 #[allow(non_camel_case_types)]
+#[derive(Default)]
 #[repr(C, packed)]
-struct timespec {
-    tv_sec: time_t,
-    tv_nsec: i32,
+pub struct timespec {
+    pub tv_sec: time_t,
+    pub tv_nsec: i32,
 }
 unsafe impl SafeRead for timespec {}
 
@@ -302,11 +901,17 @@ fn gettimeofday(
     timezone_ptr: MutPtr<timezone>,
 ) -> i32 {
     if !timezone_ptr.is_null() {
+        let zone = local_time_zone(env);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let local_time_type = zone.local_time_type(now as i64);
         env.mem.write(
             timezone_ptr,
             timezone {
-                tz_minuteswest: 0,
-                tz_dsttime: 0,
+                tz_minuteswest: -local_time_type.utc_offset / 60,
+                tz_dsttime: local_time_type.is_dst.into(),
             },
         );
     }
@@ -343,6 +948,8 @@ fn nanosleep(env: &mut Environment, rqtp: ConstPtr<timespec>, _rmtp: MutPtr<time
     0 // success
 }
 
+pub const CONSTANTS: ConstantExports = &[("_tzname", HostConstant::CustomWithEnv(create_tzname))];
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(clock()),
     export_c_func!(time(_)),
@@ -350,6 +957,12 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(gmtime(_)),
     export_c_func!(localtime_r(_, _)),
     export_c_func!(localtime(_)),
+    export_c_func!(mktime(_)),
+    export_c_func!(timegm(_)),
+    export_c_func!(tzset()),
+    export_c_func!(difftime(_, _)),
+    export_c_func!(strftime(_, _, _, _)),
+    export_c_func!(strptime(_, _, _)),
     export_c_func!(gettimeofday(_, _)),
     export_c_func!(nanosleep(_, _)),
 ];
@@ -0,0 +1,556 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Time zone database, shared by `time.h` and Foundation's `NSTimeZone`.
+//!
+//! Time zones are loaded from the host's zoneinfo files, which use the TZif
+//! format ([RFC 8536](https://www.rfc-editor.org/rfc/rfc8536)). Times after
+//! the last transition in a file, and the `TZ` environment variable, use POSIX
+//! TZ strings. If the host has no zoneinfo files (e.g. Windows), UTC is used.
+
+use super::{days_from_civil, year_for_days, DAY_SECONDS};
+
+/// Directories that zoneinfo files might be found in on the host.
+const ZONEINFO_DIRS: &[&str] = &[
+    "/usr/share/zoneinfo",
+    "/var/db/timezone/zoneinfo",
+    "/usr/share/lib/zoneinfo",
+];
+
+/// The offset from UTC, DST status and abbreviation used for some span of
+/// time in a time zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalTimeType {
+    /// Seconds east of UTC.
+    pub utc_offset: i32,
+    pub is_dst: bool,
+    /// Abbreviated name, e.g. `CET`.
+    pub abbreviation: String,
+}
+
+#[derive(Debug)]
+pub struct TimeZone {
+    /// Identifier in the time zone database, e.g. `Europe/Berlin`.
+    name: String,
+    /// UTC times at which the local time type changes, each with an index into
+    /// [Self::types]. Sorted.
+    transitions: Vec<(i64, usize)>,
+    /// The first entry is used for times before the first transition.
+    types: Vec<LocalTimeType>,
+    /// Rule for times after the last transition.
+    rule: Option<PosixRule>,
+}
+
+/// Parsed POSIX TZ string, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[derive(Debug)]
+struct PosixRule {
+    std: LocalTimeType,
+    dst: Option<DstRule>,
+}
+
+#[derive(Debug)]
+struct DstRule {
+    dst: LocalTimeType,
+    /// When DST starts, as a day and a local time of day in standard time.
+    start: (RuleDay, i32),
+    /// When DST ends, as a day and a local time of day in daylight time.
+    end: (RuleDay, i32),
+}
+
+#[derive(Debug)]
+enum RuleDay {
+    /// `Jn`: 1-based day of the year, not counting February 29.
+    JulianNoLeap(u16),
+    /// `n`: 0-based day of the year, counting February 29.
+    Julian(u16),
+    /// `Mm.w.d`: day `d` (0 = Sunday) of week `w` (1-5, 5 is the last) of
+    /// month `m` (1-12).
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+impl TimeZone {
+    pub fn utc() -> TimeZone {
+        TimeZone::fixed("UTC".to_string(), 0, "UTC".to_string())
+    }
+
+    /// A time zone with a fixed offset from UTC, in seconds east.
+    pub fn with_offset(utc_offset: i32) -> TimeZone {
+        if utc_offset == 0 {
+            return TimeZone::fixed("GMT".to_string(), 0, "GMT".to_string());
+        }
+        let sign = if utc_offset < 0 { '-' } else { '+' };
+        let hours = utc_offset.unsigned_abs() / 3600;
+        let minutes = (utc_offset.unsigned_abs() % 3600) / 60;
+        let name = format!("GMT{}{:02}{:02}", sign, hours, minutes);
+        let abbreviation = if minutes == 0 {
+            format!("GMT{}{}", sign, hours)
+        } else {
+            format!("GMT{}{}:{:02}", sign, hours, minutes)
+        };
+        TimeZone::fixed(name, utc_offset, abbreviation)
+    }
+
+    fn fixed(name: String, utc_offset: i32, abbreviation: String) -> TimeZone {
+        TimeZone {
+            name,
+            transitions: Vec::new(),
+            types: vec![LocalTimeType {
+                utc_offset,
+                is_dst: false,
+                abbreviation,
+            }],
+            rule: None,
+        }
+    }
+
+    /// Load a time zone by its name in the database (e.g. `Europe/Berlin`),
+    /// or from a POSIX TZ string (e.g. `EST5EDT`).
+    pub fn from_name(name: &str) -> Option<TimeZone> {
+        if matches!(name, "UTC" | "GMT") {
+            return Some(TimeZone::fixed(name.to_string(), 0, name.to_string()));
+        }
+        if !name.is_empty() && !name.starts_with('/') && !name.split('/').any(|c| c == "..") {
+            for dir in ZONEINFO_DIRS {
+                let Ok(data) = std::fs::read(format!("{}/{}", dir, name)) else {
+                    continue;
+                };
+                if let Some(zone) = TimeZone::from_tzif(name.to_string(), &data) {
+                    return Some(zone);
+                }
+            }
+        }
+        let rule = parse_posix_rule(name)?;
+        Some(TimeZone {
+            name: name.to_string(),
+            transitions: Vec::new(),
+            types: vec![rule.std.clone()],
+            rule: Some(rule),
+        })
+    }
+
+    /// Get the host's local time zone.
+    pub fn host() -> TimeZone {
+        if let Ok(tz) = std::env::var("TZ") {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz);
+            if let Some(zone) = TimeZone::from_name(tz) {
+                return zone;
+            }
+            log!("Warning: couldn't load time zone {:?} from TZ", tz);
+        }
+
+        // /etc/localtime is normally a symlink into the zoneinfo directory,
+        // which is the only reliable way to get the zone's name.
+        let name = std::fs::read_link("/etc/localtime")
+            .ok()
+            .and_then(|target| {
+                let target = target.to_str()?;
+                let (_, name) = target.split_once("zoneinfo/")?;
+                Some(name.to_string())
+            })
+            .or_else(|| {
+                let name = std::fs::read_to_string("/etc/timezone").ok()?;
+                Some(name.trim().to_string())
+            });
+        if let Ok(data) = std::fs::read("/etc/localtime") {
+            let name = name.unwrap_or_else(|| "Local".to_string());
+            if let Some(zone) = TimeZone::from_tzif(name, &data) {
+                return zone;
+            }
+        } else if let Some(zone) = name.and_then(|name| TimeZone::from_name(&name)) {
+            return zone;
+        }
+
+        // TODO: Windows and Android time zones
+        log!("Warning: couldn't determine the host time zone, using UTC");
+        TimeZone::utc()
+    }
+
+    fn from_tzif(name: String, data: &[u8]) -> Option<TimeZone> {
+        struct Header {
+            version: u8,
+            isutcnt: usize,
+            isstdcnt: usize,
+            leapcnt: usize,
+            timecnt: usize,
+            typecnt: usize,
+            charcnt: usize,
+        }
+        fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+            Some(u32::from_be_bytes(
+                data.get(at..at + 4)?.try_into().unwrap(),
+            ))
+        }
+        fn read_header(data: &[u8]) -> Option<Header> {
+            if data.get(..4)? != b"TZif" {
+                return None;
+            }
+            let count = |i: usize| read_u32(data, 20 + i * 4).map(|count| count as usize);
+            Some(Header {
+                version: *data.get(4)?,
+                isutcnt: count(0)?,
+                isstdcnt: count(1)?,
+                leapcnt: count(2)?,
+                timecnt: count(3)?,
+                typecnt: count(4)?,
+                charcnt: count(5)?,
+            })
+        }
+        const HEADER_SIZE: usize = 44;
+
+        let v1_header = read_header(data)?;
+        let v1_size = |h: &Header, time_size: usize| {
+            h.timecnt * time_size
+                + h.timecnt
+                + h.typecnt * 6
+                + h.charcnt
+                + h.leapcnt * (time_size + 4)
+                + h.isstdcnt
+                + h.isutcnt
+        };
+        // Version 2+ files repeat the data with 64-bit times, followed by a
+        // footer with a POSIX TZ string.
+        let (header, time_size, body) = if v1_header.version >= b'2' {
+            let v2_data = data.get(HEADER_SIZE + v1_size(&v1_header, 4)..)?;
+            (read_header(v2_data)?, 8, v2_data.get(HEADER_SIZE..)?)
+        } else {
+            (v1_header, 4, data.get(HEADER_SIZE..)?)
+        };
+        if header.typecnt == 0 {
+            return None;
+        }
+
+        let times_at = 0;
+        let indices_at = times_at + header.timecnt * time_size;
+        let types_at = indices_at + header.timecnt;
+        let chars_at = types_at + header.typecnt * 6;
+        let chars = body.get(chars_at..chars_at + header.charcnt)?;
+
+        let mut transitions = Vec::with_capacity(header.timecnt);
+        for i in 0..header.timecnt {
+            let at = times_at + i * time_size;
+            let time = if time_size == 8 {
+                i64::from_be_bytes(body.get(at..at + 8)?.try_into().unwrap())
+            } else {
+                i64::from(read_u32(body, at)? as i32)
+            };
+            let index = *body.get(indices_at + i)? as usize;
+            if index >= header.typecnt {
+                return None;
+            }
+            transitions.push((time, index));
+        }
+
+        let mut types = Vec::with_capacity(header.typecnt);
+        for i in 0..header.typecnt {
+            let at = types_at + i * 6;
+            let utc_offset = read_u32(body, at)? as i32;
+            let is_dst = *body.get(at + 4)? != 0;
+            let abbreviation_at = *body.get(at + 5)? as usize;
+            let abbreviation = chars.get(abbreviation_at..)?;
+            let abbreviation_len = abbreviation.iter().position(|&c| c == b'\0')?;
+            let abbreviation =
+                String::from_utf8_lossy(&abbreviation[..abbreviation_len]).into_owned();
+            types.push(LocalTimeType {
+                utc_offset,
+                is_dst,
+                abbreviation,
+            });
+        }
+
+        let rule = if time_size == 8 {
+            body.get(v1_size(&header, 8)..)
+                .and_then(|footer| footer.strip_prefix(b"\n"))
+                .and_then(|footer| footer.split(|&c| c == b'\n').next())
+                .and_then(|footer| std::str::from_utf8(footer).ok())
+                .and_then(parse_posix_rule)
+        } else {
+            None
+        };
+
+        Some(TimeZone {
+            name,
+            transitions,
+            types,
+            rule,
+        })
+    }
+
+    /// Identifier in the time zone database, e.g. `Europe/Berlin`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the local time type in effect at a time in seconds since the UNIX
+    /// epoch.
+    pub fn local_time_type(&self, time: i64) -> &LocalTimeType {
+        match self.transitions.last() {
+            Some(&(last, _)) if time >= last && self.rule.is_some() => {
+                self.rule.as_ref().unwrap().local_time_type(time)
+            }
+            None if self.rule.is_some() => self.rule.as_ref().unwrap().local_time_type(time),
+            _ => {
+                let idx = self.transitions.partition_point(|&(t, _)| t <= time);
+                if idx == 0 {
+                    &self.types[0]
+                } else {
+                    &self.types[self.transitions[idx - 1].1]
+                }
+            }
+        }
+    }
+
+    /// Convert a local time (seconds since the UNIX epoch as if local time
+    /// was UTC) to a time in seconds since the UNIX epoch, as `mktime()` does.
+    /// `is_dst` disambiguates times that occur twice when DST ends.
+    pub fn time_for_local(&self, local: i64, is_dst: Option<bool>) -> i64 {
+        // The offset at the local time is a good guess for the offset at the
+        // UTC time. A second iteration settles on the right one unless the
+        // local time is skipped or repeated by a transition.
+        let mut time = local - i64::from(self.local_time_type(local).utc_offset);
+        time = local - i64::from(self.local_time_type(time).utc_offset);
+        let Some(is_dst) = is_dst else {
+            return time;
+        };
+        if self.local_time_type(time).is_dst == is_dst {
+            return time;
+        }
+        // Look for a nearby offset with the requested DST status.
+        for nearby in [time - DAY_SECONDS, time + DAY_SECONDS] {
+            let local_time_type = self.local_time_type(nearby);
+            if local_time_type.is_dst == is_dst {
+                return local - i64::from(local_time_type.utc_offset);
+            }
+        }
+        time
+    }
+}
+
+impl PosixRule {
+    fn local_time_type(&self, time: i64) -> &LocalTimeType {
+        let Some(dst_rule) = &self.dst else {
+            return &self.std;
+        };
+        let year = year_for_days((time + i64::from(self.std.utc_offset)).div_euclid(DAY_SECONDS));
+        let (start_day, start_time) = &dst_rule.start;
+        let (end_day, end_time) = &dst_rule.end;
+        let start = start_day.days_since_epoch(year) * DAY_SECONDS + i64::from(*start_time)
+            - i64::from(self.std.utc_offset);
+        let end = end_day.days_since_epoch(year) * DAY_SECONDS + i64::from(*end_time)
+            - i64::from(dst_rule.dst.utc_offset);
+        let is_dst = if start < end {
+            start <= time && time < end
+        } else {
+            // Southern hemisphere: DST spans the new year.
+            !(end <= time && time < start)
+        };
+        if is_dst {
+            &dst_rule.dst
+        } else {
+            &self.std
+        }
+    }
+}
+
+impl RuleDay {
+    fn days_since_epoch(&self, year: i64) -> i64 {
+        let is_leap_year = super::is_leap_year(year.rem_euclid(400) as i32);
+        let new_year = days_from_civil(year, 1, 1);
+        match *self {
+            RuleDay::JulianNoLeap(day) => {
+                let day = i64::from(day) - 1;
+                new_year + day + i64::from(is_leap_year && day >= 59)
+            }
+            RuleDay::Julian(day) => new_year + i64::from(day),
+            RuleDay::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = days_from_civil(year, month.into(), 1);
+                let next_month = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, (month + 1).into(), 1)
+                };
+                // 1970-01-01 was a Thursday.
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first
+                    + (i64::from(weekday) - first_weekday).rem_euclid(7)
+                    + (i64::from(week) - 1) * 7;
+                // Week 5 means the last one in the month.
+                while day >= next_month {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+/// Parse a POSIX TZ string, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+fn parse_posix_rule(s: &str) -> Option<PosixRule> {
+    let mut s = s.as_bytes();
+
+    fn parse_name(s: &mut &[u8]) -> Option<String> {
+        let name = if let Some(rest) = s.strip_prefix(b"<") {
+            let len = rest.iter().position(|&c| c == b'>')?;
+            let name = &rest[..len];
+            *s = &rest[len + 1..];
+            name
+        } else {
+            let len = s.iter().take_while(|c| c.is_ascii_alphabetic()).count();
+            let name = &s[..len];
+            *s = &s[len..];
+            name
+        };
+        (name.len() >= 3).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+    fn parse_number(s: &mut &[u8]) -> Option<i32> {
+        let len = s.iter().take_while(|c| c.is_ascii_digit()).count();
+        let number = std::str::from_utf8(&s[..len]).ok()?.parse().ok()?;
+        *s = &s[len..];
+        Some(number)
+    }
+    /// `[+-]hh[:mm[:ss]]`, in seconds.
+    fn parse_time(s: &mut &[u8]) -> Option<i32> {
+        let sign = match s.first() {
+            Some(b'-') => {
+                *s = &s[1..];
+                -1
+            }
+            Some(b'+') => {
+                *s = &s[1..];
+                1
+            }
+            _ => 1,
+        };
+        let mut seconds = parse_number(s)? * 3600;
+        for unit in [60, 1] {
+            let Some(rest) = s.strip_prefix(b":") else {
+                break;
+            };
+            *s = rest;
+            seconds += parse_number(s)? * unit;
+        }
+        Some(sign * seconds)
+    }
+    fn parse_rule_day(s: &mut &[u8]) -> Option<(RuleDay, i32)> {
+        let day = if let Some(rest) = s.strip_prefix(b"M") {
+            *s = rest;
+            let month = parse_number(s)?;
+            *s = s.strip_prefix(b".")?;
+            let week = parse_number(s)?;
+            *s = s.strip_prefix(b".")?;
+            let weekday = parse_number(s)?;
+            if !(1..=12).contains(&month) || !(1..=5).contains(&week) || !(0..=6).contains(&weekday)
+            {
+                return None;
+            }
+            RuleDay::MonthWeekDay {
+                month: month as u8,
+                week: week as u8,
+                weekday: weekday as u8,
+            }
+        } else if let Some(rest) = s.strip_prefix(b"J") {
+            *s = rest;
+            RuleDay::JulianNoLeap(parse_number(s)?.clamp(1, 365) as u16)
+        } else {
+            RuleDay::Julian(parse_number(s)?.clamp(0, 365) as u16)
+        };
+        let time = if let Some(rest) = s.strip_prefix(b"/") {
+            *s = rest;
+            parse_time(s)?
+        } else {
+            2 * 3600
+        };
+        Some((day, time))
+    }
+
+    let std_abbreviation = parse_name(&mut s)?;
+    // POSIX offsets are west of UTC.
+    let std_offset = -parse_time(&mut s)?;
+    let std = LocalTimeType {
+        utc_offset: std_offset,
+        is_dst: false,
+        abbreviation: std_abbreviation,
+    };
+    if s.is_empty() {
+        return Some(PosixRule { std, dst: None });
+    }
+
+    let dst_abbreviation = parse_name(&mut s)?;
+    let dst_offset = if matches!(s.first(), Some(b'+' | b'-' | b'0'..=b'9')) {
+        -parse_time(&mut s)?
+    } else {
+        std_offset + 3600
+    };
+    let dst = LocalTimeType {
+        utc_offset: dst_offset,
+        is_dst: true,
+        abbreviation: dst_abbreviation,
+    };
+    let (start, end) = if let Some(rest) = s.strip_prefix(b",") {
+        s = rest;
+        let start = parse_rule_day(&mut s)?;
+        s = s.strip_prefix(b",")?;
+        let end = parse_rule_day(&mut s)?;
+        (start, end)
+    } else {
+        // The default rule is the US one.
+        (
+            (
+                RuleDay::MonthWeekDay {
+                    month: 3,
+                    week: 2,
+                    weekday: 0,
+                },
+                2 * 3600,
+            ),
+            (
+                RuleDay::MonthWeekDay {
+                    month: 11,
+                    week: 1,
+                    weekday: 0,
+                },
+                2 * 3600,
+            ),
+        )
+    };
+    if !s.is_empty() {
+        return None;
+    }
+    Some(PosixRule {
+        std,
+        dst: Some(DstRule { dst, start, end }),
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test_posix_rule() {
+    let zone = TimeZone::from_name("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+    let check = |time: i64, utc_offset: i32, abbreviation: &str| {
+        let local_time_type = zone.local_time_type(time);
+        assert_eq!(local_time_type.utc_offset, utc_offset);
+        assert_eq!(local_time_type.abbreviation, abbreviation);
+    };
+    // 2024-03-31 00:59:59 UTC and 01:00:00 UTC, when DST starts.
+    check(1711846799, 3600, "CET");
+    check(1711846800, 7200, "CEST");
+    // 2024-10-27 00:59:59 UTC and 01:00:00 UTC, when DST ends.
+    check(1729990799, 7200, "CEST");
+    check(1729990800, 3600, "CET");
+    // 02:30 local time on 2024-10-27 happens twice.
+    let local = 1729996200;
+    assert_eq!(zone.time_for_local(local, Some(true)), local - 7200);
+    assert_eq!(zone.time_for_local(local, Some(false)), local - 3600);
+
+    // Southern hemisphere, DST spans the new year.
+    let zone = TimeZone::from_name("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+    // 2024-01-01 00:00:00 UTC
+    assert!(zone.local_time_type(1704067200).is_dst);
+    // 2024-07-01 00:00:00 UTC
+    assert!(!zone.local_time_type(1719792000).is_dst);
+}
@@ -7,6 +7,7 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::GuestPath;
+use crate::libc::errno::{set_errno, EACCES, ENOENT, EPERM};
 use crate::libc::posix_io::{FileDescriptor, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 use crate::mem::ConstPtr;
 use crate::Environment;
@@ -44,6 +45,25 @@ fn getppid(_env: &mut Environment) -> pid_t {
     0
 }
 
+fn fork(env: &mut Environment) -> pid_t {
+    // Apps can't create processes on a stock device, because of the sandbox,
+    // so some apps call this to detect a jailbreak.
+    if env.options.jailbroken {
+        // Pretend to be the parent of a new process. touchHLE can't run the
+        // child, but apps doing this only want to know whether it worked.
+        log!("App called fork(), pretending it succeeded (--jailbroken)");
+        2
+    } else {
+        log!("App called fork(), returning EPERM like a stock device");
+        set_errno(env, EPERM);
+        -1
+    }
+}
+
+fn vfork(env: &mut Environment) -> pid_t {
+    fork(env)
+}
+
 fn isatty(_env: &mut Environment, fd: FileDescriptor) -> i32 {
     if [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO].contains(&fd) {
         1
@@ -56,23 +76,16 @@ fn access(env: &mut Environment, path: ConstPtr<u8>, mode: i32) -> i32 {
     let binding = env.mem.cstr_at_utf8(path).unwrap();
     let guest_path = GuestPath::new(&binding);
     let (exists, r, _, _) = env.fs.access(guest_path);
-    // TODO: set errno
-    match mode {
-        F_OK => {
-            if exists {
-                0
-            } else {
-                -1
-            }
-        }
-        R_OK => {
-            if r {
-                0
-            } else {
-                -1
-            }
-        }
+    let allowed = match mode {
+        F_OK => exists,
+        R_OK => r,
         _ => unimplemented!("{}", mode),
+    };
+    if allowed {
+        0
+    } else {
+        set_errno(env, if exists { EACCES } else { ENOENT });
+        -1
     }
 }
 
@@ -81,6 +94,8 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(usleep(_)),
     export_c_func!(getpid()),
     export_c_func!(getppid()),
+    export_c_func!(fork()),
+    export_c_func!(vfork()),
     export_c_func!(isatty(_)),
     export_c_func!(access(_, _)),
 ];
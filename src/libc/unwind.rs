@@ -0,0 +1,529 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `unwind.h` (exception unwinding).
+//!
+//! On ARM, iPhone OS uses "SjLj" (setjmp/longjmp) exception handling: every
+//! function that can catch an exception or needs cleanup registers a "function
+//! context" on entry, which includes a jump buffer for the function's landing
+//! pads, and unregisters it on exit. Unwinding means walking the list of
+//! function contexts, asking each function's personality routine what to do,
+//! and jumping into the landing pad of the function that handles the
+//! exception.
+//!
+//! C++ exceptions are raised and unwound by the bundled libgcc (see
+//! [super::cxxabi]), which owns the list of function contexts when it is
+//! loaded. Exceptions raised by host code (i.e. Objective-C exceptions, see
+//! [crate::objc::exceptions]) are unwound here instead, because unwinding
+//! might have to cross host stack frames, which only the host can do (see
+//! [Environment::long_jump]). This uses libgcc's function context list if
+//! libgcc is loaded, and otherwise keeps its own.
+//!
+//! Resources:
+//! - GCC's `unwind-sjlj.c` and `unwind-c.c`, which define the function context
+//!   structure, the unwinding procedure and the LSDA format.
+//! - The [Itanium C++ ABI's exception handling specification](https://itanium-cxx-abi.github.io/cxx-abi/abi-eh.html),
+//!   which SjLj unwinding follows the general shape of.
+
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::{Environment, ThreadId};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub struct State {
+    /// Most recently registered function context of each thread, if libgcc
+    /// isn't loaded.
+    function_contexts: HashMap<ThreadId, MutPtr<SjLjFunctionContext>>,
+    /// Exceptions raised by [raise_exception] that haven't been deleted yet.
+    raised_exceptions: HashSet<MutPtr<UnwindException>>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.unwind
+    }
+}
+
+#[allow(non_camel_case_types)]
+pub type _Unwind_Reason_Code = u32;
+pub const _URC_FATAL_PHASE2_ERROR: _Unwind_Reason_Code = 2;
+pub const _URC_FATAL_PHASE1_ERROR: _Unwind_Reason_Code = 3;
+pub const _URC_END_OF_STACK: _Unwind_Reason_Code = 5;
+pub const _URC_HANDLER_FOUND: _Unwind_Reason_Code = 6;
+pub const _URC_INSTALL_CONTEXT: _Unwind_Reason_Code = 7;
+pub const _URC_CONTINUE_UNWIND: _Unwind_Reason_Code = 8;
+
+#[allow(non_camel_case_types)]
+pub type _Unwind_Action = u32;
+pub const _UA_SEARCH_PHASE: _Unwind_Action = 1;
+pub const _UA_CLEANUP_PHASE: _Unwind_Action = 2;
+pub const _UA_HANDLER_FRAME: _Unwind_Action = 4;
+
+/// `struct _Unwind_Exception`, the header of every exception object.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct UnwindException {
+    pub exception_class: u64,
+    /// `_Unwind_Exception_Cleanup_Fn`
+    pub exception_cleanup: ConstVoidPtr,
+    pub private_1: u32,
+    /// For exceptions raised by [raise_exception], the function context of
+    /// the frame that handles the exception.
+    pub private_2: u32,
+    /// The structure is 8-byte aligned.
+    _padding: u32,
+}
+unsafe impl SafeRead for UnwindException {}
+impl UnwindException {
+    pub fn new(exception_class: u64) -> UnwindException {
+        UnwindException {
+            exception_class,
+            exception_cleanup: Ptr::null(),
+            private_1: 0,
+            private_2: 0,
+            _padding: 0,
+        }
+    }
+}
+
+/// `struct SjLj_Function_Context`. Only the part of the jump buffer that is
+/// used by `__builtin_longjmp()` is included.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct SjLjFunctionContext {
+    prev: MutPtr<SjLjFunctionContext>,
+    /// Index of the call site in the LSDA, plus one. 0 means the function
+    /// must not throw, -1 means there is nothing to do.
+    call_site: i32,
+    /// Values for the landing pad.
+    data: [u32; 4],
+    /// `_Unwind_Personality_Fn`
+    personality: ConstVoidPtr,
+    /// Language-specific data area.
+    lsda: ConstPtr<u8>,
+    jbuf_frame_pointer: u32,
+    /// Landing pad dispatch code address, including the Thumb bit.
+    jbuf_pc: u32,
+    jbuf_stack_pointer: u32,
+}
+unsafe impl SafeRead for SjLjFunctionContext {}
+
+/// `struct _Unwind_Context` (the SjLj version).
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct UnwindContext {
+    fc: MutPtr<SjLjFunctionContext>,
+}
+unsafe impl SafeRead for UnwindContext {}
+
+fn libgcc_function(env: &Environment, symbol: &str) -> Option<GuestFunction> {
+    env.bins
+        .iter()
+        .find_map(|bin| bin.exported_symbols.get(symbol))
+        .map(|&addr| GuestFunction::from_addr_with_thumb_bit(addr))
+}
+
+fn _Unwind_SjLj_Register(env: &mut Environment, fc: MutPtr<SjLjFunctionContext>) {
+    if let Some(register) = libgcc_function(env, "__Unwind_SjLj_Register") {
+        // Tail call, the argument is still in r0.
+        env.cpu.branch(register);
+        return;
+    }
+    let prev = get_function_context(env);
+    env.mem.write(fc.cast(), prev);
+    set_function_context(env, fc);
+}
+
+fn _Unwind_SjLj_Unregister(env: &mut Environment, fc: MutPtr<SjLjFunctionContext>) {
+    if let Some(unregister) = libgcc_function(env, "__Unwind_SjLj_Unregister") {
+        // Tail call, the argument is still in r0.
+        env.cpu.branch(unregister);
+        return;
+    }
+    let prev = env.mem.read(fc).prev;
+    set_function_context(env, prev);
+}
+
+fn _Unwind_SjLj_Resume(env: &mut Environment, exception: MutPtr<UnwindException>) {
+    if State::get(env).raised_exceptions.contains(&exception) {
+        unwind_phase2(env, exception);
+    }
+    if let Some(resume) = libgcc_function(env, "__Unwind_SjLj_Resume") {
+        // Tail call, the argument is still in r0.
+        env.cpu.branch(resume);
+        return;
+    }
+    panic!(
+        "_Unwind_SjLj_Resume() for unknown exception {:?}",
+        exception
+    );
+}
+
+/// Get the current thread's most recently registered function context.
+fn get_function_context(env: &mut Environment) -> MutPtr<SjLjFunctionContext> {
+    let Some(register) = libgcc_function(env, "__Unwind_SjLj_Register") else {
+        let thread = env.current_thread;
+        return State::get(env)
+            .function_contexts
+            .get(&thread)
+            .copied()
+            .unwrap_or_else(Ptr::null);
+    };
+    let unregister = libgcc_function(env, "__Unwind_SjLj_Unregister").unwrap();
+    // libgcc has no function for getting this, but registering a context
+    // reveals it.
+    let dummy: MutPtr<SjLjFunctionContext> =
+        env.mem.alloc(guest_size_of::<SjLjFunctionContext>()).cast();
+    () = register.call_from_host(env, (dummy,));
+    let fc = env.mem.read(dummy).prev;
+    () = unregister.call_from_host(env, (dummy,));
+    env.mem.free(dummy.cast());
+    fc
+}
+
+/// Replace the current thread's most recently registered function context.
+fn set_function_context(env: &mut Environment, fc: MutPtr<SjLjFunctionContext>) {
+    let Some(unregister) = libgcc_function(env, "__Unwind_SjLj_Unregister") else {
+        let thread = env.current_thread;
+        State::get(env).function_contexts.insert(thread, fc);
+        return;
+    };
+    // Unregistering a context makes its predecessor the current one.
+    let dummy: MutPtr<SjLjFunctionContext> =
+        env.mem.alloc(guest_size_of::<SjLjFunctionContext>()).cast();
+    env.mem.write(dummy.cast(), fc);
+    () = unregister.call_from_host(env, (dummy,));
+    env.mem.free(dummy.cast());
+}
+
+/// Call a function context's personality routine, if it has one.
+fn call_personality(
+    env: &mut Environment,
+    fc: MutPtr<SjLjFunctionContext>,
+    actions: _Unwind_Action,
+    exception: MutPtr<UnwindException>,
+) -> Option<_Unwind_Reason_Code> {
+    let personality = env.mem.read(fc).personality;
+    if personality.is_null() {
+        return None;
+    }
+    let personality = GuestFunction::from_addr_with_thumb_bit(personality.to_bits());
+    let exception_class = env.mem.read(exception).exception_class;
+    let context = env.mem.alloc_and_write(UnwindContext { fc });
+    let reason: _Unwind_Reason_Code = personality.call_from_host(
+        env,
+        (
+            1i32,
+            actions,
+            exception_class,
+            exception,
+            context.cast_void(),
+        ),
+    );
+    env.mem.free(context.cast());
+    Some(reason)
+}
+
+/// `_Unwind_SjLj_RaiseException()`, for exceptions raised by host code. If a
+/// handler is found, this jumps to it and never returns. Otherwise, the
+/// reason code is returned.
+pub fn raise_exception(
+    env: &mut Environment,
+    exception: MutPtr<UnwindException>,
+) -> _Unwind_Reason_Code {
+    // Phase 1: find a handler, without changing anything.
+    let mut fc = get_function_context(env);
+    let handler = loop {
+        if fc.is_null() {
+            return _URC_END_OF_STACK;
+        }
+        match call_personality(env, fc, _UA_SEARCH_PHASE, exception) {
+            Some(_URC_HANDLER_FOUND) => break fc,
+            None | Some(_URC_CONTINUE_UNWIND) => (),
+            Some(_) => return _URC_FATAL_PHASE1_ERROR,
+        }
+        fc = env.mem.read(fc).prev;
+    };
+
+    let mut header = env.mem.read(exception);
+    header.private_2 = handler.to_bits();
+    env.mem.write(exception, header);
+    State::get(env).raised_exceptions.insert(exception);
+
+    unwind_phase2(env, exception)
+}
+
+/// Phase 2 of unwinding: run cleanups until the handler is reached. This is
+/// also resumed by `_Unwind_SjLj_Resume()` after each cleanup.
+fn unwind_phase2(env: &mut Environment, exception: MutPtr<UnwindException>) -> ! {
+    let handler: MutPtr<SjLjFunctionContext> = Ptr::from_bits(env.mem.read(exception).private_2);
+    let mut fc = get_function_context(env);
+    loop {
+        assert!(!fc.is_null(), "Exception handler disappeared");
+        let actions = _UA_CLEANUP_PHASE | if fc == handler { _UA_HANDLER_FRAME } else { 0 };
+        match call_personality(env, fc, actions, exception) {
+            Some(_URC_INSTALL_CONTEXT) => break,
+            None | Some(_URC_CONTINUE_UNWIND) => assert!(fc != handler),
+            Some(reason) => panic!("Personality routine returned {} during unwinding", reason),
+        }
+        fc = env.mem.read(fc).prev;
+    }
+
+    // Jump to the landing pad, like __builtin_longjmp(). The function context
+    // stays registered, since the function is still running.
+    set_function_context(env, fc);
+    let SjLjFunctionContext {
+        jbuf_frame_pointer,
+        jbuf_pc,
+        jbuf_stack_pointer,
+        ..
+    } = env.mem.read(fc);
+    env.long_jump(
+        jbuf_stack_pointer,
+        jbuf_frame_pointer,
+        GuestFunction::from_addr_with_thumb_bit(jbuf_pc),
+    )
+}
+
+/// For use when an exception raised by [raise_exception] has been handled and
+/// is about to be freed.
+pub fn delete_exception(env: &mut Environment, exception: MutPtr<UnwindException>) {
+    State::get(env).raised_exceptions.remove(&exception);
+}
+
+// DWARF pointer encodings used in the LSDA.
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_ULEB128: u8 = 0x01;
+const DW_EH_PE_UDATA2: u8 = 0x02;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SLEB128: u8 = 0x09;
+const DW_EH_PE_SDATA2: u8 = 0x0a;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_SDATA8: u8 = 0x0c;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_FUNCREL: u8 = 0x40;
+const DW_EH_PE_ALIGNED: u8 = 0x50;
+const DW_EH_PE_INDIRECT: u8 = 0x80;
+const DW_EH_PE_OMIT: u8 = 0xff;
+
+fn read_u8(mem: &Mem, ptr: &mut ConstPtr<u8>) -> u8 {
+    let byte = mem.read(*ptr);
+    *ptr += 1;
+    byte
+}
+fn read_uleb128(mem: &Mem, ptr: &mut ConstPtr<u8>) -> u32 {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(mem, ptr);
+        result |= u32::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return result;
+        }
+    }
+}
+fn read_sleb128(mem: &Mem, ptr: &mut ConstPtr<u8>) -> i32 {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(mem, ptr);
+        result |= u32::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 32 && byte & 0x40 != 0 {
+                result |= u32::MAX << shift;
+            }
+            return result as i32;
+        }
+    }
+}
+/// Read a pointer in one of the DWARF encodings. Returns [None] for encodings
+/// that are invalid or that need a base address SjLj unwinding doesn't have.
+fn read_encoded_pointer(mem: &Mem, ptr: &mut ConstPtr<u8>, encoding: u8) -> Option<u32> {
+    if encoding == DW_EH_PE_ALIGNED {
+        *ptr = Ptr::from_bits(ptr.to_bits().next_multiple_of(4));
+        let value = mem.read(ptr.cast::<u32>());
+        *ptr += 4;
+        return Some(value);
+    }
+    let start = ptr.to_bits();
+    let value = match encoding & 0x0f {
+        DW_EH_PE_ABSPTR | DW_EH_PE_UDATA4 | DW_EH_PE_SDATA4 => {
+            let value = mem.read(ptr.cast::<u32>());
+            *ptr += 4;
+            value
+        }
+        // Pointers are 32-bit, so the upper half is dropped.
+        DW_EH_PE_UDATA8 | DW_EH_PE_SDATA8 => {
+            let value = mem.read(ptr.cast::<u32>());
+            *ptr += 8;
+            value
+        }
+        DW_EH_PE_UDATA2 => u32::from(u16::from_le_bytes([read_u8(mem, ptr), read_u8(mem, ptr)])),
+        DW_EH_PE_SDATA2 => i16::from_le_bytes([read_u8(mem, ptr), read_u8(mem, ptr)]) as i32 as u32,
+        DW_EH_PE_ULEB128 => read_uleb128(mem, ptr),
+        DW_EH_PE_SLEB128 => read_sleb128(mem, ptr) as u32,
+        _ => {
+            log!("Warning: invalid pointer encoding {:#x}", encoding);
+            return None;
+        }
+    };
+    let value = match encoding & 0x70 {
+        0 => value,
+        DW_EH_PE_PCREL => value.wrapping_add(start),
+        // The region start is always 0 for SjLj, like in GCC.
+        DW_EH_PE_FUNCREL => value,
+        // Text- and data-relative pointers aren't supported by GCC's SjLj
+        // unwinder either.
+        _ => {
+            log!("Warning: unsupported pointer encoding {:#x}", encoding);
+            return None;
+        }
+    };
+    Some(if encoding & DW_EH_PE_INDIRECT != 0 {
+        mem.read(ConstPtr::<u32>::from_bits(value))
+    } else {
+        value
+    })
+}
+
+/// The core of a personality routine for SjLj exceptions, following GCC's.
+/// Language-specific personality routines (e.g. `__objc_personality_v0`)
+/// call this with a function that says whether a catch clause's type
+/// (typically a pointer to a type info structure, or null for a catch-all)
+/// matches the exception.
+pub fn personality(
+    env: &mut Environment,
+    actions: _Unwind_Action,
+    exception: MutPtr<UnwindException>,
+    context: MutVoidPtr,
+    type_matches: &mut dyn FnMut(&mut Environment, ConstVoidPtr) -> bool,
+) -> _Unwind_Reason_Code {
+    let fc_ptr = env.mem.read(context.cast::<UnwindContext>()).fc;
+    let fc = env.mem.read(fc_ptr);
+    if fc.call_site < 0 {
+        return _URC_CONTINUE_UNWIND;
+    }
+    assert!(
+        fc.call_site != 0,
+        "An exception was thrown from a call site that must not throw"
+    );
+
+    // If the LSDA can't be understood, unwinding stops, and the exception is
+    // treated as uncaught.
+    let fatal_error = if actions & _UA_SEARCH_PHASE != 0 {
+        _URC_FATAL_PHASE1_ERROR
+    } else {
+        _URC_FATAL_PHASE2_ERROR
+    };
+
+    // Parse the LSDA header.
+    let mem = &env.mem;
+    let mut ptr = fc.lsda;
+    let landing_pad_base_encoding = read_u8(mem, &mut ptr);
+    if landing_pad_base_encoding != DW_EH_PE_OMIT {
+        // Landing pads are indices for SjLj, so this isn't used.
+        if read_encoded_pointer(mem, &mut ptr, landing_pad_base_encoding).is_none() {
+            return fatal_error;
+        }
+    }
+    let type_table_encoding = read_u8(mem, &mut ptr);
+    let type_table = if type_table_encoding != DW_EH_PE_OMIT {
+        let offset = read_uleb128(mem, &mut ptr);
+        Some(ptr + offset)
+    } else {
+        None
+    };
+    let _call_site_encoding = read_u8(mem, &mut ptr);
+    let call_site_table_size = read_uleb128(mem, &mut ptr);
+    let action_table = ptr + call_site_table_size;
+
+    // Find the call site. The table is indexed rather than searched.
+    let mut landing_pad = 0;
+    let mut action = 0;
+    for _ in 0..fc.call_site {
+        landing_pad = read_uleb128(mem, &mut ptr);
+        action = read_uleb128(mem, &mut ptr);
+    }
+
+    // Go through the actions (catch clauses and cleanups) in order.
+    let mut handler_switch_value = None;
+    let mut has_cleanup = action == 0;
+    let mut next_action = (action != 0).then(|| action_table + (action - 1));
+    while let Some(mut action_ptr) = next_action {
+        let filter = read_sleb128(&env.mem, &mut action_ptr);
+        let displacement_ptr = action_ptr;
+        let displacement = read_sleb128(&env.mem, &mut action_ptr);
+        match filter {
+            0 => has_cleanup = true,
+            1.. => {
+                let encoding_size = match type_table_encoding & 0x0f {
+                    _ if type_table_encoding == DW_EH_PE_ALIGNED => 4,
+                    DW_EH_PE_ABSPTR | DW_EH_PE_UDATA4 | DW_EH_PE_SDATA4 => 4,
+                    DW_EH_PE_UDATA2 | DW_EH_PE_SDATA2 => 2,
+                    DW_EH_PE_UDATA8 | DW_EH_PE_SDATA8 => 8,
+                    // Entries must have a fixed size to be indexed.
+                    _ => {
+                        log!(
+                            "Warning: invalid type table encoding {:#x}",
+                            type_table_encoding
+                        );
+                        return fatal_error;
+                    }
+                };
+                let Some(type_table) = type_table else {
+                    log!("Warning: catch clause without a type table");
+                    return fatal_error;
+                };
+                let mut entry_ptr = type_table - (filter as u32) * encoding_size;
+                let Some(type_) =
+                    read_encoded_pointer(&env.mem, &mut entry_ptr, type_table_encoding)
+                else {
+                    return fatal_error;
+                };
+                if type_matches(env, Ptr::from_bits(type_)) {
+                    handler_switch_value = Some(filter);
+                    break;
+                }
+            }
+            // Exception specifications are a C++ feature and can't match.
+            _ => (),
+        }
+        next_action = (displacement != 0)
+            .then(|| Ptr::from_bits(displacement_ptr.to_bits().wrapping_add(displacement as u32)));
+    }
+
+    if actions & _UA_SEARCH_PHASE != 0 {
+        return if handler_switch_value.is_some() {
+            _URC_HANDLER_FOUND
+        } else {
+            _URC_CONTINUE_UNWIND
+        };
+    }
+    let switch_value = if actions & _UA_HANDLER_FRAME != 0 {
+        handler_switch_value.expect("Exception handler disappeared")
+    } else if has_cleanup {
+        0
+    } else {
+        return _URC_CONTINUE_UNWIND;
+    };
+
+    // Equivalent to _Unwind_SetGR() and _Unwind_SetIP().
+    let mut fc = fc;
+    fc.data[0] = exception.to_bits();
+    fc.data[1] = switch_value as u32;
+    fc.call_site = landing_pad as i32;
+    env.mem.write(fc_ptr, fc);
+    _URC_INSTALL_CONTEXT
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(_Unwind_SjLj_Register(_)),
+    export_c_func!(_Unwind_SjLj_Unregister(_)),
+    export_c_func!(_Unwind_SjLj_Resume(_)),
+];
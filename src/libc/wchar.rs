@@ -3,13 +3,18 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! `wchar.h`
+//! `wchar.h`, and the multibyte conversion functions from `stdlib.h`.
+//!
+//! `wchar_t` is 32 bits on iPhone OS, so wide strings are UTF-32.
 
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, GuestUSize, MutPtr};
+use crate::libc::clocale::ctype_is_utf8;
+use crate::libc::errno::{set_errno, EILSEQ};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
 use crate::Environment;
 
 use super::generic_char::GenericChar;
+use std::collections::HashMap;
 
 #[allow(non_camel_case_types)]
 pub type wchar_t = i32; // not sure if this signedness is correct
@@ -19,6 +24,28 @@ type wint_t = i32;
 
 const WEOF: wint_t = -1;
 
+/// Size of `mbstate_t`, which is an opaque 128-byte union. We only use its
+/// first four bytes: the number of bytes of an incomplete multibyte character
+/// seen so far, followed by those bytes.
+const MBSTATE_SIZE: GuestUSize = 128;
+
+/// `(size_t)-1`, returned for an invalid multibyte sequence.
+const INVALID_SEQUENCE: GuestUSize = GuestUSize::MAX;
+/// `(size_t)-2`, returned for an incomplete multibyte sequence.
+const INCOMPLETE_SEQUENCE: GuestUSize = GuestUSize::MAX - 1;
+
+#[derive(Default)]
+pub struct State {
+    /// Internal `mbstate_t`s used when a function is passed `NULL`, keyed by
+    /// function name.
+    internal_mbstates: HashMap<&'static str, MutVoidPtr>,
+}
+impl State {
+    fn get_mut(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.wchar
+    }
+}
+
 fn btowc(_env: &mut Environment, c: i32) -> wint_t {
     let c = c as u8;
     // Assuming ASCII locale, like in ctype.rs.
@@ -42,6 +69,353 @@ fn wctob(_env: &mut Environment, c: wint_t) -> i32 {
     }
 }
 
+// Multibyte conversion
+
+/// Result of decoding a multibyte character.
+enum Decoded {
+    /// The character and the number of bytes it took up.
+    Char(wchar_t, usize),
+    /// The bytes are a valid start of a character, but there aren't enough.
+    Incomplete,
+    Invalid,
+}
+
+/// Decode the multibyte character at the start of `bytes`, according to the
+/// current locale.
+fn decode_mb(utf8: bool, bytes: &[u8]) -> Decoded {
+    let Some(&first) = bytes.first() else {
+        return Decoded::Incomplete;
+    };
+    if !utf8 {
+        return Decoded::Char(first as wchar_t, 1);
+    }
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Decoded::Invalid,
+    };
+    if bytes.len() < len {
+        // Reject bad continuation bytes early, rather than waiting for more.
+        return if bytes[1..].iter().all(|&b| b & 0xc0 == 0x80) {
+            Decoded::Incomplete
+        } else {
+            Decoded::Invalid
+        };
+    }
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(s) => Decoded::Char(s.chars().next().unwrap() as wchar_t, len),
+        Err(_) => Decoded::Invalid,
+    }
+}
+
+/// Encode a wide character according to the current locale, or return [None]
+/// if it can't be represented.
+fn encode_mb(utf8: bool, wc: wchar_t) -> Option<Vec<u8>> {
+    if !utf8 {
+        return u8::try_from(wc).ok().map(|c| vec![c]);
+    }
+    let c = char::from_u32(u32::try_from(wc).ok()?)?;
+    let mut buf = [0u8; 4];
+    Some(c.encode_utf8(&mut buf).as_bytes().to_vec())
+}
+
+/// Get the `mbstate_t` to use: the app's one if it provided one, otherwise an
+/// internal one for the function.
+fn mbstate_or_internal(
+    env: &mut Environment,
+    ps: MutVoidPtr,
+    function: &'static str,
+) -> MutVoidPtr {
+    if !ps.is_null() {
+        return ps;
+    }
+    if let Some(&ps) = State::get_mut(env).internal_mbstates.get(function) {
+        return ps;
+    }
+    let ps = env.mem.alloc(MBSTATE_SIZE);
+    State::get_mut(env).internal_mbstates.insert(function, ps);
+    ps
+}
+
+fn read_mbstate_pending(env: &Environment, ps: MutVoidPtr) -> Vec<u8> {
+    let ps: MutPtr<u8> = ps.cast();
+    let count = env.mem.read(ps).min(3);
+    env.mem.bytes_at(ps + 1, count.into()).to_vec()
+}
+fn write_mbstate_pending(env: &mut Environment, ps: MutVoidPtr, pending: &[u8]) {
+    assert!(pending.len() <= 3);
+    let ps: MutPtr<u8> = ps.cast();
+    env.mem.write(ps, pending.len() as u8);
+    for (i, &byte) in pending.iter().enumerate() {
+        env.mem.write(ps + 1 + i as GuestUSize, byte);
+    }
+}
+
+fn mbsinit(env: &mut Environment, ps: ConstVoidPtr) -> i32 {
+    (ps.is_null() || read_mbstate_pending(env, ps.cast_mut()).is_empty()).into()
+}
+
+fn mbrtowc(
+    env: &mut Environment,
+    pwc: MutPtr<wchar_t>,
+    s: ConstPtr<u8>,
+    n: GuestUSize,
+    ps: MutVoidPtr,
+) -> GuestUSize {
+    let ps = mbstate_or_internal(env, ps, "mbrtowc");
+    mbrtowc_inner(env, pwc, s, n, ps)
+}
+
+fn mbrtowc_inner(
+    env: &mut Environment,
+    pwc: MutPtr<wchar_t>,
+    s: ConstPtr<u8>,
+    n: GuestUSize,
+    ps: MutVoidPtr,
+) -> GuestUSize {
+    if s.is_null() {
+        // Equivalent to mbrtowc(NULL, "", 1, ps), which resets the state.
+        write_mbstate_pending(env, ps, &[]);
+        return 0;
+    }
+
+    let utf8 = ctype_is_utf8(env);
+    let mut bytes = read_mbstate_pending(env, ps);
+    let pending_count = bytes.len();
+    // No character is longer than 4 bytes, so there's no need to read more.
+    let to_read = n.min((4 - pending_count) as GuestUSize);
+    bytes.extend_from_slice(env.mem.bytes_at(s, to_read));
+
+    match decode_mb(utf8, &bytes) {
+        Decoded::Char(wc, len) => {
+            write_mbstate_pending(env, ps, &[]);
+            if !pwc.is_null() {
+                env.mem.write(pwc, wc);
+            }
+            if wc == 0 {
+                0
+            } else {
+                (len - pending_count) as GuestUSize
+            }
+        }
+        Decoded::Incomplete => {
+            write_mbstate_pending(env, ps, &bytes);
+            INCOMPLETE_SEQUENCE
+        }
+        Decoded::Invalid => {
+            write_mbstate_pending(env, ps, &[]);
+            set_errno(env, EILSEQ);
+            INVALID_SEQUENCE
+        }
+    }
+}
+
+fn mbrlen(env: &mut Environment, s: ConstPtr<u8>, n: GuestUSize, ps: MutVoidPtr) -> GuestUSize {
+    let ps = mbstate_or_internal(env, ps, "mbrlen");
+    mbrtowc_inner(env, Ptr::null(), s, n, ps)
+}
+
+fn mbtowc(env: &mut Environment, pwc: MutPtr<wchar_t>, s: ConstPtr<u8>, n: GuestUSize) -> i32 {
+    let ps = mbstate_or_internal(env, Ptr::null(), "mbtowc");
+    match mbrtowc_inner(env, pwc, s, n, ps) {
+        // Neither supported encoding is state-dependent.
+        _ if s.is_null() => 0,
+        INCOMPLETE_SEQUENCE => {
+            // mbtowc() has no way to report this, so forget the bytes.
+            write_mbstate_pending(env, ps, &[]);
+            set_errno(env, EILSEQ);
+            -1
+        }
+        INVALID_SEQUENCE => -1,
+        len => len as i32,
+    }
+}
+
+fn mblen(env: &mut Environment, s: ConstPtr<u8>, n: GuestUSize) -> i32 {
+    mbtowc(env, Ptr::null(), s, n)
+}
+
+fn wcrtomb(env: &mut Environment, s: MutPtr<u8>, wc: wchar_t, _ps: MutVoidPtr) -> GuestUSize {
+    if s.is_null() {
+        // Equivalent to wcrtomb(buf, L'\0', ps), there's no state to reset.
+        return 1;
+    }
+    let utf8 = ctype_is_utf8(env);
+    let Some(bytes) = encode_mb(utf8, wc) else {
+        set_errno(env, EILSEQ);
+        return INVALID_SEQUENCE;
+    };
+    env.mem
+        .bytes_at_mut(s, bytes.len() as GuestUSize)
+        .copy_from_slice(&bytes);
+    bytes.len() as GuestUSize
+}
+
+fn wctomb(env: &mut Environment, s: MutPtr<u8>, wc: wchar_t) -> i32 {
+    if s.is_null() {
+        // Neither supported encoding is state-dependent.
+        return 0;
+    }
+    match wcrtomb(env, s, wc, Ptr::null()) {
+        INVALID_SEQUENCE => -1,
+        len => len as i32,
+    }
+}
+
+/// Shared implementation of `mbstowcs()` and `mbsrtowcs()`. Returns the number
+/// of wide characters written (or that would be written if `dst` is `NULL`),
+/// not counting the terminator, and where conversion stopped (`NULL` if the
+/// terminator was reached). On an invalid sequence, returns where it starts.
+fn mbs_to_wcs(
+    env: &mut Environment,
+    dst: MutPtr<wchar_t>,
+    src: ConstPtr<u8>,
+    len: GuestUSize,
+) -> Result<(GuestUSize, ConstPtr<u8>), ConstPtr<u8>> {
+    let utf8 = ctype_is_utf8(env);
+    let mut src = src;
+    let mut count: GuestUSize = 0;
+    while dst.is_null() || count < len {
+        // Characters are at most 4 bytes long, and decoding stops at the
+        // first null byte, so this never reads past the string.
+        let mut bytes = Vec::with_capacity(4);
+        for i in 0..4 {
+            let byte = env.mem.read(src + i);
+            bytes.push(byte);
+            if byte == b'\0' {
+                break;
+            }
+        }
+        let Decoded::Char(wc, char_len) = decode_mb(utf8, &bytes) else {
+            set_errno(env, EILSEQ);
+            return Err(src);
+        };
+        if !dst.is_null() {
+            env.mem.write(dst + count, wc);
+        }
+        if wc == 0 {
+            return Ok((count, Ptr::null()));
+        }
+        src += char_len as GuestUSize;
+        count += 1;
+    }
+    Ok((count, src))
+}
+
+/// Shared implementation of `wcstombs()` and `wcsrtombs()`. Returns the number
+/// of bytes written (or that would be written if `dst` is `NULL`), not
+/// counting the terminator, and where conversion stopped (`NULL` if the
+/// terminator was reached). On an invalid character, returns where it is.
+fn wcs_to_mbs(
+    env: &mut Environment,
+    dst: MutPtr<u8>,
+    src: ConstPtr<wchar_t>,
+    len: GuestUSize,
+) -> Result<(GuestUSize, ConstPtr<wchar_t>), ConstPtr<wchar_t>> {
+    let utf8 = ctype_is_utf8(env);
+    let mut src = src;
+    let mut count: GuestUSize = 0;
+    loop {
+        let wc = env.mem.read(src);
+        let Some(bytes) = encode_mb(utf8, wc) else {
+            set_errno(env, EILSEQ);
+            return Err(src);
+        };
+        let byte_count = bytes.len() as GuestUSize;
+        if !dst.is_null() {
+            // Characters that don't fit entirely aren't written.
+            if count + byte_count > len {
+                return Ok((count, src));
+            }
+            env.mem
+                .bytes_at_mut(dst + count, byte_count)
+                .copy_from_slice(&bytes);
+        }
+        if wc == 0 {
+            return Ok((count, Ptr::null()));
+        }
+        src += 1;
+        count += byte_count;
+    }
+}
+
+fn mbstowcs(
+    env: &mut Environment,
+    dst: MutPtr<wchar_t>,
+    src: ConstPtr<u8>,
+    len: GuestUSize,
+) -> GuestUSize {
+    match mbs_to_wcs(env, dst, src, len) {
+        Ok((count, _)) => count,
+        Err(_) => INVALID_SEQUENCE,
+    }
+}
+
+fn mbsrtowcs(
+    env: &mut Environment,
+    dst: MutPtr<wchar_t>,
+    src: MutPtr<ConstPtr<u8>>,
+    len: GuestUSize,
+    _ps: MutVoidPtr,
+) -> GuestUSize {
+    // TODO: Use the incomplete character in the state, if any.
+    let src_str = env.mem.read(src);
+    match mbs_to_wcs(env, dst, src_str, len) {
+        Ok((count, src_end)) => {
+            if !dst.is_null() {
+                env.mem.write(src, src_end);
+            }
+            count
+        }
+        Err(bad) => {
+            // The source pointer is left pointing at the invalid sequence.
+            if !dst.is_null() {
+                env.mem.write(src, bad);
+            }
+            INVALID_SEQUENCE
+        }
+    }
+}
+
+fn wcstombs(
+    env: &mut Environment,
+    dst: MutPtr<u8>,
+    src: ConstPtr<wchar_t>,
+    len: GuestUSize,
+) -> GuestUSize {
+    match wcs_to_mbs(env, dst, src, len) {
+        Ok((count, _)) => count,
+        Err(_) => INVALID_SEQUENCE,
+    }
+}
+
+fn wcsrtombs(
+    env: &mut Environment,
+    dst: MutPtr<u8>,
+    src: MutPtr<ConstPtr<wchar_t>>,
+    len: GuestUSize,
+    _ps: MutVoidPtr,
+) -> GuestUSize {
+    let src_str = env.mem.read(src);
+    match wcs_to_mbs(env, dst, src_str, len) {
+        Ok((count, src_end)) => {
+            if !dst.is_null() {
+                env.mem.write(src, src_end);
+            }
+            count
+        }
+        Err(bad) => {
+            // The source pointer is left pointing at the invalid sequence.
+            if !dst.is_null() {
+                env.mem.write(src, bad);
+            }
+            INVALID_SEQUENCE
+        }
+    }
+}
+
 // Functions shared with string.rs
 
 fn wmemset(
@@ -148,6 +522,18 @@ fn wcslcpy(
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(btowc(_)),
     export_c_func!(wctob(_)),
+    // Multibyte conversion
+    export_c_func!(mbsinit(_)),
+    export_c_func!(mbrtowc(_, _, _, _)),
+    export_c_func!(mbrlen(_, _, _)),
+    export_c_func!(mbtowc(_, _, _)),
+    export_c_func!(mblen(_, _)),
+    export_c_func!(wcrtomb(_, _, _)),
+    export_c_func!(wctomb(_, _)),
+    export_c_func!(mbstowcs(_, _, _)),
+    export_c_func!(mbsrtowcs(_, _, _, _)),
+    export_c_func!(wcstombs(_, _, _)),
+    export_c_func!(wcsrtombs(_, _, _, _)),
     // Functions shared with string.rs
     export_c_func!(wmemset(_, _, _)),
     export_c_func!(wmemcpy(_, _, _)),
@@ -55,9 +55,10 @@ macro_rules! log_dbg {
 macro_rules! echo {
     ($($arg:tt)+) => {
         {
+            let formatted_str = format!($($arg)+);
+            $crate::log::record_line(&formatted_str);
             #[cfg(target_os = "android")]
             {
-                let formatted_str = format!($($arg)+);
                 sdl2::log::log(&formatted_str);
                 use std::io::Write;
                 let mut log_file = $crate::log::get_log_file();
@@ -65,11 +66,12 @@ macro_rules! echo {
                 let _ = log_file.write_all(b"\n");
             }
             #[cfg(not(target_os = "android"))]
-            eprintln!($($arg)+);
+            eprintln!("{}", formatted_str);
         }
     };
     () => {
         {
+            $crate::log::record_line("");
             #[cfg(target_os = "android")]
             {
                 sdl2::log::log("");
@@ -82,6 +84,41 @@ macro_rules! echo {
     }
 }
 
+/// How many lines of output are kept for crash reports (see
+/// [crate::crash_report]).
+const RECENT_LINE_LIMIT: usize = 50;
+
+/// Total number of lines output so far, and the most recent of them.
+static RECENT_LINES: std::sync::Mutex<(usize, std::collections::VecDeque<String>)> =
+    std::sync::Mutex::new((0, std::collections::VecDeque::new()));
+
+/// Only for internal use by the logging macros.
+pub fn record_line(text: &str) {
+    let mut recent_lines = RECENT_LINES.lock().unwrap();
+    let (count, lines) = &mut *recent_lines;
+    for line in text.split('\n') {
+        if lines.len() == RECENT_LINE_LIMIT {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+        *count += 1;
+    }
+}
+
+/// Get the number of lines output so far, for use with [recent_lines].
+pub fn line_count() -> usize {
+    RECENT_LINES.lock().unwrap().0
+}
+
+/// Get the most recent lines of output, excluding the first `since` lines
+/// (see [line_count]). Only a limited number of lines are kept.
+pub fn recent_lines(since: usize) -> Vec<String> {
+    let recent_lines = RECENT_LINES.lock().unwrap();
+    let (count, lines) = &*recent_lines;
+    let skip = (since + lines.len()).saturating_sub(*count);
+    lines.iter().skip(skip).cloned().collect()
+}
+
 /// Put modules to enable [log_dbg] for here, e.g. "touchHLE::mem" to see when
 /// memory is allocated and freed.
 pub const ENABLED_MODULES: &[&str] = &[];
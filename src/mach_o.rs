@@ -29,6 +29,9 @@ use mach_object::{
 use std::collections::HashMap;
 use std::io::{Cursor, Seek, SeekFrom};
 
+/// Relocation type for a prebound lazy symbol pointer, from `reloc.h`.
+const ARM_RELOC_PB_LA_PTR: u32 = 4;
+
 const VM_PROT_READ: vm_prot_t = 1;
 const VM_PROT_WRITE: vm_prot_t = 2;
 #[allow(dead_code)]
@@ -51,6 +54,16 @@ pub struct MachO {
     pub external_relocations: Vec<(u32, String)>,
     /// Address/program counter value for the entry point.
     pub entry_point_pc: Option<u32>,
+    /// Address of the Mach-O header in memory, which is at the start of the
+    /// `__TEXT` segment.
+    pub header_addr: Option<u32>,
+    /// Name of the architecture that was loaded, e.g. `"armv6"`.
+    pub arch: &'static str,
+    /// Names of all the ARM architectures in the file. There is more than one
+    /// if it is a fat binary.
+    pub archs: Vec<&'static str>,
+    /// Load commands that were found but not handled, e.g. `"LC_DYLD_INFO"`.
+    pub unhandled_load_commands: Vec<&'static str>,
 }
 
 #[derive(Debug)]
@@ -233,10 +246,15 @@ impl MachO {
     /// Load the all the sections from a Mach-O binary (provided as `bytes`)
     /// into the guest memory (`into_mem`), and return a struct containing
     /// metadata (e.g. symbols).
+    ///
+    /// If `may_slide` is [true], the binary will be moved ("slid") to another
+    /// address if the address range it was linked for is already in use. This
+    /// is only possible for dynamic libraries.
     pub fn load_from_bytes(
         bytes: &[u8],
         into_mem: &mut Mem,
         name: String,
+        may_slide: bool,
     ) -> Result<MachO, &'static str> {
         log_dbg!("Reading {:?}", name);
 
@@ -249,10 +267,12 @@ impl MachO {
             OFile::FatFile { files, .. } => {
                 let mut best_subslice = None;
                 let mut best_type = None;
+                let mut archs = Vec::new();
                 for (arch, _) in files {
                     if arch.cputype != mach_object::CPU_TYPE_ARM {
                         continue;
                     }
+                    archs.push(cpu_subtype_to_str(arch.cpusubtype));
                     if arch.cpusubtype == mach_object::CPU_SUBTYPE_ARM_V7
                         || (arch.cpusubtype == mach_object::CPU_SUBTYPE_ARM_V6
                             && best_type != Some(mach_object::CPU_SUBTYPE_ARM_V7))
@@ -265,7 +285,10 @@ impl MachO {
                     }
                 }
                 return if let Some(subslice) = best_subslice {
-                    MachO::load_from_bytes(subslice, into_mem, name)
+                    MachO::load_from_bytes(subslice, into_mem, name, may_slide).map(|mut bin| {
+                        bin.archs = archs;
+                        bin
+                    })
                 } else {
                     Err("No supported architecture in the fat binary")
                 };
@@ -278,11 +301,8 @@ impl MachO {
         if header.cputype != mach_object::CPU_TYPE_ARM {
             return Err("Executable is not for an ARM CPU!");
         }
-        log!(
-            "Loading {} slice for {:?}",
-            cpu_subtype_to_str(header.cpusubtype),
-            name
-        );
+        let arch = cpu_subtype_to_str(header.cpusubtype);
+        log!("Loading {} slice for {:?}", arch, name);
 
         let is_bigend = header.is_bigend();
         if is_bigend {
@@ -296,6 +316,52 @@ impl MachO {
 
         let split_segs = (header.flags & mach_object::MH_SPLIT_SEGS) != 0;
 
+        // Decide where to load the binary. The slide is the difference between
+        // the address it was linked for and the address it is loaded at, and
+        // must be added to every address in the binary.
+        let mut slide: u32 = 0;
+        let mut slid = false;
+        if may_slide {
+            let mut range: Option<(u32, u32)> = None;
+            for MachCommand(command, _size) in &commands {
+                let LoadCommand::Segment {
+                    segname,
+                    vmaddr,
+                    vmsize,
+                    ..
+                } = command
+                else {
+                    continue;
+                };
+                match &**segname {
+                    "__PAGEZERO" => return Err("Executables can't be loaded at runtime"),
+                    "__LINKEDIT" => continue,
+                    _ => (),
+                }
+                let start: u32 = (*vmaddr).try_into().unwrap();
+                let end: u32 = start + u32::try_from(*vmsize).unwrap();
+                range = Some(match range {
+                    Some((old_start, old_end)) => (old_start.min(start), old_end.max(end)),
+                    None => (start, end),
+                });
+            }
+            let (start, end) = range.ok_or("Binary has no segments to load")?;
+            if !into_mem.can_reserve(start, end - start) {
+                // Allocated memory is always zeroed, and the extra space makes
+                // it possible to keep page alignment.
+                let alloc = into_mem.alloc(end - start + 0xfff).to_bits();
+                let new_start = (alloc + 0xfff) & !0xfff;
+                slide = new_start.wrapping_sub(start);
+                slid = true;
+                log!(
+                    "Loading {:?} at {:#x} rather than {:#x}",
+                    name,
+                    new_start,
+                    start
+                );
+            }
+        }
+
         // Info used while parsing file
         let mut first_segment_base: Option<u32> = None;
         let mut first_read_write_segment_base: Option<u32> = None;
@@ -309,6 +375,7 @@ impl MachO {
         let mut indirect_undef_symbols: Vec<Option<String>> = Vec::new();
         let mut external_relocations: Vec<(u32, String)> = Vec::new();
         let mut entry_point_pc: Option<u32> = None;
+        let mut unhandled_load_commands = Vec::new();
 
         for MachCommand(command, _size) in commands {
             match command {
@@ -358,8 +425,13 @@ impl MachO {
                         }
                     };
 
+                    let vmaddr = vmaddr.wrapping_add(slide);
+
                     if load_me {
-                        into_mem.reserve(vmaddr, vmsize);
+                        // A slid binary is in memory that's already allocated.
+                        if !slid {
+                            into_mem.reserve(vmaddr, vmsize);
+                        }
 
                         // If filesize is less than vmsize, the rest of the
                         // segment should be filled with zeroes. We are assuming
@@ -406,6 +478,7 @@ impl MachO {
                             } = symbol
                             {
                                 let entry: u32 = entry.try_into().unwrap();
+                                let entry = entry.wrapping_add(slide);
                                 let entry = if desc & N_ARM_THUMB_DEF != 0 {
                                     entry | GuestFunction::THUMB_BIT
                                 } else {
@@ -421,6 +494,8 @@ impl MachO {
                     nindirectsyms,
                     extreloff,
                     nextrel,
+                    locreloff,
+                    nlocrel,
                     ..
                 } => {
                     let indirectsyms =
@@ -468,6 +543,7 @@ impl MachO {
                         } else {
                             addr + first_segment_base.unwrap()
                         };
+                        let addr = addr.wrapping_add(slide);
 
                         let mut cursor = cursor.clone();
                         let sym = get_sym_by_idx(
@@ -489,7 +565,7 @@ impl MachO {
                                 // Resolve them immediately, there is no value
                                 // in passing these on to Dyld.
                                 let addr = Ptr::from_bits(addr);
-                                let entry = entry as u32;
+                                let entry = (entry as u32).wrapping_add(slide);
                                 let entry = if desc & N_ARM_THUMB_DEF != 0 {
                                     entry | GuestFunction::THUMB_BIT
                                 } else {
@@ -505,6 +581,38 @@ impl MachO {
                             _ => panic!("Unexpected symbol kind {:?}", sym),
                         };
                     }
+
+                    // Local relocations are pointers within the binary, so
+                    // they only need updating if it has been slid.
+                    let locrels = &bytes[locreloff as usize..][..nlocrel as usize * 8];
+                    for entry in locrels.chunks(8).filter(|_| slide != 0) {
+                        let reloc = Reloc::parse(is_bigend, entry.try_into().unwrap());
+                        let addr = match reloc {
+                            Reloc::Local {
+                                addr,
+                                is_pc_relative: false,
+                                size: 4,
+                                type_: 0, // generic
+                                ..
+                            } => addr,
+                            Reloc::Scattered {
+                                offset,
+                                is_pc_relative: false,
+                                size: 4,
+                                type_: 0 | ARM_RELOC_PB_LA_PTR,
+                                ..
+                            } => offset,
+                            _ => panic!("Unhandled locrel: {:?}", reloc),
+                        };
+                        let addr = if split_segs {
+                            addr + first_read_write_segment_base.unwrap()
+                        } else {
+                            addr + first_segment_base.unwrap()
+                        };
+                        let ptr = Ptr::<u32, true>::from_bits(addr.wrapping_add(slide));
+                        let value = into_mem.read(ptr);
+                        into_mem.write(ptr, value.wrapping_add(slide));
+                    }
                 }
                 LoadCommand::EncryptionInfo { id, .. } => {
                     if id != 0 {
@@ -530,7 +638,7 @@ impl MachO {
                     };
                     // There should only be a single initial thread state.
                     assert!(entry_point_pc.is_none());
-                    entry_point_pc = Some(pc);
+                    entry_point_pc = Some(pc.wrapping_add(slide));
                 }
                 // New-style entry point PC command
                 LoadCommand::EntryPoint {
@@ -544,12 +652,14 @@ impl MachO {
                     // (Presumably an executable won't use both commands?)
                     assert!(entry_point_pc.is_none());
                     let entryoff: u32 = entryoff.try_into().unwrap();
-                    entry_point_pc = Some(text_segment_base.unwrap() + entryoff);
+                    entry_point_pc =
+                        Some((text_segment_base.unwrap() + entryoff).wrapping_add(slide));
                 }
                 // LoadCommand::DyldInfo is apparently a newer thing that 2008
                 // games don't have. Ignore for now? Unsure if/when iOS got it.
                 LoadCommand::DyldInfo { .. } => {
                     log!("Warning! DyldInfo is not handled.");
+                    unhandled_load_commands.push("LC_DYLD_INFO");
                 }
                 _ => (),
             }
@@ -562,6 +672,7 @@ impl MachO {
 
                 let name = section.sectname.clone();
                 let addr: u32 = section.addr.try_into().unwrap();
+                let addr = addr.wrapping_add(slide);
                 let size: u32 = section.size.try_into().unwrap();
                 let type_ = section.flags.sect_type();
 
@@ -613,16 +724,21 @@ impl MachO {
             exported_symbols,
             external_relocations,
             entry_point_pc,
+            header_addr: text_segment_base.map(|base| base.wrapping_add(slide)),
+            arch,
+            archs: vec![arch],
+            unhandled_load_commands,
         })
     }
 
     /// Load the all the sections from a Mach-O binary (from `path`) into the
     /// guest memory (`into_mem`), and return a struct containing metadata
-    /// (e.g. symbols).
+    /// (e.g. symbols). See [Self::load_from_bytes] for `may_slide`.
     pub fn load_from_file<P: AsRef<GuestPath>>(
         path: P,
         fs: &Fs,
         into_mem: &mut Mem,
+        may_slide: bool,
     ) -> Result<MachO, &'static str> {
         let name = path.as_ref().file_name().unwrap().to_string();
         Self::load_from_bytes(
@@ -630,6 +746,7 @@ impl MachO {
                 .map_err(|_| "Could not read executable file")?,
             into_mem,
             name,
+            may_slide,
         )
     }
 
@@ -201,6 +201,36 @@ impl<T: SafeRead> SafeWrite for T {}
 
 type Bytes = [u8; 1 << 32];
 
+/// How unaligned memory accesses by the guest CPU are treated, see the
+/// `--unaligned-access=` option. Accesses by the host are always allowed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnalignedAccess {
+    /// Allow all unaligned accesses.
+    #[default]
+    Allow,
+    /// Allow all unaligned accesses, but log the first one of each size.
+    Warn,
+    /// Match iPhone OS devices: 16-bit and 32-bit accesses (`LDR`, `STRH`
+    /// etc) can be unaligned, but 64-bit accesses (`LDRD`, `STRD`, `VLDR` of a
+    /// double etc) must be 4-byte aligned, otherwise there is a fault.
+    ///
+    /// `LDM` and `STM` also require alignment on a real device, but they can't
+    /// be distinguished from `LDR` and `STR` here, so they are allowed.
+    Strict,
+    /// All unaligned accesses are faults.
+    Fault,
+}
+
+/// A guard region below a thread's stack, see [Mem::alloc_thread_stack].
+struct StackGuard {
+    /// The allocation containing the guard region and the stack, or [None] for
+    /// the main thread, whose stack is reserved instead.
+    alloc: Option<MutVoidPtr>,
+    /// Address of the lowest byte of the stack. The guard region is directly
+    /// below this.
+    stack_low_end: VAddr,
+}
+
 /// The type that owns the guest memory and provides accessors for it.
 pub struct Mem {
     /// This array is 4GiB in size so that it can cover the entire 32-bit
@@ -233,6 +263,19 @@ pub struct Mem {
     /// range.
     null_segment_size: VAddr,
 
+    unaligned_access: UnalignedAccess,
+    /// Which access sizes have been warned about, for [UnalignedAccess::Warn].
+    /// Indexed by the log2 of the size in bytes.
+    unaligned_access_warned: [bool; 4],
+    /// Whether a guest CPU access was an alignment fault, see
+    /// [Self::take_alignment_fault].
+    alignment_fault: bool,
+
+    stack_guards: Vec<StackGuard>,
+    /// Address of a guest CPU access that hit a stack guard region, see
+    /// [Self::take_stack_overflow].
+    stack_overflow: Option<VAddr>,
+
     allocator: allocator::Allocator,
 }
 
@@ -259,6 +302,12 @@ impl Mem {
     /// iPhone OS secondary thread stack size.
     pub const SECONDARY_THREAD_STACK_SIZE: GuestUSize = 512 * 1024;
 
+    /// Size of the guard region below each thread's stack. Accesses to it by
+    /// the guest CPU are treated as a stack overflow. This is larger than one
+    /// page so that a function with a large stack frame can't easily skip
+    /// over it.
+    pub const STACK_GUARD_SIZE: GuestUSize = 0x4000;
+
     /// Create a fresh instance of guest memory.
     pub fn new() -> Mem {
         // This will hopefully get the host OS to lazily allocate the memory.
@@ -270,6 +319,11 @@ impl Mem {
         Mem {
             bytes,
             null_segment_size: 0,
+            unaligned_access: UnalignedAccess::Allow,
+            unaligned_access_warned: [false; 4],
+            alignment_fault: false,
+            stack_guards: Vec::new(),
+            stack_overflow: None,
             allocator,
         }
     }
@@ -283,6 +337,11 @@ impl Mem {
         let Mem {
             bytes: _,
             null_segment_size: _,
+            unaligned_access: _,
+            unaligned_access_warned: _,
+            alignment_fault: _,
+            stack_guards: _,
+            stack_overflow: _,
             ref mut allocator,
         } = mem;
         let used_chunks = allocator.reset_and_drain_used_chunks();
@@ -290,6 +349,11 @@ impl Mem {
             mem.bytes_mut()[base as usize..][..size.get() as usize].fill(0);
         }
         mem.null_segment_size = 0;
+        mem.unaligned_access = UnalignedAccess::Allow;
+        mem.unaligned_access_warned = [false; 4];
+        mem.alignment_fault = false;
+        mem.stack_guards = Vec::new();
+        mem.stack_overflow = None;
         mem
     }
 
@@ -313,6 +377,148 @@ impl Mem {
         self.null_segment_size
     }
 
+    /// Set how unaligned accesses by the guest CPU are treated. This must be
+    /// done before the [crate::cpu::Cpu] is created, because it may need to
+    /// configure itself to detect them.
+    pub fn set_unaligned_access(&mut self, unaligned_access: UnalignedAccess) {
+        self.unaligned_access = unaligned_access;
+    }
+
+    pub fn unaligned_access(&self) -> UnalignedAccess {
+        self.unaligned_access
+    }
+
+    /// Check an access by the guest CPU of `size` bytes (1, 2, 4 or 8) at
+    /// `addr` against the [UnalignedAccess] setting, panicking if it should
+    /// fault (see [Self::take_alignment_fault]). Only for use by [crate::cpu].
+    pub fn check_cpu_access_alignment(&mut self, addr: VAddr, size: GuestUSize, write: bool) {
+        if addr & (size - 1) == 0 {
+            return;
+        }
+        let kind = if write { "write" } else { "read" };
+        match self.unaligned_access {
+            UnalignedAccess::Allow => (),
+            UnalignedAccess::Warn => {
+                let warned = &mut self.unaligned_access_warned[size.ilog2() as usize];
+                if !*warned {
+                    *warned = true;
+                    log!(
+                        "Warning: unaligned {}-bit {} at {:#x}. Further unaligned {}-bit accesses will not be logged.",
+                        size * 8,
+                        kind,
+                        addr,
+                        size * 8
+                    );
+                }
+            }
+            UnalignedAccess::Strict if size < 8 || addr & 3 == 0 => (),
+            UnalignedAccess::Strict | UnalignedAccess::Fault => {
+                self.alignment_fault = true;
+                panic!(
+                    "Unaligned {}-bit {} at {:#x} is a fault with --unaligned-access={}",
+                    size * 8,
+                    kind,
+                    addr,
+                    if self.unaligned_access == UnalignedAccess::Strict {
+                        "strict"
+                    } else {
+                        "fault"
+                    }
+                );
+            }
+        }
+    }
+
+    /// Set up the main thread's stack with a guard region below it, and
+    /// enlarge it to `size` if that is bigger than
+    /// [Self::MAIN_THREAD_STACK_SIZE]. The size must be page-aligned. Returns
+    /// the address of the lowest byte of the stack.
+    ///
+    /// Accesses to the guard region will only be caught if the caller also
+    /// uses [crate::cpu::Cpu::set_pages_trapped] on it.
+    pub fn set_up_main_thread_stack(&mut self, size: GuestUSize) -> VAddr {
+        assert!(size & 0xfff == 0);
+        let size = size.max(Self::MAIN_THREAD_STACK_SIZE);
+        let stack_low_end = 0u32.wrapping_sub(size);
+        if stack_low_end < Self::MAIN_THREAD_STACK_LOW_END {
+            self.reserve(
+                stack_low_end,
+                Self::MAIN_THREAD_STACK_LOW_END - stack_low_end,
+            );
+        }
+        self.reserve(
+            stack_low_end - Self::STACK_GUARD_SIZE,
+            Self::STACK_GUARD_SIZE,
+        );
+        self.stack_guards.push(StackGuard {
+            alloc: None,
+            stack_low_end,
+        });
+        stack_low_end
+    }
+
+    /// Allocate a stack of `size` bytes for a secondary thread, with a
+    /// page-aligned guard region below it. Returns the address of the lowest
+    /// byte of the stack. Free it with [Self::free_thread_stack].
+    ///
+    /// Accesses to the guard region will only be caught if the caller also
+    /// uses [crate::cpu::Cpu::set_pages_trapped] on it.
+    pub fn alloc_thread_stack(&mut self, size: GuestUSize) -> VAddr {
+        // Extra space so the guard region can be page-aligned.
+        let alloc = self.alloc(size + Self::STACK_GUARD_SIZE + 0xfff);
+        let guard_base = (alloc.to_bits() + 0xfff) & !0xfff;
+        let stack_low_end = guard_base + Self::STACK_GUARD_SIZE;
+        self.stack_guards.push(StackGuard {
+            alloc: Some(alloc),
+            stack_low_end,
+        });
+        stack_low_end
+    }
+
+    /// Free a stack allocated with [Self::alloc_thread_stack].
+    pub fn free_thread_stack(&mut self, stack_low_end: VAddr) {
+        let idx = self
+            .stack_guards
+            .iter()
+            .position(|guard| guard.stack_low_end == stack_low_end && guard.alloc.is_some())
+            .unwrap();
+        let StackGuard { alloc, .. } = self.stack_guards.swap_remove(idx);
+        self.free(alloc.unwrap());
+    }
+
+    /// Check an access by the guest CPU of `size` bytes at `addr` against the
+    /// stack guard regions, panicking if it hits one. Only for use by
+    /// [crate::cpu].
+    pub fn check_cpu_access_stack_guard(&mut self, addr: VAddr, size: GuestUSize) {
+        let end = addr.wrapping_add(size - 1);
+        let Some(guard) = self.stack_guards.iter().find(|guard| {
+            let guard_base = guard.stack_low_end - Self::STACK_GUARD_SIZE;
+            (guard_base..guard.stack_low_end).contains(&addr)
+                || (guard_base..guard.stack_low_end).contains(&end)
+        }) else {
+            return;
+        };
+        let stack_low_end = guard.stack_low_end;
+        self.stack_overflow = Some(addr);
+        panic!(
+            "Stack overflow: access at {:#x} is in the guard region below the stack at {:#x}",
+            addr, stack_low_end
+        );
+    }
+
+    /// If the last memory error was caused by a stack overflow, return the
+    /// address of the access that hit the guard region. This resets the
+    /// stored address.
+    pub fn take_stack_overflow(&mut self) -> Option<VAddr> {
+        self.stack_overflow.take()
+    }
+
+    /// Whether the last memory error was caused by an unaligned access that
+    /// faults (see [UnalignedAccess]). This resets the stored flag.
+    pub fn take_alignment_fault(&mut self) -> bool {
+        std::mem::take(&mut self.alignment_fault)
+    }
+
     /// Get a pointer to the full 4GiB of memory. This is only for use when
     /// setting up the CPU, never call this otherwise.
     ///
@@ -472,6 +678,18 @@ impl Mem {
         ptr
     }
 
+    /// Limit the total size of the memory that can be allocated with
+    /// [Self::alloc] etc. Exceeding the limit is a fatal error. This survives
+    /// [Self::refurbish].
+    pub fn set_heap_limit(&mut self, heap_limit: Option<GuestUSize>) {
+        self.allocator.set_heap_limit(heap_limit);
+    }
+
+    /// Total size of the memory currently allocated with [Self::alloc] etc.
+    pub fn allocated_bytes(&self) -> GuestUSize {
+        self.allocator.allocated_bytes()
+    }
+
     pub fn realloc(&mut self, old_ptr: MutVoidPtr, size: GuestUSize) -> MutVoidPtr {
         // TODO: for a moment we always assume that we do not have enough size
         //       to realloc inplace
@@ -548,4 +766,13 @@ impl Mem {
     pub fn reserve(&mut self, base: VAddr, size: GuestUSize) {
         self.allocator.reserve(allocator::Chunk::new(base, size));
     }
+
+    /// Check whether a region of address space is entirely unused, so that
+    /// [Self::reserve] would succeed.
+    pub fn can_reserve(&self, base: VAddr, size: GuestUSize) -> bool {
+        base.checked_add(size).is_some()
+            && self
+                .allocator
+                .can_reserve(allocator::Chunk::new(base, size))
+    }
 }
@@ -255,6 +255,11 @@ use collections::{ChunkMap, SizeBucketedChunkMap};
 pub struct Allocator {
     used_chunks: ChunkMap,
     unused_chunks: SizeBucketedChunkMap,
+    /// Total size of the chunks allocated with [Allocator::alloc] (i.e. not
+    /// including reserved chunks).
+    allocated_bytes: GuestUSize,
+    /// Maximum value of `allocated_bytes`, if the guest heap is limited.
+    heap_limit: Option<GuestUSize>,
 }
 
 impl Allocator {
@@ -272,9 +277,22 @@ impl Allocator {
         Allocator {
             used_chunks,
             unused_chunks,
+            allocated_bytes: 0,
+            heap_limit: None,
         }
     }
 
+    pub fn set_heap_limit(&mut self, heap_limit: Option<GuestUSize>) {
+        self.heap_limit = heap_limit;
+    }
+
+    /// Check whether a chunk could be reserved with [Self::reserve].
+    pub fn can_reserve(&self, chunk: Chunk) -> bool {
+        self.unused_chunks
+            .iter()
+            .any(|unused_chunk| unused_chunk.trisect_by(chunk).is_some())
+    }
+
     pub fn reserve(&mut self, chunk: Chunk) {
         let mut to_trisect = None;
         for unused_chunk in self.unused_chunks.iter() {
@@ -307,6 +325,15 @@ impl Allocator {
             size
         };
 
+        if let Some(heap_limit) = self.heap_limit {
+            if self.allocated_bytes.saturating_add(size) > heap_limit {
+                panic!(
+                    "Could not allocate {:#x} bytes, the guest heap limit ({:#x} bytes) would be exceeded",
+                    size, heap_limit
+                );
+            }
+        }
+
         let Some(alloc) = self.unused_chunks.allocate(size) else {
             panic!(
                 "Could not find large enough chunk to allocate {:#x} bytes",
@@ -314,10 +341,16 @@ impl Allocator {
             );
         };
         self.used_chunks.insert(alloc);
+        self.allocated_bytes += alloc.size.get();
 
         alloc.base
     }
 
+    /// Total size of the current allocations, for memory usage statistics.
+    pub fn allocated_bytes(&self) -> GuestUSize {
+        self.allocated_bytes
+    }
+
     /// This is used for realloc
     pub fn find_allocated_size(&mut self, base: VAddr) -> GuestUSize {
         let Some(size) = self.used_chunks.get_size_with_base(base) else {
@@ -333,6 +366,7 @@ impl Allocator {
             log!("Can't free {:#x}, unknown allocation!", base);
             return 0;
         };
+        self.allocated_bytes -= freed.size.get();
 
         if let Some(adjacent) = self
             .unused_chunks
@@ -353,7 +387,37 @@ impl Allocator {
 
     pub(super) fn reset_and_drain_used_chunks(&mut self) -> impl Iterator<Item = Chunk> {
         let chunks = std::mem::take(&mut self.used_chunks);
+        let heap_limit = self.heap_limit;
         *self = Allocator::new();
+        self.heap_limit = heap_limit;
         chunks.drain()
     }
 }
+
+#[cfg(test)]
+mod allocator_tests {
+    use super::Allocator;
+    #[test]
+    fn test_allocated_bytes() {
+        let mut allocator = Allocator::new();
+        assert_eq!(allocator.allocated_bytes(), 0);
+        let a = allocator.alloc(1); // rounded up to the minimum chunk size
+        let b = allocator.alloc(0x100);
+        assert_eq!(allocator.allocated_bytes(), 0x110);
+        assert_eq!(allocator.free(a), 0x10);
+        assert_eq!(allocator.allocated_bytes(), 0x100);
+        assert_eq!(allocator.free(b), 0x100);
+        assert_eq!(allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_heap_limit() {
+        let mut allocator = Allocator::new();
+        allocator.set_heap_limit(Some(0x100));
+        let a = allocator.alloc(0x100);
+        let _ = allocator.free(a);
+        allocator.alloc(0x80);
+        allocator.alloc(0x81); // over the limit once rounded up
+    }
+}
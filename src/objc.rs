@@ -19,34 +19,71 @@
 //! categories and dynamic class editing).
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{ConstPtr, ConstVoidPtr};
 use crate::MutexId;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+mod associations;
+mod blocks;
 mod classes;
+mod exceptions;
+mod ivars;
 mod messages;
 mod methods;
 mod objects;
 mod properties;
+mod protocols;
 mod selectors;
 mod synchronization;
 
-pub use classes::{objc_classes, Class, ClassExports, ClassTemplate};
+pub use associations::release_associated_objects_of_deallocated;
+pub use blocks::{_Block_copy, _Block_release, block_invoke_function};
+pub use classes::{call_load_methods, objc_classes, Class, ClassExports, ClassTemplate};
+pub use exceptions::objc_exception_throw;
 pub use messages::{
-    autorelease, msg, msg_class, msg_send, msg_send_super2, msg_super, objc_super, release, retain,
+    autorelease, msg, msg_class, msg_send, msg_send_discarding_return, msg_send_prepared,
+    msg_send_super2, msg_super, objc_super, release, retain,
 };
 pub use methods::{HostIMP, IMP};
 pub use objects::{
     id, impl_HostObject_with_superclass, nil, AnyHostObject, HostObject, TrivialHostObject,
 };
-pub use selectors::{selector, SEL};
+pub use selectors::{selector, SelectorCache, SEL};
 
-use classes::{ClassHostObject, FakeClass, UnimplementedClass, CLASS_LISTS};
+use associations::{
+    objc_getAssociatedObject, objc_removeAssociatedObjects, objc_setAssociatedObject, Association,
+};
+use blocks::{_Block_object_assign, _Block_object_dispose};
+use classes::{
+    objc_allocateClassPair, objc_registerClassPair, ClassHostObject, FakeClass, UnimplementedClass,
+    CLASS_LISTS,
+};
+use exceptions::{
+    __objc_personality_v0, objc_begin_catch, objc_end_catch, objc_exception_rethrow,
+    objc_terminate, CaughtException,
+};
+use ivars::{
+    class_addIvar, class_getInstanceVariable, ivar_getOffset, ivar_list_t, ivar_t, object_getIvar,
+    object_setIvar, read_bin_ivar_list,
+};
 use messages::{
-    objc_msgSend, objc_msgSendSuper2, objc_msgSend_stret, MsgSendSignature, MsgSendSuperSignature,
+    objc_msgSend, objc_msgSendSuper2, objc_msgSendSuper2_stret, objc_msgSend_fpret,
+    objc_msgSend_stret, MsgSendSignature, MsgSendSuperSignature,
+};
+use methods::{
+    class_addMethod, class_copyMethodList, class_getClassMethod, class_getInstanceMethod,
+    class_getMethodImplementation, class_replaceMethod, find_method_in_bin_list,
+    method_exchangeImplementations, method_getImplementation, method_getName, method_list_t,
+    method_setImplementation, method_t, read_bin_method_descriptions,
 };
-use methods::method_list_t;
 use objects::{objc_object, HostObjectEntry};
 use properties::{objc_copyStruct, objc_setProperty};
+use protocols::{
+    class_conformsToProtocol, class_copyProtocolList, objc_getProtocol,
+    protocol_conformsToProtocol, protocol_copyMethodDescriptionList, protocol_getName,
+    protocol_isEqual, protocol_list_t, read_bin_protocol_names,
+};
 use selectors::sel_registerName;
 use synchronization::{objc_sync_enter, objc_sync_exit};
 
@@ -58,6 +95,10 @@ pub type NSZonePtr = crate::mem::MutVoidPtr;
 
 /// Main type holding Objective-C runtime state.
 pub struct ObjC {
+    /// Unique non-zero identifier for this runtime, so that selectors cached
+    /// by a [SelectorCache] aren't mistakenly used with another one.
+    instance_id: u32,
+
     /// Known selectors (interned method name strings).
     selectors: HashMap<String, SEL>,
 
@@ -71,6 +112,9 @@ pub struct ObjC {
     /// Look at the `isa` to get the metaclass for a class.
     classes: HashMap<String, Class>,
 
+    /// Known protocols, by name. See [protocols].
+    protocols: HashMap<String, id>,
+
     /// Mutexes used in @synchronized blocks (objc_sync_enter/exit).
     sync_mutexes: HashMap<id, MutexId>,
 
@@ -78,16 +122,62 @@ pub struct ObjC {
     /// Type information isn't part of the `objc_msgSend` ABI, so an alternative
     /// channel is needed.
     message_type_info: Option<(std::any::TypeId, &'static str)>,
+
+    /// `+load` methods from app binaries that haven't been called yet, and
+    /// the classes to call them on. See [call_load_methods].
+    pending_load_methods: Vec<(Class, methods::GuestIMP)>,
+
+    /// Results of method lookups by `objc_msgSend`: the class the method was
+    /// found on and its implementation, by receiver class, selector and
+    /// whether it was a super-call. See [messages].
+    method_cache: HashMap<(Class, SEL, bool), (Class, IMP)>,
+
+    /// `Method` pointers handed out to the app, by class and selector.
+    method_objects: HashMap<(Class, SEL), ConstPtr<method_t>>,
+
+    /// Guest functions created for host methods, so that they can be swizzled
+    /// with guest methods. See [methods].
+    host_imp_functions: Vec<(&'static dyn HostIMP, methods::GuestIMP)>,
+
+    /// Associated objects, by object and key. See [associations].
+    associated_objects: HashMap<id, HashMap<ConstVoidPtr, Association>>,
+
+    /// Values that were owned by associations of objects that have been
+    /// deallocated, and still need to be released. See [associations].
+    deallocated_association_values: Vec<id>,
+
+    /// Exceptions being handled by `@catch` blocks on each thread, innermost
+    /// last. See [exceptions].
+    caught_exceptions: HashMap<crate::ThreadId, Vec<CaughtException>>,
+
+    /// `objc_ehtype_vtable`, if it has been allocated. See [exceptions].
+    ehtype_vtable: Option<ConstPtr<ConstVoidPtr>>,
+
+    /// Exception type info created for `_OBJC_EHTYPE_` symbols, by symbol.
+    /// See [exceptions].
+    ehtypes: HashMap<String, ConstVoidPtr>,
 }
 
 impl ObjC {
     pub fn new() -> ObjC {
+        static NEXT_INSTANCE_ID: AtomicU32 = AtomicU32::new(1);
         ObjC {
+            instance_id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
             selectors: HashMap::new(),
             objects: HashMap::new(),
             classes: HashMap::new(),
+            protocols: HashMap::new(),
             sync_mutexes: HashMap::new(),
             message_type_info: None,
+            pending_load_methods: Vec::new(),
+            method_cache: HashMap::new(),
+            method_objects: HashMap::new(),
+            host_imp_functions: Vec::new(),
+            associated_objects: HashMap::new(),
+            deallocated_association_values: Vec::new(),
+            caught_exceptions: HashMap::new(),
+            ehtype_vtable: None,
+            ehtypes: HashMap::new(),
         }
     }
 }
@@ -95,10 +185,49 @@ impl ObjC {
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(objc_msgSend(_, _)),
     export_c_func!(objc_msgSend_stret(_, _, _)),
+    export_c_func!(objc_msgSend_fpret(_, _)),
     export_c_func!(objc_msgSendSuper2(_, _)),
+    export_c_func!(objc_msgSendSuper2_stret(_, _, _)),
     export_c_func!(objc_setProperty(_, _, _, _, _, _)),
     export_c_func!(objc_copyStruct(_, _, _, _, _)),
     export_c_func!(objc_sync_enter(_)),
     export_c_func!(objc_sync_exit(_)),
     export_c_func!(sel_registerName(_)),
+    export_c_func!(_Block_copy(_)),
+    export_c_func!(_Block_release(_)),
+    export_c_func!(_Block_object_assign(_, _, _)),
+    export_c_func!(_Block_object_dispose(_, _)),
+    export_c_func!(objc_getProtocol(_)),
+    export_c_func!(protocol_getName(_)),
+    export_c_func!(protocol_isEqual(_, _)),
+    export_c_func!(protocol_conformsToProtocol(_, _)),
+    export_c_func!(protocol_copyMethodDescriptionList(_, _, _, _)),
+    export_c_func!(class_conformsToProtocol(_, _)),
+    export_c_func!(class_copyProtocolList(_, _)),
+    export_c_func!(objc_allocateClassPair(_, _, _)),
+    export_c_func!(objc_registerClassPair(_)),
+    export_c_func!(class_addIvar(_, _, _, _, _)),
+    export_c_func!(class_getInstanceVariable(_, _)),
+    export_c_func!(ivar_getOffset(_)),
+    export_c_func!(object_getIvar(_, _)),
+    export_c_func!(object_setIvar(_, _, _)),
+    export_c_func!(class_addMethod(_, _, _, _)),
+    export_c_func!(class_copyMethodList(_, _)),
+    export_c_func!(method_getName(_)),
+    export_c_func!(class_getInstanceMethod(_, _)),
+    export_c_func!(class_getClassMethod(_, _)),
+    export_c_func!(class_getMethodImplementation(_, _)),
+    export_c_func!(class_replaceMethod(_, _, _, _)),
+    export_c_func!(method_getImplementation(_)),
+    export_c_func!(method_setImplementation(_, _)),
+    export_c_func!(method_exchangeImplementations(_, _)),
+    export_c_func!(objc_setAssociatedObject(_, _, _, _)),
+    export_c_func!(objc_getAssociatedObject(_, _)),
+    export_c_func!(objc_removeAssociatedObjects(_)),
+    export_c_func!(objc_exception_throw(_)),
+    export_c_func!(objc_exception_rethrow()),
+    export_c_func!(objc_begin_catch(_)),
+    export_c_func!(objc_end_catch()),
+    export_c_func!(objc_terminate()),
+    export_c_func!(__objc_personality_v0(_, _, _, _, _)),
 ];
@@ -0,0 +1,153 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Associated objects (`objc_setAssociatedObject()` and friends).
+//!
+//! Associations are keyed by object and by an arbitrary pointer (usually the
+//! address of a static variable in the app). When an object is deallocated,
+//! its associations are removed by [super::ObjC::dealloc_object], but since
+//! that can't send messages, the values are released later by
+//! [release_associated_objects_of_deallocated].
+//!
+//! Resources:
+//! - [objc4 source code](https://opensource.apple.com/source/objc4/objc4-532.2/runtime/objc-references.mm.auto.html), which defines the policy bits
+//! - [Apple's documentation of `objc_setAssociatedObject`](https://developer.apple.com/documentation/objectivec/1418509-objc_setassociatedobject?language=objc)
+
+use super::{autorelease, id, msg, nil, release, retain, ObjC};
+use crate::mem::{ConstVoidPtr, MutVoidPtr, Ptr};
+use crate::Environment;
+
+/// `objc_AssociationPolicy`
+#[allow(non_camel_case_types)]
+type objc_AssociationPolicy = u32;
+
+// Policy bits. The public constants are combinations of these:
+// OBJC_ASSOCIATION_ASSIGN = 0, OBJC_ASSOCIATION_RETAIN_NONATOMIC = 1,
+// OBJC_ASSOCIATION_COPY_NONATOMIC = 3, OBJC_ASSOCIATION_RETAIN = 01401 and
+// OBJC_ASSOCIATION_COPY = 01403.
+const SETTER_RETAIN: objc_AssociationPolicy = 1;
+const SETTER_COPY: objc_AssociationPolicy = 3;
+const SETTER_MASK: objc_AssociationPolicy = 3;
+const GETTER_RETAIN: objc_AssociationPolicy = 1 << 8;
+const GETTER_AUTORELEASE: objc_AssociationPolicy = 1 << 9;
+
+/// An object's association with a particular key.
+#[derive(Copy, Clone)]
+pub(super) struct Association {
+    value: id,
+    policy: objc_AssociationPolicy,
+}
+impl Association {
+    /// Whether the value is owned by the association (retained or copied).
+    fn is_owned(&self) -> bool {
+        self.policy & SETTER_MASK != 0
+    }
+}
+
+impl ObjC {
+    /// For use by [ObjC::dealloc_object]: remove all of an object's
+    /// associations, and queue the values it owns to be released.
+    pub(super) fn remove_associations_of_deallocated(&mut self, object: id) {
+        let Some(associations) = self.associated_objects.remove(&object) else {
+            return;
+        };
+        self.deallocated_association_values.extend(
+            associations
+                .into_values()
+                .filter(Association::is_owned)
+                .map(|association| association.value),
+        );
+    }
+}
+
+/// Release the values that were associated with objects that have since been
+/// deallocated. This should be called after sending `dealloc`.
+pub fn release_associated_objects_of_deallocated(env: &mut Environment) {
+    // Releasing a value can cause more objects to be deallocated, so this is
+    // not just a simple iteration.
+    while let Some(value) = env.objc.deallocated_association_values.pop() {
+        release(env, value);
+    }
+}
+
+pub(super) fn objc_setAssociatedObject(
+    env: &mut Environment,
+    object: id,
+    key: ConstVoidPtr,
+    value: id,
+    policy: objc_AssociationPolicy,
+) {
+    log_dbg!(
+        "objc_setAssociatedObject({:?}, {:?}, {:?}, {:#o})",
+        object,
+        key,
+        value,
+        policy
+    );
+    assert!(object != nil);
+
+    let value = if value == nil {
+        nil
+    } else {
+        match policy & SETTER_MASK {
+            0 => value,
+            SETTER_RETAIN => retain(env, value),
+            SETTER_COPY => {
+                let zone: MutVoidPtr = Ptr::null();
+                msg![env; value copyWithZone:zone]
+            }
+            _ => panic!("Unknown association policy: {:#o}", policy),
+        }
+    };
+
+    let associations = env.objc.associated_objects.entry(object).or_default();
+    let old = if value == nil {
+        associations.remove(&key)
+    } else {
+        associations.insert(key, Association { value, policy })
+    };
+    if associations.is_empty() {
+        env.objc.associated_objects.remove(&object);
+    }
+
+    // The old value is released last, in case it's the same as the new one.
+    if let Some(old) = old {
+        if old.is_owned() {
+            release(env, old.value);
+        }
+    }
+}
+
+pub(super) fn objc_getAssociatedObject(env: &mut Environment, object: id, key: ConstVoidPtr) -> id {
+    let Some(&Association { value, policy }) = env
+        .objc
+        .associated_objects
+        .get(&object)
+        .and_then(|associations| associations.get(&key))
+    else {
+        return nil;
+    };
+    // The atomic policies return a retained and autoreleased value, so that
+    // it survives the association being changed.
+    if policy & GETTER_RETAIN != 0 {
+        retain(env, value);
+    }
+    if policy & GETTER_AUTORELEASE != 0 {
+        autorelease(env, value);
+    }
+    value
+}
+
+pub(super) fn objc_removeAssociatedObjects(env: &mut Environment, object: id) {
+    log_dbg!("objc_removeAssociatedObjects({:?})", object);
+    let Some(associations) = env.objc.associated_objects.remove(&object) else {
+        return;
+    };
+    for association in associations.into_values() {
+        if association.is_owned() {
+            release(env, association.value);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Blocks runtime (`Block.h`, Apple's libclosure).
+//!
+//! Blocks are closures, and a block literal compiles to a [Block_layout] that
+//! normally lives on the stack. The compiler generates calls to
+//! `_Block_object_assign` and `_Block_object_dispose` for captured variables,
+//! and the app can call `_Block_copy` (`Block_copy()`) to move a block to the
+//! heap. Blocks are also Objective-C objects, which is why their `isa`
+//! references one of the `_NSConcrete*Block` symbols. Here those symbols are
+//! our host block classes, so messages like `copy` and `release` work.
+//!
+//! Host code can call a block with [block_invoke_function].
+//!
+//! Resources:
+//! - [Block Implementation Specification](https://clang.llvm.org/docs/Block-ABI-Apple.html)
+//! - [Source code for libclosure](https://opensource.apple.com/source/libclosure/libclosure-38/runtime.c.auto.html)
+
+use super::{id, nil, objc_classes, release, retain, Class, ClassExports, NSZonePtr, ObjC};
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::mem::{
+    guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, SafeRead,
+};
+use crate::Environment;
+
+/// Symbols the compiler uses as the `isa` of blocks, and the names of the
+/// classes they correspond to.
+const CONCRETE_BLOCK_CLASSES: &[(&str, &str)] = &[
+    ("__NSConcreteStackBlock", "__NSStackBlock__"),
+    ("__NSConcreteMallocBlock", "__NSMallocBlock__"),
+    ("__NSConcreteGlobalBlock", "__NSGlobalBlock__"),
+];
+
+// Block_layout flags
+/// Reference count of a heap block or `__block` variable, in units of 2.
+const BLOCK_REFCOUNT_MASK: i32 = 0xfffe;
+const BLOCK_REFCOUNT_ONE: i32 = 2;
+const BLOCK_NEEDS_FREE: i32 = 1 << 24;
+const BLOCK_HAS_COPY_DISPOSE: i32 = 1 << 25;
+const BLOCK_IS_GLOBAL: i32 = 1 << 28;
+
+// Flags for _Block_object_assign and _Block_object_dispose
+const BLOCK_FIELD_IS_OBJECT: i32 = 3;
+const BLOCK_FIELD_IS_BLOCK: i32 = 7;
+const BLOCK_FIELD_IS_BYREF: i32 = 8;
+const BLOCK_FIELD_IS_WEAK: i32 = 16;
+const BLOCK_BYREF_CALLER: i32 = 128;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct Block_layout {
+    isa: Class,
+    flags: i32,
+    _reserved: i32,
+    invoke: GuestFunction, // void (*invoke)(void *, ...)
+    descriptor: ConstPtr<Block_descriptor>,
+    // captured variables follow
+}
+unsafe impl SafeRead for Block_layout {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct Block_descriptor {
+    _reserved: u32,
+    size: GuestUSize,
+    // If BLOCK_HAS_COPY_DISPOSE is set, this is followed by [CopyDispose].
+}
+unsafe impl SafeRead for Block_descriptor {}
+
+/// Helper functions that follow [Block_descriptor] or [Block_byref] if
+/// `BLOCK_HAS_COPY_DISPOSE` is set.
+#[repr(C, packed)]
+struct CopyDispose {
+    copy: GuestFunction,    // void (*copy)(void *dst, void *src)
+    dispose: GuestFunction, // void (*dispose)(void *)
+}
+unsafe impl SafeRead for CopyDispose {}
+
+/// A `__block` variable.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct Block_byref {
+    _isa: ConstVoidPtr,
+    forwarding: MutPtr<Block_byref>,
+    flags: i32,
+    size: GuestUSize,
+    // If BLOCK_HAS_COPY_DISPOSE is set, this is followed by [CopyDispose].
+    // Then the variable follows.
+}
+unsafe impl SafeRead for Block_byref {}
+
+/// Pointer to a field of a struct in guest memory.
+fn field_ptr<T, F>(ptr: MutPtr<T>, offset: GuestUSize) -> MutPtr<F> {
+    (ptr.cast::<u8>() + offset).cast()
+}
+fn block_flags_ptr(block: MutPtr<Block_layout>) -> MutPtr<i32> {
+    field_ptr(block, 4)
+}
+fn byref_flags_ptr(byref: MutPtr<Block_byref>) -> MutPtr<i32> {
+    field_ptr(byref, 8)
+}
+fn byref_forwarding_ptr(byref: MutPtr<Block_byref>) -> MutPtr<MutPtr<Block_byref>> {
+    field_ptr(byref, 4)
+}
+fn copy_dispose<T>(mem: &Mem, ptr: ConstPtr<T>) -> CopyDispose {
+    mem.read((ptr.cast::<u8>() + guest_size_of::<T>()).cast())
+}
+
+impl ObjC {
+    /// For use by [crate::dyld]: if `symbol` is one of the `_NSConcrete*Block`
+    /// symbols, get the corresponding class.
+    pub fn link_block_class(&mut self, symbol: &str, mem: &mut Mem) -> Option<Class> {
+        CONCRETE_BLOCK_CLASSES
+            .iter()
+            .find(|&&(block_symbol, _)| block_symbol == symbol)
+            .map(|&(_, class_name)| self.get_known_class(class_name, mem))
+    }
+}
+
+/// Get the function that implements a block. The block itself must be passed
+/// as the first argument when calling it, e.g.:
+///
+/// ```ignore
+/// let invoke = block_invoke_function(env, block);
+/// let result: i32 = invoke.call_from_host(env, (block, arg1, arg2));
+/// ```
+pub fn block_invoke_function(env: &mut Environment, block: id) -> GuestFunction {
+    assert!(block != nil);
+    env.mem.read(block.cast::<Block_layout>()).invoke
+}
+
+pub fn _Block_copy(env: &mut Environment, block: ConstVoidPtr) -> MutVoidPtr {
+    let block: MutPtr<Block_layout> = block.cast().cast_mut();
+    if block.is_null() {
+        return block.cast();
+    }
+    let Block_layout {
+        flags, descriptor, ..
+    } = env.mem.read(block);
+    if flags & BLOCK_NEEDS_FREE != 0 {
+        // Already on the heap.
+        let refcount = flags & BLOCK_REFCOUNT_MASK;
+        assert!(refcount != BLOCK_REFCOUNT_MASK);
+        env.mem
+            .write(block_flags_ptr(block), flags + BLOCK_REFCOUNT_ONE);
+        return block.cast();
+    }
+    if flags & BLOCK_IS_GLOBAL != 0 {
+        return block.cast();
+    }
+
+    // Stack block: move it to the heap.
+    let size = env.mem.read(descriptor).size;
+    let copy: MutPtr<Block_layout> = env.mem.alloc(size).cast();
+    env.mem
+        .memmove(copy.cast(), block.cast().cast_const(), size);
+    let isa = env.objc.get_known_class("__NSMallocBlock__", &mut env.mem);
+    env.mem.write(copy.cast(), isa);
+    env.mem.write(
+        block_flags_ptr(copy),
+        (flags & !BLOCK_REFCOUNT_MASK) | BLOCK_NEEDS_FREE | BLOCK_REFCOUNT_ONE,
+    );
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        let copy_helper = copy_dispose(&env.mem, descriptor).copy;
+        log_dbg!("Calling block copy helper {:?}", copy_helper);
+        () = copy_helper.call_from_host(env, (copy, block));
+    }
+    log_dbg!("Copied block {:?} to {:?}", block, copy);
+    copy.cast()
+}
+
+pub fn _Block_release(env: &mut Environment, block: ConstVoidPtr) {
+    let block: MutPtr<Block_layout> = block.cast().cast_mut();
+    if block.is_null() {
+        return;
+    }
+    let Block_layout {
+        flags, descriptor, ..
+    } = env.mem.read(block);
+    if flags & BLOCK_NEEDS_FREE == 0 {
+        // Stack and global blocks aren't reference-counted.
+        return;
+    }
+    let refcount = flags & BLOCK_REFCOUNT_MASK;
+    assert!(refcount != 0);
+    env.mem
+        .write(block_flags_ptr(block), flags - BLOCK_REFCOUNT_ONE);
+    if refcount != BLOCK_REFCOUNT_ONE {
+        return;
+    }
+
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        let dispose_helper = copy_dispose(&env.mem, descriptor).dispose;
+        log_dbg!("Calling block dispose helper {:?}", dispose_helper);
+        () = dispose_helper.call_from_host(env, (block,));
+    }
+    log_dbg!("Freeing block {:?}", block);
+    env.mem.free(block.cast());
+}
+
+/// Copies a `__block` variable to the heap, if it's not already there, and
+/// returns the heap copy.
+fn byref_copy(env: &mut Environment, byref: MutPtr<Block_byref>) -> MutPtr<Block_byref> {
+    // If the variable has already been copied, the stack version forwards to
+    // the heap version.
+    let byref = env.mem.read(byref).forwarding;
+    let Block_byref { flags, size, .. } = env.mem.read(byref);
+
+    if flags & BLOCK_NEEDS_FREE != 0 {
+        let refcount = flags & BLOCK_REFCOUNT_MASK;
+        assert!(refcount != BLOCK_REFCOUNT_MASK);
+        env.mem
+            .write(byref_flags_ptr(byref), flags + BLOCK_REFCOUNT_ONE);
+        return byref;
+    }
+
+    let copy: MutPtr<Block_byref> = env.mem.alloc(size).cast();
+    let header_size = guest_size_of::<Block_byref>();
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        // The keep helper is responsible for copying the variable itself.
+        let helpers_size = guest_size_of::<CopyDispose>();
+        env.mem.memmove(
+            copy.cast(),
+            byref.cast().cast_const(),
+            header_size + helpers_size,
+        );
+    } else {
+        env.mem
+            .memmove(copy.cast(), byref.cast().cast_const(), size);
+    }
+    // One reference for the caller and one for the stack version, which is
+    // released when it goes out of scope.
+    env.mem.write(
+        byref_flags_ptr(copy),
+        (flags & !BLOCK_REFCOUNT_MASK) | BLOCK_NEEDS_FREE | (BLOCK_REFCOUNT_ONE * 2),
+    );
+    // Both versions now forward to the heap version.
+    env.mem.write(byref_forwarding_ptr(copy), copy);
+    env.mem.write(byref_forwarding_ptr(byref), copy);
+
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        let keep = copy_dispose(&env.mem, byref.cast_const()).copy;
+        log_dbg!("Calling __block variable keep helper {:?}", keep);
+        () = keep.call_from_host(env, (copy, byref));
+    }
+    log_dbg!("Copied __block variable {:?} to {:?}", byref, copy);
+    copy
+}
+
+fn byref_release(env: &mut Environment, byref: MutPtr<Block_byref>) {
+    let byref = env.mem.read(byref).forwarding;
+    let Block_byref { flags, .. } = env.mem.read(byref);
+    if flags & BLOCK_NEEDS_FREE == 0 {
+        // Still on the stack.
+        return;
+    }
+    let refcount = flags & BLOCK_REFCOUNT_MASK;
+    assert!(refcount != 0);
+    env.mem
+        .write(byref_flags_ptr(byref), flags - BLOCK_REFCOUNT_ONE);
+    if refcount != BLOCK_REFCOUNT_ONE {
+        return;
+    }
+
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        let destroy = copy_dispose(&env.mem, byref.cast_const()).dispose;
+        log_dbg!("Calling __block variable destroy helper {:?}", destroy);
+        () = destroy.call_from_host(env, (byref,));
+    }
+    log_dbg!("Freeing __block variable {:?}", byref);
+    env.mem.free(byref.cast());
+}
+
+/// Called by block copy helpers (and `__block` variable keep helpers) for each
+/// captured variable that needs special handling.
+pub(super) fn _Block_object_assign(
+    env: &mut Environment,
+    dest: MutPtr<ConstVoidPtr>,
+    object: ConstVoidPtr,
+    flags: i32,
+) {
+    let value: ConstVoidPtr = if flags & BLOCK_BYREF_CALLER != 0 {
+        // Called from a __block variable's keep helper: the variable is not
+        // retained by the copy.
+        object
+    } else if flags & BLOCK_FIELD_IS_BYREF == BLOCK_FIELD_IS_BYREF {
+        byref_copy(env, object.cast().cast_mut())
+            .cast()
+            .cast_const()
+    } else if flags & BLOCK_FIELD_IS_BLOCK == BLOCK_FIELD_IS_BLOCK {
+        _Block_copy(env, object).cast().cast_const()
+    } else if flags & BLOCK_FIELD_IS_OBJECT == BLOCK_FIELD_IS_OBJECT {
+        if flags & BLOCK_FIELD_IS_WEAK == 0 {
+            retain(env, object.cast().cast_mut());
+        }
+        object
+    } else {
+        unimplemented!("_Block_object_assign() with flags {:#x}", flags);
+    };
+    env.mem.write(dest, value);
+}
+
+/// Called by block dispose helpers (and `__block` variable destroy helpers),
+/// and when a `__block` variable goes out of scope.
+pub(super) fn _Block_object_dispose(env: &mut Environment, object: ConstVoidPtr, flags: i32) {
+    if flags & BLOCK_BYREF_CALLER != 0 {
+        // See _Block_object_assign
+    } else if flags & BLOCK_FIELD_IS_BYREF == BLOCK_FIELD_IS_BYREF {
+        byref_release(env, object.cast().cast_mut());
+    } else if flags & BLOCK_FIELD_IS_BLOCK == BLOCK_FIELD_IS_BLOCK {
+        _Block_release(env, object);
+    } else if flags & BLOCK_FIELD_IS_OBJECT == BLOCK_FIELD_IS_OBJECT {
+        if flags & BLOCK_FIELD_IS_WEAK == 0 {
+            release(env, object.cast().cast_mut());
+        }
+    } else {
+        unimplemented!("_Block_object_dispose() with flags {:#x}", flags);
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+// Abstract superclass of the block classes. Blocks' reference counts live in
+// the block itself, so the NSObject implementations must not be used.
+@implementation NSBlock: NSObject
+
+- (id)copy {
+    _Block_copy(env, this.cast().cast_const()).cast()
+}
+- (id)copyWithZone:(NSZonePtr)_zone {
+    _Block_copy(env, this.cast().cast_const()).cast()
+}
+
+- (id)retain {
+    // Retaining a stack block doesn't copy it.
+    let flags = env.mem.read(this.cast::<Block_layout>()).flags;
+    if flags & BLOCK_NEEDS_FREE != 0 {
+        _Block_copy(env, this.cast().cast_const());
+    }
+    this
+}
+- (())release {
+    _Block_release(env, this.cast().cast_const());
+}
+
+- (())invoke {
+    let invoke = block_invoke_function(env, this);
+    () = invoke.call_from_host(env, (this,));
+}
+
+@end
+
+@implementation __NSStackBlock__: NSBlock
+@end
+
+@implementation __NSMallocBlock__: NSBlock
+@end
+
+@implementation __NSGlobalBlock__: NSBlock
+@end
+
+};
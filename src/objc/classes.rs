@@ -14,11 +14,15 @@ mod class_lists;
 pub(super) use class_lists::CLASS_LISTS;
 
 use super::{
-    id, method_list_t, nil, objc_object, AnyHostObject, HostIMP, HostObject, ObjC, IMP, SEL,
+    find_method_in_bin_list, id, ivar_list_t, ivar_t, method_list_t, nil, objc_object,
+    protocol_list_t, read_bin_ivar_list, read_bin_protocol_names, AnyHostObject, HostIMP,
+    HostObject, ObjC, IMP, SEL,
 };
+use crate::abi::CallFromHost;
 use crate::mach_o::MachO;
 use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, Ptr, SafeRead};
-use std::collections::HashMap;
+use crate::Environment;
+use std::collections::{HashMap, HashSet};
 
 /// Generic pointer to an Objective-C class or metaclass.
 ///
@@ -38,6 +42,14 @@ pub(super) struct ClassHostObject {
     pub(super) is_metaclass: bool,
     pub(super) superclass: Class,
     pub(super) methods: HashMap<SEL, IMP>,
+    /// Type strings of methods, if known. Host methods don't have them.
+    pub(super) method_types: HashMap<SEL, ConstPtr<u8>>,
+    /// Names of the protocols the class adopts, not including those adopted by
+    /// its superclasses. See [super::protocols].
+    pub(super) protocols: Vec<String>,
+    /// The class's own ivars (not including those of its superclasses). See
+    /// [super::ivars].
+    pub(super) ivars: Vec<ConstPtr<ivar_t>>,
     /// Offset into the allocated memory for the object where the ivars of
     /// instances of this class or metaclass (respectively: normal objects or
     /// classes) should live. This is always >= the value in the superclass.
@@ -93,8 +105,8 @@ struct class_rw_t {
     _reserved: u32,
     name: ConstPtr<u8>,
     base_methods: ConstPtr<method_list_t>,
-    _base_protocols: ConstVoidPtr, // protocol list (TODO)
-    _ivars: ConstVoidPtr,          // ivar list (TODO)
+    base_protocols: ConstPtr<protocol_list_t>,
+    ivars: ConstPtr<ivar_list_t>,
     _weak_ivar_layout: u32,
     _base_properties: ConstVoidPtr, // property list (TODO)
 }
@@ -109,7 +121,7 @@ struct category_t {
     class: Class,
     instance_methods: ConstPtr<method_list_t>,
     class_methods: ConstPtr<method_list_t>,
-    _protocols: ConstVoidPtr,     // protocol list (TODO)
+    protocols: ConstPtr<protocol_list_t>,
     _property_list: ConstVoidPtr, // property list (TODO)
 }
 unsafe impl SafeRead for category_t {}
@@ -122,6 +134,8 @@ unsafe impl SafeRead for category_t {}
 pub struct ClassTemplate {
     pub name: &'static str,
     pub superclass: Option<&'static str>,
+    /// Names of the protocols the class adopts.
+    pub protocols: &'static [&'static str],
     pub class_methods: &'static [(&'static str, &'static dyn HostIMP)],
     pub instance_methods: &'static [(&'static str, &'static dyn HostIMP)],
 }
@@ -147,6 +161,17 @@ macro_rules! _objc_superclass {
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _objc_protocols {
+    (<$($name:ident),+>) => {
+        &[$(stringify!($name)),+]
+    };
+    () => {
+        &[]
+    };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! _objc_method {
@@ -190,7 +215,7 @@ macro_rules! _objc_method {
 ///                    // The second one should be `self` to match Objective-C,
 ///                    // but that's reserved in Rust, hence `this`.
 ///
-/// @implementation MyClass: NSObject
+/// @implementation MyClass: NSObject <NSCopying>
 ///
 /// + (id)foo {
 ///     // ...
@@ -215,6 +240,7 @@ macro_rules! _objc_method {
 ///     ("MyClass", ClassTemplate {
 ///         name: "MyClass",
 ///         superclass: Some("NSObject"),
+///         protocols: &["NSCopying"],
 ///         class_methods: &[
 ///             ("foo", &(|env: &mut Environment, this: id, _cmd: SEL| -> id {
 ///                 // ...
@@ -253,6 +279,7 @@ macro_rules! objc_classes {
         ($env:ident, $this:ident, $_cmd:ident);
         $(
             @implementation $class_name:ident $(: $superclass_name:ident)?
+                            $(<$($protocol_name:ident),+>)?
 
             $( + ($cm_type:ty) $cm_name:ident $(:($cm_type1:ty) $cm_arg1:ident)?
                               $($cm_namen:ident:($cm_typen:ty) $cm_argn:ident)*
@@ -279,6 +306,7 @@ macro_rules! objc_classes {
                 (_OBJC_CURRENT_CLASS, $crate::objc::ClassTemplate {
                     name: _OBJC_CURRENT_CLASS,
                     superclass: $crate::_objc_superclass!($(: $superclass_name)?),
+                    protocols: $crate::_objc_protocols!($(<$($protocol_name),+>)?),
                     class_methods: &[
                         $(
                             (
@@ -357,6 +385,13 @@ impl ClassHostObject {
                     (objc.selectors[name], IMP::Host(host_imp))
                 }),
             ),
+            protocols: template
+                .protocols
+                .iter()
+                .map(|&name| name.to_string())
+                .collect(),
+            method_types: HashMap::new(),
+            ivars: Vec::new(),
             // maybe this should be 0 for NSObject? does it matter?
             _instance_start: size,
             instance_size: size,
@@ -372,6 +407,8 @@ impl ClassHostObject {
             instance_size,
             name,
             base_methods,
+            base_protocols,
+            ivars,
             ..
         } = mem.read(data);
 
@@ -382,6 +419,9 @@ impl ClassHostObject {
             is_metaclass,
             superclass,
             methods: HashMap::new(),
+            method_types: HashMap::new(),
+            protocols: read_bin_protocol_names(base_protocols, mem),
+            ivars: read_bin_ivar_list(ivars, mem),
             _instance_start: instance_start,
             instance_size,
         };
@@ -583,6 +623,7 @@ impl ObjC {
 
         assert!(list.size % 4 == 0);
         let base: ConstPtr<Class> = Ptr::from_bits(list.addr);
+        let mut bin_classes = Vec::new();
         for i in 0..(list.size / 4) {
             let class = mem.read(base + i);
             let metaclass = Self::read_isa(class, mem);
@@ -597,6 +638,7 @@ impl ObjC {
                 self.register_static_object(metaclass, metaclass_host_object);
                 name
             } else {
+                bin_classes.push(class);
                 let class_host_object = Box::new(ClassHostObject::from_bin(
                     class, /* is_metaclass: */ false, mem, self,
                 ));
@@ -614,6 +656,45 @@ impl ObjC {
 
             self.classes.insert(name.to_string(), class);
         }
+        // Classes that were previously unimplemented may now have methods.
+        self.flush_method_cache();
+
+        // A class's +load method is called after its superclasses' ones, but
+        // otherwise the order is the one in the binary.
+        let bin_classes_set: HashSet<Class> = bin_classes.iter().copied().collect();
+        let mut scheduled = HashSet::new();
+        for class in bin_classes {
+            self.schedule_class_load(class, &bin_classes_set, &mut scheduled, mem);
+        }
+    }
+
+    /// Queue up the `+load` method of a class from an app binary, if it has
+    /// one, after those of its superclasses. Only classes in `bin_classes` are
+    /// considered, since other classes' methods were already called.
+    fn schedule_class_load(
+        &mut self,
+        class: Class,
+        bin_classes: &HashSet<Class>,
+        scheduled: &mut HashSet<Class>,
+        mem: &Mem,
+    ) {
+        if !bin_classes.contains(&class) || !scheduled.insert(class) {
+            return;
+        }
+        let superclass = self.borrow::<ClassHostObject>(class).superclass;
+        self.schedule_class_load(superclass, bin_classes, scheduled, mem);
+
+        // The method must be looked up in the class's own method list, because
+        // +load is not inherited.
+        let metaclass = Self::read_isa(class, mem);
+        let class_t { data, .. } = mem.read(metaclass.cast());
+        let class_rw_t { base_methods, .. } = mem.read(data);
+        if base_methods.is_null() {
+            return;
+        }
+        if let Some(imp) = find_method_in_bin_list(base_methods, "load", mem) {
+            self.pending_load_methods.push((class, imp));
+        }
     }
 
     /// For use by [crate::dyld]: register all the categories from the
@@ -631,8 +712,30 @@ impl ObjC {
 
             let name = mem.cstr_at_utf8(data.name).unwrap();
             let class = data.class;
+            if class == nil {
+                // This happens if the category is on a weakly-linked class that
+                // doesn't exist.
+                log!(
+                    "Warning: ignoring guest app category \"{}\" {:?} on missing class",
+                    name,
+                    cat_ptr
+                );
+                continue;
+            }
             let metaclass = Self::read_isa(class, mem);
 
+            let protocols = read_bin_protocol_names(data.protocols, mem);
+            if self
+                .get_host_object(class)
+                .unwrap()
+                .as_any()
+                .is::<ClassHostObject>()
+            {
+                self.borrow_mut::<ClassHostObject>(class)
+                    .protocols
+                    .extend(protocols);
+            }
+
             for (class, methods) in [
                 (class, data.instance_methods),
                 (metaclass, data.class_methods),
@@ -643,6 +746,13 @@ impl ObjC {
 
                 let any = self.get_host_object(class).unwrap().as_any();
                 if any.is::<FakeClass>() || any.is::<UnimplementedClass>() {
+                    log!(
+                        "Warning: ignoring guest app category \"{}\" {:?} on unimplemented class \"{}\" {:?}",
+                        name,
+                        cat_ptr,
+                        self.get_class_name(class),
+                        class,
+                    );
                     continue;
                 }
 
@@ -655,6 +765,9 @@ impl ObjC {
                         is_metaclass: Default::default(),
                         superclass: nil,
                         methods: Default::default(),
+                        method_types: Default::default(),
+                        protocols: Default::default(),
+                        ivars: Default::default(),
                         _instance_start: Default::default(),
                         instance_size: Default::default(),
                     },
@@ -678,6 +791,14 @@ impl ObjC {
                 );
                 host_obj.add_methods_from_bin(methods, mem, self);
                 *self.borrow_mut::<ClassHostObject>(class) = host_obj;
+                self.flush_method_cache();
+
+                if class == metaclass {
+                    if let Some(imp) = find_method_in_bin_list(methods, "load", mem) {
+                        // The receiver is the class, not the metaclass.
+                        self.pending_load_methods.push((data.class, imp));
+                    }
+                }
             }
         }
     }
@@ -714,4 +835,158 @@ impl ObjC {
             panic!();
         }
     }
+
+    pub fn class_is_metaclass(&self, class: Class) -> bool {
+        let host_object = self.get_host_object(class).unwrap();
+        if let Some(&ClassHostObject { is_metaclass, .. }) = host_object.as_any().downcast_ref() {
+            is_metaclass
+        } else if let Some(&UnimplementedClass { is_metaclass, .. }) =
+            host_object.as_any().downcast_ref()
+        {
+            is_metaclass
+        } else if let Some(&FakeClass { is_metaclass, .. }) = host_object.as_any().downcast_ref() {
+            is_metaclass
+        } else {
+            panic!();
+        }
+    }
+    /// Get the names of classes referenced by the app that have no
+    /// implementation (see [UnimplementedClass]), sorted. This is used by
+    /// `--probe`.
+    pub fn unimplemented_class_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .classes
+            .iter()
+            .filter(|&(_, &class)| self.class_is_unimplemented(class))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Get the names of classes from the app whose superclasses have no
+    /// implementation, and the names of those superclasses, sorted. These
+    /// classes can't be instantiated. This is used by `--probe`.
+    pub fn classes_with_unimplemented_superclass(&self) -> Vec<(&str, &str)> {
+        let mut pairs: Vec<(&str, &str)> = self
+            .classes
+            .iter()
+            .filter_map(|(name, &class)| {
+                let host_object = self.get_host_object(class)?.as_any();
+                let &ClassHostObject { superclass, .. } = host_object.downcast_ref()?;
+                (superclass != nil && self.class_is_unimplemented(superclass))
+                    .then(|| (name.as_str(), self.get_class_name(superclass)))
+            })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn class_is_unimplemented(&self, class: Class) -> bool {
+        self.get_host_object(class)
+            .unwrap()
+            .as_any()
+            .is::<UnimplementedClass>()
+    }
+
+    /// Check if a class has been registered, i.e. that it isn't one created by
+    /// `objc_allocateClassPair()` that is still under construction.
+    pub(super) fn class_is_registered(&self, class: Class) -> bool {
+        let name = self.get_class_name(class);
+        self.classes.get(name) == Some(&class)
+    }
+}
+
+/// Call the `+load` methods from app binaries that were registered since the
+/// last call. This should be done once a binary has been linked, before its
+/// static initializers are run.
+///
+/// As on Apple's runtime, all the classes' `+load` methods are called before
+/// any of the categories' ones, and a class's method is called before its
+/// subclasses' ones.
+pub fn call_load_methods(env: &mut Environment) {
+    let pending = std::mem::take(&mut env.objc.pending_load_methods);
+    if pending.is_empty() {
+        return;
+    }
+    let sel = env
+        .objc
+        .register_host_selector("load".to_string(), &mut env.mem);
+    for (class, imp) in pending {
+        log_dbg!(
+            "Calling +load method {:?} for class \"{}\" {:?}",
+            imp,
+            env.objc.get_class_name(class),
+            class
+        );
+        () = imp.call_from_host(env, (class, sel));
+    }
+}
+
+/// Create a new class and metaclass at runtime. Methods and ivars can be added
+/// to the class before it is registered with `objc_registerClassPair()`.
+pub(super) fn objc_allocateClassPair(
+    env: &mut Environment,
+    superclass: Class,
+    name: ConstPtr<u8>,
+    extra_bytes: GuestUSize,
+) -> Class {
+    let name = env.mem.cstr_at_utf8(name).unwrap().to_string();
+    if env.objc.classes.contains_key(&name) {
+        log!(
+            "Warning: objc_allocateClassPair() for existing class {:?}",
+            name
+        );
+        return nil;
+    }
+    // Creating new root classes isn't supported, see link_class_inner().
+    assert!(superclass != nil);
+    if extra_bytes != 0 {
+        log!(
+            "TODO: objc_allocateClassPair() with {} extra bytes for class {:?}",
+            extra_bytes,
+            name
+        );
+    }
+
+    let super_metaclass = ObjC::read_isa(superclass, &env.mem);
+    let [class_host_object, metaclass_host_object] = [(superclass, false), (super_metaclass, true)]
+        .map(|(superclass, is_metaclass)| {
+            let &ClassHostObject { instance_size, .. } = env.objc.borrow(superclass);
+            Box::new(ClassHostObject {
+                name: name.clone(),
+                is_metaclass,
+                superclass,
+                methods: HashMap::new(),
+                method_types: HashMap::new(),
+                protocols: Vec::new(),
+                ivars: Vec::new(),
+                _instance_start: instance_size,
+                instance_size,
+            })
+        });
+
+    let isa = env
+        .objc
+        .link_class("NSObject", /* is_metaclass: */ true, &mut env.mem);
+    let metaclass = env
+        .objc
+        .alloc_static_object(isa, metaclass_host_object, &mut env.mem);
+    let class = env
+        .objc
+        .alloc_static_object(metaclass, class_host_object, &mut env.mem);
+    log_dbg!(
+        "objc_allocateClassPair({:?}, {:?}) => {:?}",
+        superclass,
+        name,
+        class
+    );
+    class
+}
+
+/// Make a class created with `objc_allocateClassPair()` available for use.
+pub(super) fn objc_registerClassPair(env: &mut Environment, class: Class) {
+    let name = env.objc.get_class_name(class).to_string();
+    log_dbg!("objc_registerClassPair({:?}) ({:?})", class, name);
+    env.objc.classes.insert(name, class);
 }
@@ -8,12 +8,14 @@
 
 use crate::frameworks::{
     av_audio, core_animation, core_foundation, core_graphics, foundation, media_player, opengles,
-    store_kit, uikit,
+    store_kit, uikit, voice_services,
 };
 
 /// All the lists of classes that the runtime should search through.
 pub const CLASS_LISTS: &[super::ClassExports] = &[
-    crate::app_picker::CLASSES, // Not a framework! Special internal classes.
+    crate::app_picker::CLASSES,   // Not a framework! Special internal classes.
+    crate::objc::blocks::CLASSES, // Not a framework! Part of the runtime.
+    crate::objc::protocols::CLASSES, // Not a framework! Part of the runtime.
     core_animation::ca_eagl_layer::CLASSES,
     core_animation::ca_layer::CLASSES,
     core_graphics::cg_data_provider::CLASSES,
@@ -22,8 +24,10 @@ pub const CLASS_LISTS: &[super::ClassExports] = &[
     core_graphics::cg_image::CLASSES,
     core_foundation::cf_run_loop_timer::CLASSES, // Special internal classes.
     foundation::ns_array::CLASSES,
+    foundation::ns_assertion_handler::CLASSES,
     foundation::ns_autorelease_pool::CLASSES,
     foundation::ns_bundle::CLASSES,
+    foundation::ns_cache::CLASSES,
     foundation::ns_character_set::CLASSES,
     foundation::ns_coder::CLASSES,
     foundation::ns_data::CLASSES,
@@ -32,24 +36,34 @@ pub const CLASS_LISTS: &[super::ClassExports] = &[
     foundation::ns_dictionary::CLASSES,
     foundation::ns_enumerator::CLASSES,
     foundation::ns_error::CLASSES,
+    foundation::ns_exception::CLASSES,
     foundation::ns_file_manager::CLASSES,
+    foundation::ns_hash_table::CLASSES,
+    foundation::ns_index_set::CLASSES,
+    foundation::ns_invocation::CLASSES,
     foundation::ns_keyed_unarchiver::CLASSES,
     foundation::ns_locale::CLASSES,
     foundation::ns_lock::CLASSES,
+    foundation::ns_map_table::CLASSES,
+    foundation::ns_method_signature::CLASSES,
     foundation::ns_notification::CLASSES,
     foundation::ns_notification_center::CLASSES,
     foundation::ns_null::CLASSES,
     foundation::ns_object::CLASSES,
+    foundation::ns_pointer_array::CLASSES,
     foundation::ns_process_info::CLASSES,
     foundation::ns_run_loop::CLASSES,
     foundation::ns_set::CLASSES,
     foundation::ns_string::CLASSES,
     foundation::ns_thread::CLASSES,
+    foundation::ns_time_zone::CLASSES,
     foundation::ns_timer::CLASSES,
     foundation::ns_url::CLASSES,
     foundation::ns_user_defaults::CLASSES,
+    foundation::ns_uuid::CLASSES,
     foundation::ns_value::CLASSES,
     av_audio::av_audio_player::CLASSES,
+    av_audio::av_audio_recorder::CLASSES,
     media_player::movie_player::CLASSES,
     media_player::music_player::CLASSES,
     opengles::eagl::CLASSES,
@@ -74,6 +88,8 @@ pub const CLASS_LISTS: &[super::ClassExports] = &[
     uikit::ui_view::ui_control::ui_text_field::CLASSES,
     uikit::ui_view::ui_image_view::CLASSES,
     uikit::ui_view::ui_label::CLASSES,
+    uikit::ui_view::ui_scroll_view::CLASSES,
     uikit::ui_view::ui_window::CLASSES,
     uikit::ui_view_controller::CLASSES,
+    voice_services::CLASSES,
 ];
@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Objective-C exceptions (`@throw`, `@try`, `@catch` and `@finally`).
+//!
+//! `@throw` calls `objc_exception_throw()`, which wraps the object in an
+//! unwind exception and raises it with [crate::libc::unwind]. The compiler
+//! emits landing pads for `@catch` and `@finally` blocks, which
+//! `__objc_personality_v0` picks between using type info structures
+//! (`_OBJC_EHTYPE_$_SomeClass`), and `@catch` blocks are bracketed by
+//! `objc_begin_catch()` and `objc_end_catch()`.
+//!
+//! Apple's runtime makes Objective-C exceptions into C++ exceptions instead,
+//! but here they are a separate kind, so C++ `catch (...)` blocks can catch
+//! them but not vice-versa.
+//!
+//! Resources:
+//! - [objc4 source code](https://opensource.apple.com/source/objc4/objc4-532.2/runtime/objc-exception.mm.auto.html), which defines the type info structure
+//! - Apple's [Exception Programming Topics](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/Exceptions/Exceptions.html)
+
+use super::{id, nil, release, retain, Class, ObjC};
+use crate::abi::GuestFunction;
+use crate::frameworks::foundation::ns_exception::handle_uncaught_exception;
+use crate::libc::unwind::{
+    self, _Unwind_Action, _Unwind_Reason_Code, UnwindException, _URC_HANDLER_FOUND,
+};
+use crate::mem::{ConstPtr, ConstVoidPtr, Mem, MutPtr, MutVoidPtr, SafeRead};
+use crate::Environment;
+
+/// `"GNUCOBJC"`, as used by GCC's Objective-C runtime.
+const OBJC_EXCEPTION_CLASS: u64 = u64::from_be_bytes(*b"GNUCOBJC");
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct ObjCException {
+    header: UnwindException,
+    object: id,
+}
+unsafe impl SafeRead for ObjCException {}
+
+/// `struct objc_typeinfo`
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct ObjCTypeInfo {
+    vtable: ConstVoidPtr,
+    name: ConstPtr<u8>,
+    /// `nil` for `_OBJC_EHTYPE_id`, which matches any object.
+    class: Class,
+}
+unsafe impl SafeRead for ObjCTypeInfo {}
+
+/// An exception that is being handled by a `@catch` block.
+pub(super) struct CaughtException {
+    exception: MutPtr<UnwindException>,
+    object: id,
+    /// Set by `objc_exception_rethrow()`, in which case the exception is still
+    /// in use once the `@catch` block ends.
+    rethrown: bool,
+}
+
+impl ObjC {
+    /// For use by [crate::dyld]: get the value of an exception type info
+    /// symbol referenced by the app binary, if the symbol is one.
+    pub fn link_ehtype_symbol(&mut self, symbol: &str, mem: &mut Mem) -> Option<ConstVoidPtr> {
+        if symbol == "_objc_ehtype_vtable" {
+            return Some(self.ehtype_vtable(mem).cast());
+        }
+        let class_name = if symbol == "_OBJC_EHTYPE_id" {
+            None
+        } else {
+            Some(symbol.strip_prefix("_OBJC_EHTYPE_$_")?)
+        };
+        if let Some(&type_info) = self.ehtypes.get(symbol) {
+            return Some(type_info);
+        }
+        let vtable = (self.ehtype_vtable(mem) + 2).cast();
+        let name = mem.alloc_and_write_cstr(class_name.unwrap_or("id").as_bytes());
+        let class = class_name.map_or(nil, |name| {
+            self.link_class(name, /* is_metaclass: */ false, mem)
+        });
+        let type_info = mem
+            .alloc_and_write(ObjCTypeInfo {
+                vtable,
+                name: name.cast_const(),
+                class,
+            })
+            .cast_void()
+            .cast_const();
+        self.ehtypes.insert(symbol.to_string(), type_info);
+        Some(type_info)
+    }
+
+    /// `objc_ehtype_vtable`. Its contents don't matter, only its address is
+    /// used to recognize Objective-C type info. Like in a C++ vtable, type info
+    /// points two entries into it.
+    fn ehtype_vtable(&mut self, mem: &mut Mem) -> ConstPtr<ConstVoidPtr> {
+        *self
+            .ehtype_vtable
+            .get_or_insert_with(|| mem.alloc(8 * 4).cast().cast_const())
+    }
+}
+
+/// Also used by `-[NSException raise]`.
+pub fn objc_exception_throw(env: &mut Environment, object: id) {
+    log_dbg!("objc_exception_throw({:?})", object);
+    retain(env, object);
+    let exception = env
+        .mem
+        .alloc_and_write(ObjCException {
+            header: UnwindException::new(OBJC_EXCEPTION_CLASS),
+            object,
+        })
+        .cast::<UnwindException>();
+    // This only returns if no handler was found.
+    let reason = unwind::raise_exception(env, exception);
+    log_dbg!("raise_exception() returned {}", reason);
+    unwind::delete_exception(env, exception);
+    env.mem.free(exception.cast());
+    handle_uncaught_exception(env, object)
+}
+
+pub(super) fn objc_exception_rethrow(env: &mut Environment) {
+    let thread = env.current_thread;
+    let caught = env
+        .objc
+        .caught_exceptions
+        .get_mut(&thread)
+        .and_then(|caught| caught.last_mut())
+        .expect("objc_exception_rethrow() outside @catch block");
+    caught.rethrown = true;
+    let (exception, object) = (caught.exception, caught.object);
+
+    if env.mem.read(exception).exception_class != OBJC_EXCEPTION_CLASS {
+        // Probably a C++ exception caught by @catch (...).
+        let rethrow = env
+            .bins
+            .iter()
+            .find_map(|bin| bin.exported_symbols.get("__Unwind_SjLj_Resume_or_Rethrow"))
+            .map(|&addr| GuestFunction::from_addr_with_thumb_bit(addr))
+            .expect("Can't rethrow a foreign exception without libgcc");
+        let regs = env.cpu.regs_mut();
+        regs[0] = exception.to_bits();
+        // Tail call
+        env.cpu.branch(rethrow);
+        return;
+    }
+
+    log_dbg!("objc_exception_rethrow() for {:?}", object);
+    let reason = unwind::raise_exception(env, exception);
+    log_dbg!("raise_exception() returned {}", reason);
+    handle_uncaught_exception(env, object)
+}
+
+pub(super) fn objc_begin_catch(env: &mut Environment, exception: MutPtr<UnwindException>) -> id {
+    let object = if env.mem.read(exception).exception_class == OBJC_EXCEPTION_CLASS {
+        env.mem.read(exception.cast::<ObjCException>()).object
+    } else {
+        // Foreign exceptions can only be caught by @catch (...), which has no
+        // variable to put an object in.
+        nil
+    };
+    log_dbg!("objc_begin_catch({:?}) => {:?}", exception, object);
+    let thread = env.current_thread;
+    env.objc
+        .caught_exceptions
+        .entry(thread)
+        .or_default()
+        .push(CaughtException {
+            exception,
+            object,
+            rethrown: false,
+        });
+    object
+}
+
+pub(super) fn objc_end_catch(env: &mut Environment) {
+    let thread = env.current_thread;
+    let CaughtException {
+        exception,
+        object,
+        rethrown,
+    } = env
+        .objc
+        .caught_exceptions
+        .get_mut(&thread)
+        .and_then(|caught| caught.pop())
+        .expect("objc_end_catch() outside @catch block");
+    log_dbg!("objc_end_catch() for {:?}", exception);
+    if rethrown || env.mem.read(exception).exception_class != OBJC_EXCEPTION_CLASS {
+        // TODO: delete foreign exceptions with _Unwind_DeleteException()?
+        return;
+    }
+    unwind::delete_exception(env, exception);
+    env.mem.free(exception.cast());
+    release(env, object);
+}
+
+pub(super) fn objc_terminate(_env: &mut Environment) {
+    panic!("objc_terminate() called");
+}
+
+pub(super) fn __objc_personality_v0(
+    env: &mut Environment,
+    version: i32,
+    actions: _Unwind_Action,
+    exception_class: u64,
+    exception: MutPtr<UnwindException>,
+    context: MutVoidPtr,
+) -> _Unwind_Reason_Code {
+    assert!(version == 1);
+    let vtable: ConstVoidPtr = (env.objc.ehtype_vtable(&mut env.mem) + 2).cast();
+    let mut type_matches = |env: &mut Environment, type_info: ConstVoidPtr| -> bool {
+        if type_info.is_null() {
+            // @catch (...)
+            return true;
+        }
+        if exception_class != OBJC_EXCEPTION_CLASS {
+            return false;
+        }
+        let ObjCTypeInfo {
+            vtable: type_info_vtable,
+            class,
+            ..
+        } = env.mem.read(type_info.cast());
+        if type_info_vtable != vtable {
+            // Probably a C++ catch clause in Objective-C++ code.
+            return false;
+        }
+        if class == nil {
+            // @catch (id e)
+            return true;
+        }
+        let object = env.mem.read(exception.cast::<ObjCException>()).object;
+        let object_class = ObjC::read_isa(object, &env.mem);
+        env.objc.class_is_subclass_of(object_class, class)
+    };
+    let reason = unwind::personality(env, actions, exception, context, &mut type_matches);
+    if reason == _URC_HANDLER_FOUND {
+        log_dbg!("Found handler for exception {:?}", exception);
+    }
+    reason
+}
@@ -0,0 +1,213 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Handling of Objective-C instance variables (ivars).
+//!
+//! Our host classes keep all their data in host objects, so only classes from
+//! the guest app, and classes created at runtime by the guest app, have ivars.
+//!
+//! Resources:
+//! - [objc4 source code](https://opensource.apple.com/source/objc4/objc4-532.2/runtime/objc-runtime-new.mm.auto.html), which defines `ivar_t` and `ivar_list_t`
+//! - [Apple's documentation of `class_addIvar`](https://developer.apple.com/documentation/objectivec/1418756-class_addivar?language=objc)
+
+use super::{id, nil, Class, ClassHostObject, ObjC};
+use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
+use crate::Environment;
+
+/// The layout of an ivar list in an app binary.
+#[repr(C, packed)]
+pub(super) struct ivar_list_t {
+    entsize: GuestUSize,
+    count: GuestUSize,
+    // entries follow the struct
+}
+unsafe impl SafeRead for ivar_list_t {}
+
+/// The layout of an ivar in an app binary. This is also what an `Ivar` points
+/// to.
+#[repr(C, packed)]
+pub(super) struct ivar_t {
+    /// Pointer to the ivar's offset within the object. The compiler accesses
+    /// ivars through this, so it can be updated if a superclass grows.
+    offset: MutPtr<u32>,
+    name: ConstPtr<u8>,
+    type_: ConstPtr<u8>,
+    /// log2 of the alignment.
+    alignment: u32,
+    size: u32,
+}
+unsafe impl SafeRead for ivar_t {}
+
+/// Get pointers to the entries of an ivar list in an app binary. The list
+/// pointer may be null.
+pub(super) fn read_bin_ivar_list(
+    list_ptr: ConstPtr<ivar_list_t>,
+    mem: &Mem,
+) -> Vec<ConstPtr<ivar_t>> {
+    if list_ptr.is_null() {
+        return Vec::new();
+    }
+    let ivar_list_t { entsize, count } = mem.read(list_ptr);
+    assert!(entsize >= guest_size_of::<ivar_t>());
+
+    let base: ConstPtr<ivar_t> = (list_ptr + 1).cast();
+    (0..count)
+        .map(|i| Ptr::from_bits(base.to_bits() + i * entsize))
+        .collect()
+}
+
+/// Add an ivar to a class created with `objc_allocateClassPair()`. This must
+/// happen before the class is registered, because it changes the instance
+/// size.
+pub(super) fn class_addIvar(
+    env: &mut Environment,
+    class: Class,
+    name: ConstPtr<u8>,
+    size: GuestUSize,
+    alignment: u8, // log2
+    types: ConstPtr<u8>,
+) -> bool {
+    if class == nil {
+        return false;
+    }
+    let name_str = env.mem.cstr_at_utf8(name).unwrap().to_string();
+
+    let host_object = env.objc.borrow::<ClassHostObject>(class);
+    let already_exists = host_object.ivars.iter().any(|&ivar| {
+        let ivar_t { name, .. } = env.mem.read(ivar);
+        env.mem.cstr_at(name) == name_str.as_bytes()
+    });
+    if host_object.is_metaclass
+        || env.objc.class_is_registered(class)
+        || already_exists
+        || alignment >= 32
+    {
+        log!("Warning: class_addIvar({:?}, {:?}) failed", class, name_str);
+        return false;
+    }
+
+    let align_mask = (1u32 << alignment) - 1;
+    let offset = (host_object.instance_size + align_mask) & !align_mask;
+    let instance_size = offset + size;
+
+    // The runtime owns these, since the strings passed in might not live as
+    // long as the class.
+    let ivar = ivar_t {
+        offset: env.mem.alloc_and_write(offset),
+        name: env
+            .mem
+            .alloc_and_write_cstr(name_str.as_bytes())
+            .cast_const(),
+        type_: if types.is_null() {
+            Ptr::null()
+        } else {
+            let types = env.mem.cstr_at(types).to_vec();
+            env.mem.alloc_and_write_cstr(&types).cast_const()
+        },
+        alignment: alignment.into(),
+        size,
+    };
+    let ivar = env.mem.alloc_and_write(ivar).cast_const();
+
+    log_dbg!(
+        "class_addIvar({:?}, {:?}, {}, {}) => offset {}",
+        class,
+        name_str,
+        size,
+        alignment,
+        offset
+    );
+
+    let host_object = env.objc.borrow_mut::<ClassHostObject>(class);
+    host_object.ivars.push(ivar);
+    host_object.instance_size = instance_size;
+    true
+}
+
+pub(super) fn class_getInstanceVariable(
+    env: &mut Environment,
+    class: Class,
+    name: ConstPtr<u8>,
+) -> ConstPtr<ivar_t> {
+    if class == nil || name.is_null() {
+        return Ptr::null();
+    }
+    let name = env.mem.cstr_at(name);
+    env.objc
+        .lookup_ivar(class, name, &env.mem)
+        .unwrap_or_default()
+}
+
+pub(super) fn ivar_getOffset(env: &mut Environment, ivar: ConstPtr<ivar_t>) -> i32 {
+    if ivar.is_null() {
+        return 0;
+    }
+    let ivar_t { offset, .. } = env.mem.read(ivar);
+    env.mem.read(offset) as i32
+}
+
+pub(super) fn object_getIvar(env: &mut Environment, object: id, ivar: ConstPtr<ivar_t>) -> id {
+    if object == nil || ivar.is_null() {
+        return nil;
+    }
+    let offset = ivar_getOffset(env, ivar) as GuestUSize;
+    let ptr: ConstPtr<id> = Ptr::from_bits(object.to_bits() + offset);
+    env.mem.read(ptr)
+}
+
+/// Like Apple's runtime, this doesn't retain the new value or release the old
+/// one.
+pub(super) fn object_setIvar(env: &mut Environment, object: id, ivar: ConstPtr<ivar_t>, value: id) {
+    if object == nil || ivar.is_null() {
+        return;
+    }
+    let offset = ivar_getOffset(env, ivar) as GuestUSize;
+    let ptr: MutPtr<id> = Ptr::from_bits(object.to_bits() + offset);
+    env.mem.write(ptr, value);
+}
+
+impl ObjC {
+    /// Look up an ivar by name in a class or its superclasses.
+    fn lookup_ivar(&self, class: Class, name: &[u8], mem: &Mem) -> Option<ConstPtr<ivar_t>> {
+        let mut class = class;
+        while class != nil {
+            let host_object = self.borrow::<ClassHostObject>(class);
+            for &ivar in &host_object.ivars {
+                let ivar_t {
+                    name: ivar_name, ..
+                } = mem.read(ivar);
+                if mem.cstr_at(ivar_name) == name {
+                    return Some(ivar);
+                }
+            }
+            class = host_object.superclass;
+        }
+        None
+    }
+
+    /// Look up an ivar by name in a class or its superclasses, for code that
+    /// needs to access ivars directly, like key-value coding. Returns the
+    /// ivar's offset within an instance, its size and its type encoding.
+    pub fn find_ivar(
+        &self,
+        class: Class,
+        name: &str,
+        mem: &Mem,
+    ) -> Option<(GuestUSize, GuestUSize, String)> {
+        let ivar = self.lookup_ivar(class, name.as_bytes(), mem)?;
+        let ivar_t {
+            offset,
+            type_,
+            size,
+            ..
+        } = mem.read(ivar);
+        let type_ = if type_.is_null() {
+            String::new()
+        } else {
+            String::from_utf8_lossy(mem.cstr_at(type_)).into_owned()
+        };
+        Some((mem.read(offset), size, type_))
+    }
+}
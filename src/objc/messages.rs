@@ -12,7 +12,9 @@
 //! - Peter Steinberger's [Calling Super at Runtime in Swift](https://steipete.com/posts/calling-super-at-runtime/) explains `objc_msgSendSuper2`
 
 use super::{id, nil, Class, ObjC, IMP, SEL};
-use crate::abi::{CallFromHost, GuestRet};
+use crate::abi::{CallFromHost, GuestArg, GuestRet};
+use crate::api_stats;
+use crate::frameworks::foundation::ns_invocation;
 use crate::mem::{ConstPtr, MutVoidPtr, SafeRead};
 use crate::Environment;
 use std::any::TypeId;
@@ -29,13 +31,27 @@ use std::any::TypeId;
 /// Similarly, the return value of `objc_msgSend` is whatever value is returned
 /// by the method implementation. We are relying on CallFromGuest not
 /// overwriting it.
+///
+/// The result of looking up a method is remembered in a cache on [ObjC], so
+/// the superclass chain is only walked the first time a class receives a
+/// particular message. Anything that changes the methods of a class must call
+/// [ObjC::flush_method_cache].
 #[allow(non_snake_case)]
-fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2: Option<Class>) {
+fn objc_msgSend_inner(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    super2: Option<Class>,
+    stret: bool,
+) {
     let message_type_info = env.objc.message_type_info.take();
+    let from_guest = api_stats::take_guest_message_pending(env);
 
     if receiver == nil {
         // https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjectiveC/Chapters/ocObjectsClasses.html#//apple_ref/doc/uid/TP30001163-CH11-SW7
         log_dbg!("[nil {}]", selector.as_str(&env.mem));
+        // For a struct return, Apple's Arm runtime leaves the memory
+        // untouched. Host callers get zeroes (see [crate::abi::CallFromHost]).
         env.cpu.regs_mut()[0..2].fill(0);
         return;
     }
@@ -43,6 +59,20 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
     let orig_class = super2.unwrap_or_else(|| ObjC::read_isa(receiver, &env.mem));
     assert!(orig_class != nil);
 
+    let cache_key = (orig_class, selector, super2.is_some());
+    if let Some(&(class, imp)) = env.objc.method_cache.get(&cache_key) {
+        call_method(
+            env,
+            receiver,
+            selector,
+            class,
+            imp,
+            message_type_info,
+            from_guest,
+        );
+        return;
+    }
+
     // Traverse the chain of superclasses to find the method implementation.
 
     let mut class = orig_class;
@@ -50,13 +80,27 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
         if class == nil {
             assert!(class != orig_class);
 
+            if forward_message(env, receiver, selector, stret) {
+                return;
+            }
+
             let class_host_object = env.objc.get_host_object(orig_class).unwrap();
             let &super::ClassHostObject {
                 ref name,
                 is_metaclass,
                 ..
             } = class_host_object.as_any().downcast_ref().unwrap();
+            let name = name.clone();
 
+            if from_guest {
+                let description = format!(
+                    "{}[{} {}] (does not respond)",
+                    if is_metaclass { '+' } else { '-' },
+                    name,
+                    selector.as_str(&env.mem)
+                );
+                api_stats::count_missing(env, description);
+            }
             panic!(
                 "{} {:?} ({}class \"{}\", {:?}){} does not respond to selector \"{}\"!",
                 if is_metaclass { "Class" } else { "Object" },
@@ -88,36 +132,17 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
                 continue;
             }
 
-            if let Some(imp) = methods.get(&selector) {
-                match imp {
-                    IMP::Host(host_imp) => {
-                        // TODO: do type checks when calling GuestIMPs too.
-                        // That requires using Objective-C type strings, rather
-                        // than Rust types, and should probably warn rather than
-                        // panicking, because apps might rely on type punning.
-                        if let Some((sent_type_id, sent_type_desc)) = message_type_info {
-                            let (expected_type_id, expected_type_desc) = host_imp.type_info();
-                            if sent_type_id != expected_type_id {
-                                panic!(
-                                    "\
-Type mismatch when sending message {} to {:?}!
-- Message has type: {:?} / {}
-- Method expects type: {:?} / {}",
-                                    selector.as_str(&env.mem),
-                                    receiver,
-                                    sent_type_id,
-                                    sent_type_desc,
-                                    expected_type_id,
-                                    expected_type_desc
-                                );
-                            }
-                        }
-                        host_imp.call_from_guest(env)
-                    }
-                    // We can't create a new stack frame, because that would
-                    // interfere with pass-through of stack arguments.
-                    IMP::Guest(guest_imp) => guest_imp.call_without_pushing_stack_frame(env),
-                }
+            if let Some(&imp) = methods.get(&selector) {
+                env.objc.method_cache.insert(cache_key, (class, imp));
+                call_method(
+                    env,
+                    receiver,
+                    selector,
+                    class,
+                    imp,
+                    message_type_info,
+                    from_guest,
+                );
                 return;
             } else {
                 class = superclass;
@@ -127,6 +152,16 @@ Type mismatch when sending message {} to {:?}!
             is_metaclass,
         }) = host_object.as_any().downcast_ref()
         {
+            let name = name.clone();
+            if from_guest {
+                let description = format!(
+                    "{}[{} {}] (unimplemented class)",
+                    if is_metaclass { '+' } else { '-' },
+                    name,
+                    selector.as_str(&env.mem)
+                );
+                api_stats::count_missing(env, description);
+            }
             panic!(
                 "Class \"{}\" ({:?}) is unimplemented. Call to {} method \"{}\".",
                 name,
@@ -139,6 +174,16 @@ Type mismatch when sending message {} to {:?}!
             is_metaclass,
         }) = host_object.as_any().downcast_ref()
         {
+            let name = name.clone();
+            if from_guest {
+                let description = format!(
+                    "{}[{} {}] (faked class)",
+                    if is_metaclass { '+' } else { '-' },
+                    name,
+                    selector.as_str(&env.mem)
+                );
+                api_stats::count_missing(env, description);
+            }
             log!(
                 "Call to faked class \"{}\" ({:?}) {} method \"{}\". Behaving as if message was sent to nil.",
                 name,
@@ -157,10 +202,98 @@ Type mismatch when sending message {} to {:?}!
     }
 }
 
+/// Call the method implementation found by [objc_msgSend_inner]. `class` is the
+/// class the method belongs to.
+fn call_method(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    class: Class,
+    imp: IMP,
+    message_type_info: Option<(TypeId, &'static str)>,
+    from_guest: bool,
+) {
+    match imp {
+        IMP::Host(host_imp) => {
+            if from_guest {
+                api_stats::count_host_method(env, class, selector);
+            }
+            // TODO: do type checks when calling GuestIMPs too.
+            // That requires using Objective-C type strings, rather
+            // than Rust types, and should probably warn rather than
+            // panicking, because apps might rely on type punning.
+            if let Some((sent_type_id, sent_type_desc)) = message_type_info {
+                let (expected_type_id, expected_type_desc) = host_imp.type_info();
+                if sent_type_id != expected_type_id {
+                    panic!(
+                        "\
+Type mismatch when sending message {} to {:?}!
+- Message has type: {:?} / {}
+- Method expects type: {:?} / {}",
+                        selector.as_str(&env.mem),
+                        receiver,
+                        sent_type_id,
+                        sent_type_desc,
+                        expected_type_id,
+                        expected_type_desc
+                    );
+                }
+            }
+            host_imp.call_from_guest(env)
+        }
+        // We can't create a new stack frame, because that would
+        // interfere with pass-through of stack arguments.
+        IMP::Guest(guest_imp) => guest_imp.call_without_pushing_stack_frame(env),
+    }
+}
+
+impl ObjC {
+    /// Forget the results of method lookups. This must be called whenever a
+    /// method is added to a class or its implementation changes.
+    pub(super) fn flush_method_cache(&mut self) {
+        self.method_cache.clear();
+    }
+}
+
+/// Called when the receiver of a message has no method for it, to give it a
+/// chance to handle the message anyway. Returns `false` if it doesn't.
+///
+/// Like in Apple's runtime, `forwardingTargetForSelector:` is tried first, then
+/// `methodSignatureForSelector:` and `forwardInvocation:` (see
+/// [crate::frameworks::foundation::ns_invocation]).
+fn forward_message(env: &mut Environment, receiver: id, selector: SEL, stret: bool) -> bool {
+    // Sending other messages will clobber the argument registers.
+    let arg_regs: [u32; 4] = env.cpu.regs()[0..4].try_into().unwrap();
+
+    if env
+        .objc
+        .object_has_method_named(&env.mem, receiver, "forwardingTargetForSelector:")
+    {
+        let target: id = msg![env; receiver forwardingTargetForSelector:selector];
+        if target != nil && target != receiver {
+            log_dbg!(
+                "Forwarding {:?} from {:?} to {:?}",
+                selector.as_str(&env.mem),
+                receiver,
+                target
+            );
+            let regs = env.cpu.regs_mut();
+            regs[0..4].copy_from_slice(&arg_regs);
+            regs[usize::from(stret)] = target.to_bits();
+            objc_msgSend_inner(env, target, selector, /* super2: */ None, stret);
+            return true;
+        }
+    }
+
+    ns_invocation::forward_invocation(env, receiver, selector, stret, arg_regs)
+}
+
 /// Standard variant of `objc_msgSend`. See [objc_msgSend_inner].
 #[allow(non_snake_case)]
 pub(super) fn objc_msgSend(env: &mut Environment, receiver: id, selector: SEL) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(
+        env, receiver, selector, /* super2: */ None, /* stret: */ false,
+    )
 }
 
 /// Variant of `objc_msgSend` for methods that return a struct via a pointer.
@@ -178,7 +311,24 @@ pub(super) fn objc_msgSend_stret(
     receiver: id,
     selector: SEL,
 ) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(
+        env, receiver, selector, /* super2: */ None, /* stret: */ true,
+    )
+}
+
+/// Variant of `objc_msgSend` for methods that return a floating-point value.
+/// See [objc_msgSend_inner].
+///
+/// On x86 this exists because floating-point values are returned on the x87
+/// stack, but on Arm they are returned in r0 (and r1 for `double`) like any
+/// other value, so this is the same as [objc_msgSend]. Some apps call it
+/// anyway, e.g. if they were compiled with a toolchain that picks the variant
+/// without regard to the target.
+#[allow(non_snake_case)]
+pub(super) fn objc_msgSend_fpret(env: &mut Environment, receiver: id, selector: SEL) {
+    objc_msgSend_inner(
+        env, receiver, selector, /* super2: */ None, /* stret: */ false,
+    )
 }
 
 #[repr(C, packed)]
@@ -212,7 +362,37 @@ pub(super) fn objc_msgSendSuper2(
     // Rewrite first argument to match the normal ABI.
     crate::abi::write_next_arg(&mut 0, env.cpu.regs_mut(), &mut env.mem, receiver);
 
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ Some(class))
+    objc_msgSend_inner(
+        env,
+        receiver,
+        selector,
+        /* super2: */ Some(class),
+        /* stret: */ false,
+    )
+}
+
+/// Variant of `objc_msgSendSuper2` for methods that return a struct via a
+/// pointer. See [objc_msgSendSuper2] and [objc_msgSend_stret].
+#[allow(non_snake_case)]
+pub(super) fn objc_msgSendSuper2_stret(
+    env: &mut Environment,
+    _stret: MutVoidPtr,
+    super_ptr: ConstPtr<objc_super>,
+    selector: SEL,
+) {
+    let objc_super { receiver, class } = env.mem.read(super_ptr);
+
+    // Rewrite second argument (after the struct return pointer) to match the
+    // normal ABI.
+    crate::abi::write_next_arg(&mut 1, env.cpu.regs_mut(), &mut env.mem, receiver);
+
+    objc_msgSend_inner(
+        env,
+        receiver,
+        selector,
+        /* super2: */ Some(class),
+        /* stret: */ true,
+    )
 }
 
 /// Trait that assists with type-checking of [msg_send]'s arguments.
@@ -255,6 +435,30 @@ where
     }
 }
 
+/// Variant of [msg_send] for when the method's return type isn't known and the
+/// return value is discarded, e.g. for `makeObjectsPerformSelector:`. There is
+/// no type checking, so host methods with any non-struct return type can be
+/// called. The method must not return a struct in memory.
+pub fn msg_send_discarding_return<P>(env: &mut Environment, args: P)
+where
+    fn(&mut Environment, id, SEL): CallFromHost<(), P>,
+{
+    (objc_msgSend as fn(&mut Environment, id, SEL)).call_from_host(env, args)
+}
+
+/// Send a message whose arguments, including the receiver and selector, have
+/// already been written to registers and the stack. This is for
+/// `NSInvocation`, which only knows the arguments' types at runtime.
+///
+/// If `stret` is `true`, the first argument is the pointer for the struct
+/// return (see [objc_msgSend_stret]).
+pub fn msg_send_prepared(env: &mut Environment, stret: bool) {
+    let regs = &env.cpu.regs()[usize::from(stret)..];
+    let receiver = <id as GuestArg>::from_regs(&regs[0..1]);
+    let selector = <SEL as GuestArg>::from_regs(&regs[1..2]);
+    objc_msgSend_inner(env, receiver, selector, /* super2: */ None, stret)
+}
+
 /// Counterpart of [MsgSendSignature] for [msg_send_super2].
 pub trait MsgSendSuperSignature: 'static {
     /// Signature with the [objc_super] pointer replaced by [id].
@@ -273,7 +477,8 @@ where
     // Provide type info for dynamic type checking.
     env.objc.message_type_info = Some(<(R, P) as MsgSendSuperSignature>::WithoutSuper::type_info());
     if R::SIZE_IN_MEM.is_some() {
-        todo!() // no stret yet
+        (objc_msgSendSuper2_stret as fn(&mut Environment, MutVoidPtr, ConstPtr<objc_super>, SEL))
+            .call_from_host(env, args)
     } else {
         (objc_msgSendSuper2 as fn(&mut Environment, ConstPtr<objc_super>, SEL))
             .call_from_host(env, args)
@@ -308,8 +513,9 @@ macro_rules! msg {
                              $($namen:ident: $argn:tt)*] => {
         {
             let sel = $crate::objc::selector!($($arg1;)? $name $(, $namen)*);
-            let sel = $env.objc.lookup_selector(sel)
-                .expect("Unknown selector");
+            static SEL_CACHE: $crate::objc::SelectorCache =
+                $crate::objc::SelectorCache::new();
+            let sel = SEL_CACHE.lookup(&$env.objc, sel);
             let args = ($receiver, sel, $($arg1,)? $($argn),*);
             $crate::objc::msg_send($env, args)
         }
@@ -351,8 +557,9 @@ macro_rules! msg_super {
                 &mut $env.mem
             );
             let sel = $crate::objc::selector!($($arg1;)? $name $(, $namen)*);
-            let sel = $env.objc.lookup_selector(sel)
-                .expect("Unknown selector");
+            static SEL_CACHE: $crate::objc::SelectorCache =
+                $crate::objc::SelectorCache::new();
+            let sel = SEL_CACHE.lookup(&$env.objc, sel);
 
             let sp = &mut $env.cpu.regs_mut()[$crate::cpu::Cpu::SP];
             let old_sp = *sp;
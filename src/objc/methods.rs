@@ -12,7 +12,8 @@ use super::{
     id, nil, objc_super, Class, ClassHostObject, MsgSendSignature, MsgSendSuperSignature, ObjC, SEL,
 };
 use crate::abi::{CallFromGuest, DotDotDot, GuestArg, GuestFunction, GuestRet};
-use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, Ptr, SafeRead};
+use crate::dyld::HostFunction;
+use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
 use crate::Environment;
 use std::any::TypeId;
 
@@ -24,6 +25,7 @@ use std::any::TypeId;
 /// "guest methods" (functions in the guest app). Either way, the function needs
 /// to conform to the same ABI: [id] and [SEL] must be its first two parameters.
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum IMP {
     Host(&'static dyn HostIMP),
     Guest(GuestIMP),
@@ -33,6 +35,9 @@ pub enum IMP {
 pub trait HostIMP: CallFromGuest {
     /// See [MsgSendSignature::type_info].
     fn type_info(&self) -> (TypeId, &'static str);
+    /// Get this as a [HostFunction], so that a guest function can be created
+    /// for it (see [guest_function_for_imp]).
+    fn to_host_function(&'static self) -> HostFunction;
 }
 
 macro_rules! impl_HostIMP {
@@ -45,6 +50,9 @@ macro_rules! impl_HostIMP {
             fn type_info(&self) -> (TypeId, &'static str) {
                 <(R, (id, SEL, $($P,)*)) as MsgSendSignature>::type_info()
             }
+            fn to_host_function(&'static self) -> HostFunction {
+                self
+            }
         }
         impl<R, $($P,)*> HostIMP for fn(&mut Environment, id, SEL, $($P,)* DotDotDot) -> R
         where
@@ -54,6 +62,9 @@ macro_rules! impl_HostIMP {
             fn type_info(&self) -> (TypeId, &'static str) {
                 todo!("host-to-host message calls with var-args"); // TODO
             }
+            fn to_host_function(&'static self) -> HostFunction {
+                self
+            }
         }
 
         // Currently there is a one-to-one mapping between valid host IMP
@@ -97,17 +108,35 @@ pub(super) struct method_list_t {
 }
 unsafe impl SafeRead for method_list_t {}
 
-/// The layout of a method in an app binary.
+/// The layout of a method in an app binary. This is also what a `Method`
+/// points to.
 ///
 /// The name, field names and field layout are based on what Ghidra outputs.
 #[repr(C, packed)]
-struct method_t {
+pub(super) struct method_t {
     name: ConstPtr<u8>,
     types: ConstPtr<u8>,
     imp: GuestIMP,
 }
 unsafe impl SafeRead for method_t {}
 
+/// Iterate over the entries of a method list in an app binary.
+fn read_bin_method_list(
+    method_list_ptr: ConstPtr<method_list_t>,
+    mem: &Mem,
+) -> impl Iterator<Item = method_t> + '_ {
+    let method_list_t { entsize, count } = mem.read(method_list_ptr);
+    assert!(entsize >= guest_size_of::<method_t>());
+
+    let methods_base_ptr: ConstPtr<method_t> = (method_list_ptr + 1).cast();
+
+    (0..count).map(move |i| {
+        let method_ptr: ConstPtr<method_t> =
+            Ptr::from_bits(methods_base_ptr.to_bits() + i * entsize);
+        mem.read(method_ptr)
+    })
+}
+
 impl ClassHostObject {
     // See classes.rs for host method parsing
 
@@ -117,30 +146,53 @@ impl ClassHostObject {
         mem: &Mem,
         objc: &mut ObjC,
     ) {
-        let method_list_t { entsize, count } = mem.read(method_list_ptr);
-        assert!(entsize >= guest_size_of::<method_t>());
-
-        let methods_base_ptr: ConstPtr<method_t> = (method_list_ptr + 1).cast();
-
-        for i in 0..count {
-            let method_ptr: ConstPtr<method_t> =
-                Ptr::from_bits(methods_base_ptr.to_bits() + i * entsize);
-
-            // TODO: support type strings
-            let method_t {
-                name,
-                types: _,
-                imp,
-            } = mem.read(method_ptr);
-
+        for method_t { name, types, imp } in read_bin_method_list(method_list_ptr, mem) {
             // There is no guarantee this string is unique or known.
             // We must deduplicate it like any other.
             let sel = objc.register_bin_selector(name, mem);
             self.methods.insert(sel, IMP::Guest(imp));
+            self.set_method_types(sel, types);
+        }
+    }
+
+    /// Record the type string of a method. A null pointer means the type
+    /// string is unknown.
+    pub(super) fn set_method_types(&mut self, sel: SEL, types: ConstPtr<u8>) {
+        if types.is_null() {
+            self.method_types.remove(&sel);
+        } else {
+            self.method_types.insert(sel, types);
         }
     }
 }
 
+/// Find the implementation of a method in a method list from an app binary,
+/// by name. This is needed for `+load`, which is called directly rather than
+/// through a message send, so that methods with the same name in other
+/// categories or in the class itself don't shadow it.
+pub(super) fn find_method_in_bin_list(
+    method_list_ptr: ConstPtr<method_list_t>,
+    name: &str,
+    mem: &Mem,
+) -> Option<GuestIMP> {
+    read_bin_method_list(method_list_ptr, mem)
+        .find(|method| mem.cstr_at(method.name) == name.as_bytes())
+        .map(|method| method.imp)
+}
+
+/// Get the selectors and type strings of the methods in a method list from an
+/// app binary. This is used for protocols, whose methods have no
+/// implementations.
+pub(super) fn read_bin_method_descriptions(
+    method_list_ptr: ConstPtr<method_list_t>,
+    mem: &Mem,
+    objc: &mut ObjC,
+) -> Vec<(SEL, ConstPtr<u8>)> {
+    read_bin_method_list(method_list_ptr, mem)
+        .map(|method_t { name, types, .. }| (objc.register_bin_selector(name, mem), types))
+        .collect()
+}
+
 impl ObjC {
     /// Checks if the provided class has a method in its class chain (that is
     /// to say, objects of the given class respond to a selector).
@@ -188,6 +240,50 @@ impl ObjC {
         }
     }
 
+    /// Get the type string of the method a class (or one of its superclasses)
+    /// has for a selector, if there is such a method and its type string is
+    /// known.
+    pub fn class_method_types(&self, class: Class, sel: SEL) -> Option<ConstPtr<u8>> {
+        let mut class = class;
+        while class != nil {
+            // Unimplemented and fake classes have no methods.
+            let &ClassHostObject {
+                superclass,
+                ref methods,
+                ref method_types,
+                ..
+            } = self.get_host_object(class)?.as_any().downcast_ref()?;
+            if methods.contains_key(&sel) {
+                return method_types.get(&sel).copied();
+            }
+            class = superclass;
+        }
+        None
+    }
+
+    /// Get the [TypeId] of the Rust signature (see
+    /// [MsgSendSignature::type_info]) of the method a class (or one of its
+    /// superclasses) has for a selector, if it's a host method.
+    pub fn class_host_method_type_id(&self, class: Class, sel: SEL) -> Option<TypeId> {
+        let mut class = class;
+        while class != nil {
+            // Unimplemented and fake classes have no methods.
+            let &ClassHostObject {
+                superclass,
+                ref methods,
+                ..
+            } = self.get_host_object(class)?.as_any().downcast_ref()?;
+            if let Some(imp) = methods.get(&sel) {
+                return match imp {
+                    IMP::Host(host_imp) => Some(host_imp.type_info().0),
+                    IMP::Guest(_) => None,
+                };
+            }
+            class = superclass;
+        }
+        None
+    }
+
     /// Checks if a class overrides a method provided by its superclass.
     ///
     /// This looks through a superclass chain looking for the selector, stopping
@@ -220,3 +316,321 @@ impl ObjC {
         }
     }
 }
+
+/// Get a guest function pointer for an IMP. For host methods, a guest function
+/// that calls the host method is created the first time.
+fn guest_function_for_imp(env: &mut Environment, imp: IMP) -> GuestIMP {
+    match imp {
+        IMP::Guest(guest_imp) => guest_imp,
+        IMP::Host(host_imp) => {
+            let existing = env
+                .objc
+                .host_imp_functions
+                .iter()
+                .find(|&&(other, _)| std::ptr::addr_eq(other, host_imp));
+            if let Some(&(_, guest_imp)) = existing {
+                return guest_imp;
+            }
+            let guest_imp = env.dyld.create_guest_function(
+                &mut env.mem,
+                "__touchHLE_host_method",
+                host_imp.to_host_function(),
+            );
+            env.objc.host_imp_functions.push((host_imp, guest_imp));
+            guest_imp
+        }
+    }
+}
+
+/// The inverse of [guest_function_for_imp]. This means that if a host method is
+/// moved around by swizzling, it's still called directly by host code.
+fn imp_for_guest_function(env: &Environment, guest_imp: GuestIMP) -> IMP {
+    env.objc
+        .host_imp_functions
+        .iter()
+        .find(|&&(_, other)| other.addr_with_thumb_bit() == guest_imp.addr_with_thumb_bit())
+        .map_or(IMP::Guest(guest_imp), |&(host_imp, _)| IMP::Host(host_imp))
+}
+
+/// Get the `Method` for a method of a class (not including its superclasses).
+/// The same pointer is returned each time.
+///
+/// The `types` field is null if the type string is unknown, e.g. for host
+/// methods.
+fn get_method_object(env: &mut Environment, class: Class, sel: SEL) -> ConstPtr<method_t> {
+    if let Some(&method) = env.objc.method_objects.get(&(class, sel)) {
+        return method;
+    }
+    let host_object = env.objc.borrow::<ClassHostObject>(class);
+    let imp = host_object.methods[&sel];
+    let types = host_object
+        .method_types
+        .get(&sel)
+        .copied()
+        .unwrap_or(Ptr::null());
+    let imp = guest_function_for_imp(env, imp);
+    let method = env
+        .mem
+        .alloc_and_write(method_t {
+            name: sel.as_ptr(),
+            types,
+            imp,
+        })
+        .cast_const();
+    env.objc.method_objects.insert((class, sel), method);
+    method
+}
+
+/// Find the class and selector a `Method` belongs to.
+fn method_object_owner(env: &Environment, method: ConstPtr<method_t>) -> (Class, SEL) {
+    env.objc
+        .method_objects
+        .iter()
+        .find(|&(_, &other)| other == method)
+        .map(|(&owner, _)| owner)
+        .unwrap()
+}
+
+/// Set the implementation of a class's own method, keeping its `Method` (if
+/// there is one) up to date.
+fn set_method_imp(env: &mut Environment, class: Class, sel: SEL, imp: IMP) {
+    env.objc
+        .borrow_mut::<ClassHostObject>(class)
+        .methods
+        .insert(sel, imp);
+    env.objc.flush_method_cache();
+    if let Some(&method) = env.objc.method_objects.get(&(class, sel)) {
+        let guest_imp = guest_function_for_imp(env, imp);
+        let method: MutPtr<method_t> = method.cast_mut();
+        let method_t { name, types, .. } = env.mem.read(method);
+        env.mem.write(
+            method,
+            method_t {
+                name,
+                types,
+                imp: guest_imp,
+            },
+        );
+    }
+}
+
+/// Add a method to a class, unless the class itself (not counting its
+/// superclasses) already has a method with that name.
+pub(super) fn class_addMethod(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+    imp: GuestIMP,
+    types: ConstPtr<u8>,
+) -> bool {
+    let added = !env
+        .objc
+        .borrow::<ClassHostObject>(class)
+        .methods
+        .contains_key(&sel);
+    if added {
+        let imp = imp_for_guest_function(env, imp);
+        set_method_imp(env, class, sel, imp);
+        env.objc
+            .borrow_mut::<ClassHostObject>(class)
+            .set_method_types(sel, types);
+    }
+    log_dbg!(
+        "class_addMethod({:?}, {:?}, {:?}) => {}",
+        class,
+        sel.as_str(&env.mem),
+        imp,
+        added
+    );
+    added
+}
+
+/// Returns a `malloc()`'d array of the methods of a class (not including its
+/// superclasses), or `NULL` if there are none.
+pub(super) fn class_copyMethodList(
+    env: &mut Environment,
+    class: Class,
+    out_count: MutPtr<GuestUSize>,
+) -> MutPtr<ConstPtr<method_t>> {
+    let sels: Vec<SEL> = if class == nil {
+        Vec::new()
+    } else {
+        env.objc
+            .borrow::<ClassHostObject>(class)
+            .methods
+            .keys()
+            .copied()
+            .collect()
+    };
+
+    let count: GuestUSize = sels.len().try_into().unwrap();
+    if !out_count.is_null() {
+        env.mem.write(out_count, count);
+    }
+    if count == 0 {
+        return Ptr::null();
+    }
+    let list: MutPtr<ConstPtr<method_t>> = env
+        .mem
+        .alloc(count * guest_size_of::<ConstPtr<method_t>>())
+        .cast();
+    for (i, sel) in sels.into_iter().enumerate() {
+        let method = get_method_object(env, class, sel);
+        env.mem.write(list + i.try_into().unwrap(), method);
+    }
+    list
+}
+
+pub(super) fn method_getName(env: &mut Environment, method: ConstPtr<method_t>) -> SEL {
+    let method_t { name, .. } = env.mem.read(method);
+    env.objc
+        .lookup_selector(env.mem.cstr_at_utf8(name).unwrap())
+        .unwrap()
+}
+
+pub(super) fn method_getImplementation(
+    env: &mut Environment,
+    method: ConstPtr<method_t>,
+) -> GuestIMP {
+    let method_t { imp, .. } = env.mem.read(method);
+    imp
+}
+
+/// Returns the old implementation.
+pub(super) fn method_setImplementation(
+    env: &mut Environment,
+    method: ConstPtr<method_t>,
+    imp: GuestIMP,
+) -> GuestIMP {
+    let (class, sel) = method_object_owner(env, method);
+    let old_imp = method_getImplementation(env, method);
+    log_dbg!(
+        "method_setImplementation({:?}) on \"{}\" {:?} for {:?}: {:?} => {:?}",
+        method,
+        env.objc.get_class_name(class),
+        class,
+        sel.as_str(&env.mem),
+        old_imp,
+        imp
+    );
+    let imp = imp_for_guest_function(env, imp);
+    set_method_imp(env, class, sel, imp);
+    old_imp
+}
+
+pub(super) fn method_exchangeImplementations(
+    env: &mut Environment,
+    method1: ConstPtr<method_t>,
+    method2: ConstPtr<method_t>,
+) {
+    let (class1, sel1) = method_object_owner(env, method1);
+    let (class2, sel2) = method_object_owner(env, method2);
+    log_dbg!(
+        "method_exchangeImplementations(): swapping \"{}\" {:?} {:?} and \"{}\" {:?} {:?}",
+        env.objc.get_class_name(class1),
+        class1,
+        sel1.as_str(&env.mem),
+        env.objc.get_class_name(class2),
+        class2,
+        sel2.as_str(&env.mem),
+    );
+    let imp1 = env.objc.borrow::<ClassHostObject>(class1).methods[&sel1];
+    let imp2 = env.objc.borrow::<ClassHostObject>(class2).methods[&sel2];
+    set_method_imp(env, class1, sel1, imp2);
+    set_method_imp(env, class2, sel2, imp1);
+}
+
+/// Find the class in a class's superclass chain that has a method, if any.
+fn find_method_owner(env: &Environment, class: Class, sel: SEL) -> Option<Class> {
+    let mut class = class;
+    while class != nil {
+        let host_object = env.objc.get_host_object(class).unwrap();
+        // Unimplemented and fake classes have no methods.
+        let &ClassHostObject {
+            superclass,
+            ref methods,
+            ..
+        } = host_object.as_any().downcast_ref()?;
+        if methods.contains_key(&sel) {
+            return Some(class);
+        }
+        class = superclass;
+    }
+    None
+}
+
+/// Note that if the method is inherited, the `Method` belongs to the
+/// superclass, like in Apple's runtime.
+pub(super) fn class_getInstanceMethod(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+) -> ConstPtr<method_t> {
+    match find_method_owner(env, class, sel) {
+        Some(owner) => get_method_object(env, owner, sel),
+        None => Ptr::null(),
+    }
+}
+
+pub(super) fn class_getClassMethod(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+) -> ConstPtr<method_t> {
+    if class == nil {
+        return Ptr::null();
+    }
+    let metaclass = ObjC::read_isa(class, &env.mem);
+    class_getInstanceMethod(env, metaclass, sel)
+}
+
+/// Returns null if there is no such method. (Apple's runtime returns a
+/// function that forwards the message instead.)
+pub(super) fn class_getMethodImplementation(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+) -> GuestIMP {
+    match find_method_owner(env, class, sel) {
+        Some(owner) => {
+            let imp = env.objc.borrow::<ClassHostObject>(owner).methods[&sel];
+            guest_function_for_imp(env, imp)
+        }
+        None => GuestFunction::from_addr_with_thumb_bit(0),
+    }
+}
+
+/// Replace the implementation of a class's own method, or add the method if
+/// the class itself doesn't have it. Returns the old implementation, or null
+/// if the method was added.
+pub(super) fn class_replaceMethod(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+    imp: GuestIMP,
+    types: ConstPtr<u8>,
+) -> GuestIMP {
+    let old_imp = env
+        .objc
+        .borrow::<ClassHostObject>(class)
+        .methods
+        .get(&sel)
+        .copied();
+    log_dbg!(
+        "class_replaceMethod({:?}, {:?}, {:?}) (replacing: {})",
+        class,
+        sel.as_str(&env.mem),
+        imp,
+        old_imp.is_some()
+    );
+    // Like class_addMethod(), the type string is only used for new methods.
+    if old_imp.is_none() {
+        env.objc
+            .borrow_mut::<ClassHostObject>(class)
+            .set_method_types(sel, types);
+    }
+    let old_imp = old_imp.map(|old_imp| guest_function_for_imp(env, old_imp));
+    let imp = imp_for_guest_function(env, imp);
+    set_method_imp(env, class, sel, imp);
+    old_imp.unwrap_or(GuestFunction::from_addr_with_thumb_bit(0))
+}
@@ -346,6 +346,8 @@ impl super::ObjC {
 
         std::mem::drop(host_object);
 
+        self.remove_associations_of_deallocated(object);
+
         mem.free(object.cast());
     }
 }
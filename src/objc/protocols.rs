@@ -0,0 +1,359 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Handling of Objective-C protocols.
+//!
+//! Protocols are always compared by name. The compiler emits a copy of a
+//! protocol in every binary that uses it, and host classes refer to the
+//! protocols they adopt by name (see [super::ClassTemplate]), so there is no
+//! single object that could be compared instead. The first definition of a
+//! protocol that is registered is the one returned by `objc_getProtocol()`.
+//!
+//! Resources:
+//! - [objc4 source code](https://opensource.apple.com/source/objc4/objc4-532.2/runtime/objc-runtime-new.mm.auto.html), which defines `protocol_t` and `protocol_list_t`
+//! - Apple's [Objective-C Runtime Reference](https://developer.apple.com/documentation/objectivec/objective-c_runtime?language=objc)
+
+use super::{
+    id, method_list_t, nil, objc_classes, read_bin_method_descriptions, Class, ClassExports,
+    ClassHostObject, HostObject, ObjC, CLASS_LISTS, SEL,
+};
+use crate::mach_o::MachO;
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
+use crate::Environment;
+
+/// Our internal representation of a protocol.
+pub(super) struct ProtocolHostObject {
+    name: String,
+    /// The name as a C string in guest memory, for `protocol_getName()`.
+    name_cstr: ConstPtr<u8>,
+    /// Names of the protocols this protocol incorporates.
+    protocols: Vec<String>,
+    methods: Vec<MethodDescription>,
+}
+impl HostObject for ProtocolHostObject {}
+
+struct MethodDescription {
+    sel: SEL,
+    types: ConstPtr<u8>,
+    is_required: bool,
+    is_instance_method: bool,
+}
+
+/// The layout of a protocol in an app binary.
+///
+/// There are more fields after these in newer versions of the runtime, but
+/// they aren't needed.
+#[repr(C, packed)]
+struct protocol_t {
+    _isa: Class,
+    name: ConstPtr<u8>,
+    protocols: ConstPtr<protocol_list_t>,
+    instance_methods: ConstPtr<method_list_t>,
+    class_methods: ConstPtr<method_list_t>,
+    optional_instance_methods: ConstPtr<method_list_t>,
+    optional_class_methods: ConstPtr<method_list_t>,
+    _instance_properties: ConstVoidPtr, // property list (TODO)
+}
+unsafe impl SafeRead for protocol_t {}
+
+/// The layout of a protocol list in an app binary.
+#[repr(C, packed)]
+pub(super) struct protocol_list_t {
+    count: GuestUSize,
+    // entries (pointers to protocol_t) follow the struct
+}
+unsafe impl SafeRead for protocol_list_t {}
+
+/// `struct objc_method_description`
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct objc_method_description {
+    name: SEL,
+    types: ConstPtr<u8>,
+}
+unsafe impl SafeRead for objc_method_description {}
+
+/// Get the names of the protocols in a protocol list from an app binary. The
+/// list pointer may be null.
+pub(super) fn read_bin_protocol_names(
+    list_ptr: ConstPtr<protocol_list_t>,
+    mem: &Mem,
+) -> Vec<String> {
+    if list_ptr.is_null() {
+        return Vec::new();
+    }
+    let protocol_list_t { count } = mem.read(list_ptr);
+    let base: ConstPtr<ConstPtr<protocol_t>> = (list_ptr + 1).cast();
+    (0..count)
+        .map(|i| {
+            let protocol = mem.read(mem.read(base + i));
+            mem.cstr_at_utf8(protocol.name).unwrap().to_string()
+        })
+        .collect()
+}
+
+impl ObjC {
+    /// For use by [crate::dyld]: register all the protocols from the
+    /// application binary.
+    pub fn register_bin_protocols(&mut self, bin: &MachO, mem: &mut Mem) {
+        let Some(list) = bin.get_section("__objc_protolist") else {
+            return;
+        };
+
+        let protocol_class = self.get_known_class("Protocol", mem);
+
+        assert!(list.size % 4 == 0);
+        let base: ConstPtr<ConstPtr<protocol_t>> = Ptr::from_bits(list.addr);
+        for i in 0..(list.size / 4) {
+            let protocol_ptr = mem.read(base + i);
+            let protocol: id = protocol_ptr.cast().cast_mut();
+            if self.get_host_object(protocol).is_some() {
+                continue;
+            }
+
+            let data = mem.read(protocol_ptr);
+            let name = mem.cstr_at_utf8(data.name).unwrap().to_string();
+
+            let mut methods = Vec::new();
+            for (list, is_required, is_instance_method) in [
+                (data.instance_methods, true, true),
+                (data.class_methods, true, false),
+                (data.optional_instance_methods, false, true),
+                (data.optional_class_methods, false, false),
+            ] {
+                if list.is_null() {
+                    continue;
+                }
+                methods.extend(
+                    read_bin_method_descriptions(list, mem, self)
+                        .into_iter()
+                        .map(|(sel, types)| MethodDescription {
+                            sel,
+                            types,
+                            is_required,
+                            is_instance_method,
+                        }),
+                );
+            }
+
+            log_dbg!("Registering guest app protocol \"{}\" {:?}", name, protocol);
+
+            // Protocols are objects, but the compiler doesn't fill in the isa.
+            mem.write(protocol_ptr.cast().cast_mut(), protocol_class);
+            self.register_static_object(
+                protocol,
+                Box::new(ProtocolHostObject {
+                    name: name.clone(),
+                    name_cstr: data.name,
+                    protocols: read_bin_protocol_names(data.protocols, mem),
+                    methods,
+                }),
+            );
+            self.protocols.entry(name).or_insert(protocol);
+        }
+    }
+
+    /// Get the protocol with a particular name, if there is one. Protocols
+    /// that are only adopted by host classes are created on demand.
+    fn get_protocol(&mut self, name: &str, mem: &mut Mem) -> Option<id> {
+        if let Some(&protocol) = self.protocols.get(name) {
+            return Some(protocol);
+        }
+
+        let adopted_by_host_class = CLASS_LISTS
+            .iter()
+            .flat_map(|class_list| class_list.iter())
+            .any(|(_, template)| template.protocols.contains(&name));
+        if !adopted_by_host_class {
+            return None;
+        }
+
+        let protocol_class = self.get_known_class("Protocol", mem);
+        let name_cstr = mem.alloc_and_write_cstr(name.as_bytes()).cast_const();
+        let host_object = Box::new(ProtocolHostObject {
+            name: name.to_string(),
+            name_cstr,
+            protocols: Vec::new(),
+            methods: Vec::new(),
+        });
+        let protocol = self.alloc_static_object(protocol_class, host_object, mem);
+        self.protocols.insert(name.to_string(), protocol);
+        Some(protocol)
+    }
+
+    fn protocol_name(&self, protocol: id) -> &str {
+        &self.borrow::<ProtocolHostObject>(protocol).name
+    }
+
+    /// Check if the protocol named `name` is, or incorporates, the protocol
+    /// named `other_name`.
+    fn protocol_name_conforms_to(&self, name: &str, other_name: &str) -> bool {
+        if name == other_name {
+            return true;
+        }
+        let Some(&protocol) = self.protocols.get(name) else {
+            return false;
+        };
+        self.borrow::<ProtocolHostObject>(protocol)
+            .protocols
+            .iter()
+            .any(|name| self.protocol_name_conforms_to(name, other_name))
+    }
+
+    /// Check if a class, or one of its superclasses, adopts a protocol (this
+    /// includes protocols incorporated by the ones it adopts).
+    pub fn class_conforms_to_protocol(&self, class: Class, protocol: id) -> bool {
+        if class == nil || protocol == nil {
+            return false;
+        }
+        let other_name = self.protocol_name(protocol);
+        let mut class = class;
+        loop {
+            let host_object = self.get_host_object(class).unwrap();
+            // Unimplemented and fake classes don't adopt anything.
+            let Some(ClassHostObject {
+                superclass,
+                protocols,
+                ..
+            }) = host_object.as_any().downcast_ref()
+            else {
+                return false;
+            };
+            if protocols
+                .iter()
+                .any(|name| self.protocol_name_conforms_to(name, other_name))
+            {
+                return true;
+            }
+            if *superclass == nil {
+                return false;
+            }
+            class = *superclass;
+        }
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation Protocol: NSObject
+
+- (bool)conformsTo:(id)other {
+    protocol_conformsToProtocol(env, this, other)
+}
+
+- (ConstPtr<u8>)name {
+    protocol_getName(env, this)
+}
+
+@end
+
+};
+
+pub(super) fn objc_getProtocol(env: &mut Environment, name: ConstPtr<u8>) -> id {
+    let name = env.mem.cstr_at_utf8(name).unwrap().to_string();
+    let res = env.objc.get_protocol(&name, &mut env.mem).unwrap_or(nil);
+    log_dbg!("objc_getProtocol({:?}) => {:?}", name, res);
+    res
+}
+
+pub(super) fn protocol_getName(env: &mut Environment, protocol: id) -> ConstPtr<u8> {
+    env.objc.borrow::<ProtocolHostObject>(protocol).name_cstr
+}
+
+pub(super) fn protocol_isEqual(env: &mut Environment, protocol: id, other: id) -> bool {
+    if protocol == nil || other == nil {
+        return protocol == other;
+    }
+    env.objc.protocol_name(protocol) == env.objc.protocol_name(other)
+}
+
+pub(super) fn protocol_conformsToProtocol(env: &mut Environment, protocol: id, other: id) -> bool {
+    if protocol == nil || other == nil {
+        return false;
+    }
+    let objc = &env.objc;
+    objc.protocol_name_conforms_to(objc.protocol_name(protocol), objc.protocol_name(other))
+}
+
+pub(super) fn class_conformsToProtocol(env: &mut Environment, class: Class, protocol: id) -> bool {
+    env.objc.class_conforms_to_protocol(class, protocol)
+}
+
+/// Returns a `malloc()`'d array of the protocols adopted by a class (not
+/// including its superclasses), or `NULL` if there are none.
+pub(super) fn class_copyProtocolList(
+    env: &mut Environment,
+    class: Class,
+    out_count: MutPtr<GuestUSize>,
+) -> MutPtr<id> {
+    let names = if class == nil {
+        Vec::new()
+    } else {
+        env.objc.borrow::<ClassHostObject>(class).protocols.clone()
+    };
+    let protocols: Vec<id> = names
+        .iter()
+        .filter_map(|name| env.objc.get_protocol(name, &mut env.mem))
+        .collect();
+
+    let count: GuestUSize = protocols.len().try_into().unwrap();
+    if !out_count.is_null() {
+        env.mem.write(out_count, count);
+    }
+    if count == 0 {
+        return Ptr::null();
+    }
+    let list: MutPtr<id> = env.mem.alloc(count * guest_size_of::<id>()).cast();
+    for (i, protocol) in protocols.into_iter().enumerate() {
+        env.mem.write(list + i.try_into().unwrap(), protocol);
+    }
+    list
+}
+
+/// Returns a `malloc()`'d array of `struct objc_method_description`, or
+/// `NULL` if there are no matching methods.
+pub(super) fn protocol_copyMethodDescriptionList(
+    env: &mut Environment,
+    protocol: id,
+    is_required_method: bool,
+    is_instance_method: bool,
+    out_count: MutPtr<GuestUSize>,
+) -> ConstVoidPtr {
+    let descriptions: Vec<objc_method_description> = if protocol == nil {
+        Vec::new()
+    } else {
+        env.objc
+            .borrow::<ProtocolHostObject>(protocol)
+            .methods
+            .iter()
+            .filter(|method| {
+                method.is_required == is_required_method
+                    && method.is_instance_method == is_instance_method
+            })
+            .map(|method| objc_method_description {
+                name: method.sel,
+                types: method.types,
+            })
+            .collect()
+    };
+
+    let count: GuestUSize = descriptions.len().try_into().unwrap();
+    if !out_count.is_null() {
+        env.mem.write(out_count, count);
+    }
+    if count == 0 {
+        return Ptr::null();
+    }
+    let list: MutPtr<objc_method_description> = env
+        .mem
+        .alloc(count * guest_size_of::<objc_method_description>())
+        .cast();
+    for (i, description) in descriptions.into_iter().enumerate() {
+        env.mem.write(list + i.try_into().unwrap(), description);
+    }
+    list.cast().cast_const()
+}
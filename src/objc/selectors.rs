@@ -17,8 +17,9 @@
 use super::ObjC;
 use crate::abi::{GuestArg, GuestRet};
 use crate::mach_o::MachO;
-use crate::mem::{ConstPtr, Mem, MutPtr, Ptr};
+use crate::mem::{ConstPtr, Mem, MutPtr, Ptr, SafeRead};
 use crate::Environment;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Create a string literal for a selector from Objective-C message syntax
 /// components. Useful for [super::objc_classes] and for [super::msg].
@@ -38,6 +39,7 @@ pub use crate::selector; // #[macro_export] is weird...
 #[repr(transparent)]
 #[allow(clippy::upper_case_acronyms)] // silly clippit, this isn't an acronym!
 pub struct SEL(ConstPtr<u8>);
+unsafe impl SafeRead for SEL {}
 
 impl GuestArg for SEL {
     const REG_COUNT: usize = <ConstPtr<u8> as GuestArg>::REG_COUNT;
@@ -65,6 +67,38 @@ impl SEL {
     pub fn is_null(self) -> bool {
         self.0.is_null()
     }
+    /// Get the pointer to the selector's C string.
+    pub fn as_ptr(self) -> ConstPtr<u8> {
+        self.0
+    }
+}
+
+/// Cache for the selector used at a particular place in host code, so that
+/// [super::msg] and friends don't have to look up the selector's name each
+/// time a message is sent. The cache remembers which [ObjC] instance the
+/// selector belongs to, because there can be more than one over the lifetime
+/// of the process (e.g. the app picker's and the app's).
+pub struct SelectorCache(AtomicU64);
+impl SelectorCache {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        SelectorCache(AtomicU64::new(0))
+    }
+
+    /// Get the cached selector, or look it up by name if there is none.
+    /// Panics if the selector is unknown.
+    pub fn lookup(&self, objc: &ObjC, name: &str) -> SEL {
+        let cached = self.0.load(Ordering::Relaxed);
+        if (cached >> 32) as u32 == objc.instance_id {
+            return SEL(Ptr::from_bits(cached as u32));
+        }
+        let sel = objc.lookup_selector(name).expect("Unknown selector");
+        self.0.store(
+            (u64::from(objc.instance_id) << 32) | u64::from(sel.0.to_bits()),
+            Ordering::Relaxed,
+        );
+        sel
+    }
 }
 
 impl ObjC {
@@ -5,16 +5,117 @@
  */
 //! Parsing and management of user-configurable options, e.g. for input methods.
 
+use crate::frameworks::opengles::{FrameSkip, GLErrorCheck};
 use crate::gles::GLESImplementation;
+use crate::libc::time::zone::TimeZone;
+use crate::mem::UnalignedAccess;
 use crate::window::DeviceOrientation;
+use crate::PowerSaving;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 
 pub const OPTIONS_HELP: &str =
     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/OPTIONS_HELP.txt"));
 
+/// Device model for the `--device-model=` option.
+pub struct DeviceModel {
+    /// Machine identifier, e.g. `iPhone1,2` (`hw.machine`, `uname()`).
+    pub identifier: &'static str,
+    /// Internal board name, e.g. `N82AP` (`hw.model`).
+    pub board: &'static str,
+    /// Product name, as returned by `-[UIDevice model]`.
+    pub name: &'static str,
+    /// Amount of RAM the device has, in MiB.
+    pub ram_mib: u32,
+    /// CPU clock speed, in MHz (`hw.cpufrequency`, `--limit-cpu-speed`).
+    pub cpu_mhz: u32,
+    /// Graphics processor, which decides what OpenGL ES reports and, with
+    /// `--enforce-npot-restrictions`, what textures are allowed.
+    pub gpu: Gpu,
+}
+
+/// Graphics processor of a [DeviceModel].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gpu {
+    /// PowerVR MBX Lite, which has no support for non-power-of-two textures.
+    PowerVRMBXLite,
+    /// PowerVR SGX 535, which supports non-power-of-two textures with the
+    /// limitations of `GL_APPLE_texture_2D_limited_npot`.
+    PowerVRSGX535,
+}
+impl Gpu {
+    /// `GL_MAX_TEXTURE_SIZE`
+    pub fn max_texture_size(self) -> i32 {
+        match self {
+            Gpu::PowerVRMBXLite => 1024,
+            Gpu::PowerVRSGX535 => 2048,
+        }
+    }
+    /// `GL_MAX_TEXTURE_UNITS`
+    pub fn max_texture_units(self) -> i32 {
+        match self {
+            Gpu::PowerVRMBXLite => 2,
+            Gpu::PowerVRSGX535 => 8,
+        }
+    }
+}
+
+/// Device models that can be used with `--device-model=`. The first one is the
+/// default.
+pub const DEVICE_MODELS: &[DeviceModel] = &[
+    DeviceModel {
+        identifier: "iPhone1,1",
+        board: "M68AP",
+        name: "iPhone",
+        ram_mib: 128,
+        cpu_mhz: 412,
+        gpu: Gpu::PowerVRMBXLite,
+    },
+    DeviceModel {
+        identifier: "iPhone1,2",
+        board: "N82AP",
+        name: "iPhone",
+        ram_mib: 128,
+        cpu_mhz: 412,
+        gpu: Gpu::PowerVRMBXLite,
+    },
+    DeviceModel {
+        identifier: "iPhone2,1",
+        board: "N88AP",
+        name: "iPhone",
+        ram_mib: 256,
+        cpu_mhz: 600,
+        gpu: Gpu::PowerVRSGX535,
+    },
+    DeviceModel {
+        identifier: "iPod1,1",
+        board: "N45AP",
+        name: "iPod touch",
+        ram_mib: 128,
+        cpu_mhz: 412,
+        gpu: Gpu::PowerVRMBXLite,
+    },
+    DeviceModel {
+        identifier: "iPod2,1",
+        board: "N72AP",
+        name: "iPod touch",
+        ram_mib: 128,
+        cpu_mhz: 532,
+        gpu: Gpu::PowerVRMBXLite,
+    },
+    DeviceModel {
+        identifier: "iPod3,1",
+        board: "N18AP",
+        name: "iPod touch",
+        ram_mib: 256,
+        cpu_mhz: 600,
+        gpu: Gpu::PowerVRSGX535,
+    },
+];
+
 /// Game controller button for `--button-to-touch=` option.
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
 pub enum Button {
@@ -34,7 +135,7 @@ pub enum Button {
 pub struct Options {
     pub fullscreen: bool,
     pub initial_orientation: DeviceOrientation,
-    pub scale_hack: NonZeroU32,
+    pub scale_hack: f32,
     pub deadzone: f32,
     pub x_tilt_range: f32,
     pub y_tilt_range: f32,
@@ -43,12 +144,83 @@ pub struct Options {
     pub button_to_touch: HashMap<Button, (f32, f32)>,
     pub stabilize_virtual_cursor: Option<(f32, f32)>,
     pub gles1_implementation: Option<GLESImplementation>,
+    /// Apply the simulated device's restrictions on non-power-of-two textures.
+    pub enforce_npot_restrictions: bool,
+    /// Number of samples to use for multisample anti-aliasing (MSAA) of the
+    /// app's OpenGL ES output, if forced. See
+    /// [crate::frameworks::opengles::eagl].
+    pub force_msaa: Option<NonZeroU32>,
+    /// Anisotropic filtering level to use for all textures, if forced.
+    pub force_anisotropic_filtering: Option<NonZeroU32>,
+    /// Use trilinear filtering for all mipmapped textures.
+    pub force_trilinear_filtering: bool,
     pub direct_memory_access: bool,
+    pub unaligned_access: UnalignedAccess,
+    pub gl_error_check: GLErrorCheck,
     pub gdb_listen_addrs: Option<Vec<SocketAddr>>,
+    /// Run guest code no faster than the simulated device's CPU could.
+    pub limit_cpu_speed: bool,
+    /// Catch panics in host functions and return a default value instead.
+    pub keep_going: bool,
+    /// Show crash reports in a message box, see [crate::crash_report].
+    pub crash_report_window: bool,
+    /// Where to write API usage statistics at exit, if anywhere.
+    pub api_stats_path: Option<PathBuf>,
     pub preferred_languages: Option<Vec<String>>,
+    /// Two-letter country code to use instead of the host's region, see
+    /// [crate::frameworks::foundation::ns_locale].
+    pub region: Option<String>,
+    /// Time zone name to use instead of the host's.
+    pub time_zone: Option<String>,
+    /// Name of the OpenAL output device to use instead of the default one.
+    pub audio_device: Option<String>,
+    /// Output volume, from 0.0 to 1.0. Unlike most options, this is changed at
+    /// runtime by the simulated volume buttons, see
+    /// [crate::frameworks::audio_toolbox::audio_session].
+    pub volume: f32,
+    /// Speak synthesized speech using the host's speech synthesizer, see
+    /// [crate::audio::Speech].
+    pub text_to_speech: bool,
+    /// Keep the device identifiers reported to the app the same between runs,
+    /// see [crate::frameworks::uikit::ui_device].
+    pub persistent_device_id: bool,
+    /// Extract the app from its IPA file before running it, see
+    /// [crate::fs::BundleData::extract_to_cache].
+    pub extract_ipa: bool,
+    /// Pretend to be a jailbroken device rather than a stock one, for apps'
+    /// jailbreak checks. See [crate::fs::Fs::add_jailbreak_files].
+    pub jailbroken: bool,
     pub headless: bool,
     pub print_fps: bool,
     pub fps_limit: Option<f64>,
+    /// Skip presenting some frames on slow hosts, see [FrameSkip].
+    pub frame_skip: Option<FrameSkip>,
+    /// Trade quality for lower energy use, see [crate::Environment::fps_limit]
+    /// etc.
+    pub power_saving: Option<PowerSaving>,
+    /// Make the Core Animation compositor redraw everything every frame rather
+    /// than only what changed, see
+    /// [crate::frameworks::core_animation::recomposite_if_necessary].
+    pub full_recomposite: bool,
+    /// Simulated device model, reported by `sysctl()`, `uname()` and
+    /// `UIDevice`.
+    pub device_model: &'static DeviceModel,
+    /// Simulated device RAM size in MiB, if it differs from the device model's.
+    /// See [Options::device_ram_mib].
+    pub device_ram_override_mib: Option<NonZeroU32>,
+    /// Maximum guest heap size in MiB, if limited.
+    pub heap_limit_mib: Option<NonZeroU32>,
+    pub memory_warnings: bool,
+    pub jetsam: bool,
+    /// Factor by which all guest thread stacks are enlarged.
+    pub stack_size_multiplier: NonZeroU32,
+    /// Host names (lowercase) to look up in place of others, for `netdb.h`.
+    pub host_redirects: HashMap<String, String>,
+    /// Environment variables to add to or override the defaults, in order.
+    pub environment_variables: Vec<(String, String)>,
+    /// Fixed seed for random number generators that are normally seeded
+    /// unpredictably, e.g. `arc4random()`.
+    pub random_seed: Option<u32>,
 }
 
 impl Default for Options {
@@ -56,7 +228,7 @@ impl Default for Options {
         Options {
             fullscreen: false,
             initial_orientation: DeviceOrientation::Portrait,
-            scale_hack: NonZeroU32::new(1).unwrap(),
+            scale_hack: 1.0,
             deadzone: 0.1,
             x_tilt_range: 60.0,
             y_tilt_range: 60.0,
@@ -65,17 +237,55 @@ impl Default for Options {
             button_to_touch: HashMap::new(),
             stabilize_virtual_cursor: None,
             gles1_implementation: None,
+            enforce_npot_restrictions: false,
+            force_msaa: None,
+            force_anisotropic_filtering: None,
+            force_trilinear_filtering: false,
             direct_memory_access: true,
+            unaligned_access: UnalignedAccess::Allow,
+            gl_error_check: GLErrorCheck::Off,
             gdb_listen_addrs: None,
+            limit_cpu_speed: false,
+            keep_going: false,
+            crash_report_window: false,
+            api_stats_path: None,
             preferred_languages: None,
+            region: None,
+            time_zone: None,
+            audio_device: None,
+            volume: 1.0,
+            text_to_speech: false,
+            persistent_device_id: false,
+            extract_ipa: false,
+            jailbroken: false,
             headless: false,
             print_fps: false,
             fps_limit: Some(60.0), // Original iPhone is 60Hz and uses v-sync
+            frame_skip: None,
+            power_saving: None,
+            full_recomposite: false,
+            device_model: &DEVICE_MODELS[0],
+            device_ram_override_mib: None,
+            heap_limit_mib: None,
+            memory_warnings: true,
+            jetsam: false,
+            stack_size_multiplier: NonZeroU32::new(1).unwrap(),
+            host_redirects: HashMap::new(),
+            environment_variables: Vec::new(),
+            random_seed: None,
         }
     }
 }
 
 impl Options {
+    /// Simulated device RAM size in MiB, used for memory warnings. This is the
+    /// device model's, unless `--device-ram=` overrides it, whichever order
+    /// the options were given in.
+    pub fn device_ram_mib(&self) -> NonZeroU32 {
+        self.device_ram_override_mib
+            .unwrap_or_else(|| NonZeroU32::new(self.device_model.ram_mib).unwrap())
+    }
+
     /// Parse the command-line argument syntax for an option. Returns `Ok(true)`
     /// if the option was valid and has been applied, or `Ok(false)` if the
     /// option was not recognized.
@@ -97,9 +307,13 @@ impl Options {
         } else if arg == "--landscape-right" {
             self.initial_orientation = DeviceOrientation::LandscapeRight;
         } else if let Some(value) = arg.strip_prefix("--scale-hack=") {
-            self.scale_hack = value
+            let factor: f32 = value
                 .parse()
                 .map_err(|_| "Invalid scale hack factor".to_string())?;
+            if !factor.is_finite() || factor < 1.0 {
+                return Err("Scale hack factor must be at least 1".to_string());
+            }
+            self.scale_hack = factor;
         } else if let Some(value) = arg.strip_prefix("--deadzone=") {
             self.deadzone = parse_degrees(value, "deadzone")?;
         } else if let Some(value) = arg.strip_prefix("--x-tilt-range=") {
@@ -161,8 +375,39 @@ impl Options {
                 GLESImplementation::from_short_name(value)
                     .map_err(|_| "Unrecognized --gles1= value".to_string())?,
             );
+        } else if arg == "--enforce-npot-restrictions" {
+            self.enforce_npot_restrictions = true;
+        } else if let Some(value) = arg.strip_prefix("--force-msaa=") {
+            let samples: NonZeroU32 = value
+                .parse()
+                .map_err(|_| "Invalid sample count for --force-msaa=".to_string())?;
+            // 1 sample is the same as no MSAA.
+            self.force_msaa = (samples.get() > 1).then_some(samples);
+        } else if let Some(value) = arg.strip_prefix("--force-anisotropic-filtering=") {
+            let level: NonZeroU32 = value
+                .parse()
+                .map_err(|_| "Invalid level for --force-anisotropic-filtering=".to_string())?;
+            // 1× is the same as no anisotropic filtering.
+            self.force_anisotropic_filtering = (level.get() > 1).then_some(level);
+        } else if arg == "--force-trilinear-filtering" {
+            self.force_trilinear_filtering = true;
         } else if arg == "--disable-direct-memory-access" {
             self.direct_memory_access = false;
+        } else if let Some(value) = arg.strip_prefix("--unaligned-access=") {
+            self.unaligned_access = match value {
+                "allow" => UnalignedAccess::Allow,
+                "warn" => UnalignedAccess::Warn,
+                "strict" => UnalignedAccess::Strict,
+                "fault" => UnalignedAccess::Fault,
+                _ => return Err("Unrecognized --unaligned-access= value".to_string()),
+            };
+        } else if let Some(value) = arg.strip_prefix("--gl-error-check=") {
+            self.gl_error_check = match value {
+                "off" => GLErrorCheck::Off,
+                "log" => GLErrorCheck::Log,
+                "permissive" => GLErrorCheck::Permissive,
+                _ => return Err("Unrecognized --gl-error-check= value".to_string()),
+            };
         } else if let Some(address) = arg.strip_prefix("--gdb=") {
             let addrs = address
                 .to_socket_addrs()
@@ -171,6 +416,55 @@ impl Options {
             self.gdb_listen_addrs = Some(addrs);
         } else if let Some(value) = arg.strip_prefix("--preferred-languages=") {
             self.preferred_languages = Some(value.split(',').map(ToOwned::to_owned).collect());
+        } else if let Some(value) = arg.strip_prefix("--region=") {
+            if value.len() != 2 || !value.bytes().all(|c| c.is_ascii_alphabetic()) {
+                return Err(format!(
+                    "Invalid region {:?} for --region=, expected a two-letter country code",
+                    value
+                ));
+            }
+            self.region = Some(value.to_ascii_uppercase());
+        } else if arg == "--limit-cpu-speed" {
+            self.limit_cpu_speed = true;
+        } else if arg == "--keep-going" {
+            self.keep_going = true;
+        } else if arg == "--crash-report-window" {
+            self.crash_report_window = true;
+        } else if let Some(value) = arg.strip_prefix("--api-stats=") {
+            if value.is_empty() {
+                return Err("Value for --api-stats= must not be empty".to_string());
+            }
+            self.api_stats_path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--random-seed=") {
+            let seed: u32 = value
+                .parse()
+                .map_err(|_| "Invalid value for --random-seed=".to_string())?;
+            self.random_seed = Some(seed);
+        } else if let Some(value) = arg.strip_prefix("--time-zone=") {
+            if TimeZone::from_name(value).is_none() {
+                return Err(format!("Unknown time zone {:?} for --time-zone=", value));
+            }
+            self.time_zone = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--audio-device=") {
+            if value.is_empty() {
+                return Err("Value for --audio-device= must not be empty".to_string());
+            }
+            self.audio_device = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--volume=") {
+            let percentage: u32 = value
+                .parse()
+                .ok()
+                .filter(|&percentage| percentage <= 100)
+                .ok_or_else(|| "Invalid value for --volume=".to_string())?;
+            self.volume = percentage as f32 / 100.0;
+        } else if arg == "--text-to-speech" {
+            self.text_to_speech = true;
+        } else if arg == "--persistent-device-id" {
+            self.persistent_device_id = true;
+        } else if arg == "--extract-ipa" {
+            self.extract_ipa = true;
+        } else if arg == "--jailbroken" {
+            self.jailbroken = true;
         } else if arg == "--headless" {
             self.headless = true;
         } else if arg == "--print-fps" {
@@ -186,6 +480,68 @@ impl Options {
                     .ok_or_else(|| "Invalid value for --fps-limit=".to_string())?;
                 self.fps_limit = Some(limit);
             }
+        } else if let Some(value) = arg.strip_prefix("--power-saving=") {
+            self.power_saving = match value {
+                "off" => None,
+                "auto" => Some(PowerSaving::Auto),
+                "on" => Some(PowerSaving::Always),
+                _ => return Err("Invalid value for --power-saving=".to_string()),
+            };
+        } else if let Some(value) = arg.strip_prefix("--frame-skip=") {
+            self.frame_skip = match value {
+                "off" => None,
+                "auto" => Some(FrameSkip::Auto),
+                _ => {
+                    Some(FrameSkip::Fixed(value.parse().map_err(|_| {
+                        "Invalid value for --frame-skip=".to_string()
+                    })?))
+                }
+            };
+        } else if arg == "--full-recomposite" {
+            self.full_recomposite = true;
+        } else if let Some(value) = arg.strip_prefix("--device-model=") {
+            let model = DEVICE_MODELS
+                .iter()
+                .find(|model| model.identifier.eq_ignore_ascii_case(value))
+                .ok_or_else(|| "Unknown device model for --device-model=".to_string())?;
+            self.device_model = model;
+        } else if let Some(value) = arg.strip_prefix("--device-ram=") {
+            let ram_mib = value
+                .parse()
+                .map_err(|_| "Invalid value for --device-ram=".to_string())?;
+            self.device_ram_override_mib = Some(ram_mib);
+        } else if let Some(value) = arg.strip_prefix("--heap-limit=") {
+            let limit: NonZeroU32 = value
+                .parse()
+                .ok()
+                .filter(|&limit: &NonZeroU32| limit.get() < 4096)
+                .ok_or_else(|| "Invalid value for --heap-limit=".to_string())?;
+            self.heap_limit_mib = Some(limit);
+        } else if arg == "--disable-memory-warnings" {
+            self.memory_warnings = false;
+        } else if arg == "--jetsam" {
+            self.jetsam = true;
+        } else if let Some(value) = arg.strip_prefix("--stack-size-multiplier=") {
+            let multiplier: NonZeroU32 = value
+                .parse()
+                .ok()
+                .filter(|&multiplier: &NonZeroU32| multiplier.get() <= 16)
+                .ok_or_else(|| "Invalid value for --stack-size-multiplier=".to_string())?;
+            self.stack_size_multiplier = multiplier;
+        } else if let Some(value) = arg.strip_prefix("--redirect-host=") {
+            let (from, to) = value
+                .split_once('=')
+                .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                .ok_or_else(|| "Invalid value for --redirect-host=".to_string())?;
+            self.host_redirects
+                .insert(from.to_ascii_lowercase(), to.to_string());
+        } else if let Some(value) = arg.strip_prefix("--env=") {
+            let (name, value) = value
+                .split_once('=')
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| "Invalid value for --env=".to_string())?;
+            self.environment_variables
+                .push((name.to_string(), value.to_string()));
         } else {
             return Ok(false);
         };
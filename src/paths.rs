@@ -15,8 +15,8 @@
 //!   [USER_OPTIONS_FILE]. These are ordinary files and are found in
 //!   [user_data_base_path].
 //! * Files that touchHLE will create and modify, and the user may modify if
-//!   they want to: [SANDBOX_DIR]. These are ordinary files and are found in
-//!   [user_data_base_path].
+//!   they want to: [SANDBOX_DIR], [SHORTCUT_ICONS_DIR], [IPA_CACHE_DIR].
+//!   These are ordinary files and are found in [user_data_base_path].
 //!
 //! See also [crate::fs], which provides a virtual filesystem for the guest app
 //! and defines path types.
@@ -80,6 +80,15 @@ pub const USER_OPTIONS_FILE: &str = "touchHLE_options.txt";
 /// the `Documents` directory.
 pub const SANDBOX_DIR: &str = "touchHLE_sandbox";
 
+/// Name of the directory where touchHLE will store app icons converted for
+/// use by desktop shortcuts (see [crate::shortcut]).
+pub const SHORTCUT_ICONS_DIR: &str = "touchHLE_shortcut_icons";
+
+/// Name of the directory where touchHLE will store app bundles extracted from
+/// IPA files with `--extract-ipa` (see [crate::fs::BundleData]). It is safe to
+/// delete, the bundles will be extracted again when needed.
+pub const IPA_CACHE_DIR: &str = "touchHLE_ipa_cache";
+
 /// Get a platform-specific base path needed for accessing touchHLE's
 /// user-modifiable files. This is empty on platforms other than Android.
 pub fn user_data_base_path() -> &'static Path {
@@ -0,0 +1,196 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Compatibility probing (`--probe`).
+//!
+//! This loads and links an app like [crate::Environment::new] does, but
+//! doesn't run it. Instead, it prints a JSON report of the things that are
+//! likely to stop the app from working: symbols and classes touchHLE doesn't
+//! implement, unsupported Mach-O features, required device capabilities, etc.
+//! The report is only a prediction: apps often reference functions they never
+//! call, and many problems only show up when an app runs.
+//!
+//! The intended use is scanning a large collection of apps for ones that are
+//! worth trying, e.g. with a shell loop over `touchHLE --probe`. Only the last
+//! line of output is the report, so it can be extracted with `tail -n 1`.
+
+use crate::bundle::Bundle;
+use crate::dyld::Dyld;
+use crate::environment::{classify_dylib, DylibKind};
+use crate::fs::{Fs, GuestPath};
+use crate::mach_o::MachO;
+use crate::mem::Mem;
+use crate::objc::ObjC;
+
+/// Device capabilities (`UIRequiredDeviceCapabilities`) touchHLE can't
+/// provide.
+const UNSUPPORTED_CAPABILITIES: &[&str] = &[
+    "opengles-2",
+    "telephony",
+    "sms",
+    "still-camera",
+    "auto-focus-camera",
+    "front-facing-camera",
+    "camera-flash",
+    "video-camera",
+    "gps",
+    "magnetometer",
+    "gyroscope",
+    "peer-peer",
+    "bluetooth-le",
+];
+
+/// Load and link an app, then print a report about it.
+pub fn probe(bundle: &Bundle, fs: &Fs) {
+    let mut report = Report::default();
+    report.string("name", bundle.display_name());
+    report.string("identifier", bundle.bundle_identifier());
+    report.string("version", bundle.bundle_version());
+    match bundle.minimum_os_version() {
+        Some(version) => report.string("minimum_os_version", version),
+        None => report.raw("minimum_os_version", "null".to_string()),
+    }
+
+    let mut blockers = Vec::new();
+
+    if let Some(version) = bundle.minimum_os_version() {
+        if !os_version_is_supported(version) {
+            blockers.push(format!("requires iPhone OS {}", version));
+        }
+    }
+
+    let capabilities = bundle.required_device_capabilities();
+    report.strings("required_device_capabilities", &capabilities);
+    for &capability in &capabilities {
+        if UNSUPPORTED_CAPABILITIES.contains(&capability) {
+            blockers.push(format!("requires unsupported capability {:?}", capability));
+        }
+    }
+
+    let mut mem = Mem::new();
+    let executable = match MachO::load_from_file(bundle.executable_path(), fs, &mut mem, false) {
+        Ok(executable) => executable,
+        Err(e) => {
+            blockers.push(format!("could not load executable: {}", e));
+            report.finish(blockers);
+            return;
+        }
+    };
+    report.strings("architectures", &executable.archs);
+    report.string("loaded_architecture", executable.arch);
+
+    let mut dylibs = Vec::new();
+    let mut unexpected_dylibs = Vec::new();
+    for dylib in &executable.dynamic_libraries {
+        match classify_dylib(dylib, fs) {
+            DylibKind::Bundled => {
+                match MachO::load_from_file(GuestPath::new(dylib), fs, &mut mem, false) {
+                    Ok(dylib) => dylibs.push(dylib),
+                    Err(e) => blockers.push(format!("could not load {:?}: {}", dylib, e)),
+                }
+            }
+            DylibKind::Unexpected => {
+                blockers.push(format!("depends on unexpected dylib {:?}", dylib));
+                unexpected_dylibs.push(dylib.as_str());
+            }
+            DylibKind::HostImplementation | DylibKind::SystemFramework => (),
+        }
+    }
+    report.strings("dylibs", &executable.dynamic_libraries);
+    report.strings("unexpected_dylibs", &unexpected_dylibs);
+
+    let mut bins = dylibs;
+    bins.insert(0, executable);
+
+    let unhandled_load_commands: Vec<&str> = bins
+        .iter()
+        .flat_map(|bin| bin.unhandled_load_commands.iter().copied())
+        .collect();
+    report.strings("unhandled_load_commands", &unhandled_load_commands);
+    for command in unhandled_load_commands {
+        blockers.push(format!("uses unsupported load command {}", command));
+    }
+
+    let mut objc = ObjC::new();
+    let mut dyld = Dyld::new();
+    dyld.do_initial_linking(&bins, &mut mem, &mut objc);
+
+    let unresolved_symbols = dyld.unresolved_symbols(&bins);
+    report.strings("unresolved_symbols", &unresolved_symbols);
+
+    report.strings("unimplemented_classes", &objc.unimplemented_class_names());
+    for (class, superclass) in objc.classes_with_unimplemented_superclass() {
+        blockers.push(format!(
+            "class {:?} is a subclass of unimplemented class {:?}",
+            class, superclass
+        ));
+    }
+
+    report.finish(blockers);
+}
+
+/// Whether touchHLE supports apps with a particular `MinimumOSVersion`.
+fn os_version_is_supported(version: &str) -> bool {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    let (Some(Ok(major)), minor) = (parts.next(), parts.next()) else {
+        return true; // can't tell
+    };
+    let minor = minor.and_then(|minor| minor.ok()).unwrap_or(0);
+    major < 3 || (major == 3 && minor == 0)
+}
+
+/// Builder for the JSON report.
+#[derive(Default)]
+struct Report {
+    fields: Vec<(&'static str, String)>,
+}
+impl Report {
+    fn raw(&mut self, key: &'static str, json: String) {
+        self.fields.push((key, json));
+    }
+    fn string(&mut self, key: &'static str, value: &str) {
+        self.raw(key, json_string(value));
+    }
+    fn strings<S: AsRef<str>>(&mut self, key: &'static str, values: &[S]) {
+        let values: Vec<String> = values.iter().map(|v| json_string(v.as_ref())).collect();
+        self.raw(key, format!("[{}]", values.join(",")));
+    }
+
+    fn finish(mut self, blockers: Vec<String>) {
+        self.raw("likely_to_work", blockers.is_empty().to_string());
+        self.strings("blockers", &blockers);
+
+        echo!("Probe results:");
+        if blockers.is_empty() {
+            echo!("- No blockers found.");
+        }
+        for blocker in &blockers {
+            echo!("- Blocker: {}", blocker);
+        }
+        echo!();
+
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, json)| format!("{}:{}", json_string(key), json))
+            .collect();
+        echo!("{{{}}}", fields.join(","));
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,608 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Creating host desktop shortcuts for apps (`--create-shortcut`).
+//!
+//! A shortcut runs touchHLE with the app bundle and options it was created
+//! with, so the user doesn't need to use the command line or the app picker
+//! afterwards. The kind of shortcut depends on the host OS:
+//!
+//! * Linux and other Unix-like OSes: a `.desktop` file, by default in the
+//!   user's applications directory so it shows up in menus and launchers.
+//! * Windows: a `.lnk` file, by default on the user's desktop.
+//! * macOS: a `.command` script, by default on the user's desktop.
+//!
+//! The app icon is converted to PNG (or ICO on Windows) and stored in
+//! [paths::SHORTCUT_ICONS_DIR]. macOS shortcuts don't use it.
+//!
+//! Resources:
+//! - freedesktop.org [Desktop Entry Specification](https://specifications.freedesktop.org/desktop-entry-spec/latest/)
+//! - Microsoft's [\[MS-SHLLINK\]: Shell Link (.LNK) Binary File Format](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-shllink/16cb4ca1-9339-4d0c-a68d-bf1d6cc0f943)
+
+use crate::bundle::Bundle;
+use crate::fs::Fs;
+use crate::paths;
+use std::path::{Path, PathBuf};
+
+/// Create a shortcut for an app. `option_args` are the options to run it
+/// with, and `dir` overrides the default directory for the shortcut. Returns
+/// the path of the new shortcut.
+pub fn create_shortcut(
+    bundle: &Bundle,
+    fs: &Fs,
+    bundle_path: &Path,
+    option_args: &[String],
+    dir: Option<&Path>,
+) -> Result<PathBuf, String> {
+    let kind = ShortcutKind::for_host();
+
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => kind.default_dir()?,
+    };
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Could not create directory {}: {}", dir.display(), e))?;
+
+    let launch = Launch {
+        executable: absolute_path(
+            &std::env::current_exe()
+                .map_err(|e| format!("Could not get path of touchHLE executable: {}", e))?,
+        )?,
+        // touchHLE's resources are found relative to the working directory,
+        // so the shortcut must use the current one.
+        working_dir: absolute_path(Path::new("."))?,
+        args: std::iter::once(path_to_string(&absolute_path(bundle_path)?)?)
+            .chain(option_args.iter().cloned())
+            .collect(),
+    };
+
+    let app_id = bundle.bundle_identifier();
+    let icon_path = match kind.icon_extension() {
+        Some(extension) => match write_icon(bundle, fs, app_id, extension) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                echo!("Warning: couldn't create icon for shortcut: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let name = bundle.display_name();
+    let file_name = format!("{}.{}", sanitize_file_name(name), kind.extension());
+    let shortcut_path = dir.join(file_name);
+    let contents = match kind {
+        ShortcutKind::Desktop => desktop_entry(name, app_id, &launch, icon_path.as_deref())?,
+        ShortcutKind::Lnk => shell_link(name, &launch, icon_path.as_deref())?,
+        ShortcutKind::Command => command_script(&launch)?,
+    };
+    std::fs::write(&shortcut_path, contents)
+        .map_err(|e| format!("Could not write {}: {}", shortcut_path.display(), e))?;
+
+    // Desktop entries outside the applications directory, and scripts, must
+    // be executable.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(0o755);
+        std::fs::set_permissions(&shortcut_path, permissions).map_err(|e| {
+            format!(
+                "Could not make {} executable: {}",
+                shortcut_path.display(),
+                e
+            )
+        })?;
+    }
+
+    Ok(shortcut_path)
+}
+
+#[derive(Copy, Clone)]
+enum ShortcutKind {
+    /// Desktop entry (`.desktop`)
+    Desktop,
+    /// Windows shell link (`.lnk`)
+    Lnk,
+    /// macOS Terminal script (`.command`)
+    Command,
+}
+impl ShortcutKind {
+    fn for_host() -> Self {
+        match std::env::consts::OS {
+            "windows" => ShortcutKind::Lnk,
+            "macos" => ShortcutKind::Command,
+            _ => ShortcutKind::Desktop,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ShortcutKind::Desktop => "desktop",
+            ShortcutKind::Lnk => "lnk",
+            ShortcutKind::Command => "command",
+        }
+    }
+
+    fn icon_extension(self) -> Option<&'static str> {
+        match self {
+            ShortcutKind::Desktop => Some("png"),
+            ShortcutKind::Lnk => Some("ico"),
+            ShortcutKind::Command => None,
+        }
+    }
+
+    fn default_dir(self) -> Result<PathBuf, String> {
+        let env_path = |name: &str| -> Result<PathBuf, String> {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} is not set, use --create-shortcut=<dir>", name))
+        };
+        match self {
+            ShortcutKind::Desktop => env_path("XDG_DATA_HOME")
+                .or_else(|_| Ok(env_path("HOME")?.join(".local").join("share")))
+                .map(|path| path.join("applications")),
+            ShortcutKind::Lnk => Ok(env_path("USERPROFILE")?.join("Desktop")),
+            ShortcutKind::Command => Ok(env_path("HOME")?.join("Desktop")),
+        }
+    }
+}
+
+/// How to run touchHLE for an app.
+struct Launch {
+    executable: PathBuf,
+    working_dir: PathBuf,
+    args: Vec<String>,
+}
+
+fn absolute_path(path: &Path) -> Result<PathBuf, String> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("Can't canonicalize path {}: {}", path.display(), e))?;
+    // std::fs::canonicalize() on Windows uses the extended-length path syntax,
+    // which shortcuts don't need and some programs don't understand.
+    if std::env::consts::OS == "windows" {
+        if let Some(stripped) = path.to_str().and_then(|s| s.strip_prefix("\\\\?\\")) {
+            return Ok(PathBuf::from(stripped));
+        }
+    }
+    Ok(path)
+}
+
+fn path_to_string(path: &Path) -> Result<String, String> {
+    path.to_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("Path {} is not UTF-8", path.display()))
+}
+
+/// Replace characters that aren't allowed in file names on some OSes.
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() {
+        "touchHLE app".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Convert the app's icon and store it where the shortcut can refer to it.
+fn write_icon(bundle: &Bundle, fs: &Fs, app_id: &str, extension: &str) -> Result<PathBuf, String> {
+    let icon = bundle.load_icon(fs)?;
+    let png = icon.to_png();
+    let contents = if extension == "ico" {
+        ico_with_png(icon.dimensions(), &png)
+    } else {
+        png
+    };
+
+    let dir = paths::user_data_base_path().join(paths::SHORTCUT_ICONS_DIR);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Could not create directory {}: {}", dir.display(), e))?;
+    let path = dir.join(format!("{}.{}", sanitize_file_name(app_id), extension));
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    absolute_path(&path)
+}
+
+/// Create an ICO file containing a single PNG image, which Windows Vista and
+/// later support.
+fn ico_with_png(dimensions: (u32, u32), png: &[u8]) -> Vec<u8> {
+    // 0 means 256 pixels.
+    let dimension = |pixels: u32| if pixels >= 256 { 0 } else { pixels as u8 };
+    let mut ico = Vec::with_capacity(22 + png.len());
+    // ICONDIR: reserved, type (1 = icon), image count
+    ico.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    // ICONDIRENTRY: width, height, palette size, reserved, color planes,
+    // bits per pixel, data size, data offset
+    ico.extend_from_slice(&[dimension(dimensions.0), dimension(dimensions.1), 0, 0]);
+    ico.extend_from_slice(&1u16.to_le_bytes());
+    ico.extend_from_slice(&32u16.to_le_bytes());
+    ico.extend_from_slice(&u32::try_from(png.len()).unwrap().to_le_bytes());
+    ico.extend_from_slice(&22u32.to_le_bytes());
+    ico.extend_from_slice(png);
+    ico
+}
+
+/// Escape a string value in a desktop entry.
+fn desktop_entry_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+fn desktop_entry(
+    name: &str,
+    app_id: &str,
+    launch: &Launch,
+    icon_path: Option<&Path>,
+) -> Result<Vec<u8>, String> {
+    // Arguments in the Exec key are quoted, then the whole key is escaped like
+    // any other string value.
+    let quote = |arg: &str| -> String {
+        let mut quoted = String::from('"');
+        for c in arg.chars() {
+            match c {
+                '"' | '`' | '$' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                '%' => quoted.push_str("%%"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    };
+    let exec: Vec<String> = std::iter::once(path_to_string(&launch.executable)?)
+        .chain(launch.args.iter().cloned())
+        .map(|arg| quote(&arg))
+        .collect();
+
+    let mut entry = String::new();
+    entry.push_str("[Desktop Entry]\n");
+    entry.push_str("Type=Application\n");
+    entry.push_str(&format!("Name={}\n", desktop_entry_escape(name)));
+    entry.push_str(&format!(
+        "Comment={}\n",
+        desktop_entry_escape(&format!("{} (run with touchHLE)", app_id))
+    ));
+    entry.push_str(&format!("Exec={}\n", desktop_entry_escape(&exec.join(" "))));
+    entry.push_str(&format!(
+        "Path={}\n",
+        desktop_entry_escape(&path_to_string(&launch.working_dir)?)
+    ));
+    if let Some(icon_path) = icon_path {
+        entry.push_str(&format!(
+            "Icon={}\n",
+            desktop_entry_escape(&path_to_string(icon_path)?)
+        ));
+    }
+    entry.push_str("Terminal=false\n");
+    entry.push_str("Categories=Game;\n");
+    Ok(entry.into_bytes())
+}
+
+fn command_script(launch: &Launch) -> Result<Vec<u8>, String> {
+    let quote = |arg: &str| format!("'{}'", arg.replace('\'', "'\\''"));
+    let command: Vec<String> = std::iter::once(path_to_string(&launch.executable)?)
+        .chain(launch.args.iter().cloned())
+        .map(|arg| quote(&arg))
+        .collect();
+    Ok(format!(
+        "#!/bin/sh\ncd {} && exec {}\n",
+        quote(&path_to_string(&launch.working_dir)?),
+        command.join(" ")
+    )
+    .into_bytes())
+}
+
+/// Quote an argument so that `CommandLineToArgvW()` will parse it back into
+/// the same string.
+fn windows_quote(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quotation mark must be escaped, and so
+                // must the quotation mark.
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    // Backslashes before the closing quotation mark must also be escaped.
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+fn shell_link(name: &str, launch: &Launch, icon_path: Option<&Path>) -> Result<Vec<u8>, String> {
+    const HAS_LINK_INFO: u32 = 0x2;
+    const HAS_NAME: u32 = 0x4;
+    const HAS_WORKING_DIR: u32 = 0x10;
+    const HAS_ARGUMENTS: u32 = 0x20;
+    const HAS_ICON_LOCATION: u32 = 0x40;
+    const IS_UNICODE: u32 = 0x80;
+    const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x20;
+    const SW_SHOWNORMAL: u32 = 1;
+    const DRIVE_FIXED: u32 = 3;
+    const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x1;
+
+    let target = path_to_string(&launch.executable)?;
+    let arguments: Vec<String> = launch.args.iter().map(|arg| windows_quote(arg)).collect();
+    let arguments = arguments.join(" ");
+    let working_dir = path_to_string(&launch.working_dir)?;
+    let icon_location = icon_path.map(path_to_string).transpose()?;
+
+    let mut flags = HAS_LINK_INFO | HAS_NAME | HAS_WORKING_DIR | HAS_ARGUMENTS | IS_UNICODE;
+    if icon_location.is_some() {
+        flags |= HAS_ICON_LOCATION;
+    }
+
+    let mut link = Vec::new();
+    let u16_le = |link: &mut Vec<u8>, value: u16| link.extend_from_slice(&value.to_le_bytes());
+    let u32_le = |link: &mut Vec<u8>, value: u32| link.extend_from_slice(&value.to_le_bytes());
+
+    // ShellLinkHeader
+    u32_le(&mut link, 0x4C); // HeaderSize
+    link.extend_from_slice(&[
+        0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x46,
+    ]); // LinkCLSID: 00021401-0000-0000-C000-000000000046
+    u32_le(&mut link, flags); // LinkFlags
+    u32_le(&mut link, FILE_ATTRIBUTE_ARCHIVE); // FileAttributes
+    link.extend_from_slice(&[0; 24]); // CreationTime, AccessTime, WriteTime
+    u32_le(&mut link, 0); // FileSize
+    u32_le(&mut link, 0); // IconIndex
+    u32_le(&mut link, SW_SHOWNORMAL); // ShowCommand
+    u16_le(&mut link, 0); // HotKey
+    link.extend_from_slice(&[0; 10]); // Reserved1, Reserved2, Reserved3
+    assert!(link.len() == 0x4C);
+
+    // LinkInfo. The ANSI path is required, but the Unicode one is used if
+    // present, so non-ASCII characters are simply replaced in the former.
+    let ansi_target: Vec<u8> = target
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .chain(std::iter::once(0))
+        .collect();
+    let unicode_target: Vec<u8> = target
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    const HEADER_SIZE: u32 = 0x24;
+    // VolumeIDSize, DriveType, DriveSerialNumber, VolumeLabelOffset, and an
+    // empty label.
+    const VOLUME_ID_SIZE: u32 = 0x11;
+    let local_base_path_offset = HEADER_SIZE + VOLUME_ID_SIZE;
+    let common_path_suffix_offset = local_base_path_offset + ansi_target.len() as u32;
+    let local_base_path_offset_unicode = common_path_suffix_offset + 1;
+    let common_path_suffix_offset_unicode =
+        local_base_path_offset_unicode + unicode_target.len() as u32;
+    let link_info_size = common_path_suffix_offset_unicode + 2;
+
+    u32_le(&mut link, link_info_size); // LinkInfoSize
+    u32_le(&mut link, HEADER_SIZE); // LinkInfoHeaderSize
+    u32_le(&mut link, VOLUME_ID_AND_LOCAL_BASE_PATH); // LinkInfoFlags
+    u32_le(&mut link, HEADER_SIZE); // VolumeIDOffset
+    u32_le(&mut link, local_base_path_offset); // LocalBasePathOffset
+    u32_le(&mut link, 0); // CommonNetworkRelativeLinkOffset
+    u32_le(&mut link, common_path_suffix_offset); // CommonPathSuffixOffset
+    u32_le(&mut link, local_base_path_offset_unicode); // LocalBasePathOffsetUnicode
+    u32_le(&mut link, common_path_suffix_offset_unicode); // CommonPathSuffixOffsetUnicode
+    u32_le(&mut link, VOLUME_ID_SIZE); // VolumeIDSize
+    u32_le(&mut link, DRIVE_FIXED); // DriveType
+    u32_le(&mut link, 0); // DriveSerialNumber
+    u32_le(&mut link, 0x10); // VolumeLabelOffset
+    link.push(0); // VolumeLabel
+    link.extend_from_slice(&ansi_target); // LocalBasePath
+    link.push(0); // CommonPathSuffix
+    link.extend_from_slice(&unicode_target); // LocalBasePathUnicode
+    u16_le(&mut link, 0); // CommonPathSuffixUnicode
+
+    // StringData, in the order the specification requires.
+    let mut strings = vec![name, &working_dir, &arguments];
+    if let Some(icon_location) = &icon_location {
+        strings.push(icon_location);
+    }
+    for string in strings {
+        let units: Vec<u16> = string.encode_utf16().collect();
+        let count = u16::try_from(units.len())
+            .map_err(|_| format!("String too long for shortcut: {:?}", string))?;
+        u16_le(&mut link, count);
+        for unit in units {
+            u16_le(&mut link, unit);
+        }
+    }
+
+    // TerminalBlock
+    u32_le(&mut link, 0);
+
+    Ok(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("Super Monkey Ball"), "Super Monkey Ball");
+        assert_eq!(sanitize_file_name("AC/DC: Live?"), "AC_DC_ Live_");
+        assert_eq!(sanitize_file_name("a\\b*c\"d<e>f|g"), "a_b_c_d_e_f_g");
+        assert_eq!(sanitize_file_name("tab\there"), "tab_here");
+        assert_eq!(sanitize_file_name("  .hidden.  "), "hidden");
+        assert_eq!(sanitize_file_name(""), "touchHLE app");
+        assert_eq!(sanitize_file_name(" ... "), "touchHLE app");
+    }
+
+    /// The reverse of [desktop_entry_escape].
+    fn desktop_entry_unescape(value: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            unescaped.push(match chars.next() {
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                other => panic!("Bad escape sequence: {:?}", other),
+            });
+        }
+        unescaped
+    }
+
+    #[test]
+    fn test_desktop_entry_escape() {
+        assert_eq!(desktop_entry_escape("plain"), "plain");
+        assert_eq!(desktop_entry_escape("a\\b\nc"), "a\\\\b\\nc");
+        for value in ["", "plain", "a\\b", "line\nbreak", "\\n", "\t\r\\\\", "ü\\"] {
+            let escaped = desktop_entry_escape(value);
+            assert!(!escaped.contains(['\n', '\t', '\r']));
+            assert_eq!(desktop_entry_unescape(&escaped), value);
+        }
+    }
+
+    #[test]
+    fn test_desktop_entry_exec() {
+        let launch = Launch {
+            executable: PathBuf::from("/opt/touch HLE/touchHLE"),
+            working_dir: PathBuf::from("/opt/touch HLE"),
+            args: vec![
+                "/apps/$5 \"deal\".ipa".to_string(),
+                "--fps-limit=50%".to_string(),
+            ],
+        };
+        let entry = desktop_entry("Game", "com.example.game", &launch, None).unwrap();
+        let entry = String::from_utf8(entry).unwrap();
+        let exec = entry
+            .lines()
+            .find_map(|line| line.strip_prefix("Exec="))
+            .unwrap();
+        assert_eq!(
+            exec,
+            r#""/opt/touch HLE/touchHLE" "/apps/\\$5 \\"deal\\".ipa" "--fps-limit=50%%""#
+        );
+        assert!(!entry.contains("Icon="));
+    }
+
+    #[test]
+    fn test_command_script() {
+        let launch = Launch {
+            executable: PathBuf::from("/Applications/touchHLE"),
+            working_dir: PathBuf::from("/Users/me/it's here"),
+            args: vec!["a b".to_string(), "''".to_string()],
+        };
+        let script = String::from_utf8(command_script(&launch).unwrap()).unwrap();
+        assert_eq!(
+            script,
+            "#!/bin/sh\ncd '/Users/me/it'\\''s here' && exec '/Applications/touchHLE' 'a b' \
+             ''\\'''\\'''\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let launch = Launch {
+            executable: PathBuf::from(std::ffi::OsStr::from_bytes(b"/opt/\xFF/touchHLE")),
+            working_dir: PathBuf::from("/opt"),
+            args: Vec::new(),
+        };
+        assert!(desktop_entry("Game", "com.example.game", &launch, None).is_err());
+        assert!(command_script(&launch).is_err());
+        assert!(shell_link("Game", &launch, None).is_err());
+    }
+
+    /// Parse a single argument the way `CommandLineToArgvW()` does, as the
+    /// reverse of [windows_quote].
+    fn windows_unquote(quoted: &str) -> String {
+        let mut arg = String::new();
+        let mut in_quotes = false;
+        let mut backslashes = 0;
+        for c in quoted.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    arg.push_str(&"\\".repeat(backslashes / 2));
+                    if backslashes % 2 == 1 {
+                        arg.push('"');
+                    } else {
+                        in_quotes = !in_quotes;
+                    }
+                    backslashes = 0;
+                }
+                c => {
+                    assert!(in_quotes || c != ' ', "Unquoted space in {:?}", quoted);
+                    arg.push_str(&"\\".repeat(backslashes));
+                    arg.push(c);
+                    backslashes = 0;
+                }
+            }
+        }
+        arg.push_str(&"\\".repeat(backslashes));
+        assert!(!in_quotes, "Unterminated quotes in {:?}", quoted);
+        arg
+    }
+
+    #[test]
+    fn test_windows_quote() {
+        assert_eq!(windows_quote("plain"), "\"plain\"");
+        assert_eq!(windows_quote("C:\\Games\\"), "\"C:\\Games\\\\\"");
+        assert_eq!(windows_quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        for arg in [
+            "",
+            "plain",
+            "with space",
+            "C:\\Program Files\\touchHLE\\",
+            "\"",
+            "\\\"",
+            "a\\\\\"b",
+            "trailing\\\\",
+            "ünïcödé \"quoted\"",
+        ] {
+            assert_eq!(windows_unquote(&windows_quote(arg)), arg);
+        }
+    }
+
+    #[test]
+    fn test_ico_with_png() {
+        let png = [0x89, b'P', b'N', b'G'];
+        let ico = ico_with_png((57, 57), &png);
+        assert_eq!(ico.len(), 22 + png.len());
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 1, 0]);
+        assert_eq!(&ico[6..8], &[57, 57]);
+        assert_eq!(&ico[14..18], &4u32.to_le_bytes());
+        assert_eq!(&ico[18..22], &22u32.to_le_bytes());
+        assert_eq!(&ico[22..], &png);
+        // Dimensions of 256 and up don't fit in a byte, so they're stored as 0.
+        let ico = ico_with_png((256, 512), &png);
+        assert_eq!(&ico[6..8], &[0, 0]);
+    }
+}
@@ -23,7 +23,6 @@ use sdl2::surface::Surface;
 use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::f32::consts::FRAC_PI_2;
-use std::num::NonZeroU32;
 use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -32,12 +31,12 @@ pub enum DeviceOrientation {
     LandscapeLeft,
     LandscapeRight,
 }
-fn size_for_orientation(orientation: DeviceOrientation, scale_hack: NonZeroU32) -> (u32, u32) {
-    let scale_hack = scale_hack.get();
+fn size_for_orientation(orientation: DeviceOrientation, scale_hack: f32) -> (u32, u32) {
+    let scale = |size: u32| (size as f32 * scale_hack).round() as u32;
     match orientation {
-        DeviceOrientation::Portrait => (320 * scale_hack, 480 * scale_hack),
-        DeviceOrientation::LandscapeLeft => (480 * scale_hack, 320 * scale_hack),
-        DeviceOrientation::LandscapeRight => (480 * scale_hack, 320 * scale_hack),
+        DeviceOrientation::Portrait => (scale(320), scale(480)),
+        DeviceOrientation::LandscapeLeft => (scale(480), scale(320)),
+        DeviceOrientation::LandscapeRight => (scale(480), scale(320)),
     }
 }
 fn rotate_fullscreen_size(orientation: DeviceOrientation, screen_size: (u32, u32)) -> (u32, u32) {
@@ -93,6 +92,12 @@ pub enum Event {
     /// User pressed F12, requesting that execution be paused and the debugger
     /// take over.
     EnterDebugger,
+    /// User pressed F11, requesting that a memory warning be sent to the app.
+    MemoryWarning,
+    /// User pressed F9, simulating the volume down button.
+    VolumeDown,
+    /// User pressed F10, simulating the volume up button.
+    VolumeUp,
 }
 
 pub enum GLVersion {
@@ -143,7 +148,7 @@ pub struct Window {
     /// Copy of `fullscreen` on [Options]. Note that this is meaningless when
     /// [Self::rotatable_fullscreen] returns [true].
     fullscreen: bool,
-    scale_hack: NonZeroU32,
+    scale_hack: f32,
     internal_gl_ctx: Option<Box<dyn GLES>>,
     splash_image: Option<Image>,
     device_orientation: DeviceOrientation,
@@ -332,8 +337,7 @@ impl Window {
             independent_of_viewport: bool,
         ) -> (f32, f32) {
             let (vx, vy, vw, vh) = if independent_of_viewport {
-                let (width, height) =
-                    size_for_orientation(window.device_orientation, NonZeroU32::new(1).unwrap());
+                let (width, height) = size_for_orientation(window.device_orientation, 1.0);
                 (0, 0, width, height)
             } else {
                 window.viewport()
@@ -575,6 +579,21 @@ impl Window {
                     echo!("F12 pressed, EnterDebugger event queued.");
                     Event::EnterDebugger
                 }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F11),
+                    ..
+                } => {
+                    echo!("F11 pressed, MemoryWarning event queued.");
+                    Event::MemoryWarning
+                }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F9),
+                    ..
+                } => Event::VolumeDown,
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F10),
+                    ..
+                } => Event::VolumeUp,
                 _ => continue,
             })
         }
@@ -967,6 +986,19 @@ impl Window {
         self.window.gl_swap_window();
     }
 
+    /// Show an error message in a dialog box on top of the window. This
+    /// doesn't return until the user dismisses it.
+    pub fn show_error_message(&self, title: &str, message: &str) {
+        if let Err(e) = sdl2::messagebox::show_simple_message_box(
+            sdl2::messagebox::MessageBoxFlag::ERROR,
+            title,
+            message,
+            &self.window,
+        ) {
+            log!("Couldn't show message box: {}", e);
+        }
+    }
+
     /// Consider the emulated device to be rotated to a particular orientation.
     ///
     /// On a PC or laptop, this will make the window be rotated so the app
@@ -1040,16 +1072,7 @@ impl Window {
     /// The aspect ratio, scale and orientation reflect the guest app's view of
     /// the world.
     pub fn size_unrotated_unscaled(&self) -> (u32, u32) {
-        size_for_orientation(DeviceOrientation::Portrait, NonZeroU32::new(1).unwrap())
-    }
-
-    /// Get the size in pixels of the window without rotation but with the
-    /// scale hack. Scaling caused by fullscreen mode is not included.
-    ///
-    /// Only the aspect ratio and orientation reflect the guest app's view of
-    /// the world.
-    pub fn size_unrotated_scalehacked(&self) -> (u32, u32) {
-        size_for_orientation(DeviceOrientation::Portrait, self.scale_hack)
+        size_for_orientation(DeviceOrientation::Portrait, 1.0)
     }
 
     /// Get the region of the on-screen window (x, y, width, height) used to
@@ -0,0 +1,98 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Tests related to the CGRect functions from CGGeometry.h.
+// These reuse the type declarations in CGAffineTransform.c, so that file must
+// be included first. To run them on macOS, use a command like:
+//
+// cc -include tests/TestApp_source/CGAffineTransform.c
+// tests/TestApp_source/CGGeometry.c -framework CoreGraphics
+// -DDEFINE_ME_WHEN_BUILDING_ON_MACOS -Dtest_CGGeometry=main && ./a.out;
+// echo $?
+
+// === Declarations ===
+
+typedef unsigned int CGRectEdge;
+#define CGRectMinXEdge 0
+#define CGRectMinYEdge 1
+#define CGRectMaxXEdge 2
+#define CGRectMaxYEdge 3
+
+bool CGRectIsNull(CGRect);
+bool CGRectIsEmpty(CGRect);
+CGRect CGRectStandardize(CGRect);
+CGFloat CGRectGetMinX(CGRect);
+CGFloat CGRectGetMaxY(CGRect);
+CGFloat CGRectGetWidth(CGRect);
+bool CGRectContainsPoint(CGRect, CGPoint);
+bool CGRectContainsRect(CGRect, CGRect);
+bool CGRectIntersectsRect(CGRect, CGRect);
+CGRect CGRectIntersection(CGRect, CGRect);
+CGRect CGRectUnion(CGRect, CGRect);
+CGRect CGRectInset(CGRect, CGFloat, CGFloat);
+CGRect CGRectOffset(CGRect, CGFloat, CGFloat);
+CGRect CGRectIntegral(CGRect);
+void CGRectDivide(CGRect, CGRect *, CGRect *, CGFloat, CGRectEdge);
+
+// === Main code ===
+
+int test_CGGeometry(void) {
+  CGRect a = {0.0, 0.0, 10.0, 10.0};
+  CGRect b = {5.0, 5.0, 10.0, 10.0};
+  CGRect flipped = {10.0, 10.0, -10.0, -10.0};
+
+  if (!CGRectEqualToRect(a, CGRectStandardize(flipped)))
+    return -1;
+  if (CGRectGetMinX(flipped) != 0.0 || CGRectGetMaxY(flipped) != 10.0 ||
+      CGRectGetWidth(flipped) != 10.0)
+    return -2;
+
+  if (!CGRectContainsPoint(a, (CGPoint){0.0, 0.0}) ||
+      CGRectContainsPoint(a, (CGPoint){10.0, 5.0}))
+    return -3;
+  if (!CGRectContainsRect(a, (CGRect){2.0, 2.0, 8.0, 8.0}) ||
+      CGRectContainsRect(a, b))
+    return -4;
+
+  if (!CGRectEqualToRect((CGRect){5.0, 5.0, 5.0, 5.0},
+                         CGRectIntersection(a, b)))
+    return -5;
+  if (!CGRectEqualToRect((CGRect){0.0, 0.0, 15.0, 15.0}, CGRectUnion(a, b)))
+    return -6;
+
+  // Rectangles sharing an edge don't intersect, and disjoint ones produce a
+  // null rectangle.
+  CGRect c = {10.0, 0.0, 5.0, 5.0};
+  if (CGRectIntersectsRect(a, c) || !CGRectIntersectsRect(a, b))
+    return -7;
+  CGRect d = {20.0, 20.0, 5.0, 5.0};
+  if (!CGRectIsNull(CGRectIntersection(a, d)))
+    return -8;
+  if (!CGRectIsEmpty(CGRectIntersection(a, d)))
+    return -9;
+  if (!CGRectEqualToRect(a, CGRectUnion(a, CGRectIntersection(a, d))))
+    return -10;
+
+  if (!CGRectEqualToRect((CGRect){1.0, 2.0, 8.0, 6.0},
+                         CGRectInset(a, 1.0, 2.0)))
+    return -11;
+  if (!CGRectIsNull(CGRectInset(a, 6.0, 0.0)))
+    return -12;
+  if (!CGRectEqualToRect((CGRect){-1.0, 3.0, 10.0, 10.0},
+                         CGRectOffset(a, -1.0, 3.0)))
+    return -13;
+  if (!CGRectEqualToRect((CGRect){0.0, -1.0, 2.0, 3.0},
+                         CGRectIntegral((CGRect){0.5, -0.5, 1.0, 2.0})))
+    return -14;
+
+  CGRect slice, remainder;
+  CGRectDivide(a, &slice, &remainder, 3.0, CGRectMaxYEdge);
+  if (!CGRectEqualToRect((CGRect){0.0, 7.0, 10.0, 3.0}, slice) ||
+      !CGRectEqualToRect((CGRect){0.0, 0.0, 10.0, 7.0}, remainder))
+    return -15;
+
+  return 0;
+}
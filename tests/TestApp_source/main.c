@@ -13,6 +13,7 @@
 // For convenience, let's just include the other source files.
 
 #include "CGAffineTransform.c"
+#include "CGGeometry.c"
 
 // === Declarations ===
 
@@ -27,6 +28,9 @@ typedef int wchar_t;
 // <errno.h>
 int *__error(void);
 #define errno (*__error())
+#define ENOENT 2
+#define EBADF 9
+#define EINVAL 22
 
 // <stdarg.h>
 typedef __builtin_va_list va_list;
@@ -54,17 +58,29 @@ void *realloc(void *, size_t);
 double atof(const char *);
 float strtof(const char *, char **);
 unsigned long strtoul(const char *, char **, int);
+char *getenv(const char *);
+int setenv(const char *, const char *, int);
+int unsetenv(const char *);
+int rand_r(unsigned int *);
+void srand48(long);
+double drand48(void);
+long lrand48(void);
+long nrand48(unsigned short[3]);
+unsigned int arc4random_uniform(unsigned int);
 
 // <string.h>
 void *memset(void *, int, size_t);
 int memcmp(const void *, const void *, size_t);
 void *memmove(void *, const void *, size_t);
 int strcmp(const char *, const char *);
+int strncmp(const char *, const char *, size_t);
 char *strncpy(char *, const char *, size_t);
 char *strncat(char *, const char *, size_t);
 size_t strlcpy(char *, const char *, size_t);
 char *strchr(const char *s, int c);
 char *strrchr(const char *s, int c);
+char *strerror(int);
+size_t strlen(const char *);
 
 // <unistd.h>
 typedef unsigned int __uint32_t;
@@ -72,9 +88,50 @@ typedef __uint32_t useconds_t;
 int chdir(const char *);
 char *getcwd(char *, size_t);
 int usleep(useconds_t);
+int close(int);
+long read(int, void *, size_t);
 
 // <fcntl.h>
+#define O_RDONLY 0x00000000
+#define O_NONBLOCK 0x00000004
 #define O_CREAT 0x00000200
+#define F_GETFD 1
+#define F_SETFD 2
+#define F_GETFL 3
+#define F_SETFL 4
+#define FD_CLOEXEC 1
+int open(const char *, int, ...);
+int fcntl(int, int, ...);
+
+// <sys/ioctl.h>
+#define FIONBIO 0x8004667e
+#define FIONREAD 0x4004667f
+int ioctl(int, unsigned long, ...);
+
+// <sys/mman.h>
+#define PROT_READ 0x01
+#define PROT_WRITE 0x02
+#define MAP_PRIVATE 0x0002
+#define MAP_ANON 0x1000
+#define MAP_FAILED ((void *)-1)
+typedef long long off_t;
+void *mmap(void *, size_t, int, int, int, off_t);
+int munmap(void *, size_t);
+
+// <sys/stat.h>
+#define S_IFMT 0170000
+#define S_IFDIR 0040000
+#define S_IFREG 0100000
+struct stat {
+  int st_dev;
+  unsigned short st_mode;
+  unsigned short st_nlink;
+  unsigned long long st_ino;
+  char _unused[44]; // TODO
+  long long st_size;
+  char _unused2[40]; // TODO
+};
+int stat(const char *, struct stat *);
 
 // <pthread.h>
 typedef struct opaque_pthread_t opaque_pthread_t;
@@ -85,6 +142,36 @@ typedef struct opaque_pthread_attr_t *__pthread_attr_t;
 typedef __pthread_attr_t pthread_attr_t;
 int pthread_create(pthread_t *, const pthread_attr_t *, void *(*)(void *),
                    void *);
+int pthread_join(pthread_t, void **);
+typedef struct {
+  long __sig;
+  char __opaque[40];
+} pthread_mutex_t;
+#define PTHREAD_MUTEX_INITIALIZER {0x32AAABA7, {0}}
+int pthread_mutex_lock(pthread_mutex_t *);
+int pthread_mutex_unlock(pthread_mutex_t *);
+typedef struct {
+  long __sig;
+  char __opaque[24];
+} pthread_cond_t;
+#define PTHREAD_COND_INITIALIZER {0x3CB0B1BB, {0}}
+int pthread_cond_wait(pthread_cond_t *, pthread_mutex_t *);
+int pthread_cond_signal(pthread_cond_t *);
+typedef struct {
+  long __sig;
+  char __opaque[124];
+} pthread_rwlock_t;
+#define PTHREAD_RWLOCK_INITIALIZER {0x2DA8B3B4, {0}}
+int pthread_rwlock_rdlock(pthread_rwlock_t *);
+int pthread_rwlock_tryrdlock(pthread_rwlock_t *);
+int pthread_rwlock_trywrlock(pthread_rwlock_t *);
+int pthread_rwlock_unlock(pthread_rwlock_t *);
+int pthread_rwlock_destroy(pthread_rwlock_t *);
+typedef unsigned long pthread_key_t;
+int pthread_key_create(pthread_key_t *, void (*)(void *));
+int pthread_key_delete(pthread_key_t);
+void *pthread_getspecific(pthread_key_t);
+int pthread_setspecific(pthread_key_t, const void *);
 
 // <semaphore.h>
 #define SEM_FAILED ((sem_t *)-1)
@@ -105,6 +192,12 @@ int sem_wait(sem_t *);
 #define LC_TIME 5
 #define LC_MESSAGES 6
 char *setlocale(int category, const char *locale);
+struct lconv {
+  char *decimal_point;
+  char *thousands_sep;
+  // Other fields omitted
+};
+struct lconv *localeconv(void);
 
 // <dirent.h>
 typedef struct {
@@ -116,8 +209,372 @@ struct dirent {
 };
 DIR *opendir(const char *);
 struct dirent *readdir(DIR *);
+int readdir_r(DIR *, struct dirent *, struct dirent **);
+void rewinddir(DIR *);
 int closedir(DIR *);
 
+// <notify.h>
+#define NOTIFY_STATUS_OK 0
+int notify_register_check(const char *, int *);
+int notify_check(int, int *);
+int notify_post(const char *);
+int notify_cancel(int);
+
+// <sys/sysctl.h>
+#define CTL_KERN 1
+#define KERN_OSRELEASE 2
+#define CTL_HW 6
+#define HW_PHYSMEM 5
+int sysctl(int *, unsigned int, void *, size_t *, void *, size_t);
+int sysctlbyname(const char *, void *, size_t *, void *, size_t);
+
+// <sys/utsname.h>
+struct utsname {
+  char sysname[256];
+  char nodename[256];
+  char release[256];
+  char version[256];
+  char machine[256];
+};
+int uname(struct utsname *);
+
+// <netdb.h>
+#define AF_INET 2
+#define SOCK_STREAM 1
+#define AI_NUMERICHOST 4
+struct hostent {
+  char *h_name;
+  char **h_aliases;
+  int h_addrtype;
+  int h_length;
+  char **h_addr_list;
+};
+struct addrinfo {
+  int ai_flags;
+  int ai_family;
+  int ai_socktype;
+  int ai_protocol;
+  unsigned int ai_addrlen;
+  char *ai_canonname;
+  void *ai_addr;
+  struct addrinfo *ai_next;
+};
+struct hostent *gethostbyname(const char *);
+int getaddrinfo(const char *, const char *, const struct addrinfo *,
+                struct addrinfo **);
+void freeaddrinfo(struct addrinfo *);
+
+// <poll.h>, <sys/select.h>, <sys/event.h>
+#define POLLIN 0x1
+#define POLLOUT 0x4
+struct pollfd {
+  int fd;
+  short events;
+  short revents;
+};
+int poll(struct pollfd *, unsigned int, int);
+typedef struct {
+  __uint32_t fds_bits[32];
+} fd_set;
+struct timeval {
+  long tv_sec;
+  int tv_usec;
+};
+int select(int, fd_set *, fd_set *, fd_set *, struct timeval *);
+#define EVFILT_TIMER (-7)
+#define EV_ADD 0x1
+#define EV_ONESHOT 0x10
+struct kevent {
+  unsigned long ident;
+  short filter;
+  unsigned short flags;
+  unsigned int fflags;
+  long data;
+  void *udata;
+};
+struct timespec {
+  long tv_sec;
+  long tv_nsec;
+};
+int kqueue(void);
+int kevent(int, const struct kevent *, int, struct kevent *, int,
+           const struct timespec *);
+
+// <mach/mach.h>
+typedef int kern_return_t;
+typedef unsigned int mach_port_t;
+typedef unsigned int natural_t;
+typedef int integer_t;
+typedef struct {
+  unsigned int tv_sec;
+  int tv_nsec;
+} mach_timespec_t;
+#define KERN_SUCCESS 0
+#define KERN_OPERATION_TIMED_OUT 49
+#define TASK_BASIC_INFO 5
+#define HOST_BASIC_INFO 1
+#define HOST_VM_INFO 2
+typedef struct {
+  unsigned int numer;
+  unsigned int denom;
+} mach_timebase_info_data_t;
+extern mach_port_t mach_task_self_;
+mach_port_t mach_host_self(void);
+kern_return_t semaphore_create(mach_port_t, mach_port_t *, int, int);
+kern_return_t semaphore_destroy(mach_port_t, mach_port_t);
+kern_return_t semaphore_signal(mach_port_t);
+kern_return_t semaphore_wait(mach_port_t);
+kern_return_t semaphore_timedwait(mach_port_t, mach_timespec_t);
+kern_return_t task_info(mach_port_t, natural_t, integer_t *, natural_t *);
+kern_return_t host_statistics(mach_port_t, int, integer_t *, natural_t *);
+kern_return_t host_info(mach_port_t, int, integer_t *, natural_t *);
+unsigned long long mach_absolute_time(void);
+kern_return_t mach_timebase_info(mach_timebase_info_data_t *);
+kern_return_t mach_wait_until(unsigned long long);
+
+// <time.h>
+typedef long time_t;
+struct tm {
+  int tm_sec;
+  int tm_min;
+  int tm_hour;
+  int tm_mday;
+  int tm_mon;
+  int tm_year;
+  int tm_wday;
+  int tm_yday;
+  int tm_isdst;
+  long tm_gmtoff;
+  char *tm_zone;
+};
+struct tm *gmtime_r(const time_t *, struct tm *);
+time_t timegm(struct tm *);
+size_t strftime(char *, size_t, const char *, const struct tm *);
+char *strptime(const char *, const char *, struct tm *);
+struct tm *localtime_r(const time_t *, struct tm *);
+time_t mktime(struct tm *);
+double difftime(time_t, time_t);
+extern char *tzname[2];
+
+// <signal.h>
+typedef unsigned int sigset_t;
+struct sigaction {
+  void (*sa_handler)(int);
+  sigset_t sa_mask;
+  int sa_flags;
+};
+#define SIGUSR1 30
+#define SIGUSR2 31
+#define SIG_IGN ((void (*)(int))1)
+#define SIG_BLOCK 1
+#define SIG_UNBLOCK 2
+void (*signal(int, void (*)(int)))(int);
+int sigaction(int, const struct sigaction *, struct sigaction *);
+int raise(int);
+int sigprocmask(int, const sigset_t *, sigset_t *);
+int sigemptyset(sigset_t *);
+int sigaddset(sigset_t *, int);
+int sigismember(const sigset_t *, int);
+
+// <syslog.h>, <err.h>
+#define LOG_ERR 3
+#define LOG_DEBUG 7
+#define LOG_UPTO(pri) ((1 << ((pri) + 1)) - 1)
+void openlog(const char *, int, int);
+void syslog(int, const char *, ...);
+int setlogmask(int);
+void closelog(void);
+void warnx(const char *, ...);
+
+// <wchar.h>
+typedef union {
+  char __mbstate8[128];
+  long long _mbstateL;
+} mbstate_t;
+size_t wcslen(const wchar_t *);
+int wcscmp(const wchar_t *, const wchar_t *);
+size_t mbrtowc(wchar_t *, const char *, size_t, mbstate_t *);
+size_t wcrtomb(char *, wchar_t, mbstate_t *);
+size_t mbstowcs(wchar_t *, const char *, size_t);
+size_t wcstombs(char *, const wchar_t *, size_t);
+
+// <zlib.h>
+typedef struct {
+  const unsigned char *next_in;
+  unsigned int avail_in;
+  unsigned long total_in;
+  unsigned char *next_out;
+  unsigned int avail_out;
+  unsigned long total_out;
+  const char *msg;
+  void *state;
+  void *zalloc;
+  void *zfree;
+  void *opaque;
+  int data_type;
+  unsigned long adler;
+  unsigned long reserved;
+} z_stream;
+#define Z_OK 0
+#define Z_STREAM_END 1
+#define Z_BUF_ERROR (-5)
+#define Z_NO_FLUSH 0
+const char *zlibVersion(void);
+unsigned long compressBound(unsigned long);
+int compress2(unsigned char *, unsigned long *, const unsigned char *,
+              unsigned long, int);
+int uncompress(unsigned char *, unsigned long *, const unsigned char *,
+               unsigned long);
+int inflateInit_(z_stream *, const char *, int);
+int inflate(z_stream *, int);
+int inflateEnd(z_stream *);
+unsigned long crc32(unsigned long, const unsigned char *, unsigned int);
+unsigned long adler32(unsigned long, const unsigned char *, unsigned int);
+
+// <sqlite3.h>
+typedef struct sqlite3 sqlite3;
+typedef struct sqlite3_stmt sqlite3_stmt;
+#define SQLITE_OK 0
+#define SQLITE_ROW 100
+#define SQLITE_DONE 101
+#define SQLITE_TRANSIENT ((void (*)(void *))-1)
+int sqlite3_open(const char *, sqlite3 **);
+int sqlite3_close(sqlite3 *);
+int sqlite3_exec(sqlite3 *, const char *,
+                 int (*)(void *, int, char **, char **), void *, char **);
+int sqlite3_prepare_v2(sqlite3 *, const char *, int, sqlite3_stmt **,
+                       const char **);
+int sqlite3_bind_int(sqlite3_stmt *, int, int);
+int sqlite3_bind_text(sqlite3_stmt *, int, const char *, int,
+                      void (*)(void *));
+int sqlite3_step(sqlite3_stmt *);
+int sqlite3_column_int(sqlite3_stmt *, int);
+const unsigned char *sqlite3_column_text(sqlite3_stmt *, int);
+const char *sqlite3_column_name(sqlite3_stmt *, int);
+int sqlite3_finalize(sqlite3_stmt *);
+
+// <libxml/tree.h>, <libxml/parser.h>, <libxml/xpath.h>
+typedef unsigned char xmlChar;
+typedef struct _xmlNode {
+  void *_private;
+  int type;
+  const xmlChar *name;
+  struct _xmlNode *children;
+  struct _xmlNode *last;
+  struct _xmlNode *parent;
+  struct _xmlNode *next;
+  struct _xmlNode *prev;
+  void *doc;
+  void *ns;
+  xmlChar *content;
+} xmlNode;
+typedef struct xmlDoc xmlDoc;
+typedef struct {
+  int nodeNr;
+  int nodeMax;
+  xmlNode **nodeTab;
+} xmlNodeSet;
+typedef struct {
+  int type;
+  xmlNodeSet *nodesetval;
+  int boolval;
+  double floatval;
+} xmlXPathObject;
+typedef struct xmlXPathContext xmlXPathContext;
+typedef struct {
+  void *callbacks[14];
+  void (*startElement)(void *, const xmlChar *, const xmlChar **);
+  void *more_callbacks[12];
+  unsigned int initialized;
+  void *_private;
+  void *startElementNs;
+  void *endElementNs;
+  void *serror;
+} xmlSAXHandler;
+#define XML_ELEMENT_NODE 1
+#define XML_PARSE_NOBLANKS 256
+extern void (*xmlFree)(void *);
+xmlDoc *xmlReadMemory(const char *, int, const char *, const char *, int);
+void xmlFreeDoc(xmlDoc *);
+xmlNode *xmlDocGetRootElement(xmlDoc *);
+xmlChar *xmlGetProp(xmlNode *, const xmlChar *);
+xmlChar *xmlNodeGetContent(xmlNode *);
+xmlXPathContext *xmlXPathNewContext(xmlDoc *);
+void xmlXPathFreeContext(xmlXPathContext *);
+xmlXPathObject *xmlXPathEvalExpression(const xmlChar *, xmlXPathContext *);
+void xmlXPathFreeObject(xmlXPathObject *);
+int xmlSAXUserParseMemory(xmlSAXHandler *, void *, const char *, int);
+
+// <CommonCrypto/CommonDigest.h>, <CommonCrypto/CommonHMAC.h>,
+// <CommonCrypto/CommonCryptor.h>
+unsigned char *CC_MD5(const void *, unsigned int, unsigned char *);
+unsigned char *CC_SHA1(const void *, unsigned int, unsigned char *);
+int CC_SHA256_Init(void *);
+int CC_SHA256_Update(void *, const void *, unsigned int);
+int CC_SHA256_Final(unsigned char *, void *);
+#define kCCHmacAlgSHA256 2
+void CCHmac(unsigned int, const void *, size_t, const void *, size_t, void *);
+#define kCCSuccess 0
+#define kCCBufferTooSmall (-4301)
+#define kCCEncrypt 0
+#define kCCDecrypt 1
+#define kCCAlgorithmAES128 0
+#define kCCOptionPKCS7Padding 1
+#define kCCOptionECBMode 2
+int CCCrypt(unsigned int, unsigned int, unsigned int, const void *, size_t,
+            const void *, const void *, size_t, void *, size_t, size_t *);
+
+// <Security/SecRandom.h>
+typedef const struct __SecRandom *SecRandomRef;
+extern const SecRandomRef kSecRandomDefault;
+int SecRandomCopyBytes(SecRandomRef, size_t, unsigned char *);
+
+// <dlfcn.h>
+#define RTLD_LAZY 0x1
+#define RTLD_DEFAULT ((void *)-2)
+typedef struct {
+  const char *dli_fname;
+  void *dli_fbase;
+  const char *dli_sname;
+  void *dli_saddr;
+} Dl_info;
+void *dlopen(const char *, int);
+void *dlsym(void *, const char *);
+int dlclose(void *);
+char *dlerror(void);
+int dladdr(const void *, Dl_info *);
+
+// <Block.h>
+void *_Block_copy(const void *);
+void _Block_release(const void *);
+#define Block_copy(b) ((__typeof__(b))_Block_copy((const void *)(b)))
+#define Block_release(b) _Block_release((const void *)(b))
+
+// <dispatch/dispatch.h>
+typedef void *dispatch_queue_t;
+typedef void *dispatch_group_t;
+typedef void *dispatch_semaphore_t;
+typedef long dispatch_once_t;
+typedef unsigned long long dispatch_time_t;
+#define DISPATCH_TIME_NOW 0ull
+#define DISPATCH_TIME_FOREVER (~0ull)
+#define DISPATCH_QUEUE_PRIORITY_DEFAULT 0
+dispatch_queue_t dispatch_get_global_queue(long, unsigned long);
+dispatch_queue_t dispatch_queue_create(const char *, void *);
+void dispatch_release(void *);
+void dispatch_async(dispatch_queue_t, void (^)(void));
+void dispatch_sync(dispatch_queue_t, void (^)(void));
+void dispatch_async_f(dispatch_queue_t, void *, void (*)(void *));
+void dispatch_once(dispatch_once_t *, void (^)(void));
+dispatch_time_t dispatch_time(dispatch_time_t, long long);
+dispatch_semaphore_t dispatch_semaphore_create(long);
+long dispatch_semaphore_signal(dispatch_semaphore_t);
+long dispatch_semaphore_wait(dispatch_semaphore_t, dispatch_time_t);
+dispatch_group_t dispatch_group_create(void);
+void dispatch_group_async(dispatch_group_t, dispatch_queue_t,
+                          void (^)(void));
+long dispatch_group_wait(dispatch_group_t, dispatch_time_t);
+
 // === Main code ===
 
 int int_compar(const void *a, const void *b) { return *(int *)a - *(int *)b; }
@@ -578,6 +1035,466 @@ int test_sem() {
   return 0;
 }
 
+pthread_mutex_t cond_mutex = PTHREAD_MUTEX_INITIALIZER;
+pthread_cond_t cond = PTHREAD_COND_INITIALIZER;
+int cond_value = 0;
+
+void *cond_thread_func(void *arg) {
+  pthread_mutex_lock(&cond_mutex);
+  cond_value = 1;
+  pthread_cond_signal(&cond);
+  pthread_mutex_unlock(&cond_mutex);
+  return arg;
+}
+
+int test_pthread_cond_rwlock_join() {
+  pthread_t thread;
+  pthread_mutex_lock(&cond_mutex);
+  if (pthread_create(&thread, NULL, cond_thread_func, (void *)42) != 0)
+    return -1;
+  while (cond_value == 0)
+    pthread_cond_wait(&cond, &cond_mutex);
+  pthread_mutex_unlock(&cond_mutex);
+  void *retval;
+  if (pthread_join(thread, &retval) != 0 || retval != (void *)42)
+    return -2;
+
+  pthread_rwlock_t rwlock = PTHREAD_RWLOCK_INITIALIZER;
+  // Read locks can be shared, but exclude write locks.
+  if (pthread_rwlock_rdlock(&rwlock) != 0 ||
+      pthread_rwlock_tryrdlock(&rwlock) != 0)
+    return -3;
+  if (pthread_rwlock_trywrlock(&rwlock) == 0)
+    return -4;
+  if (pthread_rwlock_unlock(&rwlock) != 0 ||
+      pthread_rwlock_unlock(&rwlock) != 0)
+    return -5;
+  // Write locks exclude everything.
+  if (pthread_rwlock_trywrlock(&rwlock) != 0)
+    return -6;
+  if (pthread_rwlock_tryrdlock(&rwlock) == 0)
+    return -7;
+  if (pthread_rwlock_unlock(&rwlock) != 0 ||
+      pthread_rwlock_destroy(&rwlock) != 0)
+    return -8;
+  return 0;
+}
+
+pthread_key_t tls_key;
+int tls_destructor_value = 0;
+
+void tls_destructor(void *value) { tls_destructor_value = *(int *)value; }
+
+void *tls_thread_func(void *arg) {
+  if (pthread_getspecific(tls_key) != NULL)
+    return (void *)-1;
+  pthread_setspecific(tls_key, arg);
+  if (pthread_getspecific(tls_key) != arg)
+    return (void *)-1;
+  return NULL;
+}
+
+int test_pthread_key() {
+  if (pthread_key_create(&tls_key, tls_destructor) != 0)
+    return -1;
+  // Values are per-thread.
+  int main_value = 1;
+  pthread_setspecific(tls_key, &main_value);
+  int thread_value = 2;
+  pthread_t thread;
+  pthread_create(&thread, NULL, tls_thread_func, &thread_value);
+  void *retval;
+  if (pthread_join(thread, &retval) != 0 || retval != NULL)
+    return -2;
+  if (pthread_getspecific(tls_key) != &main_value)
+    return -3;
+  // The destructor runs when the thread exits.
+  if (tls_destructor_value != 2)
+    return -4;
+  if (pthread_key_delete(tls_key) != 0)
+    return -5;
+  return 0;
+}
+
+int test_sysctl() {
+  char machine[16];
+  size_t len = 0;
+  // Query the size first.
+  if (sysctlbyname("hw.machine", NULL, &len, NULL, 0) != 0 || len == 0 ||
+      len > sizeof(machine))
+    return -1;
+  if (sysctlbyname("hw.machine", machine, &len, NULL, 0) != 0 ||
+      memcmp(machine, "iPhone", 6) != 0)
+    return -2;
+  unsigned long long memsize = 0;
+  len = sizeof(memsize);
+  if (sysctlbyname("hw.memsize", &memsize, &len, NULL, 0) != 0 ||
+      len != sizeof(memsize) || memsize == 0)
+    return -3;
+  int mib[2] = {CTL_HW, HW_PHYSMEM};
+  unsigned int physmem = 0;
+  len = sizeof(physmem);
+  if (sysctl(mib, 2, &physmem, &len, NULL, 0) != 0 ||
+      len != sizeof(physmem) || physmem != memsize)
+    return -4;
+  // Too small a buffer is an error.
+  len = 1;
+  if (sysctlbyname("hw.memsize", &memsize, &len, NULL, 0) != -1)
+    return -5;
+  // uname() should agree with sysctl().
+  struct utsname name;
+  if (uname(&name) != 0 || strcmp(name.sysname, "Darwin") != 0 ||
+      strncmp(name.machine, machine, sizeof(machine)) != 0)
+    return -6;
+  char release[16];
+  int kern_mib[2] = {CTL_KERN, KERN_OSRELEASE};
+  len = sizeof(release);
+  if (sysctl(kern_mib, 2, release, &len, NULL, 0) != 0 ||
+      strcmp(release, name.release) != 0)
+    return -7;
+  return 0;
+}
+
+int test_netdb() {
+  // Only numeric addresses are used, so this doesn't need a network.
+  struct hostent *host = gethostbyname("127.0.0.1");
+  if (host == NULL || host->h_addrtype != AF_INET || host->h_length != 4 ||
+      host->h_addr_list[0] == NULL || host->h_addr_list[1] != NULL ||
+      memcmp(host->h_addr_list[0], "\x7f\0\0\x01", 4) != 0)
+    return -1;
+  struct addrinfo hints = {0};
+  hints.ai_flags = AI_NUMERICHOST;
+  hints.ai_family = AF_INET;
+  hints.ai_socktype = SOCK_STREAM;
+  struct addrinfo *res = NULL;
+  if (getaddrinfo("10.0.0.1", "8080", &hints, &res) != 0 || res == NULL)
+    return -2;
+  // struct sockaddr_in: length, family, port and address in network order.
+  unsigned char *addr = res->ai_addr;
+  if (res->ai_next != NULL || res->ai_family != AF_INET ||
+      res->ai_socktype != SOCK_STREAM || res->ai_addrlen != 16 ||
+      addr[0] != 16 || addr[1] != AF_INET ||
+      memcmp(addr + 2, "\x1f\x90\x0a\0\0\x01", 6) != 0)
+    return -3;
+  freeaddrinfo(res);
+  // A host name is not allowed with AI_NUMERICHOST.
+  if (getaddrinfo("example.com", NULL, &hints, &res) == 0)
+    return -4;
+  return 0;
+}
+
+int test_poll_select() {
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  const char *file_path = "./tests/TestApp.app/PkgInfo";
+#else
+  const char *file_path = "/var/mobile/Applications/"
+                          "00000000-0000-0000-0000-000000000000/TestApp.app/"
+                          "PkgInfo";
+#endif
+  int fd = open(file_path, O_RDONLY);
+  if (fd == -1)
+    return -1;
+  // Regular files are always ready.
+  struct pollfd fds[2] = {{fd, POLLIN | POLLOUT, 0}, {-1, POLLIN, 0}};
+  int res = poll(fds, 2, 1000);
+  if (res != 1 || fds[0].revents != (POLLIN | POLLOUT) || fds[1].revents != 0)
+    res = -2;
+  fd_set read_set = {0};
+  read_set.fds_bits[fd / 32] = 1u << (fd % 32);
+  struct timeval timeout = {0, 0};
+  if (res == 1 && (select(fd + 1, &read_set, NULL, NULL, &timeout) != 1 ||
+                   !(read_set.fds_bits[fd / 32] & (1u << (fd % 32)))))
+    res = -3;
+  close(fd);
+  if (res < 0)
+    return res;
+
+  // Nothing is ready on stdin, so this times out.
+  struct pollfd stdin_fd = {0, POLLIN, 0};
+  if (poll(&stdin_fd, 1, 10) != 0 || stdin_fd.revents != 0)
+    return -4;
+
+  int kq = kqueue();
+  if (kq == -1)
+    return -5;
+  struct kevent change = {1, EVFILT_TIMER, EV_ADD | EV_ONESHOT, 0, 10, NULL};
+  struct kevent event;
+  struct timespec kq_timeout = {1, 0};
+  res = kevent(kq, &change, 1, &event, 1, &kq_timeout);
+  if (res != 1 || event.ident != 1 || event.filter != EVFILT_TIMER ||
+      event.data != 1)
+    res = -6;
+  // The one-shot timer is gone, so this times out.
+  kq_timeout.tv_sec = 0;
+  kq_timeout.tv_nsec = 10000000;
+  if (res == 1 && kevent(kq, NULL, 0, &event, 1, &kq_timeout) != 0)
+    res = -7;
+  close(kq);
+  return res < 0 ? res : 0;
+}
+
+int test_fcntl_ioctl() {
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  const char *file_path = "./tests/TestApp.app/PkgInfo";
+#else
+  const char *file_path = "/var/mobile/Applications/"
+                          "00000000-0000-0000-0000-000000000000/TestApp.app/"
+                          "PkgInfo";
+#endif
+  int fd = open(file_path, O_RDONLY);
+  if (fd == -1)
+    return -1;
+  int res = 0;
+  int available = -1;
+  char buf[3];
+  int non_blocking = 0;
+  if (fcntl(fd, F_GETFL) != O_RDONLY)
+    res = -2;
+  else if (fcntl(fd, F_SETFL, O_NONBLOCK) != 0 ||
+           fcntl(fd, F_GETFL) != (O_RDONLY | O_NONBLOCK))
+    res = -3;
+  else if (fcntl(fd, F_GETFD) != 0 || fcntl(fd, F_SETFD, FD_CLOEXEC) != 0 ||
+           fcntl(fd, F_GETFD) != FD_CLOEXEC)
+    res = -4;
+  // PkgInfo is always 8 bytes.
+  else if (ioctl(fd, FIONREAD, &available) != 0 || available != 8)
+    res = -5;
+  else if (read(fd, buf, 3) != 3 || ioctl(fd, FIONREAD, &available) != 0 ||
+           available != 5)
+    res = -6;
+  else if (ioctl(fd, FIONBIO, &non_blocking) != 0 ||
+           fcntl(fd, F_GETFL) != O_RDONLY)
+    res = -7;
+  close(fd);
+  return res;
+}
+
+int test_mach_semaphore_task() {
+  mach_port_t task = mach_task_self_;
+  mach_port_t sem;
+  if (semaphore_create(task, &sem, 0, 1) != KERN_SUCCESS)
+    return -1;
+  mach_timespec_t no_wait = {0, 0};
+  int res = 0;
+  if (semaphore_wait(sem) != KERN_SUCCESS)
+    res = -2;
+  else if (semaphore_timedwait(sem, no_wait) != KERN_OPERATION_TIMED_OUT)
+    res = -3;
+  else if (semaphore_signal(sem) != KERN_SUCCESS ||
+           semaphore_timedwait(sem, no_wait) != KERN_SUCCESS)
+    res = -4;
+  if (semaphore_destroy(task, sem) != KERN_SUCCESS)
+    return -5;
+  if (res != 0)
+    return res;
+
+  // struct task_basic_info: virtual_size and resident_size are the 2nd and
+  // 3rd fields.
+  integer_t task_basic_info[8];
+  natural_t count = 8;
+  if (task_info(task, TASK_BASIC_INFO, task_basic_info, &count) !=
+          KERN_SUCCESS ||
+      count != 8 || task_basic_info[2] <= 0)
+    return -6;
+  // struct vm_statistics: free_count is the 1st field.
+  integer_t vm_statistics[15];
+  count = 15;
+  if (host_statistics(mach_host_self(), HOST_VM_INFO, vm_statistics,
+                      &count) != KERN_SUCCESS ||
+      count != 15 || vm_statistics[0] <= 0)
+    return -7;
+  // struct host_basic_info: cpu_type is the 4th field.
+  integer_t host_basic_info[12];
+  count = 12;
+  if (host_info(mach_host_self(), HOST_BASIC_INFO, host_basic_info,
+                &count) != KERN_SUCCESS ||
+      count != 12 || host_basic_info[3] != 12 /* CPU_TYPE_ARM */)
+    return -8;
+  // Wait for one millisecond.
+  mach_timebase_info_data_t timebase;
+  mach_timebase_info(&timebase);
+  unsigned long long start = mach_absolute_time();
+  unsigned long long wait = 1000000ULL * timebase.denom / timebase.numer;
+  if (mach_wait_until(start + wait) != KERN_SUCCESS ||
+      mach_absolute_time() < start + wait)
+    return -9;
+  return 0;
+}
+
+int signal_count;
+int signal_last;
+void test_signal_handler(int sig) {
+  signal_count++;
+  signal_last = sig;
+}
+int test_signal() {
+  if (signal(SIGUSR1, test_signal_handler) != 0)
+    return -1;
+  if (raise(SIGUSR1) != 0 || signal_count != 1 || signal_last != SIGUSR1)
+    return -2;
+
+  struct sigaction act = {test_signal_handler, 0, 0};
+  struct sigaction oldact;
+  if (sigaction(SIGUSR2, &act, &oldact) != 0 || oldact.sa_handler != 0)
+    return -3;
+
+  // A blocked signal is delivered once it's unblocked.
+  sigset_t set;
+  sigemptyset(&set);
+  sigaddset(&set, SIGUSR2);
+  if (!sigismember(&set, SIGUSR2) || sigismember(&set, SIGUSR1))
+    return -4;
+  if (sigprocmask(SIG_BLOCK, &set, NULL) != 0)
+    return -5;
+  raise(SIGUSR2);
+  if (signal_count != 1)
+    return -6;
+  if (sigprocmask(SIG_UNBLOCK, &set, NULL) != 0 || signal_count != 2 ||
+      signal_last != SIGUSR2)
+    return -7;
+
+  if (signal(SIGUSR1, SIG_IGN) != test_signal_handler)
+    return -8;
+  raise(SIGUSR1);
+  if (signal_count != 2)
+    return -9;
+  return 0;
+}
+
+void *errno_thread_func(void *arg) {
+  errno = EBADF;
+  return (void *)(long)errno;
+}
+int test_errno_per_thread() {
+  errno = 0;
+  if (open("/this/does/not/exist", O_RDONLY) != -1 || errno != ENOENT)
+    return -1;
+  if (close(-1) != -1 || errno != EBADF)
+    return -2;
+  if (strcmp(strerror(ENOENT), "No such file or directory"))
+    return -3;
+
+  // Each thread has its own errno.
+  errno = ENOENT;
+  pthread_t thread;
+  void *retval;
+  if (pthread_create(&thread, NULL, errno_thread_func, NULL) != 0 ||
+      pthread_join(thread, &retval) != 0 || retval != (void *)EBADF)
+    return -4;
+  if (errno != ENOENT)
+    return -5;
+  errno = 0;
+  return 0;
+}
+
+int test_strftime_strptime() {
+  // 2024-02-29 13:05:09 UTC, a Thursday
+  time_t t = 1709211909;
+  struct tm tm;
+  if (gmtime_r(&t, &tm) != &tm || tm.tm_year != 124 || tm.tm_mon != 1 ||
+      tm.tm_mday != 29 || tm.tm_wday != 4 || tm.tm_yday != 59)
+    return -1;
+  char buf[100];
+  size_t len = strftime(buf, sizeof(buf),
+                        "%a %A %b %B %d %e %H %I %j %m %M %p %S %u %w %y %Y "
+                        "%V %G %D %T %F %R %Z %z %%",
+                        &tm);
+  if (len != strlen(buf) ||
+      strcmp(buf, "Thu Thursday Feb February 29 29 13 01 060 02 05 PM 09 4 4 "
+                  "24 2024 09 2024 02/29/24 13:05:09 2024-02-29 13:05 UTC "
+                  "+0000 %"))
+    return -2;
+  // Too small buffer
+  if (strftime(buf, 4, "%Y", &tm) != 0)
+    return -3;
+
+  struct tm parsed;
+  memset(&parsed, 0, sizeof(parsed));
+  char *end =
+      strptime("2024-02-29 1:05:09 pm!", "%Y-%m-%d %I:%M:%S %p", &parsed);
+  if (!end || *end != '!' || parsed.tm_hour != 13 || parsed.tm_wday != 4 ||
+      parsed.tm_yday != 59 || timegm(&parsed) != t)
+    return -4;
+  if (strptime("Feb 30x", "%b %d %H", &parsed) != NULL)
+    return -5;
+
+  // Out-of-range fields are normalized
+  memset(&parsed, 0, sizeof(parsed));
+  parsed.tm_year = 124;
+  parsed.tm_mon = 1;
+  parsed.tm_mday = 30;
+  if (timegm(&parsed) != 1709251200 || parsed.tm_mon != 2 ||
+      parsed.tm_mday != 1)
+    return -6;
+  return 0;
+}
+
+int test_syslog_warn() {
+  int old_mask = setlogmask(LOG_UPTO(LOG_ERR));
+  if (old_mask != 0xff)
+    return -1;
+  // Zero leaves the mask unchanged.
+  if (setlogmask(0) != LOG_UPTO(LOG_ERR))
+    return -2;
+  openlog("TestApp", 0, 0);
+  errno = ENOENT;
+  syslog(LOG_ERR, "test_syslog_warn: %d%% (%m)", 100);
+  syslog(LOG_DEBUG, "test_syslog_warn: this is filtered out");
+  closelog();
+  setlogmask(old_mask);
+  warnx("test_syslog_warn: %s", "ok");
+  return 0;
+}
+
+int test_getenv_setenv() {
+  // Default variables
+  if (getenv("HOME") == NULL || getenv("TMPDIR") == NULL)
+    return -1;
+  if (getenv("TOUCHHLE_TEST_VAR") != NULL)
+    return -2;
+  if (setenv("TOUCHHLE_TEST_VAR", "foo", 0) != 0)
+    return -3;
+  if (strcmp(getenv("TOUCHHLE_TEST_VAR"), "foo") != 0)
+    return -4;
+  // No overwrite
+  setenv("TOUCHHLE_TEST_VAR", "bar", 0);
+  if (strcmp(getenv("TOUCHHLE_TEST_VAR"), "foo") != 0)
+    return -5;
+  setenv("TOUCHHLE_TEST_VAR", "bar", 1);
+  if (strcmp(getenv("TOUCHHLE_TEST_VAR"), "bar") != 0)
+    return -6;
+  // A name that's a prefix of another shouldn't match
+  if (getenv("TOUCHHLE_TEST") != NULL)
+    return -7;
+  if (unsetenv("TOUCHHLE_TEST_VAR") != 0 ||
+      getenv("TOUCHHLE_TEST_VAR") != NULL)
+    return -8;
+  errno = 0;
+  if (setenv("A=B", "C", 1) != -1 || errno != EINVAL)
+    return -9;
+  return 0;
+}
+
+int test_localtime_mktime() {
+  // Whatever the local time zone is, this should round-trip.
+  time_t t = 1700000000;
+  struct tm tm;
+  if (localtime_r(&t, &tm) != &tm)
+    return -1;
+  if (tm.tm_isdst < 0 || tm.tm_zone == NULL)
+    return -2;
+  tm.tm_isdst = -1; // let mktime() work it out
+  if (mktime(&tm) != t)
+    return -3;
+  // mktime() normalizes out-of-range fields.
+  tm.tm_mday += 31;
+  if (difftime(mktime(&tm), t) < 30 * 86400.0)
+    return -4;
+  if (tzname[0] == NULL || tzname[1] == NULL)
+    return -5;
+  return 0;
+}
+
 int test_strncpy() {
   char *src = "test\0abcd";
   char dst[10];
@@ -696,9 +1613,39 @@ int test_setlocale() {
     return 3;
   }
 
+  // Restore the default so other tests aren't affected
+  setlocale(LC_ALL, "C");
+
   return 0;
 }
 
+int test_localeconv_strtod() {
+  int res = 0;
+
+  if (strcmp(localeconv()->decimal_point, ".") != 0)
+    return -1;
+  res += atof("1,5") != 1.0;
+
+  if (setlocale(LC_NUMERIC, "de_DE.UTF-8") == NULL)
+    return -2;
+  res += !!strcmp(localeconv()->decimal_point, ",");
+  res += !!strcmp(localeconv()->thousands_sep, ".");
+  res += atof("1,5") != 1.5;
+  res += atof("1.5") != 1.0;
+  char *str = str_format("%.1f", 1.5);
+  res += !!strcmp(str, "1,5");
+  free(str);
+  // Mixed locales
+  res += !!strcmp(setlocale(LC_ALL, NULL), "C/C/C/de_DE.UTF-8/C/C");
+
+  // Invalid locale names are rejected
+  res += setlocale(LC_ALL, "not a locale") != NULL;
+
+  setlocale(LC_ALL, "C");
+  res += atof("1,5") != 1.0;
+  return res;
+}
+
 int test_dirent() {
   struct dirent *dp;
 #ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
@@ -732,6 +1679,132 @@ int test_dirent() {
   return 0;
 }
 
+int test_readdir_r_rewinddir() {
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  const char *path = "./tests/TestApp.app";
+#else
+  const char *path = "/var/mobile/Applications/"
+                     "00000000-0000-0000-0000-000000000000/TestApp.app";
+#endif
+  DIR *dirp = opendir(path);
+  if (dirp == NULL) {
+    return -1;
+  }
+  struct dirent entry;
+  struct dirent *result;
+  int first_pass = 0;
+  while (readdir_r(dirp, &entry, &result) == 0 && result != NULL) {
+    if (result != &entry) {
+      closedir(dirp);
+      return -2;
+    }
+    first_pass++;
+  }
+  // TestApp, Info.plist, PkgInfo, plus "." and ".."
+  if (first_pass != 5) {
+    closedir(dirp);
+    return -3;
+  }
+  rewinddir(dirp);
+  int second_pass = 0;
+  int found_dot = 0;
+  struct dirent *dp;
+  while ((dp = readdir(dirp)) != NULL) {
+    if (strcmp(dp->d_name, ".") == 0) {
+      found_dot = 1;
+    }
+    second_pass++;
+  }
+  closedir(dirp);
+  if (second_pass != first_pass || !found_dot) {
+    return -4;
+  }
+  return 0;
+}
+
+int test_stat() {
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  const char *bundle_path = "./tests/TestApp.app";
+  const char *file_path = "./tests/TestApp.app/PkgInfo";
+#else
+  const char *bundle_path = "/var/mobile/Applications/"
+                            "00000000-0000-0000-0000-000000000000/TestApp.app";
+  const char *file_path = "/var/mobile/Applications/"
+                          "00000000-0000-0000-0000-000000000000/TestApp.app/"
+                          "PkgInfo";
+#endif
+  struct stat st;
+#ifndef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  if (sizeof(st) != 108)
+    return -1;
+#endif
+  if (stat(bundle_path, &st) != 0 || (st.st_mode & S_IFMT) != S_IFDIR)
+    return -2;
+  // PkgInfo is always "APPL????".
+  if (stat(file_path, &st) != 0 || (st.st_mode & S_IFMT) != S_IFREG ||
+      st.st_size != 8)
+    return -3;
+  if (stat("/this/does/not/exist", &st) != -1)
+    return -4;
+  return 0;
+}
+
+int test_mmap() {
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+  const char *file_path = "./tests/TestApp.app/PkgInfo";
+#else
+  const char *file_path = "/var/mobile/Applications/"
+                          "00000000-0000-0000-0000-000000000000/TestApp.app/"
+                          "PkgInfo";
+#endif
+  // Anonymous mappings are page-aligned and zero-filled.
+  char *anon = mmap(NULL, 0x2000, PROT_READ | PROT_WRITE,
+                    MAP_ANON | MAP_PRIVATE, -1, 0);
+  if (anon == MAP_FAILED || ((unsigned long)anon & 0xfff) != 0)
+    return -1;
+  for (int i = 0; i < 0x2000; i++) {
+    if (anon[i] != 0)
+      return -2;
+  }
+  anon[0x1fff] = 1;
+  if (munmap(anon, 0x2000) != 0)
+    return -3;
+
+  int fd = open(file_path, O_RDONLY);
+  if (fd == -1)
+    return -4;
+  char *file = mmap(NULL, 8, PROT_READ, MAP_PRIVATE, fd, 0);
+  close(fd);
+  if (file == MAP_FAILED)
+    return -5;
+  // PkgInfo is always "APPL????".
+  int res = memcmp(file, "APPL????", 8) == 0 ? 0 : -6;
+  munmap(file, 8);
+  return res;
+}
+
+int test_notify() {
+  int token, check;
+  if (notify_register_check("com.example.TestApp.test", &token) !=
+      NOTIFY_STATUS_OK)
+    return -1;
+  // The first check always reports a notification.
+  if (notify_check(token, &check) != NOTIFY_STATUS_OK || check != 1)
+    return -2;
+  if (notify_check(token, &check) != NOTIFY_STATUS_OK || check != 0)
+    return -3;
+  if (notify_post("com.example.TestApp.other") != NOTIFY_STATUS_OK ||
+      notify_check(token, &check) != NOTIFY_STATUS_OK || check != 0)
+    return -4;
+  if (notify_post("com.example.TestApp.test") != NOTIFY_STATUS_OK ||
+      notify_check(token, &check) != NOTIFY_STATUS_OK || check != 1)
+    return -5;
+  if (notify_cancel(token) != NOTIFY_STATUS_OK ||
+      notify_check(token, &check) == NOTIFY_STATUS_OK)
+    return -6;
+  return 0;
+}
+
 int test_strchr() {
   char *src = "abc";
   if (strchr(src, 'a')[0] != 'a' || strrchr(src, 'a')[0] != 'a')
@@ -755,6 +1828,428 @@ int test_swprintf() {
   return 0;
 }
 
+int test_wchar_mb() {
+  int res = 0;
+  wchar_t wbuf[8];
+  char buf[16];
+  mbstate_t state;
+  memset(&state, 0, sizeof(state));
+
+  // The C locale has one byte per character.
+  res += mbstowcs(wbuf, "abc", 8) != 3;
+  res += !!wcscmp(wbuf, L"abc");
+
+  setlocale(LC_CTYPE, "en_US.UTF-8");
+  // "né€" in UTF-8
+  res += mbstowcs(wbuf, "n\xc3\xa9\xe2\x82\xac", 8) != 3;
+  res += wbuf[1] != 0xe9 || wbuf[2] != 0x20ac;
+  res += wcslen(wbuf) != 3;
+  res += wcstombs(buf, wbuf, 16) != 6;
+  res += !!strcmp(buf, "n\xc3\xa9\xe2\x82\xac");
+  res += wcstombs(NULL, wbuf, 0) != 6;
+  // Incomplete characters are remembered in the state.
+  res += mbrtowc(wbuf, "\xe2\x82", 2, &state) != (size_t)-2;
+  res += mbrtowc(wbuf, "\xac", 1, &state) != 1;
+  res += wbuf[0] != 0x20ac;
+  res += wcrtomb(buf, 0xe9, &state) != 2;
+  // Invalid sequences are rejected.
+  res += mbstowcs(wbuf, "\xff", 8) != (size_t)-1;
+  res += swprintf(wbuf, 8, L"%ls!", L"\x20ac") != 2;
+  res += wbuf[0] != 0x20ac || wbuf[1] != L'!';
+  // Output that doesn't fit is an error.
+  res += swprintf(wbuf, 2, L"%s", "abc") != -1;
+
+  setlocale(LC_ALL, "C");
+  return res;
+}
+
+int test_random() {
+  // drand48() and friends are fully specified by POSIX.
+  srand48(1);
+  if (lrand48() != 89400484 || lrand48() != 976015093)
+    return -1;
+  unsigned short xsubi[3] = {0x330E, 1, 0};
+  if (nrand48(xsubi) != 89400484)
+    return -2;
+  double d = drand48();
+  if (d < 0.0 || d >= 1.0)
+    return -3;
+  // rand_r() only depends on the seed it's given.
+  unsigned int seed_a = 42, seed_b = 42;
+  if (rand_r(&seed_a) != rand_r(&seed_b) || seed_a != seed_b)
+    return -4;
+  for (int i = 0; i < 100; i++) {
+    if (arc4random_uniform(10) >= 10)
+      return -5;
+  }
+  if (arc4random_uniform(0) != 0 || arc4random_uniform(1) != 0)
+    return -6;
+  return 0;
+}
+
+int test_zlib() {
+  const unsigned char *check = (const unsigned char *)"123456789";
+  if (crc32(crc32(0, NULL, 0), check, 9) != 0xCBF43926)
+    return -1;
+  if (adler32(adler32(0, NULL, 0), check, 9) != 0x091E01DE)
+    return -2;
+
+  const char *text = "touchHLE touchHLE touchHLE touchHLE touchHLE";
+  unsigned long text_len = strlen(text) + 1;
+  unsigned char compressed[128];
+  unsigned long compressed_len = sizeof(compressed);
+  if (compressBound(text_len) > compressed_len)
+    return -3;
+  if (compress2(compressed, &compressed_len, (const unsigned char *)text,
+                text_len, 9) != Z_OK)
+    return -4;
+  char buf[64];
+  unsigned long buf_len = sizeof(buf);
+  if (uncompress((unsigned char *)buf, &buf_len, compressed,
+                 compressed_len) != Z_OK ||
+      buf_len != text_len || strcmp(buf, text) != 0)
+    return -5;
+  buf_len = 4;
+  if (uncompress((unsigned char *)buf, &buf_len, compressed,
+                 compressed_len) != Z_BUF_ERROR)
+    return -6;
+
+  // Streaming decompression, a few bytes at a time.
+  z_stream strm;
+  memset(&strm, 0, sizeof(strm));
+  if (inflateInit_(&strm, zlibVersion(), sizeof(strm)) != Z_OK)
+    return -7;
+  strm.next_in = compressed;
+  strm.avail_in = compressed_len;
+  int res = Z_OK;
+  while (res == Z_OK) {
+    strm.next_out = (unsigned char *)buf + strm.total_out;
+    strm.avail_out = 5;
+    res = inflate(&strm, Z_NO_FLUSH);
+  }
+  if (res != Z_STREAM_END || strm.total_out != text_len ||
+      strcmp(buf, text) != 0 ||
+      strm.adler != adler32(1, (const unsigned char *)text, text_len))
+    return -8;
+  if (inflateEnd(&strm) != Z_OK)
+    return -9;
+  return 0;
+}
+
+int sqlite3_count_rows(void *count, int ncols, char **values, char **names) {
+  if (ncols != 2 || strcmp(names[0], "id") != 0 || values[1] == NULL)
+    return 1;
+  (*(int *)count)++;
+  return 0;
+}
+
+int test_sqlite3() {
+  sqlite3 *db;
+  if (sqlite3_open(":memory:", &db) != SQLITE_OK)
+    return -1;
+  int res = sqlite3_exec(db,
+                         "CREATE TABLE scores (id INTEGER, name TEXT);"
+                         "INSERT INTO scores VALUES (1, 'alice');"
+                         "INSERT INTO scores VALUES (2, 'bob');",
+                         NULL, NULL, NULL);
+  if (res != SQLITE_OK)
+    return -2;
+
+  sqlite3_stmt *stmt;
+  const char *tail;
+  const char *sql = "INSERT INTO scores VALUES (?, ?); -- tail";
+  if (sqlite3_prepare_v2(db, sql, -1, &stmt, &tail) != SQLITE_OK ||
+      strcmp(tail, " -- tail") != 0)
+    return -3;
+  sqlite3_bind_int(stmt, 1, 3);
+  sqlite3_bind_text(stmt, 2, "carol", -1, SQLITE_TRANSIENT);
+  if (sqlite3_step(stmt) != SQLITE_DONE || sqlite3_finalize(stmt) != SQLITE_OK)
+    return -4;
+
+  if (sqlite3_prepare_v2(db, "SELECT id, name FROM scores WHERE id > ?", -1,
+                         &stmt, NULL) != SQLITE_OK)
+    return -5;
+  sqlite3_bind_int(stmt, 1, 1);
+  if (sqlite3_step(stmt) != SQLITE_ROW || sqlite3_column_int(stmt, 0) != 2 ||
+      strcmp((const char *)sqlite3_column_text(stmt, 1), "bob") != 0 ||
+      strcmp(sqlite3_column_name(stmt, 1), "name") != 0)
+    return -6;
+  if (sqlite3_step(stmt) != SQLITE_ROW || sqlite3_column_int(stmt, 0) != 3 ||
+      sqlite3_step(stmt) != SQLITE_DONE)
+    return -7;
+  sqlite3_finalize(stmt);
+
+  int count = 0;
+  res = sqlite3_exec(db, "SELECT * FROM scores", &sqlite3_count_rows, &count,
+                     NULL);
+  if (res != SQLITE_OK || count != 3)
+    return -8;
+  char *errmsg = NULL;
+  if (sqlite3_exec(db, "SELECT * FROM nonexistent", NULL, NULL, &errmsg) ==
+          SQLITE_OK ||
+      errmsg == NULL)
+    return -9;
+  free(errmsg);
+
+  if (sqlite3_close(db) != SQLITE_OK)
+    return -10;
+  return 0;
+}
+
+void libxml2_count_elements(void *ctx, const xmlChar *name,
+                            const xmlChar **attrs) {
+  (*(int *)ctx)++;
+}
+
+int test_libxml2() {
+  const char *xml = "<?xml version=\"1.0\"?>\n"
+                    "<level name=\"one\">\n"
+                    "  <tile x=\"1\">grass</tile>\n"
+                    "  <tile x=\"2\">water &amp; sand</tile>\n"
+                    "</level>\n";
+  xmlDoc *doc = xmlReadMemory(xml, strlen(xml), NULL, NULL, XML_PARSE_NOBLANKS);
+  if (doc == NULL)
+    return -1;
+  xmlNode *root = xmlDocGetRootElement(doc);
+  if (root == NULL || root->type != XML_ELEMENT_NODE ||
+      strcmp((const char *)root->name, "level") != 0)
+    return -2;
+  xmlChar *name = xmlGetProp(root, (const xmlChar *)"name");
+  if (name == NULL || strcmp((const char *)name, "one") != 0)
+    return -3;
+  xmlFree(name);
+
+  xmlNode *tile = root->children;
+  if (tile == NULL || tile->type != XML_ELEMENT_NODE || tile->next == NULL ||
+      tile->next->next != NULL || tile->next->parent != root)
+    return -4;
+  xmlChar *content = xmlNodeGetContent(tile->next);
+  if (strcmp((const char *)content, "water & sand") != 0)
+    return -5;
+  xmlFree(content);
+
+  xmlXPathContext *ctx = xmlXPathNewContext(doc);
+  xmlXPathObject *obj =
+      xmlXPathEvalExpression((const xmlChar *)"//tile[@x='2']/text()", ctx);
+  if (obj == NULL || obj->nodesetval == NULL || obj->nodesetval->nodeNr != 1 ||
+      obj->nodesetval->nodeTab[0] != tile->next->children)
+    return -6;
+  xmlXPathFreeObject(obj);
+  obj = xmlXPathEvalExpression((const xmlChar *)"count(/level/tile)", ctx);
+  if (obj == NULL || obj->floatval != 2.0)
+    return -7;
+  xmlXPathFreeObject(obj);
+  xmlXPathFreeContext(ctx);
+  xmlFreeDoc(doc);
+
+  xmlSAXHandler sax;
+  memset(&sax, 0, sizeof(sax));
+  sax.startElement = &libxml2_count_elements;
+  int count = 0;
+  if (xmlSAXUserParseMemory(&sax, &count, xml, strlen(xml)) != 0 || count != 3)
+    return -8;
+  return 0;
+}
+
+int test_CommonCrypto() {
+  static const unsigned char md5_abc[16] = {
+      0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0,
+      0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1, 0x7f, 0x72};
+  static const unsigned char sha1_abc[20] = {
+      0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e,
+      0x25, 0x71, 0x78, 0x50, 0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d};
+  static const unsigned char sha256_abc[32] = {
+      0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40,
+      0xde, 0x5d, 0xae, 0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17,
+      0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad};
+  static const unsigned char hmac_jefe[32] = {
+      0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24,
+      0x26, 0x08, 0x95, 0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27,
+      0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec, 0x38, 0x43};
+  unsigned char md[32];
+
+  if (CC_MD5("abc", 3, md) != md || memcmp(md, md5_abc, 16) != 0)
+    return -1;
+  CC_SHA1("abc", 3, md);
+  if (memcmp(md, sha1_abc, 20) != 0)
+    return -2;
+  unsigned char sha256_ctx[104];
+  CC_SHA256_Init(sha256_ctx);
+  CC_SHA256_Update(sha256_ctx, "a", 1);
+  CC_SHA256_Update(sha256_ctx, "bc", 2);
+  CC_SHA256_Final(md, sha256_ctx);
+  if (memcmp(md, sha256_abc, 32) != 0)
+    return -3;
+  const char *hmac_data = "what do ya want for nothing?";
+  CCHmac(kCCHmacAlgSHA256, "Jefe", 4, hmac_data, strlen(hmac_data), md);
+  if (memcmp(md, hmac_jefe, 32) != 0)
+    return -4;
+
+  // FIPS-197 AES-128 example
+  unsigned char key[16], plaintext[16], ciphertext[32], decrypted[32];
+  static const unsigned char aes_expected[16] = {
+      0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30,
+      0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a};
+  for (int i = 0; i < 16; i++) {
+    key[i] = i;
+    plaintext[i] = i * 0x11;
+  }
+  size_t moved = 0;
+  if (CCCrypt(kCCEncrypt, kCCAlgorithmAES128, kCCOptionECBMode, key, 16, NULL,
+              plaintext, 16, ciphertext, 16, &moved) != kCCSuccess ||
+      moved != 16 || memcmp(ciphertext, aes_expected, 16) != 0)
+    return -5;
+
+  // CBC with padding round trip
+  const char *message = "CommonCrypto";
+  unsigned char iv[16] = {1, 2, 3};
+  if (CCCrypt(kCCEncrypt, kCCAlgorithmAES128, kCCOptionPKCS7Padding, key, 16,
+              iv, message, strlen(message), ciphertext, 8,
+              &moved) != kCCBufferTooSmall)
+    return -6;
+  if (CCCrypt(kCCEncrypt, kCCAlgorithmAES128, kCCOptionPKCS7Padding, key, 16,
+              iv, message, strlen(message), ciphertext, sizeof(ciphertext),
+              &moved) != kCCSuccess ||
+      moved != 16)
+    return -7;
+  if (CCCrypt(kCCDecrypt, kCCAlgorithmAES128, kCCOptionPKCS7Padding, key, 16,
+              iv, ciphertext, moved, decrypted, sizeof(decrypted),
+              &moved) != kCCSuccess ||
+      moved != strlen(message) || memcmp(decrypted, message, moved) != 0)
+    return -8;
+  return 0;
+}
+
+int test_SecRandomCopyBytes() {
+  unsigned char a[32] = {0}, b[32] = {0};
+  if (SecRandomCopyBytes(kSecRandomDefault, sizeof(a), a) != 0 ||
+      SecRandomCopyBytes(kSecRandomDefault, sizeof(b), b) != 0)
+    return -1;
+  // Two 256-bit outputs should never be equal, or all zero.
+  if (memcmp(a, b, sizeof(a)) == 0)
+    return -2;
+  unsigned char zero[32] = {0};
+  if (memcmp(a, zero, sizeof(a)) == 0)
+    return -3;
+  return 0;
+}
+
+int test_dlfcn() {
+  void *handle = dlopen(NULL, RTLD_LAZY);
+  if (handle == NULL)
+    return -1;
+  size_t (*strlen_ptr)(const char *) = dlsym(handle, "strlen");
+  if (strlen_ptr == NULL || strlen_ptr("abc") != 3)
+    return -2;
+  if (dlclose(handle) != 0)
+    return -3;
+  // Failures are reported by dlerror(), once.
+  if (dlsym(RTLD_DEFAULT, "touchHLE_no_such_function") != NULL)
+    return -4;
+  if (dlerror() == NULL || dlerror() != NULL)
+    return -5;
+  if (dlopen("/no/such/library.dylib", RTLD_LAZY) != NULL)
+    return -6;
+  if (dlerror() == NULL)
+    return -7;
+  // The address of a function in the app binary.
+  Dl_info info;
+  if (dladdr((void *)&test_dlfcn, &info) == 0)
+    return -8;
+  if (info.dli_fname == NULL || info.dli_fbase == NULL ||
+      (char *)info.dli_saddr > (char *)&test_dlfcn)
+    return -9;
+  return 0;
+}
+
+int test_blocks() {
+  __block int counter = 0;
+  int captured = 3;
+  int (^add)(int) = ^(int x) {
+    counter++;
+    return x + captured;
+  };
+  int (^add_copy)(int) = Block_copy(add);
+  if (add_copy == NULL || add_copy(1) != 4)
+    return -1;
+  // The __block variable is shared between the stack and heap versions.
+  if (add(2) != 5 || counter != 2)
+    return -2;
+  int (^add_copy2)(int) = Block_copy(add_copy);
+  if (add_copy2 != add_copy)
+    return -3;
+  Block_release(add_copy2);
+  Block_release(add_copy);
+  // A block that captures nothing is global and isn't really copied.
+  int (^twice)(int) = ^(int x) {
+    return x * 2;
+  };
+  if (Block_copy(twice) != twice || twice(2) != 4)
+    return -4;
+  return 0;
+}
+
+void dispatch_increment(void *counter) { (*(int *)counter)++; }
+
+int test_dispatch() {
+  static dispatch_once_t once;
+  __block int once_count = 0;
+  for (int i = 0; i < 2; i++) {
+    dispatch_once(&once, ^{
+      once_count++;
+    });
+  }
+  if (once_count != 1)
+    return -1;
+
+  dispatch_queue_t global =
+      dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_DEFAULT, 0);
+  dispatch_semaphore_t sem = dispatch_semaphore_create(0);
+  // Nothing has signalled the semaphore yet, so this should time out.
+  if (dispatch_semaphore_wait(sem, dispatch_time(DISPATCH_TIME_NOW, 0)) == 0)
+    return -2;
+  __block int value = 0;
+  dispatch_async(global, ^{
+    value = 1;
+    dispatch_semaphore_signal(sem);
+  });
+  if (dispatch_semaphore_wait(sem, DISPATCH_TIME_FOREVER) != 0 || value != 1)
+    return -3;
+  dispatch_release(sem);
+
+  // Each block writes to its own element, since they may run concurrently.
+  __block int results[4] = {0};
+  dispatch_group_t group = dispatch_group_create();
+  for (int i = 0; i < 4; i++) {
+    dispatch_group_async(group, global, ^{
+      results[i] = i + 1;
+    });
+  }
+  if (dispatch_group_wait(group, DISPATCH_TIME_FOREVER) != 0)
+    return -4;
+  if (results[0] + results[1] + results[2] + results[3] != 10)
+    return -4;
+  dispatch_release(group);
+
+  // Items on a serial queue run in order, and dispatch_sync() waits for
+  // earlier ones.
+  dispatch_queue_t serial = dispatch_queue_create("TestApp.serial", NULL);
+  __block int order = 0;
+  int counter = 0;
+  dispatch_async(serial, ^{
+    order = order * 10 + 1;
+  });
+  dispatch_async_f(serial, &counter, dispatch_increment);
+  dispatch_sync(serial, ^{
+    order = order * 10 + 2;
+  });
+  dispatch_release(serial);
+  if (order != 12 || counter != 1)
+    return -5;
+
+  return 0;
+}
+
 #define FUNC_DEF(func)                                                         \
   { &func, #func }
 struct {
@@ -770,6 +2265,21 @@ struct {
     FUNC_DEF(test_strlcpy), FUNC_DEF(test_setlocale),
     FUNC_DEF(test_strtoul), FUNC_DEF(test_dirent),
     FUNC_DEF(test_strchr),  FUNC_DEF(test_swprintf),
+    FUNC_DEF(test_readdir_r_rewinddir), FUNC_DEF(test_CGGeometry),
+    FUNC_DEF(test_stat),    FUNC_DEF(test_mmap),
+    FUNC_DEF(test_notify),  FUNC_DEF(test_pthread_cond_rwlock_join),
+    FUNC_DEF(test_pthread_key), FUNC_DEF(test_sysctl),
+    FUNC_DEF(test_netdb),   FUNC_DEF(test_poll_select),
+    FUNC_DEF(test_fcntl_ioctl), FUNC_DEF(test_mach_semaphore_task),
+    FUNC_DEF(test_signal),  FUNC_DEF(test_errno_per_thread),
+    FUNC_DEF(test_strftime_strptime), FUNC_DEF(test_syslog_warn),
+    FUNC_DEF(test_getenv_setenv), FUNC_DEF(test_localtime_mktime),
+    FUNC_DEF(test_localeconv_strtod), FUNC_DEF(test_wchar_mb),
+    FUNC_DEF(test_random),  FUNC_DEF(test_zlib),
+    FUNC_DEF(test_sqlite3), FUNC_DEF(test_libxml2),
+    FUNC_DEF(test_CommonCrypto), FUNC_DEF(test_SecRandomCopyBytes),
+    FUNC_DEF(test_dlfcn), FUNC_DEF(test_blocks),
+    FUNC_DEF(test_dispatch),
 };
 
 // Because no libc is linked into this executable, there is no libc entry point